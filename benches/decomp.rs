@@ -0,0 +1,77 @@
+// Time and memory of decompiling a whole program, on a generated 3000-function
+// binary built with the system C compiler, or on the files named on the command
+// line: cargo bench --bench decomp [-- <file>...]
+
+use std::{process::Command, time::Instant};
+
+use baretk::{AnalysisOptions, Language};
+
+const FUNCTIONS: usize = 3000;
+
+// Small functions with arithmetic, branches, loops and calls to each other, so
+// the lifter sees the usual mix of expressions.
+fn source() -> String {
+    let mut out = String::from("int sink;\n");
+    for i in 0..FUNCTIONS {
+        let callee = if i == 0 { String::from("sink") } else { format!("f{}(a ^ {}, b)", i - 1, i) };
+        out += &format!("__attribute__((noinline)) int f{i}(int a, int b) {{\n");
+        out += &format!("    int t = a * {} + (b >> {});\n", i % 13 + 3, i % 7 + 1);
+        out += &format!("    for (int j = 0; j < (b & {}); j++) t += j * a - {};\n", i % 5 + 3, i);
+        out += &format!("    if (t > {}) t -= {};\n", i * 17, callee);
+        out += &format!("    else if ((a & {}) == {}) t = (t << 2) + b / {};\n", i % 11 + 4, i % 4, i % 9 + 2);
+        out += &format!("    while (b > {}) {{ b = (b * 7 + t) >> 1; t ^= b & {}; }}\n", i % 3 + 1, i);
+        out += "    return t ^ sink;\n}\n";
+    }
+    out += &format!("int main(int argc, char** argv) {{ return f{}(argc, (int)(long)argv); }}\n", FUNCTIONS - 1);
+    out
+}
+
+fn build() -> Option<Vec<u8>> {
+    let dir = std::env::temp_dir().join(format!("baretk-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).ok()?;
+    let (src, exe) = (dir.join("funcs.c"), dir.join("funcs"));
+    std::fs::write(&src, source()).ok()?;
+    let built = Command::new("cc").arg("-O1").arg("-o").arg(&exe).arg(&src).status().ok()?.success();
+    let bytes = if built { std::fs::read(&exe).ok() } else { None };
+    let _ = std::fs::remove_dir_all(&dir);
+    bytes
+}
+
+// The peak resident set so far, where the kernel reports it.
+fn max_rss() -> Option<String> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    Some(line["VmHWM:".len()..].trim().to_string())
+}
+
+fn bench(name: &str, bytes: &[u8]) {
+    let options = AnalysisOptions::default();
+    let start = Instant::now();
+    let decomp = match baretk::decompile_bytes(bytes, &options, Language::Pseudocode) {
+        Ok(decomp) => decomp,
+        Err(err) => {
+            eprintln!("{}: {}", name, err);
+            return
+        },
+    };
+    let lifted = Instant::now();
+    let output = decomp.print(false);
+    println!("{:<24} lift {:>10.2?} print {:>10.2?} {:>10} bytes of output", name, lifted - start, lifted.elapsed(), output.len());
+    println!("{:<24} {}, max RSS {}", "", decomp.stats(), max_rss().unwrap_or_else(|| "unknown".to_string()));
+}
+
+fn main() {
+    let paths: Vec<String> = std::env::args().skip(1).filter(|arg| !arg.starts_with("--")).collect();
+    if paths.is_empty() {
+        match build() {
+            Some(bytes) => bench(&format!("{} functions", FUNCTIONS), &bytes),
+            None => eprintln!("Couldn't build the generated program with cc; name a file to decompile instead"),
+        }
+    }
+    for path in paths {
+        match std::fs::read(&path) {
+            Ok(bytes) => bench(&path, &bytes),
+            Err(err) => eprintln!("Error reading file {}: {}", path, err),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+// Throughput of the string scan on generated inputs, or on the files named on
+// the command line: cargo bench --bench strings [-- <file>...]
+
+use std::time::Instant;
+
+use baretk::AnalysisOptions;
+
+// A fixed xorshift sequence, so every run scans the same bytes.
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..len).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as u8
+    }).collect()
+}
+
+// Mostly text with a few control and non-ASCII bytes, like a string table.
+fn text(len: usize) -> Vec<u8> {
+    let words = b"the quick brown fox jumps over the lazy dog\0/usr/lib/libc.so.6\0GLIBC_2.34\n";
+    let mut bytes: Vec<u8> = words.iter().cycle().take(len).copied().collect();
+    for (i, b) in noise(len / 64).into_iter().enumerate() {
+        bytes[i * 64] = b;
+    }
+    bytes
+}
+
+fn bench(name: &str, bytes: &[u8]) {
+    let options = AnalysisOptions::default();
+    let rounds = 5;
+    let start = Instant::now();
+    let mut count = 0;
+    for _ in 0..rounds {
+        count = baretk::strings(bytes, &options).len();
+    }
+    let elapsed = start.elapsed() / rounds;
+    let mb_per_s = bytes.len() as f64 / elapsed.as_secs_f64() / 1e6;
+    println!("{:<24} {:>10} bytes {:>8} strings {:>10.2?} {:>8.0} MB/s", name, bytes.len(), count, elapsed, mb_per_s);
+}
+
+fn main() {
+    let paths: Vec<String> = std::env::args().skip(1).filter(|arg| !arg.starts_with("--")).collect();
+    if paths.is_empty() {
+        bench("noise", &noise(64 << 20));
+        bench("text", &text(64 << 20));
+        bench("zeros", &vec![0u8; 64 << 20]);
+    }
+    for path in paths {
+        match std::fs::read(&path) {
+            Ok(bytes) => bench(&path, &bytes),
+            Err(err) => eprintln!("Error reading file {}: {}", path, err),
+        }
+    }
+}
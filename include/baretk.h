@@ -1,9 +1,116 @@
 #ifndef BARETK_H_INCLUDED
 #define BARETK_H_INCLUDED
 
+#include <stddef.h>
+#include <stdint.h>
+
 // C FFI library declarations
+//
+// Functions can be called from any thread. Program and disassembly handles
+// are never changed once made, so threads can share them; a decomp handle
+// can be passed to another thread but used by one thread at a time.
+
+// Memory: whatever the library returns is freed with the library's function
+// for it, never with free(): baretk_free_program, baretk_free_disassembly,
+// baretk_free_decomp, baretk_context_free, baretk_free_strings,
+// baretk_free_string and baretk_free_bytes, which all accept NULL. const
+// pointers, including those in returned structs, are borrowed from the
+// handle they came from and stay valid until it's freed.
+//
+// Pointer arguments are either NULL, where a function accepts it, or valid
+// for what they're declared as: strings are NUL-terminated, byte buffers hold
+// at least the size passed with them, out pointers can be written, and
+// handles came from this library and haven't been freed.
+//
+// Functions return 0 or NULL on failure. The reason is kept per thread until
+// the next failure: baretk_last_error gives its kind and
+// baretk_last_error_message a description, valid until then.
+typedef enum BARETK_Error {
+    BARETK_OK = 0,
+    BARETK_ERROR_NULL_ARGUMENT = 1,
+    BARETK_ERROR_INVALID_STRING = 2,
+    BARETK_ERROR_IO = 3,
+    BARETK_ERROR_MALFORMED = 4,
+    BARETK_ERROR_OUT_OF_RANGE = 5,
+    BARETK_ERROR_NOT_FOUND = 6,
+    BARETK_ERROR_INVALID_ARGUMENT = 7,
+    // The analysis was stopped by baretk_cancel.
+    BARETK_ERROR_CANCELLED = 8,
+} BARETK_Error;
+
+BARETK_Error baretk_last_error(void);
+const char* baretk_last_error_message(void);
+void baretk_clear_error(void);
+
+// The library's version, as "major.minor.patch".
+const char* baretk_version(void);
+// 1 if the library supports a capability named "kind:name": architectures
+// ("arch:x86", "arch:amd64", "arch:arm", "arch:aarch64", "arch:riscv"), file
+// formats ("format:elf", "format:pe", "format:raw") and decompiler languages
+// ("decomp:pseudo", "decomp:rust").
+int baretk_has_feature(const char* name);
+
+// Functions returning char* give a NUL-terminated copy the caller frees
+// with baretk_free_string.
+void baretk_free_string(char* s);
+
+typedef enum BARETK_Syntax {
+    // The architecture's usual syntax: Intel for x86.
+    BARETK_SYNTAX_DEFAULT = 0,
+} BARETK_Syntax;
 
+typedef enum BARETK_Sweep {
+    // Decode the whole code section.
+    BARETK_SWEEP_LINEAR = 0,
+    // Decode only what's reachable from the entry point and function symbols.
+    BARETK_SWEEP_RECURSIVE = 1,
+} BARETK_Sweep;
+
+// Settings shared by loading, disassembly, decompilation and string search.
+// Fill them in with baretk_analysis_options_init before changing any; NULL
+// options stand for the same defaults.
+typedef struct BARETK_AnalysisOptions {
+    // Shortest run of characters reported as a string (default 4).
+    size_t min_string_length;
+    BARETK_Syntax syntax;
+    // Show each instruction's bytes in listings (default 1).
+    int show_bytes;
+    BARETK_Sweep sweep;
+    // An "arch:" name from baretk_has_feature to decode as, or NULL for the
+    // one the headers give.
+    const char* arch;
+    // Load address of raw binaries; 0 keeps the default of 0.
+    uint64_t base_addr;
+    // Nonzero rejects malformed files. By default (0) sizes are clamped and
+    // bad entries skipped, with a warning kept for each.
+    int strict;
+} BARETK_AnalysisOptions;
+
+void baretk_analysis_options_init(BARETK_AnalysisOptions* options);
+
+// Return 1 on success, writing one string per line to out_path. The library
+// never prints; baretk_get_strings gives the strings in memory.
 int baretk_print_strings(const char* path, int min_len, const char* out_path);
+int baretk_print_strings_from_bytes(const unsigned char* bytes, size_t size, int min_len, const char* out_path);
+// A string and the file offset of its first byte.
+typedef struct BARETK_String {
+    char* text;
+    size_t offset;
+} BARETK_String;
+
+// Arrays of the ASCII strings of at least min_len characters, with their
+// number in *count. Free them with baretk_free_strings and the same count,
+// which frees the texts too.
+BARETK_String* baretk_get_strings(const char* path, int min_len, size_t* count);
+BARETK_String* baretk_get_strings_from_bytes(const unsigned char* bytes, size_t size, int min_len, size_t* count);
+// Like baretk_get_strings, with the options' min_string_length.
+BARETK_String* baretk_get_strings_with_options(const char* path, const BARETK_AnalysisOptions* options, size_t* count);
+void baretk_free_strings(BARETK_String* strings, size_t count);
+
+// Writes the listing to out_path and returns 1 on success.
+int baretk_disassemble_from_file(const char* path, const char* out_path);
+// The listing baretk_disassemble_from_file writes.
+char* baretk_disassemble_to_string(const char* path);
 
 typedef struct BARETK_Program* BARETK_Program;
 typedef enum BARETK_Endianess {
@@ -12,9 +119,257 @@ typedef enum BARETK_Endianess {
 } BARETK_Endianess;
 
 BARETK_Program baretk_load_program(const char* path);
+// Copies the bytes, so the buffer can be freed once this returns.
+BARETK_Program baretk_load_program_from_bytes(const uint8_t* bytes, size_t size);
+// Loads with the options' arch and, for raw binaries, base_addr.
+BARETK_Program baretk_load_program_with_options(const char* path, const BARETK_AnalysisOptions* options);
 void baretk_free_program(BARETK_Program program);
+// An independent copy of the program, freed on its own.
+BARETK_Program baretk_clone_program(BARETK_Program program);
 BARETK_Endianess baretk_get_endianess(BARETK_Program program);
+// Owned by the program and valid until it's freed.
 const char* baretk_get_machine_type(BARETK_Program program);
+char* baretk_copy_machine_type(BARETK_Program program);
+uint64_t baretk_get_entry_point(BARETK_Program program);
+// 32 or 64, or 0 for raw binaries.
+int baretk_get_bits(BARETK_Program program);
+
+typedef enum BARETK_FileType {
+    BARETK_FILE_RAW = 0,
+    BARETK_FILE_ELF = 1,
+    BARETK_FILE_PE = 2,
+    // Recognised by baretk_get_file_info, but loaded as a raw binary.
+    BARETK_FILE_MACHO = 3,
+} BARETK_FileType;
+
+BARETK_FileType baretk_get_file_type(BARETK_Program program);
+
+// What loading worked around in a malformed file, one message per problem.
+// Copies are freed with baretk_free_string.
+size_t baretk_get_warning_count(BARETK_Program program);
+char* baretk_copy_warning(BARETK_Program program, size_t index);
+
+// What a file is, read from its first header only.
+typedef struct BARETK_FileInfo {
+    BARETK_FileType file_type;
+    // NUL-terminated machine type as baretk_get_machine_type names it, or "unknown".
+    char machine_type[16];
+    // 32 or 64, or 0 when the headers don't say.
+    int bits;
+    BARETK_Endianess endianess;
+} BARETK_FileInfo;
+
+// Fill in *info without loading the program and return 1 on success.
+int baretk_get_file_info(const char* path, BARETK_FileInfo* info);
+int baretk_get_file_info_from_bytes(const uint8_t* bytes, size_t size, BARETK_FileInfo* info);
+
+#define BARETK_PERM_EXEC  0x1
+#define BARETK_PERM_WRITE 0x2
+#define BARETK_PERM_READ  0x4
+
+// Segments as the program headers give them, in file order.
+typedef struct BARETK_Segment {
+    uint64_t offset;
+    uint64_t vaddr;
+    uint64_t paddr;
+    uint64_t size;
+    // BARETK_PERM_* flags.
+    uint8_t perm;
+} BARETK_Segment;
+
+size_t baretk_get_segment_count(BARETK_Program program);
+int baretk_get_segment(BARETK_Program program, size_t index, BARETK_Segment* out);
+
+typedef struct BARETK_Symbol {
+    // Owned by the program and valid until it's freed.
+    const char* name;
+    uint64_t addr;
+    uint64_t size;
+    int is_function;
+} BARETK_Symbol;
+
+// Symbols fill *out and return 1, or return 0 when there's none.
+size_t baretk_get_symbol_count(BARETK_Program program);
+int baretk_get_symbol(BARETK_Program program, size_t index, BARETK_Symbol* out);
+int baretk_find_symbol(BARETK_Program program, const char* name, BARETK_Symbol* out);
+// The symbol whose range contains addr.
+int baretk_symbol_at(BARETK_Program program, uint64_t addr, BARETK_Symbol* out);
+
+typedef struct BARETK_Section {
+    // Owned by the program and valid until it's freed.
+    const char* name;
+    uint64_t addr;
+    uint64_t size;
+    // File offset of the section's bytes.
+    uint64_t offset;
+    // BARETK_PERM_* flags.
+    uint8_t perm;
+    // The section's size bytes.
+    const uint8_t* bytes;
+} BARETK_Section;
+
+// Sections are ordered by address, then name. Like symbols, they fill *out
+// and return 1, or return 0 when there's none.
+size_t baretk_get_section_count(BARETK_Program program);
+int baretk_get_section(BARETK_Program program, size_t index, BARETK_Section* out);
+char* baretk_copy_section_name(BARETK_Program program, size_t index);
+// A copy of the section's bytes, with their number in *size. Free it with
+// baretk_free_bytes and the same size.
+uint8_t* baretk_copy_section_bytes(BARETK_Program program, size_t index, size_t* size);
+void baretk_free_bytes(uint8_t* bytes, size_t size);
+int baretk_find_section(BARETK_Program program, const char* name, BARETK_Section* out);
+
+// The instructions of every executable section, in address order.
+typedef struct BARETK_Disassembly* BARETK_Disassembly;
+
+// Bits of baretk_instruction_kind.
+#define BARETK_KIND_JUMP        0x1
+#define BARETK_KIND_CALL        0x2
+#define BARETK_KIND_RETURN      0x4
+#define BARETK_KIND_SYSCALL     0x8
+#define BARETK_KIND_CONDITIONAL 0x10
+#define BARETK_KIND_INDIRECT    0x20
+#define BARETK_KIND_MEMORY      0x40
+#define BARETK_KIND_SETS_FLAGS  0x80
+
+BARETK_Disassembly baretk_disassemble(const char* path);
+// Borrows the program: it stays valid and must still be freed.
+BARETK_Disassembly baretk_disassemble_program(BARETK_Program program);
+// Disassembles with the options' sweep, arch and base_addr. Their show_bytes
+// applies to baretk_disassembly_get_text with NULL options.
+BARETK_Disassembly baretk_disassemble_program_with_options(BARETK_Program program, const BARETK_AnalysisOptions* options);
+void baretk_free_disassembly(BARETK_Disassembly dis);
+
+// How to decode a buffer of code with no headers, such as shellcode.
+typedef struct BARETK_BufferSpec {
+    // An "arch:" name from baretk_has_feature, such as "amd64".
+    const char* arch;
+    // 32 or 64, or 0 for the architecture's usual width.
+    int bits;
+    // 0 for little endian.
+    BARETK_Endianess endianess;
+    // Address of the first byte.
+    uint64_t base_addr;
+} BARETK_BufferSpec;
+
+// Disassembles the whole buffer as code; the bytes are copied.
+BARETK_Disassembly baretk_disassemble_buffer(const uint8_t* bytes, size_t size, const BARETK_BufferSpec* spec);
+// An instruction passed to a disassembly callback. The strings are only
+// valid until the callback returns.
+typedef struct BARETK_InstructionInfo {
+    uint64_t addr;
+    size_t size;
+    // BARETK_KIND_* flags.
+    uint32_t kind;
+    const char* mnemonic;
+    const char* operands;
+    // Offset of the instruction in its section and the section's size, for
+    // showing progress.
+    size_t offset;
+    size_t section_size;
+} BARETK_InstructionInfo;
+
+// Returns nonzero to go on to the next instruction, or 0 to stop.
+typedef int (*BARETK_InstructionCallback)(void* user_data, const BARETK_InstructionInfo* ins);
+
+// Decodes every executable section one instruction at a time without keeping
+// a listing, in the same order as the instructions of a BARETK_Disassembly.
+// Returns 1 once the last section ends or the callback stops it, or 0 with
+// BARETK_ERROR_NOT_FOUND if the program has no executable section.
+int baretk_disassemble_with_callback(BARETK_Program program, BARETK_InstructionCallback callback, void* user_data);
+
+typedef struct BARETK_DisassemblyTextOptions {
+    // Show each instruction's bytes after its text.
+    int show_bytes;
+    BARETK_Syntax syntax;
+    // Addresses of the instructions to print; an end of 0 prints to the end of the last section.
+    uint64_t start;
+    uint64_t end;
+} BARETK_DisassemblyTextOptions;
+
+// The listing as an owned string. NULL options give the whole listing, with
+// bytes unless the disassembly's analysis options left them out.
+char* baretk_disassembly_get_text(BARETK_Disassembly dis, const BARETK_DisassemblyTextOptions* options);
+// The sections decoded, in address order. Their instructions follow each
+// other in the same order.
+size_t baretk_disassembly_section_count(BARETK_Disassembly dis);
+char* baretk_disassembly_copy_section_name(BARETK_Disassembly dis, size_t index);
+uint64_t baretk_disassembly_section_address(BARETK_Disassembly dis, size_t index);
+size_t baretk_instruction_count(BARETK_Disassembly dis);
+uint64_t baretk_instruction_address(BARETK_Disassembly dis, size_t index);
+size_t baretk_instruction_size(BARETK_Disassembly dis, size_t index);
+uint32_t baretk_instruction_kind(BARETK_Disassembly dis, size_t index);
+// Strings are owned by the disassembly and valid until it's freed.
+const char* baretk_instruction_mnemonic(BARETK_Disassembly dis, size_t index);
+const char* baretk_instruction_operands(BARETK_Disassembly dis, size_t index);
+size_t baretk_instruction_operand_count(BARETK_Disassembly dis, size_t index);
+const char* baretk_instruction_operand(BARETK_Disassembly dis, size_t index, size_t operand);
+
+typedef struct BARETK_Decomp* BARETK_Decomp;
+
+// The names of the decompiler's output languages, "pseudo" first, with their
+// number in *count. The array and names are static.
+const char* const* baretk_list_languages(size_t* count);
+
+// A zeroed struct, like a NULL pointer, decompiles the code section to pseudocode.
+typedef struct BARETK_DecompOptions {
+    // A name from baretk_list_languages, or NULL for "pseudo".
+    const char* language;
+    // Name or "0x" address of the one function to decompile, or NULL.
+    const char* function;
+    // Show the instructions each statement came from.
+    int interleave;
+    // Sweep, arch and base_addr to disassemble with, or NULL for the defaults.
+    const BARETK_AnalysisOptions* analysis;
+} BARETK_DecompOptions;
+
+BARETK_Decomp baretk_decompile(const char* path, const BARETK_DecompOptions* options);
+// Borrows the program: it stays valid and must still be freed.
+BARETK_Decomp baretk_decompile_program(BARETK_Program program, const BARETK_DecompOptions* options);
+void baretk_free_decomp(BARETK_Decomp decomp);
+char* baretk_decomp_get_text(BARETK_Decomp decomp);
+
+// The library's diagnostics, such as truncated sections or unknown opcodes,
+// go to a log callback instead of being printed. Contexts each have their
+// own callback: functions taking a context use it on the calling thread until
+// they return, so hosts can run analyses on several threads, each with its
+// own context, or share one context. Calls without a context, or with one
+// that has no callback, use the callback of baretk_set_log_callback, and
+// diagnostics are dropped if that isn't set either.
+typedef struct BARETK_Context* BARETK_Context;
+
+typedef enum BARETK_LogLevel {
+    BARETK_LOG_OFF = 0,
+    BARETK_LOG_ERROR = 1,
+    BARETK_LOG_WARN = 2,
+    BARETK_LOG_INFO = 3,
+    BARETK_LOG_DEBUG = 4,
+    BARETK_LOG_TRACE = 5,
+} BARETK_LogLevel;
+
+// Called on the thread running the analysis; the message is valid until it returns.
+typedef void (*BARETK_LogCallback)(void* user_data, BARETK_LogLevel level, const char* message);
+
+// Messages up to max_level go to the callback; a NULL callback drops them.
+void baretk_set_log_callback(BARETK_LogCallback callback, void* user_data, BARETK_LogLevel max_level);
+
+BARETK_Context baretk_context_new(void);
+void baretk_context_free(BARETK_Context context);
+// Messages up to max_level go to the callback; a NULL callback sends them to
+// the one of baretk_set_log_callback.
+void baretk_context_set_log_callback(BARETK_Context context, BARETK_LogCallback callback, void* user_data, BARETK_LogLevel max_level);
+
+// Stops the disassemblies and decompilations running with the context, which
+// then return NULL with BARETK_ERROR_CANCELLED. Can be called from any
+// thread. The context stays cancelled until baretk_reset_cancel.
+void baretk_cancel(BARETK_Context context);
+void baretk_reset_cancel(BARETK_Context context);
+
+BARETK_Program baretk_context_load_program(BARETK_Context context, const char* path);
+BARETK_Program baretk_context_load_program_from_bytes(BARETK_Context context, const uint8_t* bytes, size_t size);
+BARETK_Disassembly baretk_context_disassemble_program(BARETK_Context context, BARETK_Program program);
+BARETK_Disassembly baretk_context_disassemble_program_with_options(BARETK_Context context, BARETK_Program program, const BARETK_AnalysisOptions* options);
+BARETK_Decomp baretk_context_decompile_program(BARETK_Context context, BARETK_Program program, const BARETK_DecompOptions* options);
 
 #endif // BARETK_H_INCLUDED
 
@@ -0,0 +1,1500 @@
+// A64, the instruction set of 64-bit ARM. Instructions are always one
+// little-endian word, whatever the data endianness. The base general purpose
+// instructions, the LSE atomics, crc32 and the pointer authentication branches
+// are decoded; SIMD, floating point, MTE and the rest of pointer authentication
+// are listed as unknown.
+
+use crate::arm::cond_to_dis;
+use crate::cancel::CancelToken;
+use crate::dis::{self, DisassemblySection};
+use crate::prog::{Section, Program};
+use crate::reg::Reg;
+use crate::util::{read_u32_from_slice, BitExtr, LITTLE_ENDIAN};
+
+const COND_AL: u8 = 0xe;
+
+const COND_NAMES: [&str; 16] = [
+    "eq", "ne", "hs", "lo", "mi", "pl", "vs", "vc",
+    "hi", "ls", "ge", "lt", "gt", "le", "al", "nv",
+];
+
+const SHIFT_LSL: u8 = 0x0;
+const SHIFT_LSR: u8 = 0x1;
+const SHIFT_ASR: u8 = 0x2;
+const SHIFT_ROR: u8 = 0x3;
+
+const SHIFT_NAMES: [&str; 4] = ["lsl", "lsr", "asr", "ror"];
+
+// Extends of add/sub and register offset operands. A register offset's uxtx
+// is shown as lsl.
+const EXTEND_UXTW: u8 = 0x2;
+const EXTEND_UXTX: u8 = 0x3;
+
+const EXTEND_NAMES: [&str; 8] = ["uxtb", "uxth", "uxtw", "uxtx", "sxtb", "sxth", "sxtw", "sxtx"];
+
+// Addressing modes of loads and stores.
+const ADDR_OFFSET: u8 = 0x0;
+const ADDR_PRE: u8 = 0x1;
+const ADDR_POST: u8 = 0x2;
+
+// Ordering of atomics, written a and l after the mnemonic.
+const ORDER_ACQUIRE: u8 = 0x1;
+const ORDER_RELEASE: u8 = 0x2;
+
+const BARRIER_NAMES: [&str; 16] = [
+    "", "oshld", "oshst", "osh", "", "nshld", "nshst", "nsh",
+    "", "ishld", "ishst", "ish", "", "ld", "st", "sy",
+];
+
+// Prefetch operations by the Rt field of prfm: type, cache level and policy.
+const PREFETCH_NAMES: [&str; 24] = [
+    "pldl1keep", "pldl1strm", "pldl2keep", "pldl2strm", "pldl3keep", "pldl3strm", "", "",
+    "plil1keep", "plil1strm", "plil2keep", "plil2strm", "plil3keep", "plil3strm", "", "",
+    "pstl1keep", "pstl1strm", "pstl2keep", "pstl2strm", "pstl3keep", "pstl3strm", "", "",
+];
+
+// System registers by op0, op1, CRn, CRm and op2, packed like in mrs and msr.
+const fn sysreg(op0: u16, op1: u16, crn: u16, crm: u16, op2: u16) -> u16 {
+    op0 << 14 | op1 << 11 | crn << 7 | crm << 3 | op2
+}
+
+const SYSREG_NAMES: &[(u16, &str)] = &[
+    (sysreg(3, 3, 13, 0, 2), "tpidr_el0"),
+    (sysreg(3, 3, 13, 0, 3), "tpidrro_el0"),
+    (sysreg(3, 3, 4, 2, 0), "nzcv"),
+    (sysreg(3, 3, 4, 2, 1), "daif"),
+    (sysreg(3, 3, 4, 4, 0), "fpcr"),
+    (sysreg(3, 3, 4, 4, 1), "fpsr"),
+    (sysreg(3, 3, 0, 0, 1), "ctr_el0"),
+    (sysreg(3, 3, 0, 0, 7), "dczid_el0"),
+    (sysreg(3, 3, 14, 0, 0), "cntfrq_el0"),
+    (sysreg(3, 3, 14, 0, 1), "cntpct_el0"),
+    (sysreg(3, 3, 14, 0, 2), "cntvct_el0"),
+    (sysreg(3, 0, 0, 0, 0), "midr_el1"),
+    (sysreg(3, 0, 0, 0, 5), "mpidr_el1"),
+    (sysreg(3, 0, 1, 0, 0), "sctlr_el1"),
+    (sysreg(3, 0, 2, 0, 0), "ttbr0_el1"),
+    (sysreg(3, 0, 2, 0, 1), "ttbr1_el1"),
+    (sysreg(3, 0, 2, 0, 2), "tcr_el1"),
+    (sysreg(3, 0, 4, 0, 0), "spsr_el1"),
+    (sysreg(3, 0, 4, 0, 1), "elr_el1"),
+    (sysreg(3, 0, 4, 1, 0), "sp_el0"),
+    (sysreg(3, 0, 4, 2, 2), "currentel"),
+    (sysreg(3, 0, 5, 2, 0), "esr_el1"),
+    (sysreg(3, 0, 6, 0, 0), "far_el1"),
+    (sysreg(3, 0, 10, 2, 0), "mair_el1"),
+    (sysreg(3, 0, 12, 0, 0), "vbar_el1"),
+    (sysreg(3, 0, 13, 0, 4), "tpidr_el1"),
+    (sysreg(3, 4, 1, 1, 0), "hcr_el2"),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Add,
+    Sub,
+    Adc,
+    Sbc,
+    Neg,
+    Cmp,
+    Cmn,
+    Mov,
+    Movz,
+    Movn,
+    Movk,
+    And,
+    Orr,
+    Eor,
+    Bic,
+    Orn,
+    Eon,
+    Mvn,
+    Tst,
+    Lsl,
+    Lsr,
+    Asr,
+    Ror,
+    Sxtb,
+    Sxth,
+    Sxtw,
+    Uxtb,
+    Uxth,
+    Sbfx,
+    Ubfx,
+    Sbfiz,
+    Ubfiz,
+    Bfi,
+    Bfxil,
+    Extr,
+    Adr,
+    Adrp,
+    Mul,
+    Mneg,
+    Madd,
+    Msub,
+    Smull,
+    Smaddl,
+    Smsubl,
+    Umull,
+    Umaddl,
+    Umsubl,
+    Smulh,
+    Umulh,
+    Udiv,
+    Sdiv,
+    Rbit,
+    Rev16,
+    Rev32,
+    Rev,
+    Clz,
+    Cls,
+    Crc32b,
+    Crc32h,
+    Crc32w,
+    Crc32x,
+    Crc32cb,
+    Crc32ch,
+    Crc32cw,
+    Crc32cx,
+    Csel,
+    Csinc,
+    Csinv,
+    Csneg,
+    Cset,
+    Csetm,
+    Cinc,
+    Cinv,
+    Cneg,
+    Ccmp,
+    Ccmn,
+    B,
+    Bl,
+    Br,
+    Blr,
+    Ret,
+    Retaa,
+    Retab,
+    Braa,
+    Brab,
+    Blraa,
+    Blrab,
+    Braaz,
+    Brabz,
+    Blraaz,
+    Blrabz,
+    Cbz,
+    Cbnz,
+    Tbz,
+    Tbnz,
+    Svc,
+    Hvc,
+    Smc,
+    Brk,
+    Hlt,
+    Udf,
+    Nop,
+    Yield,
+    Wfe,
+    Wfi,
+    Sev,
+    Sevl,
+    Csdb,
+    Paciasp,
+    Pacibsp,
+    Autiasp,
+    Autibsp,
+    Bti,
+    Hint,
+    Dmb,
+    Dsb,
+    Isb,
+    Clrex,
+    Mrs,
+    Msr,
+    Ldr,
+    Ldrs,
+    Str,
+    Ldur,
+    Ldurs,
+    Stur,
+    Ldtr,
+    Ldtrs,
+    Sttr,
+    Ldapur,
+    Ldapurs,
+    Stlur,
+    Ldp,
+    Ldpsw,
+    Stp,
+    Ldnp,
+    Stnp,
+    Ldxr,
+    Ldaxr,
+    Stxr,
+    Stlxr,
+    Ldar,
+    Stlr,
+    Ldlar,
+    Stllr,
+    Ldapr,
+    Ldxp,
+    Ldaxp,
+    Stxp,
+    Stlxp,
+    Cas,
+    Casp,
+    Swp,
+    Ldadd,
+    Ldclr,
+    Ldeor,
+    Ldset,
+    Ldsmax,
+    Ldsmin,
+    Ldumax,
+    Ldumin,
+    Stadd,
+    Stclr,
+    Steor,
+    Stset,
+    Stsmax,
+    Stsmin,
+    Stumax,
+    Stumin,
+    Prfm,
+    Prfum,
+    Unknown,
+}
+
+impl Operation {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Add     => "add",
+            Self::Sub     => "sub",
+            Self::Adc     => "adc",
+            Self::Sbc     => "sbc",
+            Self::Neg     => "neg",
+            Self::Cmp     => "cmp",
+            Self::Cmn     => "cmn",
+            Self::Mov     => "mov",
+            Self::Movz    => "movz",
+            Self::Movn    => "movn",
+            Self::Movk    => "movk",
+            Self::And     => "and",
+            Self::Orr     => "orr",
+            Self::Eor     => "eor",
+            Self::Bic     => "bic",
+            Self::Orn     => "orn",
+            Self::Eon     => "eon",
+            Self::Mvn     => "mvn",
+            Self::Tst     => "tst",
+            Self::Lsl     => "lsl",
+            Self::Lsr     => "lsr",
+            Self::Asr     => "asr",
+            Self::Ror     => "ror",
+            Self::Sxtb    => "sxtb",
+            Self::Sxth    => "sxth",
+            Self::Sxtw    => "sxtw",
+            Self::Uxtb    => "uxtb",
+            Self::Uxth    => "uxth",
+            Self::Sbfx    => "sbfx",
+            Self::Ubfx    => "ubfx",
+            Self::Sbfiz   => "sbfiz",
+            Self::Ubfiz   => "ubfiz",
+            Self::Bfi     => "bfi",
+            Self::Bfxil   => "bfxil",
+            Self::Extr    => "extr",
+            Self::Adr     => "adr",
+            Self::Adrp    => "adrp",
+            Self::Mul     => "mul",
+            Self::Mneg    => "mneg",
+            Self::Madd    => "madd",
+            Self::Msub    => "msub",
+            Self::Smull   => "smull",
+            Self::Smaddl  => "smaddl",
+            Self::Smsubl  => "smsubl",
+            Self::Umull   => "umull",
+            Self::Umaddl  => "umaddl",
+            Self::Umsubl  => "umsubl",
+            Self::Smulh   => "smulh",
+            Self::Umulh   => "umulh",
+            Self::Udiv    => "udiv",
+            Self::Sdiv    => "sdiv",
+            Self::Rbit    => "rbit",
+            Self::Rev16   => "rev16",
+            Self::Rev32   => "rev32",
+            Self::Rev     => "rev",
+            Self::Clz     => "clz",
+            Self::Cls     => "cls",
+            Self::Crc32b  => "crc32b",
+            Self::Crc32h  => "crc32h",
+            Self::Crc32w  => "crc32w",
+            Self::Crc32x  => "crc32x",
+            Self::Crc32cb => "crc32cb",
+            Self::Crc32ch => "crc32ch",
+            Self::Crc32cw => "crc32cw",
+            Self::Crc32cx => "crc32cx",
+            Self::Csel    => "csel",
+            Self::Csinc   => "csinc",
+            Self::Csinv   => "csinv",
+            Self::Csneg   => "csneg",
+            Self::Cset    => "cset",
+            Self::Csetm   => "csetm",
+            Self::Cinc    => "cinc",
+            Self::Cinv    => "cinv",
+            Self::Cneg    => "cneg",
+            Self::Ccmp    => "ccmp",
+            Self::Ccmn    => "ccmn",
+            Self::B       => "b",
+            Self::Bl      => "bl",
+            Self::Br      => "br",
+            Self::Blr     => "blr",
+            Self::Ret     => "ret",
+            Self::Retaa   => "retaa",
+            Self::Retab   => "retab",
+            Self::Braa    => "braa",
+            Self::Brab    => "brab",
+            Self::Blraa   => "blraa",
+            Self::Blrab   => "blrab",
+            Self::Braaz   => "braaz",
+            Self::Brabz   => "brabz",
+            Self::Blraaz  => "blraaz",
+            Self::Blrabz  => "blrabz",
+            Self::Cbz     => "cbz",
+            Self::Cbnz    => "cbnz",
+            Self::Tbz     => "tbz",
+            Self::Tbnz    => "tbnz",
+            Self::Svc     => "svc",
+            Self::Hvc     => "hvc",
+            Self::Smc     => "smc",
+            Self::Brk     => "brk",
+            Self::Hlt     => "hlt",
+            Self::Udf     => "udf",
+            Self::Nop     => "nop",
+            Self::Yield   => "yield",
+            Self::Wfe     => "wfe",
+            Self::Wfi     => "wfi",
+            Self::Sev     => "sev",
+            Self::Sevl    => "sevl",
+            Self::Csdb    => "csdb",
+            Self::Paciasp => "paciasp",
+            Self::Pacibsp => "pacibsp",
+            Self::Autiasp => "autiasp",
+            Self::Autibsp => "autibsp",
+            Self::Bti     => "bti",
+            Self::Hint    => "hint",
+            Self::Dmb     => "dmb",
+            Self::Dsb     => "dsb",
+            Self::Isb     => "isb",
+            Self::Clrex   => "clrex",
+            Self::Mrs     => "mrs",
+            Self::Msr     => "msr",
+            Self::Ldr     => "ldr",
+            Self::Ldrs    => "ldrs",
+            Self::Str     => "str",
+            Self::Ldur    => "ldur",
+            Self::Ldurs   => "ldurs",
+            Self::Stur    => "stur",
+            Self::Ldtr    => "ldtr",
+            Self::Ldtrs   => "ldtrs",
+            Self::Sttr    => "sttr",
+            Self::Ldapur  => "ldapur",
+            Self::Ldapurs => "ldapurs",
+            Self::Stlur   => "stlur",
+            Self::Ldp     => "ldp",
+            Self::Ldpsw   => "ldpsw",
+            Self::Stp     => "stp",
+            Self::Ldnp    => "ldnp",
+            Self::Stnp    => "stnp",
+            Self::Ldxr    => "ldxr",
+            Self::Ldaxr   => "ldaxr",
+            Self::Stxr    => "stxr",
+            Self::Stlxr   => "stlxr",
+            Self::Ldar    => "ldar",
+            Self::Stlr    => "stlr",
+            Self::Ldlar   => "ldlar",
+            Self::Stllr   => "stllr",
+            Self::Ldapr   => "ldapr",
+            Self::Ldxp    => "ldxp",
+            Self::Ldaxp   => "ldaxp",
+            Self::Stxp    => "stxp",
+            Self::Stlxp   => "stlxp",
+            Self::Cas     => "cas",
+            Self::Casp    => "casp",
+            Self::Swp     => "swp",
+            Self::Ldadd   => "ldadd",
+            Self::Ldclr   => "ldclr",
+            Self::Ldeor   => "ldeor",
+            Self::Ldset   => "ldset",
+            Self::Ldsmax  => "ldsmax",
+            Self::Ldsmin  => "ldsmin",
+            Self::Ldumax  => "ldumax",
+            Self::Ldumin  => "ldumin",
+            Self::Stadd   => "stadd",
+            Self::Stclr   => "stclr",
+            Self::Steor   => "steor",
+            Self::Stset   => "stset",
+            Self::Stsmax  => "stsmax",
+            Self::Stsmin  => "stsmin",
+            Self::Stumax  => "stumax",
+            Self::Stumin  => "stumin",
+            Self::Prfm    => "prfm",
+            Self::Prfum   => "prfum",
+            Self::Unknown => "???",
+        }
+    }
+
+    // Whether the mnemonic gets the size of the access, e.g. ldrb, ldrsw.
+    fn sized(self) -> bool {
+        matches!(self, Self::Ldr | Self::Ldrs | Self::Str | Self::Ldur | Self::Ldurs | Self::Stur
+            | Self::Ldtr | Self::Ldtrs | Self::Sttr | Self::Ldapur | Self::Ldapurs | Self::Stlur
+            | Self::Ldxr | Self::Ldaxr | Self::Stxr | Self::Stlxr | Self::Ldar | Self::Stlr
+            | Self::Ldlar | Self::Stllr | Self::Ldapr | Self::Cas | Self::Swp) || self.atomic().is_some()
+    }
+
+    // The store alias of an atomic load, used when the loaded value is discarded.
+    fn atomic(self) -> Option<Self> {
+        match self {
+            Self::Ldadd | Self::Stadd => Some(Self::Stadd),
+            Self::Ldclr | Self::Stclr => Some(Self::Stclr),
+            Self::Ldeor | Self::Steor => Some(Self::Steor),
+            Self::Ldset | Self::Stset => Some(Self::Stset),
+            Self::Ldsmax | Self::Stsmax => Some(Self::Stsmax),
+            Self::Ldsmin | Self::Stsmin => Some(Self::Stsmin),
+            Self::Ldumax | Self::Stumax => Some(Self::Stumax),
+            Self::Ldumin | Self::Stumin => Some(Self::Stumin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operand {
+    Nothing,
+    Reg(Reg),
+    // Register, shift type and amount.
+    Shifted(Reg, u8, u8),
+    // Register, extend and left shift amount.
+    Extended(Reg, u8, u8),
+    Imm(i64),
+    // Logical immediates, shown in hex.
+    Mask(u64),
+    // An immediate and the amount it's shifted left by, e.g. "#1, lsl #12".
+    ShiftedImm(u64, u8),
+    // Offset from the instruction's own address.
+    Rel(i64),
+    // Base register and offset; the instruction's addressing mode says
+    // whether the base is written back before or after the access.
+    Mem(Reg, i64),
+    // Base and index registers, with the index's extend and, if it's shown,
+    // its shift amount.
+    MemReg(Reg, Reg, u8, Option<u8>),
+    // Consecutive registers of casp, shown as two operands.
+    Pair(Reg, Reg),
+    Cond(u8),
+    SysReg(u16),
+    Name(&'static str),
+}
+
+impl Operand {
+    fn print(self, mode: u8) -> String {
+        match self {
+            Self::Reg(r) => r.to_string(),
+            Self::Shifted(r, SHIFT_LSL, 0) => r.to_string(),
+            Self::Shifted(r, shift, amount) => format!("{}, {} #{}", r, SHIFT_NAMES[shift as usize], amount),
+            Self::Extended(r, extend, 0) => format!("{}, {}", r, EXTEND_NAMES[extend as usize]),
+            Self::Extended(r, extend, amount) => format!("{}, {} #{}", r, EXTEND_NAMES[extend as usize], amount),
+            Self::Imm(x) => format!("#{}", x),
+            Self::Mask(x) => format!("#{:#x}", x),
+            Self::ShiftedImm(x, 0) => format!("#{}", x),
+            Self::ShiftedImm(x, shift) => format!("#{}, lsl #{}", x, shift),
+            Self::Rel(x) => x.to_string(),
+            Self::Mem(base, offset) => match mode {
+                ADDR_PRE => format!("[{}, #{}]!", base, offset),
+                ADDR_POST => format!("[{}], #{}", base, offset),
+                _ if offset == 0 => format!("[{}]", base),
+                _ => format!("[{}, #{}]", base, offset),
+            },
+            Self::MemReg(base, index, EXTEND_UXTX, None) => format!("[{}, {}]", base, index),
+            Self::MemReg(base, index, extend, None) => format!("[{}, {}, {}]", base, index, EXTEND_NAMES[extend as usize]),
+            Self::MemReg(base, index, EXTEND_UXTX, Some(amount)) => format!("[{}, {}, lsl #{}]", base, index, amount),
+            Self::MemReg(base, index, extend, Some(amount)) => format!("[{}, {}, {} #{}]", base, index, EXTEND_NAMES[extend as usize], amount),
+            Self::Pair(first, second) => format!("{}, {}", first, second),
+            Self::Cond(cond) => COND_NAMES[cond as usize].to_string(),
+            Self::SysReg(enc) => match SYSREG_NAMES.iter().find(|(e, _)| *e == enc) {
+                Some((_, name)) => name.to_string(),
+                None => format!("s{}_{}_c{}_c{}_{}", enc >> 14, (enc >> 11) & 0x7, (enc >> 7) & 0xf, (enc >> 3) & 0xf, enc & 0x7),
+            },
+            Self::Name(name) => name.to_string(),
+            Self::Nothing => "???".to_string(),
+        }
+    }
+
+    fn into(self) -> dis::Operand {
+        match self {
+            Self::Reg(r) | Self::Pair(r, _) | Self::Shifted(r, SHIFT_LSL, 0) | Self::Extended(r, _, 0) => dis::Operand::Register(r),
+            Self::Shifted(r, shift, amount) => dis::Operand::ShiftedRegister(r, shift, amount.into()),
+            Self::Extended(r, _, amount) => dis::Operand::ShiftedRegister(r, SHIFT_LSL, amount.into()),
+            Self::Imm(x) | Self::Rel(x) => dis::Operand::Immediate(x),
+            Self::Mask(x) => dis::Operand::Immediate(x as i64),
+            Self::ShiftedImm(x, shift) => dis::Operand::Immediate((x << shift) as i64),
+            _ => dis::Operand::Nothing,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Instruction {
+    operation: Operation,
+    // Condition of b.cond.
+    cond: u8,
+    set_flags: bool,
+    operands: [Operand; 4],
+    addr_mode: u8,
+    // Acquire and release ordering of atomics.
+    order: u8,
+    // Bytes read or written by loads and stores, per register.
+    access_size: u8,
+    offset: usize,
+}
+
+impl Instruction {
+    fn mnemonic(self) -> String {
+        let name = self.operation.name();
+        if self.operation == Operation::B && self.cond != COND_AL {
+            return format!("b.{}", COND_NAMES[self.cond as usize])
+        }
+        let order = match self.order {
+            ORDER_ACQUIRE => "a",
+            ORDER_RELEASE => "l",
+            0 => "",
+            _ => "al",
+        };
+        if self.operation.sized() {
+            let suffix = match (self.access_size, self.operation) {
+                (1, _) => "b",
+                (2, _) => "h",
+                (4, Operation::Ldrs | Operation::Ldurs | Operation::Ldtrs | Operation::Ldapurs) => "w",
+                _ => "",
+            };
+            return format!("{}{}{}", name, order, suffix)
+        }
+        let s = if self.set_flags { "s" } else { "" };
+        format!("{}{}{}", name, order, s)
+    }
+
+    pub fn print(self) -> String {
+        let operands: Vec<String> = self.operands.iter()
+            .filter(|op| **op != Operand::Nothing)
+            .map(|op| op.print(self.addr_mode))
+            .collect();
+        if operands.is_empty() {
+            self.mnemonic()
+        } else {
+            format!("{} {}", self.mnemonic(), operands.join(", "))
+        }
+    }
+
+    pub fn offset(self) -> usize {
+        self.offset
+    }
+
+    pub fn size(self) -> usize {
+        4
+    }
+
+    // The memory operand of a load or store; literal loads are relative to pc.
+    fn memory_operand(self, op: Operand) -> dis::Operand {
+        let size = self.access_size;
+        match op {
+            Operand::Mem(base, _) if self.addr_mode == ADDR_POST => dis::Operand::Memory(Some(base), None, 0, 0, size),
+            Operand::Mem(base, offset) => dis::Operand::Memory(Some(base), None, 0, offset, size),
+            Operand::MemReg(base, index, _, amount) => dis::Operand::Memory(Some(base), Some(index), 1 << amount.unwrap_or(0), 0, size),
+            Operand::Rel(offset) => dis::Operand::Memory(Some(Reg::AARCH64_PC), None, 0, offset, size),
+            _ => dis::Operand::Nothing,
+        }
+    }
+
+    fn writeback_flags(self) -> u64 {
+        match self.addr_mode {
+            ADDR_PRE => dis::FLAG_PRE_INDEX,
+            ADDR_POST => dis::FLAG_POST_INDEX,
+            _ => 0,
+        }
+    }
+
+    fn writeback_amount(self, op: Operand) -> dis::Operand {
+        match op {
+            Operand::Mem(_, offset) => dis::Operand::Immediate(offset),
+            _ => dis::Operand::Immediate(0),
+        }
+    }
+
+    // The zero register as wide as a register operand.
+    fn zero(op: Operand) -> dis::Operand {
+        match op {
+            Operand::Reg(r) => dis::Operand::Register(Reg::aarch64_sized(31, r.bits() == 64, false)),
+            _ => dis::Operand::Nothing,
+        }
+    }
+
+    pub fn into(&self) -> dis::Instruction {
+        let s = if self.set_flags { dis::FLAG_SETS_FLAGS } else { 0 };
+        let [op0, op1, op2, op3] = self.operands;
+        let instruction = |opcode: &'static str, operands: Vec<dis::Operand>, flags: u64| dis::Instruction { opcode, operands, flags };
+        // Shifts are moves of a shifted register, like on ARM.
+        let shift = |shift: u8| match op2 {
+            Operand::Reg(rm) => instruction("mov", vec![op0.into(), dis::Operand::RegisterShiftedRegister(Self::reg(op1), shift, rm)], 0),
+            Operand::Imm(amount) => instruction("mov", vec![op0.into(), dis::Operand::ShiftedRegister(Self::reg(op1), shift, amount)], 0),
+            _ => instruction("unk", vec![], 0),
+        };
+        match self.operation {
+            Operation::Add => instruction("add", vec![op0.into(), op1.into(), op2.into()], s),
+            Operation::Sub => instruction("sub", vec![op0.into(), op1.into(), op2.into()], s),
+            Operation::Adc => instruction("adc", vec![op0.into(), op1.into(), op2.into()], s),
+            Operation::Sbc => instruction("sbc", vec![op0.into(), op1.into(), op2.into()], s),
+            Operation::Neg => instruction("sub", vec![op0.into(), Self::zero(op0), op1.into()], s),
+            Operation::Cmp => instruction("cmp", vec![op0.into(), op1.into()], 0),
+            Operation::Cmn => instruction("cmn", vec![op0.into(), op1.into()], 0),
+            Operation::Tst => instruction("tst", vec![op0.into(), op1.into()], 0),
+            Operation::Mov => instruction("mov", vec![op0.into(), op1.into()], 0),
+            Operation::And => instruction("and", vec![op0.into(), op1.into(), op2.into()], s),
+            Operation::Orr => instruction("or", vec![op0.into(), op1.into(), op2.into()], 0),
+            Operation::Eor => instruction("xor", vec![op0.into(), op1.into(), op2.into()], 0),
+            Operation::Bic => instruction("bic", vec![op0.into(), op1.into(), op2.into()], s),
+            Operation::Mvn => instruction("not", vec![op0.into(), op1.into()], 0),
+            Operation::Lsl => shift(SHIFT_LSL),
+            Operation::Lsr => shift(SHIFT_LSR),
+            Operation::Asr => shift(SHIFT_ASR),
+            Operation::Ror => shift(SHIFT_ROR),
+            Operation::Uxtb => instruction("and", vec![op0.into(), op1.into(), dis::Operand::Immediate(0xff)], 0),
+            Operation::Uxth => instruction("and", vec![op0.into(), op1.into(), dis::Operand::Immediate(0xffff)], 0),
+            Operation::Mul => instruction("mul", vec![op0.into(), op1.into(), op2.into()], 0),
+            Operation::Madd => instruction("mla", vec![op0.into(), op1.into(), op2.into(), op3.into()], 0),
+            Operation::B => instruction("b", vec![op0.into()], cond_to_dis(self.cond)),
+            Operation::Br => instruction("b", vec![op0.into()], 0),
+            Operation::Braa | Operation::Brab | Operation::Braaz | Operation::Brabz => instruction("b", vec![op0.into()], 0),
+            Operation::Bl | Operation::Blr | Operation::Blraa | Operation::Blrab | Operation::Blraaz | Operation::Blrabz =>
+                instruction("call", vec![op0.into()], 0),
+            Operation::Ret | Operation::Retaa | Operation::Retab => instruction("ret", vec![], 0),
+            // Compared with the zero register like RISC-V branches.
+            Operation::Cbz => instruction("beq", vec![op0.into(), Self::zero(op0), op1.into()], 0),
+            Operation::Cbnz => instruction("bne", vec![op0.into(), Self::zero(op0), op1.into()], 0),
+            Operation::Svc => instruction("svc", vec![op0.into()], 0),
+            Operation::Nop | Operation::Yield | Operation::Wfe | Operation::Wfi | Operation::Sev | Operation::Sevl
+            | Operation::Csdb | Operation::Paciasp | Operation::Pacibsp | Operation::Autiasp | Operation::Autibsp
+            | Operation::Bti | Operation::Hint | Operation::Dmb | Operation::Dsb | Operation::Isb | Operation::Clrex
+            | Operation::Prfm | Operation::Prfum => instruction("nop", vec![], 0),
+            Operation::Ldr | Operation::Ldur | Operation::Ldxr | Operation::Ldaxr | Operation::Ldar | Operation::Ldtr
+            | Operation::Ldapur | Operation::Ldlar | Operation::Ldapr =>
+                instruction("ldr", vec![op0.into(), self.memory_operand(op1), self.writeback_amount(op1)], self.writeback_flags()),
+            Operation::Ldrs | Operation::Ldurs | Operation::Ldtrs | Operation::Ldapurs =>
+                instruction("ldrs", vec![op0.into(), self.memory_operand(op1), self.writeback_amount(op1)], self.writeback_flags()),
+            Operation::Str | Operation::Stur | Operation::Stlr | Operation::Sttr | Operation::Stlur | Operation::Stllr =>
+                instruction("str", vec![op0.into(), self.memory_operand(op1), self.writeback_amount(op1)], self.writeback_flags()),
+            // Pairs of x registers are the base, the offset of the first
+            // register and how much the base is moved by, like ARM's ldm and stm.
+            Operation::Ldp | Operation::Stp | Operation::Ldnp | Operation::Stnp if self.access_size == 8 => {
+                let (base, offset) = match op2 {
+                    Operand::Mem(base, offset) => (base, offset),
+                    _ => return instruction("unk", vec![], 0),
+                };
+                let first = if self.addr_mode == ADDR_POST { 0 } else { offset };
+                let writeback = if self.addr_mode == ADDR_OFFSET { 0 } else { offset };
+                let load = matches!(self.operation, Operation::Ldp | Operation::Ldnp);
+                instruction(if load { "ldm" } else { "stm" },
+                    vec![dis::Operand::Register(base), dis::Operand::Immediate(first), dis::Operand::Immediate(writeback), op0.into(), op1.into()], 0)
+            },
+            Operation::Ldp | Operation::Stp | Operation::Ldnp | Operation::Stnp | Operation::Ldpsw =>
+                instruction(self.operation.name(), vec![op0.into(), op1.into(), self.memory_operand(op2), self.writeback_amount(op2)], self.writeback_flags()),
+            Operation::Udf | Operation::Unknown => instruction("unk", vec![], 0),
+            // The rest keep their name, with the destination first.
+            _ => {
+                let operands = self.operands.iter()
+                    .map(|op| match *op {
+                        Operand::Mem(..) | Operand::MemReg(..) => self.memory_operand(*op),
+                        op => op.into(),
+                    })
+                    .filter(|op| *op != dis::Operand::Nothing)
+                    .collect();
+                instruction(self.operation.name(), operands, s)
+            },
+        }
+    }
+
+    fn reg(op: Operand) -> Reg {
+        match op {
+            Operand::Reg(r) => r,
+            _ => Reg::aarch64_sized(31, true, false),
+        }
+    }
+}
+
+fn instruction(operation: Operation, operands: &[Operand], offset: usize) -> Instruction {
+    let mut ops = [Operand::Nothing; 4];
+    ops[..operands.len()].copy_from_slice(operands);
+    Instruction { operation, cond: COND_AL, set_flags: false, operands: ops, addr_mode: ADDR_OFFSET, order: 0, access_size: 0, offset }
+}
+
+fn unknown(offset: usize) -> Instruction {
+    instruction(Operation::Unknown, &[], offset)
+}
+
+// General register `n`, where 31 is the stack pointer if `sp` is set and the
+// zero register otherwise.
+fn gpr(n: u32, is64: bool, sp: bool) -> Operand {
+    Operand::Reg(Reg::aarch64_sized(n as u8, is64, sp))
+}
+
+fn rd(ins: u32) -> u32 {
+    ins.bextr(4, 0)
+}
+
+fn rn(ins: u32) -> u32 {
+    ins.bextr(9, 5)
+}
+
+fn rm(ins: u32) -> u32 {
+    ins.bextr(20, 16)
+}
+
+fn sf(ins: u32) -> bool {
+    ins.bextr(31, 31) != 0
+}
+
+// The low `bits` bits of value, sign extended.
+fn sign_extend(value: u32, bits: u32) -> i64 {
+    (((value << (32 - bits)) as i32) >> (32 - bits)) as i64
+}
+
+// The value of a logical immediate: a run of ones rotated within an element
+// of 2 to 64 bits, repeated across the register.
+fn decode_bit_mask(n: u32, imms: u32, immr: u32, is64: bool) -> Option<u64> {
+    let combined = (n << 6) | (!imms & 0x3f);
+    if combined == 0 || (!is64 && n != 0) {
+        return None
+    }
+    let len = 31 - combined.leading_zeros();
+    if len < 1 {
+        return None
+    }
+    let size = 1u32 << len;
+    let levels = size - 1;
+    let (s, r) = (imms & levels, immr & levels);
+    if s == levels {
+        return None
+    }
+    let ones = (1u64 << (s + 1)) - 1;
+    let mut value = if size == 64 {
+        ones.rotate_right(r)
+    } else {
+        ((ones >> r) | (ones << (size - r))) & ((1u64 << size) - 1)
+    };
+    let mut width = size;
+    while width < 64 {
+        value |= value << width;
+        width *= 2;
+    }
+    Some(if is64 { value } else { value & 0xffff_ffff })
+}
+
+fn disassemble_pc_relative(ins: u32, offset: usize) -> Instruction {
+    let imm = sign_extend((ins.bextr(23, 5) << 2) | ins.bextr(30, 29), 21);
+    if ins.bextr(31, 31) == 0 {
+        instruction(Operation::Adr, &[gpr(rd(ins), true, false), Operand::Rel(imm)], offset)
+    } else {
+        instruction(Operation::Adrp, &[gpr(rd(ins), true, false), Operand::Rel(imm << 12)], offset)
+    }
+}
+
+fn disassemble_add_sub_imm(ins: u32, offset: usize) -> Instruction {
+    let is64 = sf(ins);
+    let (sub, set_flags) = (ins.bextr(30, 30) != 0, ins.bextr(29, 29) != 0);
+    let imm = Operand::ShiftedImm(ins.bextr(21, 10).into(), if ins.bextr(22, 22) != 0 { 12 } else { 0 });
+    let rd_op = gpr(rd(ins), is64, !set_flags);
+    let rn_op = gpr(rn(ins), is64, true);
+    if !sub && !set_flags && imm == Operand::ShiftedImm(0, 0) && (rd(ins) == 31 || rn(ins) == 31) {
+        return instruction(Operation::Mov, &[rd_op, rn_op], offset)
+    }
+    if set_flags && rd(ins) == 31 {
+        return instruction(if sub { Operation::Cmp } else { Operation::Cmn }, &[rn_op, imm], offset)
+    }
+    Instruction { set_flags, ..instruction(if sub { Operation::Sub } else { Operation::Add }, &[rd_op, rn_op, imm], offset) }
+}
+
+fn disassemble_logical_imm(ins: u32, offset: usize) -> Instruction {
+    let is64 = sf(ins);
+    let opc = ins.bextr(30, 29);
+    let mask = match decode_bit_mask(ins.bextr(22, 22), ins.bextr(15, 10), ins.bextr(21, 16), is64) {
+        Some(mask) => Operand::Mask(mask),
+        None => return unknown(offset),
+    };
+    let rd_op = gpr(rd(ins), is64, opc != 3);
+    let rn_op = gpr(rn(ins), is64, false);
+    match opc {
+        0 => instruction(Operation::And, &[rd_op, rn_op, mask], offset),
+        1 if rn(ins) == 31 => instruction(Operation::Mov, &[rd_op, mask], offset),
+        1 => instruction(Operation::Orr, &[rd_op, rn_op, mask], offset),
+        2 => instruction(Operation::Eor, &[rd_op, rn_op, mask], offset),
+        _ if rd(ins) == 31 => instruction(Operation::Tst, &[rn_op, mask], offset),
+        _ => Instruction { set_flags: true, ..instruction(Operation::And, &[rd_op, rn_op, mask], offset) },
+    }
+}
+
+fn disassemble_move_wide(ins: u32, offset: usize) -> Instruction {
+    let is64 = sf(ins);
+    let shift = ins.bextr(22, 21) * 16;
+    let imm16 = ins.bextr(20, 5);
+    if !is64 && shift >= 32 {
+        return unknown(offset)
+    }
+    let rd_op = gpr(rd(ins), is64, false);
+    let value = u64::from(imm16) << shift;
+    // The value as the register holds it, signed.
+    let signed = |value: u64| if is64 { value as i64 } else { value as u32 as i32 as i64 };
+    // Shown as mov unless the other move gives the same value more simply.
+    let zero_shifted = imm16 == 0 && shift != 0;
+    match ins.bextr(30, 29) {
+        0 if !zero_shifted && (is64 || imm16 != 0xffff) => instruction(Operation::Mov, &[rd_op, Operand::Imm(signed(!value))], offset),
+        0 => instruction(Operation::Movn, &[rd_op, Operand::ShiftedImm(imm16.into(), shift as u8)], offset),
+        2 if !zero_shifted => instruction(Operation::Mov, &[rd_op, Operand::Imm(signed(value))], offset),
+        2 => instruction(Operation::Movz, &[rd_op, Operand::ShiftedImm(imm16.into(), shift as u8)], offset),
+        3 => instruction(Operation::Movk, &[rd_op, Operand::ShiftedImm(imm16.into(), shift as u8)], offset),
+        _ => unknown(offset),
+    }
+}
+
+// sbfm, bfm and ubfm, shown as the alias for what they do.
+fn disassemble_bitfield(ins: u32, offset: usize) -> Instruction {
+    let is64 = sf(ins);
+    if ins.bextr(22, 22) != u32::from(is64) {
+        return unknown(offset)
+    }
+    let size = if is64 { 64 } else { 32 };
+    let (immr, imms) = (ins.bextr(21, 16), ins.bextr(15, 10));
+    if !is64 && (immr >= 32 || imms >= 32) {
+        return unknown(offset)
+    }
+    let rd_op = gpr(rd(ins), is64, false);
+    let rn_op = gpr(rn(ins), is64, false);
+    let rn_w = gpr(rn(ins), false, false);
+    let imm = |x: u32| Operand::Imm(x.into());
+    // Inserting at lsb, or extracting from it, `width` bits.
+    let insert = [rd_op, rn_op, imm(size - immr), imm(imms + 1)];
+    let extract = [rd_op, rn_op, imm(immr), imm(imms.wrapping_sub(immr).wrapping_add(1))];
+    match ins.bextr(30, 29) {
+        0 if imms == size - 1 => instruction(Operation::Asr, &[rd_op, rn_op, imm(immr)], offset),
+        0 if immr == 0 && imms == 7 => instruction(Operation::Sxtb, &[rd_op, rn_w], offset),
+        0 if immr == 0 && imms == 15 => instruction(Operation::Sxth, &[rd_op, rn_w], offset),
+        0 if immr == 0 && imms == 31 => instruction(Operation::Sxtw, &[rd_op, rn_w], offset),
+        0 if imms < immr => instruction(Operation::Sbfiz, &insert, offset),
+        0 => instruction(Operation::Sbfx, &extract, offset),
+        1 if imms < immr => instruction(Operation::Bfi, &insert, offset),
+        1 => instruction(Operation::Bfxil, &extract, offset),
+        2 if imms == size - 1 => instruction(Operation::Lsr, &[rd_op, rn_op, imm(immr)], offset),
+        2 if imms + 1 == immr => instruction(Operation::Lsl, &[rd_op, rn_op, imm(size - 1 - imms)], offset),
+        2 if !is64 && immr == 0 && imms == 7 => instruction(Operation::Uxtb, &[rd_op, rn_w], offset),
+        2 if !is64 && immr == 0 && imms == 15 => instruction(Operation::Uxth, &[rd_op, rn_w], offset),
+        2 if imms < immr => instruction(Operation::Ubfiz, &insert, offset),
+        2 => instruction(Operation::Ubfx, &extract, offset),
+        _ => unknown(offset),
+    }
+}
+
+fn disassemble_extract(ins: u32, offset: usize) -> Instruction {
+    let is64 = sf(ins);
+    let lsb = ins.bextr(15, 10);
+    if ins.bextr(30, 29) != 0 || ins.bextr(22, 22) != u32::from(is64) || ins.bextr(21, 21) != 0 || (!is64 && lsb >= 32) {
+        return unknown(offset)
+    }
+    let (rd_op, rn_op, rm_op) = (gpr(rd(ins), is64, false), gpr(rn(ins), is64, false), gpr(rm(ins), is64, false));
+    if rn(ins) == rm(ins) {
+        instruction(Operation::Ror, &[rd_op, rn_op, Operand::Imm(lsb.into())], offset)
+    } else {
+        instruction(Operation::Extr, &[rd_op, rn_op, rm_op, Operand::Imm(lsb.into())], offset)
+    }
+}
+
+fn disassemble_data_processing_imm(ins: u32, offset: usize) -> Instruction {
+    match ins.bextr(25, 23) {
+        0b000 | 0b001 => disassemble_pc_relative(ins, offset),
+        0b010 => disassemble_add_sub_imm(ins, offset),
+        0b100 => disassemble_logical_imm(ins, offset),
+        0b101 => disassemble_move_wide(ins, offset),
+        0b110 => disassemble_bitfield(ins, offset),
+        0b111 => disassemble_extract(ins, offset),
+        _ => unknown(offset),
+    }
+}
+
+fn disassemble_exception(ins: u32, offset: usize) -> Instruction {
+    let imm = Operand::Imm(ins.bextr(20, 5).into());
+    let operation = match (ins.bextr(23, 21), ins.bextr(4, 0)) {
+        (0b000, 0b00001) => Operation::Svc,
+        (0b000, 0b00010) => Operation::Hvc,
+        (0b000, 0b00011) => Operation::Smc,
+        (0b001, 0b00000) => Operation::Brk,
+        (0b010, 0b00000) => Operation::Hlt,
+        _ => return unknown(offset),
+    };
+    instruction(operation, &[imm], offset)
+}
+
+fn disassemble_system(ins: u32, offset: usize) -> Instruction {
+    let (load, op0, op1, crn, crm, op2, rt) = (ins.bextr(21, 21) != 0, ins.bextr(20, 19), ins.bextr(18, 16),
+        ins.bextr(15, 12), ins.bextr(11, 8), ins.bextr(7, 5), ins.bextr(4, 0));
+    let sysreg = Operand::SysReg(ins.bextr(20, 5) as u16);
+    match (load, op0, op1, crn) {
+        (false, 0, 3, 2) if rt == 31 => {
+            let operation = match (crm << 3) | op2 {
+                0 => Operation::Nop,
+                1 => Operation::Yield,
+                2 => Operation::Wfe,
+                3 => Operation::Wfi,
+                4 => Operation::Sev,
+                5 => Operation::Sevl,
+                20 => Operation::Csdb,
+                25 => Operation::Paciasp,
+                27 => Operation::Pacibsp,
+                29 => Operation::Autiasp,
+                31 => Operation::Autibsp,
+                32 => return instruction(Operation::Bti, &[], offset),
+                34 => return instruction(Operation::Bti, &[Operand::Name("c")], offset),
+                36 => return instruction(Operation::Bti, &[Operand::Name("j")], offset),
+                38 => return instruction(Operation::Bti, &[Operand::Name("jc")], offset),
+                hint => return instruction(Operation::Hint, &[Operand::Imm(hint.into())], offset),
+            };
+            instruction(operation, &[], offset)
+        },
+        (false, 0, 3, 3) if rt == 31 => {
+            let option = match BARRIER_NAMES[crm as usize] {
+                "" => Operand::Imm(crm.into()),
+                name => Operand::Name(name),
+            };
+            match op2 {
+                2 if crm == 15 => instruction(Operation::Clrex, &[], offset),
+                2 => instruction(Operation::Clrex, &[Operand::Imm(crm.into())], offset),
+                4 => instruction(Operation::Dsb, &[option], offset),
+                5 => instruction(Operation::Dmb, &[option], offset),
+                6 if crm == 15 => instruction(Operation::Isb, &[], offset),
+                6 => instruction(Operation::Isb, &[Operand::Imm(crm.into())], offset),
+                _ => unknown(offset),
+            }
+        },
+        (true, 2 | 3, _, _) => instruction(Operation::Mrs, &[gpr(rt, true, false), sysreg], offset),
+        (false, 2 | 3, _, _) => instruction(Operation::Msr, &[sysreg, gpr(rt, true, false)], offset),
+        _ => unknown(offset),
+    }
+}
+
+fn disassemble_branch_register(ins: u32, offset: usize) -> Instruction {
+    if ins.bextr(20, 16) != 0b11111 {
+        return unknown(offset)
+    }
+    let target = gpr(rn(ins), true, false);
+    match (ins.bextr(24, 21), ins.bextr(15, 10), rn(ins), ins.bextr(4, 0)) {
+        (0b0000, 0, _, 0) => instruction(Operation::Br, &[target], offset),
+        (0b0001, 0, _, 0) => instruction(Operation::Blr, &[target], offset),
+        (0b0010, 0, 30, 0) => instruction(Operation::Ret, &[], offset),
+        (0b0010, 0, _, 0) => instruction(Operation::Ret, &[target], offset),
+        (0b0010, 0b000010, 31, 31) => instruction(Operation::Retaa, &[], offset),
+        (0b0010, 0b000011, 31, 31) => instruction(Operation::Retab, &[], offset),
+        // Pointer authenticated branches, with key b if bit 10 is set and a
+        // zero modifier unless bit 24 is.
+        (0b0000, 0b000010, _, 31) => instruction(Operation::Braaz, &[target], offset),
+        (0b0000, 0b000011, _, 31) => instruction(Operation::Brabz, &[target], offset),
+        (0b0001, 0b000010, _, 31) => instruction(Operation::Blraaz, &[target], offset),
+        (0b0001, 0b000011, _, 31) => instruction(Operation::Blrabz, &[target], offset),
+        (0b1000, 0b000010, _, m) => instruction(Operation::Braa, &[target, gpr(m, true, true)], offset),
+        (0b1000, 0b000011, _, m) => instruction(Operation::Brab, &[target, gpr(m, true, true)], offset),
+        (0b1001, 0b000010, _, m) => instruction(Operation::Blraa, &[target, gpr(m, true, true)], offset),
+        (0b1001, 0b000011, _, m) => instruction(Operation::Blrab, &[target, gpr(m, true, true)], offset),
+        _ => unknown(offset),
+    }
+}
+
+fn disassemble_branch_system(ins: u32, offset: usize) -> Instruction {
+    if ins & 0xff00_0010 == 0x5400_0000 {
+        let target = Operand::Rel(sign_extend(ins.bextr(23, 5), 19) << 2);
+        Instruction { cond: ins.bextr(3, 0) as u8, ..instruction(Operation::B, &[target], offset) }
+    }
+    else if ins & 0x7c00_0000 == 0x1400_0000 {
+        let target = Operand::Rel(sign_extend(ins.bextr(25, 0), 26) << 2);
+        instruction(if ins.bextr(31, 31) != 0 { Operation::Bl } else { Operation::B }, &[target], offset)
+    }
+    else if ins & 0x7e00_0000 == 0x3400_0000 {
+        let target = Operand::Rel(sign_extend(ins.bextr(23, 5), 19) << 2);
+        let operation = if ins.bextr(24, 24) != 0 { Operation::Cbnz } else { Operation::Cbz };
+        instruction(operation, &[gpr(rd(ins), sf(ins), false), target], offset)
+    }
+    else if ins & 0x7e00_0000 == 0x3600_0000 {
+        let target = Operand::Rel(sign_extend(ins.bextr(18, 5), 14) << 2);
+        let bit = (ins.bextr(31, 31) << 5) | ins.bextr(23, 19);
+        let operation = if ins.bextr(24, 24) != 0 { Operation::Tbnz } else { Operation::Tbz };
+        instruction(operation, &[gpr(rd(ins), bit >= 32, false), Operand::Imm(bit.into()), target], offset)
+    }
+    else if ins & 0xff00_0000 == 0xd400_0000 {
+        disassemble_exception(ins, offset)
+    }
+    else if ins & 0xffc0_0000 == 0xd500_0000 {
+        disassemble_system(ins, offset)
+    }
+    else if ins & 0xfe00_0000 == 0xd600_0000 {
+        disassemble_branch_register(ins, offset)
+    }
+    else {
+        unknown(offset)
+    }
+}
+
+// Load/store exclusive of one register or a pair, load-acquire/store-release
+// and compare and swap.
+fn disassemble_exclusive(ins: u32, offset: usize) -> Instruction {
+    let size = ins.bextr(31, 30);
+    let rt_op = gpr(rd(ins), size == 3, false);
+    let rs_op = gpr(rm(ins), false, false);
+    let mem = Operand::Mem(gpr_reg(rn(ins)), 0);
+    let (acquire, release) = (ins.bextr(22, 22) != 0, ins.bextr(15, 15) != 0);
+    let order = if acquire { ORDER_ACQUIRE } else { 0 } | if release { ORDER_RELEASE } else { 0 };
+    let rt2 = ins.bextr(14, 10);
+    // Pairs are of w or x registers by bit 30.
+    let pair = |n: u32| gpr(n, ins.bextr(30, 30) != 0, false);
+    let pair_size = if ins.bextr(30, 30) != 0 { 8 } else { 4 };
+    let (operation, operands) = match (ins.bextr(23, 23), acquire, ins.bextr(21, 21), release) {
+        (0, false, 0, false) => (Operation::Stxr, vec![rs_op, rt_op, mem]),
+        (0, false, 0, true) => (Operation::Stlxr, vec![rs_op, rt_op, mem]),
+        (0, true, 0, false) => (Operation::Ldxr, vec![rt_op, mem]),
+        (0, true, 0, true) => (Operation::Ldaxr, vec![rt_op, mem]),
+        (0, _, 1, _) if size >= 2 => {
+            let operation = match (acquire, release) {
+                (false, false) => Operation::Stxp,
+                (false, true) => Operation::Stlxp,
+                (true, false) => Operation::Ldxp,
+                (true, true) => Operation::Ldaxp,
+            };
+            let operands = if acquire { vec![pair(rd(ins)), pair(rt2), mem] } else { vec![rs_op, pair(rd(ins)), pair(rt2), mem] };
+            return Instruction { access_size: pair_size, ..instruction(operation, &operands, offset) }
+        },
+        // Pairs of even and odd registers.
+        (0, _, 1, _) if rt2 == 31 && (rm(ins) | rd(ins)) & 1 == 0 => {
+            let even_odd = |n: u32| match (pair(n), pair(n + 1)) {
+                (Operand::Reg(even), Operand::Reg(odd)) => Operand::Pair(even, odd),
+                _ => Operand::Nothing,
+            };
+            let operands = [even_odd(rm(ins)), even_odd(rd(ins)), mem];
+            return Instruction { order, access_size: pair_size, ..instruction(Operation::Casp, &operands, offset) }
+        },
+        (1, false, 0, false) => (Operation::Stllr, vec![rt_op, mem]),
+        (1, false, 0, true) => (Operation::Stlr, vec![rt_op, mem]),
+        (1, true, 0, false) => (Operation::Ldlar, vec![rt_op, mem]),
+        (1, true, 0, true) => (Operation::Ldar, vec![rt_op, mem]),
+        (1, _, 1, _) if rt2 == 31 => {
+            let operands = [gpr(rm(ins), size == 3, false), rt_op, mem];
+            return Instruction { order, access_size: 1 << size, ..instruction(Operation::Cas, &operands, offset) }
+        },
+        _ => return unknown(offset),
+    };
+    Instruction { access_size: 1 << size, ..instruction(operation, &operands, offset) }
+}
+
+// The atomic memory operations, swp and ldapr.
+fn disassemble_atomic(ins: u32, offset: usize) -> Instruction {
+    let size = ins.bextr(31, 30);
+    let (acquire, release) = (ins.bextr(23, 23) != 0, ins.bextr(22, 22) != 0);
+    let order = if acquire { ORDER_ACQUIRE } else { 0 } | if release { ORDER_RELEASE } else { 0 };
+    let rs_op = gpr(rm(ins), size == 3, false);
+    let rt_op = gpr(rd(ins), size == 3, false);
+    let mem = Operand::Mem(gpr_reg(rn(ins)), 0);
+    let operation = match (ins.bextr(15, 15), ins.bextr(14, 12)) {
+        (0, 0b000) => Operation::Ldadd,
+        (0, 0b001) => Operation::Ldclr,
+        (0, 0b010) => Operation::Ldeor,
+        (0, 0b011) => Operation::Ldset,
+        (0, 0b100) => Operation::Ldsmax,
+        (0, 0b101) => Operation::Ldsmin,
+        (0, 0b110) => Operation::Ldumax,
+        (0, 0b111) => Operation::Ldumin,
+        (1, 0b000) => Operation::Swp,
+        (1, 0b100) if acquire && !release && rm(ins) == 31 => {
+            return Instruction { access_size: 1 << size, ..instruction(Operation::Ldapr, &[rt_op, mem], offset) }
+        },
+        _ => return unknown(offset),
+    };
+    let instruction = match operation.atomic() {
+        Some(store) if !acquire && rd(ins) == 31 => instruction(store, &[rs_op, mem], offset),
+        _ => instruction(operation, &[rs_op, rt_op, mem], offset),
+    };
+    Instruction { order, access_size: 1 << size, ..instruction }
+}
+
+// The base register of a load or store, which can be sp.
+fn gpr_reg(n: u32) -> Reg {
+    Reg::aarch64_sized(n as u8, true, true)
+}
+
+fn disassemble_load_literal(ins: u32, offset: usize) -> Instruction {
+    let target = Operand::Rel(sign_extend(ins.bextr(23, 5), 19) << 2);
+    let (operation, is64, size) = match ins.bextr(31, 30) {
+        0 => (Operation::Ldr, false, 4),
+        1 => (Operation::Ldr, true, 8),
+        2 => (Operation::Ldrs, true, 4),
+        _ => return instruction(Operation::Prfm, &[prefetch_operation(rd(ins)), target], offset),
+    };
+    Instruction { access_size: size, ..instruction(operation, &[gpr(rd(ins), is64, false), target], offset) }
+}
+
+// ldapur and stlur, with a signed unscaled offset.
+fn disassemble_rcpc_unscaled(ins: u32, offset: usize) -> Instruction {
+    let size = ins.bextr(31, 30);
+    let (operation, is64) = match (size, ins.bextr(23, 22)) {
+        (_, 0) => (Operation::Stlur, size == 3),
+        (_, 1) => (Operation::Ldapur, size == 3),
+        (0..=2, 2) => (Operation::Ldapurs, true),
+        (0 | 1, 3) => (Operation::Ldapurs, false),
+        _ => return unknown(offset),
+    };
+    let mem = Operand::Mem(gpr_reg(rn(ins)), sign_extend(ins.bextr(20, 12), 9));
+    Instruction { access_size: 1 << size, ..instruction(operation, &[gpr(rd(ins), is64, false), mem], offset) }
+}
+
+fn prefetch_operation(rt: u32) -> Operand {
+    match PREFETCH_NAMES.get(rt as usize) {
+        Some(name) if !name.is_empty() => Operand::Name(name),
+        _ => Operand::Imm(rt.into()),
+    }
+}
+
+fn disassemble_load_store_pair(ins: u32, offset: usize) -> Instruction {
+    let load = ins.bextr(22, 22) != 0;
+    let mode = ins.bextr(24, 23);
+    let (is64, size) = match (ins.bextr(31, 30), load) {
+        (0, _) => (false, 4),
+        (1, true) if mode != 0 => (true, 4),
+        (2, _) => (true, 8),
+        _ => return unknown(offset),
+    };
+    let operation = match (ins.bextr(31, 30), load, mode) {
+        (1, _, _) => Operation::Ldpsw,
+        (_, true, 0) => Operation::Ldnp,
+        (_, false, 0) => Operation::Stnp,
+        (_, true, _) => Operation::Ldp,
+        (_, false, _) => Operation::Stp,
+    };
+    let addr_mode = match mode {
+        1 => ADDR_POST,
+        3 => ADDR_PRE,
+        _ => ADDR_OFFSET,
+    };
+    let mem = Operand::Mem(gpr_reg(rn(ins)), sign_extend(ins.bextr(21, 15), 7) * size as i64);
+    let operands = [gpr(rd(ins), is64, false), gpr(ins.bextr(14, 10), is64, false), mem];
+    Instruction { addr_mode, access_size: size, ..instruction(operation, &operands, offset) }
+}
+
+fn disassemble_load_store_register(ins: u32, offset: usize) -> Instruction {
+    if ins.bextr(24, 24) == 0 && ins.bextr(21, 21) != 0 && ins.bextr(11, 10) == 0 {
+        return disassemble_atomic(ins, offset)
+    }
+    let size = ins.bextr(31, 30);
+    let unscaled = ins.bextr(24, 24) == 0 && ins.bextr(21, 21) == 0 && ins.bextr(11, 10) == 0;
+    // Stores, zero extending loads, and loads sign extending to 64 and 32 bits.
+    let (operation, is64) = match (size, ins.bextr(23, 22)) {
+        (_, 0) => (if unscaled { Operation::Stur } else { Operation::Str }, size == 3),
+        (_, 1) => (if unscaled { Operation::Ldur } else { Operation::Ldr }, size == 3),
+        (3, 2) => (if unscaled { Operation::Prfum } else { Operation::Prfm }, true),
+        (0..=2, 2) => (if unscaled { Operation::Ldurs } else { Operation::Ldrs }, true),
+        (0 | 1, 3) => (if unscaled { Operation::Ldurs } else { Operation::Ldrs }, false),
+        _ => return unknown(offset),
+    };
+    let prefetch = matches!(operation, Operation::Prfm | Operation::Prfum);
+    let rt_op = if prefetch { prefetch_operation(rd(ins)) } else { gpr(rd(ins), is64, false) };
+    let base = gpr_reg(rn(ins));
+    let (mem, addr_mode) = if ins.bextr(24, 24) != 0 {
+        (Operand::Mem(base, i64::from(ins.bextr(21, 10)) << size), ADDR_OFFSET)
+    }
+    else if ins.bextr(21, 21) == 0 {
+        let imm = sign_extend(ins.bextr(20, 12), 9);
+        match ins.bextr(11, 10) {
+            0b00 => (Operand::Mem(base, imm), ADDR_OFFSET),
+            0b01 if !prefetch => (Operand::Mem(base, imm), ADDR_POST),
+            0b10 if !prefetch => {
+                let operation = match operation {
+                    Operation::Str => Operation::Sttr,
+                    Operation::Ldr => Operation::Ldtr,
+                    _ => Operation::Ldtrs,
+                };
+                return Instruction { access_size: 1 << size, ..instruction(operation, &[rt_op, Operand::Mem(base, imm)], offset) }
+            },
+            0b11 if !prefetch => (Operand::Mem(base, imm), ADDR_PRE),
+            _ => return unknown(offset),
+        }
+    }
+    else if ins.bextr(11, 10) == 0b10 {
+        let option = ins.bextr(15, 13) as u8;
+        if option & 0x2 == 0 {
+            return unknown(offset)
+        }
+        let index = Reg::aarch64_sized(rm(ins) as u8, option & 0x1 != 0, false);
+        let amount = if ins.bextr(12, 12) != 0 { Some(size as u8) } else { None };
+        (Operand::MemReg(base, index, option, amount), ADDR_OFFSET)
+    }
+    else {
+        return unknown(offset)
+    };
+    Instruction { addr_mode, access_size: 1 << size, ..instruction(operation, &[rt_op, mem], offset) }
+}
+
+fn disassemble_load_store(ins: u32, offset: usize) -> Instruction {
+    // SIMD and floating point registers.
+    if ins.bextr(26, 26) != 0 {
+        return unknown(offset)
+    }
+    match ins.bextr(29, 27) {
+        0b001 if ins.bextr(24, 24) == 0 => disassemble_exclusive(ins, offset),
+        0b011 if ins.bextr(25, 24) == 0 => disassemble_load_literal(ins, offset),
+        0b011 if ins.bextr(25, 24) == 1 && ins.bextr(21, 21) == 0 && ins.bextr(11, 10) == 0 => disassemble_rcpc_unscaled(ins, offset),
+        0b101 => disassemble_load_store_pair(ins, offset),
+        0b111 => disassemble_load_store_register(ins, offset),
+        _ => unknown(offset),
+    }
+}
+
+fn disassemble_logical_reg(ins: u32, offset: usize) -> Instruction {
+    let is64 = sf(ins);
+    let amount = ins.bextr(15, 10);
+    if !is64 && amount >= 32 {
+        return unknown(offset)
+    }
+    let (rd_op, rn_op) = (gpr(rd(ins), is64, false), gpr(rn(ins), is64, false));
+    let rm_reg = Reg::aarch64_sized(rm(ins) as u8, is64, false);
+    let op2 = Operand::Shifted(rm_reg, ins.bextr(23, 22) as u8, amount as u8);
+    match (ins.bextr(30, 29), ins.bextr(21, 21)) {
+        (0, 0) => instruction(Operation::And, &[rd_op, rn_op, op2], offset),
+        (0, _) => instruction(Operation::Bic, &[rd_op, rn_op, op2], offset),
+        (1, 0) if rn(ins) == 31 && op2 == Operand::Shifted(rm_reg, SHIFT_LSL, 0) => instruction(Operation::Mov, &[rd_op, Operand::Reg(rm_reg)], offset),
+        (1, 0) => instruction(Operation::Orr, &[rd_op, rn_op, op2], offset),
+        (1, _) if rn(ins) == 31 => instruction(Operation::Mvn, &[rd_op, op2], offset),
+        (1, _) => instruction(Operation::Orn, &[rd_op, rn_op, op2], offset),
+        (2, 0) => instruction(Operation::Eor, &[rd_op, rn_op, op2], offset),
+        (2, _) => instruction(Operation::Eon, &[rd_op, rn_op, op2], offset),
+        (_, 0) if rd(ins) == 31 => instruction(Operation::Tst, &[rn_op, op2], offset),
+        (_, 0) => Instruction { set_flags: true, ..instruction(Operation::And, &[rd_op, rn_op, op2], offset) },
+        _ => Instruction { set_flags: true, ..instruction(Operation::Bic, &[rd_op, rn_op, op2], offset) },
+    }
+}
+
+// add, sub and their aliases, with a shifted or extended register already
+// decoded into op2.
+fn add_sub(ins: u32, offset: usize, rd_op: Operand, rn_op: Operand, op2: Operand) -> Instruction {
+    let (sub, set_flags) = (ins.bextr(30, 30) != 0, ins.bextr(29, 29) != 0);
+    if set_flags && rd(ins) == 31 {
+        return instruction(if sub { Operation::Cmp } else { Operation::Cmn }, &[rn_op, op2], offset)
+    }
+    if sub && rn(ins) == 31 && matches!(op2, Operand::Shifted(..)) {
+        return Instruction { set_flags, ..instruction(Operation::Neg, &[rd_op, op2], offset) }
+    }
+    Instruction { set_flags, ..instruction(if sub { Operation::Sub } else { Operation::Add }, &[rd_op, rn_op, op2], offset) }
+}
+
+fn disassemble_add_sub_shifted(ins: u32, offset: usize) -> Instruction {
+    let is64 = sf(ins);
+    let (shift, amount) = (ins.bextr(23, 22) as u8, ins.bextr(15, 10));
+    if shift == SHIFT_ROR || (!is64 && amount >= 32) {
+        return unknown(offset)
+    }
+    let op2 = Operand::Shifted(Reg::aarch64_sized(rm(ins) as u8, is64, false), shift, amount as u8);
+    add_sub(ins, offset, gpr(rd(ins), is64, false), gpr(rn(ins), is64, false), op2)
+}
+
+fn disassemble_add_sub_extended(ins: u32, offset: usize) -> Instruction {
+    let is64 = sf(ins);
+    let (option, amount) = (ins.bextr(15, 13) as u8, ins.bextr(12, 10) as u8);
+    if ins.bextr(23, 22) != 0 || amount > 4 {
+        return unknown(offset)
+    }
+    let set_flags = ins.bextr(29, 29) != 0;
+    let rm_reg = Reg::aarch64_sized(rm(ins) as u8, is64 && option & 0x3 == EXTEND_UXTX, false);
+    // Extending to the register's own width next to sp is written as lsl.
+    let uses_sp = rn(ins) == 31 || (rd(ins) == 31 && !set_flags);
+    // uxtx of a w register is shown as the uxtw it amounts to.
+    let op2 = if uses_sp && option == if is64 { EXTEND_UXTX } else { EXTEND_UXTW } {
+        Operand::Shifted(rm_reg, SHIFT_LSL, amount)
+    } else if !is64 && option == EXTEND_UXTX {
+        Operand::Extended(rm_reg, EXTEND_UXTW, amount)
+    } else {
+        Operand::Extended(rm_reg, option, amount)
+    };
+    add_sub(ins, offset, gpr(rd(ins), is64, !set_flags), gpr(rn(ins), is64, true), op2)
+}
+
+fn disassemble_add_sub_carry(ins: u32, offset: usize) -> Instruction {
+    let is64 = sf(ins);
+    if ins.bextr(15, 10) != 0 {
+        return unknown(offset)
+    }
+    let operation = if ins.bextr(30, 30) != 0 { Operation::Sbc } else { Operation::Adc };
+    let operands = [gpr(rd(ins), is64, false), gpr(rn(ins), is64, false), gpr(rm(ins), is64, false)];
+    Instruction { set_flags: ins.bextr(29, 29) != 0, ..instruction(operation, &operands, offset) }
+}
+
+fn disassemble_conditional_compare(ins: u32, offset: usize) -> Instruction {
+    let is64 = sf(ins);
+    if ins.bextr(29, 29) == 0 || ins.bextr(10, 10) != 0 || ins.bextr(4, 4) != 0 {
+        return unknown(offset)
+    }
+    let operation = if ins.bextr(30, 30) != 0 { Operation::Ccmp } else { Operation::Ccmn };
+    let op2 = if ins.bextr(11, 11) != 0 { Operand::Imm(rm(ins).into()) } else { gpr(rm(ins), is64, false) };
+    let operands = [gpr(rn(ins), is64, false), op2, Operand::Imm(ins.bextr(3, 0).into()), Operand::Cond(ins.bextr(15, 12) as u8)];
+    instruction(operation, &operands, offset)
+}
+
+fn disassemble_conditional_select(ins: u32, offset: usize) -> Instruction {
+    let is64 = sf(ins);
+    if ins.bextr(29, 29) != 0 || ins.bextr(11, 11) != 0 {
+        return unknown(offset)
+    }
+    let (rd_op, rn_op, rm_op) = (gpr(rd(ins), is64, false), gpr(rn(ins), is64, false), gpr(rm(ins), is64, false));
+    let cond = ins.bextr(15, 12) as u8;
+    // The aliases test the opposite condition, which al and nv don't have.
+    let inverse = Operand::Cond(cond ^ 1);
+    let aliased = cond < COND_AL && rn(ins) == rm(ins);
+    let zero = rn(ins) == 31;
+    match (ins.bextr(30, 30), ins.bextr(10, 10)) {
+        (0, 0) => instruction(Operation::Csel, &[rd_op, rn_op, rm_op, Operand::Cond(cond)], offset),
+        (0, _) if aliased && zero => instruction(Operation::Cset, &[rd_op, inverse], offset),
+        (0, _) if aliased => instruction(Operation::Cinc, &[rd_op, rn_op, inverse], offset),
+        (0, _) => instruction(Operation::Csinc, &[rd_op, rn_op, rm_op, Operand::Cond(cond)], offset),
+        (_, 0) if aliased && zero => instruction(Operation::Csetm, &[rd_op, inverse], offset),
+        (_, 0) if aliased => instruction(Operation::Cinv, &[rd_op, rn_op, inverse], offset),
+        (_, 0) => instruction(Operation::Csinv, &[rd_op, rn_op, rm_op, Operand::Cond(cond)], offset),
+        (_, _) if aliased => instruction(Operation::Cneg, &[rd_op, rn_op, inverse], offset),
+        (_, _) => instruction(Operation::Csneg, &[rd_op, rn_op, rm_op, Operand::Cond(cond)], offset),
+    }
+}
+
+fn disassemble_data_processing_source(ins: u32, offset: usize) -> Instruction {
+    let is64 = sf(ins);
+    let (rd_op, rn_op, rm_op) = (gpr(rd(ins), is64, false), gpr(rn(ins), is64, false), gpr(rm(ins), is64, false));
+    if ins.bextr(29, 29) != 0 {
+        return unknown(offset)
+    }
+    // Two sources.
+    if ins.bextr(30, 30) == 0 {
+        // crc32 of a byte, halfword, word or doubleword; only the last is sf.
+        if ins.bextr(15, 13) == 0b010 {
+            let sz = ins.bextr(11, 10);
+            if (sz == 3) != is64 {
+                return unknown(offset)
+            }
+            let operation = [Operation::Crc32b, Operation::Crc32h, Operation::Crc32w, Operation::Crc32x,
+                Operation::Crc32cb, Operation::Crc32ch, Operation::Crc32cw, Operation::Crc32cx][ins.bextr(12, 10) as usize];
+            return instruction(operation, &[gpr(rd(ins), false, false), gpr(rn(ins), false, false), rm_op], offset)
+        }
+        let operation = match ins.bextr(15, 10) {
+            0b000010 => Operation::Udiv,
+            0b000011 => Operation::Sdiv,
+            0b001000 => Operation::Lsl,
+            0b001001 => Operation::Lsr,
+            0b001010 => Operation::Asr,
+            0b001011 => Operation::Ror,
+            _ => return unknown(offset),
+        };
+        return instruction(operation, &[rd_op, rn_op, rm_op], offset)
+    }
+    if rm(ins) != 0 {
+        return unknown(offset)
+    }
+    let operation = match (ins.bextr(15, 10), is64) {
+        (0b000000, _) => Operation::Rbit,
+        (0b000001, _) => Operation::Rev16,
+        (0b000010, true) => Operation::Rev32,
+        (0b000010, false) | (0b000011, true) => Operation::Rev,
+        (0b000100, _) => Operation::Clz,
+        (0b000101, _) => Operation::Cls,
+        _ => return unknown(offset),
+    };
+    instruction(operation, &[rd_op, rn_op], offset)
+}
+
+fn disassemble_data_processing_3(ins: u32, offset: usize) -> Instruction {
+    let is64 = sf(ins);
+    if ins.bextr(30, 29) != 0 {
+        return unknown(offset)
+    }
+    let x = |n: u32| gpr(n, true, false);
+    let w = |n: u32| gpr(n, false, false);
+    let r = |n: u32| gpr(n, is64, false);
+    let no_ra = ins.bextr(14, 10) == 31;
+    let (operation, operands) = match (ins.bextr(23, 21), ins.bextr(15, 15), is64) {
+        (0b000, 0, _) if no_ra => (Operation::Mul, vec![r(rd(ins)), r(rn(ins)), r(rm(ins))]),
+        (0b000, 0, _) => (Operation::Madd, vec![r(rd(ins)), r(rn(ins)), r(rm(ins)), r(ins.bextr(14, 10))]),
+        (0b000, _, _) if no_ra => (Operation::Mneg, vec![r(rd(ins)), r(rn(ins)), r(rm(ins))]),
+        (0b000, _, _) => (Operation::Msub, vec![r(rd(ins)), r(rn(ins)), r(rm(ins)), r(ins.bextr(14, 10))]),
+        (0b001, 0, true) if no_ra => (Operation::Smull, vec![x(rd(ins)), w(rn(ins)), w(rm(ins))]),
+        (0b001, 0, true) => (Operation::Smaddl, vec![x(rd(ins)), w(rn(ins)), w(rm(ins)), x(ins.bextr(14, 10))]),
+        (0b001, _, true) => (Operation::Smsubl, vec![x(rd(ins)), w(rn(ins)), w(rm(ins)), x(ins.bextr(14, 10))]),
+        (0b010, 0, true) => (Operation::Smulh, vec![x(rd(ins)), x(rn(ins)), x(rm(ins))]),
+        (0b101, 0, true) if no_ra => (Operation::Umull, vec![x(rd(ins)), w(rn(ins)), w(rm(ins))]),
+        (0b101, 0, true) => (Operation::Umaddl, vec![x(rd(ins)), w(rn(ins)), w(rm(ins)), x(ins.bextr(14, 10))]),
+        (0b101, _, true) => (Operation::Umsubl, vec![x(rd(ins)), w(rn(ins)), w(rm(ins)), x(ins.bextr(14, 10))]),
+        (0b110, 0, true) => (Operation::Umulh, vec![x(rd(ins)), x(rn(ins)), x(rm(ins))]),
+        _ => return unknown(offset),
+    };
+    instruction(operation, &operands, offset)
+}
+
+fn disassemble_data_processing_reg(ins: u32, offset: usize) -> Instruction {
+    match (ins.bextr(28, 24), ins.bextr(23, 21)) {
+        (0b01010, _) => disassemble_logical_reg(ins, offset),
+        (0b01011, 0b000 | 0b010 | 0b100 | 0b110) => disassemble_add_sub_shifted(ins, offset),
+        (0b01011, _) => disassemble_add_sub_extended(ins, offset),
+        (0b11010, 0b000) => disassemble_add_sub_carry(ins, offset),
+        (0b11010, 0b010) => disassemble_conditional_compare(ins, offset),
+        (0b11010, 0b100) => disassemble_conditional_select(ins, offset),
+        (0b11010, 0b110) => disassemble_data_processing_source(ins, offset),
+        (0b11011, _) => disassemble_data_processing_3(ins, offset),
+        _ => unknown(offset),
+    }
+}
+
+fn disassemble_instruction(ins: u32, offset: usize) -> Instruction {
+    match ins.bextr(28, 25) {
+        0b1000 | 0b1001 => disassemble_data_processing_imm(ins, offset),
+        0b1010 | 0b1011 => disassemble_branch_system(ins, offset),
+        0b0100 | 0b0110 | 0b1100 | 0b1110 => disassemble_load_store(ins, offset),
+        0b0101 | 0b1101 => disassemble_data_processing_reg(ins, offset),
+        0b0000 if ins >> 16 == 0 => instruction(Operation::Udf, &[Operand::Imm(ins.into())], offset),
+        _ => unknown(offset),
+    }
+}
+
+pub fn disassemble_aarch64_at(bytes: &[u8], offset: usize) -> Option<Instruction> {
+    let word = read_u32_from_slice(bytes, offset, LITTLE_ENDIAN)?;
+    let ins = disassemble_instruction(word, offset);
+    if let Operation::Unknown = ins.operation {
+        log::debug!("Unknown AArch64 instruction {:#010x} at offset {:#x}", word, offset);
+    }
+    Some(ins)
+}
+
+// Decodes one instruction after another, passing each to f until it returns false.
+pub fn decode_aarch64(bytes: &[u8], mut f: impl FnMut(Instruction) -> bool) {
+    let mut offset: usize = 0;
+    while let Some(ins) = disassemble_aarch64_at(bytes, offset) {
+        if !f(ins) {
+            return;
+        }
+        offset += 4;
+    }
+}
+
+// Stops early, with the instructions so far, once cancel is cancelled.
+pub fn disassemble_aarch64(section: &Section, section_name: &String, _program: &Program, cancel: &CancelToken) -> DisassemblySection {
+    let mut instrs = Vec::<Instruction>::new();
+    decode_aarch64(section.bytes.as_slice(), |ins| {
+        instrs.push(ins);
+        !cancel.is_cancelled()
+    });
+    DisassemblySection {
+        section_name: section_name.clone(),
+        addr: section.addr,
+        instructions: crate::dis::InstructionListing::AArch64(instrs),
+    }
+}
@@ -0,0 +1,122 @@
+// User-supplied names and comments for addresses, so naming done during one
+// analysis carries over to the next. Files are either JSON, an array of entries:
+//
+//     [{"addr": "0x401000", "name": "parse_header", "comment": "checks the magic first"}]
+//
+// or CSV with one entry per line, where the comment is the rest of the line:
+//
+//     # addr,name,comment
+//     0x401000,parse_header,checks the magic first
+//     0x401020,,loop over the records
+//
+// Addresses are hex with a "0x" prefix or decimal. Either the name or the comment
+// may be empty. A JSON entry can also say whether a new name is a function ("func": true).
+
+use crate::error::BaretkError;
+use crate::json;
+use crate::prog::{Program, Symbol};
+use crate::util;
+
+pub struct Annotation {
+    pub addr: u64,
+    pub name: Option<String>,
+    pub comment: Option<String>,
+    // Whether a new symbol is a function, when the file says.
+    pub is_func: Option<bool>,
+}
+
+fn parse_addr(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse::<u64>().ok(),
+    }
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    let s = s.trim();
+    let s = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')).unwrap_or(s);
+    if s.is_empty() { None } else { Some(s.to_string()) }
+}
+
+// Parses CSV lines, returning the entries and the line numbers that couldn't be parsed.
+pub fn parse_csv(text: &str) -> (Vec<Annotation>, Vec<usize>) {
+    let mut out = Vec::<Annotation>::new();
+    let mut errors = Vec::<usize>::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(3, ',');
+        let addr = fields.next().unwrap_or("").trim();
+        // A header line naming the columns.
+        if addr.eq_ignore_ascii_case("addr") || addr.eq_ignore_ascii_case("address") {
+            continue;
+        }
+        match parse_addr(addr) {
+            Some(addr) => out.push(Annotation {
+                addr,
+                name: fields.next().and_then(non_empty),
+                comment: fields.next().and_then(non_empty),
+                is_func: None,
+            }),
+            None => errors.push(i + 1),
+        }
+    }
+    (out, errors)
+}
+
+// Parses a JSON array of entries. Errors give the byte offset or the index of a bad entry.
+pub fn parse_json(text: &str) -> Result<Vec<Annotation>, String> {
+    let value = json::parse(text).map_err(|offset| format!("invalid JSON at byte {}", offset))?;
+    let entries = value.as_array().ok_or("expected an array of entries".to_string())?;
+    let mut out = Vec::<Annotation>::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let addr = entry.get("addr").and_then(|addr| addr.as_u64()).ok_or(format!("entry {} has no valid \"addr\"", i))?;
+        out.push(Annotation {
+            addr,
+            name: entry.get("name").and_then(|name| name.as_str()).and_then(non_empty),
+            comment: entry.get("comment").and_then(|comment| comment.as_str()).and_then(non_empty),
+            is_func: entry.get("func").and_then(|func| func.as_bool()),
+        });
+    }
+    Ok(out)
+}
+
+// Loads JSON or CSV, by extension or, failing that, by whether the file starts with '['.
+pub fn load_file(path: &str) -> Result<Vec<Annotation>, BaretkError> {
+    let contents = util::try_read_file_contents(path)?;
+    let text = String::from_utf8_lossy(&contents);
+    if path.ends_with(".json") || (!path.ends_with(".csv") && text.trim_start().starts_with('[')) {
+        parse_json(&text).map_err(|reason| BaretkError::Malformed { what: path.to_string(), offset: None, section: None, reason })
+    }
+    else {
+        let (annotations, errors) = parse_csv(&text);
+        for line in &errors {
+            log::warn!("{}:{}: can't parse annotation", path, line);
+        }
+        Ok(annotations)
+    }
+}
+
+// Names rename the symbols already at their address, or become new symbols:
+// functions in the code section, data elsewhere, unless the entry says. Comments replace earlier ones.
+pub fn apply(program: &mut Program, annotations: Vec<Annotation>) {
+    let code = program.section_table.get(program.code_section()).map(|section| section.addr..section.addr + section.bytes.len() as u64);
+    for annotation in annotations {
+        if let Some(name) = annotation.name {
+            let mut renamed = false;
+            for sym in program.symbol_table.iter_mut().filter(|sym| sym.addr == annotation.addr && !sym.name.is_empty()) {
+                sym.name = name.clone();
+                renamed = true;
+            }
+            if !renamed {
+                let is_func = annotation.is_func.unwrap_or(code.as_ref().map_or(false, |code| code.contains(&annotation.addr)));
+                program.symbol_table.push(Symbol { name, addr: annotation.addr, size: 0, is_func });
+            }
+        }
+        if let Some(comment) = annotation.comment {
+            program.comments.insert(annotation.addr, comment);
+        }
+    }
+}
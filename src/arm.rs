@@ -1,5 +1,1551 @@
-use crate::{dis::DisassemblySection, prog::{Program, Section}};
+use crate::cancel::CancelToken;
+use crate::dis::{self, DisassemblySection};
+use crate::prog::{Section, Program};
+use crate::reg::Reg;
+use crate::util::{read_u16_from_slice, read_u32_from_slice, BitExtr, LITTLE_ENDIAN};
 
-pub fn disassemble_arm(_section: &Section, _section_name: &String, _program: &Program) -> DisassemblySection {
-    todo!("TODO: ARM stuff")
+#[derive(PartialEq)]
+#[derive(Copy, Clone)]
+struct Register(u8);
+
+impl Register {
+    // const R0: Register = Register(0x0);
+    // const FP: Register = Register(0xb);
+    // const IP: Register = Register(0xc);
+    const SP: Register = Register(0xd);
+    const LR: Register = Register(0xe);
+    const PC: Register = Register(0xf);
+
+    fn reg(self) -> Reg {
+        Reg::arm(self.0)
+    }
+
+    fn name(self) -> &'static str {
+        self.reg().name()
+    }
+}
+
+const COND_EQ: u8 = 0x0;
+const COND_NE: u8 = 0x1;
+const COND_HS: u8 = 0x2;
+const COND_LO: u8 = 0x3;
+const COND_MI: u8 = 0x4;
+const COND_PL: u8 = 0x5;
+const COND_VS: u8 = 0x6;
+const COND_VC: u8 = 0x7;
+const COND_HI: u8 = 0x8;
+const COND_LS: u8 = 0x9;
+const COND_GE: u8 = 0xa;
+const COND_LT: u8 = 0xb;
+const COND_GT: u8 = 0xc;
+const COND_LE: u8 = 0xd;
+const COND_AL: u8 = 0xe;
+
+const COND_NAMES: [&str; 16] = [
+    "eq", "ne", "hs", "lo", "mi", "pl", "vs", "vc",
+    "hi", "ls", "ge", "lt", "gt", "le", "", "",
+];
+
+pub(crate) fn cond_to_dis(cond: u8) -> u64 {
+    match cond {
+        COND_EQ => dis::COND_EQ,
+        COND_NE => dis::COND_NE,
+        COND_HS => dis::COND_GEU,
+        COND_LO => dis::COND_LTU,
+        COND_MI => dis::COND_MI,
+        COND_PL => dis::COND_PL,
+        COND_VS => dis::COND_VS,
+        COND_VC => dis::COND_VC,
+        COND_HI => dis::COND_GTU,
+        COND_LS => dis::COND_LEU,
+        COND_GE => dis::COND_GE,
+        COND_LT => dis::COND_LT,
+        COND_GT => dis::COND_GT,
+        COND_LE => dis::COND_LE,
+        _ => dis::COND_AL,
+    }
+}
+
+const SHIFT_NAMES: [&str; 4] = ["lsl", "lsr", "asr", "ror"];
+
+// M-profile special registers by SYSm, for mrs and msr.
+const SPECIAL_REGISTERS: [(u32, &str); 14] = [
+    (0, "apsr"), (1, "iapsr"), (2, "eapsr"), (3, "xpsr"), (5, "ipsr"), (6, "epsr"), (7, "iepsr"),
+    (8, "msp"), (9, "psp"), (16, "primask"), (17, "basepri"), (18, "basepri_max"), (19, "faultmask"), (20, "control"),
+];
+
+const BARRIER_OPTIONS: [&str; 16] = [
+    "#0", "oshld", "oshst", "osh", "#4", "nshld", "nshst", "nsh",
+    "#8", "ishld", "ishst", "ish", "#12", "ld", "st", "sy",
+];
+
+// Addressing mode bits for loads and stores.
+const ADDR_PRE: u8 = 0x1;
+const ADDR_UP: u8 = 0x2;
+const ADDR_WRITEBACK: u8 = 0x4;
+// ldm and stm of the user mode registers, or ldm with pc restoring cpsr, written ^.
+const ADDR_USER: u8 = 0x8;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    And,
+    Eor,
+    Sub,
+    Rsb,
+    Add,
+    Adc,
+    Sbc,
+    Rsc,
+    Tst,
+    Teq,
+    Cmp,
+    Cmn,
+    Orr,
+    Mov,
+    Movt,
+    Movw,
+    Bic,
+    Mvn,
+    Mul,
+    Mla,
+    Ldr,
+    Ldrb,
+    Ldrh,
+    Ldrsb,
+    Ldrsh,
+    Str,
+    Strb,
+    Strh,
+    // Unprivileged loads and stores.
+    Ldrt,
+    Ldrbt,
+    Ldrht,
+    Ldrsbt,
+    Ldrsht,
+    Strt,
+    Strbt,
+    Strht,
+    Ldm,
+    Stm,
+    Push,
+    Pop,
+    B,
+    Bl,
+    Bx,
+    Blx,
+    Svc,
+    // Thumb only.
+    Lsl,
+    Lsr,
+    Asr,
+    Ror,
+    Orn,
+    Adr,
+    Cbz,
+    Cbnz,
+    Sxtb,
+    Sxth,
+    Uxtb,
+    Uxth,
+    Rev,
+    Rev16,
+    Revsh,
+    Rbit,
+    Clz,
+    Mls,
+    Smull,
+    Umull,
+    Smlal,
+    Umlal,
+    Sdiv,
+    Udiv,
+    Sbfx,
+    Ubfx,
+    Bfi,
+    Bfc,
+    Ldrd,
+    Strd,
+    Tbb,
+    Tbh,
+    It,
+    Nop,
+    Yield,
+    Wfe,
+    Wfi,
+    Sev,
+    Dmb,
+    Dsb,
+    Isb,
+    Cpsie,
+    Cpsid,
+    Bkpt,
+    Udf,
+    Pld,
+    Mrs,
+    Msr,
+    Ldrex,
+    Ldrexb,
+    Ldrexh,
+    Strex,
+    Strexb,
+    Strexh,
+    Clrex,
+    Ssat,
+    Usat,
+    Unknown,
+}
+
+impl Operation {
+    fn name(self) -> &'static str {
+        match self {
+            Self::And   => "and",
+            Self::Eor   => "eor",
+            Self::Sub   => "sub",
+            Self::Rsb   => "rsb",
+            Self::Add   => "add",
+            Self::Adc   => "adc",
+            Self::Sbc   => "sbc",
+            Self::Rsc   => "rsc",
+            Self::Tst   => "tst",
+            Self::Teq   => "teq",
+            Self::Cmp   => "cmp",
+            Self::Cmn   => "cmn",
+            Self::Orr   => "orr",
+            Self::Mov   => "mov",
+            Self::Movt  => "movt",
+            Self::Movw  => "movw",
+            Self::Bic   => "bic",
+            Self::Mvn   => "mvn",
+            Self::Mul   => "mul",
+            Self::Mla   => "mla",
+            Self::Ldr   => "ldr",
+            Self::Ldrb  => "ldrb",
+            Self::Ldrh  => "ldrh",
+            Self::Ldrsb => "ldrsb",
+            Self::Ldrsh => "ldrsh",
+            Self::Str   => "str",
+            Self::Strb  => "strb",
+            Self::Strh  => "strh",
+            Self::Ldrt  => "ldrt",
+            Self::Ldrbt => "ldrbt",
+            Self::Ldrht => "ldrht",
+            Self::Ldrsbt => "ldrsbt",
+            Self::Ldrsht => "ldrsht",
+            Self::Strt  => "strt",
+            Self::Strbt => "strbt",
+            Self::Strht => "strht",
+            Self::Ldm   => "ldm",
+            Self::Stm   => "stm",
+            Self::Push  => "push",
+            Self::Pop   => "pop",
+            Self::B     => "b",
+            Self::Bl    => "bl",
+            Self::Bx    => "bx",
+            Self::Blx   => "blx",
+            Self::Svc   => "svc",
+            Self::Lsl   => "lsl",
+            Self::Lsr   => "lsr",
+            Self::Asr   => "asr",
+            Self::Ror   => "ror",
+            Self::Orn   => "orn",
+            Self::Adr   => "adr",
+            Self::Cbz   => "cbz",
+            Self::Cbnz  => "cbnz",
+            Self::Sxtb  => "sxtb",
+            Self::Sxth  => "sxth",
+            Self::Uxtb  => "uxtb",
+            Self::Uxth  => "uxth",
+            Self::Rev   => "rev",
+            Self::Rev16 => "rev16",
+            Self::Revsh => "revsh",
+            Self::Rbit  => "rbit",
+            Self::Clz   => "clz",
+            Self::Mls   => "mls",
+            Self::Smull => "smull",
+            Self::Umull => "umull",
+            Self::Smlal => "smlal",
+            Self::Umlal => "umlal",
+            Self::Sdiv  => "sdiv",
+            Self::Udiv  => "udiv",
+            Self::Sbfx  => "sbfx",
+            Self::Ubfx  => "ubfx",
+            Self::Bfi   => "bfi",
+            Self::Bfc   => "bfc",
+            Self::Ldrd  => "ldrd",
+            Self::Strd  => "strd",
+            Self::Tbb   => "tbb",
+            Self::Tbh   => "tbh",
+            Self::It    => "it",
+            Self::Nop   => "nop",
+            Self::Yield => "yield",
+            Self::Wfe   => "wfe",
+            Self::Wfi   => "wfi",
+            Self::Sev   => "sev",
+            Self::Dmb   => "dmb",
+            Self::Dsb   => "dsb",
+            Self::Isb   => "isb",
+            Self::Cpsie => "cpsie",
+            Self::Cpsid => "cpsid",
+            Self::Bkpt  => "bkpt",
+            Self::Udf   => "udf",
+            Self::Pld   => "pld",
+            Self::Mrs   => "mrs",
+            Self::Msr   => "msr",
+            Self::Ldrex => "ldrex",
+            Self::Ldrexb => "ldrexb",
+            Self::Ldrexh => "ldrexh",
+            Self::Strex => "strex",
+            Self::Strexb => "strexb",
+            Self::Strexh => "strexh",
+            Self::Clrex => "clrex",
+            Self::Ssat  => "ssat",
+            Self::Usat  => "usat",
+            Self::Unknown => "???",
+        }
+    }
+
+    // The unprivileged form of a load or store.
+    fn unprivileged(self) -> Operation {
+        match self {
+            Self::Ldr   => Self::Ldrt,
+            Self::Ldrb  => Self::Ldrbt,
+            Self::Ldrh  => Self::Ldrht,
+            Self::Ldrsb => Self::Ldrsbt,
+            Self::Ldrsh => Self::Ldrsht,
+            Self::Str   => Self::Strt,
+            Self::Strb  => Self::Strbt,
+            Self::Strh  => Self::Strht,
+            _ => Self::Unknown,
+        }
+    }
+
+    fn access_size(self) -> u8 {
+        match self {
+            Self::Ldrb | Self::Ldrsb | Self::Strb | Self::Ldrbt | Self::Ldrsbt | Self::Strbt | Self::Ldrexb | Self::Strexb => 1,
+            Self::Ldrh | Self::Ldrsh | Self::Strh | Self::Ldrht | Self::Ldrsht | Self::Strht | Self::Ldrexh | Self::Strexh => 2,
+            Self::Tbb => 1,
+            Self::Tbh => 2,
+            _ => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Nothing,
+    Reg(u8),
+    RegShift(u8, u8, u8),
+    RegShiftReg(u8, u8, u8),
+    ImmU32(u32),
+    ImmS32(i32),
+    RegList(u16),
+}
+
+impl Operand {
+    fn print(self) -> String {
+        match self {
+            Self::Reg(r) => Register(r).name().to_string(),
+            Self::RegShift(r, shift, amount) => {
+                if shift == 0 && amount == 0 {
+                    Register(r).name().to_string()
+                } else if shift == 3 && amount == 0 {
+                    format!("{}, rrx", Register(r).name())
+                } else {
+                    format!("{}, {} #{}", Register(r).name(), SHIFT_NAMES[shift as usize], amount)
+                }
+            },
+            Self::RegShiftReg(r, shift, rs) => format!("{}, {} {}", Register(r).name(), SHIFT_NAMES[shift as usize], Register(rs).reg()),
+            Self::ImmU32(x) => format!("#{}", x),
+            Self::ImmS32(x) => format!("#{}", x),
+            Self::RegList(list) => {
+                let mut regs = Vec::<&str>::new();
+                for r in 0..16u8 {
+                    if list & (1 << r) != 0 {
+                        regs.push(Register(r).name());
+                    }
+                }
+                format!("{{{}}}", regs.join(", "))
+            },
+            Self::Nothing => "???".to_string(),
+        }
+    }
+
+    fn into(self) -> dis::Operand {
+        match self {
+            Self::Reg(r) => dis::Operand::Register(Register(r).reg()),
+            Self::RegShift(r, shift, amount) => {
+                if shift == 0 && amount == 0 {
+                    dis::Operand::Register(Register(r).reg())
+                } else {
+                    dis::Operand::ShiftedRegister(Register(r).reg(), shift, amount.into())
+                }
+            },
+            Self::RegShiftReg(r, shift, rs) => dis::Operand::RegisterShiftedRegister(Register(r).reg(), shift, Register(rs).reg()),
+            Self::ImmU32(x) => dis::Operand::Immediate(x.into()),
+            Self::ImmS32(x) => dis::Operand::Immediate(x.into()),
+            Self::RegList(_) | Self::Nothing => dis::Operand::Nothing,
+        }
+    }
+
+    fn reg_list(self) -> Vec<dis::Operand> {
+        let mut out = Vec::<dis::Operand>::new();
+        if let Self::RegList(list) = self {
+            for r in 0..16u8 {
+                if list & (1 << r) != 0 {
+                    out.push(dis::Operand::Register(Register(r).reg()));
+                }
+            }
+        }
+        out
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Instruction {
+    operation: Operation,
+    cond: u8,
+    set_flags: bool,
+    rd: Operand,
+    rn: Operand,
+    op2: Operand,
+    op3: Operand,
+    addr_mode: u8,
+    offset: usize,
+    ins_size: u8,
+    thumb: bool,
+}
+
+impl Instruction {
+    fn mnemonic(self) -> String {
+        let cond = COND_NAMES[self.cond as usize];
+        let s = if self.set_flags && !matches!(self.operation, Operation::Tst | Operation::Teq | Operation::Cmp | Operation::Cmn) { "s" } else { "" };
+        match self.operation {
+            Operation::Ldm | Operation::Stm => {
+                let mode = match (self.addr_mode & ADDR_PRE != 0, self.addr_mode & ADDR_UP != 0) {
+                    (false, true) => "ia",
+                    (true, true) => "ib",
+                    (false, false) => "da",
+                    (true, false) => "db",
+                };
+                format!("{}{}{}", self.operation.name(), mode, cond)
+            },
+            _ => format!("{}{}{}", self.operation.name(), s, cond),
+        }
+    }
+
+    fn print_address(self) -> String {
+        let base = self.rn.print();
+        let sign = if self.addr_mode & ADDR_UP != 0 { "" } else { "-" };
+        let off = match self.op2 {
+            Operand::ImmU32(0) => String::new(),
+            Operand::ImmU32(x) => format!(", #{}{}", sign, x),
+            Operand::Nothing => String::new(),
+            op => format!(", {}{}", sign, op.print()),
+        };
+        if self.addr_mode & ADDR_PRE == 0 {
+            format!("[{}]{}", base, off)
+        } else if self.addr_mode & ADDR_WRITEBACK != 0 {
+            format!("[{}{}]!", base, off)
+        } else {
+            format!("[{}{}]", base, off)
+        }
+    }
+
+    pub fn print(self) -> String {
+        let m = self.mnemonic();
+        match self.operation {
+            Operation::Mov | Operation::Mvn | Operation::Movt | Operation::Movw => format!("{} {}, {}", m, self.rd.print(), self.op2.print()),
+            Operation::Tst | Operation::Teq | Operation::Cmp | Operation::Cmn => format!("{} {}, {}", m, self.rn.print(), self.op2.print()),
+            Operation::Mul => format!("{} {}, {}, {}", m, self.rd.print(), self.op2.print(), self.op3.print()),
+            Operation::Mla => format!("{} {}, {}, {}, {}", m, self.rd.print(), self.op2.print(), self.op3.print(), self.rn.print()),
+            Operation::Ldr | Operation::Ldrb | Operation::Ldrh | Operation::Ldrsb | Operation::Ldrsh
+            | Operation::Str | Operation::Strb | Operation::Strh | Operation::Ldrt | Operation::Ldrbt | Operation::Ldrht
+            | Operation::Ldrsbt | Operation::Ldrsht | Operation::Strt | Operation::Strbt | Operation::Strht => {
+                format!("{} {}, {}", m, self.rd.print(), self.print_address())
+            },
+            Operation::Ldm | Operation::Stm => format!("{} {}{}, {}{}", m, self.rn.print(),
+                if self.addr_mode & ADDR_WRITEBACK != 0 { "!" } else { "" }, self.op2.print(),
+                if self.addr_mode & ADDR_USER != 0 { "^" } else { "" }),
+            Operation::Push | Operation::Pop => format!("{} {}", m, self.op2.print()),
+            Operation::B | Operation::Bl | Operation::Blx => format!("{} {}", m, self.target()),
+            Operation::Bx => format!("{} {}", m, self.op2.print()),
+            Operation::Svc | Operation::Bkpt | Operation::Udf => format!("{} {}", m, self.op2.print()),
+            Operation::Adr => format!("{} {}, {}", m, self.rd.print(), self.target()),
+            Operation::Cbz | Operation::Cbnz => format!("{} {}, {}", m, self.rn.print(), self.target()),
+            Operation::Sxtb | Operation::Sxth | Operation::Uxtb | Operation::Uxth | Operation::Rev | Operation::Rev16
+            | Operation::Revsh | Operation::Rbit | Operation::Clz => format!("{} {}, {}", m, self.rd.print(), self.op2.print()),
+            Operation::Mls => format!("{} {}, {}, {}, {}", m, self.rd.print(), self.op2.print(), self.op3.print(), self.rn.print()),
+            Operation::Smull | Operation::Umull | Operation::Smlal | Operation::Umlal | Operation::Sbfx | Operation::Ubfx
+            | Operation::Bfi => format!("{} {}, {}, {}, {}", m, self.rd.print(), self.rn.print(), self.op2.print(), self.op3.print()),
+            Operation::Bfc => format!("{} {}, {}, {}", m, self.rd.print(), self.op2.print(), self.op3.print()),
+            Operation::Ldrd | Operation::Strd | Operation::Strex | Operation::Strexb | Operation::Strexh => {
+                format!("{} {}, {}, {}", m, self.rd.print(), self.op3.print(), self.print_address())
+            },
+            Operation::Ldrex | Operation::Ldrexb | Operation::Ldrexh => format!("{} {}, {}", m, self.rd.print(), self.print_address()),
+            Operation::Mrs => format!("{} {}, {}", m, self.rd.print(), self.special_register()),
+            Operation::Msr => format!("{} {}, {}", m, self.special_register(), self.rn.print()),
+            Operation::Ssat | Operation::Usat => format!("{} {}, {}, {}", m, self.rd.print(), self.op2.print(), self.rn.print()),
+            Operation::Tbb => format!("{} [{}, {}]", m, self.rn.print(), self.op2.print()),
+            Operation::Tbh => format!("{} [{}, {}, lsl #1]", m, self.rn.print(), self.op2.print()),
+            Operation::It => self.print_it(),
+            Operation::Nop | Operation::Yield | Operation::Wfe | Operation::Wfi | Operation::Sev | Operation::Clrex => m,
+            Operation::Dmb | Operation::Dsb | Operation::Isb => match self.op2 {
+                Operand::ImmU32(option) => format!("{} {}", m, BARRIER_OPTIONS[option as usize & 0xf]),
+                _ => m,
+            },
+            Operation::Cpsie | Operation::Cpsid => match self.op2 {
+                Operand::ImmU32(aif) => format!("{} {}", m, ["a", "i", "f"].iter().enumerate()
+                    .filter(|(i, _)| aif & (4 >> i) != 0).map(|(_, flag)| *flag).collect::<String>()),
+                _ => m,
+            },
+            Operation::Pld => format!("{} {}", m, self.print_address()),
+            Operation::Unknown => "???".to_string(),
+            _ => format!("{} {}, {}, {}", m, self.rd.print(), self.rn.print(), self.op2.print()),
+        }
+    }
+
+    // The SYSm register of mrs and msr, with msr's mask of the APSR flags
+    // to write as a suffix.
+    fn special_register(self) -> String {
+        let (mask, sysm) = match self.op2 {
+            Operand::ImmU32(x) => (x >> 8, x & 0xff),
+            _ => return "???".to_string(),
+        };
+        let name = SPECIAL_REGISTERS.iter().find(|(n, _)| *n == sysm).map_or("???", |(_, name)| name);
+        let flags = match mask {
+            0b10 => "_nzcvq",
+            0b01 => "_g",
+            0b11 => "_nzcvqg",
+            _ => "",
+        };
+        if self.operation == Operation::Msr && sysm < 4 { format!("{}{}", name, flags) } else { name.to_string() }
+    }
+
+    // Branch targets are relative to the start of the instruction.
+    fn target(self) -> String {
+        match self.op2 {
+            Operand::ImmS32(x) => x.to_string(),
+            op => op.print(),
+        }
+    }
+
+    // it, then t or e for each further instruction in the block, then the
+    // condition of the first.
+    fn print_it(self) -> String {
+        let state = match self.op2 {
+            Operand::ImmU32(state) => state,
+            _ => return "it".to_string(),
+        };
+        let (first, mask) = (state >> 4, state & 0xf);
+        let rest: String = (0..3 - mask.trailing_zeros().min(3))
+            .map(|i| if (mask >> (3 - i)) & 1 == first & 1 { 't' } else { 'e' })
+            .collect();
+        format!("it{} {}", rest, if first == COND_AL as u32 { "al" } else { COND_NAMES[first as usize] })
+    }
+
+    pub fn offset(self) -> usize {
+        self.offset
+    }
+
+    pub fn size(self) -> usize {
+        self.ins_size as usize
+    }
+
+    fn memory_operand(self) -> dis::Operand {
+        let rn = match self.rn { Operand::Reg(r) => r, _ => 0 };
+        let base = Some(Register(rn).reg());
+        let size = self.operation.access_size();
+        let up = self.addr_mode & ADDR_UP != 0;
+        if self.addr_mode & ADDR_PRE == 0 {
+            return dis::Operand::Memory(base, None, 0, 0, size)
+        }
+        // pc is taken as the instruction plus 8. Thumb reads it as the
+        // instruction plus 4, rounded down to a word for literal loads.
+        let pc_adjust = if self.thumb && rn == Register::PC.0 { -4 - (self.offset as i64 & 2) } else { 0 };
+        match (self.op2, self.scaled_index()) {
+            (Operand::ImmU32(x), _) => dis::Operand::Memory(base, None, 0, if up { i64::from(x) } else { -i64::from(x) } + pc_adjust, size),
+            (_, Some((r, scale))) => dis::Operand::Memory(base, Some(Register(r).reg()), scale, 0, size),
+            // The rest go by the offset operand; see writeback_flags.
+            _ => dis::Operand::Memory(base, None, 0, 0, size),
+        }
+    }
+
+    // A register offset that is added and shifted left by few enough bits to
+    // be the index and scale of a Memory operand.
+    fn scaled_index(self) -> Option<(u8, u8)> {
+        if self.addr_mode & ADDR_UP == 0 {
+            return None
+        }
+        match self.op2 {
+            Operand::Reg(r) => Some((r, 1)),
+            Operand::RegShift(r, 0, amount) if amount < 8 => Some((r, 1 << amount)),
+            _ => None,
+        }
+    }
+
+    fn writeback_flags(self) -> u64 {
+        let register = matches!(self.op2, Operand::Reg(_) | Operand::RegShift(..));
+        let subtract = if register && self.addr_mode & ADDR_UP == 0 { dis::FLAG_SUBTRACT_OFFSET } else { 0 };
+        if self.addr_mode & ADDR_PRE == 0 {
+            dis::FLAG_POST_INDEX | subtract
+        } else if self.addr_mode & ADDR_WRITEBACK != 0 {
+            dis::FLAG_PRE_INDEX | subtract
+        } else if register && self.scaled_index().is_none() {
+            dis::FLAG_REGISTER_OFFSET | subtract
+        } else {
+            0
+        }
+    }
+
+    fn writeback_amount(self) -> dis::Operand {
+        let up = self.addr_mode & ADDR_UP != 0;
+        match self.op2 {
+            Operand::ImmU32(x) => dis::Operand::Immediate(if up { x.into() } else { -i64::from(x) }),
+            op => op.into(),
+        }
+    }
+
+    pub fn into(&self) -> dis::Instruction {
+        let cond = cond_to_dis(self.cond);
+        let s = if self.set_flags { dis::FLAG_SETS_FLAGS } else { 0 };
+        let is_pc = |op: Operand| matches!(op, Operand::Reg(r) if r == Register::PC.0);
+        match self.operation {
+            Operation::And => dis::Instruction { opcode: "and", operands: vec![self.rd.into(), self.rn.into(), self.op2.into()], flags: cond | s },
+            Operation::Eor => dis::Instruction { opcode: "xor", operands: vec![self.rd.into(), self.rn.into(), self.op2.into()], flags: cond | s },
+            Operation::Sub => dis::Instruction { opcode: "sub", operands: vec![self.rd.into(), self.rn.into(), self.op2.into()], flags: cond | s },
+            Operation::Rsb => dis::Instruction { opcode: "sub", operands: vec![self.rd.into(), self.op2.into(), self.rn.into()], flags: cond | s },
+            Operation::Add => dis::Instruction { opcode: "add", operands: vec![self.rd.into(), self.rn.into(), self.op2.into()], flags: cond | s },
+            Operation::Adc => dis::Instruction { opcode: "adc", operands: vec![self.rd.into(), self.rn.into(), self.op2.into()], flags: cond | s },
+            Operation::Sbc => dis::Instruction { opcode: "sbc", operands: vec![self.rd.into(), self.rn.into(), self.op2.into()], flags: cond | s },
+            Operation::Rsc => dis::Instruction { opcode: "sbc", operands: vec![self.rd.into(), self.op2.into(), self.rn.into()], flags: cond | s },
+            Operation::Orr => dis::Instruction { opcode: "or", operands: vec![self.rd.into(), self.rn.into(), self.op2.into()], flags: cond | s },
+            Operation::Bic => dis::Instruction { opcode: "bic", operands: vec![self.rd.into(), self.rn.into(), self.op2.into()], flags: cond | s },
+            Operation::Tst => dis::Instruction { opcode: "tst", operands: vec![self.rn.into(), self.op2.into()], flags: cond },
+            Operation::Teq => dis::Instruction { opcode: "teq", operands: vec![self.rn.into(), self.op2.into()], flags: cond },
+            Operation::Cmp => dis::Instruction { opcode: "cmp", operands: vec![self.rn.into(), self.op2.into()], flags: cond },
+            Operation::Cmn => dis::Instruction { opcode: "cmn", operands: vec![self.rn.into(), self.op2.into()], flags: cond },
+            Operation::Mov if is_pc(self.rd) && matches!(self.op2, Operand::RegShift(r, 0, 0) if r == Register::LR.0) => {
+                dis::Instruction { opcode: "ret", operands: vec![], flags: cond }
+            },
+            Operation::Mov => dis::Instruction { opcode: "mov", operands: vec![self.rd.into(), self.op2.into()], flags: cond | s },
+            Operation::Movt => dis::Instruction { opcode: "movt", operands: vec![self.rd.into(), self.op2.into()], flags: cond },
+            Operation::Movw => dis::Instruction { opcode: "mov", operands: vec![self.rd.into(), self.op2.into()], flags: cond },
+            Operation::Mvn => dis::Instruction { opcode: "not", operands: vec![self.rd.into(), self.op2.into()], flags: cond | s },
+            Operation::Mul => dis::Instruction { opcode: "mul", operands: vec![self.rd.into(), self.op2.into(), self.op3.into()], flags: cond | s },
+            Operation::Mla => dis::Instruction { opcode: "mla", operands: vec![self.rd.into(), self.op2.into(), self.op3.into(), self.rn.into()], flags: cond | s },
+            Operation::Ldr | Operation::Ldrb | Operation::Ldrh | Operation::Ldrt | Operation::Ldrbt | Operation::Ldrht
+            | Operation::Ldrex | Operation::Ldrexb | Operation::Ldrexh => dis::Instruction { opcode: "ldr",
+                operands: vec![self.rd.into(), self.memory_operand(), self.writeback_amount()], flags: cond | self.writeback_flags() },
+            Operation::Ldrsb | Operation::Ldrsh | Operation::Ldrsbt | Operation::Ldrsht => dis::Instruction { opcode: "ldrs",
+                operands: vec![self.rd.into(), self.memory_operand(), self.writeback_amount()], flags: cond | self.writeback_flags() },
+            Operation::Str | Operation::Strb | Operation::Strh | Operation::Strt | Operation::Strbt | Operation::Strht => dis::Instruction { opcode: "str",
+                operands: vec![self.rd.into(), self.memory_operand(), self.writeback_amount()], flags: cond | self.writeback_flags() },
+            Operation::Ldm | Operation::Stm => {
+                let regs = self.op2.reg_list();
+                let total = 4 * regs.len() as i64;
+                let up = self.addr_mode & ADDR_UP != 0;
+                let pre = self.addr_mode & ADDR_PRE != 0;
+                let first = match (pre, up) {
+                    (false, true) => 0,
+                    (true, true) => 4,
+                    (false, false) => 4 - total,
+                    (true, false) => -total,
+                };
+                let writeback = if self.addr_mode & ADDR_WRITEBACK != 0 { if up { total } else { -total } } else { 0 };
+                let mut operands = vec![self.rn.into(), dis::Operand::Immediate(first), dis::Operand::Immediate(writeback)];
+                operands.extend(regs);
+                dis::Instruction { opcode: if self.operation == Operation::Ldm { "ldm" } else { "stm" }, operands, flags: cond }
+            },
+            Operation::Push => dis::Instruction { opcode: "push", operands: self.op2.reg_list(), flags: cond },
+            Operation::Pop  => dis::Instruction { opcode: "pop", operands: self.op2.reg_list(), flags: cond },
+            Operation::B    => dis::Instruction { opcode: "b", operands: vec![self.op2.into()], flags: cond },
+            Operation::Bl   => dis::Instruction { opcode: "call", operands: vec![self.op2.into()], flags: cond },
+            Operation::Bx if matches!(self.op2, Operand::Reg(r) if r == Register::LR.0) => dis::Instruction { opcode: "ret", operands: vec![], flags: cond },
+            Operation::Bx   => dis::Instruction { opcode: "b", operands: vec![self.op2.into()], flags: cond },
+            Operation::Blx  => dis::Instruction { opcode: "call", operands: vec![self.op2.into()], flags: cond },
+            Operation::Svc  => dis::Instruction { opcode: "svc", operands: vec![self.op2.into()], flags: cond },
+            // Shifts are moves of a shifted register, as in ARM code.
+            Operation::Lsl | Operation::Lsr | Operation::Asr | Operation::Ror => {
+                let shift = match self.operation { Operation::Lsl => 0, Operation::Lsr => 1, Operation::Asr => 2, _ => 3 };
+                let rm = Register(match self.rn { Operand::Reg(r) => r, _ => 0 }).reg();
+                let src = match self.op2 {
+                    Operand::Reg(rs) => dis::Operand::RegisterShiftedRegister(rm, shift, Register(rs).reg()),
+                    Operand::ImmU32(amount) => dis::Operand::ShiftedRegister(rm, shift, amount.into()),
+                    _ => dis::Operand::Nothing,
+                };
+                dis::Instruction { opcode: "mov", operands: vec![self.rd.into(), src], flags: cond | s }
+            },
+            Operation::Orn => dis::Instruction { opcode: "orn", operands: vec![self.rd.into(), self.rn.into(), self.op2.into()], flags: cond | s },
+            Operation::Adr => dis::Instruction { opcode: "adr", operands: vec![self.rd.into(), self.op2.into()], flags: cond },
+            // Compared with zero like RISC-V branches.
+            Operation::Cbz  => dis::Instruction { opcode: "beq", operands: vec![self.rn.into(), dis::Operand::Immediate(0), self.op2.into()], flags: 0 },
+            Operation::Cbnz => dis::Instruction { opcode: "bne", operands: vec![self.rn.into(), dis::Operand::Immediate(0), self.op2.into()], flags: 0 },
+            Operation::Uxtb | Operation::Uxth if matches!(self.op2, Operand::RegShift(_, 0, 0)) => dis::Instruction { opcode: "and",
+                operands: vec![self.rd.into(), self.op2.into(), dis::Operand::Immediate(if self.operation == Operation::Uxtb { 0xff } else { 0xffff })], flags: cond },
+            Operation::Mls => dis::Instruction { opcode: "mls", operands: vec![self.rd.into(), self.op2.into(), self.op3.into(), self.rn.into()], flags: cond },
+            // A pair of registers, like ldm and stm.
+            Operation::Ldrd | Operation::Strd => {
+                let offset = match self.op2 { Operand::ImmU32(x) => i64::from(x), _ => 0 };
+                let offset = if self.addr_mode & ADDR_UP != 0 { offset } else { -offset };
+                let pre = self.addr_mode & ADDR_PRE != 0;
+                let first = if pre { offset } else { 0 };
+                let writeback = if !pre || self.addr_mode & ADDR_WRITEBACK != 0 { offset } else { 0 };
+                dis::Instruction { opcode: if self.operation == Operation::Ldrd { "ldm" } else { "stm" },
+                    operands: vec![self.rn.into(), dis::Operand::Immediate(first), dis::Operand::Immediate(writeback), self.rd.into(), self.op3.into()], flags: cond }
+            },
+            // Jumps through a table of halved offsets.
+            Operation::Tbb | Operation::Tbh => {
+                let size = self.operation.access_size();
+                let base = Register(match self.rn { Operand::Reg(r) => r, _ => 0 }).reg();
+                let index = Register(match self.op2 { Operand::Reg(r) => r, _ => 0 }).reg();
+                dis::Instruction { opcode: "b", operands: vec![dis::Operand::Memory(Some(base), Some(index), size, 0, size)], flags: cond }
+            },
+            Operation::It | Operation::Nop | Operation::Yield | Operation::Wfe | Operation::Wfi | Operation::Sev
+            | Operation::Dmb | Operation::Dsb | Operation::Isb | Operation::Cpsie | Operation::Cpsid | Operation::Pld => {
+                dis::Instruction { opcode: "nop", operands: vec![], flags: cond }
+            },
+            // The status register, the value and the address.
+            Operation::Strex | Operation::Strexb | Operation::Strexh => dis::Instruction { opcode: self.operation.name(),
+                operands: vec![self.rd.into(), self.op3.into(), self.memory_operand()], flags: cond },
+            Operation::Clrex => dis::Instruction { opcode: "nop", operands: vec![], flags: cond },
+            // The special register stays as its SYSm number.
+            Operation::Mrs => dis::Instruction { opcode: "mrs", operands: vec![self.rd.into(), self.op2.into()], flags: cond },
+            Operation::Msr => dis::Instruction { opcode: "msr", operands: vec![self.op2.into(), self.rn.into()], flags: cond },
+            Operation::Udf | Operation::Unknown => dis::Instruction { opcode: "unk", operands: vec![], flags: 0 },
+            // The rest keep their name, with the destination first.
+            _ => {
+                let operands = [self.rd, self.rn, self.op2, self.op3].iter()
+                    .filter(|op| !matches!(op, Operand::Nothing))
+                    .map(|&op| op.into())
+                    .collect();
+                dis::Instruction { opcode: self.operation.name(), operands, flags: cond | s }
+            },
+        }
+    }
+}
+
+fn unknown(offset: usize) -> Instruction {
+    Instruction { operation: Operation::Unknown, cond: COND_AL, set_flags: false, rd: Operand::Nothing, rn: Operand::Nothing,
+        op2: Operand::Nothing, op3: Operand::Nothing, addr_mode: 0, offset, ins_size: 4, thumb: false }
+}
+
+fn cond(ins: u32) -> u8 {
+    (ins >> 28) as u8
+}
+
+fn rn(ins: u32) -> u8 {
+    ins.bextr(19, 16) as u8
+}
+
+fn rd(ins: u32) -> u8 {
+    ins.bextr(15, 12) as u8
+}
+
+fn rs(ins: u32) -> u8 {
+    ins.bextr(11, 8) as u8
+}
+
+fn rm(ins: u32) -> u8 {
+    ins.bextr(3, 0) as u8
+}
+
+// imm8 rotated right by twice the 4-bit rotate field.
+fn rotated_imm(ins: u32) -> u32 {
+    ins.bextr(7, 0).rotate_right(ins.bextr(11, 8) * 2)
+}
+
+// A shift of 0 means 32 for lsr and asr.
+fn shift_amount(shift: u8, amount: u32) -> u32 {
+    if amount == 0 && (shift == 1 || shift == 2) { 32 } else { amount }
+}
+
+// A register shifted by imm5, which is #32 for lsr and asr and rrx for ror.
+fn imm_shifted_register(ins: u32) -> Operand {
+    let shift = ins.bextr(6, 5) as u8;
+    Operand::RegShift(rm(ins), shift, shift_amount(shift, ins.bextr(11, 7)) as u8)
+}
+
+fn shifter_operand(ins: u32) -> Operand {
+    if ins.bextr(4, 4) == 0 {
+        imm_shifted_register(ins)
+    } else {
+        Operand::RegShiftReg(rm(ins), ins.bextr(6, 5) as u8, rs(ins))
+    }
+}
+
+fn addr_mode(ins: u32) -> u8 {
+    let mut mode = 0u8;
+    if ins.bextr(24, 24) != 0 {
+        mode |= ADDR_PRE;
+    }
+    if ins.bextr(23, 23) != 0 {
+        mode |= ADDR_UP;
+    }
+    if ins.bextr(21, 21) != 0 {
+        mode |= ADDR_WRITEBACK;
+    }
+    mode
+}
+
+fn disassemble_data_processing(ins: u32, offset: usize, op2: Operand) -> Instruction {
+    let operation = match ins.bextr(24, 21) {
+        0x0 => Operation::And,
+        0x1 => Operation::Eor,
+        0x2 => Operation::Sub,
+        0x3 => Operation::Rsb,
+        0x4 => Operation::Add,
+        0x5 => Operation::Adc,
+        0x6 => Operation::Sbc,
+        0x7 => Operation::Rsc,
+        0x8 => Operation::Tst,
+        0x9 => Operation::Teq,
+        0xa => Operation::Cmp,
+        0xb => Operation::Cmn,
+        0xc => Operation::Orr,
+        0xd => Operation::Mov,
+        0xe => Operation::Bic,
+        _ => Operation::Mvn,
+    };
+    let set_flags = ins.bextr(20, 20) != 0;
+    // Compare ops without the S bit are the miscellaneous instruction space.
+    if !set_flags && matches!(operation, Operation::Tst | Operation::Teq | Operation::Cmp | Operation::Cmn) {
+        return unknown(offset)
+    }
+    Instruction { operation, cond: cond(ins), set_flags, rd: Operand::Reg(rd(ins)), rn: Operand::Reg(rn(ins)),
+        op2, op3: Operand::Nothing, addr_mode: 0, offset, ins_size: 4, thumb: false }
+}
+
+fn disassemble_multiply(ins: u32, offset: usize) -> Instruction {
+    let operation = if ins.bextr(21, 21) != 0 { Operation::Mla } else { Operation::Mul };
+    Instruction { operation, cond: cond(ins), set_flags: ins.bextr(20, 20) != 0, rd: Operand::Reg(rn(ins)), rn: Operand::Reg(rd(ins)),
+        op2: Operand::Reg(rm(ins)), op3: Operand::Reg(rs(ins)), addr_mode: 0, offset, ins_size: 4, thumb: false }
+}
+
+fn disassemble_extra_load_store(ins: u32, offset: usize) -> Instruction {
+    let load = ins.bextr(20, 20) != 0;
+    let operation = match (load, ins.bextr(6, 5)) {
+        (false, 0b01) => Operation::Strh,
+        (true, 0b01) => Operation::Ldrh,
+        (true, 0b10) => Operation::Ldrsb,
+        (true, 0b11) => Operation::Ldrsh,
+        _ => return unknown(offset),
+    };
+    let op2 = if ins.bextr(22, 22) != 0 {
+        Operand::ImmU32((ins.bextr(11, 8) << 4) | ins.bextr(3, 0))
+    } else {
+        Operand::Reg(rm(ins))
+    };
+    let mode = addr_mode(ins);
+    Instruction { operation: unprivileged_if_post_writeback(operation, mode), cond: cond(ins), set_flags: false, rd: Operand::Reg(rd(ins)), rn: Operand::Reg(rn(ins)),
+        op2, op3: Operand::Nothing, addr_mode: mode, offset, ins_size: 4, thumb: false }
+}
+
+fn disassemble_load_store(ins: u32, offset: usize, op2: Operand) -> Instruction {
+    let operation = match (ins.bextr(20, 20) != 0, ins.bextr(22, 22) != 0) {
+        (true, false) => Operation::Ldr,
+        (true, true) => Operation::Ldrb,
+        (false, false) => Operation::Str,
+        (false, true) => Operation::Strb,
+    };
+    let mode = addr_mode(ins);
+    Instruction { operation: unprivileged_if_post_writeback(operation, mode), cond: cond(ins), set_flags: false, rd: Operand::Reg(rd(ins)), rn: Operand::Reg(rn(ins)),
+        op2, op3: Operand::Nothing, addr_mode: mode, offset, ins_size: 4, thumb: false }
+}
+
+// Post-indexing always writes back, so W set with P clear is the unprivileged
+// form instead.
+fn unprivileged_if_post_writeback(operation: Operation, mode: u8) -> Operation {
+    if mode & (ADDR_PRE | ADDR_WRITEBACK) == ADDR_WRITEBACK { operation.unprivileged() } else { operation }
+}
+
+// stmdb sp!, {...} and ldmia sp!, {...} are push and pop.
+fn block_transfer_operation(load: bool, base: u8, mode: u8) -> Operation {
+    if base == Register::SP.0 && mode == (ADDR_PRE | ADDR_WRITEBACK) && !load {
+        Operation::Push
+    } else if base == Register::SP.0 && mode == (ADDR_UP | ADDR_WRITEBACK) && load {
+        Operation::Pop
+    } else if load {
+        Operation::Ldm
+    } else {
+        Operation::Stm
+    }
+}
+
+fn disassemble_block_transfer(ins: u32, offset: usize) -> Instruction {
+    let load = ins.bextr(20, 20) != 0;
+    let user = if ins.bextr(22, 22) != 0 { ADDR_USER } else { 0 };
+    let mode = addr_mode(ins) | user;
+    let list = ins.bextr(15, 0) as u16;
+    let base = rn(ins);
+    Instruction { operation: block_transfer_operation(load, base, mode), cond: cond(ins), set_flags: false, rd: Operand::Nothing, rn: Operand::Reg(base),
+        op2: Operand::RegList(list), op3: Operand::Nothing, addr_mode: mode, offset, ins_size: 4, thumb: false }
+}
+
+fn disassemble_branch(ins: u32, offset: usize) -> Instruction {
+    let operation = if ins.bextr(24, 24) != 0 { Operation::Bl } else { Operation::B };
+    // PC reads as the address of the current instruction plus 8.
+    let imm = (((ins << 8) as i32) >> 6) + 8;
+    Instruction { operation, cond: cond(ins), set_flags: false, rd: Operand::Nothing, rn: Operand::Nothing,
+        op2: Operand::ImmS32(imm), op3: Operand::Nothing, addr_mode: 0, offset, ins_size: 4, thumb: false }
+}
+
+fn disassemble_branch_exchange(ins: u32, offset: usize, operation: Operation) -> Instruction {
+    Instruction { operation, cond: cond(ins), set_flags: false, rd: Operand::Nothing, rn: Operand::Nothing,
+        op2: Operand::Reg(rm(ins)), op3: Operand::Nothing, addr_mode: 0, offset, ins_size: 4, thumb: false }
+}
+
+fn disassemble_movw_movt(ins: u32, offset: usize, operation: Operation) -> Instruction {
+    let imm = (ins.bextr(19, 16) << 12) | ins.bextr(11, 0);
+    Instruction { operation, cond: cond(ins), set_flags: false, rd: Operand::Reg(rd(ins)), rn: Operand::Nothing,
+        op2: Operand::ImmU32(imm), op3: Operand::Nothing, addr_mode: 0, offset, ins_size: 4, thumb: false }
+}
+
+fn disassemble_svc(ins: u32, offset: usize) -> Instruction {
+    Instruction { operation: Operation::Svc, cond: cond(ins), set_flags: false, rd: Operand::Nothing, rn: Operand::Nothing,
+        op2: Operand::ImmU32(ins.bextr(23, 0)), op3: Operand::Nothing, addr_mode: 0, offset, ins_size: 4, thumb: false }
+}
+
+fn disassemble_instruction(ins: u32, offset: usize) -> Instruction {
+    if cond(ins) == 0xf {
+        return unknown(offset)
+    }
+    match ins.bextr(27, 25) {
+        0b000 => {
+            if ins & 0x0ffffff0 == 0x012fff10 {
+                disassemble_branch_exchange(ins, offset, Operation::Bx)
+            }
+            else if ins & 0x0ffffff0 == 0x012fff30 {
+                disassemble_branch_exchange(ins, offset, Operation::Blx)
+            }
+            else if ins.bextr(7, 4) == 0b1001 && ins.bextr(24, 22) == 0 {
+                disassemble_multiply(ins, offset)
+            }
+            else if ins.bextr(7, 7) == 1 && ins.bextr(4, 4) == 1 {
+                disassemble_extra_load_store(ins, offset)
+            }
+            else {
+                disassemble_data_processing(ins, offset, shifter_operand(ins))
+            }
+        },
+        0b001 => match ins.bextr(24, 20) {
+            0b10000 => disassemble_movw_movt(ins, offset, Operation::Movw),
+            0b10100 => disassemble_movw_movt(ins, offset, Operation::Movt),
+            _ => disassemble_data_processing(ins, offset, Operand::ImmU32(rotated_imm(ins))),
+        },
+        0b010 => disassemble_load_store(ins, offset, Operand::ImmU32(ins.bextr(11, 0))),
+        0b011 => if ins.bextr(4, 4) == 0 {
+            disassemble_load_store(ins, offset, imm_shifted_register(ins))
+        } else {
+            unknown(offset)
+        },
+        0b100 => disassemble_block_transfer(ins, offset),
+        0b101 => disassemble_branch(ins, offset),
+        0b111 if ins.bextr(24, 24) == 1 => disassemble_svc(ins, offset),
+        _ => unknown(offset),
+    }
+}
+
+// A Thumb instruction without a condition, flags or addressing mode.
+fn thumb(operation: Operation, rd: Operand, rn: Operand, op2: Operand, offset: usize, ins_size: u8) -> Instruction {
+    Instruction { operation, cond: COND_AL, set_flags: false, rd, rn, op2, op3: Operand::Nothing, addr_mode: 0, offset, ins_size, thumb: true }
+}
+
+fn thumb_unknown(offset: usize, ins_size: u8) -> Instruction {
+    Instruction { ins_size, thumb: true, ..unknown(offset) }
+}
+
+// Thumb reads pc as the instruction plus 4; adr and blx round it down to a word.
+fn thumb_aligned_pc(offset: usize) -> i32 {
+    4 - (offset as i32 & 2)
+}
+
+const THUMB_SHIFTS: [Operation; 4] = [Operation::Lsl, Operation::Lsr, Operation::Asr, Operation::Ror];
+
+// Flag-setting 16-bit instructions don't set the flags inside an IT block.
+fn decode_thumb16(ins: u32, offset: usize, in_it: bool) -> Instruction {
+    let s = !in_it;
+    let reg = |hi: u32, lo: u32| Operand::Reg(ins.bextr(hi, lo) as u8);
+    let shifted = |hi: u32, lo: u32| Operand::RegShift(ins.bextr(hi, lo) as u8, 0, 0);
+    let t = |operation, rd, rn, op2| thumb(operation, rd, rn, op2, offset, 2);
+    let pre = ADDR_PRE | ADDR_UP;
+    match ins.bextr(15, 11) {
+        0b00000..=0b00010 => {
+            let shift = ins.bextr(12, 11) as u8;
+            let amount = ins.bextr(10, 6);
+            if shift == 0 && amount == 0 {
+                Instruction { set_flags: s, ..t(Operation::Mov, reg(2, 0), Operand::Nothing, shifted(5, 3)) }
+            } else {
+                Instruction { set_flags: s, ..t(THUMB_SHIFTS[shift as usize], reg(2, 0), reg(5, 3), Operand::ImmU32(shift_amount(shift, amount))) }
+            }
+        },
+        0b00011 => {
+            let operation = if ins.bextr(9, 9) != 0 { Operation::Sub } else { Operation::Add };
+            let op2 = if ins.bextr(10, 10) != 0 { Operand::ImmU32(ins.bextr(8, 6)) } else { shifted(8, 6) };
+            Instruction { set_flags: s, ..t(operation, reg(2, 0), reg(5, 3), op2) }
+        },
+        0b00100..=0b00111 => {
+            let imm = Operand::ImmU32(ins.bextr(7, 0));
+            match ins.bextr(12, 11) {
+                0b00 => Instruction { set_flags: s, ..t(Operation::Mov, reg(10, 8), Operand::Nothing, imm) },
+                0b01 => Instruction { set_flags: true, ..t(Operation::Cmp, Operand::Nothing, reg(10, 8), imm) },
+                0b10 => Instruction { set_flags: s, ..t(Operation::Add, reg(10, 8), reg(10, 8), imm) },
+                _ => Instruction { set_flags: s, ..t(Operation::Sub, reg(10, 8), reg(10, 8), imm) },
+            }
+        },
+        0b01000 if ins.bextr(10, 10) == 0 => {
+            let (rdn, rm) = (reg(2, 0), shifted(5, 3));
+            let (operation, rd, rn, op2) = match ins.bextr(9, 6) {
+                0x0 => (Operation::And, rdn, rdn, rm),
+                0x1 => (Operation::Eor, rdn, rdn, rm),
+                0x2 => (Operation::Lsl, rdn, rdn, reg(5, 3)),
+                0x3 => (Operation::Lsr, rdn, rdn, reg(5, 3)),
+                0x4 => (Operation::Asr, rdn, rdn, reg(5, 3)),
+                0x5 => (Operation::Adc, rdn, rdn, rm),
+                0x6 => (Operation::Sbc, rdn, rdn, rm),
+                0x7 => (Operation::Ror, rdn, rdn, reg(5, 3)),
+                0x8 => (Operation::Tst, Operand::Nothing, rdn, rm),
+                0x9 => (Operation::Rsb, rdn, reg(5, 3), Operand::ImmU32(0)),
+                0xa => (Operation::Cmp, Operand::Nothing, rdn, rm),
+                0xb => (Operation::Cmn, Operand::Nothing, rdn, rm),
+                0xc => (Operation::Orr, rdn, rdn, rm),
+                0xd => return Instruction { set_flags: s, op3: rdn, ..t(Operation::Mul, rdn, Operand::Nothing, reg(5, 3)) },
+                0xe => (Operation::Bic, rdn, rdn, rm),
+                _ => (Operation::Mvn, rdn, Operand::Nothing, rm),
+            };
+            let set_flags = s || matches!(operation, Operation::Tst | Operation::Cmp | Operation::Cmn);
+            Instruction { set_flags, ..t(operation, rd, rn, op2) }
+        },
+        // High registers, which never set the flags.
+        0b01000 => {
+            let rdn = ((ins.bextr(7, 7) << 3) | ins.bextr(2, 0)) as u8;
+            match ins.bextr(9, 8) {
+                0b00 => t(Operation::Add, Operand::Reg(rdn), Operand::Reg(rdn), shifted(6, 3)),
+                0b01 => Instruction { set_flags: true, ..t(Operation::Cmp, Operand::Nothing, Operand::Reg(rdn), shifted(6, 3)) },
+                0b10 => t(Operation::Mov, Operand::Reg(rdn), Operand::Nothing, shifted(6, 3)),
+                _ => {
+                    let operation = if ins.bextr(7, 7) != 0 { Operation::Blx } else { Operation::Bx };
+                    t(operation, Operand::Nothing, Operand::Nothing, reg(6, 3))
+                },
+            }
+        },
+        0b01001 => Instruction { addr_mode: pre, ..t(Operation::Ldr, reg(10, 8), Operand::Reg(Register::PC.0), Operand::ImmU32(ins.bextr(7, 0) * 4)) },
+        0b01010 | 0b01011 => {
+            let operation = [Operation::Str, Operation::Strh, Operation::Strb, Operation::Ldrsb,
+                Operation::Ldr, Operation::Ldrh, Operation::Ldrb, Operation::Ldrsh][ins.bextr(11, 9) as usize];
+            Instruction { addr_mode: pre, ..t(operation, reg(2, 0), reg(5, 3), reg(8, 6)) }
+        },
+        0b01100..=0b01111 => {
+            let (operation, scale) = match ins.bextr(12, 11) {
+                0b00 => (Operation::Str, 4),
+                0b01 => (Operation::Ldr, 4),
+                0b10 => (Operation::Strb, 1),
+                _ => (Operation::Ldrb, 1),
+            };
+            Instruction { addr_mode: pre, ..t(operation, reg(2, 0), reg(5, 3), Operand::ImmU32(ins.bextr(10, 6) * scale)) }
+        },
+        0b10000 | 0b10001 => {
+            let operation = if ins.bextr(11, 11) != 0 { Operation::Ldrh } else { Operation::Strh };
+            Instruction { addr_mode: pre, ..t(operation, reg(2, 0), reg(5, 3), Operand::ImmU32(ins.bextr(10, 6) * 2)) }
+        },
+        0b10010 | 0b10011 => {
+            let operation = if ins.bextr(11, 11) != 0 { Operation::Ldr } else { Operation::Str };
+            Instruction { addr_mode: pre, ..t(operation, reg(10, 8), Operand::Reg(Register::SP.0), Operand::ImmU32(ins.bextr(7, 0) * 4)) }
+        },
+        0b10100 => t(Operation::Adr, reg(10, 8), Operand::Nothing, Operand::ImmS32(thumb_aligned_pc(offset) + ins.bextr(7, 0) as i32 * 4)),
+        0b10101 => t(Operation::Add, reg(10, 8), Operand::Reg(Register::SP.0), Operand::ImmU32(ins.bextr(7, 0) * 4)),
+        0b10110 | 0b10111 => decode_thumb16_misc(ins, offset),
+        0b11000 => Instruction { addr_mode: ADDR_UP | ADDR_WRITEBACK,
+            ..t(Operation::Stm, Operand::Nothing, reg(10, 8), Operand::RegList(ins.bextr(7, 0) as u16)) },
+        0b11001 => {
+            // The base is only written back when it isn't loaded.
+            let writeback = if ins.bextr(7, 0) & (1 << ins.bextr(10, 8)) == 0 { ADDR_WRITEBACK } else { 0 };
+            Instruction { addr_mode: ADDR_UP | writeback, ..t(Operation::Ldm, Operand::Nothing, reg(10, 8), Operand::RegList(ins.bextr(7, 0) as u16)) }
+        },
+        0b11010 | 0b11011 => match ins.bextr(11, 8) as u8 {
+            0xe => t(Operation::Udf, Operand::Nothing, Operand::Nothing, Operand::ImmU32(ins.bextr(7, 0))),
+            0xf => t(Operation::Svc, Operand::Nothing, Operand::Nothing, Operand::ImmU32(ins.bextr(7, 0))),
+            cond => Instruction { cond, ..t(Operation::B, Operand::Nothing, Operand::Nothing, Operand::ImmS32((((ins << 24) as i32) >> 23) + 4)) },
+        },
+        0b11100 => t(Operation::B, Operand::Nothing, Operand::Nothing, Operand::ImmS32((((ins << 21) as i32) >> 20) + 4)),
+        _ => thumb_unknown(offset, 2),
+    }
+}
+
+fn decode_thumb16_misc(ins: u32, offset: usize) -> Instruction {
+    let reg = |hi: u32, lo: u32| Operand::Reg(ins.bextr(hi, lo) as u8);
+    let t = |operation, rd, rn, op2| thumb(operation, rd, rn, op2, offset, 2);
+    let sp = Operand::Reg(Register::SP.0);
+    match ins.bextr(11, 8) {
+        0b0000 => {
+            let operation = if ins.bextr(7, 7) != 0 { Operation::Sub } else { Operation::Add };
+            t(operation, sp, sp, Operand::ImmU32(ins.bextr(6, 0) * 4))
+        },
+        0b0001 | 0b0011 | 0b1001 | 0b1011 => {
+            let operation = if ins.bextr(11, 11) != 0 { Operation::Cbnz } else { Operation::Cbz };
+            let imm = (ins.bextr(9, 9) << 6) | (ins.bextr(7, 3) << 1);
+            t(operation, Operand::Nothing, reg(2, 0), Operand::ImmS32(imm as i32 + 4))
+        },
+        0b0010 => {
+            let operation = [Operation::Sxth, Operation::Sxtb, Operation::Uxth, Operation::Uxtb][ins.bextr(7, 6) as usize];
+            t(operation, reg(2, 0), Operand::Nothing, Operand::RegShift(ins.bextr(5, 3) as u8, 0, 0))
+        },
+        0b0100 | 0b0101 => Instruction { addr_mode: ADDR_PRE | ADDR_WRITEBACK,
+            ..t(Operation::Push, Operand::Nothing, sp, Operand::RegList((ins.bextr(7, 0) | (ins.bextr(8, 8) << 14)) as u16)) },
+        0b1100 | 0b1101 => Instruction { addr_mode: ADDR_UP | ADDR_WRITEBACK,
+            ..t(Operation::Pop, Operand::Nothing, sp, Operand::RegList((ins.bextr(7, 0) | (ins.bextr(8, 8) << 15)) as u16)) },
+        0b0110 if ins.bextr(7, 5) == 0b011 && ins.bextr(3, 3) == 0 => {
+            let operation = if ins.bextr(4, 4) != 0 { Operation::Cpsid } else { Operation::Cpsie };
+            t(operation, Operand::Nothing, Operand::Nothing, Operand::ImmU32(ins.bextr(2, 0)))
+        },
+        0b1010 => {
+            let operation = match ins.bextr(7, 6) {
+                0b00 => Operation::Rev,
+                0b01 => Operation::Rev16,
+                0b11 => Operation::Revsh,
+                _ => return thumb_unknown(offset, 2),
+            };
+            t(operation, reg(2, 0), Operand::Nothing, reg(5, 3))
+        },
+        0b1110 => t(Operation::Bkpt, Operand::Nothing, Operand::Nothing, Operand::ImmU32(ins.bextr(7, 0))),
+        0b1111 if ins.bextr(3, 0) != 0 => t(Operation::It, Operand::Nothing, Operand::Nothing, Operand::ImmU32(ins.bextr(7, 0))),
+        0b1111 => match ins.bextr(7, 4) {
+            0 => t(Operation::Nop, Operand::Nothing, Operand::Nothing, Operand::Nothing),
+            1 => t(Operation::Yield, Operand::Nothing, Operand::Nothing, Operand::Nothing),
+            2 => t(Operation::Wfe, Operand::Nothing, Operand::Nothing, Operand::Nothing),
+            3 => t(Operation::Wfi, Operand::Nothing, Operand::Nothing, Operand::Nothing),
+            4 => t(Operation::Sev, Operand::Nothing, Operand::Nothing, Operand::Nothing),
+            _ => thumb_unknown(offset, 2),
+        },
+        _ => thumb_unknown(offset, 2),
+    }
+}
+
+// i:imm3:imm8 of a Thumb-2 data-processing instruction.
+fn thumb_imm12(hw1: u32, hw2: u32) -> u32 {
+    (hw1.bextr(10, 10) << 11) | (hw2.bextr(14, 12) << 8) | hw2.bextr(7, 0)
+}
+
+// A byte repeated in a pattern, or rotated with its top bit set.
+fn thumb_expand_imm(imm12: u32) -> u32 {
+    let imm8 = imm12 & 0xff;
+    if imm12 >> 10 != 0 {
+        return (0x80 | (imm12 & 0x7f)).rotate_right(imm12 >> 7)
+    }
+    match imm12 >> 8 {
+        0 => imm8,
+        1 => (imm8 << 16) | imm8,
+        2 => (imm8 << 24) | (imm8 << 8),
+        _ => imm8 * 0x01010101,
+    }
+}
+
+fn decode_thumb32(hw1: u32, hw2: u32, offset: usize) -> Instruction {
+    match (hw1.bextr(12, 11), hw2.bextr(15, 15)) {
+        (0b01, _) => match (hw1.bextr(10, 9), hw1.bextr(6, 6)) {
+            (0b00, 0) => decode_thumb32_block_transfer(hw1, hw2, offset),
+            (0b00, _) => decode_thumb32_dual(hw1, hw2, offset),
+            (0b01, _) => {
+                let shift = hw2.bextr(5, 4) as u8;
+                let amount = shift_amount(shift, (hw2.bextr(14, 12) << 2) | hw2.bextr(7, 6));
+                let ins = decode_thumb32_data_processing(hw1, hw2, offset, Operand::RegShift(hw2.bextr(3, 0) as u8, shift, amount as u8));
+                // mov with a shift is the shift instruction, apart from rrx.
+                match (ins.operation, ins.op2) {
+                    (Operation::Mov, Operand::RegShift(rm, shift, amount)) if amount != 0 => {
+                        Instruction { operation: THUMB_SHIFTS[shift as usize], rn: Operand::Reg(rm), op2: Operand::ImmU32(amount.into()), ..ins }
+                    },
+                    _ => ins,
+                }
+            },
+            _ => thumb_unknown(offset, 4),
+        },
+        (0b10, 0) if hw1.bextr(9, 9) == 0 => {
+            decode_thumb32_data_processing(hw1, hw2, offset, Operand::ImmU32(thumb_expand_imm(thumb_imm12(hw1, hw2))))
+        },
+        (0b10, 0) => decode_thumb32_plain_imm(hw1, hw2, offset),
+        (0b10, _) => decode_thumb32_branch(hw1, hw2, offset),
+        (0b11, _) => {
+            let op = hw1.bextr(10, 4);
+            if op & 0b1110001 == 0b0000000 {
+                let operation = match hw1.bextr(6, 5) {
+                    0b00 => Operation::Strb,
+                    0b01 => Operation::Strh,
+                    0b10 => Operation::Str,
+                    _ => return thumb_unknown(offset, 4),
+                };
+                decode_thumb32_load_store(hw1, hw2, offset, operation)
+            } else if op & 0b1100001 == 0b0000001 {
+                let pld = hw2.bextr(15, 12) == 0xf && hw1.bextr(6, 5) != 0b10;
+                let operation = match (hw1.bextr(6, 5), hw1.bextr(8, 8)) {
+                    _ if pld => Operation::Pld,
+                    (0b00, 0) => Operation::Ldrb,
+                    (0b00, _) => Operation::Ldrsb,
+                    (0b01, 0) => Operation::Ldrh,
+                    (0b01, _) => Operation::Ldrsh,
+                    (0b10, 0) => Operation::Ldr,
+                    _ => return thumb_unknown(offset, 4),
+                };
+                decode_thumb32_load_store(hw1, hw2, offset, operation)
+            } else if op & 0b1110000 == 0b0100000 {
+                decode_thumb32_data_processing_reg(hw1, hw2, offset)
+            } else if op & 0b1110000 == 0b0110000 {
+                decode_thumb32_multiply(hw1, hw2, offset)
+            } else {
+                thumb_unknown(offset, 4)
+            }
+        },
+        _ => thumb_unknown(offset, 4),
+    }
+}
+
+fn decode_thumb32_block_transfer(hw1: u32, hw2: u32, offset: usize) -> Instruction {
+    let load = hw1.bextr(4, 4) != 0;
+    let base = hw1.bextr(3, 0) as u8;
+    let writeback = if hw1.bextr(5, 5) != 0 { ADDR_WRITEBACK } else { 0 };
+    let mode = match hw1.bextr(8, 7) {
+        0b01 => ADDR_UP | writeback,
+        0b10 => ADDR_PRE | writeback,
+        _ => return thumb_unknown(offset, 4),
+    };
+    // sp is never in the list; pc can't be stored, or loaded along with lr.
+    let list = hw2 as u16;
+    if list & (1 << 13) != 0 || (!load && list & (1 << 15) != 0) || (load && list & 0xc000 == 0xc000) {
+        return thumb_unknown(offset, 4)
+    }
+    Instruction { addr_mode: mode,
+        ..thumb(block_transfer_operation(load, base, mode), Operand::Nothing, Operand::Reg(base), Operand::RegList(hw2 as u16), offset, 4) }
+}
+
+// ldrd, strd, the exclusive loads and stores and the table branches.
+fn decode_thumb32_dual(hw1: u32, hw2: u32, offset: usize) -> Instruction {
+    let rn = Operand::Reg(hw1.bextr(3, 0) as u8);
+    let rt = Operand::Reg(hw2.bextr(15, 12) as u8);
+    let exclusive = |operation, status: u32, imm| Instruction { addr_mode: ADDR_PRE | ADDR_UP, op3: rt,
+        ..thumb(operation, Operand::Reg(status as u8), rn, Operand::ImmU32(imm), offset, 4) };
+    match (hw1 & 0xfff0, hw2.bextr(11, 8), hw2.bextr(7, 4)) {
+        (0xe840, _, _) => return exclusive(Operation::Strex, hw2.bextr(11, 8), hw2.bextr(7, 0) * 4),
+        (0xe850, 0xf, _) => return Instruction { rd: rt, op3: Operand::Nothing, ..exclusive(Operation::Ldrex, 0, hw2.bextr(7, 0) * 4) },
+        (0xe8c0, 0xf, 0b0100) => return exclusive(Operation::Strexb, hw2.bextr(3, 0), 0),
+        (0xe8c0, 0xf, 0b0101) => return exclusive(Operation::Strexh, hw2.bextr(3, 0), 0),
+        (0xe8d0, 0xf, 0b0100) if hw2.bextr(3, 0) == 0xf => return Instruction { rd: rt, op3: Operand::Nothing, ..exclusive(Operation::Ldrexb, 0, 0) },
+        (0xe8d0, 0xf, 0b0101) if hw2.bextr(3, 0) == 0xf => return Instruction { rd: rt, op3: Operand::Nothing, ..exclusive(Operation::Ldrexh, 0, 0) },
+        _ => (),
+    }
+    if hw1 & 0xfff0 == 0xe8d0 && hw2 & 0xffe0 == 0xf000 {
+        let operation = if hw2.bextr(4, 4) != 0 { Operation::Tbh } else { Operation::Tbb };
+        return thumb(operation, Operand::Nothing, rn, Operand::Reg(hw2.bextr(3, 0) as u8), offset, 4)
+    }
+    let (pre, up, writeback) = (hw1.bextr(8, 8) != 0, hw1.bextr(7, 7) != 0, hw1.bextr(5, 5) != 0);
+    if !pre && !writeback {
+        return thumb_unknown(offset, 4)
+    }
+    let mode = if pre { ADDR_PRE } else { 0 } | if up { ADDR_UP } else { 0 } | if writeback { ADDR_WRITEBACK } else { 0 };
+    let operation = if hw1.bextr(4, 4) != 0 { Operation::Ldrd } else { Operation::Strd };
+    Instruction { addr_mode: mode, op3: Operand::Reg(hw2.bextr(11, 8) as u8),
+        ..thumb(operation, Operand::Reg(hw2.bextr(15, 12) as u8), rn, Operand::ImmU32(hw2.bextr(7, 0) * 4), offset, 4) }
+}
+
+// The operations shared by the modified immediate and shifted register
+// encodings. Some become tst, teq, cmn, cmp, mov and mvn with rd or rn as pc.
+fn decode_thumb32_data_processing(hw1: u32, hw2: u32, offset: usize, op2: Operand) -> Instruction {
+    let (rn, rd) = (hw1.bextr(3, 0) as u8, hw2.bextr(11, 8) as u8);
+    let set_flags = hw1.bextr(4, 4) != 0;
+    let compare = rd == Register::PC.0 && set_flags;
+    let operation = match hw1.bextr(8, 5) {
+        0b0000 if compare => Operation::Tst,
+        0b0000 => Operation::And,
+        0b0001 => Operation::Bic,
+        0b0010 if rn == Register::PC.0 => Operation::Mov,
+        0b0010 => Operation::Orr,
+        0b0011 if rn == Register::PC.0 => Operation::Mvn,
+        0b0011 => Operation::Orn,
+        0b0100 if compare => Operation::Teq,
+        0b0100 => Operation::Eor,
+        0b1000 if compare => Operation::Cmn,
+        0b1000 => Operation::Add,
+        0b1010 => Operation::Adc,
+        0b1011 => Operation::Sbc,
+        0b1101 if compare => Operation::Cmp,
+        0b1101 => Operation::Sub,
+        0b1110 => Operation::Rsb,
+        _ => return thumb_unknown(offset, 4),
+    };
+    Instruction { set_flags, ..thumb(operation, Operand::Reg(rd), Operand::Reg(rn), op2, offset, 4) }
+}
+
+fn decode_thumb32_plain_imm(hw1: u32, hw2: u32, offset: usize) -> Instruction {
+    let (rn, rd) = (hw1.bextr(3, 0) as u8, Operand::Reg(hw2.bextr(11, 8) as u8));
+    let imm12 = thumb_imm12(hw1, hw2);
+    let lsb = (hw2.bextr(14, 12) << 2) | hw2.bextr(7, 6);
+    let field = hw2.bextr(4, 0);
+    let t = |operation, rn, op2| thumb(operation, rd, rn, op2, offset, 4);
+    match hw1.bextr(8, 4) {
+        0b00000 if rn == Register::PC.0 => t(Operation::Adr, Operand::Nothing, Operand::ImmS32(thumb_aligned_pc(offset) + imm12 as i32)),
+        0b00000 => t(Operation::Add, Operand::Reg(rn), Operand::ImmU32(imm12)),
+        0b01010 if rn == Register::PC.0 => t(Operation::Adr, Operand::Nothing, Operand::ImmS32(thumb_aligned_pc(offset) - imm12 as i32)),
+        0b01010 => t(Operation::Sub, Operand::Reg(rn), Operand::ImmU32(imm12)),
+        0b00100 => t(Operation::Movw, Operand::Nothing, Operand::ImmU32(((rn as u32) << 12) | imm12)),
+        0b01100 => t(Operation::Movt, Operand::Nothing, Operand::ImmU32(((rn as u32) << 12) | imm12)),
+        // With an asr of 0 these are ssat16 and usat16.
+        0b10010 | 0b11010 if lsb == 0 => thumb_unknown(offset, 4),
+        0b10000 | 0b10010 => t(Operation::Ssat, Operand::RegShift(rn, hw1.bextr(5, 5) as u8 * 2, lsb as u8), Operand::ImmU32(field + 1)),
+        0b11000 | 0b11010 => t(Operation::Usat, Operand::RegShift(rn, hw1.bextr(5, 5) as u8 * 2, lsb as u8), Operand::ImmU32(field)),
+        0b10100 => Instruction { op3: Operand::ImmU32(field + 1), ..t(Operation::Sbfx, Operand::Reg(rn), Operand::ImmU32(lsb)) },
+        0b11100 => Instruction { op3: Operand::ImmU32(field + 1), ..t(Operation::Ubfx, Operand::Reg(rn), Operand::ImmU32(lsb)) },
+        // The field runs from lsb up to msb.
+        0b10110 if field < lsb => thumb_unknown(offset, 4),
+        0b10110 if rn == Register::PC.0 => Instruction { op3: Operand::ImmU32(field - lsb + 1), ..t(Operation::Bfc, Operand::Nothing, Operand::ImmU32(lsb)) },
+        0b10110 => Instruction { op3: Operand::ImmU32(field - lsb + 1), ..t(Operation::Bfi, Operand::Reg(rn), Operand::ImmU32(lsb)) },
+        _ => thumb_unknown(offset, 4),
+    }
+}
+
+fn decode_thumb32_branch(hw1: u32, hw2: u32, offset: usize) -> Instruction {
+    let t = |operation, op2| thumb(operation, Operand::Nothing, Operand::Nothing, op2, offset, 4);
+    let s = hw1.bextr(10, 10);
+    let (j1, j2) = (hw2.bextr(13, 13), hw2.bextr(11, 11));
+    // S:I1:I2:imm10:imm11:0 where I1 and I2 are J1 and J2 flipped unless S is set.
+    let long = {
+        let (i1, i2) = (!(j1 ^ s) & 1, !(j2 ^ s) & 1);
+        let imm = (s << 24) | (i1 << 23) | (i2 << 22) | (hw1.bextr(9, 0) << 12) | (hw2.bextr(10, 0) << 1);
+        ((imm << 7) as i32) >> 7
+    };
+    match (hw2.bextr(14, 14), hw2.bextr(12, 12)) {
+        (0, 0) if hw1.bextr(9, 7) != 0b111 => {
+            let imm = (s << 20) | (j2 << 19) | (j1 << 18) | (hw1.bextr(5, 0) << 12) | (hw2.bextr(10, 0) << 1);
+            Instruction { cond: hw1.bextr(9, 6) as u8, ..t(Operation::B, Operand::ImmS32((((imm << 11) as i32) >> 11) + 4)) }
+        },
+        (0, 0) if hw1 == 0xf3af && hw2 & 0xff00 == 0x8000 => match hw2.bextr(7, 0) {
+            0 => t(Operation::Nop, Operand::Nothing),
+            1 => t(Operation::Yield, Operand::Nothing),
+            2 => t(Operation::Wfe, Operand::Nothing),
+            3 => t(Operation::Wfi, Operand::Nothing),
+            4 => t(Operation::Sev, Operand::Nothing),
+            _ => thumb_unknown(offset, 4),
+        },
+        (0, 0) if hw1 == 0xf3ef && hw2 & 0xf000 == 0x8000 => {
+            thumb(Operation::Mrs, Operand::Reg(hw2.bextr(11, 8) as u8), Operand::Nothing, Operand::ImmU32(hw2.bextr(7, 0)), offset, 4)
+        },
+        (0, 0) if hw1 & 0xfff0 == 0xf380 && hw2 & 0xf300 == 0x8000 => {
+            thumb(Operation::Msr, Operand::Nothing, Operand::Reg(hw1.bextr(3, 0) as u8), Operand::ImmU32(hw2.bextr(11, 10) << 8 | hw2.bextr(7, 0)), offset, 4)
+        },
+        (0, 0) if hw1 == 0xf3bf && hw2 & 0xff00 == 0x8f00 => {
+            let operation = match hw2.bextr(7, 4) {
+                0x2 => Operation::Clrex,
+                0x4 => Operation::Dsb,
+                0x5 => Operation::Dmb,
+                0x6 => Operation::Isb,
+                _ => return thumb_unknown(offset, 4),
+            };
+            t(operation, Operand::ImmU32(hw2.bextr(3, 0)))
+        },
+        (0, 0) if hw1 & 0xfff0 == 0xf7f0 && hw2 & 0xf000 == 0xa000 => {
+            t(Operation::Udf, Operand::ImmU32((hw1.bextr(3, 0) << 12) | hw2.bextr(11, 0)))
+        },
+        (0, 0) => thumb_unknown(offset, 4),
+        (0, _) => t(Operation::B, Operand::ImmS32(long + 4)),
+        (_, 1) => t(Operation::Bl, Operand::ImmS32(long + 4)),
+        // blx switches to ARM, so the target is word aligned.
+        _ if hw2.bextr(0, 0) == 0 => t(Operation::Blx, Operand::ImmS32(thumb_aligned_pc(offset) + long)),
+        _ => thumb_unknown(offset, 4),
+    }
+}
+
+fn decode_thumb32_load_store(hw1: u32, hw2: u32, offset: usize, operation: Operation) -> Instruction {
+    let rn = hw1.bextr(3, 0) as u8;
+    let t = |mode, op2| Instruction { addr_mode: mode,
+        ..thumb(operation, Operand::Reg(hw2.bextr(15, 12) as u8), Operand::Reg(rn), op2, offset, 4) };
+    let load = !matches!(operation, Operation::Str | Operation::Strb | Operation::Strh);
+    if rn == Register::PC.0 {
+        if !load {
+            return thumb_unknown(offset, 4)
+        }
+        let up = if hw1.bextr(7, 7) != 0 { ADDR_UP } else { 0 };
+        t(ADDR_PRE | up, Operand::ImmU32(hw2.bextr(11, 0)))
+    } else if hw1.bextr(7, 7) != 0 {
+        t(ADDR_PRE | ADDR_UP, Operand::ImmU32(hw2.bextr(11, 0)))
+    } else if hw2.bextr(11, 8) == 0b1110 {
+        Instruction { operation: operation.unprivileged(), ..t(ADDR_PRE | ADDR_UP, Operand::ImmU32(hw2.bextr(7, 0))) }
+    } else if hw2.bextr(11, 11) != 0 {
+        let mut mode = 0;
+        if hw2.bextr(10, 10) != 0 {
+            mode |= ADDR_PRE;
+        }
+        if hw2.bextr(9, 9) != 0 {
+            mode |= ADDR_UP;
+        }
+        if hw2.bextr(8, 8) != 0 {
+            mode |= ADDR_WRITEBACK;
+        }
+        t(mode, Operand::ImmU32(hw2.bextr(7, 0)))
+    } else if hw2.bextr(11, 6) == 0 {
+        t(ADDR_PRE | ADDR_UP, Operand::RegShift(hw2.bextr(3, 0) as u8, 0, hw2.bextr(5, 4) as u8))
+    } else {
+        thumb_unknown(offset, 4)
+    }
+}
+
+fn decode_thumb32_data_processing_reg(hw1: u32, hw2: u32, offset: usize) -> Instruction {
+    if hw2.bextr(15, 12) != 0xf {
+        return thumb_unknown(offset, 4)
+    }
+    let (rd, rm) = (Operand::Reg(hw2.bextr(11, 8) as u8), hw2.bextr(3, 0) as u8);
+    let (op1, op2) = (hw1.bextr(7, 4), hw2.bextr(7, 4));
+    if op1 & 0b1000 == 0 && op2 == 0 {
+        let operation = THUMB_SHIFTS[hw1.bextr(6, 5) as usize];
+        Instruction { set_flags: hw1.bextr(4, 4) != 0, ..thumb(operation, rd, Operand::Reg(hw1.bextr(3, 0) as u8), Operand::Reg(rm), offset, 4) }
+    } else if op1 & 0b1000 == 0 && op2 & 0b1000 != 0 && hw1.bextr(3, 0) == 0xf {
+        let operation = match op1 {
+            0b0000 => Operation::Sxth,
+            0b0001 => Operation::Uxth,
+            0b0100 => Operation::Sxtb,
+            0b0101 => Operation::Uxtb,
+            _ => return thumb_unknown(offset, 4),
+        };
+        let rotation = hw2.bextr(5, 4) as u8 * 8;
+        let op2 = if rotation == 0 { Operand::RegShift(rm, 0, 0) } else { Operand::RegShift(rm, 3, rotation) };
+        thumb(operation, rd, Operand::Nothing, op2, offset, 4)
+    } else if op1 & 0b1100 == 0b1000 && op2 & 0b1100 == 0b1000 {
+        let operation = match (op1 & 0b11, op2 & 0b11) {
+            (0b01, 0b00) => Operation::Rev,
+            (0b01, 0b01) => Operation::Rev16,
+            (0b01, 0b10) => Operation::Rbit,
+            (0b01, 0b11) => Operation::Revsh,
+            (0b11, 0b00) => Operation::Clz,
+            _ => return thumb_unknown(offset, 4),
+        };
+        thumb(operation, rd, Operand::Nothing, Operand::Reg(rm), offset, 4)
+    } else {
+        thumb_unknown(offset, 4)
+    }
+}
+
+// mul, mla and mls, then the long multiplies and divides.
+fn decode_thumb32_multiply(hw1: u32, hw2: u32, offset: usize) -> Instruction {
+    let (rn, ra, rd, rm) = (hw1.bextr(3, 0) as u8, hw2.bextr(15, 12) as u8, hw2.bextr(11, 8) as u8, hw2.bextr(3, 0) as u8);
+    let long = hw1.bextr(7, 7) != 0;
+    let t = |operation, rd, extra| Instruction { op3: Operand::Reg(rm), ..thumb(operation, Operand::Reg(rd), extra, Operand::Reg(rn), offset, 4) };
+    match (long, hw1.bextr(6, 4), hw2.bextr(7, 4)) {
+        (false, 0b000, 0b0000) if ra == Register::PC.0 => t(Operation::Mul, rd, Operand::Nothing),
+        (false, 0b000, 0b0000) => t(Operation::Mla, rd, Operand::Reg(ra)),
+        (false, 0b000, 0b0001) => t(Operation::Mls, rd, Operand::Reg(ra)),
+        (true, 0b000, 0b0000) => t(Operation::Smull, ra, Operand::Reg(rd)),
+        (true, 0b010, 0b0000) => t(Operation::Umull, ra, Operand::Reg(rd)),
+        (true, 0b100, 0b0000) => t(Operation::Smlal, ra, Operand::Reg(rd)),
+        (true, 0b110, 0b0000) => t(Operation::Umlal, ra, Operand::Reg(rd)),
+        (true, 0b001, 0b1111) => thumb(Operation::Sdiv, Operand::Reg(rd), Operand::Reg(rn), Operand::Reg(rm), offset, 4),
+        (true, 0b011, 0b1111) => thumb(Operation::Udiv, Operand::Reg(rd), Operand::Reg(rn), Operand::Reg(rm), offset, 4),
+        _ => thumb_unknown(offset, 4),
+    }
+}
+
+// Decodes the ARM and Thumb code of a section. The program's switches say
+// which it is at each address, and the section's default covers the rest.
+// Thumb instructions in an IT block take their condition from it, so the
+// block's state carries from one instruction to the next.
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+    endianess: u8,
+    // Section offsets where the code switches to Thumb (true) or ARM.
+    switches: Vec<(usize, bool)>,
+    thumb: bool,
+    // firstcond:mask of the IT block, shifted along as it's used up; 0 outside one.
+    it_state: u32,
+    next: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(program: &Program, section_name: &str, section: &'a Section) -> Decoder<'a> {
+        let end = section.addr + section.bytes.len() as u64;
+        let switches = program.thumb_switches.iter()
+            .filter(|(addr, _)| *addr >= section.addr && *addr < end)
+            .map(|&(addr, thumb)| ((addr - section.addr) as usize, thumb))
+            .collect();
+        Decoder {
+            bytes: section.bytes.as_slice(),
+            endianess: if program.endianess == 0 { LITTLE_ENDIAN } else { program.endianess },
+            switches,
+            thumb: program.thumb_sections.iter().any(|name| name == section_name),
+            it_state: 0,
+            next: 0,
+        }
+    }
+
+    pub fn is_thumb(&self, offset: usize) -> bool {
+        match self.switches.partition_point(|&(at, _)| at <= offset) {
+            0 => self.thumb,
+            i => self.switches[i - 1].1,
+        }
+    }
+
+    // An IT block only covers the instructions straight after it, so a jump
+    // elsewhere leaves it.
+    pub fn decode_at(&mut self, offset: usize) -> Option<Instruction> {
+        if offset != self.next {
+            self.it_state = 0;
+        }
+        let ins = if self.is_thumb(offset) {
+            self.decode_thumb_at(offset)?
+        } else {
+            let word = read_u32_from_slice(self.bytes, offset, self.endianess)?;
+            let ins = disassemble_instruction(word, offset);
+            if let Operation::Unknown = ins.operation {
+                log::debug!("Unknown ARM instruction {:#010x} at offset {:#x}", word, offset);
+            }
+            ins
+        };
+        self.next = offset + ins.size();
+        Some(ins)
+    }
+
+    fn decode_thumb_at(&mut self, offset: usize) -> Option<Instruction> {
+        let hw1 = u32::from(read_u16_from_slice(self.bytes, offset, self.endianess)?);
+        let in_it = self.it_state & 0xf != 0;
+        let mut ins = if hw1 >> 11 >= 0b11101 {
+            let hw2 = u32::from(read_u16_from_slice(self.bytes, offset + 2, self.endianess)?);
+            let ins = decode_thumb32(hw1, hw2, offset);
+            if let Operation::Unknown = ins.operation {
+                log::debug!("Unknown Thumb instruction {:04x} {:04x} at offset {:#x}", hw1, hw2, offset);
+            }
+            ins
+        } else {
+            let ins = decode_thumb16(hw1, offset, in_it);
+            if let Operation::Unknown = ins.operation {
+                log::debug!("Unknown Thumb instruction {:04x} at offset {:#x}", hw1, offset);
+            }
+            ins
+        };
+        if in_it {
+            ins.cond = (self.it_state >> 4) as u8;
+            self.it_state = if self.it_state & 0x7 == 0 { 0 } else { (self.it_state & 0xe0) | ((self.it_state << 1) & 0x1f) };
+        } else if let (Operation::It, Operand::ImmU32(state)) = (ins.operation, ins.op2) {
+            self.it_state = state;
+        }
+        Some(ins)
+    }
+}
+
+// Decodes one instruction after another, passing each to f until it returns false.
+pub fn decode_arm(section: &Section, section_name: &str, program: &Program, mut f: impl FnMut(Instruction) -> bool) {
+    let mut decoder = Decoder::new(program, section_name, section);
+    let mut offset: usize = 0;
+    while let Some(ins) = decoder.decode_at(offset) {
+        if !f(ins) {
+            return;
+        }
+        offset += ins.size();
+    }
+}
+
+// Stops early, with the instructions so far, once cancel is cancelled.
+pub fn disassemble_arm(section: &Section, section_name: &String, program: &Program, cancel: &CancelToken) -> DisassemblySection {
+    let mut instrs = Vec::<Instruction>::new();
+    decode_arm(section, section_name, program, |ins| {
+        instrs.push(ins);
+        !cancel.is_cancelled()
+    });
+    DisassemblySection {
+        section_name: section_name.clone(),
+        addr: section.addr,
+        instructions: crate::dis::InstructionListing::Arm(instrs),
+    }
 }
@@ -0,0 +1,614 @@
+// A small assembler for patching: a subset of x86 and the RV32I/RV64I base
+// instructions of RISC-V. It takes the syntax the disassembler prints, except
+// that branch and call targets are absolute addresses, and instructions are
+// separated by ';' or new lines.
+
+use crate::error::BaretkError;
+use crate::reg::{Arch, Reg, RegClass};
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Reg(Reg),
+    Imm(i64),
+    // [base + disp], with the size in bytes from a BYTE/WORD/DWORD/QWORD PTR prefix.
+    Mem { base: Reg, disp: i64, size: Option<u8> },
+}
+
+// Assembles `text` to be placed at `addr`. `bits` picks between the 32 and
+// 64-bit forms of the architecture.
+pub fn assemble(arch: Arch, bits: u8, text: &str, addr: u64) -> Result<Vec<u8>, BaretkError> {
+    let mut out = Vec::<u8>::new();
+    for line in text.split([';', '\n']).map(str::trim).filter(|line| !line.is_empty()) {
+        let line = line.to_lowercase();
+        let (mnemonic, rest) = line.split_once(char::is_whitespace).unwrap_or((line.as_str(), ""));
+        let ins_addr = addr.wrapping_add(out.len() as u64);
+        let result = parse_operands(arch, rest).and_then(|operands| match arch {
+            Arch::X86 => assemble_x86(&mut out, bits == 64, mnemonic, &operands, ins_addr),
+            Arch::RiscV => assemble_riscv(&mut out, bits == 64, mnemonic, &operands, ins_addr),
+            Arch::Arm => Err("ARM instructions can't be assembled yet".to_string()),
+            Arch::AArch64 => Err("AArch64 instructions can't be assembled yet".to_string()),
+        });
+        if let Err(reason) = result {
+            return Err(BaretkError::InvalidArgument(format!("Can't assemble \"{}\": {}", line, reason)))
+        }
+    }
+    Ok(out)
+}
+
+fn parse_operands(arch: Arch, text: &str) -> Result<Vec<Operand>, String> {
+    text.split(',').map(str::trim).filter(|s| !s.is_empty()).map(|s| parse_operand(arch, s)).collect()
+}
+
+fn parse_operand(arch: Arch, s: &str) -> Result<Operand, String> {
+    if let Some(reg) = parse_reg(arch, s) {
+        return Ok(Operand::Reg(reg))
+    }
+    if let Some(imm) = parse_imm(s) {
+        return Ok(Operand::Imm(imm))
+    }
+    let (size, mem) = match s.split_once(" ptr ") {
+        Some((size, mem)) => match size.trim() {
+            "byte" => (Some(1), mem.trim()),
+            "word" => (Some(2), mem.trim()),
+            "dword" => (Some(4), mem.trim()),
+            "qword" => (Some(8), mem.trim()),
+            _ => return Err(format!("unknown operand size \"{}\"", size)),
+        },
+        None => (None, s),
+    };
+    // [base + disp], or disp(base) as RISC-V assemblers write it.
+    let inner = match (mem.strip_prefix('['), mem.find('(')) {
+        (Some(inner), _) => inner.strip_suffix(']').map(|inner| inner.replace(' ', "")),
+        (None, Some(i)) if mem.ends_with(')') => Some(format!("{}+{}", &mem[i + 1..mem.len() - 1], if i == 0 { "0" } else { &mem[..i] })),
+        _ => None,
+    };
+    let inner = inner.ok_or_else(|| format!("can't read operand \"{}\"", s))?;
+    let (base, disp) = match inner.char_indices().skip(1).find(|(_, c)| *c == '+' || *c == '-') {
+        Some((i, '+')) => (&inner[..i], parse_imm(&inner[i + 1..])),
+        Some((i, _)) => (&inner[..i], parse_imm(&inner[i + 1..]).map(i64::wrapping_neg)),
+        None => (inner.as_str(), Some(0)),
+    };
+    match (parse_reg(arch, base), disp) {
+        (Some(base), Some(disp)) => Ok(Operand::Mem { base, disp, size }),
+        _ => Err(format!("can't read operand \"{}\"", s)),
+    }
+}
+
+fn parse_reg(arch: Arch, name: &str) -> Option<Reg> {
+    match (arch, name) {
+        (Arch::X86, "rip" | "eip") => Some(Reg::X86_PC),
+        (Arch::X86, "pc") => None,
+        // r8l-r15l, as Intel's manuals name r8b-r15b.
+        (Arch::X86, _) if name.starts_with('r') && name.ends_with('l') => Reg::parse(arch, &format!("{}b", &name[..name.len() - 1])),
+        (Arch::RiscV, "zero") => Some(Reg::riscv(0)),
+        (Arch::RiscV, "fp") => Some(Reg::riscv(8)),
+        (Arch::RiscV, _) => match name.strip_prefix('x').and_then(|n| n.parse::<u8>().ok()) {
+            Some(n) if n < 32 => Some(Reg::riscv(n)),
+            _ => Reg::parse(arch, name),
+        },
+        _ => Reg::parse(arch, name),
+    }
+}
+
+// A number in decimal or with a 0x prefix, optionally negative. Numbers up to
+// u64::MAX are taken as their two's complement value.
+fn parse_imm(s: &str) -> Option<i64> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let value = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => s.parse::<u64>().ok()?,
+    } as i64;
+    Some(if negative { value.wrapping_neg() } else { value })
+}
+
+fn fits_signed(value: i64, bits: u32) -> bool {
+    let half = 1i64 << (bits - 1);
+    (-half..half).contains(&value)
+}
+
+// Whether an immediate fits in `bytes` bytes of an operand that many bytes
+// wide, where it can be written signed or unsigned.
+fn fits_operand(value: i64, bytes: u8) -> bool {
+    bytes >= 8 || fits_signed(value, bytes as u32 * 8) || (0..1i64 << (bytes * 8)).contains(&value)
+}
+
+fn push_imm(out: &mut Vec<u8>, value: i64, bytes: u8) {
+    out.extend_from_slice(&value.to_le_bytes()[..bytes as usize]);
+}
+
+// x86
+
+const X86_ALU: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+const X86_CONDITIONS: [&[&str]; 16] = [
+    &["o"], &["no"], &["b", "c", "nae"], &["ae", "nb", "nc"], &["e", "z"], &["ne", "nz"], &["be", "na"], &["a", "nbe"],
+    &["s"], &["ns"], &["p", "pe"], &["np", "po"], &["l", "nge"], &["ge", "nl"], &["le", "ng"], &["g", "nle"],
+];
+
+fn x86_reg_size(reg: Reg) -> Result<u8, String> {
+    if reg.class() == RegClass::ProgramCounter {
+        return Err("the instruction pointer can only be used as a memory base".to_string())
+    }
+    Ok((reg.bits() / 8) as u8)
+}
+
+// The operand size of an instruction: that of its registers, or of its
+// memory operand's size prefix.
+fn x86_operand_size(operands: &[Operand]) -> Result<u8, String> {
+    let mut size = None;
+    for operand in operands {
+        let this = match operand {
+            Operand::Reg(reg) => Some(x86_reg_size(*reg)?),
+            Operand::Mem { size, .. } => *size,
+            Operand::Imm(_) => None,
+        };
+        match (size, this) {
+            (Some(a), Some(b)) if a != b => return Err("operands have different sizes".to_string()),
+            (None, this) => size = this,
+            _ => (),
+        }
+    }
+    size.ok_or_else(|| "operand size is unknown, give it with BYTE, WORD, DWORD or QWORD PTR".to_string())
+}
+
+// Writes the operand-size and REX prefixes. `rex` holds the W, R, X and B
+// bits, and `regs` the registers used, which decide whether byte registers
+// need a REX prefix or can't have one.
+fn x86_prefixes(out: &mut Vec<u8>, mode64: bool, size: u8, mut rex: u8, regs: &[Reg]) -> Result<(), String> {
+    let bytes = regs.iter().filter(|reg| reg.bits() == 8);
+    let high = bytes.clone().any(|reg| reg.part().1 == 8);
+    let low = bytes.clone().any(|reg| reg.part().1 == 0 && (4..8).contains(&reg.number()));
+    if low {
+        rex |= 0x40;
+    }
+    if rex != 0 && !mode64 {
+        return Err("64-bit operands and registers r8-r15, spl, bpl, sil and dil need 64-bit code".to_string())
+    }
+    if rex != 0 && high {
+        return Err("ah, ch, dh and bh can't be used with 64-bit operands or registers r8-r15, spl, bpl, sil and dil".to_string())
+    }
+    if size == 2 {
+        out.push(0x66);
+    }
+    if rex != 0 {
+        out.push(0x40 | rex);
+    }
+    Ok(())
+}
+
+// Writes an instruction with a ModRM byte, whose reg field holds `reg` (a
+// register number or an opcode extension) and whose r/m field encodes `rm`.
+fn x86_modrm(out: &mut Vec<u8>, mode64: bool, size: u8, opcode: &[u8], reg: u8, rm: &Operand, regs: &[Reg]) -> Result<(), String> {
+    let mut rex = if size == 8 { 0x08 } else { 0 };
+    if reg >= 8 {
+        rex |= 0x04;
+    }
+    let mut tail = Vec::<u8>::new();
+    let mut regs = regs.to_vec();
+    match rm {
+        Operand::Reg(r) => {
+            x86_reg_size(*r)?;
+            regs.push(*r);
+            if r.number() >= 8 {
+                rex |= 0x01;
+            }
+            tail.push(0xc0 | (reg & 7) << 3 | (r.number() & 7));
+        },
+        Operand::Mem { base, disp, .. } if *base == Reg::X86_PC => {
+            if !mode64 || !fits_signed(*disp, 32) {
+                return Err("rip-relative operands need 64-bit code and a 32-bit displacement".to_string())
+            }
+            tail.push((reg & 7) << 3 | 0x05);
+            push_imm(&mut tail, *disp, 4);
+        },
+        Operand::Mem { base, disp, .. } => {
+            if base.bits() != if mode64 { 64 } else { 32 } {
+                return Err(format!("{} can't be used as a memory base here", base))
+            }
+            let n = base.number();
+            if n >= 8 {
+                rex |= 0x01;
+            }
+            // rbp and r13 have no form without a displacement.
+            let (mode, disp_size) = match disp {
+                0 if n & 7 != 5 => (0x00, 0),
+                d if fits_signed(*d, 8) => (0x40, 1),
+                d if fits_signed(*d, 32) => (0x80, 4),
+                _ => return Err("memory displacement doesn't fit in 32 bits".to_string()),
+            };
+            tail.push(mode | (reg & 7) << 3 | (n & 7));
+            // rsp and r12 need a SIB byte.
+            if n & 7 == 4 {
+                tail.push(0x24);
+            }
+            push_imm(&mut tail, *disp, disp_size);
+        },
+        Operand::Imm(_) => return Err("expected a register or memory operand".to_string()),
+    }
+    x86_prefixes(out, mode64, size, rex, &regs)?;
+    out.extend_from_slice(opcode);
+    out.extend_from_slice(&tail);
+    Ok(())
+}
+
+// Writes an instruction with the register in the low bits of the opcode, like
+// push and mov reg, imm.
+fn x86_plus_reg(out: &mut Vec<u8>, mode64: bool, size: u8, w: bool, opcode: u8, reg: Reg) -> Result<(), String> {
+    let mut rex = if w { 0x08 } else { 0 };
+    if reg.number() >= 8 {
+        rex |= 0x01;
+    }
+    x86_prefixes(out, mode64, size, rex, &[reg])?;
+    out.push(opcode + (reg.number() & 7));
+    Ok(())
+}
+
+// The displacement from the end of an instruction `size` bytes long at `addr` to `target`.
+fn x86_rel(addr: u64, size: u64, target: i64, mode64: bool) -> i64 {
+    let rel = (target as u64).wrapping_sub(addr.wrapping_add(size)) as i64;
+    if mode64 { rel } else { rel as i32 as i64 }
+}
+
+fn assemble_x86(out: &mut Vec<u8>, mode64: bool, mnemonic: &str, operands: &[Operand], addr: u64) -> Result<(), String> {
+    let condition = mnemonic.strip_prefix('j').and_then(|cc| X86_CONDITIONS.iter().position(|names| names.contains(&cc)));
+    let alu = X86_ALU.iter().position(|op| *op == mnemonic);
+    match (mnemonic, operands) {
+        ("nop", []) => out.push(0x90),
+        ("ret", []) => out.push(0xc3),
+        ("ret", [Operand::Imm(n)]) if (0..=0xffff).contains(n) => {
+            out.push(0xc2);
+            push_imm(out, *n, 2);
+        },
+        ("int3", []) => out.push(0xcc),
+        ("hlt", []) => out.push(0xf4),
+        ("leave", []) => out.push(0xc9),
+        ("syscall", []) if mode64 => out.extend_from_slice(&[0x0f, 0x05]),
+        ("ud2", []) => out.extend_from_slice(&[0x0f, 0x0b]),
+        ("int", [Operand::Imm(n)]) if (0..=0xff).contains(n) => out.extend_from_slice(&[0xcd, *n as u8]),
+        ("jmp", [Operand::Imm(target)]) => {
+            let short = x86_rel(addr, 2, *target, mode64);
+            if fits_signed(short, 8) {
+                out.extend_from_slice(&[0xeb, short as u8]);
+            }
+            else {
+                out.push(0xe9);
+                push_imm(out, x86_branch(addr, 5, *target, mode64)?, 4);
+            }
+        },
+        ("call", [Operand::Imm(target)]) => {
+            out.push(0xe8);
+            push_imm(out, x86_branch(addr, 5, *target, mode64)?, 4);
+        },
+        (_, [Operand::Imm(target)]) if condition.is_some() => {
+            let cc = condition.unwrap_or_default() as u8;
+            let short = x86_rel(addr, 2, *target, mode64);
+            if fits_signed(short, 8) {
+                out.extend_from_slice(&[0x70 + cc, short as u8]);
+            }
+            else {
+                out.extend_from_slice(&[0x0f, 0x80 + cc]);
+                push_imm(out, x86_branch(addr, 6, *target, mode64)?, 4);
+            }
+        },
+        ("jmp" | "call", [rm]) => {
+            // Near indirect jumps and calls are always the full width.
+            if let Operand::Reg(reg) = rm {
+                if x86_reg_size(*reg)? != if mode64 { 8 } else { 4 } {
+                    return Err(format!("{} isn't a full-width register", reg))
+                }
+            }
+            let ext = if mnemonic == "jmp" { 4 } else { 2 };
+            x86_modrm(out, mode64, 0, &[0xff], ext, rm, &[])?;
+        },
+        ("push", [Operand::Reg(reg)]) if x86_reg_size(*reg)? == if mode64 { 8 } else { 4 } => x86_plus_reg(out, mode64, 0, false, 0x50, *reg)?,
+        ("pop", [Operand::Reg(reg)]) if x86_reg_size(*reg)? == if mode64 { 8 } else { 4 } => x86_plus_reg(out, mode64, 0, false, 0x58, *reg)?,
+        ("push", [Operand::Imm(n)]) => {
+            if fits_signed(*n, 8) {
+                out.extend_from_slice(&[0x6a, *n as u8]);
+            }
+            else if fits_operand(*n, 4) {
+                out.push(0x68);
+                push_imm(out, *n, 4);
+            }
+            else {
+                return Err("pushed immediates are at most 32 bits".to_string())
+            }
+        },
+        ("mov", [Operand::Reg(reg), Operand::Imm(n)]) => {
+            let size = x86_reg_size(*reg)?;
+            match size {
+                8 if fits_signed(*n, 32) => {
+                    x86_modrm(out, mode64, 8, &[0xc7], 0, &Operand::Reg(*reg), &[])?;
+                    push_imm(out, *n, 4);
+                },
+                8 => {
+                    x86_plus_reg(out, mode64, 8, true, 0xb8, *reg)?;
+                    push_imm(out, *n, 8);
+                },
+                _ if !fits_operand(*n, size) => return Err(format!("{:#x} doesn't fit in {}", n, reg)),
+                1 => {
+                    x86_plus_reg(out, mode64, 1, false, 0xb0, *reg)?;
+                    push_imm(out, *n, 1);
+                },
+                _ => {
+                    x86_plus_reg(out, mode64, size, false, 0xb8, *reg)?;
+                    push_imm(out, *n, size);
+                },
+            }
+        },
+        ("mov", [rm @ Operand::Mem { .. }, Operand::Imm(n)]) => {
+            let size = x86_operand_size(operands)?;
+            x86_modrm(out, mode64, size, &[if size == 1 { 0xc6 } else { 0xc7 }], 0, rm, &[])?;
+            x86_push_operand_imm(out, *n, size)?;
+        },
+        ("mov", [rm, Operand::Reg(reg)]) => {
+            let size = x86_operand_size(operands)?;
+            x86_modrm(out, mode64, size, &[if size == 1 { 0x88 } else { 0x89 }], reg.number(), rm, &[*reg])?;
+        },
+        ("mov", [Operand::Reg(reg), rm @ Operand::Mem { .. }]) => {
+            let size = x86_operand_size(operands)?;
+            x86_modrm(out, mode64, size, &[if size == 1 { 0x8a } else { 0x8b }], reg.number(), rm, &[*reg])?;
+        },
+        ("lea", [Operand::Reg(reg), rm @ Operand::Mem { .. }]) => {
+            let size = x86_reg_size(*reg)?;
+            if size == 1 {
+                return Err("lea needs a 16, 32 or 64-bit register".to_string())
+            }
+            x86_modrm(out, mode64, size, &[0x8d], reg.number(), rm, &[*reg])?;
+        },
+        (_, [rm, Operand::Imm(n)]) if alu.is_some() => {
+            let ext = alu.unwrap_or_default() as u8;
+            let size = x86_operand_size(operands)?;
+            if size != 1 && fits_signed(*n, 8) {
+                x86_modrm(out, mode64, size, &[0x83], ext, rm, &[])?;
+                push_imm(out, *n, 1);
+            }
+            else {
+                x86_modrm(out, mode64, size, &[if size == 1 { 0x80 } else { 0x81 }], ext, rm, &[])?;
+                x86_push_operand_imm(out, *n, size)?;
+            }
+        },
+        (_, [rm, Operand::Reg(reg)]) if alu.is_some() => {
+            let opcode = alu.unwrap_or_default() as u8 * 8;
+            let size = x86_operand_size(operands)?;
+            x86_modrm(out, mode64, size, &[if size == 1 { opcode } else { opcode + 1 }], reg.number(), rm, &[*reg])?;
+        },
+        (_, [Operand::Reg(reg), rm @ Operand::Mem { .. }]) if alu.is_some() => {
+            let opcode = alu.unwrap_or_default() as u8 * 8;
+            let size = x86_operand_size(operands)?;
+            x86_modrm(out, mode64, size, &[if size == 1 { opcode + 2 } else { opcode + 3 }], reg.number(), rm, &[*reg])?;
+        },
+        ("test", [rm, Operand::Reg(reg)]) => {
+            let size = x86_operand_size(operands)?;
+            x86_modrm(out, mode64, size, &[if size == 1 { 0x84 } else { 0x85 }], reg.number(), rm, &[*reg])?;
+        },
+        ("test", [rm, Operand::Imm(n)]) => {
+            let size = x86_operand_size(operands)?;
+            x86_modrm(out, mode64, size, &[if size == 1 { 0xf6 } else { 0xf7 }], 0, rm, &[])?;
+            x86_push_operand_imm(out, *n, size)?;
+        },
+        ("inc" | "dec" | "not" | "neg", [rm]) => {
+            let size = x86_operand_size(operands)?;
+            let (opcode, ext) = match mnemonic {
+                "inc" => (0xfe, 0),
+                "dec" => (0xfe, 1),
+                "not" => (0xf6, 2),
+                _ => (0xf6, 3),
+            };
+            x86_modrm(out, mode64, size, &[if size == 1 { opcode } else { opcode + 1 }], ext, rm, &[])?;
+        },
+        ("shl" | "sal" | "shr" | "sar", [rm, count]) => {
+            let size = x86_operand_size(&operands[..1])?;
+            let ext = match mnemonic {
+                "shr" => 5,
+                "sar" => 7,
+                _ => 4,
+            };
+            let byte = size == 1;
+            match count {
+                Operand::Imm(1) => x86_modrm(out, mode64, size, &[if byte { 0xd0 } else { 0xd1 }], ext, rm, &[])?,
+                Operand::Imm(n) if (0..64).contains(n) => {
+                    x86_modrm(out, mode64, size, &[if byte { 0xc0 } else { 0xc1 }], ext, rm, &[])?;
+                    out.push(*n as u8);
+                },
+                Operand::Reg(cl) if cl.name() == "cl" => x86_modrm(out, mode64, size, &[if byte { 0xd2 } else { 0xd3 }], ext, rm, &[])?,
+                _ => return Err("shift counts are an immediate or cl".to_string()),
+            }
+        },
+        _ => return Err(format!("\"{}\" with these operands isn't supported", mnemonic)),
+    }
+    Ok(())
+}
+
+// An immediate the size of the operand, which is at most 32 bits and sign
+// extended for 64-bit operands.
+fn x86_push_operand_imm(out: &mut Vec<u8>, value: i64, size: u8) -> Result<(), String> {
+    let bytes = size.min(4);
+    if !fits_operand(value, size) || (size == 8 && !fits_signed(value, 32)) {
+        return Err(format!("{:#x} doesn't fit in a {}-bit immediate", value, bytes * 8))
+    }
+    push_imm(out, value, bytes);
+    Ok(())
+}
+
+fn x86_branch(addr: u64, size: u64, target: i64, mode64: bool) -> Result<i64, String> {
+    let rel = x86_rel(addr, size, target, mode64);
+    if !fits_signed(rel, 32) {
+        return Err(format!("target {:#x} is out of reach", target))
+    }
+    Ok(rel)
+}
+
+// RISC-V
+
+#[derive(Clone, Copy)]
+enum Format {
+    // rd, rs1, rs2
+    R,
+    // rd, rs1, 12-bit immediate
+    I,
+    // rd, [rs1 + offset]
+    Load,
+    // rs2, [rs1 + offset]
+    Store,
+    // rd, rs1, shift amount
+    Shift,
+    // rs1, rs2, target
+    B,
+    // rd, upper 20 bits
+    U,
+    // [rd,] target
+    J,
+    // rd, rs1[, offset]
+    Jalr,
+    System,
+}
+
+// The fixed bits of each instruction, as in the disassembler's opcode table,
+// and whether it only exists in RV64.
+const RISCV_OPS: &[(&str, u32, Format, bool)] = &[
+    ("lui", 0x00000037, Format::U, false),
+    ("auipc", 0x00000017, Format::U, false),
+    ("jal", 0x0000006f, Format::J, false),
+    ("jalr", 0x00000067, Format::Jalr, false),
+    ("beq", 0x00000063, Format::B, false),
+    ("bne", 0x00001063, Format::B, false),
+    ("blt", 0x00004063, Format::B, false),
+    ("bge", 0x00005063, Format::B, false),
+    ("bltu", 0x00006063, Format::B, false),
+    ("bgeu", 0x00007063, Format::B, false),
+    ("lb", 0x00000003, Format::Load, false),
+    ("lh", 0x00001003, Format::Load, false),
+    ("lw", 0x00002003, Format::Load, false),
+    ("ld", 0x00003003, Format::Load, true),
+    ("lbu", 0x00004003, Format::Load, false),
+    ("lhu", 0x00005003, Format::Load, false),
+    ("lwu", 0x00006003, Format::Load, true),
+    ("sb", 0x00000023, Format::Store, false),
+    ("sh", 0x00001023, Format::Store, false),
+    ("sw", 0x00002023, Format::Store, false),
+    ("sd", 0x00003023, Format::Store, true),
+    ("addi", 0x00000013, Format::I, false),
+    ("slli", 0x00001013, Format::Shift, false),
+    ("slti", 0x00002013, Format::I, false),
+    ("sltiu", 0x00003013, Format::I, false),
+    ("sltui", 0x00003013, Format::I, false),
+    ("xori", 0x00004013, Format::I, false),
+    ("srli", 0x00005013, Format::Shift, false),
+    ("srai", 0x40005013, Format::Shift, false),
+    ("ori", 0x00006013, Format::I, false),
+    ("andi", 0x00007013, Format::I, false),
+    ("addiw", 0x0000001b, Format::I, true),
+    ("slliw", 0x0000101b, Format::Shift, true),
+    ("srliw", 0x0000501b, Format::Shift, true),
+    ("sraiw", 0x4000501b, Format::Shift, true),
+    ("add", 0x00000033, Format::R, false),
+    ("mul", 0x02000033, Format::R, false),
+    ("sub", 0x40000033, Format::R, false),
+    ("sll", 0x00001033, Format::R, false),
+    ("slt", 0x00002033, Format::R, false),
+    ("sltu", 0x00003033, Format::R, false),
+    ("xor", 0x00004033, Format::R, false),
+    ("srl", 0x00005033, Format::R, false),
+    ("sra", 0x40005033, Format::R, false),
+    ("or", 0x00006033, Format::R, false),
+    ("and", 0x00007033, Format::R, false),
+    ("addw", 0x0000003b, Format::R, true),
+    ("mulw", 0x0200003b, Format::R, true),
+    ("subw", 0x4000003b, Format::R, true),
+    ("sllw", 0x0000103b, Format::R, true),
+    ("srlw", 0x0000503b, Format::R, true),
+    ("sraw", 0x4000503b, Format::R, true),
+    ("ecall", 0x00000073, Format::System, false),
+    ("ebreak", 0x00100073, Format::System, false),
+];
+
+fn riscv_i(bits: u32, rd: u8, rs1: u8, imm: i64) -> u32 {
+    bits | ((imm as u32) & 0xfff) << 20 | (rs1 as u32) << 15 | (rd as u32) << 7
+}
+
+fn assemble_riscv(out: &mut Vec<u8>, rv64: bool, mnemonic: &str, operands: &[Operand], addr: u64) -> Result<(), String> {
+    use Operand::{Imm, Mem, Reg as R};
+    let zero = Reg::riscv(0);
+    let ra = Reg::riscv(1);
+    // Pseudo-instructions are rewritten to the base instructions they stand for.
+    let expanded: Vec<(&str, Vec<Operand>)> = match (mnemonic, operands) {
+        ("nop", []) => vec![("addi", vec![R(zero), R(zero), Imm(0)])],
+        ("mv", [R(rd), R(rs)]) => vec![("addi", vec![R(*rd), R(*rs), Imm(0)])],
+        ("not", [R(rd), R(rs)]) => vec![("xori", vec![R(*rd), R(*rs), Imm(-1)])],
+        ("neg", [R(rd), R(rs)]) => vec![("sub", vec![R(*rd), R(zero), R(*rs)])],
+        ("sext.w", [R(rd), R(rs)]) => vec![("addiw", vec![R(*rd), R(*rs), Imm(0)])],
+        ("li", [R(rd), Imm(n)]) if fits_signed(*n, 12) => vec![("addi", vec![R(*rd), R(zero), Imm(*n)])],
+        ("li", [R(rd), Imm(n)]) => {
+            if !fits_signed(*n, 32) && !(!rv64 && fits_operand(*n, 4)) {
+                return Err("li only loads values that fit in 32 bits".to_string())
+            }
+            let n = *n as i32 as i64;
+            let upper = (n + 0x800) >> 12;
+            let lower = n - (upper << 12);
+            let mut v = vec![("lui", vec![R(*rd), Imm(upper & 0xfffff)])];
+            if lower != 0 {
+                v.push((if rv64 { "addiw" } else { "addi" }, vec![R(*rd), R(*rd), Imm(lower)]));
+            }
+            v
+        },
+        ("j", [Imm(target)]) => vec![("jal", vec![R(zero), Imm(*target)])],
+        ("jal", [Imm(target)]) => vec![("jal", vec![R(ra), Imm(*target)])],
+        ("jr", [R(rs)]) => vec![("jalr", vec![R(zero), R(*rs)])],
+        ("jalr", [R(rs)]) => vec![("jalr", vec![R(ra), R(*rs)])],
+        ("ret", []) => vec![("jalr", vec![R(zero), R(ra)])],
+        ("beqz", [R(rs), Imm(target)]) => vec![("beq", vec![R(*rs), R(zero), Imm(*target)])],
+        ("bnez", [R(rs), Imm(target)]) => vec![("bne", vec![R(*rs), R(zero), Imm(*target)])],
+        _ => vec![(mnemonic, operands.to_vec())],
+    };
+    for (mnemonic, operands) in &expanded {
+        let &(_, bits, format, only64) = RISCV_OPS.iter().find(|(name, ..)| name == mnemonic)
+            .ok_or_else(|| format!("unknown instruction \"{}\"", mnemonic))?;
+        if only64 && !rv64 {
+            return Err(format!("{} is only in RV64", mnemonic))
+        }
+        let pc = addr.wrapping_add(out.len() as u64);
+        let offset = |target: &i64, bits: u32| {
+            let offset = (*target as u64).wrapping_sub(pc) as i64;
+            let offset = if rv64 { offset } else { offset as i32 as i64 };
+            if offset % 2 != 0 || !fits_signed(offset, bits) {
+                return Err(format!("target {:#x} is out of reach", target))
+            }
+            Ok(offset as u32)
+        };
+        let ins = match (format, operands.as_slice()) {
+            (Format::R, [R(rd), R(rs1), R(rs2)]) => bits | (rs2.number() as u32) << 20 | (rs1.number() as u32) << 15 | (rd.number() as u32) << 7,
+            (Format::I, [R(rd), R(rs1), Imm(n)]) if fits_signed(*n, 12) => riscv_i(bits, rd.number(), rs1.number(), *n),
+            (Format::Load, [R(rd), Mem { base, disp, .. }]) if fits_signed(*disp, 12) => riscv_i(bits, rd.number(), base.number(), *disp),
+            (Format::Store, [R(rs2), Mem { base, disp, .. }]) if fits_signed(*disp, 12) => {
+                let imm = *disp as u32;
+                bits | (imm >> 5 & 0x7f) << 25 | (rs2.number() as u32) << 20 | (base.number() as u32) << 15 | (imm & 0x1f) << 7
+            },
+            (Format::Shift, [R(rd), R(rs1), Imm(n)]) => {
+                let max = if rv64 && !mnemonic.ends_with('w') { 64 } else { 32 };
+                if !(0..max).contains(n) {
+                    return Err(format!("shift amounts are less than {}", max))
+                }
+                bits | (*n as u32) << 20 | (rs1.number() as u32) << 15 | (rd.number() as u32) << 7
+            },
+            (Format::B, [R(rs1), R(rs2), Imm(target)]) => {
+                let imm = offset(target, 13)?;
+                bits | (imm >> 12 & 1) << 31 | (imm >> 5 & 0x3f) << 25 | (rs2.number() as u32) << 20 | (rs1.number() as u32) << 15
+                    | (imm >> 1 & 0xf) << 8 | (imm >> 11 & 1) << 7
+            },
+            (Format::U, [R(rd), Imm(n)]) if (0..1 << 20).contains(n) => bits | (*n as u32) << 12 | (rd.number() as u32) << 7,
+            (Format::J, [R(rd), Imm(target)]) => {
+                let imm = offset(target, 21)?;
+                bits | (imm >> 20 & 1) << 31 | (imm >> 1 & 0x3ff) << 21 | (imm >> 11 & 1) << 20 | (imm >> 12 & 0xff) << 12 | (rd.number() as u32) << 7
+            },
+            (Format::Jalr, [R(rd), R(rs1)]) => riscv_i(bits, rd.number(), rs1.number(), 0),
+            (Format::Jalr, [R(rd), R(rs1), Imm(n)] | [R(rd), Mem { base: rs1, disp: n, .. }]) if fits_signed(*n, 12) => riscv_i(bits, rd.number(), rs1.number(), *n),
+            (Format::System, []) => bits,
+            _ => return Err(format!("wrong or out of range operands for {}", mnemonic)),
+        };
+        out.extend_from_slice(&ins.to_le_bytes());
+    }
+    Ok(())
+}
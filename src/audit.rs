@@ -0,0 +1,106 @@
+// Finds calls to library functions that are easy to misuse: unbounded string
+// copies, format functions writing to fixed buffers and command execution.
+
+use std::collections::BTreeSet;
+
+use crate::dis::Disassembly;
+use crate::imports;
+use crate::xref::XrefDb;
+
+const DANGEROUS: &[(&str, &str)] = &[
+    ("gets", "reads a line with no bound on its length"),
+    ("_getws", "reads a line with no bound on its length"),
+    ("strcpy", "unbounded string copy"),
+    ("stpcpy", "unbounded string copy"),
+    ("wcscpy", "unbounded string copy"),
+    ("lstrcpyA", "unbounded string copy"),
+    ("lstrcpyW", "unbounded string copy"),
+    ("strcat", "unbounded string concatenation"),
+    ("wcscat", "unbounded string concatenation"),
+    ("lstrcatA", "unbounded string concatenation"),
+    ("lstrcatW", "unbounded string concatenation"),
+    ("sprintf", "unbounded formatted write"),
+    ("vsprintf", "unbounded formatted write"),
+    ("swprintf", "unbounded formatted write"),
+    ("wsprintfA", "unbounded formatted write"),
+    ("wsprintfW", "unbounded formatted write"),
+    ("scanf", "%s conversions have no bound"),
+    ("sscanf", "%s conversions have no bound"),
+    ("fscanf", "%s conversions have no bound"),
+    ("vscanf", "%s conversions have no bound"),
+    ("vsscanf", "%s conversions have no bound"),
+    ("vfscanf", "%s conversions have no bound"),
+    ("strncpy", "doesn't always terminate the copy"),
+    ("strncat", "bound is often miscalculated"),
+    ("memcpy", "unchecked copy length"),
+    ("alloca", "unchecked stack allocation"),
+    ("mktemp", "predictable temporary file name"),
+    ("tmpnam", "predictable temporary file name"),
+    ("tempnam", "predictable temporary file name"),
+    ("system", "runs a shell command"),
+    ("popen", "runs a shell command"),
+    ("execl", "executes a program"),
+    ("execlp", "executes a program found through PATH"),
+    ("execle", "executes a program"),
+    ("execv", "executes a program"),
+    ("execvp", "executes a program found through PATH"),
+    ("execvpe", "executes a program found through PATH"),
+    ("execve", "executes a program"),
+    ("WinExec", "executes a program"),
+    ("ShellExecuteA", "executes a program"),
+    ("ShellExecuteW", "executes a program"),
+    ("CreateProcessA", "executes a program"),
+    ("CreateProcessW", "executes a program"),
+];
+
+pub struct Finding {
+    pub name: String,
+    pub reason: &'static str,
+    // Whether the function is imported rather than linked in.
+    pub imported: bool,
+    // Call sites and the function containing each one.
+    pub sites: Vec<(u64, Option<String>)>,
+}
+
+// The dangerous function a symbol refers to. glibc's C99 scanf variants carry a prefix.
+fn lookup(name: &str) -> Option<(&'static str, &'static str)> {
+    let name = name.strip_prefix("__isoc99_").or(name.strip_prefix("__isoc23_")).unwrap_or(name);
+    DANGEROUS.iter().find(|(n, _)| *n == name).copied()
+}
+
+pub fn audit(dis: &Disassembly) -> Vec<Finding> {
+    let program = dis.program();
+    let db = XrefDb::build(dis);
+
+    // Every address a call to each dangerous function can go through.
+    let mut targets = Vec::<(&'static str, &'static str, bool, Vec<u64>)>::new();
+    let mut add = |name: &'static str, reason: &'static str, imported: bool, addrs: Vec<u64>| {
+        match targets.iter_mut().find(|(n, _, _, _)| *n == name) {
+            Some(entry) => {
+                entry.2 |= imported;
+                entry.3.extend(addrs);
+            },
+            None => targets.push((name, reason, imported, addrs)),
+        }
+    };
+    for import in imports::imports(program) {
+        if let Some((name, reason)) = lookup(&import.name) {
+            add(name, reason, true, import.stub.into_iter().chain([import.slot]).collect());
+        }
+    }
+    for sym in program.symbol_table.iter().filter(|sym| sym.is_func && sym.addr != 0) {
+        if let Some((name, reason)) = lookup(&sym.name) {
+            add(name, reason, false, vec![sym.addr]);
+        }
+    }
+
+    targets.into_iter().map(|(name, reason, imported, addrs)| {
+        let froms: BTreeSet<u64> = addrs.iter().flat_map(|addr| db.refs_to(*addr)).map(|r| r.from).collect();
+        Finding {
+            name: name.to_string(),
+            reason,
+            imported,
+            sites: froms.into_iter().map(|from| (from, program.function_at(from).map(|sym| sym.name.clone()))).collect(),
+        }
+    }).collect()
+}
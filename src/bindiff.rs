@@ -0,0 +1,115 @@
+// Byte-level comparison of two files, for changes the function differ can't
+// explain: patched constants, data tables, resources and headers. Differing
+// bytes are grouped into ranges, and each range is placed in the section and
+// symbol of the old file that holds it.
+
+use crate::prog::Program;
+
+// Differing bytes closer together than this are reported as one range.
+const MERGE_GAP: usize = 8;
+
+pub struct ByteRange {
+    // File offsets, end exclusive. Past the end of the shorter file only one side has bytes.
+    pub start: usize,
+    pub end: usize,
+}
+
+// Ranges of file offsets where the two files differ, including a size change at the end.
+pub fn diff_ranges(old: &[u8], new: &[u8]) -> Vec<ByteRange> {
+    let mut ranges = Vec::<ByteRange>::new();
+    let common = old.len().min(new.len());
+    let mut i = 0;
+    while i < common {
+        if old[i] == new[i] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let mut end = i + 1;
+        while end < common {
+            match (end..(end + MERGE_GAP).min(common)).find(|j| old[*j] != new[*j]) {
+                Some(j) => end = j + 1,
+                None => break,
+            }
+        }
+        ranges.push(ByteRange { start, end });
+        i = end;
+    }
+    if old.len() != new.len() {
+        let end = old.len().max(new.len());
+        match ranges.last_mut() {
+            Some(last) if last.end + MERGE_GAP >= common => last.end = end,
+            _ => ranges.push(ByteRange { start: common, end }),
+        }
+    }
+    ranges
+}
+
+fn hex_line(bytes: &[u8], start: usize, other: &[u8]) -> String {
+    let mut out = String::new();
+    for i in start..start + 16 {
+        match bytes.get(i) {
+            // Bytes that differ from the other file are marked.
+            Some(b) if other.get(i) != Some(b) => out += format!("{:02x}*", b).as_str(),
+            Some(b) => out += format!("{:02x} ", b).as_str(),
+            None => out += "   ",
+        }
+    }
+    out += " |";
+    for i in start..(start + 16).min(bytes.len()) {
+        let c = bytes[i];
+        out.push(if (0x20..0x7f).contains(&c) { c as char } else { '.' });
+    }
+    out += "|";
+    out
+}
+
+// Prints each range with its location and a hexdump of both files, with
+// `context` bytes before and after it.
+pub fn print(old: &[u8], new: &[u8], old_program: &Program, ranges: &[ByteRange], context: usize) -> String {
+    let mut out = String::new();
+    let symbols = old_program.symbol_index();
+    for range in ranges {
+        out += format!("{:#010x}..{:#010x} ({} byte(s))", range.start, range.end, range.end - range.start).as_str();
+        if let Some((section, addr)) = old_program.section_at_offset(range.start as u64) {
+            out += format!(" {}", section).as_str();
+            if let Some(addr) = addr {
+                out += format!(" {:#x}", addr).as_str();
+                if let Some(sym) = symbols.containing(addr) {
+                    out += format!(" {}+{:#x}", sym.name, addr - sym.addr).as_str();
+                }
+            }
+        }
+        if range.start >= old.len() {
+            out += " appended";
+        }
+        else if range.end > new.len() {
+            out += " truncated";
+        }
+        out += "\n";
+        let first = range.start.saturating_sub(context) / 16 * 16;
+        let last = (range.end + context).min(old.len().max(new.len()));
+        for line in (first..last).step_by(16) {
+            let old_line = hex_line(old, line, new);
+            let new_line = hex_line(new, line, old);
+            if old_line == new_line {
+                out += format!("  {:08x}  {}\n", line, old_line).as_str();
+            }
+            else {
+                if line < old.len() {
+                    out += format!("- {:08x}  {}\n", line, old_line).as_str();
+                }
+                if line < new.len() {
+                    out += format!("+ {:08x}  {}\n", line, new_line).as_str();
+                }
+            }
+        }
+        out += "\n";
+    }
+    let changed: usize = ranges.iter().map(|range| range.end - range.start).sum();
+    out += format!("{} range(s), {} byte(s) differ", ranges.len(), changed).as_str();
+    if old.len() != new.len() {
+        out += format!(", size {} -> {}", old.len(), new.len()).as_str();
+    }
+    out
+}
@@ -0,0 +1,131 @@
+// Every call instruction in the code section and where it goes: a direct call
+// to code in the binary, a call through a PLT stub or an import slot (GOT/IAT)
+// to a library function, or an indirect call through a register, resolved
+// when its value is known. System calls are found the same way, with their
+// numbers where the register holding them is known.
+
+use std::collections::HashMap;
+
+use crate::dis::{self, Disassembly};
+use crate::imports;
+use crate::reg::RegClass;
+use crate::resolve;
+use crate::syscall;
+use crate::xref;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CallKind {
+    Direct,
+    Plt,
+    Import,
+    Indirect,
+}
+
+impl CallKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            CallKind::Direct => "direct",
+            CallKind::Plt => "plt",
+            CallKind::Import => "import",
+            CallKind::Indirect => "indirect",
+        }
+    }
+}
+
+pub struct Call {
+    pub from: u64,
+    pub kind: CallKind,
+    // Unknown for indirect calls that couldn't be resolved.
+    pub target: Option<u64>,
+    // The function called, as a symbol or import name, with an offset if it's inside one.
+    pub name: Option<String>,
+    // The function the call is made from.
+    pub function: Option<String>,
+}
+
+fn is_call(ins: &dis::Instruction) -> bool {
+    match ins.opcode {
+        "call" => true,
+        "jal" | "jalr" => !matches!(ins.operands.first(), Some(dis::Operand::Register(r)) if r.class() == RegClass::Zero),
+        _ => false,
+    }
+}
+
+pub fn find_calls(dis: &Disassembly) -> Vec<Call> {
+    let program = dis.program();
+    let listing = &dis.section().instructions;
+    let instrs = listing.instruction_vec();
+    let offsets = listing.instruction_offset_vec_in(0..usize::MAX);
+    let sizes = listing.instruction_size_vec_in(0..usize::MAX);
+    let base = dis.section_addr();
+    let resolved = resolve::resolve_indirect_in(dis, 0..usize::MAX);
+
+    let imports = imports::imports(program);
+    let stubs: HashMap<u64, &str> = imports.iter().filter_map(|i| i.stub.map(|stub| (stub, i.name.as_str()))).collect();
+    let slots: HashMap<u64, &str> = imports.iter().map(|i| (i.slot, i.name.as_str())).collect();
+    let symbols = program.symbol_index();
+    let symbolize = |addr: u64| -> Option<String> {
+        let sym = symbols.function_containing(addr).or_else(|| symbols.containing(addr))?;
+        Some(if sym.addr == addr { sym.name.clone() } else { format!("{}+{:#x}", sym.name, addr - sym.addr) })
+    };
+
+    let mut calls = Vec::<Call>::new();
+    for ((ins, offset), size) in instrs.iter().zip(offsets).zip(sizes) {
+        if !is_call(ins) {
+            continue;
+        }
+        let from = base + offset as u64;
+        let function = symbols.function_containing(from).map(|sym| sym.name.clone());
+        let operand = if ins.opcode == "call" { ins.operands.first() } else { ins.operands.get(1) };
+        let call = match operand {
+            Some(dis::Operand::Immediate(rel)) if ins.opcode != "jalr" => {
+                let target = from.wrapping_add(*rel as u64);
+                match stubs.get(&target) {
+                    Some(name) => Call { from, kind: CallKind::Plt, target: Some(target), name: Some(name.to_string()), function },
+                    None => Call { from, kind: CallKind::Direct, target: Some(target), name: symbolize(target), function },
+                }
+            },
+            _ => {
+                let slot = ins.operands.iter().find_map(|op| xref::memory_target(op, from, size));
+                match slot.and_then(|slot| slots.get(&slot).map(|name| (slot, name))) {
+                    Some((slot, name)) => Call { from, kind: CallKind::Import, target: Some(slot), name: Some(name.to_string()), function },
+                    None => {
+                        let target = resolved.get(&from).map(|r| r.target);
+                        let name = target.and_then(|t| stubs.get(&t).map(|name| name.to_string()).or_else(|| symbolize(t)));
+                        Call { from, kind: CallKind::Indirect, target, name, function }
+                    },
+                }
+            },
+        };
+        calls.push(call);
+    }
+    calls
+}
+
+pub struct SyscallSite {
+    pub addr: u64,
+    // The syscall number, when the register holding it is set to a constant in the same block.
+    pub number: Option<i64>,
+    pub name: Option<&'static str>,
+}
+
+// Every syscall instruction in the code section, with its number where it can be recovered.
+pub fn find_syscalls(dis: &Disassembly) -> Vec<SyscallSite> {
+    let machine_type = dis.program().machine_type.as_str();
+    let mut sites = Vec::<SyscallSite>::new();
+    resolve::walk_in(dis, 0..usize::MAX, |state, ins, addr, _| {
+        let svc = match (ins.opcode, ins.operands.first()) {
+            ("syscall", _) => None,
+            ("svc", Some(dis::Operand::Immediate(imm))) => Some(*imm),
+            _ => return,
+        };
+        let abi = syscall::syscall_abi(machine_type, svc);
+        let number = match svc.and_then(syscall::arm_oabi_number) {
+            Some(number) if machine_type == "arm" => Some(number),
+            _ => abi.and_then(|abi| state.get(abi.number)).map(|n| n as i64),
+        };
+        let name = abi.zip(number).and_then(|(abi, n)| abi.lookup(n)).map(|sys| sys.name);
+        sites.push(SyscallSite { addr, number, name });
+    });
+    sites
+}
@@ -0,0 +1,275 @@
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+
+use crate::dis::{self, Disassembly, Flow};
+use crate::resolve;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum EdgeKind {
+    True,
+    False,
+    Jump,
+    Fallthrough,
+}
+
+impl EdgeKind {
+    fn name(self) -> &'static str {
+        match self {
+            EdgeKind::True => "true",
+            EdgeKind::False => "false",
+            EdgeKind::Jump => "jump",
+            EdgeKind::Fallthrough => "fallthrough",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            EdgeKind::True => "darkgreen",
+            EdgeKind::False => "red",
+            EdgeKind::Jump => "blue",
+            EdgeKind::Fallthrough => "black",
+        }
+    }
+}
+
+pub struct BasicBlock {
+    pub start: u64,
+    // Address one past the last instruction.
+    pub end: u64,
+    pub lines: Vec<String>,
+    pub succs: Vec<(u64, EdgeKind)>,
+}
+
+pub struct Cfg {
+    pub name: String,
+    pub blocks: Vec<BasicBlock>,
+}
+
+// Splits the instructions in range into basic blocks. Blocks start at the function
+// entry, at every branch target inside the function and after every branch.
+pub fn build_cfg(dis: &Disassembly, name: &str, range: Range<usize>) -> Cfg {
+    let listing = &dis.section().instructions;
+    let instrs = listing.instruction_vec_in(range.clone());
+    let texts = listing.instruction_text_vec_in(range.clone());
+    let offsets = listing.instruction_offset_vec_in(range.clone());
+    let sizes = listing.instruction_size_vec_in(range.clone());
+    let base = dis.section_addr();
+    let addrs: Vec<u64> = offsets.iter().map(|offset| base + *offset as u64).collect();
+    let resolved = resolve::resolve_indirect_in(dis, range.clone());
+    let flows: Vec<Flow> = instrs.iter().zip(&addrs).map(|(ins, addr)| match resolved.get(addr) {
+        Some(r) if !r.is_call && ins.cond() == dis::COND_AL => Flow::Jump(Some(r.target)),
+        Some(r) if !r.is_call => Flow::Branch(Some(r.target)),
+        _ => dis::flow(ins, *addr),
+    }).collect();
+
+    let inside: BTreeSet<u64> = addrs.iter().copied().collect();
+    let mut leaders = BTreeSet::<u64>::new();
+    if let Some(first) = addrs.first() {
+        leaders.insert(*first);
+    }
+    for (i, f) in flows.iter().enumerate() {
+        let target = match f {
+            Flow::Jump(t) | Flow::Branch(t) => *t,
+            Flow::Stop => None,
+            Flow::Next => continue,
+        };
+        if let Some(t) = target.filter(|t| inside.contains(t)) {
+            leaders.insert(t);
+        }
+        if let Some(next) = addrs.get(i + 1) {
+            leaders.insert(*next);
+        }
+    }
+
+    let mut blocks = Vec::<BasicBlock>::new();
+    for (i, addr) in addrs.iter().enumerate() {
+        if leaders.contains(addr) || blocks.is_empty() {
+            blocks.push(BasicBlock { start: *addr, end: *addr, lines: Vec::new(), succs: Vec::new() });
+        }
+        let block = blocks.last_mut().expect("block");
+        block.end = addr + sizes[i] as u64;
+        block.lines.push(texts[i].clone());
+        let next = addrs.get(i + 1).copied();
+        let ends_block = next.map_or(true, |n| leaders.contains(&n));
+        if !ends_block {
+            continue;
+        }
+        let inside_target = |t: &Option<u64>| t.filter(|t| inside.contains(t));
+        match &flows[i] {
+            Flow::Next => if let Some(n) = next {
+                block.succs.push((n, EdgeKind::Fallthrough));
+            },
+            Flow::Jump(t) => if let Some(t) = inside_target(t) {
+                block.succs.push((t, EdgeKind::Jump));
+            },
+            Flow::Branch(t) => {
+                if let Some(t) = inside_target(t) {
+                    block.succs.push((t, EdgeKind::True));
+                }
+                if let Some(n) = next {
+                    block.succs.push((n, EdgeKind::False));
+                }
+            },
+            Flow::Stop => (),
+        }
+    }
+    Cfg { name: name.to_string(), blocks }
+}
+
+// SVG layout, in pixels.
+const CHAR_WIDTH: usize = 7;
+const LINE_HEIGHT: usize = 14;
+const PADDING: usize = 6;
+const LAYER_GAP: usize = 40;
+const BLOCK_GAP: usize = 30;
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl Cfg {
+    pub fn to_dot(&self) -> String {
+        let mut out = format!("digraph \"{}\" {{\n", dot_escape(&self.name));
+        out += "    node [shape=box, fontname=\"monospace\"];\n";
+        for block in &self.blocks {
+            let mut label = format!("{:#010x}:\\l", block.start);
+            for line in &block.lines {
+                label += format!("    {}\\l", dot_escape(line)).as_str();
+            }
+            out += format!("    \"{:#x}\" [label=\"{}\"];\n", block.start, label).as_str();
+        }
+        for block in &self.blocks {
+            for (succ, kind) in &block.succs {
+                out += format!("    \"{:#x}\" -> \"{:#x}\" [label=\"{}\", color={}];\n", block.start, succ, kind.name(), kind.color()).as_str();
+            }
+        }
+        out += "}\n";
+        out
+    }
+
+    // Layers of blocks, each block one layer below its deepest predecessor,
+    // ignoring loop back edges. Back edges are also returned.
+    fn layers(&self) -> (Vec<usize>, BTreeSet<(usize, usize)>) {
+        let index: HashMap<u64, usize> = self.blocks.iter().enumerate().map(|(i, b)| (b.start, i)).collect();
+        let succs: Vec<Vec<usize>> = self.blocks.iter()
+            .map(|b| b.succs.iter().filter_map(|(s, _)| index.get(s).copied()).collect())
+            .collect();
+        // Depth-first from every unvisited block in address order; edges to a block on the stack are back edges.
+        let mut back = BTreeSet::<(usize, usize)>::new();
+        let mut state = vec![0u8; self.blocks.len()];
+        for root in 0..self.blocks.len() {
+            if state[root] != 0 {
+                continue;
+            }
+            let mut stack = vec![(root, 0usize)];
+            state[root] = 1;
+            while let Some((block, next)) = stack.pop() {
+                match succs[block].get(next) {
+                    Some(succ) => {
+                        stack.push((block, next + 1));
+                        match state[*succ] {
+                            0 => {
+                                state[*succ] = 1;
+                                stack.push((*succ, 0));
+                            },
+                            1 => { back.insert((block, *succ)); },
+                            _ => (),
+                        }
+                    },
+                    None => state[block] = 2,
+                }
+            }
+        }
+        let mut preds = vec![0usize; self.blocks.len()];
+        for (i, list) in succs.iter().enumerate() {
+            for s in list.iter().filter(|s| !back.contains(&(i, **s))) {
+                preds[*s] += 1;
+            }
+        }
+        let mut layer = vec![0usize; self.blocks.len()];
+        let mut ready: Vec<usize> = (0..self.blocks.len()).filter(|i| preds[*i] == 0).collect();
+        while let Some(i) = ready.pop() {
+            for s in succs[i].iter().filter(|s| !back.contains(&(i, **s))) {
+                layer[*s] = layer[*s].max(layer[i] + 1);
+                preds[*s] -= 1;
+                if preds[*s] == 0 {
+                    ready.push(*s);
+                }
+            }
+        }
+        (layer, back)
+    }
+
+    // Lays the blocks out top to bottom by layer, with the disassembly in each box.
+    // Forward edges are straight lines; loop back edges curve around the right side.
+    pub fn to_svg(&self) -> String {
+        let (layer, back) = self.layers();
+        let size: Vec<(usize, usize)> = self.blocks.iter().map(|b| {
+            let longest = b.lines.iter().map(|l| l.chars().count() + 4).max().unwrap_or(0).max(10);
+            (longest * CHAR_WIDTH + 2 * PADDING, (b.lines.len() + 1) * LINE_HEIGHT + 2 * PADDING)
+        }).collect();
+        let layer_count = layer.iter().max().map_or(0, |l| l + 1);
+        let mut rows = vec![Vec::<usize>::new(); layer_count];
+        for (i, l) in layer.iter().enumerate() {
+            rows[*l].push(i);
+        }
+        let row_width = |row: &Vec<usize>| row.iter().map(|i| size[*i].0).sum::<usize>() + BLOCK_GAP * row.len().saturating_sub(1);
+        let width = rows.iter().map(row_width).max().unwrap_or(0) + 2 * BLOCK_GAP + 60;
+        // Top-left corner of each block.
+        let mut pos = vec![(0usize, 0usize); self.blocks.len()];
+        let mut y = BLOCK_GAP;
+        for row in &rows {
+            let mut x = (width - 60 - row_width(row)) / 2;
+            for i in row {
+                pos[*i] = (x, y);
+                x += size[*i].0 + BLOCK_GAP;
+            }
+            y += row.iter().map(|i| size[*i].1).max().unwrap_or(0) + LAYER_GAP;
+        }
+        let height = y;
+
+        let mut out = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"monospace\" font-size=\"12\">\n", width, height);
+        out += format!("<title>{}</title>\n<defs>\n", xml_escape(&self.name)).as_str();
+        for kind in [EdgeKind::True, EdgeKind::False, EdgeKind::Jump, EdgeKind::Fallthrough] {
+            out += format!("<marker id=\"{}\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"8\" markerHeight=\"8\" orient=\"auto\"><path d=\"M0,0 L10,5 L0,10 z\" fill=\"{}\"/></marker>\n", kind.name(), kind.color()).as_str();
+        }
+        out += "</defs>\n";
+        let index: HashMap<u64, usize> = self.blocks.iter().enumerate().map(|(i, b)| (b.start, i)).collect();
+        for (i, block) in self.blocks.iter().enumerate() {
+            for (succ, kind) in &block.succs {
+                let j = match index.get(succ) {
+                    Some(j) => *j,
+                    None => continue,
+                };
+                let style = format!("fill=\"none\" stroke=\"{}\" marker-end=\"url(#{})\"", kind.color(), kind.name());
+                if back.contains(&(i, j)) || layer[j] <= layer[i] {
+                    // Leaving lower on the side than it arrives, so a block looping to itself shows a loop.
+                    let (x1, y1) = (pos[i].0 + size[i].0, pos[i].1 + size[i].1 * 2 / 3);
+                    let (x2, y2) = (pos[j].0 + size[j].0, pos[j].1 + size[j].1 / 3);
+                    let bulge = x1.max(x2) + 40;
+                    out += format!("<path d=\"M{},{} C{},{} {},{} {},{}\" {}/>\n", x1, y1, bulge, y1, bulge, y2, x2, y2, style).as_str();
+                }
+                else {
+                    let (x1, y1) = (pos[i].0 + size[i].0 / 2, pos[i].1 + size[i].1);
+                    let (x2, y2) = (pos[j].0 + size[j].0 / 2, pos[j].1);
+                    out += format!("<path d=\"M{},{} L{},{}\" {}/>\n", x1, y1, x2, y2, style).as_str();
+                }
+            }
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            let (x, y) = pos[i];
+            out += format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"white\" stroke=\"black\"/>\n", x, y, size[i].0, size[i].1).as_str();
+            let mut lines = vec![format!("{:#010x}:", block.start)];
+            lines.extend(block.lines.iter().map(|line| format!("    {}", line)));
+            for (n, line) in lines.iter().enumerate() {
+                out += format!("<text x=\"{}\" y=\"{}\" xml:space=\"preserve\">{}</text>\n", x + PADDING, y + PADDING + (n + 1) * LINE_HEIGHT - 3, xml_escape(line)).as_str();
+            }
+        }
+        out += "</svg>\n";
+        out
+    }
+}
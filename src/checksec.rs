@@ -0,0 +1,323 @@
+// The exploit mitigations a binary was built with, in the manner of checksec
+// and winchecksec: NX/DEP, PIE/ASLR, RELRO, stack canaries, fortified libc
+// calls and control flow protection, read from the headers, the dynamic
+// section and the functions the binary imports or links in.
+
+use std::collections::BTreeSet;
+
+use crate::elf::{self, Header, ProgramHeaderEntry};
+use crate::error::BaretkError;
+use crate::headers;
+use crate::imports;
+use crate::options::ParseMode;
+use crate::pe;
+use crate::prog::{Diagnostics, Program};
+use crate::query;
+use crate::util::{read_u16_checked, read_u32_checked, read_u64_checked, LITTLE_ENDIAN};
+
+pub struct Mitigation {
+    pub name: &'static str,
+    pub enabled: bool,
+    pub detail: String,
+}
+
+fn mitigation(name: &'static str, enabled: bool, detail: impl Into<String>) -> Mitigation {
+    Mitigation { name, enabled, detail: detail.into() }
+}
+
+// Library functions _FORTIFY_SOURCE replaces with a checked __*_chk version.
+const FORTIFIABLE: &[&str] = &[
+    "memcpy", "mempcpy", "memmove", "memset", "strcpy", "stpcpy", "strncpy", "stpncpy", "strcat", "strncat",
+    "sprintf", "snprintf", "vsprintf", "vsnprintf", "printf", "fprintf", "dprintf", "vprintf", "vfprintf",
+    "vdprintf", "asprintf", "vasprintf", "syslog", "vsyslog", "gets", "fgets", "fgets_unlocked", "fread",
+    "fread_unlocked", "read", "pread", "pread64", "recv", "recvfrom", "readlink", "readlinkat", "realpath",
+    "getcwd", "getwd", "confstr", "getgroups", "ttyname_r", "getlogin_r", "gethostname", "getdomainname",
+    "wcscpy", "wcsncpy", "wcscat", "wcsncat", "wmemcpy", "wmemmove", "wmemset", "swprintf", "wprintf",
+    "fwprintf", "vswprintf", "mbstowcs", "wcstombs", "wctomb", "wcrtomb", "poll", "ppoll", "longjmp",
+    "explicit_bzero", "fdelt",
+];
+
+const CANARY: &[&str] = &["__stack_chk_fail", "__stack_chk_fail_local", "__stack_chk_guard", "__intel_security_cookie"];
+
+// Names of the functions imported or defined, without symbol versions.
+fn function_names(program: &Program) -> BTreeSet<String> {
+    let imported = imports::imports(program).into_iter().map(|import| import.name);
+    let defined = program.symbol_table.iter().map(|sym| sym.name.clone());
+    imported.chain(defined)
+        .map(|name| name.split('@').next().unwrap_or_default().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+fn list(names: &[&str]) -> String {
+    const SHOWN: usize = 6;
+    let mut s = names.iter().take(SHOWN).copied().collect::<Vec<_>>().join(", ");
+    if names.len() > SHOWN {
+        s += format!(", ... ({} more)", names.len() - SHOWN).as_str();
+    }
+    s
+}
+
+// What the headers say about the exploit mitigations the file was built with.
+struct ElfHardening {
+    machine: u16,
+    elf_type: u16,
+    has_interp: bool,
+    // DF_1_PIE, set by linkers on position independent executables, including static ones.
+    pie_flag: bool,
+    // None without a GNU_STACK header, which leaves the stack executable on most targets.
+    exec_stack: Option<bool>,
+    relro: bool,
+    bind_now: bool,
+    // The feature bits of the GNU property note: IBT and SHSTK on x86, BTI and PAC on AArch64.
+    features: Option<u32>,
+}
+
+const GNU_PROPERTY_AARCH64_FEATURE_1_AND: u32 = 0xc0000000;
+const GNU_PROPERTY_X86_FEATURE_1_AND: u32 = 0xc0000002;
+
+// The x86 or AArch64 feature bits of the NT_GNU_PROPERTY_TYPE_0 notes in a note segment.
+fn property_features(bytes: &[u8], header: &Header, note: &ProgramHeaderEntry) -> Option<u32> {
+    // Notes are aligned to their segment; property data to the word size.
+    let note_align = if note.p_align == 8 { 8 } else { 4 };
+    let word = if header.class == 0x2 { 8 } else { 4 };
+    let pad = |n: usize, align: usize| (n + align - 1) / align * align;
+    let u32_at = |at: usize| read_u32_checked(bytes, at, header.data, "ELF note").ok();
+    let mut offset = note.p_offset as usize;
+    let end = offset.saturating_add(note.p_filesz as usize).min(bytes.len());
+    let mut features = None;
+    while offset + 12 <= end {
+        let (namesz, descsz, kind) = (u32_at(offset)? as usize, u32_at(offset + 4)? as usize, u32_at(offset + 8)?);
+        let name = offset + 12;
+        let desc = offset + pad(12 + namesz, note_align);
+        if kind == 5 && bytes.get(name..name + namesz) == Some(&b"GNU\0"[..]) {
+            features = Some(features.unwrap_or(0));
+            let mut at = desc;
+            while at + 8 <= (desc + descsz).min(end) {
+                let (pr_type, pr_size) = (u32_at(at)?, u32_at(at + 4)? as usize);
+                if pr_type == GNU_PROPERTY_X86_FEATURE_1_AND || pr_type == GNU_PROPERTY_AARCH64_FEATURE_1_AND {
+                    features = Some(features.unwrap_or(0) | u32_at(at + 8)?);
+                }
+                at += 8 + pad(pr_size, word);
+            }
+        }
+        offset = desc + pad(descsz, note_align);
+    }
+    features
+}
+
+fn elf_hardening(bytes: &[u8]) -> Result<ElfHardening, BaretkError> {
+    let (header, common_header, program_headers, _) = elf::read_headers(bytes, &mut Diagnostics::new(ParseMode::Permissive))?;
+    let entries = program_headers.iter().find(|ph| ph.p_type == 0x2)
+        .map(|dynamic| headers::dynamic_entries(bytes, &header, dynamic))
+        .unwrap_or_default();
+    let flags = entries.iter().filter(|(tag, _)| *tag == 30).fold(0, |acc, (_, value)| acc | value);
+    let flags_1 = entries.iter().filter(|(tag, _)| *tag == 0x6ffffffb).fold(0, |acc, (_, value)| acc | value);
+    // The property notes are in PT_GNU_PROPERTY, or in a PT_NOTE segment on older linkers.
+    let features = program_headers.iter()
+        .filter(|ph| ph.p_type == 0x6474e553 || ph.p_type == 0x4)
+        .find_map(|ph| property_features(bytes, &header, ph));
+    Ok(ElfHardening {
+        machine: common_header.e_machine,
+        elf_type: common_header.e_type,
+        has_interp: program_headers.iter().any(|ph| ph.p_type == 0x3),
+        pie_flag: flags_1 & 0x8000000 != 0,
+        exec_stack: program_headers.iter().find(|ph| ph.p_type == 0x6474e551).map(|ph| ph.p_flags & 0x1 != 0),
+        relro: program_headers.iter().any(|ph| ph.p_type == 0x6474e552),
+        // DT_BIND_NOW, DF_BIND_NOW or DF_1_NOW.
+        bind_now: entries.iter().any(|(tag, _)| *tag == 24) || flags & 0x8 != 0 || flags_1 & 0x1 != 0,
+        features,
+    })
+}
+
+fn elf_mitigations(bytes: &[u8], program: &Program) -> Result<Vec<Mitigation>, BaretkError> {
+    let hardening = elf_hardening(bytes)?;
+    let names = function_names(program);
+    let mut out = Vec::<Mitigation>::new();
+
+    out.push(match hardening.exec_stack {
+        Some(false) => mitigation("NX", true, "stack not executable"),
+        Some(true) => mitigation("NX", false, "GNU_STACK asks for an executable stack"),
+        None => mitigation("NX", false, "no GNU_STACK header; the stack is executable by default"),
+    });
+
+    out.push(match (hardening.elf_type, hardening.has_interp || hardening.pie_flag) {
+        (3, true) => mitigation("PIE", true, if hardening.has_interp { "position independent executable" } else { "static PIE" }),
+        (3, false) => mitigation("PIE", true, "shared object"),
+        (1, _) => mitigation("PIE", false, "relocatable object"),
+        _ => {
+            let base = program.program_table.iter().filter(|seg| seg.size > 0).map(|seg| seg.vaddr).min().unwrap_or(0);
+            mitigation("PIE", false, format!("fixed load address {:#x}", base))
+        },
+    });
+
+    out.push(match (hardening.relro, hardening.bind_now) {
+        (true, true) => mitigation("RELRO", true, "full: GOT read-only after relocation, symbols bound at load"),
+        (true, false) => mitigation("RELRO", true, "partial: lazily bound GOT entries stay writable"),
+        (false, _) => mitigation("RELRO", false, "no GNU_RELRO header"),
+    });
+
+    out.push(match CANARY.iter().find(|name| names.contains(**name)) {
+        Some(name) => mitigation("Stack canary", true, format!("uses {}", name)),
+        None => mitigation("Stack canary", false, "no __stack_chk_fail"),
+    });
+
+    let fortified: Vec<&str> = names.iter().map(String::as_str)
+        .filter(|name| name.starts_with("__") && name.ends_with("_chk") && !CANARY.contains(name))
+        .collect();
+    let unfortified: Vec<&str> = FORTIFIABLE.iter().copied().filter(|name| names.contains(*name)).collect();
+    let detail = match (fortified.is_empty(), unfortified.is_empty()) {
+        (true, true) => "no fortifiable functions".to_string(),
+        (false, true) => format!("{} fortified: {}", fortified.len(), list(&fortified)),
+        (true, false) => format!("{} unfortified: {}", unfortified.len(), list(&unfortified)),
+        (false, false) => format!("{} fortified: {}; {} unfortified: {}", fortified.len(), list(&fortified), unfortified.len(), list(&unfortified)),
+    };
+    out.push(mitigation("Fortify", !fortified.is_empty(), detail));
+
+    // Control flow protection only exists on x86 (CET) and AArch64 (BTI and PAC).
+    let (name, flags): (&str, &[(u32, &str)]) = match hardening.machine {
+        0x3 | 0x3e => ("CET", &[(0x1, "IBT"), (0x2, "SHSTK")]),
+        0xb7 => ("BTI/PAC", &[(0x1, "BTI"), (0x2, "PAC")]),
+        _ => return Ok(out),
+    };
+    out.push(match hardening.features {
+        Some(features) if features & 0x3 != 0 => {
+            let enabled: Vec<&str> = flags.iter().filter(|(bit, _)| features & bit != 0).map(|(_, name)| *name).collect();
+            mitigation(name, true, enabled.join(", "))
+        },
+        Some(_) => mitigation(name, false, "not marked compatible in the GNU property note"),
+        None => mitigation(name, false, "no GNU property note"),
+    });
+    Ok(out)
+}
+
+// What the headers, load config and debug directory say about the exploit
+// mitigations the image was built with.
+struct PeHardening {
+    is_64: bool,
+    dll_characteristics: u16,
+    relocs_stripped: bool,
+    // Virtual address of the /GS security cookie, or 0.
+    security_cookie: u64,
+    // SafeSEH handler count; only 32-bit images have one.
+    seh_handlers: Option<u64>,
+    guard_flags: u32,
+    guard_functions: u64,
+    // IMAGE_DLLCHARACTERISTICS_EX_CET_COMPAT from the extended DLL characteristics.
+    cet_compat: bool,
+}
+
+fn pe_hardening(bytes: &[u8]) -> Result<PeHardening, BaretkError> {
+    let offset = read_u32_checked(bytes, pe::PE_OFFSET_OFFSET, LITTLE_ENDIAN, "DOS header")? as usize;
+    let coff_header = pe::read_coff_header(bytes, offset)?;
+    let section_table = pe::read_section_table(bytes, offset, &coff_header, &mut Diagnostics::new(ParseMode::Permissive))?;
+    let opt = offset + 0x18;
+    let is_64 = coff_header.optional_header_size >= 0x60 && read_u16_checked(bytes, opt, LITTLE_ENDIAN, "PE optional header")? == 0x20b;
+    let mut hardening = PeHardening {
+        is_64,
+        dll_characteristics: 0,
+        relocs_stripped: coff_header.characteristics & 0x1 != 0,
+        security_cookie: 0,
+        seh_handlers: None,
+        guard_flags: 0,
+        guard_functions: 0,
+        cet_compat: false,
+    };
+    if coff_header.optional_header_size < 0x60 {
+        return Ok(hardening)
+    }
+    hardening.dll_characteristics = read_u16_checked(bytes, opt + 0x46, LITTLE_ENDIAN, "PE optional header")?;
+    let u32_at = |at: usize| read_u32_checked(bytes, at, LITTLE_ENDIAN, "PE load config").unwrap_or(0);
+    let word_at = |at: usize| if !is_64 { u32_at(at) as u64 } else { read_u64_checked(bytes, at, LITTLE_ENDIAN, "PE load config").unwrap_or(0) };
+    let directories = opt + if is_64 { 0x70 } else { 0x60 };
+    let end = opt + coff_header.optional_header_size as usize;
+    let directory = |index: usize| {
+        let at = directories + index * 8;
+        if at + 8 > end { None } else { Some((u32_at(at), u32_at(at + 4) as usize)) }
+    };
+
+    // The load config's fields past its own size weren't written by the linker.
+    if let Some((rva, _)) = directory(10).filter(|(rva, _)| *rva != 0) {
+        if let Some(config) = pe::rva_to_offset(rva, &section_table) {
+            let size = u32_at(config) as usize;
+            let (cookie, seh_count, guard_count, guard_flags) = if is_64 { (0x58, 0x68, 0x88, 0x90) } else { (0x3c, 0x44, 0x54, 0x58) };
+            let word = if is_64 { 8 } else { 4 };
+            if size >= cookie + word {
+                hardening.security_cookie = word_at(config + cookie);
+            }
+            if !is_64 && size >= seh_count + word {
+                hardening.seh_handlers = Some(word_at(config + seh_count));
+            }
+            if size >= guard_count + word {
+                hardening.guard_functions = word_at(config + guard_count);
+            }
+            if size >= guard_flags + 4 {
+                hardening.guard_flags = u32_at(config + guard_flags);
+            }
+        }
+    }
+
+    // IMAGE_DEBUG_TYPE_EX_DLLCHARACTERISTICS entries in the debug directory.
+    if let Some((rva, size)) = directory(6).filter(|(rva, _)| *rva != 0) {
+        if let Some(debug) = pe::rva_to_offset(rva, &section_table) {
+            for entry in (debug..debug + size).step_by(28).take_while(|entry| entry + 28 <= bytes.len()) {
+                if u32_at(entry + 0xc) == 20 && u32_at(entry + 0x10) >= 4 {
+                    hardening.cet_compat = u32_at(u32_at(entry + 0x18) as usize) & 0x1 != 0;
+                }
+            }
+        }
+    }
+    Ok(hardening)
+}
+
+fn pe_mitigations(bytes: &[u8], program: &Program) -> Result<Vec<Mitigation>, BaretkError> {
+    let hardening = pe_hardening(bytes)?;
+    let names = function_names(program);
+    let dll = hardening.dll_characteristics;
+    let mut out = Vec::<Mitigation>::new();
+
+    out.push(mitigation("DEP", dll & 0x100 != 0, if dll & 0x100 != 0 { "NX compatible" } else { "not marked NX compatible" }));
+
+    let high_entropy = if hardening.is_64 && dll & 0x20 != 0 { ", high entropy VA" } else { "" };
+    out.push(match (dll & 0x40 != 0, hardening.relocs_stripped) {
+        (true, false) => mitigation("ASLR", true, format!("dynamic base{}", high_entropy)),
+        (true, true) => mitigation("ASLR", false, "dynamic base, but relocations are stripped"),
+        (false, _) => mitigation("ASLR", false, "no dynamic base"),
+    });
+
+    let gs = ["__security_check_cookie", "__GSHandlerCheck", "__security_cookie"].iter().find(|name| names.contains(**name));
+    out.push(match (hardening.security_cookie, gs) {
+        (0, None) => mitigation("Stack canary", false, "no /GS security cookie"),
+        (0, Some(name)) => mitigation("Stack canary", true, format!("/GS, uses {}", name)),
+        (cookie, _) => mitigation("Stack canary", true, format!("/GS, security cookie at {:#x}", cookie)),
+    });
+
+    // 64-bit exception handling is table based, so SafeSEH only applies to 32-bit images.
+    if !hardening.is_64 {
+        out.push(match (dll & 0x400 != 0, hardening.seh_handlers) {
+            (true, _) => mitigation("SafeSEH", true, "no SEH handlers"),
+            (false, Some(count)) if count > 0 => mitigation("SafeSEH", true, format!("{} registered handler(s)", count)),
+            _ => mitigation("SafeSEH", false, "no SafeSEH handler table"),
+        });
+    }
+
+    // IMAGE_GUARD_CF_INSTRUMENTED in the load config's guard flags.
+    out.push(match (dll & 0x4000 != 0, hardening.guard_flags & 0x100 != 0) {
+        (true, true) => mitigation("CFG", true, format!("{} guarded function(s)", hardening.guard_functions)),
+        (true, false) => mitigation("CFG", false, "guard CF set, but the load config has no CFG instrumentation"),
+        (false, _) => mitigation("CFG", false, "not built with /guard:cf"),
+    });
+
+    out.push(mitigation("CET", hardening.cet_compat,
+        if hardening.cet_compat { "shadow stack compatible" } else { "not marked CET compatible" }));
+    Ok(out)
+}
+
+// The mitigations for an ELF or PE file, or None for raw binaries.
+pub fn checksec(bytes: &[u8], program: &Program) -> Result<Option<Vec<Mitigation>>, BaretkError> {
+    match query::get_file_type(bytes) {
+        query::FileType::Elf => elf_mitigations(bytes, program).map(Some),
+        query::FileType::PE => pe_mitigations(bytes, program).map(Some),
+        _ => Ok(None),
+    }
+}
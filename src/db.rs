@@ -0,0 +1,94 @@
+// Writes the analysis of a binary to a SQLite database, so other tools and
+// diffs can query it with SQL. Addresses are stored as integers, the way
+// SQLite keeps 64-bit values; an address above i64::MAX reads back negative.
+
+use std::io;
+
+use rusqlite::{params, Connection};
+
+use crate::dis::Disassembly;
+use crate::error::BaretkError;
+use crate::query::{self, Encoding};
+use crate::xref::XrefDb;
+
+// Extension of the files written, e.g. "a.out.baretkdb".
+pub const EXTENSION: &str = "baretkdb";
+
+const SCHEMA: &str = "
+    CREATE TABLE program (format TEXT, machine TEXT, bits INTEGER, entry_point INTEGER, code_section TEXT);
+    CREATE TABLE sections (name TEXT, addr INTEGER, offset INTEGER, size INTEGER, perm INTEGER);
+    CREATE TABLE symbols (name TEXT, addr INTEGER, size INTEGER, is_func INTEGER);
+    CREATE TABLE imports (library TEXT, name TEXT, slot INTEGER);
+    CREATE TABLE functions (name TEXT, addr INTEGER, end_addr INTEGER);
+    CREATE TABLE instructions (addr INTEGER PRIMARY KEY, bytes BLOB, mnemonic TEXT, operands TEXT, comment TEXT);
+    CREATE TABLE xrefs (from_addr INTEGER, to_addr INTEGER, kind TEXT);
+    CREATE TABLE strings (offset INTEGER, addr INTEGER, section TEXT, encoding TEXT, text TEXT);
+    CREATE INDEX xrefs_to ON xrefs (to_addr);
+    CREATE INDEX xrefs_from ON xrefs (from_addr);
+";
+
+// Writes a new database at `path`, replacing any file there. `bytes` is the
+// whole input file, which strings are read from.
+pub fn write_database(path: &str, dis: &Disassembly, bytes: &[u8], min_string_len: usize) -> Result<(), BaretkError> {
+    let error = |err: rusqlite::Error| BaretkError::io(path, "writing", io::Error::other(err));
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(BaretkError::io(path, "replacing", err)),
+        _ => (),
+    }
+    let mut conn = Connection::open(path).map_err(error)?;
+    conn.execute_batch(SCHEMA).map_err(error)?;
+    let tx = conn.transaction().map_err(error)?;
+    write_tables(&tx, dis, bytes, min_string_len).map_err(error)?;
+    tx.commit().map_err(error)
+}
+
+fn write_tables(conn: &Connection, dis: &Disassembly, bytes: &[u8], min_string_len: usize) -> rusqlite::Result<()> {
+    let program = dis.program();
+    conn.execute("INSERT INTO program VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![program.format, program.machine_type, program.bits, program.entry_point as i64, dis.section().section_name])?;
+
+    let mut names: Vec<&String> = program.section_table.keys().collect();
+    names.sort_by_key(|name| program.section_table[*name].addr);
+    let mut insert = conn.prepare("INSERT INTO sections VALUES (?1, ?2, ?3, ?4, ?5)")?;
+    for name in names {
+        let section = &program.section_table[name];
+        insert.execute(params![name, section.addr as i64, section.offset as i64, section.bytes.len() as i64, section.perm])?;
+    }
+
+    let mut insert = conn.prepare("INSERT INTO symbols VALUES (?1, ?2, ?3, ?4)")?;
+    for sym in &program.symbol_table {
+        insert.execute(params![sym.name, sym.addr as i64, sym.size as i64, sym.is_func])?;
+    }
+
+    let mut insert = conn.prepare("INSERT INTO imports VALUES (?1, ?2, ?3)")?;
+    for import in &program.import_table {
+        insert.execute(params![import.library, import.name, import.slot as i64])?;
+    }
+
+    let base = dis.section_addr();
+    let mut insert = conn.prepare("INSERT INTO functions VALUES (?1, ?2, ?3)")?;
+    for func in dis.functions() {
+        insert.execute(params![func.name, func.addr as i64, (base + func.range.end as u64) as i64])?;
+    }
+
+    let mut insert = conn.prepare("INSERT INTO instructions VALUES (?1, ?2, ?3, ?4, ?5)")?;
+    for record in dis.instructions() {
+        insert.execute(params![record.addr as i64, record.bytes, record.mnemonic, record.operands.join(", "), record.comment])?;
+    }
+
+    let xrefs = XrefDb::build(dis);
+    let mut insert = conn.prepare("INSERT INTO xrefs VALUES (?1, ?2, ?3)")?;
+    for (to, refs) in xrefs.refs_in(0..u64::MAX) {
+        for xref in refs {
+            insert.execute(params![xref.from as i64, *to as i64, xref.kind.name()])?;
+        }
+    }
+
+    let mut strings = query::find_strings(bytes, min_string_len, false, &[Encoding::Ascii], None);
+    query::locate_strings(program, &mut strings);
+    let mut insert = conn.prepare("INSERT INTO strings VALUES (?1, ?2, ?3, ?4, ?5)")?;
+    for found in &strings {
+        insert.execute(params![found.offset as i64, found.addr.map(|addr| addr as i64), found.section, found.encoding.name(), found.text])?;
+    }
+    Ok(())
+}
@@ -1,122 +1,899 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::{BTreeMap, BTreeSet, HashMap, HashSet}, fmt::Write, ops::Range, rc::Rc};
 
-use crate::dis::{self, Disassembly, Instruction};
+use crate::dis::{self, Disassembly, DisassemblySection, Flow, Instruction};
+use crate::error::BaretkError;
+use crate::imports;
+use crate::json::Value;
+use crate::options::AnalysisOptions;
+use crate::prog::{self, Program, SymbolIndex};
+use crate::proto::PrototypeDb;
+use crate::reg::{Reg, RegClass};
+use crate::syscall;
+use crate::xref;
 
 #[derive(Clone, Copy)]
 pub enum Language {
     Pseudocode, // TODO: Add C decompilation target
+    Rust,
 }
 
-const OP_ADD: u8 = 0x0;
-const OP_SUB: u8 = 0x1;
-const OP_MUL: u8 = 0x2;
-const OP_AND: u8 = 0x3;
-const OP_OR: u8 = 0x4;
-const OP_XOR: u8 = 0x5;
+// Output languages by the names -lang and the C API take, default first.
+pub const LANGUAGES: &[(&str, Language)] = &[("pseudo", Language::Pseudocode), ("rust", Language::Rust)];
 
-enum Expr {
+impl Language {
+    pub fn from_name(name: &str) -> Option<Language> {
+        LANGUAGES.iter().find(|(n, _)| *n == name).map(|(_, lang)| *lang)
+    }
+}
+
+pub(crate) const OP_ADD: u8 = 0x0;
+pub(crate) const OP_SUB: u8 = 0x1;
+pub(crate) const OP_MUL: u8 = 0x2;
+pub(crate) const OP_AND: u8 = 0x3;
+pub(crate) const OP_OR: u8 = 0x4;
+pub(crate) const OP_XOR: u8 = 0x5;
+pub(crate) const OP_SHL: u8 = 0x6;
+pub(crate) const OP_SHR: u8 = 0x7;
+pub(crate) const OP_SAR: u8 = 0x8;
+pub(crate) const OP_ROR: u8 = 0x9;
+pub(crate) const OP_EQ: u8 = 0x10;
+pub(crate) const OP_NE: u8 = 0x11;
+pub(crate) const OP_LT: u8 = 0x12;
+pub(crate) const OP_LE: u8 = 0x13;
+pub(crate) const OP_GT: u8 = 0x14;
+pub(crate) const OP_GE: u8 = 0x15;
+pub(crate) const OP_LTU: u8 = 0x16;
+pub(crate) const OP_LEU: u8 = 0x17;
+pub(crate) const OP_GTU: u8 = 0x18;
+pub(crate) const OP_GEU: u8 = 0x19;
+
+pub(crate) const OP_NOT: u8 = 0x20;
+pub(crate) const OP_NEG: u8 = 0x21;
+pub(crate) const OP_INV: u8 = 0x22;
+pub(crate) const OP_SEXT: u8 = 0x23;
+
+fn binary_op_str(op: u8) -> &'static str {
+    match op {
+        OP_ADD => "+",
+        OP_SUB => "-",
+        OP_MUL => "*",
+        OP_AND => "&",
+        OP_OR  => "|",
+        OP_XOR => "^",
+        OP_SHL => "<<",
+        OP_SHR => ">>",
+        OP_SAR => ">>s",
+        OP_ROR => "ror",
+        OP_EQ  => "==",
+        OP_NE  => "!=",
+        OP_LT  => "<",
+        OP_LE  => "<=",
+        OP_GT  => ">",
+        OP_GE  => ">=",
+        OP_LTU => "<u",
+        OP_LEU => "<=u",
+        OP_GTU => ">u",
+        OP_GEU => ">=u",
+        _ => "?"
+    }
+}
+
+fn unary_op_str(op: u8) -> &'static str {
+    match op {
+        OP_NOT => "!",
+        OP_NEG => "-",
+        OP_INV => "~",
+        OP_SEXT => "sext",
+        _ => "?"
+    }
+}
+
+// Index of an expression node in an ExprArena.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ExprId(u32);
+
+// A run of child ids stored in ExprArena::lists, used by variadic nodes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ExprList {
+    start: u32,
+    len: u32,
+}
+
+// Expression nodes refer to their children by id, so subtrees can be shared
+// between expressions instead of deep cloned.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Expr {
     Constant(i64),
-    Memory(i64),
-    Register(&'static str),
-    Dereference(u8, Box<Expr>),
-    Binary(u8, Box<Expr>, Box<Expr>),
-    Unary(u8, Box<Expr>),
-    Call(Box<Expr>),
-    Store(Box<Expr>, Box<Expr>),
-    Group(Vec<Box<Expr>>),
+    Register(Reg),
+    Dereference(u8, ExprId),
+    Binary(u8, ExprId, ExprId),
+    Unary(u8, ExprId),
+    // A value sign-extended (when true) or zero-extended to a size in bytes.
+    Cast(u8, bool, ExprId),
+    Call(ExprId, ExprList),
+    // A call argument with its parameter name from ExprArena::names.
+    Argument(u32, ExprId),
+    Intrinsic(&'static str, ExprList),
+    Store(ExprId, ExprId),
+    Group(ExprList),
+    If(ExprId, ExprId),
+    Goto(ExprId),
+    Flag(&'static str),
+    // A name from ExprArena::names, such as a function symbol.
+    Symbol(u32),
     Nop,
-    Return
+    Return,
+    ReturnValue(ExprId),
 }
 
-impl Expr {
-    fn print(&self, depth: i32, lang: Language) -> String {
-        let mut out = String::new();
+// Flat storage for every expression built during a decompilation. Nodes are
+// never freed individually; the whole arena is dropped with the Decomp.
+pub(crate) struct ExprArena {
+    nodes: RefCell<Vec<Expr>>,
+    lists: RefCell<Vec<ExprId>>,
+    names: RefCell<Vec<String>>,
+}
+
+impl ExprArena {
+    fn new() -> Self {
+        ExprArena { nodes: RefCell::new(Vec::new()), lists: RefCell::new(Vec::new()), names: RefCell::new(Vec::new()) }
+    }
+
+    fn push(&self, expr: Expr) -> ExprId {
+        let mut nodes = self.nodes.borrow_mut();
+        nodes.push(expr);
+        ExprId(nodes.len() as u32 - 1)
+    }
+
+    fn push_list(&self, ids: &[ExprId]) -> ExprList {
+        let mut lists = self.lists.borrow_mut();
+        let start = lists.len() as u32;
+        lists.extend_from_slice(ids);
+        ExprList { start, len: ids.len() as u32 }
+    }
+
+    pub(crate) fn get(&self, id: ExprId) -> Expr {
+        self.nodes.borrow()[id.0 as usize]
+    }
+
+    pub(crate) fn list(&self, list: ExprList) -> Vec<ExprId> {
+        self.lists.borrow()[list.start as usize..(list.start + list.len) as usize].to_vec()
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.borrow().len()
+    }
+
+    fn size_in_bytes(&self) -> usize {
+        self.nodes.borrow().capacity() * std::mem::size_of::<Expr>() + self.lists.borrow().capacity() * std::mem::size_of::<ExprId>()
+    }
+
+    // Structural equality, for expressions built separately from the same operands.
+    fn equal(&self, a: ExprId, b: ExprId) -> bool {
+        if a == b {
+            return true
+        }
+        match (self.get(a), self.get(b)) {
+            (Expr::Dereference(s1, x1), Expr::Dereference(s2, x2)) => s1 == s2 && self.equal(x1, x2),
+            (Expr::Binary(o1, l1, r1), Expr::Binary(o2, l2, r2)) => o1 == o2 && self.equal(l1, l2) && self.equal(r1, r2),
+            (Expr::Unary(o1, x1), Expr::Unary(o2, x2)) => o1 == o2 && self.equal(x1, x2),
+            (Expr::Cast(s1, g1, x1), Expr::Cast(s2, g2, x2)) => s1 == s2 && g1 == g2 && self.equal(x1, x2),
+            (Expr::Call(x1, l1), Expr::Call(x2, l2)) => self.equal(x1, x2) && self.equal_lists(l1, l2),
+            (Expr::Argument(n1, x1), Expr::Argument(n2, x2)) => n1 == n2 && self.equal(x1, x2),
+            (Expr::Goto(x1), Expr::Goto(x2))
+            | (Expr::ReturnValue(x1), Expr::ReturnValue(x2)) => self.equal(x1, x2),
+            (Expr::Store(d1, s1), Expr::Store(d2, s2)) | (Expr::If(d1, s1), Expr::If(d2, s2)) => self.equal(d1, d2) && self.equal(s1, s2),
+            (Expr::Intrinsic(n1, l1), Expr::Intrinsic(n2, l2)) => n1 == n2 && self.equal_lists(l1, l2),
+            (Expr::Group(l1), Expr::Group(l2)) => self.equal_lists(l1, l2),
+            (x, y) => x == y,
+        }
+    }
+
+    fn equal_lists(&self, a: ExprList, b: ExprList) -> bool {
+        a.len == b.len && self.list(a).iter().zip(self.list(b)).all(|(x, y)| self.equal(*x, y))
+    }
+
+    fn register(&self, r: Reg) -> ExprId {
+        self.push(Expr::Register(r))
+    }
+
+    fn constant(&self, i: i64) -> ExprId {
+        self.push(Expr::Constant(i))
+    }
+
+    fn binary(&self, op: u8, lhs: ExprId, rhs: ExprId) -> ExprId {
+        self.push(Expr::Binary(op, lhs, rhs))
+    }
+
+    fn dereference(&self, size: u8, rhs: ExprId) -> ExprId {
+        self.push(Expr::Dereference(size, rhs))
+    }
+
+    fn store(&self, dest: ExprId, src: ExprId) -> ExprId {
+        self.push(Expr::Store(dest, src))
+    }
+
+    fn group(&self, group: &[ExprId]) -> ExprId {
+        let list = self.push_list(group);
+        self.push(Expr::Group(list))
+    }
+
+    fn unary(&self, op: u8, rhs: ExprId) -> ExprId {
+        self.push(Expr::Unary(op, rhs))
+    }
+
+    fn cast(&self, size: u8, signed: bool, rhs: ExprId) -> ExprId {
+        self.push(Expr::Cast(size, signed, rhs))
+    }
+
+    // Size in bytes of a register or memory value.
+    fn value_size(&self, id: ExprId) -> Option<u8> {
+        match self.get(id) {
+            Expr::Register(r) => Some((r.bits() / 8) as u8),
+            Expr::Dereference(size, _) => Some(size),
+            _ => None,
+        }
+    }
+
+    fn if_(&self, cond: ExprId, body: ExprId) -> ExprId {
+        self.push(Expr::If(cond, body))
+    }
+
+    fn goto(&self, target: ExprId) -> ExprId {
+        self.push(Expr::Goto(target))
+    }
+
+    fn flag(&self, name: &'static str) -> ExprId {
+        self.push(Expr::Flag(name))
+    }
+
+    fn call(&self, target: ExprId, args: &[ExprId]) -> ExprId {
+        let list = self.push_list(args);
+        self.push(Expr::Call(target, list))
+    }
+
+    fn argument(&self, name: &str, value: ExprId) -> ExprId {
+        let index = self.name_index(name);
+        self.push(Expr::Argument(index, value))
+    }
+
+    fn intrinsic(&self, name: &'static str, args: &[ExprId]) -> ExprId {
+        let list = self.push_list(args);
+        self.push(Expr::Intrinsic(name, list))
+    }
+
+    fn name_index(&self, name: &str) -> u32 {
+        let mut names = self.names.borrow_mut();
+        match names.iter().position(|n| n == name) {
+            Some(index) => index as u32,
+            None => {
+                names.push(name.to_string());
+                names.len() as u32 - 1
+            }
+        }
+    }
+
+    pub(crate) fn name(&self, index: u32) -> String {
+        self.names.borrow()[index as usize].clone()
+    }
+
+    fn symbol(&self, name: &str) -> ExprId {
+        let index = self.name_index(name);
+        self.push(Expr::Symbol(index))
+    }
+
+    fn ret_value(&self, value: ExprId) -> ExprId {
+        self.push(Expr::ReturnValue(value))
+    }
+
+    fn nop(&self) -> ExprId {
+        self.push(Expr::Nop)
+    }
+
+    fn ret(&self) -> ExprId {
+        self.push(Expr::Return)
+    }
+
+    fn write(&self, out: &mut String, id: ExprId, depth: i32, lang: Language) {
         for _ in 0..depth {
-            out += "    ";
+            *out += "    ";
+        }
+        match lang {
+            // Writing to a String can't fail.
+            Language::Pseudocode => { let _ = self.write_pseudocode(out, id, depth); },
+            // Rust output needs the statement address to resolve gotos; see write_rust.
+            Language::Rust => { let _ = self.write_rust(out, id, 0, &BTreeSet::new()); },
         }
-        out += (match self {
-            Self::Constant(i) => format!("{}", i),
-            Self::Register(r) => format!("{}", r),
-            Self::Dereference(s, rhs) => {
-                match lang {
-                    Language::Pseudocode => match s {
-                        1 => format!("*u8({})", (*rhs).print(0, lang)),
-                        2 => format!("*u16({})", (*rhs).print(0, lang)),
-                        4 => format!("*u32({})", (*rhs).print(0, lang)),
-                        8 => format!("*u64({})", (*rhs).print(0, lang)),
-                        _ => format!("*({})", (*rhs).print(0, lang))
+    }
+
+    fn write_pseudocode(&self, out: &mut String, id: ExprId, depth: i32) -> std::fmt::Result {
+        let lang = Language::Pseudocode;
+        match self.get(id) {
+            Expr::Constant(i) => write!(out, "{}", i),
+            Expr::Register(r) => write!(out, "{}", r),
+            Expr::Dereference(s, rhs) => {
+                *out += match s {
+                    1 => "*u8(",
+                    2 => "*u16(",
+                    4 => "*u32(",
+                    8 => "*u64(",
+                    _ => "*(",
+                };
+                self.write(out, rhs, 0, lang);
+                write!(out, ")")
+            },
+            Expr::Binary(op, lhs, rhs) => {
+                *out += "(";
+                self.write(out, lhs, 0, lang);
+                write!(out, " {} ", binary_op_str(op))?;
+                self.write(out, rhs, 0, lang);
+                write!(out, ")")
+            },
+            Expr::Unary(op, rhs) => {
+                match self.get(rhs) {
+                    Expr::Binary(..) => {
+                        *out += unary_op_str(op);
+                        self.write(out, rhs, 0, lang);
+                    },
+                    _ if op == OP_SEXT => {
+                        write!(out, "{}(", unary_op_str(op))?;
+                        self.write(out, rhs, 0, lang);
+                        *out += ")";
+                    },
+                    _ => {
+                        *out += unary_op_str(op);
+                        self.write(out, rhs, 0, lang);
+                    },
+                };
+                Ok(())
+            },
+            // `(i64)eax`, with a signed load read as signed: `(i32)*i8(rbx)`.
+            Expr::Cast(size, signed, rhs) => {
+                let sign = if signed { "i" } else { "u" };
+                write!(out, "({}{})", sign, size as u32 * 8)?;
+                match self.get(rhs) {
+                    Expr::Dereference(s, addr) if signed => {
+                        write!(out, "*i{}(", s as u32 * 8)?;
+                        self.write(out, addr, 0, lang);
+                        write!(out, ")")
+                    },
+                    _ => {
+                        self.write(out, rhs, 0, lang);
+                        Ok(())
+                    },
+                }
+            },
+            Expr::If(cond, body) => {
+                match self.get(cond) {
+                    Expr::Binary(..) => {
+                        *out += "if ";
+                        self.write(out, cond, 0, lang);
+                        *out += " ";
+                    },
+                    _ => {
+                        *out += "if (";
+                        self.write(out, cond, 0, lang);
+                        *out += ") ";
+                    },
+                };
+                self.write(out, body, 0, lang);
+                Ok(())
+            },
+            Expr::Goto(target) => {
+                *out += "goto ";
+                self.write(out, target, 0, lang);
+                Ok(())
+            },
+            Expr::Flag(name) => write!(out, "{}", name),
+            Expr::Call(op, args) => {
+                match self.get(op) {
+                    Expr::Symbol(_) => self.write(out, op, 0, lang),
+                    // A call to an address without a name.
+                    Expr::Constant(target) => write!(out, "({:#x})", target)?,
+                    _ => {
+                        *out += "(";
+                        self.write(out, op, 0, lang);
+                        *out += ")";
+                    },
+                }
+                *out += "(";
+                for (i, arg) in self.list(args).into_iter().enumerate() {
+                    if i != 0 {
+                        *out += ", ";
                     }
-                    _ => todo!("Other languages besides the pseudocode")
+                    self.write(out, arg, 0, lang);
                 }
+                write!(out, ")")
             },
-            Self::Binary(op, lhs, rhs) => {
-                match lang {
-                    Language::Pseudocode => match *op {
-                        OP_ADD => format!("({} + {})", (*lhs).print(0, lang), (*rhs).print(0, lang)),
-                        OP_SUB => format!("({} - {})", (*lhs).print(0, lang), (*rhs).print(0, lang)),
-                        OP_MUL => format!("({} * {})", (*lhs).print(0, lang), (*rhs).print(0, lang)),
-                        OP_XOR => format!("({} ^ {})", (*lhs).print(0, lang), (*rhs).print(0, lang)),
-                        _ => format!("({} ? {})", (*lhs).print(0, lang), (*rhs).print(0, lang))
+            Expr::Argument(name, value) => {
+                write!(out, "{}: ", self.names.borrow()[name as usize])?;
+                self.write(out, value, 0, lang);
+                Ok(())
+            },
+            Expr::Symbol(name) => write!(out, "{}", self.names.borrow()[name as usize]),
+            Expr::Intrinsic(name, args) => {
+                write!(out, "{}(", name)?;
+                for (i, arg) in self.list(args).into_iter().enumerate() {
+                    if i != 0 {
+                        *out += ", ";
                     }
-                    _ => todo!("Other languages besides the pseudocode")
+                    self.write(out, arg, 0, lang);
                 }
+                write!(out, ")")
+            },
+            Expr::Return => write!(out, "return"),
+            Expr::ReturnValue(value) => {
+                *out += "return ";
+                self.write(out, value, 0, lang);
+                Ok(())
+            },
+            Expr::Store(dest, src) => {
+                self.write(out, dest, 0, lang);
+                *out += " = ";
+                self.write(out, src, 0, lang);
+                Ok(())
             },
-            Self::Call(op) => {
-                match lang {
-                    Language::Pseudocode => format!("({})()", (*op).print(0, lang)),
-                    _ => todo!("Other languages besides the pseudocode")
+            Expr::Nop => write!(out, "nop"),
+            Expr::Group(group) => {
+                *out += "do:";
+                for expr in self.list(group) {
+                    *out += "\n    ";
+                    self.write(out, expr, depth + 1, lang);
                 }
+                Ok(())
             },
-            Self::Return => {
-                match lang {
-                    Language::Pseudocode => format!("return"),
-                    _ => todo!("Other languages besides the pseudocode")
+        }
+    }
+}
+
+fn rust_op_str(op: u8) -> &'static str {
+    match op {
+        OP_SAR => ">>",
+        OP_LTU => "<",
+        OP_LEU => "<=",
+        OP_GTU => ">",
+        OP_GEU => ">=",
+        _ => binary_op_str(op),
+    }
+}
+
+fn rust_int_type(size: u8) -> &'static str {
+    match size {
+        1 => "u8",
+        2 => "u16",
+        4 => "u32",
+        _ => "u64",
+    }
+}
+
+impl ExprArena {
+    // Registers are u64 values; signed operations cast to i64 and back, and memory
+    // accesses go through raw pointers of the access width.
+    // `addr` is the address of the statement, for resolving relative goto targets
+    // against `labels`, the statement addresses that start a match arm.
+    // A value as i64, sign-extended from the width of its register or access.
+    fn write_rust_signed(&self, out: &mut String, id: ExprId, addr: u64, labels: &BTreeSet<u64>) -> std::fmt::Result {
+        *out += "(";
+        self.write_rust(out, id, addr, labels)?;
+        match self.value_size(id) {
+            Some(size) if size < 8 => write!(out, " as i{} as i64)", size as u32 * 8),
+            _ => write!(out, " as i64)"),
+        }
+    }
+
+    fn write_rust(&self, out: &mut String, id: ExprId, addr: u64, labels: &BTreeSet<u64>) -> std::fmt::Result {
+        match self.get(id) {
+            Expr::Constant(i) if i < 0 => write!(out, "({} as u64)", i),
+            Expr::Constant(i) => write!(out, "{}", i),
+            Expr::Register(r) => write!(out, "{}", r),
+            Expr::Dereference(s, rhs) => {
+                *out += "(*(";
+                self.write_rust(out, rhs, addr, labels)?;
+                write!(out, " as *const {}) as u64)", rust_int_type(s))
+            },
+            Expr::Binary(OP_ROR, lhs, rhs) => {
+                self.write_rust(out, lhs, addr, labels)?;
+                *out += ".rotate_right(";
+                self.write_rust(out, rhs, addr, labels)?;
+                write!(out, " as u32)")
+            },
+            Expr::Binary(op @ (OP_SAR | OP_LT | OP_LE | OP_GT | OP_GE), lhs, rhs) => {
+                *out += "(";
+                self.write_rust_signed(out, lhs, addr, labels)?;
+                write!(out, " {} ", rust_op_str(op))?;
+                self.write_rust_signed(out, rhs, addr, labels)?;
+                if op == OP_SAR { write!(out, ") as u64") } else { write!(out, ")") }
+            },
+            Expr::Binary(op, lhs, rhs) => {
+                *out += "(";
+                self.write_rust(out, lhs, addr, labels)?;
+                match op {
+                    OP_ADD => *out += ").wrapping_add(",
+                    OP_SUB => *out += ").wrapping_sub(",
+                    OP_MUL => *out += ").wrapping_mul(",
+                    _ => write!(out, " {} ", rust_op_str(op))?,
                 }
+                self.write_rust(out, rhs, addr, labels)?;
+                write!(out, ")")
+            },
+            Expr::Unary(OP_SEXT, rhs) => {
+                *out += "((";
+                self.write_rust(out, rhs, addr, labels)?;
+                write!(out, " as i64) as u64)")
             },
-            Self::Store(dest, src) => {
-                match lang {
-                    Language::Pseudocode => format!("{} = {}", (*dest).print(0, lang), (*src).print(0, lang)),
-                    _ => todo!("Other languages besides the pseudocode")
+            // Truncated to the source size, extended to the destination size, and
+            // kept in a u64 like every register.
+            Expr::Cast(size, signed, rhs) => {
+                let bits = size as u32 * 8;
+                *out += "(";
+                self.write_rust(out, rhs, addr, labels)?;
+                if let Some(from) = self.value_size(rhs) {
+                    write!(out, " as {}{}", if signed { "i" } else { "u" }, from as u32 * 8)?;
                 }
+                if signed {
+                    write!(out, " as i{}", bits)?;
+                }
+                if bits < 64 {
+                    write!(out, " as u{}", bits)?;
+                }
+                write!(out, " as u64)")
+            },
+            Expr::Unary(op, rhs) => {
+                *out += match op {
+                    OP_NOT => "(",
+                    OP_NEG => "(0u64.wrapping_sub(",
+                    _ => "!(",
+                };
+                self.write_rust(out, rhs, addr, labels)?;
+                *out += match op {
+                    OP_NOT => " == 0)",
+                    OP_NEG => "))",
+                    _ => ")",
+                };
+                Ok(())
             },
-            Self::Nop => format!("nop"),
-            Self::Group(group) => {
-                let mut out = String::new();
-                out += "do:\n";
-                for expr in group {
-                    out += format!("    {}\n", (*expr).print(depth + 1, lang)).as_str();
+            Expr::If(cond, body) => {
+                *out += "if ";
+                self.write_rust(out, cond, addr, labels)?;
+                *out += " { ";
+                self.write_rust_stmt(out, body, addr, labels)?;
+                write!(out, " }}")
+            },
+            Expr::Goto(target) => match self.get(target) {
+                Expr::Constant(rel) if labels.contains(&addr.wrapping_add(rel as u64)) => {
+                    write!(out, "label = {:#x}; continue;", addr.wrapping_add(rel as u64))
+                },
+                Expr::Constant(rel) => write!(out, "jump({:#x});", addr.wrapping_add(rel as u64)),
+                _ => {
+                    *out += "jump(";
+                    self.write_rust(out, target, addr, labels)?;
+                    write!(out, ");")
+                },
+            },
+            Expr::Flag(name) => write!(out, "{}", name),
+            Expr::Symbol(name) => write!(out, "{}", self.names.borrow()[name as usize]),
+            Expr::Call(op, args) => {
+                match self.get(op) {
+                    Expr::Symbol(_) => self.write_rust(out, op, addr, labels)?,
+                    Expr::Constant(target) => write!(out, "call({:#x})", target)?,
+                    _ => {
+                        *out += "call(";
+                        self.write_rust(out, op, addr, labels)?;
+                        *out += ")";
+                    },
                 }
-                out.strip_suffix("\n").unwrap_or(out.as_str()).to_string()
+                *out += "(";
+                for (i, arg) in self.list(args).into_iter().enumerate() {
+                    if i != 0 {
+                        *out += ", ";
+                    }
+                    self.write_rust(out, arg, addr, labels)?;
+                }
+                write!(out, ")")
             },
-            _ => todo!("Finish expression printing")
-        }).as_str();
-        out
+            Expr::Argument(name, value) => {
+                write!(out, "/* {} */ ", self.names.borrow()[name as usize])?;
+                self.write_rust(out, value, addr, labels)
+            },
+            Expr::Intrinsic(name, args) => {
+                write!(out, "{}(", name)?;
+                for (i, arg) in self.list(args).into_iter().enumerate() {
+                    if i != 0 {
+                        *out += ", ";
+                    }
+                    self.write_rust(out, arg, addr, labels)?;
+                }
+                write!(out, ")")
+            },
+            Expr::Return => write!(out, "return;"),
+            Expr::ReturnValue(value) => {
+                *out += "return ";
+                self.write_rust(out, value, addr, labels)?;
+                write!(out, ";")
+            },
+            Expr::Store(dest, src) => {
+                match self.get(dest) {
+                    Expr::Dereference(s, ptr) => {
+                        *out += "*(";
+                        self.write_rust(out, ptr, addr, labels)?;
+                        write!(out, " as *mut {}) = ", rust_int_type(s))?;
+                        self.write_rust(out, src, addr, labels)?;
+                        write!(out, " as {};", rust_int_type(s))
+                    },
+                    _ => {
+                        self.write_rust(out, dest, addr, labels)?;
+                        *out += " = ";
+                        self.write_rust(out, src, addr, labels)?;
+                        write!(out, ";")
+                    },
+                }
+            },
+            Expr::Nop => write!(out, "()"),
+            Expr::Group(group) => {
+                for (i, expr) in self.list(group).into_iter().enumerate() {
+                    if i != 0 {
+                        *out += " ";
+                    }
+                    self.write_rust_stmt(out, expr, addr, labels)?;
+                }
+                Ok(())
+            },
+        }
+    }
+
+    // A statement, terminated with a semicolon unless it already ends in one or a block.
+    fn write_rust_stmt(&self, out: &mut String, id: ExprId, addr: u64, labels: &BTreeSet<u64>) -> std::fmt::Result {
+        self.write_rust(out, id, addr, labels)?;
+        if !out.ends_with(';') && !out.ends_with('}') {
+            *out += ";";
+        }
+        Ok(())
+    }
+
+    // Registers assigned anywhere in the expression.
+    fn stored_registers(&self, id: ExprId, regs: &mut Vec<Reg>) {
+        match self.get(id) {
+            Expr::Store(dest, _) => if let Expr::Register(r) = self.get(dest) {
+                if !regs.contains(&r) {
+                    regs.push(r);
+                }
+            },
+            Expr::If(_, body) => self.stored_registers(body, regs),
+            Expr::Group(group) => {
+                for expr in self.list(group) {
+                    self.stored_registers(expr, regs);
+                }
+            },
+            _ => (),
+        }
     }
 }
 
-struct ExprList {
-    exprs: Vec<Expr>,
+// One decompiled statement, as print_json writes it and serde serializes it.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StatementRecord {
+    pub function: String,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_hex"))]
+    pub addr: u64,
+    pub statement: String,
+}
+
+impl StatementRecord {
+    pub fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("function".to_string(), Value::String(self.function.clone())),
+            ("addr".to_string(), Value::hex(self.addr)),
+            ("statement".to_string(), Value::String(self.statement.clone())),
+        ])
+    }
 }
 
 pub struct Decomp {
-    disassembly: Disassembly,
+    // Shared by the functions of a program decompiled one by one.
+    disassembly: Rc<Disassembly>,
     dest_lang: Language,
-    expr_list: Vec<Expr>,
+    name: String,
+    arena: ExprArena,
+    expr_list: Vec<ExprId>,
+    // Source instruction text, keyed by the index of the first expression it lifted to.
+    source: Vec<(usize, String)>,
+    // Address of the instruction each expression was lifted from.
+    addresses: Vec<u64>,
 }
 
 impl Decomp {
-    pub fn print(&self) -> String {
-        let addr = if let Some(section) = self.disassembly.program().section_table.get(&self.disassembly.section().section_name) {
-            section.addr
-        } else {
-            0
+    // User comments on the instructions lifted to statement i: the ones from
+    // after the previous statement's instruction up to its own.
+    fn comments_before<'a>(&self, comments: &BTreeMap<u64, &'a str>, i: usize) -> Vec<&'a str> {
+        let addr = self.addresses[i];
+        let lines = match i.checked_sub(1).map(|p| self.addresses[p]) {
+            Some(prev) if prev == addr => return Vec::new(),
+            Some(prev) if prev < addr => comments.range(prev + 1..=addr),
+            _ => comments.range(addr..=addr),
         };
-        let mut out = format!("fn sub_{:08x}:\n", addr);
-        for expr in self.expr_list.as_slice() {
-            out += format!("    {}\n", expr.print(0, self.dest_lang)).as_str();
+        lines.map(|(_, comment)| *comment).collect()
+    }
+
+    fn sorted_comments(&self) -> BTreeMap<u64, &str> {
+        self.disassembly.program().comments.iter().map(|(addr, comment)| (*addr, comment.as_str())).collect()
+    }
+
+    // With interleave set, each source instruction is printed as a comment above the
+    // statements it was lifted to.
+    pub fn print(&self, interleave: bool) -> String {
+        if let Language::Rust = self.dest_lang {
+            return self.print_rust(interleave)
+        }
+        let mut out = format!("fn {}:\n", self.name);
+        let comments = self.sorted_comments();
+        let mut source = self.source.iter().peekable();
+        for (i, expr) in self.expr_list.iter().enumerate() {
+            while let Some((_, text)) = source.next_if(|(idx, _)| *idx <= i) {
+                if interleave {
+                    out += format!("    // {}\n", text).as_str();
+                }
+            }
+            for comment in self.comments_before(&comments, i) {
+                out += format!("    // {}\n", comment).as_str();
+            }
+            out += "    ";
+            self.arena.write(&mut out, *expr, 0, self.dest_lang);
+            out += "\n";
+        }
+        if interleave {
+            for (_, text) in source {
+                out += format!("    // {}\n", text).as_str();
+            }
+        }
+        out
+    }
+
+    // Straight-line code is printed as-is. When there are jumps within the function,
+    // the body becomes a `loop { match label { ... } }` state machine, with one arm
+    // per jump target.
+    fn print_rust(&self, interleave: bool) -> String {
+        let a = &self.arena;
+        let starts: HashSet<u64> = self.addresses.iter().copied().collect();
+        let mut labels = BTreeSet::<u64>::new();
+        for (expr, addr) in self.expr_list.iter().zip(&self.addresses) {
+            let mut stack = vec![*expr];
+            while let Some(id) = stack.pop() {
+                match a.get(id) {
+                    Expr::Goto(target) => if let Expr::Constant(rel) = a.get(target) {
+                        let target = addr.wrapping_add(rel as u64);
+                        if starts.contains(&target) {
+                            labels.insert(target);
+                        }
+                    },
+                    Expr::If(_, body) => stack.push(body),
+                    Expr::Group(group) => stack.extend(a.list(group)),
+                    _ => (),
+                }
+            }
+        }
+        if !labels.is_empty() {
+            if let Some(first) = self.addresses.first() {
+                labels.insert(*first);
+            }
+        }
+
+        // Registers first assigned by a top-level statement in straight-line code are
+        // bound with `let mut` there; the rest are declared up front.
+        let mut declared = Vec::<Reg>::new();
+        let mut inline_lets = HashSet::<usize>::new();
+        let mut inline_regs = HashSet::<Reg>::new();
+        for (i, expr) in self.expr_list.iter().enumerate() {
+            let mut regs = Vec::new();
+            a.stored_registers(*expr, &mut regs);
+            for r in regs {
+                if declared.contains(&r) {
+                    continue;
+                }
+                declared.push(r);
+                if labels.is_empty() && matches!(a.get(*expr), Expr::Store(dest, _) if a.get(dest) == Expr::Register(r)) {
+                    inline_lets.insert(i);
+                    inline_regs.insert(r);
+                }
+            }
+        }
+
+        let mut out = format!("unsafe fn {}() {{\n", self.name);
+        for r in declared.iter().filter(|r| !inline_regs.contains(*r)) {
+            out += format!("    let mut {}: u64;\n", r).as_str();
+        }
+        let indent = if labels.is_empty() { "    " } else { "                " };
+        if !labels.is_empty() {
+            out += format!("    let mut label: u64 = {:#x};\n    loop {{\n        match label {{\n", labels.first().unwrap_or(&0)).as_str();
+        }
+        let comments = self.sorted_comments();
+        let mut source = self.source.iter().peekable();
+        let mut open_arm = false;
+        for (i, (expr, addr)) in self.expr_list.iter().zip(&self.addresses).enumerate() {
+            if labels.contains(addr) && (i == 0 || self.addresses[i - 1] != *addr) {
+                if open_arm {
+                    out += format!("{}label = {:#x};\n            }}\n", indent, addr).as_str();
+                }
+                out += format!("            {:#x} => {{\n", addr).as_str();
+                open_arm = true;
+            }
+            while let Some((_, text)) = source.next_if(|(idx, _)| *idx <= i) {
+                if interleave {
+                    out += format!("{}// {}\n", indent, text).as_str();
+                }
+            }
+            for comment in self.comments_before(&comments, i) {
+                out += format!("{}// {}\n", indent, comment).as_str();
+            }
+            out += indent;
+            if inline_lets.contains(&i) {
+                out += "let mut ";
+            }
+            let _ = a.write_rust_stmt(&mut out, *expr, *addr, &labels);
+            out += "\n";
         }
+        if interleave {
+            for (_, text) in source {
+                out += format!("{}// {}\n", indent, text).as_str();
+            }
+        }
+        if open_arm {
+            if !matches!(self.expr_list.last().map(|e| a.get(*e)), Some(Expr::Return | Expr::ReturnValue(_))) {
+                out += format!("{}return;\n", indent).as_str();
+            }
+            out += format!("            }}\n            _ => unreachable!(),\n        }}\n    }}\n").as_str();
+        }
+        out += "}\n";
         out
     }
+
+    pub fn disassembly(&self) -> &Disassembly {
+        &self.disassembly
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // The lifted statements, with the address of the instruction each came from.
+    pub(crate) fn statements(&self) -> (&ExprArena, &[ExprId], &[u64]) {
+        (&self.arena, &self.expr_list, &self.addresses)
+    }
+
+    // One JSON object per line for each statement, with the function and address
+    // of the instruction it was lifted from.
+    // Each statement with the function and address it was lifted from.
+    pub fn records(&self) -> Vec<StatementRecord> {
+        let program = self.disassembly.program();
+        self.expr_list.iter().enumerate().map(|(i, expr)| {
+            let addr = self.addresses[i];
+            let mut statement = String::new();
+            self.arena.write(&mut statement, *expr, 0, self.dest_lang);
+            let function = program.function_at(addr).map_or(self.name.clone(), |sym| sym.name.clone());
+            StatementRecord { function, addr, statement }
+        }).collect()
+    }
+
+    // One JSON object per line for each statement.
+    pub fn print_json(&self) -> String {
+        let mut out = String::new();
+        for record in self.records() {
+            record.to_json().write(&mut out);
+            out += "\n";
+        }
+        out
+    }
+
+    pub fn stats(&self) -> String {
+        format!("{} statements, {} expression nodes ({} KiB)", self.expr_list.len(), self.arena.len(), self.arena.size_in_bytes() / 1024)
+    }
+}
+
+// Decompiler output serializes as its statement records.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Decomp {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.records())
+    }
 }
 
 struct ChangeList {
@@ -145,113 +922,237 @@ impl ChangeList {
     }
 }
 
-fn expr_register(r: &'static str) -> Box<Expr> {
-    Box::new(Expr::Register(r))
+fn shift_op(shift: u8) -> u8 {
+    match shift {
+        dis::SHIFT_LSL => OP_SHL,
+        dis::SHIFT_LSR => OP_SHR,
+        dis::SHIFT_ASR => OP_SAR,
+        _ => OP_ROR,
+    }
 }
 
-fn expr_constant(i: i64) -> Box<Expr> {
-    Box::new(Expr::Constant(i))
+fn memory_base(op: &dis::Operand) -> Option<Reg> {
+    match *op {
+        dis::Operand::Memory(base, _, _, _, _) => base,
+        _ => None,
+    }
 }
 
-fn expr_binary(op: u8, lhs: Box<Expr>, rhs: Box<Expr>) -> Box<Expr> {
-    Box::new(Expr::Binary(op, lhs, rhs))
+fn memory_size(op: &dis::Operand) -> u8 {
+    match *op {
+        dis::Operand::Memory(_, _, _, _, size) => size,
+        _ => 0,
+    }
 }
 
-fn expr_dereference(size: u8, rhs: Box<Expr>) -> Box<Expr> {
-    Box::new(Expr::Dereference(size, rhs))
+fn operand_to_expr(a: &ExprArena, op: &dis::Operand) -> ExprId {
+    match *op {
+        dis::Operand::Memory(r1, r2, scale, offset, size) => {
+            // Build base + index * scale + offset, leaving out any terms that aren't present.
+            let base = r1.map(|r| a.register(r));
+            let index = match (r2, scale) {
+                (None, _) => None,
+                (Some(r2), 0 | 1) => Some(a.register(r2)),
+                (Some(r2), _) => Some(a.binary(OP_MUL, a.register(r2), a.constant(scale.into()))),
+            };
+            let addr = match (base, index) {
+                (Some(b), Some(i)) => Some(a.binary(OP_ADD, b, i)),
+                (b, i) => b.or(i),
+            };
+            let addr = match addr {
+                Some(addr) if offset == 0 => addr,
+                Some(addr) => a.binary(OP_ADD, addr, a.constant(offset)),
+                None => a.constant(offset),
+            };
+            a.dereference(size, addr)
+        },
+        dis::Operand::Register(r) => a.register(r),
+        dis::Operand::Immediate(i) => a.constant(i),
+        dis::Operand::ShiftedRegister(r, dis::SHIFT_ROR, 0) => a.intrinsic("__rrx", &[a.register(r)]),
+        dis::Operand::ShiftedRegister(r, shift, amount) => a.binary(shift_op(shift), a.register(r), a.constant(amount)),
+        dis::Operand::RegisterShiftedRegister(r, shift, amount) => a.binary(shift_op(shift), a.register(r), a.register(amount)),
+        dis::Operand::Nothing => a.intrinsic("__unknown", &[]),
+    }
 }
 
-fn expr_store(dest: Box<Expr>, src: Box<Expr>) -> Box<Expr> {
-    Box::new(Expr::Store(dest, src))
+// The most recent definition of the condition flags, kept symbolically so a
+// later conditional branch can be rewritten as a comparison of the operands.
+#[derive(Clone, Copy)]
+enum FlagsDef {
+    Compare(ExprId, ExprId), // flags = lhs - rhs (cmp, subs)
+    Test(ExprId, ExprId),    // flags = lhs & rhs (test, tst)
+    Result(ExprId),          // flags = result of an arithmetic op
+    Subtract(ExprId, ExprId, ExprId), // flags = result of lhs - rhs (sub, subs)
 }
 
-fn expr_group(group: Vec<Box<Expr>>) -> Box<Expr> {
-    Box::new(Expr::Group(group))
+fn cond_name(cond: u64) -> &'static str {
+    match cond {
+        dis::COND_EQ => "eq",
+        dis::COND_NE => "ne",
+        dis::COND_LT => "lt",
+        dis::COND_LE => "le",
+        dis::COND_GT => "gt",
+        dis::COND_GE => "ge",
+        dis::COND_LTU => "ltu",
+        dis::COND_LEU => "leu",
+        dis::COND_GTU => "gtu",
+        dis::COND_GEU => "geu",
+        dis::COND_MI => "mi",
+        dis::COND_PL => "pl",
+        dis::COND_VS => "overflow",
+        dis::COND_VC => "!overflow",
+        dis::COND_PE => "parity",
+        dis::COND_PO => "!parity",
+        _ => "al",
+    }
 }
 
-fn expr_nop() -> Box<Expr> {
-    Box::new(Expr::Nop)
+// Condition on lhs - rhs.
+fn compare_condition(a: &ExprArena, cond: u64, lhs: ExprId, rhs: ExprId) -> ExprId {
+    match cond {
+        dis::COND_EQ => a.binary(OP_EQ, lhs, rhs),
+        dis::COND_NE => a.binary(OP_NE, lhs, rhs),
+        dis::COND_LT => a.binary(OP_LT, lhs, rhs),
+        dis::COND_LE => a.binary(OP_LE, lhs, rhs),
+        dis::COND_GT => a.binary(OP_GT, lhs, rhs),
+        dis::COND_GE => a.binary(OP_GE, lhs, rhs),
+        dis::COND_LTU => a.binary(OP_LTU, lhs, rhs),
+        dis::COND_LEU => a.binary(OP_LEU, lhs, rhs),
+        dis::COND_GTU => a.binary(OP_GTU, lhs, rhs),
+        dis::COND_GEU => a.binary(OP_GEU, lhs, rhs),
+        dis::COND_MI => a.binary(OP_LT, a.binary(OP_SUB, lhs, rhs), a.constant(0)),
+        dis::COND_PL => a.binary(OP_GE, a.binary(OP_SUB, lhs, rhs), a.constant(0)),
+        dis::COND_AL => a.constant(1),
+        _ => a.flag(cond_name(cond)),
+    }
 }
 
-fn expr_ret() -> Box<Expr> {
-    Box::new(Expr::Return)
+// Condition on a value compared against zero. `logical` is set when the carry
+// and overflow flags are known to be cleared (test, and, or, xor).
+fn zero_condition(a: &ExprArena, cond: u64, value: ExprId, logical: bool) -> ExprId {
+    match cond {
+        dis::COND_EQ => a.binary(OP_EQ, value, a.constant(0)),
+        dis::COND_NE => a.binary(OP_NE, value, a.constant(0)),
+        dis::COND_LT | dis::COND_MI => a.binary(OP_LT, value, a.constant(0)),
+        dis::COND_GE | dis::COND_PL => a.binary(OP_GE, value, a.constant(0)),
+        dis::COND_LE => a.binary(OP_LE, value, a.constant(0)),
+        dis::COND_GT => a.binary(OP_GT, value, a.constant(0)),
+        dis::COND_LTU | dis::COND_VS if logical => a.constant(0),
+        dis::COND_GEU | dis::COND_VC if logical => a.constant(1),
+        dis::COND_LEU if logical => a.binary(OP_EQ, value, a.constant(0)),
+        dis::COND_GTU if logical => a.binary(OP_NE, value, a.constant(0)),
+        dis::COND_AL => a.constant(1),
+        _ => a.flag(cond_name(cond)),
+    }
 }
 
-fn operand_to_expr(op: &dis::Operand) -> Box<Expr> {
-    match *op {
-        dis::Operand::Memory(r1, r2, offset, size) => {
-            if r1 == "" {
-                expr_dereference(size, 
-                    expr_binary(OP_ADD, expr_register(r1), expr_constant(offset))
-                )
-            }
-            else if r1 == "." {
-                expr_dereference( size, 
-                    expr_binary(OP_ADD, expr_register("pc"), expr_constant(offset))
-                )
-            }
-            else if r2 != "" {
-                expr_dereference(size, 
-                    expr_binary(OP_ADD, 
-                        expr_binary(OP_ADD,
-                            expr_register(r1), 
-                            expr_binary( OP_MUL, 
-                                expr_register(r2), 
-                                expr_constant(size.into())
-                            )
-                        ),
-                        expr_constant(offset)
-                    )
-                )
-            }
-            else {
-                expr_dereference(size, 
-                    expr_binary(OP_ADD, expr_register(r1), expr_constant(offset))
-                )
-            }
+fn flags_condition(a: &ExprArena, flags: Option<FlagsDef>, cond: u64) -> ExprId {
+    match flags {
+        Some(FlagsDef::Compare(lhs, rhs)) => compare_condition(a, cond, lhs, rhs),
+        Some(FlagsDef::Test(lhs, rhs)) => {
+            let value = if a.equal(lhs, rhs) { lhs } else { a.binary(OP_AND, lhs, rhs) };
+            zero_condition(a, cond, value, true)
         },
-        dis::Operand::Register(r) => expr_register(r),
-        dis::Operand::Immediate(i) => expr_constant(i),
-        _ => todo!("finish this")
+        Some(FlagsDef::Result(value)) => zero_condition(a, cond, value, false),
+        // Equality only needs the result. The ordered conditions compare the
+        // operands, recovering one the result overwrote: x86 `sub a, b` leaves
+        // the old a as result + b.
+        Some(FlagsDef::Subtract(result, _, _)) if cond == dis::COND_EQ || cond == dis::COND_NE => zero_condition(a, cond, result, false),
+        Some(FlagsDef::Subtract(result, lhs, rhs)) if a.equal(result, lhs) => compare_condition(a, cond, a.binary(OP_ADD, result, rhs), rhs),
+        Some(FlagsDef::Subtract(result, lhs, rhs)) if a.equal(result, rhs) => compare_condition(a, cond, lhs, a.binary(OP_SUB, lhs, result)),
+        Some(FlagsDef::Subtract(_, lhs, rhs)) => compare_condition(a, cond, lhs, rhs),
+        None => a.flag(cond_name(cond)),
     }
 }
 
-struct ExprBuilder {
+// Where arguments to a called function are passed. Arguments past the register
+// list are read from the stack, starting stack_offset bytes above the stack pointer.
+struct CallConv {
+    args: &'static [Reg],
+    ret: Reg,
+    stack_offset: i64,
+}
+
+static CONV_CDECL: CallConv = CallConv { args: &[], ret: Reg::EAX, stack_offset: 0 };
+static CONV_SYSV_AMD64: CallConv = CallConv { args: &[Reg::RDI, Reg::RSI, Reg::RDX, Reg::RCX, Reg::R8, Reg::R9], ret: Reg::RAX, stack_offset: 0 };
+static CONV_WIN64: CallConv = CallConv { args: &[Reg::RCX, Reg::RDX, Reg::R8, Reg::R9], ret: Reg::RAX, stack_offset: 32 };
+static CONV_AAPCS: CallConv = CallConv { args: &[Reg::arm(0), Reg::arm(1), Reg::arm(2), Reg::arm(3)], ret: Reg::arm(0), stack_offset: 0 };
+static CONV_AAPCS64: CallConv = CallConv {
+    args: &[Reg::aarch64(0), Reg::aarch64(1), Reg::aarch64(2), Reg::aarch64(3), Reg::aarch64(4), Reg::aarch64(5), Reg::aarch64(6), Reg::aarch64(7)],
+    ret: Reg::aarch64(0),
+    stack_offset: 0,
+};
+static CONV_RISCV: CallConv = CallConv {
+    args: &[Reg::RISCV_A0, Reg::RISCV_A1, Reg::RISCV_A2, Reg::RISCV_A3, Reg::RISCV_A4, Reg::RISCV_A5, Reg::RISCV_A6, Reg::RISCV_A7],
+    ret: Reg::RISCV_A0,
+    stack_offset: 0,
+};
+
+fn call_conv(program: &Program) -> &'static CallConv {
+    match program.machine_type.as_str() {
+        "amd64" if program.format == "pe" => &CONV_WIN64,
+        "amd64" => &CONV_SYSV_AMD64,
+        "arm" => &CONV_AAPCS,
+        "aarch64" => &CONV_AAPCS64,
+        "riscv" | "riscv32" | "riscv64" => &CONV_RISCV,
+        _ => &CONV_CDECL,
+    }
+}
+
+struct ExprBuilder<'a> {
+    arena: &'a ExprArena,
     next_id: u64,
-    change_lists: HashMap<&'static str, ChangeList>,
+    change_lists: HashMap<Reg, ChangeList>,
+    flags: Option<FlagsDef>,
+    // Registers last set to a known constant. x86 sub-registers share the
+    // constant of the full register they belong to.
+    constants: HashMap<Reg, i64>,
+    machine_type: &'a str,
+    protos: &'a PrototypeDb,
+    call_conv: &'static CallConv,
+    symbols: SymbolIndex<'a>,
+    // PLT stubs and import slots (GOT/IAT) by address, with the imported function's name.
+    stubs: HashMap<u64, String>,
+    slots: HashMap<u64, String>,
+    // Address and size of the instruction being lifted.
+    address: u64,
+    size: usize,
+    stack_pointer: Reg,
+    word_size: u8,
 }
 
-impl ExprBuilder {
-    fn add_change_list_if_not_created(&mut self, s: &'static str) {
+impl<'a> ExprBuilder<'a> {
+    fn add_change_list_if_not_created(&mut self, s: Reg) {
         if !self.change_lists.contains_key(&s) {
             self.change_lists.insert(s, ChangeList { uses: vec![], stores: vec![], loads: vec![], last_store: 0, last_load: 0 });
         }
     }
 
-    fn add_register_store(&mut self, s: &'static str) {
+    fn add_register_store(&mut self, s: Reg) {
         self.add_change_list_if_not_created(s);
-        self.change_lists.get_mut(s).expect("").add_store(self.next_id);
+        self.change_lists.get_mut(&s).expect("").add_store(self.next_id);
     }
 
-    fn add_register_use(&mut self, s: &'static str) {
+    fn add_register_use(&mut self, s: Reg) {
         self.add_change_list_if_not_created(s);
-        self.change_lists.get_mut(s).expect("").add_use(self.next_id);
+        self.change_lists.get_mut(&s).expect("").add_use(self.next_id);
     }
 
-    fn create_uses_in_expr(&mut self, expr: &Expr) {
-        match expr {
+    fn create_uses_in_expr(&mut self, expr: ExprId) {
+        let a = self.arena;
+        match a.get(expr) {
             Expr::Store(dest, src) => {
-                match **dest {
+                match a.get(dest) {
                     Expr::Register(r) => self.add_register_store(r),
                     _ => (),
                 };
-                match **src {
+                match a.get(src) {
                     Expr::Register(r) => self.add_register_use(r),
                     _ => (),
                 };
             },
             Expr::Group(group) => {
-                for expr in group {
+                for expr in a.list(group) {
                     self.create_uses_in_expr(expr);
                 }
             },
@@ -259,93 +1160,539 @@ impl ExprBuilder {
         }
     }
 
-    fn decomp_instruction(&mut self, ins: &Instruction, expr_list: &Vec<Expr>) -> Expr {
-        match ins.opcode {
+    fn constant_value(&self, expr: ExprId) -> Option<i64> {
+        let a = self.arena;
+        match a.get(expr) {
+            Expr::Constant(i) => Some(i),
+            Expr::Register(r) => self.constants.get(&r.full()).copied(),
+            Expr::Binary(OP_XOR, lhs, rhs) if a.equal(lhs, rhs) => Some(0),
+            _ => None,
+        }
+    }
+
+    // Records constants written to registers. A conditional store only invalidates.
+    fn track_constants(&mut self, expr: ExprId, conditional: bool) {
+        let a = self.arena;
+        match a.get(expr) {
+            Expr::Store(dest, src) => if let Expr::Register(r) = a.get(dest) {
+                let key = r.full();
+                match self.constant_value(src) {
+                    Some(value) if !conditional => { self.constants.insert(key, value); },
+                    _ => { self.constants.remove(&key); },
+                }
+            },
+            Expr::Group(group) => {
+                for expr in a.list(group) {
+                    self.track_constants(expr, conditional);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    // Names the system call when its number is known, passing the ABI's argument
+    // registers (or their known constant values): `rax = sys_write(1, rsi, rdx)`.
+    fn decomp_syscall(&self, svc: Option<i64>) -> ExprId {
+        let a = self.arena;
+        let fallback = match svc {
+            Some(imm) => a.intrinsic("__svc", &[a.constant(imm)]),
+            None => a.intrinsic("__syscall", &[]),
+        };
+        let abi = match syscall::syscall_abi(self.machine_type, svc) {
+            Some(abi) => abi,
+            None => return fallback,
+        };
+        let number = match svc.and_then(syscall::arm_oabi_number) {
+            Some(number) if self.machine_type == "arm" => Some(number),
+            _ => self.constants.get(&abi.number.full()).copied(),
+        };
+        let sys = match number.and_then(|n| abi.lookup(n)) {
+            Some(sys) => sys,
+            None => return fallback,
+        };
+        let args: Vec<ExprId> = abi.args.iter().take(sys.argc as usize).map(|r| {
+            match self.constants.get(&r.full()) {
+                Some(value) => a.constant(*value),
+                None => a.register(*r),
+            }
+        }).collect();
+        a.store(a.register(abi.ret), a.intrinsic(sys.name, &args))
+    }
+
+    // A jump to the start of a function other than the one being lifted is a tail call.
+    fn tail_call_target(&self, target: u64) -> Option<&str> {
+        let callee = self.function_name(target)?;
+        if self.symbols.nearest_function(self.address).is_some_and(|current| current.addr == target) {
+            return None
+        }
+        Some(callee)
+    }
+
+    // Name of the function at an address, either a symbol or the import a PLT stub jumps to.
+    fn function_name(&self, addr: u64) -> Option<&str> {
+        match self.symbols.function_starting_at(addr) {
+            Some(sym) => Some(sym.name.as_str()),
+            None => self.stubs.get(&addr).map(|name| name.as_str()),
+        }
+    }
+
+    // Calls are named the way `calls` resolves them, directly or through a PLT stub
+    // or import slot, and given arguments when the function has a known prototype:
+    // `rax = memcpy(dest: rdi, src: rsi, n: rdx)`. Other direct calls go to their
+    // absolute address.
+    fn decomp_call(&self, target: &dis::Operand) -> ExprId {
+        let a = self.arena;
+        let name = match *target {
+            dis::Operand::Immediate(rel) => {
+                let addr = self.address.wrapping_add(rel as u64);
+                match self.function_name(addr) {
+                    Some(name) => name,
+                    None => return a.call(a.constant(addr as i64), &[]),
+                }
+            },
+            _ => match xref::memory_target(target, self.address, self.size).and_then(|slot| self.slots.get(&slot)) {
+                Some(name) => name.as_str(),
+                None => return a.call(operand_to_expr(a, target), &[]),
+            },
+        };
+        let proto = match self.protos.get(name) {
+            Some(proto) => proto,
+            None => return a.call(a.symbol(name), &[]),
+        };
+        let conv = self.call_conv;
+        let sp = a.register(self.stack_pointer);
+        let args: Vec<ExprId> = proto.params.iter().enumerate().map(|(i, param)| {
+            let value = match conv.args.get(i) {
+                Some(reg) => match self.constants.get(&reg.full()) {
+                    Some(value) => a.constant(*value),
+                    None => a.register(*reg),
+                },
+                None => {
+                    let offset = conv.stack_offset + (i - conv.args.len()) as i64 * self.word_size as i64;
+                    let addr = if offset == 0 { sp } else { a.binary(OP_ADD, sp, a.constant(offset)) };
+                    a.dereference(self.word_size, addr)
+                },
+            };
+            a.argument(param, value)
+        }).collect();
+        let call = a.call(a.symbol(name), &args);
+        if proto.returns_void {
+            call
+        } else {
+            a.store(a.register(conv.ret), call)
+        }
+    }
+
+    // Loads and stores with optional base register writeback.
+    fn decomp_load_store(&self, ins: &Instruction, load: bool, signed: bool) -> ExprId {
+        let a = self.arena;
+        let reg = operand_to_expr(a, &ins.operands[0]);
+        let mem = &ins.operands[1];
+        let base = memory_base(mem).map_or_else(|| a.constant(0), |r| a.register(r));
+        let offset_op = if ins.flags & dis::FLAG_SUBTRACT_OFFSET != 0 { OP_SUB } else { OP_ADD };
+        let access = if ins.flags & (dis::FLAG_PRE_INDEX | dis::FLAG_POST_INDEX) != 0 {
+            a.dereference(memory_size(mem), base)
+        } else if ins.flags & dis::FLAG_REGISTER_OFFSET != 0 {
+            a.dereference(memory_size(mem), a.binary(offset_op, base, operand_to_expr(a, &ins.operands[2])))
+        } else {
+            operand_to_expr(a, mem)
+        };
+        let access = if load && signed { a.unary(OP_SEXT, access) } else { access };
+        let transfer = if load { a.store(reg, access) } else { a.store(access, reg) };
+        if ins.flags & dis::FLAG_PRE_INDEX != 0 {
+            let writeback = a.store(base, a.binary(offset_op, base, operand_to_expr(a, &ins.operands[2])));
+            a.group(&[writeback, transfer])
+        } else if ins.flags & dis::FLAG_POST_INDEX != 0 {
+            let writeback = a.store(base, a.binary(offset_op, base, operand_to_expr(a, &ins.operands[2])));
+            a.group(&[transfer, writeback])
+        } else {
+            transfer
+        }
+    }
+
+    // Block transfers: base, first offset, writeback amount, registers...
+    fn decomp_load_store_multiple(&self, ins: &Instruction, load: bool) -> ExprId {
+        let a = self.arena;
+        let base = operand_to_expr(a, &ins.operands[0]);
+        let first = match ins.operands[1] { dis::Operand::Immediate(i) => i, _ => 0 };
+        let writeback = match ins.operands[2] { dis::Operand::Immediate(i) => i, _ => 0 };
+        let mut group = Vec::<ExprId>::new();
+        let mut returns = false;
+        for (i, reg) in ins.operands[3..].iter().enumerate() {
+            let addr = a.dereference(self.word_size, a.binary(OP_ADD, base, a.constant(first + i as i64 * self.word_size as i64)));
+            if load && matches!(reg, dis::Operand::Register(r) if r.class() == RegClass::ProgramCounter) {
+                returns = true;
+            }
+            else if load {
+                group.push(a.store(operand_to_expr(a, reg), addr));
+            }
+            else {
+                group.push(a.store(addr, operand_to_expr(a, reg)));
+            }
+        }
+        if writeback != 0 {
+            group.push(a.store(base, a.binary(OP_ADD, base, a.constant(writeback))));
+        }
+        if returns {
+            group.push(a.ret());
+        }
+        a.group(&group)
+    }
+
+    fn decomp_instruction(&mut self, ins: &Instruction, _expr_list: &[ExprId]) -> Option<ExprId> {
+        let a = self.arena;
+        // Conditionally executed instructions test the flags as they were before the instruction.
+        let guard = if ins.cond() != dis::COND_AL && ins.opcode != "b" && ins.opcode != "set" {
+            Some(flags_condition(a, self.flags, ins.cond()))
+        } else {
+            None
+        };
+        let expr = match ins.opcode {
             "add" => { // op0 = op1 + op2
                 let dest = &ins.operands[0];
                 let src1 = &ins.operands[1];
                 let src2 = &ins.operands[2];
-                let expr = expr_binary(OP_ADD, 
-                    operand_to_expr(src1), operand_to_expr(src2));
-                *expr_store(operand_to_expr(dest), expr)
+                let expr = a.binary(OP_ADD, 
+                    operand_to_expr(a, src1), operand_to_expr(a, src2));
+                a.store(operand_to_expr(a, dest), expr)
             },
             "sub" => { // op0 = op1 - op2
                 let dest = &ins.operands[0];
                 let src1 = &ins.operands[1];
                 let src2 = &ins.operands[2];
-                let expr = expr_binary(OP_SUB, 
-                    operand_to_expr(src1), operand_to_expr(src2));
-                *expr_store(operand_to_expr(dest), expr)
+                let expr = a.binary(OP_SUB, 
+                    operand_to_expr(a, src1), operand_to_expr(a, src2));
+                a.store(operand_to_expr(a, dest), expr)
             },
             "and" => { // op0 = op1 & op2
                 let dest = &ins.operands[0];
                 let src1 = &ins.operands[1];
                 let src2 = &ins.operands[2];
-                let expr = expr_binary(OP_AND, 
-                    operand_to_expr(src1), operand_to_expr(src2));
-                *expr_store(operand_to_expr(dest), expr)
+                let expr = a.binary(OP_AND, 
+                    operand_to_expr(a, src1), operand_to_expr(a, src2));
+                a.store(operand_to_expr(a, dest), expr)
+            },
+            "or" => { // op0 = op1 | op2
+                let dest = &ins.operands[0];
+                let src1 = &ins.operands[1];
+                let src2 = &ins.operands[2];
+                let expr = a.binary(OP_OR, 
+                    operand_to_expr(a, src1), operand_to_expr(a, src2));
+                a.store(operand_to_expr(a, dest), expr)
             },
             "xor" => { // op0 = op1 ^ op2
                 let dest = &ins.operands[0];
                 let src1 = &ins.operands[1];
                 let src2 = &ins.operands[2];
-                let expr = expr_binary(OP_XOR, 
-                    operand_to_expr(src1), operand_to_expr(src2));
-                *expr_store(operand_to_expr(dest), expr)
+                let expr = a.binary(OP_XOR, 
+                    operand_to_expr(a, src1), operand_to_expr(a, src2));
+                a.store(operand_to_expr(a, dest), expr)
+            },
+            "adc" => { // op0 = op1 + op2 + carry
+                let expr = a.binary(OP_ADD, 
+                    a.binary(OP_ADD, operand_to_expr(a, &ins.operands[1]), operand_to_expr(a, &ins.operands[2])), a.flag("carry"));
+                a.store(operand_to_expr(a, &ins.operands[0]), expr)
+            },
+            "sbc" => { // op0 = op1 - op2 - !carry
+                let expr = a.binary(OP_SUB, 
+                    a.binary(OP_SUB, operand_to_expr(a, &ins.operands[1]), operand_to_expr(a, &ins.operands[2])), a.unary(OP_NOT, a.flag("carry")));
+                a.store(operand_to_expr(a, &ins.operands[0]), expr)
+            },
+            "sbb" => { // op0 = op1 - op2 - borrow
+                let expr = a.binary(OP_SUB, 
+                    a.binary(OP_SUB, operand_to_expr(a, &ins.operands[1]), operand_to_expr(a, &ins.operands[2])), a.flag("carry"));
+                a.store(operand_to_expr(a, &ins.operands[0]), expr)
+            },
+            "bic" => { // op0 = op1 & ~op2
+                let expr = a.binary(OP_AND, 
+                    operand_to_expr(a, &ins.operands[1]), a.unary(OP_INV, operand_to_expr(a, &ins.operands[2])));
+                a.store(operand_to_expr(a, &ins.operands[0]), expr)
+            },
+            "not" => { // op0 = ~op1
+                a.store(operand_to_expr(a, &ins.operands[0]), a.unary(OP_INV, operand_to_expr(a, &ins.operands[1])))
+            },
+            "mul" => { // op0 = op1 * op2
+                let expr = a.binary(OP_MUL, 
+                    operand_to_expr(a, &ins.operands[1]), operand_to_expr(a, &ins.operands[2]));
+                a.store(operand_to_expr(a, &ins.operands[0]), expr)
+            },
+            "mla" => { // op0 = op1 * op2 + op3
+                let expr = a.binary(OP_ADD, 
+                    a.binary(OP_MUL, operand_to_expr(a, &ins.operands[1]), operand_to_expr(a, &ins.operands[2])), operand_to_expr(a, &ins.operands[3]));
+                a.store(operand_to_expr(a, &ins.operands[0]), expr)
+            },
+            "movt" => { // op0 = (op0 & 0xffff) | (op1 << 16)
+                let dest = operand_to_expr(a, &ins.operands[0]);
+                let expr = a.binary(OP_OR, 
+                    a.binary(OP_AND, dest, a.constant(0xffff)), a.binary(OP_SHL, operand_to_expr(a, &ins.operands[1]), a.constant(16)));
+                a.store(dest, expr)
+            },
+            "ldr" => self.decomp_load_store(ins, true, false),
+            "ldrs" => self.decomp_load_store(ins, true, true),
+            "str" => self.decomp_load_store(ins, false, false),
+            "ldm" => self.decomp_load_store_multiple(ins, true),
+            "stm" => self.decomp_load_store_multiple(ins, false),
+            "call" => {
+                let expr = self.decomp_call(&ins.operands[0]);
+                // The callee may clobber any register.
+                self.constants.clear();
+                expr
+            },
+            "svc" => match ins.operands[0] {
+                dis::Operand::Immediate(imm) => self.decomp_syscall(Some(imm)),
+                _ => a.intrinsic("__svc", &[operand_to_expr(a, &ins.operands[0])]),
+            },
+            "syscall" => self.decomp_syscall(None),
+            "unk" => a.intrinsic("__unknown", &[]),
+            "cmp" => { // flags = op0 - op1
+                self.flags = Some(FlagsDef::Compare(operand_to_expr(a, &ins.operands[0]), operand_to_expr(a, &ins.operands[1])));
+                return None
+            },
+            "cmn" => { // flags = op0 + op1
+                self.flags = Some(FlagsDef::Compare(operand_to_expr(a, &ins.operands[0]), a.unary(OP_NEG, operand_to_expr(a, &ins.operands[1]))));
+                return None
+            },
+            "test" | "tst" => { // flags = op0 & op1
+                self.flags = Some(FlagsDef::Test(operand_to_expr(a, &ins.operands[0]), operand_to_expr(a, &ins.operands[1])));
+                return None
+            },
+            "teq" => { // flags = op0 ^ op1
+                let value = a.binary(OP_XOR, operand_to_expr(a, &ins.operands[0]), operand_to_expr(a, &ins.operands[1]));
+                self.flags = Some(FlagsDef::Test(value, value));
+                return None
+            },
+            "b" => { // if (cond) goto op0
+                let tail_call = match ins.operands[0] {
+                    dis::Operand::Immediate(rel) => self.tail_call_target(self.address.wrapping_add(rel as u64)),
+                    _ => None,
+                };
+                let target = match tail_call {
+                    Some(name) => a.ret_value(a.call(a.symbol(name), &[])),
+                    None => a.goto(operand_to_expr(a, &ins.operands[0])),
+                };
+                if ins.cond() == dis::COND_AL {
+                    target
+                } else {
+                    a.if_(flags_condition(a, self.flags, ins.cond()), target)
+                }
+            },
+            "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" => { // if (op0 cmp op1) goto op2
+                let op = match ins.opcode {
+                    "beq" => OP_EQ,
+                    "bne" => OP_NE,
+                    "blt" => OP_LT,
+                    "bge" => OP_GE,
+                    "bltu" => OP_LTU,
+                    _ => OP_GEU,
+                };
+                let cond = a.binary(op, operand_to_expr(a, &ins.operands[0]), operand_to_expr(a, &ins.operands[1]));
+                a.if_(cond, a.goto(operand_to_expr(a, &ins.operands[2])))
+            },
+            "tbz" | "tbnz" => { // if ((op0 & (1 << op1)) cmp 0) goto op2
+                let bit = match ins.operands[1] {
+                    dis::Operand::Immediate(bit) => a.constant(1 << bit),
+                    _ => a.binary(OP_SHL, a.constant(1), operand_to_expr(a, &ins.operands[1])),
+                };
+                let op = if ins.opcode == "tbz" { OP_EQ } else { OP_NE };
+                let cond = a.binary(op, a.binary(OP_AND, operand_to_expr(a, &ins.operands[0]), bit), a.constant(0));
+                a.if_(cond, a.goto(operand_to_expr(a, &ins.operands[2])))
+            },
+            "adr" => { // op0 = pc + op1
+                a.store(operand_to_expr(a, &ins.operands[0]), a.binary(OP_ADD, a.constant(self.address as i64), operand_to_expr(a, &ins.operands[1])))
+            },
+            "adrp" => { // op0 = (pc & ~0xfff) + op1
+                a.store(operand_to_expr(a, &ins.operands[0]), a.binary(OP_ADD, a.constant((self.address & !0xfff) as i64), operand_to_expr(a, &ins.operands[1])))
             },
             "mov" => { // op0 = op1
                 let dest = &ins.operands[0];
                 let src = &ins.operands[1];
-                let out = expr_store(operand_to_expr(dest), operand_to_expr(src));
-                self.create_uses_in_expr(&out);
-                *out
-            },
-            "push" => { // sp -= size, *sp = op0
-                let op0 = &ins.operands[0];
-                let sp = dis::Operand::Register("rsp");
-                let out = expr_group(vec![
-                    expr_store(operand_to_expr(&sp), expr_binary(OP_SUB, operand_to_expr(&sp), expr_constant(8))),
-                    expr_store(expr_dereference(8, operand_to_expr(&sp)), operand_to_expr(op0)),
-                ]);
-                self.create_uses_in_expr(&out);
-                *out
-            },
-            "pop" => { // op0 = *sp, sp += size
-                let op0 = &ins.operands[0];
-                let sp = dis::Operand::Register("rsp");
-                let out = expr_group(vec![
-                    expr_store(operand_to_expr(op0), expr_dereference(8, operand_to_expr(&sp))),
-                    expr_store(operand_to_expr(&sp), expr_binary(OP_ADD, operand_to_expr(&sp), expr_constant(8))),
+                let out = a.store(operand_to_expr(a, dest), operand_to_expr(a, src));
+                self.create_uses_in_expr(out);
+                out
+            },
+            "push" => { // sp -= size * n, *(sp + size * i) = op[i]
+                let sp = a.register(self.stack_pointer);
+                let size = self.word_size;
+                let mut group = vec![
+                    a.store(sp, a.binary(OP_SUB, sp, a.constant(size as i64 * ins.operands.len() as i64))),
+                ];
+                for (i, op) in ins.operands.iter().enumerate() {
+                    let addr = if i == 0 {
+                        sp
+                    } else {
+                        a.binary(OP_ADD, sp, a.constant(size as i64 * i as i64))
+                    };
+                    group.push(a.store(a.dereference(size, addr), operand_to_expr(a, op)));
+                }
+                let out = a.group(&group);
+                self.create_uses_in_expr(out);
+                out
+            },
+            "pop" => { // op[i] = *(sp + size * i), sp += size * n
+                let sp = a.register(self.stack_pointer);
+                let size = self.word_size;
+                let mut group = Vec::<ExprId>::new();
+                let mut returns = false;
+                for (i, op) in ins.operands.iter().enumerate() {
+                    let addr = if i == 0 {
+                        sp
+                    } else {
+                        a.binary(OP_ADD, sp, a.constant(size as i64 * i as i64))
+                    };
+                    if matches!(op, dis::Operand::Register(r) if r.class() == RegClass::ProgramCounter) {
+                        returns = true;
+                    } else {
+                        group.push(a.store(operand_to_expr(a, op), a.dereference(size, addr)));
+                    }
+                }
+                group.push(a.store(sp, a.binary(OP_ADD, sp, a.constant(size as i64 * ins.operands.len() as i64))));
+                if returns {
+                    group.push(a.ret());
+                }
+                let out = a.group(&group);
+                self.create_uses_in_expr(out);
+                out
+            },
+            "leave" => { // sp = fp, fp = *sp, sp += size
+                let sp = a.register(self.stack_pointer);
+                let fp = a.register(if self.stack_pointer == Reg::RSP { Reg::RBP } else { Reg::EBP });
+                let size = self.word_size;
+                let out = a.group(&[
+                    a.store(sp, fp),
+                    a.store(fp, a.dereference(size, sp)),
+                    a.store(sp, a.binary(OP_ADD, sp, a.constant(size as i64))),
                 ]);
-                self.create_uses_in_expr(&out);
-                *out
+                self.create_uses_in_expr(out);
+                out
+            },
+            "sext" | "zext" => { // op0 = (op0 size)op1, sign- or zero-extended
+                let dest = operand_to_expr(a, &ins.operands[0]);
+                let size = a.value_size(dest).unwrap_or(self.word_size);
+                let out = a.store(dest, a.cast(size, ins.opcode == "sext", operand_to_expr(a, &ins.operands[1])));
+                self.create_uses_in_expr(out);
+                out
             },
-            "nop" => *expr_nop(),
-            "ret" => *expr_ret(),
-            _ => todo!("need to implement {} decompilation", ins.opcode)
+            "set" => { // op0 = (u8)cond
+                let cond = flags_condition(a, self.flags, ins.cond());
+                let out = a.store(operand_to_expr(a, &ins.operands[0]), a.cast(1, false, cond));
+                self.create_uses_in_expr(out);
+                out
+            },
+            "lea" => { // op0 = &op1
+                let addr = match a.get(operand_to_expr(a, &ins.operands[1])) {
+                    Expr::Dereference(_, addr) => addr,
+                    _ => a.intrinsic("__unknown", &[]),
+                };
+                let out = a.store(operand_to_expr(a, &ins.operands[0]), addr);
+                self.create_uses_in_expr(out);
+                out
+            },
+            "nop" => a.nop(),
+            "ret" => a.ret(),
+            // Anything not lifted yet is kept as a call to the instruction.
+            _ => {
+                let args: Vec<ExprId> = ins.operands.iter().map(|op| operand_to_expr(a, op)).collect();
+                a.intrinsic(ins.opcode, &args)
+            },
+        };
+        if ins.sets_flags() {
+            if let Expr::Store(dest, src) = a.get(expr) {
+                self.flags = match a.get(src) {
+                    Expr::Binary(OP_SUB, lhs, rhs) if ins.opcode == "sub" => Some(FlagsDef::Subtract(dest, lhs, rhs)),
+                    _ => Some(FlagsDef::Result(dest)),
+                };
+            }
+        }
+        self.track_constants(expr, guard.is_some());
+        match guard {
+            Some(cond) => Some(a.if_(cond, expr)),
+            None => Some(expr),
         }
     }
 }
 
-fn decomp_disassembly(dis: &Disassembly) -> Vec<Expr> {
-    let instrs = dis.section().instructions.instruction_vec();
-    let mut expr_list = Vec::<Expr>::new();
-    let mut expr_builder = ExprBuilder { change_lists: HashMap::<&str, ChangeList>::new(), next_id: 1 };
-    for instr in instrs {
-        let expr = expr_builder.decomp_instruction(&instr, &expr_list);
-        println!("{} // {}", expr.print(0, Language::Pseudocode), instr.print());
-        expr_list.push(expr);
+fn decomp_disassembly(dis: &Disassembly, section: &DisassemblySection, arena: &ExprArena, range: Range<usize>, protos: &PrototypeDb) -> (Vec<ExprId>, Vec<(usize, String)>, Vec<u64>) {
+    let instrs = section.instructions.instruction_vec_in(range.clone());
+    let texts = section.instructions.instruction_text_vec_in(range.clone());
+    let offsets = section.instructions.instruction_offset_vec_in(range.clone());
+    let sizes = section.instructions.instruction_size_vec_in(range);
+    let mut expr_list = Vec::<ExprId>::new();
+    let mut source = Vec::<(usize, String)>::new();
+    let mut addresses = Vec::<u64>::new();
+    let (stack_pointer, word_size) = match dis.program().machine_type.as_str() {
+        "amd64" => (Reg::RSP, 8),
+        "x86" => (Reg::ESP, 4),
+        "riscv" if dis.program().bits == 32 => (Reg::RISCV_SP, 4),
+        "riscv" => (Reg::RISCV_SP, 8),
+        "arm" => (Reg::ARM_SP, 4),
+        "aarch64" => (Reg::AARCH64_SP, 8),
+        _ => (Reg::ARM_SP, dis.program().bits / 8),
+    };
+    let base = section.addr;
+    let imports = imports::imports(dis.program());
+    let stubs = imports.iter().filter_map(|i| i.stub.map(|stub| (stub, i.name.clone()))).collect();
+    let slots = imports.iter().map(|i| (i.slot, i.name.clone())).collect();
+    let mut expr_builder = ExprBuilder { arena, change_lists: HashMap::<Reg, ChangeList>::new(), next_id: 1, flags: None,
+        constants: HashMap::new(), machine_type: dis.program().machine_type.as_str(),
+        protos, call_conv: call_conv(dis.program()), symbols: dis.program().symbol_index(), stubs, slots, address: 0, size: 0, stack_pointer, word_size };
+    // Flags set in one block can't be fused into a branch of another, so they are
+    // forgotten at every branch target and after every jump or return.
+    let labels: HashSet<u64> = instrs.iter().zip(&offsets).filter_map(|(instr, offset)| match dis::flow(instr, base + *offset as u64) {
+        Flow::Jump(target) | Flow::Branch(target) => target,
+        _ => None,
+    }).collect();
+    let mut block_start = true;
+    for (((instr, text), offset), size) in instrs.iter().zip(texts).zip(offsets).zip(sizes) {
+        source.push((expr_list.len(), text));
+        expr_builder.address = base + offset as u64;
+        expr_builder.size = size;
+        if block_start || labels.contains(&expr_builder.address) {
+            expr_builder.flags = None;
+        }
+        block_start = matches!(dis::flow(instr, expr_builder.address), Flow::Jump(_) | Flow::Stop);
+        if let Some(expr) = expr_builder.decomp_instruction(instr, &expr_list) {
+            expr_list.push(expr);
+            addresses.push(expr_builder.address);
+        }
         expr_builder.next_id += 1;
     }
-    expr_list
+    (expr_list, source, addresses)
+}
+
+pub fn decomp_program_from_bytes(bytes: &[u8], options: &AnalysisOptions, dest_lang: Language, protos: &PrototypeDb) -> Result<Decomp, BaretkError> {
+    let dis = dis::disassemble_with_options(prog::load_program_with_options(bytes, options)?, options)?;
+    Ok(decomp_program(dis, dest_lang, protos))
+}
+
+// Decompiles every section, one after another in address order.
+pub fn decomp_program(dis: Disassembly, dest_lang: Language, protos: &PrototypeDb) -> Decomp {
+    let arena = ExprArena::new();
+    let (mut expr_list, mut source, mut addresses) = (Vec::new(), Vec::new(), Vec::new());
+    for section in dis.sections() {
+        let (section_exprs, section_source, section_addresses) = decomp_disassembly(&dis, section, &arena, 0..usize::MAX, protos);
+        source.extend(section_source.into_iter().map(|(i, text)| (expr_list.len() + i, text)));
+        expr_list.extend(section_exprs);
+        addresses.extend(section_addresses);
+    }
+    let name = format!("sub_{:08x}", dis.section_addr());
+    Decomp { disassembly: Rc::new(dis), dest_lang, name, arena, expr_list, source, addresses }
 }
 
-pub fn decomp_program_from_bytes(bytes: &[u8], dest_lang: Language) -> Decomp {
-    let dis = dis::disassemble(bytes);
-    decomp_program(dis, dest_lang)
+// Decompiles a single function, given either its symbol name or its address as "0x...".
+pub fn decomp_function(dis: Disassembly, func: &str, dest_lang: Language, protos: &PrototypeDb) -> Result<Decomp, BaretkError> {
+    let (name, section, range) = dis.find_function(func)?;
+    let arena = ExprArena::new();
+    let (expr_list, source, addresses) = decomp_disassembly(&dis, section, &arena, range, protos);
+    Ok(Decomp { disassembly: Rc::new(dis), dest_lang, name, arena, expr_list, source, addresses })
 }
 
-pub fn decomp_program(dis: Disassembly, dest_lang: Language) -> Decomp {
-    let expr_list = decomp_disassembly(&dis);
-    Decomp { disassembly: dis, dest_lang, expr_list }
+// Decompiles each function of every section on its own, in address order.
+pub fn decomp_functions(dis: Disassembly, dest_lang: Language, protos: &PrototypeDb) -> Vec<Decomp> {
+    let dis = Rc::new(dis);
+    dis.all_functions().into_iter().map(|func| {
+        let arena = ExprArena::new();
+        let section = dis.section_named(&func.section).expect("functions are in disassembled sections");
+        let (expr_list, source, addresses) = decomp_disassembly(&dis, section, &arena, func.range, protos);
+        Decomp { disassembly: dis.clone(), dest_lang, name: func.name, arena, expr_list, source, addresses }
+    }).collect()
 }
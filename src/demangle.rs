@@ -0,0 +1,898 @@
+// Symbol demangling: Itanium C++ names as GCC and Clang mangle them (_Z...),
+// and Rust names in both the legacy and v0 schemes. Names that aren't mangled,
+// or use parts of the C++ grammar not handled here (most expressions in
+// template arguments), are left as they are.
+//
+//     _ZNSt6vectorIiSaIiEE9push_backERKi
+//     std::vector<int, std::allocator<int> >::push_back(int const&)
+
+use crate::prog::Program;
+
+// Demangles a C++ or Rust symbol, or returns None if it isn't one.
+pub fn demangle(name: &str) -> Option<String> {
+    // Symbol versions (foo@@GLIBCXX_3.4) aren't part of the mangled name.
+    let (name, version) = match name.find('@') {
+        Some(at) => (&name[..at], &name[at..]),
+        None => (name, ""),
+    };
+    let text = if is_rust(name) {
+        rustc_demangle::try_demangle(name).ok().map(|d| format!("{:#}", d))?
+    }
+    else {
+        demangle_itanium(name)?
+    };
+    Some(text + version)
+}
+
+// Replaces the mangled names in the symbol table with their demangled form.
+pub fn demangle_symbols(program: &mut Program) {
+    for sym in program.symbol_table.iter_mut() {
+        if let Some(name) = demangle(&sym.name) {
+            sym.name = name;
+        }
+    }
+}
+
+// Demangles every mangled name in a line of text, like c++filt.
+pub fn demangle_text(line: &str) -> String {
+    let mut out = String::new();
+    let mut word = String::new();
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == '.';
+    for c in line.chars().chain(std::iter::once('\0')) {
+        if is_word(c) {
+            word.push(c);
+            continue;
+        }
+        out += demangle(&word).as_deref().unwrap_or(&word);
+        word.clear();
+        if c != '\0' {
+            out.push(c);
+        }
+    }
+    out
+}
+
+// v0 names start with _R; legacy names look like C++ but end in a 17h<hash>E element.
+fn is_rust(name: &str) -> bool {
+    if name.starts_with("_R") || name.starts_with("__R") {
+        return true
+    }
+    // The hash may be followed by a suffix such as .llvm.1234.
+    name.starts_with("_ZN") && name.match_indices("17h").any(|(i, _)| {
+        let hash = name.as_bytes().get(i + 3..i + 19).unwrap_or(&[]);
+        hash.len() == 16 && hash.iter().all(|c| c.is_ascii_hexdigit())
+            && name.as_bytes().get(i + 19) == Some(&b'E')
+            && matches!(name.as_bytes().get(i + 20), None | Some(b'.'))
+    })
+}
+
+fn demangle_itanium(name: &str) -> Option<String> {
+    let mangled = name.strip_prefix("_Z").or_else(|| name.strip_prefix("__Z"))?;
+    // Compiler-generated clones (.cold, .constprop.0, .isra.0) follow the name.
+    let (mangled, clones) = match mangled.find('.') {
+        Some(dot) => (&mangled[..dot], &mangled[dot..]),
+        None => (mangled, ""),
+    };
+    let mut parser = Parser { s: mangled.as_bytes(), pos: 0, subs: Vec::new(), template_args: Vec::new(), pack_index: None, pack_len: None };
+    let mut text = parser.encoding()?;
+    if parser.pos != parser.s.len() {
+        return None
+    }
+    let mut rest = clones;
+    while !rest.is_empty() {
+        // A clone is a name plus any numeric parts: .constprop.0
+        let mut end = rest[1..].find('.').map_or(rest.len(), |i| i + 1);
+        while rest[end..].len() > 1 && rest.as_bytes()[end + 1].is_ascii_digit() {
+            end = rest[end + 1..].find('.').map_or(rest.len(), |i| end + 1 + i);
+        }
+        text += format!(" [clone {}]", &rest[..end]).as_str();
+        rest = &rest[end..];
+    }
+    Some(text)
+}
+
+// The last component of a qualified name without its template arguments:
+// "basic_string" for "std::basic_string<char, ...>".
+fn base_name(name: &str) -> &str {
+    let mut end = name.len();
+    if name.ends_with('>') {
+        let mut depth = 0;
+        for (i, c) in name.char_indices().rev() {
+            match c {
+                '>' => depth += 1,
+                '<' => depth -= 1,
+                _ => (),
+            }
+            if depth == 0 {
+                end = i;
+                break;
+            }
+        }
+    }
+    let name = &name[..end];
+    name.rfind("::").map_or(name, |i| &name[i + 2..])
+}
+
+// Template arguments after a name, spaced apart from an operator< or operator<<.
+fn with_args(name: &str, args: &str) -> String {
+    match name.ends_with('<') {
+        true => format!("{} {}", name, args),
+        false => format!("{}{}", name, args),
+    }
+}
+
+// A type split around its declarator, so that pointers to functions and arrays
+// print inside the parentheses: "int (*)(char)".
+#[derive(Clone)]
+struct Type {
+    left: String,
+    right: String,
+    // Whether the "(" of a declarator has been opened in `left`.
+    wrapped: bool,
+}
+
+impl Type {
+    fn plain(text: String) -> Type {
+        Type { left: text, right: String::new(), wrapped: false }
+    }
+
+    fn text(&self) -> String {
+        format!("{}{}", self.left, self.right).trim_end().to_string()
+    }
+
+    // Applies a pointer or reference declarator ("*", "&" or "&&"). References
+    // to references collapse, as in T&& with T = int&.
+    fn declare(mut self, op: &str) -> Type {
+        if op.starts_with('&') && self.left.ends_with('&') {
+            if op == "&" && self.left.ends_with("&&") {
+                self.left.pop();
+            }
+            return self
+        }
+        if !self.right.is_empty() && !self.wrapped {
+            // Arrays are spaced from the declarator: "char (&) [11]".
+            let space = if self.right.starts_with('[') { " " } else { "" };
+            self.left += "(";
+            self.right = format!("){}{}", space, self.right);
+            self.wrapped = true;
+        }
+        self.left += op;
+        self
+    }
+
+    // Qualifiers on an array type apply to its elements, and those on a function
+    // type are the qualifiers of a member function: "() const".
+    fn qualify(mut self, qualifiers: &str) -> Type {
+        // const T with T = int const is still int const.
+        if self.left.trim_end().ends_with(qualifiers) {
+            return self
+        }
+        if self.right.is_empty() || self.wrapped {
+            self.left += qualifiers;
+        }
+        else if self.right.starts_with('[') {
+            self.left = format!("{}{} ", self.left.trim_end(), qualifiers);
+        }
+        else {
+            self.right += qualifiers;
+        }
+        self
+    }
+}
+
+// What a name parse gives besides its text: whether it ends in template
+// arguments, and whether it names a constructor, destructor or conversion
+// operator, which have no return type.
+struct Name {
+    text: String,
+    template: bool,
+    no_return: bool,
+    // Qualifiers of a member function: " const", " &".
+    qualifiers: String,
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+    // Substitution candidates, referred to by S_, S0_, S1_...
+    subs: Vec<Type>,
+    // The function's template arguments, referred to by T_, T0_, T1_...
+    template_args: Vec<TemplateArg>,
+    // While expanding a pack (Dp), the element being printed, and the number of
+    // elements once a parameter pack has been met.
+    pack_index: Option<usize>,
+    pack_len: Option<usize>,
+}
+
+struct TemplateArg {
+    ty: Type,
+    // The elements of an argument pack.
+    pack: Option<Vec<Type>>,
+}
+
+fn builtin(c: u8) -> Option<&'static str> {
+    Some(match c {
+        b'v' => "void",
+        b'w' => "wchar_t",
+        b'b' => "bool",
+        b'c' => "char",
+        b'a' => "signed char",
+        b'h' => "unsigned char",
+        b's' => "short",
+        b't' => "unsigned short",
+        b'i' => "int",
+        b'j' => "unsigned int",
+        b'l' => "long",
+        b'm' => "unsigned long",
+        b'x' => "long long",
+        b'y' => "unsigned long long",
+        b'n' => "__int128",
+        b'o' => "unsigned __int128",
+        b'f' => "float",
+        b'd' => "double",
+        b'e' => "long double",
+        b'g' => "__float128",
+        b'z' => "...",
+        _ => return None,
+    })
+}
+
+fn operator(code: &[u8]) -> Option<&'static str> {
+    Some(match code {
+        b"nw" => "new",
+        b"na" => "new[]",
+        b"dl" => "delete",
+        b"da" => "delete[]",
+        b"ps" | b"pl" => "+",
+        b"ng" | b"mi" => "-",
+        b"ad" | b"an" => "&",
+        b"de" | b"ml" => "*",
+        b"co" => "~",
+        b"dv" => "/",
+        b"rm" => "%",
+        b"or" => "|",
+        b"eo" => "^",
+        b"aS" => "=",
+        b"pL" => "+=",
+        b"mI" => "-=",
+        b"mL" => "*=",
+        b"dV" => "/=",
+        b"rM" => "%=",
+        b"aN" => "&=",
+        b"oR" => "|=",
+        b"eO" => "^=",
+        b"ls" => "<<",
+        b"rs" => ">>",
+        b"lS" => "<<=",
+        b"rS" => ">>=",
+        b"eq" => "==",
+        b"ne" => "!=",
+        b"lt" => "<",
+        b"gt" => ">",
+        b"le" => "<=",
+        b"ge" => ">=",
+        b"ss" => "<=>",
+        b"nt" => "!",
+        b"aa" => "&&",
+        b"oo" => "||",
+        b"pp" => "++",
+        b"mm" => "--",
+        b"cm" => ",",
+        b"pm" => "->*",
+        b"pt" => "->",
+        b"cl" => "()",
+        b"ix" => "[]",
+        b"qu" => "?",
+        _ => return None,
+    })
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> u8 {
+        self.s.get(self.pos).copied().unwrap_or(0)
+    }
+
+    fn peek_at(&self, n: usize) -> u8 {
+        self.s.get(self.pos + n).copied().unwrap_or(0)
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == c {
+            self.pos += 1;
+            return true
+        }
+        false
+    }
+
+    fn expect(&mut self, c: u8) -> Option<()> {
+        if self.eat(c) { Some(()) } else { None }
+    }
+
+    // A decimal number, negative when prefixed with 'n'.
+    fn number(&mut self) -> Option<i64> {
+        let negative = self.eat(b'n');
+        let start = self.pos;
+        while self.peek().is_ascii_digit() {
+            self.pos += 1;
+        }
+        let value = std::str::from_utf8(&self.s[start..self.pos]).ok()?.parse::<i64>().ok()?;
+        Some(if negative { -value } else { value })
+    }
+
+    // The index in "S<n>_" and "T<n>_": nothing for 0, then base-36 digits plus one.
+    fn index(&mut self) -> Option<usize> {
+        if self.eat(b'_') {
+            return Some(0)
+        }
+        let mut value = 0usize;
+        while self.peek() != b'_' {
+            let digit = (self.peek() as char).to_digit(36)? as usize;
+            if self.peek().is_ascii_lowercase() {
+                return None
+            }
+            value = value.checked_mul(36)?.checked_add(digit)?;
+            self.pos += 1;
+        }
+        self.pos += 1;
+        Some(value + 1)
+    }
+
+    fn encoding(&mut self) -> Option<String> {
+        if self.peek() == b'T' || (self.peek() == b'G' && matches!(self.peek_at(1), b'V' | b'T')) {
+            return self.special_name()
+        }
+        let name = self.name(true)?;
+        if matches!(self.peek(), 0 | b'E') {
+            return Some(name.text)
+        }
+        // Function templates mangle their return type; other functions don't.
+        let ret = match name.template && !name.no_return {
+            true => Some(self.type_()?.text()),
+            false => None,
+        };
+        let mut params = Vec::<String>::new();
+        while !matches!(self.peek(), 0 | b'E') {
+            params.push(self.type_()?.text());
+        }
+        // Empty packs expand to nothing.
+        params.retain(|param| !param.is_empty());
+        if params.len() == 1 && params[0] == "void" {
+            params.clear();
+        }
+        let mut text = String::new();
+        if let Some(ret) = ret {
+            text += ret.as_str();
+            text += " ";
+        }
+        text += format!("{}({}){}", name.text, params.join(", "), name.qualifiers).as_str();
+        Some(text)
+    }
+
+    fn special_name(&mut self) -> Option<String> {
+        if self.s[self.pos..].starts_with(b"GTt") {
+            self.pos += 3;
+            return Some(format!("transaction clone for {}", self.encoding()?))
+        }
+        let code = [self.peek(), self.peek_at(1)];
+        self.pos += 2;
+        let text = match &code {
+            b"TV" => format!("vtable for {}", self.type_()?.text()),
+            b"TT" => format!("VTT for {}", self.type_()?.text()),
+            b"TI" => format!("typeinfo for {}", self.type_()?.text()),
+            b"TS" => format!("typeinfo name for {}", self.type_()?.text()),
+            b"TH" => format!("TLS init function for {}", self.name(false)?.text),
+            b"TW" => format!("TLS wrapper function for {}", self.name(false)?.text),
+            b"GV" => format!("guard variable for {}", self.name(false)?.text),
+            b"Th" => {
+                self.number()?;
+                self.expect(b'_')?;
+                format!("non-virtual thunk to {}", self.encoding()?)
+            },
+            b"Tv" => {
+                self.number()?;
+                self.expect(b'_')?;
+                self.number()?;
+                self.expect(b'_')?;
+                format!("virtual thunk to {}", self.encoding()?)
+            },
+            _ => return None,
+        };
+        Some(text)
+    }
+
+    // `top` is set for the name of the function being demangled, whose template
+    // arguments are what T_ refers to.
+    fn name(&mut self, top: bool) -> Option<Name> {
+        match self.peek() {
+            b'N' => self.nested_name(top),
+            b'Z' => {
+                self.pos += 1;
+                let function = self.encoding()?;
+                self.expect(b'E')?;
+                if self.eat(b's') {
+                    self.discriminator();
+                    return Some(Name { text: format!("{}::string literal", function), template: false, no_return: false, qualifiers: String::new() })
+                }
+                let mut entity = self.name(false)?;
+                self.discriminator();
+                entity.text = format!("{}::{}", function, entity.text);
+                Some(entity)
+            },
+            b'S' if self.peek_at(1) != b't' => {
+                let sub = self.substitution()?.text();
+                let args = self.template_args(top)?;
+                Some(Name { text: sub + args.as_str(), template: true, no_return: false, qualifiers: String::new() })
+            },
+            _ => {
+                let std = self.peek() == b'S' && self.peek_at(1) == b't';
+                if std {
+                    self.pos += 2;
+                }
+                let (text, no_return) = self.unqualified_name("")?;
+                let text = if std { format!("std::{}", text) } else { text };
+                if self.peek() != b'I' {
+                    return Some(Name { text, template: false, no_return, qualifiers: String::new() })
+                }
+                self.subs.push(Type::plain(text.clone()));
+                let args = self.template_args(top)?;
+                Some(Name { text: with_args(&text, &args), template: true, no_return, qualifiers: String::new() })
+            },
+        }
+    }
+
+    fn discriminator(&mut self) {
+        if self.eat(b'_') {
+            if self.eat(b'_') {
+                self.number();
+                self.eat(b'_');
+            }
+            else if self.peek().is_ascii_digit() {
+                self.pos += 1;
+            }
+        }
+    }
+
+    fn nested_name(&mut self, top: bool) -> Option<Name> {
+        self.expect(b'N')?;
+        let mut qualifiers = String::new();
+        let (restrict, volatile) = (self.eat(b'r'), self.eat(b'V'));
+        if self.eat(b'K') {
+            qualifiers += " const";
+        }
+        if volatile {
+            qualifiers += " volatile";
+        }
+        if restrict {
+            qualifiers += " restrict";
+        }
+        if self.eat(b'R') {
+            qualifiers += " &";
+        }
+        else if self.eat(b'O') {
+            qualifiers += " &&";
+        }
+
+        let mut so_far = String::new();
+        // The last plain component, which constructors and destructors are named after.
+        let mut last = String::new();
+        let mut template = false;
+        let mut no_return = false;
+        while !self.eat(b'E') {
+            let join = |so_far: &str, part: &str| if so_far.is_empty() { part.to_string() } else { format!("{}::{}", so_far, part) };
+            match self.peek() {
+                b'S' if self.peek_at(1) == b't' => {
+                    self.pos += 2;
+                    so_far = "std".to_string();
+                    continue;
+                },
+                // Substitutions aren't added as candidates again.
+                b'S' => {
+                    so_far = self.substitution()?.text();
+                    last = base_name(&so_far).to_string();
+                    continue;
+                },
+                b'I' => {
+                    so_far = with_args(&so_far, &self.template_args(top)?);
+                    template = true;
+                },
+                b'T' => {
+                    so_far = join(&so_far, &self.template_param()?.text());
+                    template = false;
+                },
+                b'M' => {
+                    self.pos += 1;
+                    continue;
+                },
+                0 => return None,
+                _ => {
+                    let (part, is_special) = self.unqualified_name(&last)?;
+                    if !is_special {
+                        last = part.split("[abi:").next().unwrap_or("").to_string();
+                    }
+                    so_far = join(&so_far, &part);
+                    template = false;
+                    no_return = is_special;
+                },
+            }
+            self.subs.push(Type::plain(so_far.clone()));
+        }
+        // The whole name is only a candidate as a type, which the caller adds.
+        self.subs.pop()?;
+        Some(Name { text: so_far, template, no_return, qualifiers })
+    }
+
+    // A source name, constructor, destructor, operator or unnamed type, with any
+    // ABI tags. The flag is set for names without a return type.
+    fn unqualified_name(&mut self, class: &str) -> Option<(String, bool)> {
+        let (mut text, special) = match self.peek() {
+            b'0'..=b'9' => (self.source_name()?, false),
+            b'L' => {
+                self.pos += 1;
+                let text = self.source_name()?;
+                self.discriminator();
+                (text, false)
+            },
+            b'C' if matches!(self.peek_at(1), b'1'..=b'5') => {
+                self.pos += 2;
+                (class.to_string(), true)
+            },
+            b'D' if matches!(self.peek_at(1), b'0' | b'1' | b'2' | b'4' | b'5') => {
+                self.pos += 2;
+                (format!("~{}", class), true)
+            },
+            b'U' if self.peek_at(1) == b't' => {
+                self.pos += 2;
+                let n = self.closure_number()?;
+                (format!("{{unnamed type#{}}}", n), false)
+            },
+            b'U' if self.peek_at(1) == b'l' => {
+                self.pos += 2;
+                let mut params = Vec::<String>::new();
+                while !self.eat(b'E') {
+                    params.push(self.type_()?.text());
+                }
+                if params.len() == 1 && params[0] == "void" {
+                    params.clear();
+                }
+                let n = self.closure_number()?;
+                (format!("{{lambda({})#{}}}", params.join(", "), n), false)
+            },
+            b'c' if self.peek_at(1) == b'v' => {
+                self.pos += 2;
+                (format!("operator {}", self.type_()?.text()), true)
+            },
+            b'l' if self.peek_at(1) == b'i' => {
+                self.pos += 2;
+                (format!("operator\"\" {}", self.source_name()?), false)
+            },
+            _ => {
+                let op = operator(self.s.get(self.pos..self.pos + 2)?)?;
+                self.pos += 2;
+                let space = if op.starts_with(|c: char| c.is_ascii_alphabetic()) { " " } else { "" };
+                (format!("operator{}{}", space, op), false)
+            },
+        };
+        while self.eat(b'B') {
+            text += format!("[abi:{}]", self.source_name()?).as_str();
+        }
+        Some((text, special))
+    }
+
+    // The "[<number>] _" ending an unnamed type or lambda, numbered from 1.
+    fn closure_number(&mut self) -> Option<i64> {
+        let n = if self.peek() == b'_' { 1 } else { self.number()? + 2 };
+        self.expect(b'_')?;
+        Some(n)
+    }
+
+    // A source name with any template arguments.
+    fn simple_id(&mut self) -> Option<String> {
+        let name = self.source_name()?;
+        if self.peek() != b'I' {
+            return Some(name)
+        }
+        Some(with_args(&name, &self.template_args(false)?))
+    }
+
+    fn source_name(&mut self) -> Option<String> {
+        let len = self.number()?;
+        if len <= 0 {
+            return None
+        }
+        let end = self.pos.checked_add(len as usize)?;
+        let text = std::str::from_utf8(self.s.get(self.pos..end)?).ok()?;
+        self.pos = end;
+        if text.starts_with("_GLOBAL__N") {
+            return Some("(anonymous namespace)".to_string())
+        }
+        Some(text.to_string())
+    }
+
+    fn substitution(&mut self) -> Option<Type> {
+        self.expect(b'S')?;
+        let abbreviation = match self.peek() {
+            b'a' => "std::allocator",
+            b'b' => "std::basic_string",
+            b's' => "std::basic_string<char, std::char_traits<char>, std::allocator<char> >",
+            b'i' => "std::basic_istream<char, std::char_traits<char> >",
+            b'o' => "std::basic_ostream<char, std::char_traits<char> >",
+            b'd' => "std::basic_iostream<char, std::char_traits<char> >",
+            _ => "",
+        };
+        if !abbreviation.is_empty() {
+            self.pos += 1;
+            return Some(Type::plain(abbreviation.to_string()))
+        }
+        let index = self.index()?;
+        self.subs.get(index).cloned()
+    }
+
+    fn template_param(&mut self) -> Option<Type> {
+        self.expect(b'T')?;
+        let index = self.index()?;
+        let arg = self.template_args.get(index)?;
+        match (&arg.pack, self.pack_index) {
+            (Some(items), Some(i)) => {
+                self.pack_len = Some(items.len());
+                Some(items.get(i).cloned().unwrap_or(Type::plain(String::new())))
+            },
+            _ => Some(arg.ty.clone()),
+        }
+    }
+
+    // Returns the arguments as text, "<int, char>".
+    fn template_args(&mut self, top: bool) -> Option<String> {
+        self.expect(b'I')?;
+        let mut args = Vec::<TemplateArg>::new();
+        while !self.eat(b'E') {
+            args.push(self.template_arg()?);
+        }
+        let texts: Vec<String> = args.iter().map(|arg| arg.ty.text()).collect();
+        let mut text = format!("<{}", texts.iter().filter(|text| !text.is_empty()).cloned().collect::<Vec<_>>().join(", "));
+        // c++filt spaces "> >" apart unless the last argument is an empty pack.
+        if texts.last().map_or(false, |text| text.ends_with('>')) {
+            text += " ";
+        }
+        text += ">";
+        if top {
+            self.template_args = args;
+        }
+        Some(text)
+    }
+
+    fn template_arg(&mut self) -> Option<TemplateArg> {
+        match self.peek() {
+            b'L' => Some(TemplateArg { ty: Type::plain(self.literal()?), pack: None }),
+            b'J' => {
+                self.pos += 1;
+                let mut items = Vec::<Type>::new();
+                while !self.eat(b'E') {
+                    items.push(self.template_arg()?.ty);
+                }
+                let text = items.iter().map(|item| item.text()).collect::<Vec<_>>().join(", ");
+                Some(TemplateArg { ty: Type::plain(text), pack: Some(items) })
+            },
+            b'X' => {
+                self.pos += 1;
+                let text = self.expression()?;
+                self.expect(b'E')?;
+                Some(TemplateArg { ty: Type::plain(text), pack: None })
+            },
+            _ => Some(TemplateArg { ty: self.type_()?, pack: None }),
+        }
+    }
+
+    // Only the simplest expressions: literals, template parameters and scoped
+    // names such as has_trait<T>::value, which enable_if conditions use.
+    fn expression(&mut self) -> Option<String> {
+        match (self.peek(), self.peek_at(1)) {
+            (b'L', _) => self.literal(),
+            (b'T', _) => Some(self.template_param()?.text()),
+            (b's', b'r') => {
+                self.pos += 2;
+                // Either a template parameter or substitution, or names up to an E.
+                let mut scope = Vec::<String>::new();
+                if matches!(self.peek(), b'T' | b'S') {
+                    scope.push(self.type_()?.text());
+                }
+                else {
+                    while !self.eat(b'E') {
+                        scope.push(self.simple_id()?);
+                    }
+                }
+                scope.push(self.simple_id()?);
+                Some(scope.join("::"))
+            },
+            _ => None,
+        }
+    }
+
+    // A pack expansion: the pattern printed once for each element of the
+    // parameter pack it refers to.
+    fn pack_expansion(&mut self) -> Option<Type> {
+        let start = self.pos;
+        let outer = (self.pack_index, self.pack_len);
+        self.pack_index = Some(0);
+        self.pack_len = None;
+        let mut items = vec![self.type_()?.text()];
+        let (end, subs) = (self.pos, self.subs.len());
+        let count = self.pack_len.unwrap_or(1);
+        for i in 1..count {
+            self.pos = start;
+            self.pack_index = Some(i);
+            items.push(self.type_()?.text());
+            self.subs.truncate(subs);
+        }
+        items.truncate(count);
+        self.pos = end;
+        (self.pack_index, self.pack_len) = outer;
+        Some(Type::plain(items.join(", ")))
+    }
+
+    fn literal(&mut self) -> Option<String> {
+        self.expect(b'L')?;
+        if self.peek() == b'_' && self.peek_at(1) == b'Z' {
+            self.pos += 2;
+            let text = self.encoding()?;
+            self.expect(b'E')?;
+            return Some(text)
+        }
+        let kind = self.peek();
+        let type_name = self.type_()?.text();
+        // Kept as text, since unsigned values may not fit an i64.
+        let negative = self.eat(b'n');
+        let start = self.pos;
+        while self.peek().is_ascii_digit() {
+            self.pos += 1;
+        }
+        let digits = std::str::from_utf8(&self.s[start..self.pos]).ok()?;
+        let value = if negative { format!("-{}", digits) } else { digits.to_string() };
+        self.expect(b'E')?;
+        Some(match kind {
+            b'b' if value == "0" => "false".to_string(),
+            b'b' => "true".to_string(),
+            b'i' => value,
+            b'j' => format!("{}u", value),
+            b'l' => format!("{}l", value),
+            b'm' => format!("{}ul", value),
+            b'x' => format!("{}ll", value),
+            b'y' => format!("{}ull", value),
+            _ => format!("({}){}", type_name, value),
+        })
+    }
+
+    fn type_(&mut self) -> Option<Type> {
+        let c = self.peek();
+        if let Some(name) = builtin(c) {
+            self.pos += 1;
+            return Some(Type::plain(name.to_string()))
+        }
+        let ty = match c {
+            b'D' => {
+                let name = match self.peek_at(1) {
+                    b'n' => "decltype(nullptr)",
+                    b'i' => "char32_t",
+                    b's' => "char16_t",
+                    b'u' => "char8_t",
+                    b'a' => "auto",
+                    b'c' => "decltype(auto)",
+                    b'h' => "half",
+                    b'f' => "decimal32",
+                    b'd' => "decimal64",
+                    b'e' => "decimal128",
+                    b'p' => {
+                        self.pos += 2;
+                        let ty = self.pack_expansion()?;
+                        self.subs.push(ty.clone());
+                        return Some(ty)
+                    },
+                    _ => return None,
+                };
+                self.pos += 2;
+                return Some(Type::plain(name.to_string()))
+            },
+            b'u' => {
+                self.pos += 1;
+                return Some(Type::plain(self.source_name()?))
+            },
+            b'r' | b'V' | b'K' => {
+                let (restrict, volatile, constant) = (self.eat(b'r'), self.eat(b'V'), self.eat(b'K'));
+                let mut qualifiers = String::new();
+                if constant {
+                    qualifiers += " const";
+                }
+                if volatile {
+                    qualifiers += " volatile";
+                }
+                if restrict {
+                    qualifiers += " restrict";
+                }
+                self.type_()?.qualify(&qualifiers)
+            },
+            b'P' => {
+                self.pos += 1;
+                self.type_()?.declare("*")
+            },
+            b'R' => {
+                self.pos += 1;
+                self.type_()?.declare("&")
+            },
+            b'O' => {
+                self.pos += 1;
+                self.type_()?.declare("&&")
+            },
+            b'C' => {
+                self.pos += 1;
+                Type::plain(format!("{} _Complex", self.type_()?.text()))
+            },
+            b'G' => {
+                self.pos += 1;
+                Type::plain(format!("{} _Imaginary", self.type_()?.text()))
+            },
+            b'F' => self.function_type()?,
+            b'A' => {
+                self.pos += 1;
+                let dim = if self.peek() == b'_' { String::new() } else { self.number()?.to_string() };
+                self.expect(b'_')?;
+                let element = self.type_()?;
+                Type { left: format!("{} ", element.left), right: format!("[{}]{}", dim, element.right), wrapped: false }
+            },
+            b'M' => {
+                self.pos += 1;
+                let class = self.type_()?.text();
+                let member = self.type_()?;
+                match member.right.is_empty() {
+                    true => Type::plain(format!("{} {}::*", member.text(), class)),
+                    false => Type { left: format!("{}({}::*", member.left, class), right: format!("){}", member.right), wrapped: true },
+                }
+            },
+            b'T' => {
+                let param = self.template_param()?;
+                if self.peek() != b'I' {
+                    param
+                }
+                else {
+                    self.subs.push(param.clone());
+                    Type::plain(param.text() + self.template_args(false)?.as_str())
+                }
+            },
+            b'S' if self.peek_at(1) != b't' => {
+                let sub = self.substitution()?;
+                if self.peek() != b'I' {
+                    return Some(sub)
+                }
+                Type::plain(sub.text() + self.template_args(false)?.as_str())
+            },
+            b'S' | b'N' | b'Z' | b'0'..=b'9' => Type::plain(self.name(false)?.text),
+            _ => return None,
+        };
+        self.subs.push(ty.clone());
+        Some(ty)
+    }
+
+    fn function_type(&mut self) -> Option<Type> {
+        self.expect(b'F')?;
+        self.eat(b'Y');
+        let ret = self.type_()?.text();
+        let mut params = Vec::<String>::new();
+        let mut qualifiers = "";
+        while !self.eat(b'E') {
+            if self.peek() == b'R' && self.peek_at(1) == b'E' {
+                self.pos += 1;
+                qualifiers = " &";
+                continue;
+            }
+            if self.peek() == b'O' && self.peek_at(1) == b'E' {
+                self.pos += 1;
+                qualifiers = " &&";
+                continue;
+            }
+            params.push(self.type_()?.text());
+        }
+        params.retain(|param| !param.is_empty());
+        if params.len() == 1 && params[0] == "void" {
+            params.clear();
+        }
+        Some(Type { left: format!("{} ", ret), right: format!("({}){}", params.join(", "), qualifiers), wrapped: false })
+    }
+}
@@ -0,0 +1,226 @@
+// Function-level diffing of two builds of the same program, for patch analysis.
+// Functions are paired by name, then by identical normalized code, then by
+// control flow graph similarity; paired functions with different code are
+// reported with an instruction diff.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use crate::cfg;
+use crate::dis::{self, Disassembly};
+use crate::reg::RegClass;
+
+// Pairs scoring below this aren't considered the same function.
+const MIN_SIMILARITY: f64 = 0.7;
+
+struct FuncInfo {
+    name: String,
+    addr: u64,
+    // Instructions with addresses and relative offsets removed, so code that only
+    // moved compares equal.
+    keys: Vec<String>,
+    texts: Vec<String>,
+    hash: u64,
+    blocks: usize,
+    edges: usize,
+}
+
+pub enum DiffLine {
+    Same(String),
+    Removed(String),
+    Added(String),
+}
+
+pub struct ChangedFunction {
+    pub old_name: String,
+    pub new_name: String,
+    pub old_addr: u64,
+    pub new_addr: u64,
+    // How the pair was found: "name", "hash" or "cfg".
+    pub matched_by: &'static str,
+    pub lines: Vec<DiffLine>,
+}
+
+pub struct FunctionDiff {
+    pub identical: usize,
+    pub changed: Vec<ChangedFunction>,
+    pub added: Vec<(String, u64)>,
+    pub removed: Vec<(String, u64)>,
+}
+
+fn is_branch(opcode: &str) -> bool {
+    matches!(opcode, "call" | "b" | "jal" | "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" | "tbz" | "tbnz")
+}
+
+fn instruction_key(ins: &dis::Instruction) -> String {
+    let mut key = format!("{}.{}", ins.opcode, ins.cond());
+    for op in &ins.operands {
+        let text = match op {
+            dis::Operand::Immediate(_) if is_branch(ins.opcode) => "?".to_string(),
+            dis::Operand::Memory(Some(base), _, _, _, size) if base.class() == RegClass::ProgramCounter => format!("[pc+?]:{}", size),
+            _ => op.print(),
+        };
+        key += " ";
+        key += text.as_str();
+    }
+    key
+}
+
+fn collect_functions(dis: &Disassembly) -> Vec<FuncInfo> {
+    let listing = &dis.section().instructions;
+    dis.functions().into_iter().map(|func| {
+        let keys: Vec<String> = listing.instruction_vec_in(func.range.clone()).iter().map(instruction_key).collect();
+        let texts = listing.instruction_text_vec_in(func.range.clone());
+        let mut hasher = DefaultHasher::new();
+        keys.hash(&mut hasher);
+        let graph = cfg::build_cfg(dis, &func.name, func.range);
+        FuncInfo {
+            name: func.name,
+            addr: func.addr,
+            keys,
+            texts,
+            hash: hasher.finish(),
+            blocks: graph.blocks.len(),
+            edges: graph.blocks.iter().map(|block| block.succs.len()).sum(),
+        }
+    }).collect()
+}
+
+// Names made up for stripped functions say nothing about identity.
+fn has_real_name(func: &FuncInfo) -> bool {
+    !func.name.starts_with("sub_")
+}
+
+fn ratio(a: usize, b: usize) -> f64 {
+    if a == 0 && b == 0 { 1.0 } else { a.min(b) as f64 / a.max(b) as f64 }
+}
+
+fn similarity(a: &FuncInfo, b: &FuncInfo) -> f64 {
+    (ratio(a.blocks, b.blocks) + ratio(a.edges, b.edges) + ratio(a.keys.len(), b.keys.len())) / 3.0
+}
+
+// Longest-common-subsequence diff of the two instruction lists.
+fn diff_lines(old: &FuncInfo, new: &FuncInfo) -> Vec<DiffLine> {
+    let (n, m) = (old.keys.len(), new.keys.len());
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old.keys[i] == new.keys[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    let mut out = Vec::<DiffLine>::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old.keys[i] == new.keys[j] {
+            out.push(DiffLine::Same(new.texts[j].clone()));
+            i += 1;
+            j += 1;
+        }
+        else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push(DiffLine::Removed(old.texts[i].clone()));
+            i += 1;
+        }
+        else {
+            out.push(DiffLine::Added(new.texts[j].clone()));
+            j += 1;
+        }
+    }
+    out
+}
+
+pub fn diff_functions(old: &Disassembly, new: &Disassembly) -> FunctionDiff {
+    let old_funcs = collect_functions(old);
+    let new_funcs = collect_functions(new);
+    let mut old_used = vec![false; old_funcs.len()];
+    let mut new_used = vec![false; new_funcs.len()];
+    let mut pairs = Vec::<(usize, usize, &'static str)>::new();
+
+    let new_by_name: HashMap<&str, usize> = new_funcs.iter().enumerate()
+        .filter(|(_, f)| has_real_name(f))
+        .map(|(i, f)| (f.name.as_str(), i))
+        .collect();
+    for (i, f) in old_funcs.iter().enumerate().filter(|(_, f)| has_real_name(f)) {
+        if let Some(j) = new_by_name.get(f.name.as_str()) {
+            pairs.push((i, *j, "name"));
+            old_used[i] = true;
+            new_used[*j] = true;
+        }
+    }
+
+    for (i, f) in old_funcs.iter().enumerate() {
+        if old_used[i] {
+            continue;
+        }
+        if let Some(j) = (0..new_funcs.len()).find(|j| !new_used[*j] && new_funcs[*j].hash == f.hash) {
+            pairs.push((i, j, "hash"));
+            old_used[i] = true;
+            new_used[j] = true;
+        }
+    }
+
+    // Greedily pair the remaining functions, most similar first.
+    let mut candidates = Vec::<(f64, usize, usize)>::new();
+    for (i, a) in old_funcs.iter().enumerate().filter(|(i, _)| !old_used[*i]) {
+        for (j, b) in new_funcs.iter().enumerate().filter(|(j, _)| !new_used[*j]) {
+            let score = similarity(a, b);
+            if score >= MIN_SIMILARITY {
+                candidates.push((score, i, j));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+    for (_, i, j) in candidates {
+        if !old_used[i] && !new_used[j] {
+            pairs.push((i, j, "cfg"));
+            old_used[i] = true;
+            new_used[j] = true;
+        }
+    }
+
+    let mut diff = FunctionDiff { identical: 0, changed: Vec::new(), added: Vec::new(), removed: Vec::new() };
+    pairs.sort_by_key(|(i, _, _)| old_funcs[*i].addr);
+    for (i, j, matched_by) in pairs {
+        let (a, b) = (&old_funcs[i], &new_funcs[j]);
+        if a.keys == b.keys {
+            diff.identical += 1;
+            continue;
+        }
+        diff.changed.push(ChangedFunction {
+            old_name: a.name.clone(),
+            new_name: b.name.clone(),
+            old_addr: a.addr,
+            new_addr: b.addr,
+            matched_by,
+            lines: diff_lines(a, b),
+        });
+    }
+    diff.removed = old_funcs.iter().zip(&old_used).filter(|(_, used)| !**used).map(|(f, _)| (f.name.clone(), f.addr)).collect();
+    diff.added = new_funcs.iter().zip(&new_used).filter(|(_, used)| !**used).map(|(f, _)| (f.name.clone(), f.addr)).collect();
+    diff
+}
+
+impl FunctionDiff {
+    pub fn print(&self) -> String {
+        let mut out = format!("{} identical, {} changed, {} added, {} removed function(s)\n",
+            self.identical, self.changed.len(), self.added.len(), self.removed.len());
+        for (name, addr) in &self.removed {
+            out += format!("removed {} ({:#010x})\n", name, addr).as_str();
+        }
+        for (name, addr) in &self.added {
+            out += format!("added   {} ({:#010x})\n", name, addr).as_str();
+        }
+        for func in &self.changed {
+            out += format!("changed {} ({:#010x}) -> {} ({:#010x}), matched by {}\n",
+                func.old_name, func.old_addr, func.new_name, func.new_addr, func.matched_by).as_str();
+            for line in &func.lines {
+                out += match line {
+                    DiffLine::Same(text) => format!("      {}\n", text),
+                    DiffLine::Removed(text) => format!("    - {}\n", text),
+                    DiffLine::Added(text) => format!("    + {}\n", text),
+                }.as_str();
+            }
+        }
+        out
+    }
+}
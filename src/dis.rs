@@ -1,20 +1,52 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+use crate::json::Value;
+use crate::cancel::CancelToken;
+use crate::error::BaretkError;
+use crate::options::{AnalysisOptions, Sweep, ARCHITECTURES};
 use crate::prog;
+use crate::util::RWX_EXEC;
+use crate::reg::{Reg, RegClass};
 use crate::arm;
+use crate::aarch64;
 use crate::x86;
 use crate::riscv;
+use crate::xref::XrefDb;
 
+#[derive(PartialEq)]
 pub enum Operand {
     Nothing,
-    Register(&'static str),
-    Memory(&'static str, &'static str, i64, u8),
+    Register(Reg),
+    // Base, index, scale, offset and access size. Without a base the offset
+    // is an absolute address.
+    Memory(Option<Reg>, Option<Reg>, u8, i64, u8),
     Immediate(i64),
+    ShiftedRegister(Reg, u8, i64),
+    RegisterShiftedRegister(Reg, u8, Reg),
+}
+
+// Shift types for shifted register operands.
+pub const SHIFT_LSL: u8 = 0x0;
+pub const SHIFT_LSR: u8 = 0x1;
+pub const SHIFT_ASR: u8 = 0x2;
+pub const SHIFT_ROR: u8 = 0x3;
+
+fn shift_name(shift: u8) -> &'static str {
+    match shift {
+        SHIFT_LSL => "lsl",
+        SHIFT_LSR => "lsr",
+        SHIFT_ASR => "asr",
+        SHIFT_ROR => "ror",
+        _ => "?"
+    }
 }
 
 impl Operand {
     pub fn print(&self) -> String {
         match *self {
             Operand::Register(name) => format!("{}", name),
-            Operand::Memory(base, index, offset, size) => {
+            Operand::Memory(base, index, scale, offset, size) => {
                 let word_name = match size {
                     1 => "BYTE",
                     2 => "WORD",
@@ -22,25 +54,64 @@ impl Operand {
                     8 => "QWORD",
                     _ => "?"
                 };
-                if base == "" {
-                    format!("{} [{}]", word_name, offset)
-                }
-                else if base == "." {
-                    format!("{} [pc+{}]", word_name, offset)
-                }
-                else if index != "" {
-                    format!("{} [{}+{}*{}]", word_name, base, index, offset)
-                }
-                else {
-                    format!("{} [{}+{}]", word_name, base, offset)
+                match (base, index) {
+                    (None, _) => format!("{} [{}]", word_name, offset),
+                    (Some(base), Some(index)) => format!("{} [{}+{}*{}+{}]", word_name, base, index, scale, offset),
+                    (Some(base), None) => format!("{} [{}+{}]", word_name, base, offset),
                 }
             },
             Operand::Immediate(i) => format!("{}", i),
+            Operand::ShiftedRegister(reg, shift, amount) => format!("{} {} {}", reg, shift_name(shift), amount),
+            Operand::RegisterShiftedRegister(reg, shift, amount) => format!("{} {} {}", reg, shift_name(shift), amount),
             _ => format!("()")
         }
     }
 }
 
+// Condition codes, stored in the low bits of Instruction::flags.
+pub const COND_MASK: u64 = 0x1f;
+pub const COND_AL: u64 = 0x0;
+pub const COND_EQ: u64 = 0x1;
+pub const COND_NE: u64 = 0x2;
+pub const COND_LT: u64 = 0x3;
+pub const COND_LE: u64 = 0x4;
+pub const COND_GT: u64 = 0x5;
+pub const COND_GE: u64 = 0x6;
+pub const COND_LTU: u64 = 0x7;
+pub const COND_LEU: u64 = 0x8;
+pub const COND_GTU: u64 = 0x9;
+pub const COND_GEU: u64 = 0xa;
+pub const COND_MI: u64 = 0xb;
+pub const COND_PL: u64 = 0xc;
+pub const COND_VS: u64 = 0xd;
+pub const COND_VC: u64 = 0xe;
+pub const COND_PE: u64 = 0xf;
+pub const COND_PO: u64 = 0x10;
+
+// Set when the instruction updates the condition flags with its result.
+pub const FLAG_SETS_FLAGS: u64 = 0x100;
+// Set on loads/stores that write the effective address back to the base
+// register before (pre-index) or after (post-index) the access.
+pub const FLAG_PRE_INDEX: u64 = 0x200;
+pub const FLAG_POST_INDEX: u64 = 0x400;
+// Set on loads/stores whose address is the base plus the register offset in
+// the operand after the Memory operand, which can't hold it as a scaled index.
+pub const FLAG_REGISTER_OFFSET: u64 = 0x800;
+// Set when that offset operand is subtracted from the base rather than added.
+pub const FLAG_SUBTRACT_OFFSET: u64 = 0x1000;
+
+// What an instruction does, as returned by Instruction::kind.
+pub const KIND_JUMP: u32 = 0x1;
+pub const KIND_CALL: u32 = 0x2;
+pub const KIND_RETURN: u32 = 0x4;
+pub const KIND_SYSCALL: u32 = 0x8;
+// Only executes, or only branches, when a condition holds.
+pub const KIND_CONDITIONAL: u32 = 0x10;
+// The jump or call target comes from a register or memory.
+pub const KIND_INDIRECT: u32 = 0x20;
+pub const KIND_MEMORY: u32 = 0x40;
+pub const KIND_SETS_FLAGS: u32 = 0x80;
+
 // Common instruction struct for all architectures
 pub struct Instruction {
     pub opcode: &'static str,
@@ -56,40 +127,83 @@ impl Instruction {
         }
         str.strip_suffix(",").unwrap_or(str.as_str()).to_string()
     }
+
+    pub fn cond(&self) -> u64 {
+        self.flags & COND_MASK
+    }
+
+    pub fn sets_flags(&self) -> bool {
+        (self.flags & FLAG_SETS_FLAGS) != 0
+    }
+
+    // KIND_* bits describing the instruction independently of the architecture.
+    pub fn kind(&self) -> u32 {
+        let register = |i: usize, class: RegClass| matches!(self.operands.get(i), Some(Operand::Register(r)) if r.class() == class);
+        let target = |i: usize| if matches!(self.operands.get(i), Some(Operand::Immediate(_))) { 0 } else { KIND_INDIRECT };
+        let writes_pc = self.operands.iter().any(|op| matches!(op, Operand::Register(r) if r.class() == RegClass::ProgramCounter));
+        let mut kind = match self.opcode {
+            "b" => KIND_JUMP | target(0),
+            "call" => KIND_CALL | target(0),
+            "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" | "tbz" | "tbnz" => KIND_JUMP | KIND_CONDITIONAL,
+            "jal" if register(0, RegClass::Zero) => KIND_JUMP,
+            "jal" => KIND_CALL,
+            "jalr" if register(0, RegClass::Zero) && register(1, RegClass::Link) => KIND_RETURN,
+            "jalr" if register(0, RegClass::Zero) => KIND_JUMP | KIND_INDIRECT,
+            "jalr" => KIND_CALL | KIND_INDIRECT,
+            "ret" => KIND_RETURN,
+            "pop" | "ldm" if writes_pc => KIND_RETURN,
+            "syscall" | "svc" => KIND_SYSCALL,
+            _ => 0,
+        };
+        if self.cond() != COND_AL {
+            kind |= KIND_CONDITIONAL;
+        }
+        if self.operands.iter().any(|op| matches!(op, Operand::Memory(..))) {
+            kind |= KIND_MEMORY;
+        }
+        if self.sets_flags() {
+            kind |= KIND_SETS_FLAGS;
+        }
+        kind
+    }
 }
 
 pub enum InstructionListing {
+    Arm(Vec<arm::Instruction>),
+    AArch64(Vec<aarch64::Instruction>),
     Rv(Vec<riscv::Instruction>),
     X86(Vec<x86::Instruction>),
     Unknown,
 }
 
 impl InstructionListing {
-    pub fn print(&self, addr: u64, bytes: Option<&[u8]>) -> String {
+    // `labels` are printed above the instructions at their addresses, and
+    // `comments` after them. Only instructions whose section offset falls
+    // inside the range are printed.
+    pub fn print(&self, addr: u64, range: Range<usize>, bytes: Option<&[u8]>, labels: &HashMap<u64, &str>, comments: &HashMap<u64, String>) -> String {
         let mut out = String::new();
+        let mut line = |text: String, offset: usize, size: usize| {
+            print_line(&mut out, &text, addr + offset as u64, bytes.map(|b| &b[offset..offset + size]), labels, comments);
+        };
         match self {
+            Self::Arm(instrs) => {
+                for ins in instrs.iter().filter(|ins| range.contains(&ins.offset())) {
+                    line(ins.print(), ins.offset(), ins.size());
+                }
+            },
+            Self::AArch64(instrs) => {
+                for ins in instrs.iter().filter(|ins| range.contains(&ins.offset())) {
+                    line(ins.print(), ins.offset(), ins.size());
+                }
+            },
             Self::Rv(instrs) => {
-                for ins in instrs {
-                    out += format!("    {:32}", ins.print()).as_str();
-                    if let Some(b) = bytes {
-                        out += format!("({:02x}", b[ins.offset()]).as_str();
-                        for i in 1..ins.size() {
-                            out += format!(" {:02x}", b[ins.offset() + i]).as_str();
-                        }
-                        out += ")\n";
-                    }
+                for ins in instrs.iter().filter(|ins| range.contains(&ins.offset())) {
+                    line(ins.print(), ins.offset(), ins.size());
                 }
             },
             Self::X86(instrs) => {
-                for ins in instrs {
-                    out += format!("    {:32}", ins.print()).as_str();
-                    if let Some(b) = bytes {
-                        out += format!("({:02x}", b[ins.offset()]).as_str();
-                        for i in 1..ins.size() {
-                            out += format!(" {:02x}", b[ins.offset() + i]).as_str();
-                        }
-                        out += ")\n";
-                    }
+                for ins in instrs.iter().filter(|ins| range.contains(&ins.offset())) {
+                    line(ins.print(), ins.offset(), ins.size());
                 }
             },
             _ => out += "unknown\n",
@@ -98,17 +212,36 @@ impl InstructionListing {
     }
 
     pub fn instruction_vec(&self) -> Vec<Instruction> {
+        self.instruction_vec_in(0..usize::MAX)
+    }
+
+    // Only instructions whose section offset falls inside the range.
+    pub fn instruction_vec_in(&self, range: Range<usize>) -> Vec<Instruction> {
         let mut out = Vec::<Instruction>::new();
         match self {
+            Self::Arm(arm) => { 
+                let iter = arm.iter().filter(|it| range.contains(&it.offset()));
+                for it in iter {
+                    out.push(it.into());
+                }
+                out
+            },
+            Self::AArch64(a64) => { 
+                let iter = a64.iter().filter(|it| range.contains(&it.offset()));
+                for it in iter {
+                    out.push(it.into());
+                }
+                out
+            },
             Self::Rv(rv) => { 
-                let iter = rv.into_iter();
+                let iter = rv.iter().filter(|it| range.contains(&it.offset()));
                 for it in iter {
                     out.push(it.into());
                 }
                 out
             },
             Self::X86(rv) => { 
-                let iter = rv.into_iter();
+                let iter = rv.iter().filter(|it| range.contains(&it.offset()));
                 for it in iter {
                     out.push(it.into());
                 }
@@ -117,65 +250,552 @@ impl InstructionListing {
             _ => out
         }
     }
+
+    // Section offsets of the same instructions returned by instruction_vec_in.
+    pub fn instruction_offset_vec_in(&self, range: Range<usize>) -> Vec<usize> {
+        match self {
+            Self::Arm(arm) => arm.iter().map(|it| it.offset()).filter(|offset| range.contains(offset)).collect(),
+            Self::AArch64(a64) => a64.iter().map(|it| it.offset()).filter(|offset| range.contains(offset)).collect(),
+            Self::Rv(rv) => rv.iter().map(|it| it.offset()).filter(|offset| range.contains(offset)).collect(),
+            Self::X86(x86) => x86.iter().map(|it| it.offset()).filter(|offset| range.contains(offset)).collect(),
+            _ => Vec::new()
+        }
+    }
+
+    // Sizes in bytes of the same instructions returned by instruction_vec_in.
+    pub fn instruction_size_vec_in(&self, range: Range<usize>) -> Vec<usize> {
+        match self {
+            Self::Arm(arm) => arm.iter().filter(|it| range.contains(&it.offset())).map(|it| it.size()).collect(),
+            Self::AArch64(a64) => a64.iter().filter(|it| range.contains(&it.offset())).map(|it| it.size()).collect(),
+            Self::Rv(rv) => rv.iter().filter(|it| range.contains(&it.offset())).map(|it| it.size()).collect(),
+            Self::X86(x86) => x86.iter().filter(|it| range.contains(&it.offset())).map(|it| it.size()).collect(),
+            _ => Vec::new()
+        }
+    }
+
+    // Native assembly text of the same instructions returned by instruction_vec_in.
+    pub fn instruction_text_vec_in(&self, range: Range<usize>) -> Vec<String> {
+        match self {
+            Self::Arm(arm) => arm.iter().filter(|it| range.contains(&it.offset())).map(|it| it.print()).collect(),
+            Self::AArch64(a64) => a64.iter().filter(|it| range.contains(&it.offset())).map(|it| it.print()).collect(),
+            Self::Rv(rv) => rv.iter().filter(|it| range.contains(&it.offset())).map(|it| it.print()).collect(),
+            Self::X86(x86) => x86.iter().filter(|it| range.contains(&it.offset())).map(|it| it.print()).collect(),
+            _ => Vec::new()
+        }
+    }
+}
+
+// One line of a listing, preceded by the label at its address if there is one.
+pub fn print_line(out: &mut String, text: &str, addr: u64, bytes: Option<&[u8]>, labels: &HashMap<u64, &str>, comments: &HashMap<u64, String>) {
+    if let Some(label) = labels.get(&addr) {
+        *out += format!("{}:\n", label).as_str();
+    }
+    *out += format!("    {:32}", text).as_str();
+    if let Some(b) = bytes {
+        *out += format!("({:02x}", b[0]).as_str();
+        for byte in &b[1..] {
+            *out += format!(" {:02x}", byte).as_str();
+        }
+        *out += ")";
+    }
+    if let Some(comment) = comments.get(&addr) {
+        *out += format!(" ; {}", comment).as_str();
+    }
+    *out += "\n";
+}
+
+// How an instruction affects control flow.
+pub enum Flow {
+    Next,
+    Jump(Option<u64>),
+    Branch(Option<u64>),
+    Stop,
+}
+
+fn relative_target(op: Option<&Operand>, addr: u64) -> Option<u64> {
+    match op {
+        Some(Operand::Immediate(rel)) => Some(addr.wrapping_add(*rel as u64)),
+        _ => None,
+    }
+}
+
+pub fn flow(ins: &Instruction, addr: u64) -> Flow {
+    let writes_pc = ins.operands.iter().any(|op| matches!(op, Operand::Register(r) if r.class() == RegClass::ProgramCounter));
+    match ins.opcode {
+        "b" if ins.cond() == COND_AL => Flow::Jump(relative_target(ins.operands.first(), addr)),
+        "b" => Flow::Branch(relative_target(ins.operands.first(), addr)),
+        "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" | "tbz" | "tbnz" => Flow::Branch(relative_target(ins.operands.get(2), addr)),
+        "jal" if matches!(ins.operands.first(), Some(Operand::Register(r)) if r.class() == RegClass::Zero) => Flow::Jump(relative_target(ins.operands.get(1), addr)),
+        "jalr" if matches!(ins.operands.first(), Some(Operand::Register(r)) if r.class() == RegClass::Zero) => Flow::Stop,
+        "ret" if ins.cond() == COND_AL => Flow::Stop,
+        "pop" | "ldm" if writes_pc && ins.cond() == COND_AL => Flow::Stop,
+        _ => Flow::Next,
+    }
+}
+
+// Target of a direct call.
+pub fn call_target(ins: &Instruction, addr: u64) -> Option<u64> {
+    let rel = match ins.opcode {
+        "call" => ins.operands.first(),
+        "jal" if !matches!(ins.operands.first(), Some(Operand::Register(r)) if r.class() == RegClass::Zero) => ins.operands.get(1),
+        _ => None,
+    };
+    match rel {
+        Some(Operand::Immediate(rel)) => Some(addr.wrapping_add(*rel as u64)),
+        _ => None,
+    }
 }
 
 pub struct DisassemblySection {
     pub section_name: String,
+    // Load address of the section, which instruction offsets are relative to.
+    pub addr: u64,
     pub instructions: InstructionListing,
 }
 
+pub struct Function {
+    pub name: String,
+    pub addr: u64,
+    // Name of the section the function is in.
+    pub section: String,
+    // Section offsets covered by the function.
+    pub range: Range<usize>,
+}
+
+// One instruction of a listing, as print_json writes it and serde serializes it.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct InstructionRecord {
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_hex"))]
+    pub addr: u64,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_bytes"))]
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    pub operands: Vec<String>,
+    // Name of the function starting at the instruction.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub label: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub comment: Option<String>,
+}
+
+impl InstructionRecord {
+    pub fn to_json(&self) -> Value {
+        let mut members = vec![
+            ("addr".to_string(), Value::hex(self.addr)),
+            ("bytes".to_string(), Value::String(self.bytes.iter().map(|b| format!("{:02x}", b)).collect())),
+            ("mnemonic".to_string(), Value::String(self.mnemonic.clone())),
+            ("operands".to_string(), Value::Array(self.operands.iter().cloned().map(Value::String).collect())),
+        ];
+        if let Some(label) = &self.label {
+            members.push(("label".to_string(), Value::String(label.clone())));
+        }
+        if let Some(comment) = &self.comment {
+            members.push(("comment".to_string(), Value::String(comment.clone())));
+        }
+        Value::Object(members)
+    }
+}
+
+// The program's executable sections, each decoded separately, in address
+// order. The code section is always among them, even when it's missing from a
+// malformed file.
 pub struct Disassembly {
     program: prog::Program,
-    section: DisassemblySection,
+    sections: Vec<DisassemblySection>,
+    // Index of the code section in `sections`.
+    code: usize,
 }
 
 impl Disassembly {
+    fn new(program: prog::Program, sections: Vec<DisassemblySection>) -> Disassembly {
+        let code = sections.iter().position(|section| section.section_name == program.code_section()).unwrap_or(0);
+        Disassembly { program, sections, code }
+    }
+
     pub fn program(&self) -> &prog::Program {
         &self.program
     }
 
+    // The code section, which the analyses other than printing and
+    // decompiling look at.
     pub fn section(&self) -> &DisassemblySection {
-        &self.section
+        &self.sections[self.code]
+    }
+
+    pub fn sections(&self) -> &[DisassemblySection] {
+        &self.sections
+    }
+
+    pub fn section_named(&self, name: &str) -> Option<&DisassemblySection> {
+        self.sections.iter().find(|section| section.section_name == name)
+    }
+
+    // The disassembled section containing the address.
+    pub fn section_at(&self, addr: u64) -> Option<&DisassemblySection> {
+        self.sections.iter().find(|section| self.section_bounds(section).contains(&addr))
+    }
+
+    // Load address of the code section.
+    pub fn section_addr(&self) -> u64 {
+        self.section().addr
+    }
+
+    // Addresses the section's bytes are loaded at.
+    fn section_bounds(&self, section: &DisassemblySection) -> Range<u64> {
+        match self.program.section_table.get(&section.section_name) {
+            Some(loaded) => loaded.addr..loaded.addr + loaded.bytes.len() as u64,
+            None => section.addr..section.addr,
+        }
+    }
+
+    // Finds a function by symbol name or "0x..." address in any of the
+    // sections, returning its name, section and the section offsets it covers.
+    // Without a symbol size, the function is assumed to run up to the next symbol.
+    pub fn find_function(&self, func: &str) -> Result<(String, &DisassemblySection, Range<usize>), BaretkError> {
+        let program = &self.program;
+        let (name, start, size) = match program.find_symbol(func) {
+            Some(sym) => (sym.name.clone(), sym.addr, sym.size),
+            None => match func.strip_prefix("0x").and_then(|hex| u64::from_str_radix(hex, 16).ok()) {
+                Some(addr) => (format!("sub_{:08x}", addr), addr, 0),
+                None => return Err(BaretkError::NotFound(format!("Function \"{}\"", func))),
+            }
+        };
+        let section = match self.section_at(start) {
+            Some(section) => section,
+            None => return Err(BaretkError::NotFound(format!("Function \"{}\" at {:#010x} in an executable section", func, start))),
+        };
+        let Range { start: section_start, end: section_end } = self.section_bounds(section);
+        let end = if size != 0 {
+            start + size
+        } else {
+            program.symbol_index().next_after(start)
+                .map_or(section_end, |sym| sym.addr)
+                .min(section_end)
+        };
+        Ok((name, section, (start - section_start) as usize..(end - section_start) as usize))
+    }
+
+    // Like find_function, for the analyses that only look at the code section.
+    pub fn function_range(&self, func: &str) -> Result<(String, Range<usize>), BaretkError> {
+        match self.find_function(func) {
+            Ok((name, section, range)) if section.section_name == self.section().section_name => Ok((name, range)),
+            Ok((_, section, range)) => Err(BaretkError::NotFound(format!("Function \"{}\" at {:#010x} in {}",
+                func, section.addr + range.start as u64, self.section().section_name))),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Every function in the code section: function symbols, plus the targets
+    // of direct calls for stripped code (named sub_<addr>). Each runs up to
+    // the next one.
+    pub fn functions(&self) -> Vec<Function> {
+        self.functions_in(std::slice::from_ref(self.section()))
+    }
+
+    // Every function in every section, like functions.
+    pub fn all_functions(&self) -> Vec<Function> {
+        self.functions_in(&self.sections)
+    }
+
+    fn functions_in(&self, sections: &[DisassemblySection]) -> Vec<Function> {
+        let mut starts = std::collections::BTreeMap::<u64, (String, u64)>::new();
+        for addr in XrefDb::build(self).call_targets() {
+            starts.insert(addr, (format!("sub_{:08x}", addr), 0));
+        }
+        for sym in self.program.symbol_table.iter().filter(|sym| sym.is_func && !sym.name.is_empty()) {
+            starts.insert(sym.addr, (sym.name.clone(), sym.size));
+        }
+        let mut functions = Vec::<Function>::new();
+        for section in sections {
+            let Range { start, end } = self.section_bounds(section);
+            let addrs: Vec<u64> = starts.range(start..end).map(|(addr, _)| *addr).collect();
+            functions.extend(addrs.iter().enumerate().map(|(i, addr)| {
+                let (name, size) = &starts[addr];
+                let next = addrs.get(i + 1).copied().unwrap_or(end);
+                let func_end = if *size != 0 { (addr + size).min(end) } else { next };
+                Function { name: name.clone(), addr: *addr, section: section.section_name.clone(), range: (addr - start) as usize..(func_end - start) as usize }
+            }));
+        }
+        functions
     }
 
     pub fn print(&self, show_bytes: bool) -> String {
+        self.print_range(show_bytes, 0..u64::MAX)
+    }
+
+    // The listing of the instructions whose addresses fall inside the range,
+    // a section at a time.
+    pub fn print_range(&self, show_bytes: bool, addrs: Range<u64>) -> String {
         let mut out = String::new();
-        out += format!(".section {}\n", self.section.section_name).as_str();
-        if let Some(section) = self.program.section_table.get(&self.section.section_name) {
-            out += format!(".org {:#010x}\n", section.addr).as_str();
-            let bytes = match show_bytes {
-                true => Some(section.bytes.as_slice()),
-                _ => None,
-            };
-            out += self.section.instructions.print(section.addr, bytes).as_str();
+        let labels: HashMap<u64, &str> = self.program.symbol_table.iter()
+            .filter(|sym| sym.is_func && !sym.name.is_empty())
+            .map(|sym| (sym.addr, sym.name.as_str()))
+            .collect();
+        for listing in &self.sections {
+            let section = self.program.section_table.get(&listing.section_name);
+            let bounds = self.section_bounds(listing);
+            if section.is_some() && (bounds.end <= addrs.start || bounds.start >= addrs.end) {
+                continue;
+            }
+            if !out.is_empty() {
+                out += "\n";
+            }
+            out += format!(".section {}\n", listing.section_name).as_str();
+            if let Some(section) = section {
+                out += format!(".org {:#010x}\n", section.addr).as_str();
+                let bytes = match show_bytes {
+                    true => Some(section.bytes.as_slice()),
+                    _ => None,
+                };
+                let range = addrs.start.saturating_sub(section.addr) as usize..addrs.end.saturating_sub(section.addr) as usize;
+                out += listing.instructions.print(section.addr, range, bytes, &labels, &self.program.comments).as_str();
+            }
+            else {
+                let range = addrs.start as usize..addrs.end as usize;
+                out += listing.instructions.print(0x0, range, None, &labels, &self.program.comments).as_str();
+            }
         }
-        else {
-            out += self.section.instructions.print(0x0, None).as_str();
+        out
+    }
+
+    // Each instruction of every section with its bytes, and the function label
+    // and comment when there is one.
+    pub fn instructions(&self) -> impl Iterator<Item = InstructionRecord> + '_ {
+        let labels: HashMap<u64, &str> = self.program.symbol_table.iter()
+            .filter(|sym| sym.is_func && !sym.name.is_empty())
+            .map(|sym| (sym.addr, sym.name.as_str()))
+            .collect();
+        self.sections.iter().flat_map(move |section| {
+            let listing = &section.instructions;
+            let bytes = self.program.section_table.get(&section.section_name).map(|section| section.bytes.as_slice()).unwrap_or(&[]);
+            let offsets = listing.instruction_offset_vec_in(0..usize::MAX);
+            let sizes = listing.instruction_size_vec_in(0..usize::MAX);
+            let texts = listing.instruction_text_vec_in(0..usize::MAX);
+            let labels = labels.clone();
+            offsets.into_iter().zip(sizes).zip(texts).map(move |((offset, size), text)| {
+                let addr = section.addr + offset as u64;
+                let (mnemonic, operands) = split_instruction_text(&text);
+                InstructionRecord {
+                    addr,
+                    bytes: bytes.get(offset..offset + size).unwrap_or(&[]).to_vec(),
+                    mnemonic: mnemonic.to_string(),
+                    operands,
+                    label: labels.get(&addr).map(|label| label.to_string()),
+                    comment: self.program.comments.get(&addr).cloned(),
+                }
+            })
+        })
+    }
+
+    // One JSON object per line for each instruction.
+    pub fn print_json(&self) -> String {
+        let mut out = String::new();
+        for record in self.instructions() {
+            record.to_json().write(&mut out);
+            out += "\n";
         }
         out
     }
 }
 
-pub fn disassemble(bytes: &[u8]) -> Disassembly {
-    let program = prog::load_program_from_bytes(bytes);
-    disassemble_program(program)
+// A disassembly serializes as its instruction records.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Disassembly {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.instructions())
+    }
 }
 
-pub fn disassemble_program(program: prog::Program) -> Disassembly {
-    let default_section = if program.section_table.contains_key(".text") { ".text" } else { "file" };
-    let section_name = String::from(default_section);
-    let section = match program.machine_type.as_str() {
-        "arm" => arm::disassemble_arm(&program.section_table[default_section], &section_name, &program),
-        "x86" => x86::disassemble_x86(&program.section_table[default_section], &section_name, &program),
-        "amd64" => x86::disassemble_x86(&program.section_table[default_section], &section_name, &program), // TODO: Maybe separate amd64 and x86 disassembly code?
-        "riscv" => riscv::disassemble_riscv(&program.section_table[default_section], &section_name, &program),
-        _ => {
-            eprintln!("Can't disassemble this. Not enough info or not able to disassemble architecture yet.\nArch: {}", program.machine_type);
-            DisassemblySection { section_name: section_name.clone(), instructions: InstructionListing::Unknown }
+// Splits assembly text into the mnemonic and its operands, keeping commas inside
+// memory operands and register lists, e.g. "ldr r0, [r1, #4]".
+pub fn split_instruction_text(text: &str) -> (&str, Vec<String>) {
+    let (mnemonic, rest) = text.trim().split_once(' ').unwrap_or((text.trim(), ""));
+    let mut operands = Vec::<String>::new();
+    let mut depth = 0i32;
+    let mut current = String::new();
+    for c in rest.chars() {
+        match c {
+            '[' | '{' | '(' => depth += 1,
+            ']' | '}' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(current.trim().to_string());
+                current.clear();
+                continue;
+            },
+            _ => (),
         }
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        operands.push(current.trim().to_string());
+    }
+    (mnemonic, operands)
+}
+
+pub fn disassemble(bytes: &[u8]) -> Result<Disassembly, BaretkError> {
+    let program = prog::load_program_from_bytes(bytes)?;
+    Ok(disassemble_program(program))
+}
+
+// How to decode a buffer of code with no headers, such as shellcode.
+pub struct BufferSpec {
+    // One of options::ARCHITECTURES.
+    pub arch: String,
+    // 0 for the architecture's usual width.
+    pub bits: u8,
+    // 0 for little endian.
+    pub endianess: u8,
+    // Address of the first byte.
+    pub base_addr: u64,
+}
+
+// Disassembles the whole buffer as code of the given architecture.
+pub fn disassemble_buffer(bytes: &[u8], spec: &BufferSpec) -> Result<Disassembly, BaretkError> {
+    if !ARCHITECTURES.contains(&spec.arch.as_str()) {
+        return Err(BaretkError::UnsupportedArch(spec.arch.clone()))
+    }
+    let program = prog::build_program_from_binary(bytes, Some(spec.bits), Some(spec.endianess), None);
+    let options = AnalysisOptions { arch: Some(spec.arch.clone()), base_addr: Some(spec.base_addr), ..AnalysisOptions::default() };
+    disassemble_with_options(program, &options)
+}
+
+// Err(Cancelled) if options.cancel is cancelled before the disassembly is done.
+pub fn disassemble_with_options(mut program: prog::Program, options: &AnalysisOptions) -> Result<Disassembly, BaretkError> {
+    prog::apply_options(&mut program, options);
+    let dis = match options.sweep {
+        Sweep::Linear => disassemble_linear(program, &options.cancel),
+        Sweep::Recursive => disassemble_recursive(program, &options.cancel),
     };
-    Disassembly {
-        program,
-        section,
+    options.cancel.check()?;
+    Ok(dis)
+}
+
+// Decodes from each seed address, following branches and direct calls, until
+// a jump, return or undecodable bytes. decode gives the instruction at a
+// section offset, its common form and its size.
+fn sweep<T>(seeds: Vec<u64>, base: u64, len: usize, cancel: &CancelToken, mut decode: impl FnMut(usize) -> Option<(T, Instruction, usize)>) -> Vec<T> {
+    let mut found = BTreeMap::<usize, T>::new();
+    let mut todo = seeds;
+    while let Some(mut addr) = todo.pop() {
+        while !cancel.is_cancelled() && addr >= base && addr < base + len as u64 && !found.contains_key(&((addr - base) as usize)) {
+            let offset = (addr - base) as usize;
+            let (ins, common, size) = match decode(offset) {
+                Some(decoded) if decoded.2 > 0 => decoded,
+                _ => break,
+            };
+            found.insert(offset, ins);
+            todo.extend(call_target(&common, addr));
+            match flow(&common, addr) {
+                Flow::Next => (),
+                Flow::Branch(target) => todo.extend(target),
+                Flow::Jump(target) => {
+                    todo.extend(target);
+                    break;
+                },
+                Flow::Stop => break,
+            }
+            addr += size as u64;
+        }
+    }
+    found.into_values().collect()
+}
+
+// Disassembles only the code reachable from the entry point and the function
+// symbols, so data between functions isn't decoded as instructions.
+fn disassemble_recursive(program: prog::Program, cancel: &CancelToken) -> Disassembly {
+    if !matches!(program.machine_type.as_str(), "arm" | "aarch64" | "x86" | "amd64" | "riscv") {
+        return unsupported_arch(program)
     }
+    let seeds: Vec<u64> = std::iter::once(program.entry_point)
+        .chain(program.symbol_table.iter().filter(|sym| sym.is_func).map(|sym| sym.addr))
+        .collect();
+    disassemble_sections(program, |program, section_name, section| {
+        let (base, bytes) = (section.addr, section.bytes.as_slice());
+        let seeds = seeds.clone();
+        let instructions = match program.machine_type.as_str() {
+            "arm" => {
+                let mut decoder = arm::Decoder::new(program, section_name, section);
+                InstructionListing::Arm(sweep(seeds, base, bytes.len(), cancel, |offset| {
+                    decoder.decode_at(offset).map(|ins| (ins, (&ins).into(), ins.size()))
+                }))
+            },
+            "aarch64" => InstructionListing::AArch64(sweep(seeds, base, bytes.len(), cancel, |offset| {
+                aarch64::disassemble_aarch64_at(bytes, offset).map(|ins| (ins, (&ins).into(), ins.size()))
+            })),
+            "x86" | "amd64" => InstructionListing::X86(sweep(seeds, base, bytes.len(), cancel, |offset| {
+                x86::disassemble_x86_at(bytes, offset).map(|ins| (ins, (&ins).into(), ins.size()))
+            })),
+            _ => InstructionListing::Rv(sweep(seeds, base, bytes.len(), cancel, |offset| {
+                riscv::disassemble_riscv_at(bytes, offset).map(|ins| (ins, (&ins).into(), ins.size()))
+            })),
+        };
+        DisassemblySection { section_name: section_name.clone(), addr: base, instructions }
+    })
+}
+
+// Decodes a section like disassemble_program without keeping the listing: f
+// gets each instruction with its section offset, size and text, and returns
+// false to stop. Returns false if the architecture isn't supported.
+pub fn for_each_instruction(program: &prog::Program, section_name: &str, mut f: impl FnMut(&Instruction, usize, usize, String) -> bool) -> bool {
+    let section = match program.section_table.get(section_name) {
+        Some(section) => section,
+        None => return false,
+    };
+    let bytes = section.bytes.as_slice();
+    match program.machine_type.as_str() {
+        "arm" => arm::decode_arm(section, section_name, program, |ins| f(&(&ins).into(), ins.offset(), ins.size(), ins.print())),
+        "aarch64" => aarch64::decode_aarch64(bytes, |ins| f(&(&ins).into(), ins.offset(), ins.size(), ins.print())),
+        "x86" | "amd64" => x86::decode_x86(bytes, |ins| f(&(&ins).into(), ins.offset(), ins.size(), ins.print())),
+        "riscv" => riscv::decode_riscv(bytes, |ins| f(&(&ins).into(), ins.offset(), ins.size(), ins.print())),
+        _ => return false,
+    }
+    true
+}
+
+pub fn disassemble_program(program: prog::Program) -> Disassembly {
+    disassemble_linear(program, &CancelToken::new())
+}
+
+// Names of the sections to disassemble: the code section and every other
+// executable section with bytes, in address order.
+pub fn executable_sections(program: &prog::Program) -> Vec<&String> {
+    let mut names: Vec<&String> = program.section_table.iter()
+        .filter(|(name, section)| name.as_str() == program.code_section() || (section.perm & RWX_EXEC != 0 && !section.bytes.is_empty()))
+        .map(|(name, _)| name)
+        .collect();
+    names.sort_by_key(|name| (program.section_table[*name].addr, *name));
+    names
+}
+
+// Decodes each executable section with `decode`, in address order. A
+// permissive load can leave a malformed file without its code section, which
+// is kept as an empty listing.
+fn disassemble_sections(program: prog::Program, mut decode: impl FnMut(&prog::Program, &String, &prog::Section) -> DisassemblySection) -> Disassembly {
+    let mut sections: Vec<DisassemblySection> = executable_sections(&program).into_iter()
+        .map(|name| decode(&program, name, &program.section_table[name]))
+        .collect();
+    if !program.section_table.contains_key(program.code_section()) {
+        log::error!("There's no code section ({}) to disassemble.", program.code_section());
+        sections.insert(0, DisassemblySection { section_name: String::from(program.code_section()), addr: 0, instructions: InstructionListing::Unknown });
+    }
+    Disassembly::new(program, sections)
+}
+
+fn unsupported_arch(program: prog::Program) -> Disassembly {
+    log::error!("Can't disassemble this. Not enough info or not able to disassemble architecture yet.\nArch: {}", program.machine_type);
+    disassemble_sections(program, |_, section_name, section| {
+        DisassemblySection { section_name: section_name.clone(), addr: section.addr, instructions: InstructionListing::Unknown }
+    })
+}
+
+fn disassemble_linear(program: prog::Program, cancel: &CancelToken) -> Disassembly {
+    let decode = match program.machine_type.as_str() {
+        "arm" => arm::disassemble_arm,
+        "aarch64" => aarch64::disassemble_aarch64,
+        "x86" => x86::disassemble_x86,
+        "amd64" => x86::disassemble_x86, // TODO: Maybe separate amd64 and x86 disassembly code?
+        "riscv" => riscv::disassemble_riscv,
+        _ => return unsupported_arch(program),
+    };
+    disassemble_sections(program, |program, section_name, section| decode(section, section_name, program, cancel))
 }
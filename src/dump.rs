@@ -1,4 +1,4 @@
-use crate::{prog::Program, util::{BIG_ENDIAN, LITTLE_ENDIAN}};
+use crate::{dis, imports, packer, prog::{Program, Section}, reg::Arch, toolchain, util::{BIG_ENDIAN, LITTLE_ENDIAN}, validate};
 
 pub fn rwx_string(flags: u32) -> String {
     format!("{}{}{}", 
@@ -7,20 +7,126 @@ pub fn rwx_string(flags: u32) -> String {
         if (flags & 0x1) != 0x0 { "X" } else { " " })
 }
 
-pub fn dump_program(program: &Program) -> String {
+// Sections in address order with their permissions and the segments containing
+// them. With `perm` set, only sections having all of those RWX_* bits.
+pub fn sections_table(program: &Program, perm: u8) -> String {
+    let mut sections: Vec<(&String, &Section)> = program.section_table.iter()
+        .filter(|(name, section)| !name.is_empty() && section.perm & perm == perm)
+        .collect();
+    sections.sort_by_key(|(name, section)| (section.addr == 0, section.addr, section.offset, name.as_str()));
+    let mut s = format!("  {:<20} {:<18} {:<10} {:<10} {:<4} {:<6} {}\n", "Name", "Addr", "Offset", "Size", "Perm", "Align", "Segments");
+    for (name, section) in sections {
+        let size = section.bytes.len() as u64;
+        // Segments whose file bytes hold the section, by index in the program table.
+        let segments: Vec<String> = program.program_table.iter().enumerate()
+            .filter(|(_, seg)| size != 0 && section.perm != 0 && section.offset >= seg.offset && section.offset + size <= seg.offset + seg.size as u64)
+            .map(|(i, _)| i.to_string())
+            .collect();
+        s += format!("  {:<20} {:#018x} {:#010x} {:#010x} {:<4} {:<6} {}\n", name, section.addr, section.offset, size,
+            rwx_string(section.perm as u32), section.align, segments.join(",")).as_str();
+    }
+    s
+}
+
+pub fn dump_program(program: &Program, bytes: &[u8]) -> String {
     let mut s = String::new();
     s += format!("{}-bit, {}, {} executable\n", 
         program.bits,
         match program.endianess { LITTLE_ENDIAN => "little-endian", BIG_ENDIAN => "big-endian", _ => "?-endian" },
         program.machine_type
     ).as_str();
+    s += format!("Entry point: {:#010x}", program.entry_point).as_str();
+    if let Some(sym) = program.symbol_index().function_starting_at(program.entry_point) {
+        s += format!(" ({})", sym.name).as_str();
+    }
+    s += "\n";
     s += format!("Segments:\n  {:<6} {:<8} {:<8} {:<8} {:<8}\n", " Perm", "Offset", "PAddr", "VAddr", "Size").as_str();
     for item in program.program_table.iter() {
         s += format!("  {:<6} {:08x} {:08x} {:08x} {:08x}\n", rwx_string(item.perm as u32), item.offset, item.paddr, item.vaddr, item.size).as_str();
     }
-    s += format!("Sections:\n  {:<16} {:<8} {:<8}\n", " Name", "Offset", "Size").as_str();
-    for item in program.section_table.iter() {
-        s += format!("  {:<16} {:08x} {:08x}\n", item.0, item.1.addr, item.1.bytes.len()).as_str();
+    s += "Sections:\n";
+    s += sections_table(program, 0).as_str();
+    s += section_sizes(program).as_str();
+    let toolchains = toolchain::identify(program, bytes);
+    if !toolchains.is_empty() {
+        s += "Toolchain:\n";
+        for t in &toolchains {
+            s += format!("  {:<24} {:<12} {}\n", t.name, t.version.as_deref().unwrap_or("?"), t.evidence).as_str();
+        }
+    }
+    let imported = imports::imports(program);
+    if !imported.is_empty() {
+        let mut libraries: Vec<&str> = imported.iter().map(|import| import.library.as_str()).filter(|lib| !lib.is_empty()).collect();
+        libraries.sort();
+        libraries.dedup();
+        s += format!("Imports: {} function(s)", imported.len()).as_str();
+        if !libraries.is_empty() {
+            s += format!(" from {}", libraries.join(", ")).as_str();
+        }
+        s += "\n";
+        let capabilities = imports::capabilities(&imported);
+        if !capabilities.is_empty() {
+            s += "Behavior (imported APIs):\n";
+            for (category, names) in &capabilities {
+                s += format!("  {:<18} {}\n", category, names.join(", ")).as_str();
+            }
+        }
+    }
+    let report = packer::analyze(program);
+    if !report.findings.is_empty() {
+        s += "Packer/obfuscation:\n";
+        for finding in &report.findings {
+            s += format!("  {}\n", finding).as_str();
+        }
+        if report.is_suspicious() {
+            s += "  => probably packed or obfuscated, unpack it before analysis\n";
+        }
+    }
+    let validation = validate::validate(program, bytes);
+    if !validation.findings.is_empty() {
+        s += "Validation:\n";
+        for finding in &validation.findings {
+            s += format!("  {:<11} {}\n", if finding.suspicious { "suspicious" } else { "ok" }, finding.text).as_str();
+        }
+        if !validation.is_valid() {
+            s += "  => the headers don't add up, the file may be tampered with or hand-crafted\n";
+        }
+    }
+    s += functions_table(program).as_str();
+    s
+}
+
+// Bytes in each section, biggest first.
+fn section_sizes(program: &Program) -> String {
+    let mut sections: Vec<(&String, usize)> = program.section_table.iter()
+        .filter(|(name, section)| !name.is_empty() && !section.bytes.is_empty())
+        .map(|(name, section)| (name, section.bytes.len()))
+        .collect();
+    if sections.is_empty() {
+        return String::new()
+    }
+    sections.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    let total: usize = sections.iter().map(|(_, size)| size).sum();
+    let mut s = String::from("Section sizes:\n");
+    for (name, size) in sections {
+        s += format!("  {:<20} {:>10} byte(s) {:>5.1}%\n", name, size, size as f64 * 100.0 / total as f64).as_str();
+    }
+    s
+}
+
+// The functions found by disassembling the executable sections: symbols,
+// plus the targets of direct calls in stripped code.
+fn functions_table(program: &Program) -> String {
+    if Arch::from_machine_type(&program.machine_type).is_none() {
+        return String::new()
+    }
+    let functions = dis::disassemble_program(program.clone()).all_functions();
+    if functions.is_empty() {
+        return String::new()
+    }
+    let mut s = format!("Functions: {}\n  {:<18} {:<10} {}\n", functions.len(), "Addr", "Size", "Name");
+    for func in &functions {
+        s += format!("  {:#018x} {:#010x} {}\n", func.addr, func.range.len(), func.name).as_str();
     }
     s
 }
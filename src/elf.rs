@@ -1,40 +1,47 @@
-use std::{collections::HashMap, usize};
-use crate::prog::{Program, Section, Segment};
-use crate::util::{read_u16_from_slice, read_u32_from_slice, read_u32_to_u64_from_slice, read_u64_from_slice, BIG_ENDIAN, LITTLE_ENDIAN};
+use std::{collections::HashMap, sync::Arc, usize};
+use crate::error::BaretkError;
+use crate::options::ParseMode;
+use crate::prog::{self, Diagnostics, Program, Section, Segment, Symbol};
+use crate::util::{read_checked, read_u16_checked, read_u32_checked, read_u64_checked, read_u8_checked, BIG_ENDIAN, LITTLE_ENDIAN, RWX_EXEC, RWX_READ, RWX_WRITE};
+use crate::validate::{check, Finding};
 
-struct Header {
-    class: u8,
-    data: u8,
+pub struct Header {
+    pub class: u8,
+    pub data: u8,
     // version: u8,
     // abi: u8,
     // abi_version: u8,
 }
 
-fn read_header(bytes: &[u8]) -> Header {
-    Header{
-        class: bytes[0x04],
-        data: bytes[0x05],
-        // version: bytes[0x06],
-        // abi: bytes[0x07],
-        // abi_version: bytes[0x08],
+fn read_header(bytes: &[u8]) -> Result<Header, BaretkError> {
+    let ident = read_checked(bytes, 0, 0x10, "ELF identification")?;
+    if ident[0x05] != LITTLE_ENDIAN && ident[0x05] != BIG_ENDIAN {
+        return Err(BaretkError::malformed("ELF identification", 0x5, format!("unknown byte order {}", ident[0x05])))
     }
+    Ok(Header{
+        class: ident[0x04],
+        data: ident[0x05],
+        // version: ident[0x06],
+        // abi: ident[0x07],
+        // abi_version: ident[0x08],
+    })
 }
 
 #[derive(Debug)]
-struct HeaderCommon {
-    e_type: u16,
-    e_machine: u16,
-    e_version: u32,
-    e_entry: u64,
-    e_phoff: u64,
-    e_shoff: u64,
-    e_flags: u32,
-    e_ehsize: u16,
-    e_phentsize: u16,
-    e_phnum: u16,
-    e_shentsize: u16,
-    e_shnum: u16,
-    e_shstrndx: u16,
+pub struct HeaderCommon {
+    pub e_type: u16,
+    pub e_machine: u16,
+    pub e_version: u32,
+    pub e_entry: u64,
+    pub e_phoff: u64,
+    pub e_shoff: u64,
+    pub e_flags: u32,
+    pub e_ehsize: u16,
+    pub e_phentsize: u16,
+    pub e_phnum: u16,
+    pub e_shentsize: u16,
+    pub e_shnum: u16,
+    pub e_shstrndx: u16,
 }
 #[derive(PartialEq)]
 struct ElfType(u16);
@@ -46,7 +53,7 @@ impl ElfType {
     const CORE: ElfType = ElfType(0x4);
 }
 
-fn elf_file_type_string(t: u16) -> &'static str {
+pub fn elf_file_type_string(t: u16) -> &'static str {
     let et = ElfType(t);
     match et {
         ElfType::NONE => "none",
@@ -59,21 +66,23 @@ fn elf_file_type_string(t: u16) -> &'static str {
 }
 
 #[derive(PartialEq)]
-struct MachineType(u16);
+pub struct MachineType(pub u16);
 impl MachineType {
     const UNKNOWN   : MachineType = MachineType(0x0);
     const X86       : MachineType = MachineType(0x3);
-    const ARM       : MachineType = MachineType(0x28);
+    pub const ARM   : MachineType = MachineType(0x28);
     const AMD64     : MachineType = MachineType(0x3e);
-    const RISCV     : MachineType = MachineType(0xf3);
+    const AARCH64   : MachineType = MachineType(0xb7);
+    pub const RISCV : MachineType = MachineType(0xf3);
 }
 
-fn machine_type_string(t: u16) -> &'static str {
+pub fn machine_type_string(t: u16) -> &'static str {
     match MachineType(t) {
         MachineType::UNKNOWN => "unknown",
         MachineType::X86     => "x86",
         MachineType::AMD64   => "amd64",
         MachineType::ARM     => "arm",
+        MachineType::AARCH64 => "aarch64",
         MachineType::RISCV   => "riscv",
         _ => "unknown",
     }
@@ -86,196 +95,400 @@ impl SectionType {
     const PROGBITS  : SectionType = SectionType(0x1);
     const SYMTAB    : SectionType = SectionType(0x2);
     const STRTAB    : SectionType = SectionType(0x3);
+    const RELA      : SectionType = SectionType(0x4);
+    const HASH      : SectionType = SectionType(0x5);
+    const DYNAMIC   : SectionType = SectionType(0x6);
+    const NOTE      : SectionType = SectionType(0x7);
+    const NOBITS    : SectionType = SectionType(0x8);
+    const REL       : SectionType = SectionType(0x9);
+    const DYNSYM    : SectionType = SectionType(0xb);
+    const INIT_ARRAY: SectionType = SectionType(0xe);
+    const FINI_ARRAY: SectionType = SectionType(0xf);
+    const PREINIT_ARRAY: SectionType = SectionType(0x10);
+    const GROUP     : SectionType = SectionType(0x11);
+    const GNU_HASH  : SectionType = SectionType(0x6ffffff6);
+    const VERDEF    : SectionType = SectionType(0x6ffffffd);
+    const VERNEED   : SectionType = SectionType(0x6ffffffe);
+    const VERSYM    : SectionType = SectionType(0x6fffffff);
+    const ATTRIBUTES: SectionType = SectionType(0x70000003);
 }
 
-fn section_type_string(t: u32) -> &'static str {
+pub fn section_type_string(t: u32) -> &'static str {
     match SectionType(t) {
         SectionType::NULL       => "null",
         SectionType::PROGBITS   => "program bits",
         SectionType::STRTAB     => "string table",
         SectionType::SYMTAB     => "symbol table",
+        SectionType::RELA       => "relocations+addend",
+        SectionType::HASH       => "symbol hash",
+        SectionType::DYNAMIC    => "dynamic",
+        SectionType::NOTE       => "note",
+        SectionType::NOBITS     => "no bits",
+        SectionType::REL        => "relocations",
+        SectionType::DYNSYM     => "dynamic symbols",
+        SectionType::INIT_ARRAY => "init array",
+        SectionType::FINI_ARRAY => "fini array",
+        SectionType::PREINIT_ARRAY => "preinit array",
+        SectionType::GROUP      => "group",
+        SectionType::GNU_HASH   => "gnu hash",
+        SectionType::VERDEF     => "version defs",
+        SectionType::VERNEED    => "version needs",
+        SectionType::VERSYM     => "symbol versions",
+        SectionType::ATTRIBUTES => "attributes",
         _ => "unknown",
     }
 }
 
 #[derive(Debug)]
-struct ProgramHeaderEntry {
-    p_type: u32,
-    p_flags: u32,
-    p_offset: u64,
-    p_vaddr: u64,
-    p_paddr: u64,
-    p_filesz: u64,
-    p_memsz: u64,
-    p_align: u64,
+pub struct ProgramHeaderEntry {
+    pub p_type: u32,
+    pub p_flags: u32,
+    pub p_offset: u64,
+    pub p_vaddr: u64,
+    pub p_paddr: u64,
+    pub p_filesz: u64,
+    pub p_memsz: u64,
+    pub p_align: u64,
 }
 
 #[derive(Debug)]
-struct SectionHeaderEntry {
-    sh_name: u32,
-    sh_type: u32,
-    sh_flags: u64,
-    sh_addr: u64,
-    sh_offset: u64,
-    sh_size: u64,
-    sh_link: u32,
-    sh_info: u32,
-    sh_addralign: u64,
-    sh_entsize: u64,
+pub struct SectionHeaderEntry {
+    pub sh_name: u32,
+    pub sh_type: u32,
+    pub sh_flags: u64,
+    pub sh_addr: u64,
+    pub sh_offset: u64,
+    pub sh_size: u64,
+    pub sh_link: u32,
+    pub sh_info: u32,
+    pub sh_addralign: u64,
+    pub sh_entsize: u64,
 }
 
-fn read_common_header_32(bytes: &[u8], endianness: u8) -> HeaderCommon {
-    HeaderCommon {
-        e_type: read_u16_from_slice(bytes, 0x10, endianness),
-        e_machine: read_u16_from_slice(bytes, 0x12,endianness),
-        e_version: read_u32_from_slice(bytes, 0x14,endianness),
-        e_entry: read_u32_to_u64_from_slice(bytes, 0x18, endianness),
-        e_phoff: read_u32_to_u64_from_slice(bytes, 0x1c, endianness),
-        e_shoff: read_u32_to_u64_from_slice(bytes, 0x20, endianness),
-        e_flags: read_u32_from_slice(bytes, 0x24, endianness),
-        e_ehsize: read_u16_from_slice(bytes, 0x28, endianness),
-        e_phentsize: read_u16_from_slice(bytes, 0x2a, endianness),
-        e_phnum: read_u16_from_slice(bytes, 0x2c, endianness),
-        e_shentsize: read_u16_from_slice(bytes, 0x2e, endianness),
-        e_shnum: read_u16_from_slice(bytes, 0x30, endianness),
-        e_shstrndx: read_u16_from_slice(bytes, 0x32, endianness),
-    }
+fn read_common_header_32(bytes: &[u8], endianness: u8) -> Result<HeaderCommon, BaretkError> {
+    let bytes = read_checked(bytes, 0, 0x34, "ELF header")?;
+    let u16_at = |at| read_u16_checked(bytes, at, endianness, "ELF header");
+    let u32_at = |at| read_u32_checked(bytes, at, endianness, "ELF header");
+    Ok(HeaderCommon {
+        e_type: u16_at(0x10)?,
+        e_machine: u16_at(0x12)?,
+        e_version: u32_at(0x14)?,
+        e_entry: u64::from(u32_at(0x18)?),
+        e_phoff: u64::from(u32_at(0x1c)?),
+        e_shoff: u64::from(u32_at(0x20)?),
+        e_flags: u32_at(0x24)?,
+        e_ehsize: u16_at(0x28)?,
+        e_phentsize: u16_at(0x2a)?,
+        e_phnum: u16_at(0x2c)?,
+        e_shentsize: u16_at(0x2e)?,
+        e_shnum: u16_at(0x30)?,
+        e_shstrndx: u16_at(0x32)?,
+    })
+}
+
+fn read_common_header_64(bytes: &[u8], endianness: u8) -> Result<HeaderCommon, BaretkError> {
+    let bytes = read_checked(bytes, 0, 0x40, "ELF header")?;
+    let u16_at = |at| read_u16_checked(bytes, at, endianness, "ELF header");
+    let u32_at = |at| read_u32_checked(bytes, at, endianness, "ELF header");
+    let u64_at = |at| read_u64_checked(bytes, at, endianness, "ELF header");
+    Ok(HeaderCommon {
+        e_type: u16_at(0x10)?,
+        e_machine: u16_at(0x12)?,
+        e_version: u32_at(0x14)?,
+        e_entry: u64_at(0x18)?,
+        e_phoff: u64_at(0x20)?,
+        e_shoff: u64_at(0x28)?,
+        e_flags: u32_at(0x30)?,
+        e_ehsize: u16_at(0x34)?,
+        e_phentsize: u16_at(0x36)?,
+        e_phnum: u16_at(0x38)?,
+        e_shentsize: u16_at(0x3a)?,
+        e_shnum: u16_at(0x3c)?,
+        e_shstrndx: u16_at(0x3e)?,
+    })
 }
 
-fn read_common_header_64(bytes: &[u8], endianness: u8) -> HeaderCommon {
-    HeaderCommon {
-        e_type: read_u16_from_slice(bytes, 0x10, endianness),
-        e_machine: read_u16_from_slice(bytes, 0x12, endianness),
-        e_version: read_u32_from_slice(bytes, 0x14,endianness),
-        e_entry: read_u64_from_slice(bytes, 0x18, endianness),
-        e_phoff: read_u64_from_slice(bytes, 0x20, endianness),
-        e_shoff: read_u64_from_slice(bytes, 0x28, endianness),
-        e_flags: read_u32_from_slice(bytes, 0x30, endianness),
-        e_ehsize: read_u16_from_slice(bytes, 0x34, endianness),
-        e_phentsize: read_u16_from_slice(bytes, 0x36, endianness),
-        e_phnum: read_u16_from_slice(bytes, 0x38, endianness),
-        e_shentsize: read_u16_from_slice(bytes, 0x3a, endianness),
-        e_shnum: read_u16_from_slice(bytes, 0x3c, endianness),
-        e_shstrndx: read_u16_from_slice(bytes, 0x3e, endianness),
+// Entries smaller than the format's overlap each other; a permissive load reads them anyway.
+fn check_entry_size(what: &str, count: u16, entsize: u16, expected: u16, offset: u64, diagnostics: &mut Diagnostics) -> Result<(), BaretkError> {
+    if count != 0 && entsize < expected {
+        diagnostics.tolerate(BaretkError::malformed(what, offset, format!("entry size {:#x} is smaller than {:#x}", entsize, expected)))?;
     }
+    Ok(())
 }
 
-fn read_program_header_32(bytes: &[u8], phnum: u16, phsize: u16, start: u64, endianness: u8) -> Vec<ProgramHeaderEntry> {
+fn read_program_header_32(bytes: &[u8], phnum: u16, phsize: u16, start: u64, endianness: u8, diagnostics: &mut Diagnostics) -> Result<Vec<ProgramHeaderEntry>, BaretkError> {
+    check_entry_size("ELF program headers", phnum, phsize, 0x20, start, diagnostics)?;
     let mut out = Vec::<ProgramHeaderEntry>::with_capacity(phnum as usize);
-    let mut s = start as usize;
-    for _ in 0..phnum {
+    for i in 0..phnum as usize {
+        let s = (start as usize).saturating_add(i * phsize as usize);
+        // A permissive load keeps the entries before the end of the file.
+        let bytes = match read_checked(bytes, s, 0x20, "ELF program headers") {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                diagnostics.tolerate(err)?;
+                break;
+            },
+        };
+        let u32_at = |at| read_u32_checked(bytes, at, endianness, "ELF program headers");
         out.push(ProgramHeaderEntry{
-            p_type: read_u32_from_slice(bytes, s + 0x0, endianness),
-            p_flags: read_u32_from_slice(bytes, s + 0x18, endianness),
-            p_offset: read_u32_to_u64_from_slice(bytes, s + 0x4, endianness),
-            p_vaddr: read_u32_to_u64_from_slice(bytes, s + 0x8, endianness),
-            p_paddr: read_u32_to_u64_from_slice(bytes, s + 0xc, endianness),
-            p_filesz: read_u32_to_u64_from_slice(bytes, s + 0x10, endianness),
-            p_memsz: read_u32_to_u64_from_slice(bytes, s + 0x14, endianness),
-            p_align: read_u32_to_u64_from_slice(bytes, s + 0x1c, endianness),
+            p_type: u32_at(0x0)?,
+            p_flags: u32_at(0x18)?,
+            p_offset: u64::from(u32_at(0x4)?),
+            p_vaddr: u64::from(u32_at(0x8)?),
+            p_paddr: u64::from(u32_at(0xc)?),
+            p_filesz: u64::from(u32_at(0x10)?),
+            p_memsz: u64::from(u32_at(0x14)?),
+            p_align: u64::from(u32_at(0x1c)?),
         });
-        s += phsize as usize;
     }
-    out
+    Ok(out)
 }
 
-fn read_program_header_64(bytes: &[u8], phnum: u16, phsize: u16, start: u64, endianness: u8) -> Vec<ProgramHeaderEntry> {
+fn read_program_header_64(bytes: &[u8], phnum: u16, phsize: u16, start: u64, endianness: u8, diagnostics: &mut Diagnostics) -> Result<Vec<ProgramHeaderEntry>, BaretkError> {
+    check_entry_size("ELF program headers", phnum, phsize, 0x38, start, diagnostics)?;
     let mut out = Vec::<ProgramHeaderEntry>::with_capacity(phnum as usize);
-    let mut s = start as usize;
-    for _ in 0..phnum {
+    for i in 0..phnum as usize {
+        let s = (start as usize).saturating_add(i * phsize as usize);
+        let bytes = match read_checked(bytes, s, 0x38, "ELF program headers") {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                diagnostics.tolerate(err)?;
+                break;
+            },
+        };
+        let u32_at = |at| read_u32_checked(bytes, at, endianness, "ELF program headers");
+        let u64_at = |at| read_u64_checked(bytes, at, endianness, "ELF program headers");
         out.push(ProgramHeaderEntry {
-            p_type: read_u32_from_slice(bytes, s + 0x0, endianness),
-            p_flags: read_u32_from_slice(bytes, s + 0x4, endianness),
-            p_offset: read_u64_from_slice(bytes, s + 0x8, endianness),
-            p_vaddr: read_u64_from_slice(bytes, s + 0x10, endianness),
-            p_paddr: read_u64_from_slice(bytes, s + 0x18, endianness),
-            p_filesz: read_u64_from_slice(bytes, s + 0x20, endianness),
-            p_memsz: read_u64_from_slice(bytes, s + 0x28, endianness),
-            p_align: read_u64_from_slice(bytes, s + 0x30, endianness),
+            p_type: u32_at(0x0)?,
+            p_flags: u32_at(0x4)?,
+            p_offset: u64_at(0x8)?,
+            p_vaddr: u64_at(0x10)?,
+            p_paddr: u64_at(0x18)?,
+            p_filesz: u64_at(0x20)?,
+            p_memsz: u64_at(0x28)?,
+            p_align: u64_at(0x30)?,
         });
-        s += phsize as usize;
     }
-    out
+    Ok(out)
 }
 
-fn read_section_header_32(bytes: &[u8], shnum: u16, shsize: u16, start: u64, endianness: u8) -> Vec<SectionHeaderEntry> {
+fn read_section_header_32(bytes: &[u8], shnum: u16, shsize: u16, start: u64, endianness: u8, diagnostics: &mut Diagnostics) -> Result<Vec<SectionHeaderEntry>, BaretkError> {
+    check_entry_size("ELF section headers", shnum, shsize, 0x28, start, diagnostics)?;
     let mut out = Vec::<SectionHeaderEntry>::with_capacity(shnum as usize);
-    let mut s = start as usize;
-    for _ in 0..shnum {
+    for i in 0..shnum as usize {
+        let s = (start as usize).saturating_add(i * shsize as usize);
+        let bytes = match read_checked(bytes, s, 0x28, "ELF section headers") {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                diagnostics.tolerate(err)?;
+                break;
+            },
+        };
+        let u32_at = |at| read_u32_checked(bytes, at, endianness, "ELF section headers");
         out.push(SectionHeaderEntry{
-            sh_name: read_u32_from_slice(bytes, s + 0x0, endianness),
-            sh_type: read_u32_from_slice(bytes, s + 0x4, endianness),
-            sh_flags: read_u32_to_u64_from_slice(bytes, s + 0x8, endianness),
-            sh_addr: read_u32_to_u64_from_slice(bytes, s + 0xc, endianness),
-            sh_offset: read_u32_to_u64_from_slice(bytes, s + 0x10, endianness),
-            sh_size: read_u32_to_u64_from_slice(bytes, s + 0x14, endianness),
-            sh_link: read_u32_from_slice(bytes, s + 0x18, endianness),
-            sh_info: read_u32_from_slice(bytes, s + 0x1c, endianness),
-            sh_addralign: read_u32_to_u64_from_slice(bytes, s + 0x20, endianness),
-            sh_entsize: read_u32_to_u64_from_slice(bytes, s + 0x24, endianness),
+            sh_name: u32_at(0x0)?,
+            sh_type: u32_at(0x4)?,
+            sh_flags: u64::from(u32_at(0x8)?),
+            sh_addr: u64::from(u32_at(0xc)?),
+            sh_offset: u64::from(u32_at(0x10)?),
+            sh_size: u64::from(u32_at(0x14)?),
+            sh_link: u32_at(0x18)?,
+            sh_info: u32_at(0x1c)?,
+            sh_addralign: u64::from(u32_at(0x20)?),
+            sh_entsize: u64::from(u32_at(0x24)?),
         });
-        s += shsize as usize;
     }
-    out
+    Ok(out)
 }
 
-fn read_section_header_64(bytes: &[u8], shnum: u16, shsize: u16, start: u64, endianness: u8) -> Vec<SectionHeaderEntry> {
+fn read_section_header_64(bytes: &[u8], shnum: u16, shsize: u16, start: u64, endianness: u8, diagnostics: &mut Diagnostics) -> Result<Vec<SectionHeaderEntry>, BaretkError> {
+    check_entry_size("ELF section headers", shnum, shsize, 0x40, start, diagnostics)?;
     let mut out = Vec::<SectionHeaderEntry>::with_capacity(shnum as usize);
-    let mut s = start as usize;
-    for _ in 0..shnum {
+    for i in 0..shnum as usize {
+        let s = (start as usize).saturating_add(i * shsize as usize);
+        let bytes = match read_checked(bytes, s, 0x40, "ELF section headers") {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                diagnostics.tolerate(err)?;
+                break;
+            },
+        };
+        let u32_at = |at| read_u32_checked(bytes, at, endianness, "ELF section headers");
+        let u64_at = |at| read_u64_checked(bytes, at, endianness, "ELF section headers");
         out.push(SectionHeaderEntry{
-            sh_name: read_u32_from_slice(bytes, s + 0x0, endianness),
-            sh_type: read_u32_from_slice(bytes, s + 0x4, endianness),
-            sh_flags: read_u64_from_slice(bytes, s + 0x8, endianness),
-            sh_addr: read_u64_from_slice(bytes, s + 0x10, endianness),
-            sh_offset: read_u64_from_slice(bytes, s + 0x18, endianness),
-            sh_size: read_u64_from_slice(bytes, s + 0x20, endianness),
-            sh_link: read_u32_from_slice(bytes, s + 0x28, endianness),
-            sh_info: read_u32_from_slice(bytes, s + 0x2c, endianness),
-            sh_addralign: read_u64_from_slice(bytes, s + 0x30, endianness),
-            sh_entsize: read_u64_from_slice(bytes, s + 0x38, endianness),
+            sh_name: u32_at(0x0)?,
+            sh_type: u32_at(0x4)?,
+            sh_flags: u64_at(0x8)?,
+            sh_addr: u64_at(0x10)?,
+            sh_offset: u64_at(0x18)?,
+            sh_size: u64_at(0x20)?,
+            sh_link: u32_at(0x28)?,
+            sh_info: u32_at(0x2c)?,
+            sh_addralign: u64_at(0x30)?,
+            sh_entsize: u64_at(0x38)?,
         });
-        s += shsize as usize;
     }
-    out
+    Ok(out)
 }
 
-fn abi_string(abi: u8) -> String {
-    match abi {
-        0x0 => format!("none"),
-        0x3 => format!("Linux"),
-        _ => format!("unknown (0x{abi:02x})")
+// File offset of the section name string table.
+pub fn section_names(common_header: &HeaderCommon, section_headers: &[SectionHeaderEntry], diagnostics: &mut Diagnostics) -> Result<Option<u64>, BaretkError> {
+    if section_headers.is_empty() {
+        return Ok(None)
+    }
+    match section_headers.get(common_header.e_shstrndx as usize) {
+        Some(sh) => Ok(Some(sh.sh_offset)),
+        None => {
+            diagnostics.tolerate(BaretkError::malformed("ELF header", common_header.e_shoff,
+                format!("section name table {} is past the {} section headers", common_header.e_shstrndx, section_headers.len())))?;
+            Ok(None)
+        },
     }
 }
 
-fn shstring(bytes: &[u8], idx: u32) -> String {
-    let i = idx as usize;
-    let mut j = i;
-    while j < bytes.len() {
-        if bytes[j] == 0x0 {
-            break;
-        }
-        j += 1;
+// Without a name table, sections are named by their index.
+pub fn section_name(bytes: &[u8], names: Option<u64>, index: usize, entry: &SectionHeaderEntry) -> String {
+    match names {
+        Some(names) => c_string_at(bytes, names.saturating_add(entry.sh_name as u64) as usize),
+        None => format!("section{}", index),
     }
-    let s = &bytes[i..j];
-    // println!("0x{:08x}..{}, 0x{:02x} 0x{:02x}", i, s.len(), s[0], s[1]);
-    let s = match std::str::from_utf8(s) {
-        Ok(v) => v,
-        Err(e) => panic!("Invalid UTF-8 sequence: {}", e),
-    };
-    String::from(s)
 }
 
-fn build_section_table(bytes: &[u8], common_header: &HeaderCommon, section_headers: &Vec<SectionHeaderEntry>) -> HashMap<String, Section> {
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+
+fn section_perm(flags: u64) -> u8 {
+    let mut perm = 0u8;
+    if flags & SHF_ALLOC != 0 {
+        perm |= RWX_READ;
+    }
+    if flags & SHF_WRITE != 0 {
+        perm |= RWX_WRITE;
+    }
+    if flags & SHF_EXECINSTR != 0 {
+        perm |= RWX_EXEC;
+    }
+    perm
+}
+
+// Largest NOBITS section that gets zeroed memory; a bigger size is taken as a
+// corrupt header rather than allocated.
+const MAX_NOBITS_SIZE: u64 = 1 << 30;
+
+fn build_section_table(bytes: &Arc<[u8]>, common_header: &HeaderCommon, section_headers: &Vec<SectionHeaderEntry>, diagnostics: &mut Diagnostics) -> Result<HashMap<String, Section>, BaretkError> {
     let mut hashmap = HashMap::<String, Section>::new();
-    for entry in section_headers {
-        let key = shstring(bytes, section_headers[common_header.e_shstrndx as usize].sh_offset as u32 + entry.sh_name);
+    let names = section_names(common_header, section_headers, diagnostics)?;
+    for (i, entry) in section_headers.iter().enumerate() {
+        let key = section_name(bytes, names, i, entry);
+        // NOBITS sections (.bss) take no space in the file and start zeroed.
+        let section_bytes = if entry.sh_type == SHT_NOBITS {
+            if entry.sh_size > MAX_NOBITS_SIZE {
+                diagnostics.tolerate(BaretkError::Malformed { what: "ELF section headers".to_string(), offset: None, section: Some(key),
+                    reason: format!("NOBITS size {:#x} is over the {:#x} byte limit", entry.sh_size, MAX_NOBITS_SIZE) })?;
+                continue;
+            }
+            vec![0; entry.sh_size as usize].into()
+        } else {
+            prog::section_bytes(bytes, &key, entry.sh_offset, entry.sh_size, diagnostics)?
+        };
         hashmap.insert(key, Section {
             addr: entry.sh_addr,
-            bytes: bytes[entry.sh_offset as usize..(entry.sh_offset as usize + entry.sh_size as usize)].to_vec()
+            bytes: section_bytes,
+            offset: entry.sh_offset,
+            perm: section_perm(entry.sh_flags),
+            align: entry.sh_addralign,
         });
     }
-    hashmap
+    Ok(hashmap)
+}
+
+pub const SHT_SYMTAB: u32 = 0x2;
+const SHT_DYNSYM: u32 = 0xb;
+
+const STT_NOTYPE: u8 = 0x0;
+const STT_OBJECT: u8 = 0x1;
+const STT_FUNC: u8 = 0x2;
+
+// Function and object symbols. Where the ARM mapping symbols ($a, $t) say
+// the code switches to ARM or Thumb goes in mapping.
+fn build_symbol_table(bytes: &[u8], header: &Header, section_headers: &Vec<SectionHeaderEntry>, mapping: &mut Vec<(u64, bool)>, diagnostics: &mut Diagnostics) -> Result<Vec<Symbol>, BaretkError> {
+    let mut v = Vec::<Symbol>::new();
+    for entry in section_headers {
+        if entry.sh_type != SHT_SYMTAB && entry.sh_type != SHT_DYNSYM {
+            continue;
+        }
+        let strtab = match section_headers.get(entry.sh_link as usize) {
+            Some(strtab) => strtab.sh_offset,
+            None => {
+                diagnostics.tolerate(BaretkError::malformed("ELF symbol table", entry.sh_offset,
+                    format!("string table {} is past the {} section headers", entry.sh_link, section_headers.len())))?;
+                continue;
+            },
+        };
+        let symbol_size = if header.class == 0x1 { 0x10 } else { 0x18 };
+        let entsize = if entry.sh_entsize != 0 { entry.sh_entsize } else { symbol_size as u64 };
+        if entsize < symbol_size as u64 {
+            diagnostics.tolerate(BaretkError::malformed("ELF symbol table", entry.sh_offset,
+                format!("entry size {:#x} is smaller than a symbol ({:#x})", entsize, symbol_size)))?;
+            continue;
+        }
+        let mut s = entry.sh_offset as usize;
+        let end = entry.sh_offset.saturating_add(entry.sh_size);
+        if end > bytes.len() as u64 {
+            diagnostics.tolerate(BaretkError::malformed("ELF symbol table", entry.sh_offset,
+                format!("{:#x} of {:#x} byte(s) are in the file", (bytes.len() as u64).saturating_sub(entry.sh_offset), entry.sh_size)))?;
+        }
+        let end = end.min(bytes.len() as u64) as usize;
+        let u8_at = |at| read_u8_checked(bytes, at, "ELF symbol table");
+        let u32_at = |at| read_u32_checked(bytes, at, header.data, "ELF symbol table");
+        let u64_at = |at| read_u64_checked(bytes, at, header.data, "ELF symbol table");
+        while s.saturating_add(entsize as usize) <= end {
+            let (name, addr, size, info) = if header.class == 0x1 {
+                (u32_at(s)?, u64::from(u32_at(s + 0x4)?), u64::from(u32_at(s + 0x8)?), u8_at(s + 0xc)?)
+            } else {
+                (u32_at(s)?, u64_at(s + 0x8)?, u64_at(s + 0x10)?, u8_at(s + 0x4)?)
+            };
+            s += entsize as usize;
+            let sym_type = info & 0xf;
+            if name != 0 && sym_type == STT_NOTYPE {
+                let name = c_string_at(bytes, strtab.saturating_add(name as u64) as usize);
+                match name.split('.').next() {
+                    Some("$a") => mapping.push((addr, false)),
+                    Some("$t") => mapping.push((addr, true)),
+                    _ => {},
+                }
+                continue;
+            }
+            if name == 0 || (sym_type != STT_FUNC && sym_type != STT_OBJECT) {
+                continue;
+            }
+            v.push(Symbol {
+                name: c_string_at(bytes, strtab.saturating_add(name as u64) as usize),
+                addr,
+                size,
+                is_func: sym_type == STT_FUNC,
+            });
+        }
+    }
+    Ok(v)
+}
+
+// Thumb function symbols and entry points have the low bit set. Clears it and
+// records where ARM and Thumb code starts, with the mapping symbols.
+fn thumb_switches(symbols: &mut [Symbol], entry_point: &mut u64, mapping: Vec<(u64, bool)>) -> Vec<(u64, bool)> {
+    let mut switches = mapping;
+    for symbol in symbols.iter_mut().filter(|s| s.is_func) {
+        switches.push((symbol.addr & !1, symbol.addr & 1 != 0));
+        symbol.addr &= !1;
+    }
+    if *entry_point & 1 != 0 {
+        *entry_point &= !1;
+        switches.push((*entry_point, true));
+    }
+    switches.sort_by_key(|&(addr, _)| addr);
+    switches.dedup_by_key(|&mut (addr, _)| addr);
+    switches
 }
 
 fn build_program_table(common_header: &HeaderCommon, program_headers: &Vec<ProgramHeaderEntry>) -> Vec<Segment> {
@@ -292,68 +505,181 @@ fn build_program_table(common_header: &HeaderCommon, program_headers: &Vec<Progr
     v
 }
 
-fn build_program(bytes: &[u8], header: &Header, common_header: &HeaderCommon, program_headers: &Vec<ProgramHeaderEntry>, section_headers: &Vec<SectionHeaderEntry>) -> Program {
-    Program{
+fn build_program(bytes: &Arc<[u8]>, header: &Header, common_header: &HeaderCommon, program_headers: &Vec<ProgramHeaderEntry>, section_headers: &Vec<SectionHeaderEntry>, diagnostics: &mut Diagnostics) -> Result<Program, BaretkError> {
+    let mut mapping = Vec::new();
+    let mut symbol_table = build_symbol_table(bytes, header, section_headers, &mut mapping, diagnostics)?;
+    let mut entry_point = common_header.e_entry;
+    let thumb_switches = if MachineType(common_header.e_machine) == MachineType::ARM {
+        thumb_switches(&mut symbol_table, &mut entry_point, mapping)
+    } else {
+        Vec::new()
+    };
+    Ok(Program{
+        format: "elf",
         bits: if header.class == 0x1 { 32 } else if header.class == 0x2 { 64 } else { 0 },
         endianess: if header.data == 0x1 { LITTLE_ENDIAN } else { BIG_ENDIAN },
         machine_type: machine_type_string(common_header.e_machine).to_string(),
-        entry_point: common_header.e_entry,
+        entry_point,
         program_table: build_program_table(common_header, program_headers),
-        section_table: build_section_table(bytes, common_header, section_headers)
+        section_table: build_section_table(bytes, common_header, section_headers, diagnostics)?,
+        symbol_table,
+        import_table: Vec::new(),
+        comments: HashMap::new(),
+        data_types: HashMap::new(),
+        warnings: Vec::new(),
+        thumb_switches,
+        thumb_sections: Vec::new(),
+        prior_xrefs: None,
+    })
+}
+
+pub const SHT_NOBITS: u32 = 0x8;
+
+pub fn read_headers(bytes: &[u8], diagnostics: &mut Diagnostics) -> Result<(Header, HeaderCommon, Vec<ProgramHeaderEntry>, Vec<SectionHeaderEntry>), BaretkError> {
+    let header = read_header(bytes)?;
+    let common_header = if header.class == 0x1 {
+        read_common_header_32(bytes, header.data)?
+    } else {
+        read_common_header_64(bytes, header.data)?
+    };
+    let (program_headers, section_headers) = if header.class == 0x1 {
+        (read_program_header_32(bytes, common_header.e_phnum, common_header.e_phentsize, common_header.e_phoff, header.data, diagnostics)?,
+         read_section_header_32(bytes, common_header.e_shnum, common_header.e_shentsize, common_header.e_shoff, header.data, diagnostics)?)
+    } else {
+        (read_program_header_64(bytes, common_header.e_phnum, common_header.e_phentsize, common_header.e_phoff, header.data, diagnostics)?,
+         read_section_header_64(bytes, common_header.e_shnum, common_header.e_shentsize, common_header.e_shoff, header.data, diagnostics)?)
+    };
+    Ok((header, common_header, program_headers, section_headers))
+}
+
+// Machine type, bits and byte order from the identification bytes and
+// e_machine, without reading the rest of the headers.
+pub fn identify(bytes: &[u8]) -> (&'static str, u8, u8) {
+    let endianess = if bytes.get(0x05) == Some(&0x2) { BIG_ENDIAN } else { LITTLE_ENDIAN };
+    let machine = match read_u16_checked(bytes, 0x12, endianess, "ELF header") {
+        Ok(machine) => machine,
+        Err(_) => return ("unknown", 0, LITTLE_ENDIAN),
+    };
+    let bits = match bytes[0x04] { 0x1 => 32, 0x2 => 64, _ => 0 };
+    (machine_type_string(machine), bits, endianess)
+}
+
+pub fn c_string_at(bytes: &[u8], offset: usize) -> String {
+    let tail = bytes.get(offset..).unwrap_or(&[]);
+    let end = tail.iter().position(|b| *b == 0).unwrap_or(tail.len());
+    String::from_utf8_lossy(&tail[..end]).to_string()
+}
+
+// Whether the header fields, segments and sections agree with each other and
+// fit in the file.
+pub fn validate(bytes: &[u8]) -> Result<Vec<Finding>, BaretkError> {
+    let mut diagnostics = Diagnostics::new(ParseMode::Permissive);
+    let (header, common_header, program_headers, section_headers) = read_headers(bytes, &mut diagnostics)?;
+    let mut findings = Vec::<Finding>::new();
+    let file_size = bytes.len() as u64;
+
+    let mut problems = Vec::<String>::new();
+    if header.class != 0x1 && header.class != 0x2 {
+        problems.push(format!("unknown class {}", header.class));
+    }
+    if bytes[0x6] != 1 || common_header.e_version != 1 {
+        problems.push(format!("version {} (identification) and {} (header) should both be 1", bytes[0x6], common_header.e_version));
+    }
+    let (ehsize, phentsize, shentsize) = if header.class == 0x1 { (0x34, 0x20, 0x28) } else { (0x40, 0x38, 0x40) };
+    if common_header.e_ehsize != ehsize {
+        problems.push(format!("header size {:#x} isn't {:#x}", common_header.e_ehsize, ehsize));
     }
+    if common_header.e_phnum != 0 && common_header.e_phentsize != phentsize {
+        problems.push(format!("program header entry size {:#x} isn't {:#x}", common_header.e_phentsize, phentsize));
+    }
+    if common_header.e_shnum != 0 && common_header.e_shentsize != shentsize {
+        problems.push(format!("section header entry size {:#x} isn't {:#x}", common_header.e_shentsize, shentsize));
+    }
+    if common_header.e_shnum != 0 && common_header.e_shstrndx >= common_header.e_shnum {
+        problems.push(format!("section name table {} is past the {} section headers", common_header.e_shstrndx, common_header.e_shnum));
+    }
+    let is_image = common_header.e_type == ElfType::EXEC.0 || common_header.e_type == ElfType::DYN.0;
+    if is_image && common_header.e_entry != 0 && !program_headers.is_empty() {
+        let executable = program_headers.iter()
+            .filter(|ph| ph.p_type == 0x1 && ph.p_flags & 0x1 != 0)
+            .any(|ph| common_header.e_entry >= ph.p_vaddr && common_header.e_entry < ph.p_vaddr.saturating_add(ph.p_memsz));
+        if !executable {
+            problems.push(format!("entry point {:#x} isn't in an executable segment", common_header.e_entry));
+        }
+    }
+    check(&mut findings, "header fields are consistent", problems);
+
+    let mut problems = Vec::<String>::new();
+    for (i, ph) in program_headers.iter().enumerate().filter(|(_, ph)| ph.p_type == 0x1) {
+        if ph.p_offset.saturating_add(ph.p_filesz) > file_size {
+            problems.push(format!("segment {} runs past the end of the file", i));
+        }
+        if ph.p_filesz > ph.p_memsz {
+            problems.push(format!("segment {} has more bytes in the file ({:#x}) than in memory ({:#x})", i, ph.p_filesz, ph.p_memsz));
+        }
+        if ph.p_align > 1 && (!ph.p_align.is_power_of_two() || ph.p_offset % ph.p_align != ph.p_vaddr % ph.p_align) {
+            problems.push(format!("segment {} offset {:#x} and address {:#x} don't agree with its alignment {:#x}", i, ph.p_offset, ph.p_vaddr, ph.p_align));
+        }
+    }
+    check(&mut findings, "loadable segments fit in the file", problems);
+
+    let mut problems = Vec::<String>::new();
+    let names = section_names(&common_header, &section_headers, &mut diagnostics)?;
+    let mut in_file: Vec<(String, u64, u64)> = section_headers.iter().enumerate()
+        .filter(|(_, sh)| sh.sh_type != 0 && sh.sh_type != SHT_NOBITS && sh.sh_size != 0)
+        .map(|(i, sh)| (section_name(bytes, names, i, sh), sh.sh_offset, sh.sh_offset.saturating_add(sh.sh_size)))
+        .collect();
+    for (name, _, end) in &in_file {
+        if *end > file_size {
+            problems.push(format!("section {} runs past the end of the file", name));
+        }
+    }
+    in_file.sort_by_key(|(_, start, _)| *start);
+    for pair in in_file.windows(2) {
+        if pair[1].1 < pair[0].2 {
+            problems.push(format!("sections {} and {} overlap in the file", pair[0].0, pair[1].0));
+        }
+    }
+    check(&mut findings, "sections fit in the file without overlapping", problems);
+    Ok(findings)
 }
 
-pub fn load_program_from_bytes(bytes: &[u8]) -> Program {
-    let header = read_header(bytes);
-    // println!("ELF version {}, {}-bit, {}, ABI {} version {}",
-    //     header.version, 
-    //     match header.class {
-    //         0x1 => "32",
-    //         0x2 => "64",
-    //         _ => "?"
-    //     }, 
-    //     match header.data {
-    //         0x1 => "little-endian",
-    //         0x2 => "big-endian",
-    //         _ => "unknown-endian"
-    //     }, 
-    //     abi_string(header.abi), 
-    //     header.abi_version);
+pub fn load_program(bytes: &Arc<[u8]>, diagnostics: &mut Diagnostics) -> Result<Program, BaretkError> {
+    let header = read_header(bytes)?;
     let common_header = if header.class == 0x1 {
-        read_common_header_32(bytes, header.data)
+        read_common_header_32(bytes, header.data)?
     } else {
-        read_common_header_64(bytes, header.data)
+        read_common_header_64(bytes, header.data)?
     };
-    println!("{} file, {} (0x{:02X}), version {}",
+    log::debug!("{} file, {} (0x{:02X}), version {}",
         elf_file_type_string(common_header.e_type),
         machine_type_string(common_header.e_machine), common_header.e_machine,
         common_header.e_version);
-    // println!("entry point = 0x{:08x}", common_header.e_entry);
-    // println!("program header = 0x{:08x}", common_header.e_phoff);
-    // println!("section header = 0x{:08x}", common_header.e_shoff);
-    // println!("header size = 0x{:08x}", common_header.e_ehsize);
+    log::debug!("entry point = 0x{:08x}, program header = 0x{:08x}, section header = 0x{:08x}, header size = 0x{:08x}",
+        common_header.e_entry, common_header.e_phoff, common_header.e_shoff, common_header.e_ehsize);
     let program_headers = if header.class == 0x1 {
-        read_program_header_32(bytes, common_header.e_phnum, common_header.e_phentsize, common_header.e_phoff, header.data)
+        read_program_header_32(bytes, common_header.e_phnum, common_header.e_phentsize, common_header.e_phoff, header.data, diagnostics)?
     } else {
-        read_program_header_64(bytes, common_header.e_phnum, common_header.e_phentsize, common_header.e_phoff, header.data)
+        read_program_header_64(bytes, common_header.e_phnum, common_header.e_phentsize, common_header.e_phoff, header.data, diagnostics)?
     };
-    // println!("Program headers: count={}", common_header.e_phnum);
-    // for entry in &program_headers {
-    //     println!("{} offset=0x{:08x}, size=0x{:08x}, align=0x{:04x}", 
-    //         rwx_string(entry.p_flags), entry.p_offset, entry.p_filesz, entry.p_align);
-    // }
+    log::debug!("Program headers: count={}", common_header.e_phnum);
+    for entry in &program_headers {
+        log::debug!("flags=0x{:x} offset=0x{:08x}, size=0x{:08x}, align=0x{:04x}",
+            entry.p_flags, entry.p_offset, entry.p_filesz, entry.p_align);
+    }
     let section_headers = if header.class == 0x1 {
-        read_section_header_32(bytes, common_header.e_shnum, common_header.e_shentsize, common_header.e_shoff, header.data)
+        read_section_header_32(bytes, common_header.e_shnum, common_header.e_shentsize, common_header.e_shoff, header.data, diagnostics)?
     } else {
-        read_section_header_64(bytes, common_header.e_shnum, common_header.e_shentsize, common_header.e_shoff, header.data)
+        read_section_header_64(bytes, common_header.e_shnum, common_header.e_shentsize, common_header.e_shoff, header.data, diagnostics)?
     };
-    println!("Section headers: count={}", common_header.e_shnum);
-    for entry in &section_headers {
-        println!("name={:<16} type={:<16} offset=0x{:08x}, size=0x{:08x}", 
-            shstring(bytes, section_headers[common_header.e_shstrndx as usize].sh_offset as u32 + entry.sh_name),
+    log::debug!("Section headers: count={}", common_header.e_shnum);
+    let names = section_names(&common_header, &section_headers, &mut Diagnostics::new(ParseMode::Permissive))?;
+    for (i, entry) in section_headers.iter().enumerate() {
+        log::trace!("name={:<16} type={:<16} offset=0x{:08x}, size=0x{:08x}",
+            section_name(bytes, names, i, entry),
             section_type_string(entry.sh_type),
             entry.sh_offset,
             entry.sh_size);
     }
-    build_program(bytes, &header, &common_header, &program_headers, &section_headers)
+    build_program(bytes, &header, &common_header, &program_headers, &section_headers, diagnostics)
 }
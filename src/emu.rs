@@ -0,0 +1,350 @@
+// An interpreter for the lifted decomp IR. It runs one function with chosen
+// register and memory inputs, which is often the quickest way to see what a
+// string decoding or checksum routine produces.
+//
+// The model is deliberately simple: registers hold 64-bit values, memory is a
+// sparse byte map on top of the program's sections, and calls to other
+// functions are not followed - they're recorded and return 0.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::decomp::{self, Decomp, Expr, ExprArena, ExprId};
+use crate::reg::{Arch, Reg, RegClass};
+use crate::util::BIG_ENDIAN;
+
+// Where the stack pointer starts when no value is given for it.
+pub const DEFAULT_STACK: u64 = 0x7fff0000;
+
+pub enum Stop {
+    Returned,
+    StepLimit,
+    // Jumped to an address outside the function.
+    LeftFunction(u64),
+    Unsupported(String),
+}
+
+// A value with the width in bits of the expression that produced it, so signed
+// comparisons and sign extension know where the sign bit is.
+#[derive(Clone, Copy)]
+struct Value {
+    v: u64,
+    bits: u32,
+}
+
+enum Flow {
+    Next,
+    Jump(u64),
+    Return,
+}
+
+pub struct Emulator<'a> {
+    decomp: &'a Decomp,
+    machine_type: &'a str,
+    word_bits: u32,
+    big_endian: bool,
+    regs: HashMap<Reg, u64>,
+    memory: BTreeMap<u64, u8>,
+    // Addresses written by the function, as opposed to given as input.
+    written: BTreeSet<u64>,
+    // Instruction sizes by address, for x86 rip-relative operands.
+    sizes: HashMap<u64, u64>,
+    pub calls: Vec<String>,
+    pub steps: usize,
+}
+
+fn mask(v: u64, bits: u32) -> u64 {
+    if bits >= 64 { v } else { v & ((1u64 << bits) - 1) }
+}
+
+fn sign_extend(v: u64, bits: u32) -> i64 {
+    if bits >= 64 { v as i64 } else { ((v << (64 - bits)) as i64) >> (64 - bits) }
+}
+
+impl<'a> Emulator<'a> {
+    pub fn new(decomp: &'a Decomp) -> Self {
+        let dis = decomp.disassembly();
+        let program = dis.program();
+        let listing = &dis.section().instructions;
+        let base = dis.section_addr();
+        let sizes = listing.instruction_offset_vec_in(0..usize::MAX).into_iter()
+            .zip(listing.instruction_size_vec_in(0..usize::MAX))
+            .map(|(offset, size)| (base + offset as u64, size as u64))
+            .collect();
+        let mut emu = Emulator {
+            decomp,
+            machine_type: program.machine_type.as_str(),
+            word_bits: if program.bits == 0 { 32 } else { program.bits as u32 },
+            big_endian: program.endianess == BIG_ENDIAN,
+            regs: HashMap::new(),
+            memory: BTreeMap::new(),
+            written: BTreeSet::new(),
+            sizes,
+            calls: Vec::new(),
+            steps: 0,
+        };
+        let sp = match emu.machine_type {
+            "amd64" => Reg::RSP,
+            "x86" => Reg::ESP,
+            "riscv" => Reg::RISCV_SP,
+            _ => Reg::ARM_SP,
+        };
+        emu.set_register(sp, DEFAULT_STACK);
+        emu
+    }
+
+    // A register of the program's architecture by name.
+    pub fn parse_register(&self, name: &str) -> Option<Reg> {
+        Reg::parse(Arch::from_machine_type(self.machine_type)?, name)
+    }
+
+    // Full register, bit offset and width of the part of it a register is.
+    fn register_slot(&self, r: Reg) -> (Reg, u32, u32) {
+        match r.arch() {
+            Arch::X86 | Arch::AArch64 => {
+                let (full, shift, bits) = r.part();
+                (full, shift, bits.min(self.word_bits))
+            },
+            _ => (r, 0, self.word_bits),
+        }
+    }
+
+    pub fn set_register(&mut self, r: Reg, value: u64) {
+        let (key, shift, bits) = self.register_slot(r);
+        let old = self.regs.get(&key).copied().unwrap_or(0);
+        // Writing a 32-bit x86 register clears the upper half; smaller parts are merged.
+        let new = if bits >= 32 {
+            mask(value, bits)
+        } else {
+            let field = mask(u64::MAX, bits) << shift;
+            (old & !field) | ((value << shift) & field)
+        };
+        self.regs.insert(key, new);
+    }
+
+    pub fn register(&self, r: Reg) -> u64 {
+        let (key, shift, bits) = self.register_slot(r);
+        mask(self.regs.get(&key).copied().unwrap_or(0) >> shift, bits)
+    }
+
+    // Registers with a value, sorted by name.
+    pub fn registers(&self) -> Vec<(Reg, u64)> {
+        let mut regs: Vec<(Reg, u64)> = self.regs.iter().map(|(k, v)| (*k, *v)).collect();
+        regs.sort_by_key(|(r, _)| r.name());
+        regs
+    }
+
+    pub fn write_memory(&mut self, addr: u64, bytes: &[u8]) {
+        for (i, b) in bytes.iter().enumerate() {
+            self.memory.insert(addr + i as u64, *b);
+        }
+    }
+
+    fn read_byte(&self, addr: u64) -> u8 {
+        if let Some(b) = self.memory.get(&addr) {
+            return *b
+        }
+        let program = self.decomp.disassembly().program();
+        for section in program.section_table.values() {
+            if addr >= section.addr && addr < section.addr + section.bytes.len() as u64 {
+                return section.bytes[(addr - section.addr) as usize]
+            }
+        }
+        0
+    }
+
+    fn load(&self, addr: u64, size: u8) -> u64 {
+        let mut v = 0u64;
+        for i in 0..size as u64 {
+            let b = self.read_byte(addr.wrapping_add(i)) as u64;
+            v |= if self.big_endian { b << (8 * (size as u64 - 1 - i)) } else { b << (8 * i) };
+        }
+        v
+    }
+
+    fn store(&mut self, addr: u64, size: u8, v: u64) {
+        for i in 0..size as u64 {
+            let shift = if self.big_endian { 8 * (size as u64 - 1 - i) } else { 8 * i };
+            self.memory.insert(addr.wrapping_add(i), (v >> shift) as u8);
+            self.written.insert(addr.wrapping_add(i));
+        }
+    }
+
+    // Runs of consecutive bytes written by the function.
+    pub fn written_memory(&self) -> Vec<(u64, Vec<u8>)> {
+        let mut out = Vec::<(u64, Vec<u8>)>::new();
+        for addr in &self.written {
+            match out.last_mut() {
+                Some((start, bytes)) if *start + bytes.len() as u64 == *addr => bytes.push(self.memory[addr]),
+                _ => out.push((*addr, vec![self.memory[addr]])),
+            }
+        }
+        out
+    }
+
+    fn pc_value(&self, addr: u64) -> u64 {
+        match self.machine_type {
+            "x86" | "amd64" => addr + self.sizes.get(&addr).copied().unwrap_or(0),
+            "arm" => addr + 8,
+            _ => addr,
+        }
+    }
+
+    fn eval(&mut self, a: &ExprArena, id: ExprId, addr: u64) -> Result<Value, String> {
+        let word = self.word_bits;
+        let value = match a.get(id) {
+            Expr::Constant(i) => Value { v: mask(i as u64, word), bits: word },
+            Expr::Register(r) if r.class() == RegClass::ProgramCounter => Value { v: self.pc_value(addr), bits: word },
+            Expr::Register(r) if r.class() == RegClass::Zero => Value { v: 0, bits: word },
+            Expr::Register(r) => Value { v: self.register(r), bits: self.register_slot(r).2 },
+            Expr::Dereference(size, x) => {
+                let at = self.eval(a, x, addr)?.v;
+                Value { v: self.load(at, size), bits: size as u32 * 8 }
+            },
+            Expr::Binary(op, lhs, rhs) => {
+                let (l, r) = (self.eval(a, lhs, addr)?, self.eval(a, rhs, addr)?);
+                let bits = l.bits.max(r.bits);
+                let (sl, sr) = (sign_extend(l.v, l.bits), sign_extend(r.v, r.bits));
+                let v = match op {
+                    decomp::OP_ADD => l.v.wrapping_add(r.v),
+                    decomp::OP_SUB => l.v.wrapping_sub(r.v),
+                    decomp::OP_MUL => l.v.wrapping_mul(r.v),
+                    decomp::OP_AND => l.v & r.v,
+                    decomp::OP_OR => l.v | r.v,
+                    decomp::OP_XOR => l.v ^ r.v,
+                    decomp::OP_SHL => l.v.wrapping_shl(r.v as u32),
+                    decomp::OP_SHR => mask(l.v, l.bits).wrapping_shr(r.v as u32),
+                    decomp::OP_SAR => sl.wrapping_shr(r.v as u32) as u64,
+                    decomp::OP_ROR => {
+                        let n = r.v as u32 % l.bits;
+                        if n == 0 { l.v } else { (l.v >> n) | (l.v << (l.bits - n)) }
+                    },
+                    decomp::OP_EQ => (mask(l.v, bits) == mask(r.v, bits)) as u64,
+                    decomp::OP_NE => (mask(l.v, bits) != mask(r.v, bits)) as u64,
+                    decomp::OP_LT => (sl < sr) as u64,
+                    decomp::OP_LE => (sl <= sr) as u64,
+                    decomp::OP_GT => (sl > sr) as u64,
+                    decomp::OP_GE => (sl >= sr) as u64,
+                    decomp::OP_LTU => (mask(l.v, bits) < mask(r.v, bits)) as u64,
+                    decomp::OP_LEU => (mask(l.v, bits) <= mask(r.v, bits)) as u64,
+                    decomp::OP_GTU => (mask(l.v, bits) > mask(r.v, bits)) as u64,
+                    decomp::OP_GEU => (mask(l.v, bits) >= mask(r.v, bits)) as u64,
+                    _ => return Err(format!("unknown binary operator {:#x}", op)),
+                };
+                Value { v: mask(v, bits), bits }
+            },
+            Expr::Unary(op, x) => {
+                let x = self.eval(a, x, addr)?;
+                match op {
+                    decomp::OP_NOT => Value { v: (x.v == 0) as u64, bits: x.bits },
+                    decomp::OP_NEG => Value { v: mask(x.v.wrapping_neg(), x.bits), bits: x.bits },
+                    decomp::OP_INV => Value { v: mask(!x.v, x.bits), bits: x.bits },
+                    decomp::OP_SEXT => Value { v: mask(sign_extend(x.v, x.bits) as u64, word), bits: word },
+                    _ => return Err(format!("unknown unary operator {:#x}", op)),
+                }
+            },
+            Expr::Argument(_, x) => self.eval(a, x, addr)?,
+            Expr::Call(target, args) => {
+                let name = match a.get(target) {
+                    Expr::Symbol(name) => a.name(name),
+                    _ => format!("{:#x}", self.eval(a, target, addr)?.v),
+                };
+                let mut values = Vec::<String>::new();
+                for arg in a.list(args) {
+                    values.push(format!("{:#x}", self.eval(a, arg, addr)?.v));
+                }
+                self.calls.push(format!("{:#010x} {}({})", addr, name, values.join(", ")));
+                Value { v: 0, bits: word }
+            },
+            Expr::Intrinsic(name, args) if name.starts_with("sys_") => {
+                let mut values = Vec::<String>::new();
+                for arg in a.list(args) {
+                    values.push(format!("{:#x}", self.eval(a, arg, addr)?.v));
+                }
+                self.calls.push(format!("{:#010x} {}({})", addr, name, values.join(", ")));
+                Value { v: 0, bits: word }
+            },
+            Expr::Intrinsic(name, _) => return Err(format!("{:#010x}: can't emulate {}", addr, name)),
+            Expr::Flag(name) => return Err(format!("{:#010x}: flag \"{}\" isn't tracked", addr, name)),
+            _ => return Err(format!("{:#010x}: not a value", addr)),
+        };
+        Ok(value)
+    }
+
+    fn exec(&mut self, a: &ExprArena, id: ExprId, addr: u64) -> Result<Flow, String> {
+        match a.get(id) {
+            Expr::Store(dest, src) => {
+                let value = self.eval(a, src, addr)?;
+                match a.get(dest) {
+                    Expr::Register(r) if r.class() == RegClass::Zero => (),
+                    Expr::Register(r) => self.set_register(r, value.v),
+                    Expr::Dereference(size, x) => {
+                        let at = self.eval(a, x, addr)?.v;
+                        self.store(at, size, value.v);
+                    },
+                    _ => return Err(format!("{:#010x}: can't store to this destination", addr)),
+                }
+                Ok(Flow::Next)
+            },
+            Expr::Group(group) => {
+                for expr in a.list(group) {
+                    match self.exec(a, expr, addr)? {
+                        Flow::Next => (),
+                        flow => return Ok(flow),
+                    }
+                }
+                Ok(Flow::Next)
+            },
+            Expr::If(cond, body) => {
+                if self.eval(a, cond, addr)?.v != 0 {
+                    self.exec(a, body, addr)
+                } else {
+                    Ok(Flow::Next)
+                }
+            },
+            // Branch targets are relative to the branch instruction.
+            Expr::Goto(target) => match a.get(target) {
+                Expr::Constant(rel) => Ok(Flow::Jump(addr.wrapping_add(rel as u64))),
+                _ => Ok(Flow::Jump(self.eval(a, target, addr)?.v)),
+            },
+            Expr::Return => Ok(Flow::Return),
+            Expr::ReturnValue(value) => {
+                self.eval(a, value, addr)?;
+                Ok(Flow::Return)
+            },
+            Expr::Nop => Ok(Flow::Next),
+            _ => {
+                self.eval(a, id, addr)?;
+                Ok(Flow::Next)
+            },
+        }
+    }
+
+    // Runs the function from its first statement until it returns, leaves the
+    // function, hits something it can't emulate, or executes max_steps statements.
+    pub fn run(&mut self, max_steps: usize) -> Stop {
+        let (a, stmts, addrs) = self.decomp.statements();
+        let (first, last) = match (addrs.first(), addrs.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return Stop::Returned,
+        };
+        let mut i = 0;
+        while i < stmts.len() {
+            if self.steps >= max_steps {
+                return Stop::StepLimit
+            }
+            self.steps += 1;
+            match self.exec(a, stmts[i], addrs[i]) {
+                Err(msg) => return Stop::Unsupported(msg),
+                Ok(Flow::Next) => i += 1,
+                Ok(Flow::Return) => return Stop::Returned,
+                // Instructions that only set flags have no statement, so a jump to
+                // one lands on the next statement after it.
+                Ok(Flow::Jump(target)) if target >= first && target <= last => {
+                    i = addrs.partition_point(|addr| *addr < target);
+                },
+                Ok(Flow::Jump(target)) => return Stop::LeftFunction(target),
+            }
+        }
+        Stop::Returned
+    }
+}
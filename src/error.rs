@@ -0,0 +1,70 @@
+// The library's error type. Each variant keeps what a caller needs to report
+// the failure or act on it, such as the path, offset or section involved.
+
+use std::fmt;
+use std::io;
+
+use crate::cancel::Cancelled;
+
+#[derive(Debug)]
+pub enum BaretkError {
+    // Opening, reading, creating or writing a file failed.
+    Io { path: String, action: &'static str, source: io::Error },
+    // Input that can't be parsed. The offset is into the file or, with a
+    // section, into that section.
+    Malformed { what: String, offset: Option<u64>, section: Option<String>, reason: String },
+    // A function, symbol or section looked up by name or address isn't there.
+    NotFound(String),
+    // An option or argument has a value outside the ones defined.
+    InvalidArgument(String),
+    // The headers name an architecture that can't be disassembled.
+    UnsupportedArch(String),
+    Cancelled,
+}
+
+impl BaretkError {
+    pub fn io(path: &str, action: &'static str, source: io::Error) -> Self {
+        BaretkError::Io { path: path.to_string(), action, source }
+    }
+
+    pub fn malformed(what: &str, offset: u64, reason: String) -> Self {
+        BaretkError::Malformed { what: what.to_string(), offset: Some(offset), section: None, reason }
+    }
+}
+
+impl fmt::Display for BaretkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BaretkError::Io { path, action, source } => write!(f, "Error {} file {}: {}", action, path, source),
+            BaretkError::Malformed { what, offset, section, reason } => {
+                write!(f, "Error parsing {}", what)?;
+                match (section, offset) {
+                    (Some(section), Some(offset)) => write!(f, " at {:#x} in {}", offset, section)?,
+                    (Some(section), None) => write!(f, " in {}", section)?,
+                    (None, Some(offset)) => write!(f, " at {:#x}", offset)?,
+                    (None, None) => (),
+                }
+                write!(f, ": {}", reason)
+            },
+            BaretkError::NotFound(what) => write!(f, "{} not found", what),
+            BaretkError::InvalidArgument(what) => write!(f, "{}", what),
+            BaretkError::UnsupportedArch(arch) => write!(f, "Can't disassemble architecture {}", arch),
+            BaretkError::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for BaretkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BaretkError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<Cancelled> for BaretkError {
+    fn from(_: Cancelled) -> Self {
+        BaretkError::Cancelled
+    }
+}
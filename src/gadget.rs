@@ -0,0 +1,155 @@
+// ROP gadget search: short instruction sequences in the code section that end
+// in a return, an indirect jump or call, or a system call. On x86 gadgets may
+// start at any byte, including inside the instructions linear disassembly sees;
+// on ARM and RISC-V they start on instruction boundaries.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::dis::{self, Disassembly, InstructionListing};
+use crate::reg::RegClass;
+use crate::x86;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Ending {
+    Ret,
+    Jmp,
+    Call,
+    Syscall,
+}
+
+impl Ending {
+    // Parses a comma-separated list such as "ret,jmp", or "all".
+    pub fn parse_list(s: &str) -> Option<Vec<Ending>> {
+        let mut out = Vec::<Ending>::new();
+        for name in s.split(',') {
+            match name.trim() {
+                "ret" => out.push(Ending::Ret),
+                "jmp" => out.push(Ending::Jmp),
+                "call" => out.push(Ending::Call),
+                "syscall" => out.push(Ending::Syscall),
+                "all" => out.extend([Ending::Ret, Ending::Jmp, Ending::Call, Ending::Syscall]),
+                _ => return None,
+            }
+        }
+        Some(out)
+    }
+}
+
+pub struct Gadget {
+    // Address of the first occurrence.
+    pub addr: u64,
+    // Instruction texts joined with " ; ".
+    pub text: String,
+    pub count: usize,
+}
+
+#[derive(Clone)]
+struct Decoded {
+    ending: Option<Ending>,
+    breaks: bool,
+    text: String,
+    size: usize,
+}
+
+impl Decoded {
+    fn new(ins: &dis::Instruction, text: String, size: usize) -> Decoded {
+        Decoded { ending: ending(ins), breaks: breaks_gadget(ins), text, size }
+    }
+}
+
+fn is_register(op: Option<&dis::Operand>, class: RegClass) -> bool {
+    matches!(op, Some(dis::Operand::Register(r)) if r.class() == class)
+}
+
+fn ending(ins: &dis::Instruction) -> Option<Ending> {
+    let writes_pc = ins.operands.iter().any(|op| is_register(Some(op), RegClass::ProgramCounter));
+    let indirect = !matches!(ins.operands.first(), Some(dis::Operand::Immediate(_)));
+    if ins.cond() != dis::COND_AL && ins.opcode != "jalr" {
+        return None
+    }
+    match ins.opcode {
+        "ret" => Some(Ending::Ret),
+        "pop" | "ldm" if writes_pc => Some(Ending::Ret),
+        "jalr" if is_register(ins.operands.first(), RegClass::Zero) && is_register(ins.operands.get(1), RegClass::Link) => Some(Ending::Ret),
+        "jalr" if is_register(ins.operands.first(), RegClass::Zero) => Some(Ending::Jmp),
+        "jalr" => Some(Ending::Call),
+        "b" if indirect => Some(Ending::Jmp),
+        "call" if indirect => Some(Ending::Call),
+        "syscall" | "svc" => Some(Ending::Syscall),
+        _ => None,
+    }
+}
+
+// Instructions a gadget can't pass through: other control flow and bytes that don't decode.
+fn breaks_gadget(ins: &dis::Instruction) -> bool {
+    matches!(ins.opcode, "b" | "call" | "ret" | "jal" | "jalr" | "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" | "tbz" | "tbnz" | "syscall" | "svc" | "unk")
+        || (matches!(ins.opcode, "pop" | "ldm") && ins.operands.iter().any(|op| is_register(Some(op), RegClass::ProgramCounter)))
+}
+
+// Finds the unique gadgets of up to `max_len` instructions, ordered by address.
+pub fn find_gadgets(dis: &Disassembly, max_len: usize, endings: &[Ending]) -> Vec<Gadget> {
+    let program = dis.program();
+    let base = dis.section_addr();
+    let bytes = program.section_table.get(program.code_section()).map(|section| section.bytes.as_slice()).unwrap_or(&[]);
+    let listing = &dis.section().instructions;
+
+    // Instructions of fixed-width architectures come from the listing; x86 is decoded at every byte.
+    let linear: HashMap<usize, Decoded> = match listing {
+        InstructionListing::X86(_) => HashMap::new(),
+        _ => {
+            let instrs = listing.instruction_vec();
+            let offsets = listing.instruction_offset_vec_in(0..usize::MAX);
+            let sizes = listing.instruction_size_vec_in(0..usize::MAX);
+            let texts = listing.instruction_text_vec_in(0..usize::MAX);
+            instrs.into_iter().zip(offsets).zip(sizes).zip(texts)
+                .map(|(((ins, offset), size), text)| (offset, Decoded::new(&ins, text, size)))
+                .collect()
+        },
+    };
+    let is_x86 = matches!(listing, InstructionListing::X86(_));
+    let decode = |offset: usize| -> Option<Decoded> {
+        if is_x86 {
+            let ins = x86::disassemble_x86_at(bytes, offset)?;
+            Some(Decoded::new(&x86::Instruction::into(&ins), ins.print(), ins.size()))
+        }
+        else {
+            linear.get(&offset).cloned()
+        }
+    };
+    let max_instruction = if is_x86 { 15 } else { 4 };
+
+    // Canonical text to the first address and number of occurrences.
+    let mut found = BTreeMap::<String, (u64, usize)>::new();
+    for end in 0..bytes.len() {
+        let last = match decode(end) {
+            Some(last) if last.ending.map_or(false, |e| endings.contains(&e)) => last,
+            _ => continue,
+        };
+        let earliest = end.saturating_sub((max_len.max(1) - 1) * max_instruction);
+        for start in (earliest..=end).rev() {
+            // Decode forward from start; the gadget counts only if it lands exactly on the ending.
+            let mut texts = Vec::<String>::new();
+            let mut offset = start;
+            while offset < end && texts.len() + 1 < max_len {
+                match decode(offset) {
+                    Some(d) if !d.breaks && d.size > 0 => {
+                        texts.push(d.text);
+                        offset += d.size;
+                    },
+                    _ => break,
+                }
+            }
+            if offset != end {
+                continue;
+            }
+            texts.push(last.text.clone());
+            let text = texts.iter().map(|t| t.split_whitespace().collect::<Vec<_>>().join(" ")).collect::<Vec<_>>().join(" ; ");
+            let entry = found.entry(text).or_insert((base + start as u64, 0));
+            entry.0 = entry.0.min(base + start as u64);
+            entry.1 += 1;
+        }
+    }
+    let mut gadgets: Vec<Gadget> = found.into_iter().map(|(text, (addr, count))| Gadget { addr, text, count }).collect();
+    gadgets.sort_by_key(|g| g.addr);
+    gadgets
+}
@@ -0,0 +1,197 @@
+// Hashes for reports and threat intel lookups: MD5, SHA-1 and SHA-256 of the
+// file and its sections, the PE import hash (imphash) as pefile computes it,
+// and the ssdeep fuzzy hash, which stays similar when a file changes a little.
+
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use sha2::Sha256;
+
+use crate::prog::Program;
+
+pub struct Hashes {
+    pub md5: String,
+    pub sha1: String,
+    pub sha256: String,
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn hashes(bytes: &[u8]) -> Hashes {
+    Hashes {
+        md5: hex(&Md5::digest(bytes)),
+        sha1: hex(&Sha1::digest(bytes)),
+        sha256: hex(&Sha256::digest(bytes)),
+    }
+}
+
+// Winsock 1.1 exports, which ws2_32.dll and wsock32.dll keep at fixed
+// ordinals; pefile names these when they're imported by ordinal.
+const WINSOCK_ORDINALS: &[(u16, &str)] = &[
+    (1, "accept"), (2, "bind"), (3, "closesocket"), (4, "connect"), (5, "getpeername"), (6, "getsockname"),
+    (7, "getsockopt"), (8, "htonl"), (9, "htons"), (10, "ioctlsocket"), (11, "inet_addr"), (12, "inet_ntoa"),
+    (13, "listen"), (14, "ntohl"), (15, "ntohs"), (16, "recv"), (17, "recvfrom"), (18, "select"), (19, "send"),
+    (20, "sendto"), (21, "setsockopt"), (22, "shutdown"), (23, "socket"), (51, "gethostbyaddr"),
+    (52, "gethostbyname"), (53, "getprotobyname"), (54, "getprotobynumber"), (55, "getservbyname"),
+    (56, "getservbyport"), (57, "gethostname"), (101, "WSAAsyncSelect"), (102, "WSAAsyncGetHostByAddr"),
+    (103, "WSAAsyncGetHostByName"), (104, "WSAAsyncGetProtoByNumber"), (105, "WSAAsyncGetProtoByName"),
+    (106, "WSAAsyncGetServByPort"), (107, "WSAAsyncGetServByName"), (108, "WSACancelAsyncRequest"),
+    (109, "WSASetBlockingHook"), (110, "WSAUnhookBlockingHook"), (111, "WSAGetLastError"), (112, "WSASetLastError"),
+    (113, "WSACancelBlockingCall"), (114, "WSAIsBlocking"), (115, "WSAStartup"), (116, "WSACleanup"),
+    (151, "__WSAFDIsSet"), (500, "WEP"),
+];
+
+// An import's name for the imphash: ordinals are looked up for Winsock and
+// otherwise written as "ord<n>".
+fn imphash_function(library: &str, name: &str) -> String {
+    let ordinal = match name.strip_prefix('#').and_then(|n| n.parse::<u16>().ok()) {
+        Some(ordinal) => ordinal,
+        None => return name.to_lowercase(),
+    };
+    let known = match library {
+        "ws2_32.dll" | "wsock32.dll" => WINSOCK_ORDINALS.iter().find(|(n, _)| *n == ordinal).map(|(_, name)| *name),
+        _ => None,
+    };
+    match known {
+        Some(name) => name.to_lowercase(),
+        None => format!("ord{}", ordinal),
+    }
+}
+
+// MD5 of "library.function" for every import in table order, lowercased and
+// with .dll, .ocx and .sys dropped from library names. None without imports.
+pub fn imphash(program: &Program) -> Option<String> {
+    if program.format != "pe" || program.import_table.is_empty() {
+        return None
+    }
+    let names: Vec<String> = program.import_table.iter().map(|import| {
+        let library = import.library.to_lowercase();
+        let base = match library.rsplit_once('.') {
+            Some((base, "dll" | "ocx" | "sys")) => base,
+            _ => library.as_str(),
+        };
+        format!("{}.{}", base, imphash_function(&library, &import.name))
+    }).collect();
+    Some(hex(&Md5::digest(names.join(",").as_bytes())))
+}
+
+// ssdeep (context triggered piecewise hashing). A rolling hash over a 7-byte
+// window picks trigger points; the bytes between triggers are hashed down to
+// one base64 character each. The block size is chosen so the first part has
+// between 32 and 64 characters, and the second part uses twice the block size.
+const SPAMSUM_LENGTH: usize = 64;
+const MIN_BLOCKSIZE: u64 = 3;
+const NUM_BLOCKHASHES: usize = 31;
+const ROLLING_WINDOW: usize = 7;
+const HASH_PRIME: u32 = 0x01000193;
+const HASH_INIT: u32 = 0x28021967;
+const B64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Default)]
+struct RollingHash {
+    window: [u8; ROLLING_WINDOW],
+    h1: u32,
+    h2: u32,
+    h3: u32,
+    n: usize,
+}
+
+impl RollingHash {
+    fn update(&mut self, c: u8) {
+        self.h2 = self.h2.wrapping_sub(self.h1).wrapping_add(ROLLING_WINDOW as u32 * c as u32);
+        self.h1 = self.h1.wrapping_add(c as u32).wrapping_sub(self.window[self.n] as u32);
+        self.window[self.n] = c;
+        self.n = (self.n + 1) % ROLLING_WINDOW;
+        self.h3 = (self.h3 << 5) ^ c as u32;
+    }
+
+    fn sum(&self) -> u32 {
+        self.h1.wrapping_add(self.h2).wrapping_add(self.h3)
+    }
+}
+
+fn sum_hash(c: u8, h: u32) -> u32 {
+    h.wrapping_mul(HASH_PRIME) ^ c as u32
+}
+
+// The digest at one block size. Once it's full, its last character keeps
+// being replaced, and `half` covers everything after the 31st character.
+struct BlockHash {
+    digest: Vec<u8>,
+    h: u32,
+    half: u32,
+    last: Option<u8>,
+    half_last: Option<u8>,
+}
+
+impl BlockHash {
+    fn trigger(&mut self) {
+        let c = B64[self.h as usize % 64];
+        if self.digest.len() < SPAMSUM_LENGTH - 1 {
+            self.digest.push(c);
+            self.h = HASH_INIT;
+            self.last = None;
+            self.half_last = if self.digest.len() < SPAMSUM_LENGTH / 2 {
+                self.half = HASH_INIT;
+                None
+            } else {
+                Some(B64[self.half as usize % 64])
+            };
+        }
+        else {
+            self.last = Some(c);
+            self.half_last = Some(B64[self.half as usize % 64]);
+        }
+    }
+}
+
+fn block_size(index: usize) -> u64 {
+    MIN_BLOCKSIZE << index
+}
+
+pub fn ssdeep(bytes: &[u8]) -> String {
+    // The first guess; smaller block sizes are kept in case it gives too short a digest.
+    let mut index = 0;
+    while block_size(index) * (SPAMSUM_LENGTH as u64) < bytes.len() as u64 && index < NUM_BLOCKHASHES - 1 {
+        index += 1;
+    }
+    let count = (index + 2).min(NUM_BLOCKHASHES);
+    let mut blocks: Vec<BlockHash> = (0..count)
+        .map(|_| BlockHash { digest: Vec::new(), h: HASH_INIT, half: HASH_INIT, last: None, half_last: None })
+        .collect();
+    let mut roll = RollingHash::default();
+    for &c in bytes {
+        roll.update(c);
+        let sum = roll.sum() as u64;
+        for block in blocks.iter_mut() {
+            block.h = sum_hash(c, block.h);
+            block.half = sum_hash(c, block.half);
+        }
+        // A trigger for a block size is also one for every smaller block size.
+        for (i, block) in blocks.iter_mut().enumerate() {
+            if sum % block_size(i) != block_size(i) - 1 {
+                break;
+            }
+            block.trigger();
+        }
+    }
+    while index > 0 && blocks[index].digest.len() < SPAMSUM_LENGTH / 2 {
+        index -= 1;
+    }
+
+    // The bytes after the last trigger count unless the rolling hash is zero.
+    let partial = roll.sum() != 0;
+    let block = &blocks[index];
+    let mut first = block.digest.clone();
+    first.extend(if partial { Some(B64[block.h as usize % 64]) } else { block.last });
+    let mut second = Vec::<u8>::new();
+    if let Some(block) = blocks.get(index + 1) {
+        second.extend(block.digest.iter().take(SPAMSUM_LENGTH / 2 - 1));
+        second.extend(if partial { Some(B64[block.half as usize % 64]) } else { block.half_last });
+    }
+    else if partial {
+        second.push(B64[block.h as usize % 64]);
+    }
+    format!("{}:{}:{}", block_size(index), String::from_utf8_lossy(&first), String::from_utf8_lossy(&second))
+}
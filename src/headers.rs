@@ -0,0 +1,478 @@
+// Readelf- and dumpbin-style reports of an ELF or PE file's headers, and how
+// much of the file the headers account for. Only the command line shows
+// these, so they live here rather than in the loaders.
+
+use crate::elf::{self, c_string_at, Header, MachineType, ProgramHeaderEntry, SHT_NOBITS, SHT_SYMTAB};
+use crate::error::BaretkError;
+use crate::options::ParseMode;
+use crate::pe::{self, IMAGE_SCN_MEM_EXECUTE, IMAGE_SCN_MEM_READ, IMAGE_SCN_MEM_WRITE, PE_OFFSET_OFFSET};
+use crate::prog::Diagnostics;
+use crate::query;
+use crate::util::{read_u16_checked, read_u32_checked, read_u64_checked, LITTLE_ENDIAN};
+
+// Bytes of the file the format's headers account for, or None for raw
+// binaries and headers that can't be read.
+pub fn image_size(bytes: &[u8]) -> Option<u64> {
+    match query::get_file_type(bytes) {
+        query::FileType::Elf => elf_image_size(bytes).ok(),
+        query::FileType::PE => pe_image_size(bytes).ok(),
+        _ => None,
+    }
+}
+
+// A detailed report of the file's headers, or None for raw binaries.
+pub fn header_info(bytes: &[u8]) -> Result<Option<String>, BaretkError> {
+    match query::get_file_type(bytes) {
+        query::FileType::Elf => elf_info(bytes).map(Some),
+        query::FileType::PE => pe_info(bytes).map(Some),
+        _ => Ok(None),
+    }
+}
+
+fn segment_type_string(t: u32) -> &'static str {
+    match t {
+        0x0 => "NULL",
+        0x1 => "LOAD",
+        0x2 => "DYNAMIC",
+        0x3 => "INTERP",
+        0x4 => "NOTE",
+        0x5 => "SHLIB",
+        0x6 => "PHDR",
+        0x7 => "TLS",
+        0x6474e550 => "GNU_EH_FRAME",
+        0x6474e551 => "GNU_STACK",
+        0x6474e552 => "GNU_RELRO",
+        0x6474e553 => "GNU_PROPERTY",
+        0x70000001 => "EXIDX",
+        0x70000003 => "ATTRIBUTES",
+        _ => "unknown",
+    }
+}
+
+fn abi_string(abi: u8) -> String {
+    match abi {
+        0x0 => format!("none"),
+        0x3 => format!("Linux"),
+        _ => format!("unknown (0x{abi:02x})")
+    }
+}
+
+// Bytes of the file covered by the headers, segments and sections; anything
+// past this was appended.
+fn elf_image_size(bytes: &[u8]) -> Result<u64, BaretkError> {
+    let (_, common_header, program_headers, section_headers) = elf::read_headers(bytes, &mut Diagnostics::new(ParseMode::Permissive))?;
+    let segments = program_headers.iter().map(|ph| ph.p_offset.saturating_add(ph.p_filesz));
+    let sections = section_headers.iter().filter(|sh| sh.sh_type != SHT_NOBITS).map(|sh| sh.sh_offset.saturating_add(sh.sh_size));
+    let tables = [
+        common_header.e_phoff.saturating_add(common_header.e_phnum as u64 * common_header.e_phentsize as u64),
+        common_header.e_shoff.saturating_add(common_header.e_shnum as u64 * common_header.e_shentsize as u64),
+    ];
+    Ok(segments.chain(sections).chain(tables).max().unwrap_or(0))
+}
+
+fn dynamic_tag_string(tag: u64) -> &'static str {
+    match tag {
+        0 => "NULL",
+        1 => "NEEDED",
+        2 => "PLTRELSZ",
+        3 => "PLTGOT",
+        4 => "HASH",
+        5 => "STRTAB",
+        6 => "SYMTAB",
+        7 => "RELA",
+        8 => "RELASZ",
+        9 => "RELAENT",
+        10 => "STRSZ",
+        11 => "SYMENT",
+        12 => "INIT",
+        13 => "FINI",
+        14 => "SONAME",
+        15 => "RPATH",
+        16 => "SYMBOLIC",
+        17 => "REL",
+        18 => "RELSZ",
+        19 => "RELENT",
+        20 => "PLTREL",
+        21 => "DEBUG",
+        22 => "TEXTREL",
+        23 => "JMPREL",
+        24 => "BIND_NOW",
+        25 => "INIT_ARRAY",
+        26 => "FINI_ARRAY",
+        27 => "INIT_ARRAYSZ",
+        28 => "FINI_ARRAYSZ",
+        29 => "RUNPATH",
+        30 => "FLAGS",
+        32 => "PREINIT_ARRAY",
+        33 => "PREINIT_ARRAYSZ",
+        35 => "RELRSZ",
+        36 => "RELR",
+        37 => "RELRENT",
+        0x6ffffef5 => "GNU_HASH",
+        0x6ffffff0 => "VERSYM",
+        0x6ffffff9 => "RELACOUNT",
+        0x6ffffffa => "RELCOUNT",
+        0x6ffffffb => "FLAGS_1",
+        0x6ffffffc => "VERDEF",
+        0x6ffffffd => "VERDEFNUM",
+        0x6ffffffe => "VERNEED",
+        0x6fffffff => "VERNEEDNUM",
+        _ => "unknown",
+    }
+}
+
+// Names of the set flags, with any bits left over in hex.
+fn flag_names(value: u64, names: &[(u64, &str)], separator: &str) -> String {
+    let mut out: Vec<&str> = names.iter().filter(|(bit, _)| value & bit != 0).map(|(_, name)| *name).collect();
+    let unknown = names.iter().fold(value, |v, (bit, _)| v & !bit);
+    let rest = format!("{:#x}", unknown);
+    if unknown != 0 {
+        out.push(rest.as_str());
+    }
+    out.join(separator)
+}
+
+// Processor-specific e_flags, where they mean something to us.
+fn machine_flags_string(machine: u16, flags: u32) -> String {
+    match MachineType(machine) {
+        MachineType::ARM => {
+            let mut out = format!("EABI version {}", flags >> 24);
+            if flags & 0x400 != 0 {
+                out += ", hard-float ABI";
+            }
+            if flags & 0x200 != 0 {
+                out += ", soft-float ABI";
+            }
+            out
+        },
+        MachineType::RISCV => {
+            let float = ["soft-float", "single-float", "double-float", "quad-float"][(flags as usize >> 1) & 3];
+            let mut out = format!("{} ABI", float);
+            if flags & 0x1 != 0 {
+                out += ", compressed";
+            }
+            if flags & 0x8 != 0 {
+                out += ", RVE";
+            }
+            if flags & 0x10 != 0 {
+                out += ", TSO";
+            }
+            out
+        },
+        _ => String::new(),
+    }
+}
+
+// File offset of a virtual address inside a loaded segment.
+fn vaddr_to_offset(program_headers: &[ProgramHeaderEntry], vaddr: u64) -> Option<usize> {
+    program_headers.iter()
+        .find(|ph| ph.p_type == 0x1 && vaddr >= ph.p_vaddr && vaddr < ph.p_vaddr.saturating_add(ph.p_filesz))
+        .map(|ph| (vaddr - ph.p_vaddr).saturating_add(ph.p_offset) as usize)
+}
+
+// The tag and value of each entry in the dynamic segment, up to DT_NULL.
+pub fn dynamic_entries(bytes: &[u8], header: &Header, dynamic: &ProgramHeaderEntry) -> Vec<(u64, u64)> {
+    let is_64 = header.class == 0x2;
+    let entry_size = if is_64 { 16 } else { 8 };
+    let read = |offset: usize| if is_64 {
+        read_u64_checked(bytes, offset, header.data, "ELF dynamic section")
+    } else {
+        read_u32_checked(bytes, offset, header.data, "ELF dynamic section").map(u64::from)
+    };
+    let start = dynamic.p_offset as usize;
+    let end = start.saturating_add(dynamic.p_filesz as usize).min(bytes.len());
+    let mut entries = Vec::<(u64, u64)>::new();
+    let mut offset = start;
+    while offset + entry_size <= end {
+        let (Ok(tag), Ok(value)) = (read(offset), read(offset + entry_size / 2)) else {
+            break;
+        };
+        if tag == 0 {
+            break;
+        }
+        entries.push((tag, value));
+        offset += entry_size;
+    }
+    entries
+}
+
+// A readelf-style report of every header: the file header, program headers,
+// section headers and the dynamic section.
+fn elf_info(bytes: &[u8]) -> Result<String, BaretkError> {
+    let mut diagnostics = Diagnostics::new(ParseMode::Permissive);
+    let (header, common_header, program_headers, section_headers) = elf::read_headers(bytes, &mut diagnostics)?;
+    let is_64 = header.class == 0x2;
+    let mut s = String::new();
+    s += "ELF header:\n";
+    s += format!("  Class:              {}\n", if is_64 { "ELF64" } else { "ELF32" }).as_str();
+    s += format!("  Data:               {}\n", if header.data == 0x1 { "little-endian" } else { "big-endian" }).as_str();
+    s += format!("  OS/ABI:             {}, version {}\n", abi_string(bytes[0x07]), bytes[0x08]).as_str();
+    s += format!("  Type:               {} ({})\n", elf::elf_file_type_string(common_header.e_type), common_header.e_type).as_str();
+    s += format!("  Machine:            {} ({:#x})\n", elf::machine_type_string(common_header.e_machine), common_header.e_machine).as_str();
+    s += format!("  Version:            {}\n", common_header.e_version).as_str();
+    s += format!("  Entry point:        {:#x}\n", common_header.e_entry).as_str();
+    let flags = format!("{:#x} {}", common_header.e_flags, machine_flags_string(common_header.e_machine, common_header.e_flags));
+    s += format!("  Flags:              {}\n", flags.trim_end()).as_str();
+    s += format!("  Header size:        {} bytes\n", common_header.e_ehsize).as_str();
+    s += format!("  Program headers:    {} of {} bytes at offset {:#x}\n", common_header.e_phnum, common_header.e_phentsize, common_header.e_phoff).as_str();
+    s += format!("  Section headers:    {} of {} bytes at offset {:#x}\n", common_header.e_shnum, common_header.e_shentsize, common_header.e_shoff).as_str();
+    s += format!("  Section names:      section {}\n", common_header.e_shstrndx).as_str();
+
+    let has_dynamic = program_headers.iter().any(|ph| ph.p_type == 0x2);
+    let mut characteristics = vec![if has_dynamic { "dynamically linked" } else { "statically linked" }];
+    if common_header.e_type == 0x3 && program_headers.iter().any(|ph| ph.p_type == 0x3) {
+        characteristics.push("position independent executable");
+    }
+    if !section_headers.iter().any(|sh| sh.sh_type == SHT_SYMTAB) {
+        characteristics.push("stripped");
+    }
+    if program_headers.iter().any(|ph| ph.p_type == 0x6474e551 && ph.p_flags & 0x1 != 0) {
+        characteristics.push("executable stack");
+    }
+    s += format!("  Characteristics:    {}\n", characteristics.join(", ")).as_str();
+
+    s += format!("\nProgram headers:\n  {:<14} {:<10} {:<18} {:<18} {:<10} {:<10} {:<4} {}\n",
+        "Type", "Offset", "VirtAddr", "PhysAddr", "FileSize", "MemSize", "Flg", "Align").as_str();
+    for ph in &program_headers {
+        let flags = format!("{}{}{}",
+            if ph.p_flags & 0x4 != 0 { "R" } else { " " },
+            if ph.p_flags & 0x2 != 0 { "W" } else { " " },
+            if ph.p_flags & 0x1 != 0 { "E" } else { " " });
+        s += format!("  {:<14} {:#010x} {:#018x} {:#018x} {:#010x} {:#010x} {:<4} {:#x}\n",
+            segment_type_string(ph.p_type), ph.p_offset, ph.p_vaddr, ph.p_paddr, ph.p_filesz, ph.p_memsz, flags, ph.p_align).as_str();
+        if ph.p_type == 0x3 {
+            s += format!("      interpreter: {}\n", c_string_at(bytes, ph.p_offset as usize)).as_str();
+        }
+    }
+
+    let names = elf::section_names(&common_header, &section_headers, &mut diagnostics)?;
+    const SECTION_FLAGS: &[(u64, &str)] = &[(0x1, "W"), (0x2, "A"), (0x4, "X"), (0x10, "M"), (0x20, "S"), (0x40, "I"),
+        (0x80, "L"), (0x100, "O"), (0x200, "G"), (0x400, "T")];
+    s += format!("\nSection headers:\n  {:<4} {:<20} {:<18} {:<18} {:<10} {:<10} {:<6} {:<8} {:<4} {:<4} {}\n",
+        "Nr", "Name", "Type", "Addr", "Offset", "Size", "EntSz", "Flags", "Link", "Info", "Align").as_str();
+    for (i, sh) in section_headers.iter().enumerate() {
+        let flags = flag_names(sh.sh_flags, SECTION_FLAGS, "");
+        s += format!("  {:<4} {:<20} {:<18} {:#018x} {:#010x} {:#010x} {:<6x} {:<8} {:<4} {:<4} {:#x}\n",
+            i, elf::section_name(bytes, names, i, sh), elf::section_type_string(sh.sh_type), sh.sh_addr,
+            sh.sh_offset, sh.sh_size, sh.sh_entsize, flags, sh.sh_link, sh.sh_info, sh.sh_addralign).as_str();
+    }
+    s += "  Flags: W write, A alloc, X execute, M merge, S strings, I info link, L link order, O OS specific, G group, T TLS\n";
+
+    if let Some(dynamic) = program_headers.iter().find(|ph| ph.p_type == 0x2) {
+        let start = dynamic.p_offset as usize;
+        let entries = dynamic_entries(bytes, &header, dynamic);
+        let strtab = entries.iter().find(|(tag, _)| *tag == 5).and_then(|(_, addr)| vaddr_to_offset(&program_headers, *addr));
+        s += format!("\nDynamic section: {} entries at offset {:#x}\n", entries.len(), start).as_str();
+        for (tag, value) in entries {
+            let text = match (tag, strtab) {
+                (1, Some(strtab)) => format!("shared library: {}", c_string_at(bytes, strtab.saturating_add(value as usize))),
+                (14, Some(strtab)) => format!("library name: {}", c_string_at(bytes, strtab.saturating_add(value as usize))),
+                (15 | 29, Some(strtab)) => format!("search path: {}", c_string_at(bytes, strtab.saturating_add(value as usize))),
+                (2 | 8 | 9 | 10 | 11 | 18 | 19 | 27 | 28 | 33 | 35 | 37, _) => format!("{} bytes", value),
+                (20, _) => (if value == 7 { "RELA" } else { "REL" }).to_string(),
+                (30, _) => format!("{:#x} {}", value, flag_names(value, &[(0x1, "ORIGIN"), (0x2, "SYMBOLIC"), (0x4, "TEXTREL"), (0x8, "BIND_NOW"), (0x10, "STATIC_TLS")], " ")),
+                (0x6ffffffb, _) => format!("{:#x} {}", value, flag_names(value, &[(0x1, "NOW"), (0x8, "NODELETE"), (0x8000000, "PIE")], " ")),
+                (0x6ffffff9 | 0x6ffffffa | 0x6ffffffd | 0x6fffffff, _) => format!("{}", value),
+                _ => format!("{:#x}", value),
+            };
+            s += format!("  {:<16} {}\n", dynamic_tag_string(tag), text).as_str();
+        }
+    }
+
+    if !diagnostics.warnings.is_empty() {
+        s += "\nWarnings:\n";
+        for warning in &diagnostics.warnings {
+            s += format!("  {}\n", warning).as_str();
+        }
+    }
+    Ok(s)
+}
+
+// Bytes of the file covered by the headers and section data; anything past
+// this is an overlay, such as an installer payload or a signature.
+fn pe_image_size(bytes: &[u8]) -> Result<u64, BaretkError> {
+    let offset = read_u32_checked(bytes, PE_OFFSET_OFFSET, LITTLE_ENDIAN, "DOS header")? as usize;
+    let coff_header = pe::read_coff_header(bytes, offset)?;
+    let toffset = coff_header.optional_header_size as usize + offset + 0x18;
+    let mut end = (toffset + coff_header.num_sections as usize * 40) as u64;
+    for i in 0..coff_header.num_sections {
+        let section_header = pe::read_section_header_32(bytes, toffset + (i as usize * 40))?;
+        end = end.max(section_header.data_ptr as u64 + section_header.data_size as u64);
+    }
+    Ok(end)
+}
+
+const COFF_CHARACTERISTICS: &[(u32, &str)] = &[
+    (0x1, "relocations stripped"), (0x2, "executable"), (0x4, "line numbers stripped"), (0x8, "symbols stripped"),
+    (0x10, "aggressive working set trim"), (0x20, "large address aware"), (0x80, "bytes reversed (lo)"),
+    (0x100, "32-bit machine"), (0x200, "debug info stripped"), (0x400, "run from swap if removable"),
+    (0x800, "run from swap if on network"), (0x1000, "system file"), (0x2000, "DLL"), (0x4000, "uniprocessor only"),
+    (0x8000, "bytes reversed (hi)"),
+];
+
+const DLL_CHARACTERISTICS: &[(u32, &str)] = &[
+    (0x20, "high entropy VA"), (0x40, "dynamic base (ASLR)"), (0x80, "force integrity"), (0x100, "NX compatible (DEP)"),
+    (0x200, "no isolation"), (0x400, "no SEH"), (0x800, "no bind"), (0x1000, "app container"), (0x2000, "WDM driver"),
+    (0x4000, "control flow guard"), (0x8000, "terminal server aware"),
+];
+
+const SECTION_CHARACTERISTICS: &[(u32, &str)] = &[
+    (0x20, "code"), (0x40, "initialized data"), (0x80, "uninitialized data"), (0x200, "info"), (0x800, "remove"),
+    (0x1000, "COMDAT"), (0x8000, "global pointer"), (0x1000000, "extended relocations"), (0x2000000, "discardable"),
+    (0x4000000, "not cached"), (0x8000000, "not paged"), (0x10000000, "shared"), (IMAGE_SCN_MEM_EXECUTE, "execute"),
+    (IMAGE_SCN_MEM_READ, "read"), (IMAGE_SCN_MEM_WRITE, "write"),
+];
+
+const DATA_DIRECTORIES: &[&str] = &[
+    "Export", "Import", "Resource", "Exception", "Certificate", "Base relocation", "Debug", "Architecture",
+    "Global pointer", "TLS", "Load config", "Bound import", "IAT", "Delay import", "CLR runtime", "Reserved",
+];
+
+fn flags_string(value: u32, names: &[(u32, &str)]) -> String {
+    let mut out: Vec<String> = names.iter().filter(|(bit, _)| value & bit != 0).map(|(_, name)| name.to_string()).collect();
+    // Section alignment is a 4-bit field, not a flag.
+    let unknown = names.iter().fold(value, |v, (bit, _)| v & !bit) & !0x00f00000;
+    if unknown != 0 {
+        out.push(format!("{:#x}", unknown));
+    }
+    out.join(", ")
+}
+
+fn subsystem_string(subsystem: u16) -> &'static str {
+    match subsystem {
+        1 => "native",
+        2 => "Windows GUI",
+        3 => "Windows console",
+        5 => "OS/2 console",
+        7 => "POSIX console",
+        9 => "Windows CE GUI",
+        10 => "EFI application",
+        11 => "EFI boot service driver",
+        12 => "EFI runtime driver",
+        13 => "EFI ROM",
+        14 => "Xbox",
+        16 => "Windows boot application",
+        _ => "unknown",
+    }
+}
+
+// UTC date and time of a Unix timestamp, as "YYYY-MM-DD hh:mm:ss".
+fn timestamp_string(timestamp: u32) -> String {
+    let days = timestamp as i64 / 86400;
+    let secs = timestamp as i64 % 86400;
+    // Days to a civil date, from Howard Hinnant's algorithm.
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+// The optional header's fields and data directories, for info.
+fn optional_header_info(bytes: &[u8], opt: usize, optional_header_size: u16) -> Result<String, BaretkError> {
+    let u16_at = |at: usize| read_u16_checked(bytes, at, LITTLE_ENDIAN, "PE optional header");
+    let u32_at = |at: usize| read_u32_checked(bytes, at, LITTLE_ENDIAN, "PE optional header");
+    let mut s = String::new();
+    let header = pe::read_optional_header(bytes, opt)?;
+    let is_64 = header.magic == 0x20b;
+    let u64_at = |at: usize| if is_64 { read_u64_checked(bytes, at, LITTLE_ENDIAN, "PE optional header") } else { u32_at(at).map(u64::from) };
+    // PE32+ drops the base of data and widens the image base and stack/heap sizes.
+    let (sizes, directories) = if is_64 { (opt + 0x48, opt + 0x70) } else { (opt + 0x48, opt + 0x60) };
+    let word = if is_64 { 8 } else { 4 };
+    s += "\nOptional header:\n";
+    s += format!("  Magic:              {:#x} ({})\n", header.magic, match header.magic { 0x10b => "PE32", 0x20b => "PE32+", 0x107 => "ROM", _ => "unknown" }).as_str();
+    s += format!("  Linker version:     {}.{}\n", header.major_link_ver, header.minor_link_ver).as_str();
+    s += format!("  Size of code:       {:#x}\n", header.code_size).as_str();
+    s += format!("  Initialized data:   {:#x}\n", header.data_size).as_str();
+    s += format!("  Uninitialized data: {:#x}\n", header.bss_size).as_str();
+    s += format!("  Entry point:        {:#x}\n", header.entry_point).as_str();
+    s += format!("  Base of code:       {:#x}\n", header.base_addr).as_str();
+    s += format!("  Image base:         {:#x}\n", if is_64 { u64_at(opt + 0x18)? } else { u32_at(opt + 0x1c)? as u64 }).as_str();
+    s += format!("  Section alignment:  {:#x}\n", u32_at(opt + 0x20)?).as_str();
+    s += format!("  File alignment:     {:#x}\n", u32_at(opt + 0x24)?).as_str();
+    s += format!("  OS version:         {}.{}\n", u16_at(opt + 0x28)?, u16_at(opt + 0x2a)?).as_str();
+    s += format!("  Image version:      {}.{}\n", u16_at(opt + 0x2c)?, u16_at(opt + 0x2e)?).as_str();
+    s += format!("  Subsystem version:  {}.{}\n", u16_at(opt + 0x30)?, u16_at(opt + 0x32)?).as_str();
+    s += format!("  Size of image:      {:#x}\n", u32_at(opt + 0x38)?).as_str();
+    s += format!("  Size of headers:    {:#x}\n", u32_at(opt + 0x3c)?).as_str();
+    s += format!("  Checksum:           {:#x}\n", u32_at(opt + 0x40)?).as_str();
+    s += format!("  Subsystem:          {} ({})\n", subsystem_string(u16_at(opt + 0x44)?), u16_at(opt + 0x44)?).as_str();
+    let dll = u16_at(opt + 0x46)? as u32;
+    s += format!("  DLL characteristics: {:#06x} {}\n", dll, flags_string(dll, DLL_CHARACTERISTICS)).as_str();
+    s += format!("  Stack reserve:      {:#x}, commit {:#x}\n", u64_at(sizes)?, u64_at(sizes + word)?).as_str();
+    s += format!("  Heap reserve:       {:#x}, commit {:#x}\n", u64_at(sizes + 2 * word)?, u64_at(sizes + 3 * word)?).as_str();
+    let count = u32_at(directories - 4)? as usize;
+    let end = opt + optional_header_size as usize;
+    s += "\nData directories:\n";
+    for (i, name) in DATA_DIRECTORIES.iter().enumerate().take(count) {
+        let at = directories + i * 8;
+        if at + 8 > end {
+            break;
+        }
+        let (rva, size) = (u32_at(at)?, u32_at(at + 4)?);
+        if rva != 0 || size != 0 {
+            s += format!("  {:<16} RVA {:#010x} size {:#x}\n", name, rva, size).as_str();
+        }
+    }
+    Ok(s)
+}
+
+// A dumpbin-style report of every header: the COFF file header, the optional
+// header with its data directories, and the section headers.
+fn pe_info(bytes: &[u8]) -> Result<String, BaretkError> {
+    let mut diagnostics = Diagnostics::new(ParseMode::Permissive);
+    let offset = read_u32_checked(bytes, PE_OFFSET_OFFSET, LITTLE_ENDIAN, "DOS header")? as usize;
+    let coff_header = pe::read_coff_header(bytes, offset)?;
+    let u32_at = |at: usize| read_u32_checked(bytes, at, LITTLE_ENDIAN, "PE file header");
+    let mut s = String::new();
+    s += format!("PE signature at offset {:#x}\n", offset).as_str();
+    s += "\nFile header:\n";
+    s += format!("  Machine:            {} ({:#x})\n", pe::get_machine_type_string(coff_header.machine), coff_header.machine).as_str();
+    s += format!("  Sections:           {}\n", coff_header.num_sections).as_str();
+    s += format!("  Timestamp:          {:#x} ({} UTC)\n", coff_header.timestamp, timestamp_string(coff_header.timestamp)).as_str();
+    s += format!("  Symbol table:       {} symbol(s) at offset {:#x}\n", u32_at(offset + 0x10)?, u32_at(offset + 0xc)?).as_str();
+    s += format!("  Optional header:    {} bytes\n", coff_header.optional_header_size).as_str();
+    s += format!("  Characteristics:    {:#06x} {}\n", coff_header.characteristics,
+        flags_string(coff_header.characteristics as u32, COFF_CHARACTERISTICS)).as_str();
+
+    let opt = offset + 0x18;
+    if coff_header.optional_header_size >= 0x60 {
+        match optional_header_info(bytes, opt, coff_header.optional_header_size) {
+            Ok(text) => s += text.as_str(),
+            Err(err) => diagnostics.tolerate(err)?,
+        }
+    }
+
+    let table = opt + coff_header.optional_header_size as usize;
+    s += format!("\nSection headers:\n  {:<8} {:<10} {:<10} {:<10} {:<10} {:<10} {}\n",
+        "Name", "VirtAddr", "VirtSize", "RawPtr", "RawSize", "Flags", "Characteristics").as_str();
+    for i in 0..coff_header.num_sections as usize {
+        let header = match pe::read_section_header_32(bytes, table + i * 40) {
+            Ok(header) => header,
+            Err(err) => {
+                diagnostics.tolerate(err)?;
+                break;
+            },
+        };
+        let mut characteristics = flags_string(header.characteristics, SECTION_CHARACTERISTICS);
+        let align = pe::section_alignment(header.characteristics);
+        if align != 0 {
+            characteristics += format!(", align {}", align).as_str();
+        }
+        s += format!("  {:<8} {:#010x} {:#010x} {:#010x} {:#010x} {:#010x} {}\n", pe::get_name_from_section_header(&header),
+            header.virtual_addr, header.virtual_size, header.data_ptr, header.data_size, header.characteristics, characteristics).as_str();
+    }
+
+    if !diagnostics.warnings.is_empty() {
+        s += "\nWarnings:\n";
+        for warning in &diagnostics.warnings {
+            s += format!("  {}\n", warning).as_str();
+        }
+    }
+    Ok(s)
+}
@@ -0,0 +1,282 @@
+// Intel HEX and Motorola S-record firmware images: text files of addressed data
+// records, read into chunks of contiguous bytes and written back from them.
+//
+//     :10010000214601360121470136007EFE09D2190140    Intel HEX data record
+//     S1130000285F245F2212226A000424290008237C2A    S-record data record
+
+pub enum Format {
+    Raw,
+    IntelHex,
+    SRecord,
+}
+
+pub struct Chunk {
+    pub addr: u64,
+    pub bytes: Vec<u8>,
+}
+
+pub struct Image {
+    // Sorted by address and not overlapping.
+    pub chunks: Vec<Chunk>,
+    // Start address from the file's start or termination record.
+    pub entry: Option<u64>,
+}
+
+// Data bytes per written record.
+const RECORD_SIZE: usize = 16;
+
+// Guesses the format from the contents: lines starting with ':' or 'S' followed by hex.
+pub fn detect(bytes: &[u8]) -> Format {
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(_) => return Format::Raw,
+    };
+    let mut lines = text.lines().map(|line| line.trim()).filter(|line| !line.is_empty()).peekable();
+    let first = match lines.peek() {
+        Some(line) => *line,
+        None => return Format::Raw,
+    };
+    let is_hex = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit());
+    if lines.clone().all(|line| line.starts_with(':') && is_hex(&line[1..])) {
+        return Format::IntelHex
+    }
+    if first.starts_with('S') && lines.all(|line| line.starts_with('S') && is_hex(&line[1..])) {
+        return Format::SRecord
+    }
+    Format::Raw
+}
+
+// The format an output file name implies.
+pub fn format_for_path(path: &str) -> Format {
+    let ext = path.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).unwrap_or_default();
+    match ext.as_str() {
+        "hex" | "ihex" | "ihx" => Format::IntelHex,
+        "srec" | "s19" | "s28" | "s37" | "mot" => Format::SRecord,
+        _ => Format::Raw,
+    }
+}
+
+fn hex_bytes(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok()).collect()
+}
+
+fn be_value(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0u64, |value, b| value << 8 | *b as u64)
+}
+
+impl Image {
+    pub fn from_raw(bytes: &[u8], base: u64) -> Image {
+        Image { chunks: vec![Chunk { addr: base, bytes: bytes.to_vec() }], entry: None }
+    }
+
+    // Adds data, merging it into the chunk it continues.
+    fn add(&mut self, addr: u64, data: &[u8]) {
+        if let Some(last) = self.chunks.last_mut() {
+            if last.addr + last.bytes.len() as u64 == addr {
+                last.bytes.extend_from_slice(data);
+                return;
+            }
+        }
+        self.chunks.push(Chunk { addr, bytes: data.to_vec() });
+    }
+
+    // Sorts the chunks and joins adjacent ones; where records overlap, later ones win.
+    fn finish(mut self) -> Image {
+        self.chunks.sort_by_key(|chunk| chunk.addr);
+        let mut chunks = Vec::<Chunk>::new();
+        for chunk in self.chunks {
+            match chunks.last_mut() {
+                Some(last) if chunk.addr <= last.addr + last.bytes.len() as u64 => {
+                    let start = (chunk.addr - last.addr) as usize;
+                    let end = start + chunk.bytes.len();
+                    if end > last.bytes.len() {
+                        last.bytes.resize(end, 0);
+                    }
+                    last.bytes[start..end].copy_from_slice(&chunk.bytes);
+                },
+                _ => chunks.push(chunk),
+            }
+        }
+        Image { chunks, entry: self.entry }
+    }
+
+    // Errors give the line number and what's wrong with it.
+    pub fn parse_ihex(text: &str) -> Result<Image, String> {
+        let mut image = Image { chunks: Vec::new(), entry: None };
+        let mut base = 0u64;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |what: &str| format!("line {}: {}", i + 1, what);
+            let record = line.strip_prefix(':').and_then(hex_bytes).ok_or(error("not an Intel HEX record"))?;
+            if record.len() < 5 || record.len() != record[0] as usize + 5 {
+                return Err(error("wrong record length"))
+            }
+            if record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+                return Err(error("bad checksum"))
+            }
+            let offset = be_value(&record[1..3]);
+            let data = &record[4..record.len() - 1];
+            match record[3] {
+                0x00 => image.add(base + offset, data),
+                0x01 => break,
+                0x02 if data.len() == 2 => base = be_value(data) << 4,
+                0x04 if data.len() == 2 => base = be_value(data) << 16,
+                // Start segment address (CS:IP) and start linear address.
+                0x03 if data.len() == 4 => image.entry = Some((be_value(&data[0..2]) << 4) + be_value(&data[2..4])),
+                0x05 if data.len() == 4 => image.entry = Some(be_value(data)),
+                _ => return Err(error("unknown record type")),
+            }
+        }
+        Ok(image.finish())
+    }
+
+    pub fn parse_srec(text: &str) -> Result<Image, String> {
+        let mut image = Image { chunks: Vec::new(), entry: None };
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |what: &str| format!("line {}: {}", i + 1, what);
+            let kind = line.as_bytes().get(1).copied().unwrap_or(0);
+            let record = line.strip_prefix('S').and_then(|s| s.get(1..)).and_then(hex_bytes).ok_or(error("not an S-record"))?;
+            if record.is_empty() || record.len() != record[0] as usize + 1 {
+                return Err(error("wrong record length"))
+            }
+            if record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0xff {
+                return Err(error("bad checksum"))
+            }
+            let addr_len = match kind {
+                b'0' | b'1' | b'5' | b'9' => 2,
+                b'2' | b'6' | b'8' => 3,
+                b'3' | b'7' => 4,
+                _ => return Err(error("unknown record type")),
+            };
+            if record.len() < addr_len + 2 {
+                return Err(error("record too short for its address"))
+            }
+            let addr = be_value(&record[1..1 + addr_len]);
+            let data = &record[1 + addr_len..record.len() - 1];
+            match kind {
+                b'1' | b'2' | b'3' => image.add(addr, data),
+                b'7' | b'8' | b'9' => image.entry = Some(addr),
+                // Header and record counts.
+                _ => (),
+            }
+        }
+        Ok(image.finish())
+    }
+
+    // `base` is the load address of raw bytes; the other formats carry their own.
+    pub fn parse(bytes: &[u8], format: &Format, base: u64) -> Result<Image, String> {
+        let text = String::from_utf8_lossy(bytes);
+        match format {
+            Format::Raw => Ok(Image::from_raw(bytes, base)),
+            Format::IntelHex => Image::parse_ihex(&text),
+            Format::SRecord => Image::parse_srec(&text),
+        }
+    }
+
+    // The lowest address and the bytes from there to the end of the last chunk,
+    // with gaps filled.
+    pub fn to_raw(&self, fill: u8) -> (u64, Vec<u8>) {
+        let base = match self.chunks.first() {
+            Some(chunk) => chunk.addr,
+            None => return (0, Vec::new()),
+        };
+        let mut out = Vec::<u8>::new();
+        for chunk in &self.chunks {
+            out.resize((chunk.addr - base) as usize, fill);
+            out.extend_from_slice(&chunk.bytes);
+        }
+        (base, out)
+    }
+
+    pub fn end(&self) -> u64 {
+        self.chunks.last().map_or(0, |chunk| chunk.addr + chunk.bytes.len() as u64)
+    }
+
+    // Fails if the data doesn't fit in 32-bit addresses.
+    pub fn to_ihex(&self) -> Result<String, String> {
+        if self.end() > 0x1_0000_0000 {
+            return Err("Intel HEX addresses are limited to 32 bits".to_string())
+        }
+        let mut out = String::new();
+        let mut record = |kind: u8, offset: u16, data: &[u8]| {
+            let mut bytes = vec![data.len() as u8, (offset >> 8) as u8, offset as u8, kind];
+            bytes.extend_from_slice(data);
+            let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+            bytes.push(sum.wrapping_neg());
+            out += ":";
+            out += bytes.iter().map(|b| format!("{:02X}", b)).collect::<String>().as_str();
+            out += "\n";
+        };
+        let mut upper = 0u64;
+        for chunk in &self.chunks {
+            let mut addr = chunk.addr;
+            for data in chunk.bytes.chunks(RECORD_SIZE) {
+                // Records don't cross a 64 KiB boundary; split them where they would.
+                let mut data = data;
+                while !data.is_empty() {
+                    if addr >> 16 != upper {
+                        upper = addr >> 16;
+                        record(0x04, 0, &[(upper >> 8) as u8, upper as u8]);
+                    }
+                    let len = data.len().min((0x10000 - (addr & 0xffff)) as usize);
+                    record(0x00, addr as u16, &data[..len]);
+                    addr += len as u64;
+                    data = &data[len..];
+                }
+            }
+        }
+        if let Some(entry) = self.entry {
+            record(0x05, 0, &(entry as u32).to_be_bytes());
+        }
+        record(0x01, 0, &[]);
+        Ok(out)
+    }
+
+    // Uses the shortest addresses (S1, S2 or S3) that fit the data.
+    pub fn to_srec(&self) -> Result<String, String> {
+        let last = self.end().max(self.entry.unwrap_or(0) + 1);
+        let addr_len = match last {
+            0..=0x1_0000 => 2,
+            0x1_0001..=0x100_0000 => 3,
+            0x100_0001..=0x1_0000_0000 => 4,
+            _ => return Err("S-record addresses are limited to 32 bits".to_string()),
+        };
+        let mut out = String::new();
+        let mut record = |kind: u8, addr: u64, data: &[u8]| {
+            // Headers and counts always have 16-bit addresses.
+            let len = if kind == 0 || kind == 5 { 2 } else { addr_len };
+            let mut bytes = vec![(len + data.len() + 1) as u8];
+            bytes.extend_from_slice(&addr.to_be_bytes()[8 - len..]);
+            bytes.extend_from_slice(data);
+            let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+            bytes.push(!sum);
+            out += format!("S{}", kind).as_str();
+            out += bytes.iter().map(|b| format!("{:02X}", b)).collect::<String>().as_str();
+            out += "\n";
+        };
+        let data_kind = (addr_len - 1) as u8;
+        record(0, 0, b"baretk");
+        let mut count = 0u64;
+        for chunk in &self.chunks {
+            for (i, data) in chunk.bytes.chunks(RECORD_SIZE).enumerate() {
+                record(data_kind, chunk.addr + (i * RECORD_SIZE) as u64, data);
+                count += 1;
+            }
+        }
+        if count <= 0xffff {
+            record(5, count, &[]);
+        }
+        record(10 - data_kind, self.entry.unwrap_or(0), &[]);
+        Ok(out)
+    }
+}
@@ -0,0 +1,209 @@
+// Functions imported by dynamically linked programs. ELF imports are found
+// through their relocations, which give the GOT slot each import is loaded
+// from; calls go through a PLT stub that jumps through that slot. PE imports
+// are read from the import directory when the program is loaded.
+
+use std::collections::HashMap;
+
+use crate::prog::{Import, Program};
+use crate::util::{self, BIG_ENDIAN, LITTLE_ENDIAN};
+
+fn c_string(bytes: &[u8], offset: usize) -> String {
+    let tail = bytes.get(offset..).unwrap_or(&[]);
+    let end = tail.iter().position(|b| *b == 0).unwrap_or(tail.len());
+    String::from_utf8_lossy(&tail[..end]).to_string()
+}
+
+// PLT header and entry sizes for targets whose stubs are found by position.
+fn plt_layout(machine_type: &str) -> Option<(u64, u64)> {
+    match machine_type {
+        "arm" => Some((20, 12)),
+        "aarch64" => Some((32, 16)),
+        "riscv" => Some((32, 16)),
+        _ => None,
+    }
+}
+
+// Maps GOT slots to the x86 stubs jumping through them (`jmp [slot]`), looking
+// through every PLT flavour: lazy .plt, .plt.sec with IBT and .plt.got.
+fn x86_stubs(program: &Program) -> HashMap<u64, u64> {
+    let got_plt = program.section_table.get(".got.plt").map_or(0, |got| got.addr);
+    let mut stubs = HashMap::<u64, u64>::new();
+    for name in [".plt", ".plt.sec", ".plt.got"] {
+        let section = match program.section_table.get(name) {
+            Some(section) => section,
+            None => continue,
+        };
+        let bytes = &section.bytes;
+        for i in 0..bytes.len().saturating_sub(5) {
+            let disp = util::read_i32_from_slice(bytes, i + 2, LITTLE_ENDIAN).unwrap_or(0) as u64;
+            let slot = match (bytes[i], bytes[i + 1]) {
+                (0xff, 0x25) if program.bits == 64 => (section.addr + i as u64 + 6).wrapping_add(disp),
+                (0xff, 0x25) => disp & 0xffff_ffff,
+                // 32-bit position independent stubs index from the GOT held in ebx.
+                (0xff, 0xa3) => got_plt.wrapping_add(disp),
+                _ => continue,
+            };
+            // Include a bnd prefix and endbr instruction in front of the jump.
+            let mut start = i;
+            if start >= 1 && bytes[start - 1] == 0xf2 {
+                start -= 1;
+            }
+            if start >= 4 && matches!(bytes[start - 4..start], [0xf3, 0x0f, 0x1e, 0xfa | 0xfb]) {
+                start -= 4;
+            }
+            // Lazy .plt stubs are shadowed by their .plt.sec counterparts.
+            stubs.insert(slot, section.addr + start as u64);
+        }
+    }
+    stubs
+}
+
+fn elf_imports(program: &Program) -> Vec<Import> {
+    let (dynsym, dynstr) = match (program.section_table.get(".dynsym"), program.section_table.get(".dynstr")) {
+        (Some(dynsym), Some(dynstr)) => (&dynsym.bytes, &dynstr.bytes),
+        _ => return Vec::new(),
+    };
+    let endianness = if program.endianess == BIG_ENDIAN { BIG_ENDIAN } else { LITTLE_ENDIAN };
+    let is_64 = program.bits == 64;
+    let sym_size = if is_64 { 24 } else { 16 };
+    let x86 = matches!(program.machine_type.as_str(), "x86" | "amd64");
+    let stubs = if x86 { x86_stubs(program) } else { HashMap::new() };
+    let plt = program.section_table.get(".plt").map(|plt| plt.addr);
+
+    let mut imports = Vec::<Import>::new();
+    for (name, has_addend) in [(".rela.plt", true), (".rel.plt", false), (".rela.dyn", true), (".rel.dyn", false)] {
+        let relocs = match program.section_table.get(name) {
+            Some(section) => &section.bytes,
+            None => continue,
+        };
+        let entry_size = match (is_64, has_addend) {
+            (true, true) => 24,
+            (true, false) => 16,
+            (false, true) => 12,
+            (false, false) => 8,
+        };
+        for (index, entry) in relocs.chunks_exact(entry_size).enumerate() {
+            // Each entry is whole, so the reads can't come up short.
+            let (slot, sym) = if is_64 {
+                (util::read_u64_from_slice(entry, 0, endianness).unwrap_or(0), util::read_u64_from_slice(entry, 8, endianness).unwrap_or(0) >> 32)
+            } else {
+                (util::read_u32_from_slice(entry, 0, endianness).unwrap_or(0) as u64, util::read_u32_from_slice(entry, 4, endianness).unwrap_or(0) as u64 >> 8)
+            };
+            let sym_offset = sym as usize * sym_size;
+            if sym == 0 || sym_offset + sym_size > dynsym.len() {
+                continue;
+            }
+            // Only undefined symbols are imports.
+            let shndx = util::read_u16_from_slice(dynsym, sym_offset + if is_64 { 6 } else { 14 }, endianness);
+            if shndx != Some(0) {
+                continue;
+            }
+            let name_offset = util::read_u32_from_slice(dynsym, sym_offset, endianness).unwrap_or(0) as usize;
+            let stub = match (stubs.get(&slot), plt_layout(&program.machine_type), plt) {
+                (Some(stub), _, _) => Some(*stub),
+                (None, Some((header, entry)), Some(plt)) if name.ends_with(".plt") => Some(plt + header + index as u64 * entry),
+                _ => None,
+            };
+            imports.push(Import { library: String::new(), name: c_string(dynstr, name_offset), slot, stub });
+        }
+    }
+    imports
+}
+
+// Functions a shared object defines in its dynamic symbol table, by name and address.
+pub fn exports(program: &Program) -> Vec<(String, u64)> {
+    let (dynsym, dynstr) = match (program.section_table.get(".dynsym"), program.section_table.get(".dynstr")) {
+        (Some(dynsym), Some(dynstr)) => (&dynsym.bytes, &dynstr.bytes),
+        _ => return Vec::new(),
+    };
+    let endianness = if program.endianess == BIG_ENDIAN { BIG_ENDIAN } else { LITTLE_ENDIAN };
+    let is_64 = program.bits == 64;
+    let mut exports = Vec::<(String, u64)>::new();
+    for entry in dynsym.chunks_exact(if is_64 { 24 } else { 16 }) {
+        let (value, info, shndx) = if is_64 {
+            (util::read_u64_from_slice(entry, 8, endianness).unwrap_or(0), entry[4], util::read_u16_from_slice(entry, 6, endianness).unwrap_or(0))
+        } else {
+            (util::read_u32_from_slice(entry, 4, endianness).unwrap_or(0) as u64, entry[12], util::read_u16_from_slice(entry, 14, endianness).unwrap_or(0))
+        };
+        // Defined functions (STT_FUNC) only.
+        if info & 0xf != 2 || shndx == 0 || value == 0 {
+            continue;
+        }
+        let name = c_string(dynstr, util::read_u32_from_slice(entry, 0, endianness).unwrap_or(0) as usize);
+        exports.push((name, value));
+    }
+    exports
+}
+
+pub fn imports(program: &Program) -> Vec<Import> {
+    match program.format {
+        "elf" => elf_imports(program),
+        _ => program.import_table.clone(),
+    }
+}
+
+// Imported APIs grouped by what they let a program do, for a quick triage of
+// its behavior. Names are matched without their A/W character set suffix.
+const CAPABILITIES: &[(&str, &[&str])] = &[
+    ("filesystem", &[
+        "CreateFile", "ReadFile", "WriteFile", "DeleteFile", "CopyFile", "CopyFileEx", "MoveFile", "MoveFileEx",
+        "FindFirstFile", "FindFirstFileEx", "FindNextFile", "GetTempPath", "GetTempFileName", "CreateDirectory",
+        "RemoveDirectory", "SetFileAttributes", "GetFileAttributes", "SetFilePointer", "SetFilePointerEx",
+        "open", "openat", "creat", "unlink", "unlinkat", "rename", "fopen", "fwrite", "opendir", "readdir",
+    ]),
+    ("network", &[
+        "WSAStartup", "socket", "connect", "bind", "listen", "accept", "send", "recv", "sendto", "recvfrom",
+        "gethostbyname", "getaddrinfo", "GetAddrInfo", "inet_addr", "InternetOpen", "InternetOpenUrl",
+        "InternetConnect", "InternetReadFile", "HttpOpenRequest", "HttpSendRequest", "WinHttpOpen",
+        "WinHttpConnect", "WinHttpSendRequest", "URLDownloadToFile", "DnsQuery", "DnsQuery_",
+    ]),
+    ("registry", &[
+        "RegOpenKey", "RegOpenKeyEx", "RegCreateKey", "RegCreateKeyEx", "RegSetValue", "RegSetValueEx",
+        "RegQueryValue", "RegQueryValueEx", "RegDeleteKey", "RegDeleteKeyEx", "RegDeleteValue", "RegEnumKey",
+        "RegEnumKeyEx", "RegEnumValue", "RegGetValue",
+    ]),
+    ("crypto", &[
+        "CryptAcquireContext", "CryptCreateHash", "CryptHashData", "CryptDeriveKey", "CryptGenKey", "CryptEncrypt",
+        "CryptDecrypt", "CryptImportKey", "CryptExportKey", "CryptGenRandom", "CryptProtectData", "CryptUnprotectData",
+        "BCryptOpenAlgorithmProvider", "BCryptEncrypt", "BCryptDecrypt", "BCryptGenRandom", "BCryptGenerateSymmetricKey",
+        "EVP_EncryptInit_ex", "EVP_DecryptInit_ex", "EVP_CipherInit_ex", "AES_set_encrypt_key", "RAND_bytes",
+    ]),
+    ("process injection", &[
+        "OpenProcess", "VirtualAllocEx", "VirtualProtectEx", "WriteProcessMemory", "ReadProcessMemory",
+        "CreateRemoteThread", "CreateRemoteThreadEx", "NtCreateThreadEx", "RtlCreateUserThread", "QueueUserAPC",
+        "NtQueueApcThread", "SetThreadContext", "GetThreadContext", "ResumeThread", "SuspendThread",
+        "NtUnmapViewOfSection", "ZwUnmapViewOfSection", "NtMapViewOfSection", "SetWindowsHookEx", "ptrace",
+        "process_vm_writev",
+    ]),
+    ("process execution", &[
+        "CreateProcess", "CreateProcessAsUser", "WinExec", "ShellExecute", "ShellExecuteEx", "system", "popen",
+        "execl", "execlp", "execle", "execv", "execvp", "execvpe", "execve", "fork", "vfork", "posix_spawn",
+    ]),
+    ("anti-debugging", &[
+        "IsDebuggerPresent", "CheckRemoteDebuggerPresent", "NtQueryInformationProcess", "OutputDebugString",
+        "NtSetInformationThread",
+    ]),
+];
+
+fn capability_name(name: &str) -> &str {
+    let trimmed = name.strip_suffix('A').or(name.strip_suffix('W')).unwrap_or(name);
+    if CAPABILITIES.iter().any(|(_, names)| names.contains(&trimmed)) { trimmed } else { name }
+}
+
+// Categories with at least one imported API, with those APIs' names.
+pub fn capabilities(imports: &[Import]) -> Vec<(&'static str, Vec<String>)> {
+    let mut out = Vec::<(&'static str, Vec<String>)>::new();
+    for (category, names) in CAPABILITIES {
+        let mut found: Vec<String> = imports.iter()
+            .filter(|import| names.contains(&capability_name(&import.name)))
+            .map(|import| import.name.clone())
+            .collect();
+        found.sort();
+        found.dedup();
+        if !found.is_empty() {
+            out.push((*category, found));
+        }
+    }
+    out
+}
@@ -0,0 +1,249 @@
+// A small JSON reader and writer for annotation and project files. Numbers keep their source text so
+// 64-bit addresses survive without going through a float.
+
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    // Members in file order.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items.as_slice()),
+            _ => None,
+        }
+    }
+
+    // Integers, either as JSON numbers or as "0x..." or decimal strings.
+    pub fn as_u64(&self) -> Option<u64> {
+        let text = match self {
+            Value::Number(n) => n.as_str(),
+            Value::String(s) => s.as_str(),
+            _ => return None,
+        };
+        match text.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => text.parse::<u64>().ok(),
+        }
+    }
+
+    // Addresses are written as "0x..." strings, which read back through as_u64.
+    pub fn hex(n: u64) -> Value {
+        Value::String(format!("{:#x}", n))
+    }
+
+    pub fn write(&self, out: &mut String) {
+        match self {
+            Value::Null => *out += "null",
+            Value::Bool(b) => *out += if *b { "true" } else { "false" },
+            Value::Number(n) => *out += n,
+            Value::String(s) => write_string(out, s),
+            Value::Array(items) => {
+                *out += "[";
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        *out += ", ";
+                    }
+                    item.write(out);
+                }
+                *out += "]";
+            },
+            Value::Object(members) => {
+                *out += "{";
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        *out += ", ";
+                    }
+                    write_string(out, key);
+                    *out += ": ";
+                    value.write(out);
+                }
+                *out += "}";
+            },
+        }
+    }
+}
+
+// Serializers that lay out addresses and bytes the way the writer does, so
+// serde output has the same schema as the command line's JSON.
+#[cfg(feature = "serde")]
+pub fn serialize_hex<S: serde::Serializer>(n: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:#x}", n))
+}
+
+#[cfg(feature = "serde")]
+pub fn serialize_bytes<S: serde::Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+}
+
+pub fn write_string(out: &mut String, s: &str) {
+    *out += "\"";
+    for c in s.chars() {
+        match c {
+            '"' => *out += "\\\"",
+            '\\' => *out += "\\\\",
+            '\n' => *out += "\\n",
+            '\r' => *out += "\\r",
+            '\t' => *out += "\\t",
+            c if (c as u32) < 0x20 => *out += format!("\\u{:04x}", c as u32).as_str(),
+            c => out.push(c),
+        }
+    }
+    *out += "\"";
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            return true
+        }
+        false
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, usize> {
+        if self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            return Ok(value)
+        }
+        Err(self.pos)
+    }
+
+    fn string(&mut self) -> Result<String, usize> {
+        if !self.eat(b'"') {
+            return Err(self.pos)
+        }
+        let mut out = Vec::<u8>::new();
+        loop {
+            let c = *self.bytes.get(self.pos).ok_or(self.pos)?;
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escape = *self.bytes.get(self.pos).ok_or(self.pos)?;
+                    self.pos += 1;
+                    match escape {
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'b' => out.push(0x08),
+                        b'f' => out.push(0x0c),
+                        b'u' => {
+                            let hex = self.bytes.get(self.pos..self.pos + 4).ok_or(self.pos)?;
+                            let code = u32::from_str_radix(&String::from_utf8_lossy(hex), 16).map_err(|_| self.pos)?;
+                            self.pos += 4;
+                            let c = char::from_u32(code).unwrap_or('\u{fffd}');
+                            out.extend_from_slice(c.to_string().as_bytes());
+                        },
+                        c => out.push(c),
+                    }
+                },
+                c => out.push(c),
+            }
+        }
+        Ok(String::from_utf8_lossy(&out).to_string())
+    }
+
+    fn value(&mut self) -> Result<Value, usize> {
+        self.skip_whitespace();
+        match self.bytes.get(self.pos) {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut members = Vec::<(String, Value)>::new();
+                if self.eat(b'}') {
+                    return Ok(Value::Object(members))
+                }
+                loop {
+                    let key = self.string()?;
+                    if !self.eat(b':') {
+                        return Err(self.pos)
+                    }
+                    members.push((key, self.value()?));
+                    if self.eat(b'}') {
+                        return Ok(Value::Object(members))
+                    }
+                    if !self.eat(b',') {
+                        return Err(self.pos)
+                    }
+                }
+            },
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::<Value>::new();
+                if self.eat(b']') {
+                    return Ok(Value::Array(items))
+                }
+                loop {
+                    items.push(self.value()?);
+                    if self.eat(b']') {
+                        return Ok(Value::Array(items))
+                    }
+                    if !self.eat(b',') {
+                        return Err(self.pos)
+                    }
+                }
+            },
+            Some(b'"') => Ok(Value::String(self.string()?)),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(c) if *c == b'-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                while self.pos < self.bytes.len() && matches!(self.bytes[self.pos], b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') {
+                    self.pos += 1;
+                }
+                Ok(Value::Number(String::from_utf8_lossy(&self.bytes[start..self.pos]).to_string()))
+            },
+            _ => Err(self.pos),
+        }
+    }
+}
+
+// Parses a whole document, or gives the byte offset where it stops making sense.
+pub fn parse(text: &str) -> Result<Value, usize> {
+    let mut parser = Parser { bytes: text.as_bytes(), pos: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos != parser.bytes.len() {
+        return Err(parser.pos)
+    }
+    Ok(value)
+}
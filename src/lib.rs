@@ -1,179 +1,1610 @@
+// The C entry points that take pointers are unsafe; what the pointers must be
+// is set out once, at the top of include/baretk.h.
+#![allow(clippy::missing_safety_doc)]
+
 use core::slice;
-use std::{alloc::Layout, ffi::{c_int, CStr}};
+use std::{cell::{Cell, RefCell}, ffi::{c_char, c_int, c_void, CStr, CString}, fs, panic, sync::{Arc, Mutex, OnceLock}};
 
-use prog::Program;
 use util::LITTLE_ENDIAN;
 
 mod query;
 mod dis;
+mod reg;
+mod decomp;
+mod imports;
+mod json;
 mod prog;
+mod proto;
+mod syscall;
 mod util;
+mod xref;
+mod resolve;
+mod options;
+mod cancel;
+mod error;
+mod loader;
+mod validate;
 
 mod arm;
+mod aarch64;
 mod riscv;
 mod pe;
 mod elf;
 mod x86;
 
-fn cstr_to_string(s: *const i8) -> Option<String> {
+// The Rust API. The modules stay private; these are the types and entry points
+// for using baretk as a dependency, next to the C API below.
+pub use cancel::{CancelToken, Cancelled};
+pub use decomp::{Decomp, Language, StatementRecord};
+pub use dis::{BufferSpec, Disassembly, DisassemblySection, Function, InstructionRecord};
+pub use error::BaretkError;
+pub use loader::Loader;
+pub use options::{AnalysisOptions, ParseMode, Sweep, Syntax, ARCHITECTURES};
+pub use prog::{Diagnostics, Import, Program, Section, SectionBytes, Segment, Symbol};
+pub use query::{Encoding, FileInfo, FileType, FoundString};
+pub use validate::{Finding, ValidationReport};
+
+// Loads a file with the options' architecture and base address overrides.
+pub fn load(path: &str, options: &AnalysisOptions) -> Result<Program, BaretkError> {
+    let bytes = util::try_read_file_contents(path)?;
+    prog::load_program_with_options(&bytes, options)
+}
+
+// Copies the bytes once, into a buffer the sections share.
+pub fn load_bytes(bytes: &[u8], options: &AnalysisOptions) -> Result<Program, BaretkError> {
+    prog::load_program_with_options(bytes, options)
+}
+
+// Loads without copying; the sections keep the buffer alive.
+pub fn load_shared(bytes: Arc<[u8]>, options: &AnalysisOptions) -> Result<Program, BaretkError> {
+    let mut program = prog::load_program_from_shared(bytes, options.parse_mode)?;
+    prog::apply_options(&mut program, options);
+    Ok(program)
+}
+
+// Adds a loader, tried before the built-in ones and those registered earlier.
+pub fn register_loader(loader: Arc<dyn loader::Loader>) {
+    loader::loaders().write().unwrap_or_else(|err| err.into_inner()).insert(0, loader);
+}
+
+// Imported functions with their slots, and on ELF the PLT stubs that call them.
+pub fn imports(program: &Program) -> Vec<Import> {
+    imports::imports(program)
+}
+
+// Functions a shared object defines in its dynamic symbol table, by name and address.
+pub fn exports(program: &Program) -> Vec<(String, u64)> {
+    imports::exports(program)
+}
+
+// Categories of imported APIs, each with the imports that fall in it.
+pub fn capabilities(imports: &[Import]) -> Vec<(&'static str, Vec<String>)> {
+    imports::capabilities(imports)
+}
+
+// Structural checks of the headers in `bytes`, the file `program` was loaded from.
+pub fn validate(program: &Program, bytes: &[u8]) -> ValidationReport {
+    validate::validate(program, bytes)
+}
+
+pub fn file_info(bytes: &[u8]) -> FileInfo {
+    query::get_file_info(bytes)
+}
+
+pub fn strings(bytes: &[u8], options: &AnalysisOptions) -> Vec<FoundString> {
+    query::strings(bytes, options)
+}
+
+// Disassembles headerless code, such as shellcode, as the given architecture.
+pub fn disassemble_buffer(bytes: &[u8], spec: &BufferSpec) -> Result<Disassembly, BaretkError> {
+    dis::disassemble_buffer(bytes, spec)
+}
+
+// Decompiles a whole file with the built-in function prototypes.
+pub fn decompile_bytes(bytes: &[u8], options: &AnalysisOptions, lang: Language) -> Result<Decomp, BaretkError> {
+    decomp::decomp_program_from_bytes(bytes, options, lang, &proto::PrototypeDb::new())
+}
+
+// Why the last failing call on this thread failed. Functions keep returning
+// 0 or NULL on failure; hosts call baretk_last_error for the reason.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub enum ErrorCode {
+    Ok = 0,
+    NullArgument = 1,
+    // A string argument isn't valid UTF-8.
+    InvalidString = 2,
+    Io = 3,
+    // The file couldn't be parsed.
+    Malformed = 4,
+    // An index past the end of a list.
+    OutOfRange = 5,
+    // A lookup by name or address found nothing.
+    NotFound = 6,
+    // An option has a value outside the ones defined.
+    InvalidArgument = 7,
+    // The analysis was stopped by baretk_cancel.
+    Cancelled = 8,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<(ErrorCode, CString)> = RefCell::new((ErrorCode::Ok, CString::default()));
+}
+
+fn set_error(error: ErrorCode, message: String) {
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = (error, message));
+}
+
+// Sets the last error from one returned by the library.
+fn report(error: BaretkError) {
+    let code = match error {
+        BaretkError::Io { .. } => ErrorCode::Io,
+        BaretkError::Malformed { .. } => ErrorCode::Malformed,
+        BaretkError::NotFound(_) => ErrorCode::NotFound,
+        BaretkError::InvalidArgument(_) | BaretkError::UnsupportedArch(_) => ErrorCode::InvalidArgument,
+        BaretkError::Cancelled => ErrorCode::Cancelled,
+    };
+    set_error(code, error.to_string());
+}
+
+#[no_mangle]
+pub extern "C" fn baretk_last_error() -> ErrorCode {
+    LAST_ERROR.with(|last| last.borrow().0)
+}
+
+// Valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn baretk_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().1.as_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn baretk_clear_error() {
+    set_error(ErrorCode::Ok, String::new());
+}
+
+// File formats that can be loaded, as "format:name". Architectures that can
+// be disassembled are "arch:name" and decompiler languages "decomp:name".
+const FORMATS: &[&str] = &["format:elf", "format:pe", "format:raw"];
+
+#[no_mangle]
+pub extern "C" fn baretk_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_has_feature(name: *const i8) -> c_int {
+    match cstr_to_string(name, "name") {
+        Ok(name) => match name.split_once(':') {
+            Some(("arch", arch)) => options::ARCHITECTURES.contains(&arch) as c_int,
+            Some(("decomp", lang)) => decomp::Language::from_name(lang).is_some() as c_int,
+            _ => FORMATS.contains(&name.as_str()) as c_int,
+        },
+        Err(()) => 0,
+    }
+}
+
+fn cstr_to_string(s: *const i8, what: &str) -> Result<String, ()> {
     if s.is_null() {
-        None
-    }
-    else {
-        unsafe {
-            match CStr::from_ptr(s).to_str() {
-                Ok(s) => Some(String::from(s)),
-                Err(error) => {
-                    eprintln!("Error parsing string: {}", error);
-                    None
-                }
+        set_error(ErrorCode::NullArgument, format!("{} is NULL", what));
+        return Err(())
+    }
+    unsafe {
+        match CStr::from_ptr(s).to_str() {
+            Ok(s) => Ok(String::from(s)),
+            Err(error) => {
+                set_error(ErrorCode::InvalidString, format!("{} isn't valid UTF-8: {}", what, error));
+                Err(())
             }
         }
     }
 }
 
+fn read_file(path: &str) -> Result<Vec<u8>, ()> {
+    util::try_read_file_contents(path).map_err(report)
+}
+
+fn write_file(path: &str, bytes: &[u8]) -> Result<(), ()> {
+    fs::write(path, bytes).map_err(|error| report(BaretkError::io(path, "writing", error)))
+}
+
+// Runs a parser that may panic on malformed input, which mustn't unwind into C.
+fn catch_malformed<T>(what: &str, f: impl FnOnce() -> T + panic::UnwindSafe) -> Result<T, ()> {
+    panic::catch_unwind(f).map_err(|payload| {
+        let reason = payload.downcast_ref::<String>().cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_default();
+        report(BaretkError::Malformed { what: what.to_string(), offset: None, section: None, reason });
+    })
+}
+
+// For parsers that return their errors; a panic is still caught and reported.
+fn try_parse<T>(what: &str, f: impl FnOnce() -> Result<T, BaretkError> + panic::UnwindSafe) -> Result<T, ()> {
+    catch_malformed(what, f)?.map_err(report)
+}
+
+#[repr(C)]
+pub struct AnalysisOptionsC {
+    // Shortest run of characters reported as a string.
+    min_string_length: usize,
+    // Only 0, the architecture's usual syntax (Intel for x86), is defined.
+    syntax: c_int,
+    // Show each instruction's bytes in listings.
+    show_bytes: c_int,
+    // 0 decodes the whole code section, 1 only what's reachable from the
+    // entry point and function symbols.
+    sweep: c_int,
+    // Machine type to decode as, or NULL for the one the headers give.
+    arch: *const c_char,
+    // Load address of raw binaries; 0 keeps the default of 0.
+    base_addr: u64,
+    // Nonzero rejects malformed files instead of loading what can be read.
+    strict: c_int,
+}
+
+// Fills in the defaults, which NULL options also stand for.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_analysis_options_init(options: *mut AnalysisOptionsC) {
+    if options.is_null() {
+        set_error(ErrorCode::NullArgument, "options is NULL".to_string());
+        return;
+    }
+    let defaults = AnalysisOptions::default();
+    unsafe {
+        *options = AnalysisOptionsC {
+            min_string_length: defaults.min_string_len,
+            syntax: 0,
+            show_bytes: defaults.show_bytes as c_int,
+            sweep: 0,
+            arch: std::ptr::null(),
+            base_addr: 0,
+            strict: 0,
+        };
+    }
+}
+
+fn analysis_options(options: *const AnalysisOptionsC) -> Result<AnalysisOptions, ()> {
+    let mut settings = AnalysisOptions::default();
+    if let Some(cancel) = CURRENT_CANCEL.with(|current| current.borrow().clone()) {
+        settings.cancel = cancel;
+    }
+    if options.is_null() {
+        return Ok(settings)
+    }
+    let options = unsafe { &*options };
+    let invalid = |what: String| {
+        set_error(ErrorCode::InvalidArgument, what);
+        Err(())
+    };
+    if options.syntax != 0 {
+        return invalid(format!("unknown syntax {}", options.syntax))
+    }
+    settings.sweep = match options.sweep {
+        0 => Sweep::Linear,
+        1 => Sweep::Recursive,
+        sweep => return invalid(format!("unknown sweep {}", sweep)),
+    };
+    if !options.arch.is_null() {
+        let arch = cstr_to_string(options.arch, "arch")?;
+        if !options::ARCHITECTURES.contains(&arch.as_str()) {
+            return invalid(format!("unknown architecture {}", arch))
+        }
+        settings.arch = Some(arch);
+    }
+    if options.base_addr != 0 {
+        settings.base_addr = Some(options.base_addr);
+    }
+    settings.min_string_len = options.min_string_length;
+    settings.show_bytes = options.show_bytes != 0;
+    settings.parse_mode = if options.strict != 0 { ParseMode::Strict } else { ParseMode::Permissive };
+    Ok(settings)
+}
+
+// The strings are written to out_path, one per line.
 #[no_mangle]
-pub extern "C" fn baretk_print_strings(path: *const i8, min_len: i32, out_path: *const i8) -> i32 {
-    let in_file = match cstr_to_string(path) {
-        Some(s) => s,
-        None => { return 0; }
+pub unsafe extern "C" fn baretk_print_strings(path: *const i8, min_len: i32, out_path: *const i8) -> i32 {
+    let (in_file, out) = match (cstr_to_string(path, "path"), cstr_to_string(out_path, "out_path")) {
+        (Ok(in_file), Ok(out)) => (in_file, out),
+        _ => return 0,
     };
 
-    let contents = match util::try_read_file_contents(in_file.as_str()) {
+    let contents = match read_file(in_file.as_str()) {
         Err(()) => return 0,
         Ok(vec) => vec,
     };
 
     let printable = false;
 
-    let strings = query::get_strings(contents.as_slice(), min_len as usize, printable);
-    if let Some(out) = cstr_to_string(out_path) {
-        if !util::try_write_file_lines(out.as_str(), strings) {
-            return 0;
-        }
-        return 1;
-    }
-    else {
-        println!("ASCII strings found in {}:", in_file);
-        for str in strings {
-            println!(" {}", str);
-        }
-        return 1;
-    }
+    let strings = query::get_strings(contents.as_slice(), min_len as usize, printable, None);
+    let lines: String = strings.into_iter().map(|s| s + "\n").collect();
+    write_file(out.as_str(), lines.as_bytes()).is_ok() as i32
 }
 
 #[no_mangle]
-pub extern "C" fn baretk_print_strings_from_bytes(bytes: *const u8, size: usize, min_len: i32, out_path: *const i8) -> i32 {
+pub unsafe extern "C" fn baretk_print_strings_from_bytes(bytes: *const u8, size: usize, min_len: i32, out_path: *const i8) -> i32 {
     if bytes.is_null() {
+        set_error(ErrorCode::NullArgument, "bytes is NULL".to_string());
         return 0
     }
+    let out = match cstr_to_string(out_path, "out_path") {
+        Ok(out) => out,
+        Err(()) => return 0,
+    };
     let slice = unsafe {
         slice::from_raw_parts(bytes, size)
     };
-    let strings = query::get_strings(slice, min_len as usize, true);
-    let out_file = unsafe { 
-        if out_path.is_null() {
-            None
-        } else {
-            match CStr::from_ptr(out_path).to_str() {
-                Ok(s) => Some(s),
-                Err(_error) => None,
-            }
-        }
+    let strings = query::get_strings(slice, min_len as usize, true, None);
+    let lines: String = strings.into_iter().map(|s| s + "\n").collect();
+    write_file(out.as_str(), lines.as_bytes()).is_ok() as i32
+}
+
+// A string found in the input and the file offset of its first byte.
+#[repr(C)]
+pub struct StringC {
+    text: *mut c_char,
+    offset: usize,
+}
+
+// Strings as an array the caller frees with baretk_free_strings; empty
+// results are a valid pointer with a count of 0.
+fn strings_to_c(bytes: &[u8], min_len: c_int, printable: bool, count: *mut usize) -> *mut StringC {
+    found_strings_to_c(query::find_strings(bytes, min_len.max(1) as usize, printable, &[query::Encoding::Ascii], None), count)
+}
+
+fn found_strings_to_c(strings: Vec<query::FoundString>, count: *mut usize) -> *mut StringC {
+    let strings: Box<[StringC]> = strings
+        .into_iter()
+        .map(|found| StringC { text: CString::new(found.text).unwrap_or_default().into_raw(), offset: found.offset })
+        .collect();
+    if !count.is_null() {
+        unsafe { *count = strings.len() };
+    }
+    Box::into_raw(strings).cast()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_get_strings(path: *const i8, min_len: c_int, count: *mut usize) -> *mut StringC {
+    if count.is_null() {
+        set_error(ErrorCode::NullArgument, "count is NULL".to_string());
+        return std::ptr::null_mut()
+    }
+    let contents = match cstr_to_string(path, "path").and_then(|path| read_file(&path)) {
+        Ok(contents) => contents,
+        Err(()) => return std::ptr::null_mut(),
     };
+    strings_to_c(&contents, min_len, false, count)
+}
 
-    if let Some(out) = out_file {
-        if !util::try_write_file_lines(out, strings) {
-            return 0;
-        }
-        return 1;
+#[no_mangle]
+pub unsafe extern "C" fn baretk_get_strings_from_bytes(bytes: *const u8, size: usize, min_len: c_int, count: *mut usize) -> *mut StringC {
+    if bytes.is_null() || count.is_null() {
+        set_error(ErrorCode::NullArgument, format!("{} is NULL", if bytes.is_null() { "bytes" } else { "count" }));
+        return std::ptr::null_mut()
+    }
+    let slice = unsafe {
+        slice::from_raw_parts(bytes, size)
+    };
+    strings_to_c(slice, min_len, true, count)
+}
+
+// Like baretk_get_strings, with the minimum length from the options.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_get_strings_with_options(path: *const i8, options: *const AnalysisOptionsC, count: *mut usize) -> *mut StringC {
+    if count.is_null() {
+        set_error(ErrorCode::NullArgument, "count is NULL".to_string());
+        return std::ptr::null_mut()
+    }
+    let (in_file, options) = match (cstr_to_string(path, "path"), analysis_options(options)) {
+        (Ok(in_file), Ok(options)) => (in_file, options),
+        _ => return std::ptr::null_mut(),
+    };
+    match read_file(&in_file) {
+        Ok(contents) => found_strings_to_c(query::strings(&contents, &options), count),
+        Err(()) => std::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_free_strings(strings: *mut StringC, count: usize) {
+    if strings.is_null() {
+        return;
     }
-    else {
-        println!("ASCII strings found:");
-        for str in strings {
-            println!(" {}", str);
+    unsafe {
+        let strings = Box::from_raw(std::ptr::slice_from_raw_parts_mut(strings, count));
+        for string in strings.iter() {
+            drop(CString::from_raw(string.text));
         }
-        return 1;
     }
 }
 
+// The listing baretk dis prints for a file.
+fn disassembly_text(path: *const i8) -> Result<String, ()> {
+    let in_file = cstr_to_string(path, "path")?;
+    let contents = read_file(in_file.as_str())?;
+    try_parse(&in_file, || dis::disassemble(&contents).map(|dis| dis.print(true)))
+}
+
 #[no_mangle]
-pub extern "C" fn baretk_disassemble_from_file(path: *const i8, out_path: *const i8) -> i32 {
-    let in_file = match cstr_to_string(path) {
-        Some(s) => s,
-        None => { return 0; }
+pub unsafe extern "C" fn baretk_disassemble_from_file(path: *const i8, out_path: *const i8) -> i32 {
+    let out = match cstr_to_string(out_path, "out_path") {
+        Ok(out) => out,
+        Err(()) => return 0,
     };
-
-    let contents = match util::try_read_file_contents(in_file.as_str()) {
+    let output = match disassembly_text(path) {
+        Ok(output) => output,
         Err(()) => return 0,
-        Ok(vec) => vec,
     };
+    write_file(out.as_str(), output.as_bytes()).is_ok() as i32
+}
+
+// A copy of the string the caller frees with baretk_free_string.
+fn owned_string(s: &str) -> *mut c_char {
+    c_string(s).into_raw()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        unsafe { drop(CString::from_raw(s)) };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_disassemble_to_string(path: *const i8) -> *mut c_char {
+    match disassembly_text(path) {
+        Ok(output) => owned_string(&output),
+        Err(()) => std::ptr::null_mut(),
+    }
+}
+
+// A loaded program, with NUL-terminated copies of its names made on first use.
+pub struct ProgramC {
+    program: Program,
+    symbol_names: OnceLock<Vec<CString>>,
+    // Section table keys ordered by address, then name.
+    section_keys: Vec<String>,
+    section_names: Vec<CString>,
+    machine_type: CString,
+}
+
+impl ProgramC {
+    fn new(program: Program) -> ProgramC {
+        let mut section_keys: Vec<String> = program.section_table.keys().cloned().collect();
+        section_keys.sort_by(|a, b| (program.section_table[a].addr, a).cmp(&(program.section_table[b].addr, b)));
+        let section_names = section_keys.iter().map(|key| c_string(key)).collect();
+        let machine_type = c_string(&program.machine_type);
+        ProgramC { program, symbol_names: OnceLock::new(), section_keys, section_names, machine_type }
+    }
+
+    fn symbol_names(&self) -> &[CString] {
+        self.symbol_names.get_or_init(|| self.program.symbol_table.iter().map(|sym| c_string(&sym.name)).collect())
+    }
+}
+
+fn program_ref<'a>(program: *const ProgramC) -> Option<&'a ProgramC> {
+    if program.is_null() {
+        set_error(ErrorCode::NullArgument, "program is NULL".to_string());
+        return None
+    }
+    Some(unsafe { &*program })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_load_program(path: *const i8) -> *mut ProgramC {
+    let in_file = match cstr_to_string(path, "path") {
+        Ok(s) => s,
+        Err(()) => return std::ptr::null_mut(),
+    };
+    let contents = match read_file(&in_file) {
+        Ok(contents) => contents,
+        Err(()) => return std::ptr::null_mut(),
+    };
+    match try_parse(&in_file, || prog::load_program_from_bytes(&contents)) {
+        Ok(prog) => Box::into_raw(Box::new(ProgramC::new(prog))),
+        Err(()) => std::ptr::null_mut(),
+    }
+}
+
+// Loads a file with the architecture and base address of the options, which
+// mostly matter for raw binaries.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_load_program_with_options(path: *const i8, options: *const AnalysisOptionsC) -> *mut ProgramC {
+    let (in_file, options) = match (cstr_to_string(path, "path"), analysis_options(options)) {
+        (Ok(in_file), Ok(options)) => (in_file, options),
+        _ => return std::ptr::null_mut(),
+    };
+    let contents = match read_file(&in_file) {
+        Ok(contents) => contents,
+        Err(()) => return std::ptr::null_mut(),
+    };
+    match try_parse(&in_file, || prog::load_program_with_options(&contents, &options)) {
+        Ok(prog) => Box::into_raw(Box::new(ProgramC::new(prog))),
+        Err(()) => std::ptr::null_mut(),
+    }
+}
+
+// The bytes are copied, so the buffer can be freed once this returns.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_load_program_from_bytes(bytes: *const u8, size: usize) -> *mut ProgramC {
+    if bytes.is_null() {
+        set_error(ErrorCode::NullArgument, "bytes is NULL".to_string());
+        return std::ptr::null_mut()
+    }
+    let slice = unsafe {
+        slice::from_raw_parts(bytes, size)
+    };
+    match try_parse("buffer", || prog::load_program_from_bytes(slice)) {
+        Ok(prog) => Box::into_raw(Box::new(ProgramC::new(prog))),
+        Err(()) => std::ptr::null_mut(),
+    }
+}
 
-    let dis = dis::disassemble(&contents);
+// An independent copy of the program, freed on its own.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_clone_program(program: *const ProgramC) -> *mut ProgramC {
+    match program_ref(program) {
+        Some(program) => Box::into_raw(Box::new(ProgramC::new(program.program.clone()))),
+        None => std::ptr::null_mut(),
+    }
+}
 
-    let output = dis.print(true);
+#[no_mangle]
+pub unsafe extern "C" fn baretk_free_program(program: *mut ProgramC) {
+    if !program.is_null() {
+        unsafe { drop(Box::from_raw(program)) };
+    }
+}
 
-    if let Some(out) = cstr_to_string(out_path) {
-        if !util::try_write_file(out.as_str(), output.as_bytes()) {
-            return 0;
-        }
-        return 1;
+#[no_mangle]
+pub unsafe extern "C" fn baretk_get_endianess(program: *const ProgramC) -> c_int {
+    match program_ref(program) {
+        Some(program) => program.program.endianess as c_int,
+        None => LITTLE_ENDIAN as c_int,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_get_machine_type(program: *const ProgramC) -> *const i8 {
+    match program_ref(program) {
+        Some(program) => program.machine_type.as_ptr(),
+        None => c"???".as_ptr(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_copy_machine_type(program: *const ProgramC) -> *mut c_char {
+    match program_ref(program) {
+        Some(program) => owned_string(&program.program.machine_type),
+        None => std::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_get_entry_point(program: *const ProgramC) -> u64 {
+    program_ref(program).map_or(0, |program| program.program.entry_point)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_get_bits(program: *const ProgramC) -> c_int {
+    program_ref(program).map_or(0, |program| program.program.bits as c_int)
+}
+
+#[repr(C)]
+pub enum FileTypeC {
+    Raw = 0,
+    Elf = 1,
+    PE = 2,
+    MachO = 3,
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_get_file_type(program: *const ProgramC) -> FileTypeC {
+    match program_ref(program).map(|program| program.program.format) {
+        Some("elf") => FileTypeC::Elf,
+        Some("pe") => FileTypeC::PE,
+        _ => FileTypeC::Raw,
     }
+}
 
-    return 1;
+#[no_mangle]
+pub unsafe extern "C" fn baretk_get_warning_count(program: *const ProgramC) -> usize {
+    program_ref(program).map_or(0, |program| program.program.warnings.len())
 }
 
 #[no_mangle]
-pub extern "C" fn baretk_load_program(path: *const i8) -> *mut prog::Program {
-    let in_file = match cstr_to_string(path) {
-        Some(s) => s,
+pub unsafe extern "C" fn baretk_copy_warning(program: *const ProgramC, index: usize) -> *mut c_char {
+    let program = match program_ref(program) {
+        Some(program) => program,
         None => return std::ptr::null_mut(),
     };
+    match program.program.warnings.get(index) {
+        Some(warning) => owned_string(warning),
+        None => {
+            set_error(ErrorCode::OutOfRange, format!("warning {} of {}", index, program.program.warnings.len()));
+            std::ptr::null_mut()
+        }
+    }
+}
 
-    let layout = Layout::new::<Program>();
+#[repr(C)]
+pub struct FileInfoC {
+    file_type: FileTypeC,
+    // NUL-terminated, so the struct owns nothing.
+    machine_type: [c_char; 16],
+    bits: c_int,
+    endianess: c_int,
+}
 
+fn file_info_to_c(bytes: &[u8], info: *mut FileInfoC) -> c_int {
+    let found = query::get_file_info(bytes);
+    let mut machine_type = [0 as c_char; 16];
+    for (dest, b) in machine_type.iter_mut().zip(found.machine_type.bytes().take(15)) {
+        *dest = b as c_char;
+    }
+    let file_type = match found.file_type {
+        query::FileType::RawBinary => FileTypeC::Raw,
+        query::FileType::Elf => FileTypeC::Elf,
+        query::FileType::PE => FileTypeC::PE,
+        query::FileType::MachO => FileTypeC::MachO,
+    };
     unsafe {
-        let prog = match prog::load_program_from_file(&in_file) {
-            Ok(prog) => prog,
-            Err(()) => {
-                return std::ptr::null_mut()
-            },
-        };
+        *info = FileInfoC { file_type, machine_type, bits: found.bits as c_int, endianess: found.endianess as c_int };
+    }
+    1
+}
 
-        let dst: *mut Program = std::alloc::alloc(layout).cast();
-        dst.copy_from(&prog, 1);
+// Identifies a file from its headers without loading the program.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_get_file_info(path: *const i8, info: *mut FileInfoC) -> c_int {
+    if info.is_null() {
+        set_error(ErrorCode::NullArgument, "info is NULL".to_string());
+        return 0
+    }
+    let contents = match cstr_to_string(path, "path").and_then(|path| read_file(&path)) {
+        Ok(contents) => contents,
+        Err(()) => return 0,
+    };
+    catch_malformed("file", || file_info_to_c(&contents, info)).unwrap_or(0)
+}
 
-        dst
+#[no_mangle]
+pub unsafe extern "C" fn baretk_get_file_info_from_bytes(bytes: *const u8, size: usize, info: *mut FileInfoC) -> c_int {
+    if bytes.is_null() || info.is_null() {
+        set_error(ErrorCode::NullArgument, format!("{} is NULL", if bytes.is_null() { "bytes" } else { "info" }));
+        return 0
     }
+    let slice = unsafe {
+        slice::from_raw_parts(bytes, size)
+    };
+    catch_malformed("buffer", || file_info_to_c(slice, info)).unwrap_or(0)
+}
+
+#[repr(C)]
+pub struct SegmentC {
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    size: u64,
+    // RWX_* permissions the segment is mapped with.
+    perm: u8,
 }
 
 #[no_mangle]
-pub extern "C" fn baretk_free_program(program: *mut Program) {
-    let layout = Layout::new::<Program>();
+pub unsafe extern "C" fn baretk_get_segment_count(program: *const ProgramC) -> usize {
+    program_ref(program).map_or(0, |program| program.program.program_table.len())
+}
 
-    if program.is_null() {
-        return;
+#[no_mangle]
+pub unsafe extern "C" fn baretk_get_segment(program: *const ProgramC, index: usize, out: *mut SegmentC) -> c_int {
+    let program = match program_ref(program) {
+        Some(program) => program,
+        None => return 0,
+    };
+    let segments = &program.program.program_table;
+    let segment = match segments.get(index) {
+        Some(segment) => segment,
+        None => {
+            set_error(ErrorCode::OutOfRange, format!("segment {} of {}", index, segments.len()));
+            return 0
+        }
+    };
+    if out.is_null() {
+        set_error(ErrorCode::NullArgument, "out is NULL".to_string());
+        return 0
+    }
+    unsafe {
+        *out = SegmentC {
+            offset: segment.offset,
+            vaddr: segment.vaddr,
+            paddr: segment.paddr,
+            size: segment.size as u64,
+            perm: segment.perm,
+        };
+    }
+    1
+}
+
+#[repr(C)]
+pub struct SymbolC {
+    // Owned by the program and valid until it's freed.
+    name: *const c_char,
+    addr: u64,
+    size: u64,
+    is_function: c_int,
+}
+
+fn symbol_to_c(program: &ProgramC, index: usize, out: *mut SymbolC) -> c_int {
+    if out.is_null() {
+        set_error(ErrorCode::NullArgument, "out is NULL".to_string());
+        return 0
     }
-    
+    let sym = &program.program.symbol_table[index];
     unsafe {
-        std::alloc::dealloc(program.cast(), layout);
+        *out = SymbolC {
+            name: program.symbol_names()[index].as_ptr(),
+            addr: sym.addr,
+            size: sym.size,
+            is_function: sym.is_func as c_int,
+        };
     }
+    1
 }
 
 #[no_mangle]
-pub extern "C" fn baretk_get_endianess(program: *const Program) -> c_int {
-    if program.is_null() {
-        return LITTLE_ENDIAN as c_int;
+pub unsafe extern "C" fn baretk_get_symbol_count(program: *const ProgramC) -> usize {
+    program_ref(program).map_or(0, |program| program.program.symbol_table.len())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_get_symbol(program: *const ProgramC, index: usize, out: *mut SymbolC) -> c_int {
+    let program = match program_ref(program) {
+        Some(program) => program,
+        None => return 0,
+    };
+    let count = program.program.symbol_table.len();
+    if index >= count {
+        set_error(ErrorCode::OutOfRange, format!("symbol {} of {}", index, count));
+        return 0
     }
+    symbol_to_c(program, index, out)
+}
 
-    unsafe { (*program).endianess as c_int }
+// Index in the symbol table of a symbol found by a Program lookup.
+fn symbol_index(program: &Program, sym: &prog::Symbol) -> usize {
+    program.symbol_table.iter().position(|s| std::ptr::eq(s, sym)).unwrap_or(0)
 }
 
 #[no_mangle]
-pub extern "C" fn baretk_get_machine_type(program: *const Program) -> *const i8 {
-    if program.is_null() {
-        return "???".as_ptr().cast();
+pub unsafe extern "C" fn baretk_find_symbol(program: *const ProgramC, name: *const i8, out: *mut SymbolC) -> c_int {
+    let (program, name) = match (program_ref(program), cstr_to_string(name, "name")) {
+        (Some(program), Ok(name)) => (program, name),
+        _ => return 0,
+    };
+    match program.program.symbol_table.iter().position(|sym| sym.name == name) {
+        Some(index) => symbol_to_c(program, index, out),
+        None => {
+            set_error(ErrorCode::NotFound, format!("no symbol named {}", name));
+            0
+        }
+    }
+}
+
+// The symbol whose range contains the address, as Program::symbol_at finds it.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_symbol_at(program: *const ProgramC, addr: u64, out: *mut SymbolC) -> c_int {
+    let program = match program_ref(program) {
+        Some(program) => program,
+        None => return 0,
+    };
+    match program.program.symbol_at(addr) {
+        Some(sym) => symbol_to_c(program, symbol_index(&program.program, sym), out),
+        None => {
+            set_error(ErrorCode::NotFound, format!("no symbol at {:#x}", addr));
+            0
+        }
     }
+}
+
+#[repr(C)]
+pub struct SectionC {
+    // Owned by the program and valid until it's freed.
+    name: *const c_char,
+    addr: u64,
+    size: u64,
+    // File offset of the section's bytes.
+    offset: u64,
+    // RWX_* permissions the section is mapped with.
+    perm: u8,
+    // The section's size bytes, owned by the program.
+    bytes: *const u8,
+}
 
-    unsafe { (*program).machine_type.as_str().as_ptr().cast() }
+fn section_to_c(program: &ProgramC, index: usize, out: *mut SectionC) -> c_int {
+    if out.is_null() {
+        set_error(ErrorCode::NullArgument, "out is NULL".to_string());
+        return 0
+    }
+    let section = &program.program.section_table[&program.section_keys[index]];
+    unsafe {
+        *out = SectionC {
+            name: program.section_names[index].as_ptr(),
+            addr: section.addr,
+            size: section.bytes.len() as u64,
+            offset: section.offset,
+            perm: section.perm,
+            bytes: section.bytes.as_ptr(),
+        };
+    }
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_get_section_count(program: *const ProgramC) -> usize {
+    program_ref(program).map_or(0, |program| program.section_keys.len())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_get_section(program: *const ProgramC, index: usize, out: *mut SectionC) -> c_int {
+    let program = match program_ref(program) {
+        Some(program) => program,
+        None => return 0,
+    };
+    let count = program.section_keys.len();
+    if index >= count {
+        set_error(ErrorCode::OutOfRange, format!("section {} of {}", index, count));
+        return 0
+    }
+    section_to_c(program, index, out)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_copy_section_name(program: *const ProgramC, index: usize) -> *mut c_char {
+    let program = match program_ref(program) {
+        Some(program) => program,
+        None => return std::ptr::null_mut(),
+    };
+    match program.section_keys.get(index) {
+        Some(name) => owned_string(name),
+        None => {
+            set_error(ErrorCode::OutOfRange, format!("section {} of {}", index, program.section_keys.len()));
+            std::ptr::null_mut()
+        }
+    }
+}
+
+// A copy of a section's bytes the caller frees with baretk_free_bytes.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_copy_section_bytes(program: *const ProgramC, index: usize, size: *mut usize) -> *mut u8 {
+    let program = match program_ref(program) {
+        Some(program) => program,
+        None => return std::ptr::null_mut(),
+    };
+    if size.is_null() {
+        set_error(ErrorCode::NullArgument, "size is NULL".to_string());
+        return std::ptr::null_mut()
+    }
+    let key = match program.section_keys.get(index) {
+        Some(key) => key,
+        None => {
+            set_error(ErrorCode::OutOfRange, format!("section {} of {}", index, program.section_keys.len()));
+            return std::ptr::null_mut()
+        }
+    };
+    let bytes: Box<[u8]> = program.program.section_table[key].bytes.as_slice().into();
+    unsafe { *size = bytes.len() };
+    Box::into_raw(bytes).cast()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_free_bytes(bytes: *mut u8, size: usize) {
+    if !bytes.is_null() {
+        unsafe { drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(bytes, size))) };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_find_section(program: *const ProgramC, name: *const i8, out: *mut SectionC) -> c_int {
+    let (program, name) = match (program_ref(program), cstr_to_string(name, "name")) {
+        (Some(program), Ok(name)) => (program, name),
+        _ => return 0,
+    };
+    match program.section_keys.iter().position(|key| *key == name) {
+        Some(index) => section_to_c(program, index, out),
+        None => {
+            set_error(ErrorCode::NotFound, format!("no section named {}", name));
+            0
+        }
+    }
+}
+
+struct InstructionC {
+    addr: u64,
+    size: usize,
+    kind: u32,
+    mnemonic: CString,
+    // The operands as printed, and each one on its own.
+    operand_text: CString,
+    operands: Vec<CString>,
+}
+
+// A disassembly with every instruction's details ready for indexed access,
+// so bindings don't have to parse the printed listing.
+pub struct DisassemblyC {
+    dis: dis::Disassembly,
+    instructions: Vec<InstructionC>,
+    // Whether the text from NULL options shows instruction bytes.
+    show_bytes: bool,
+}
+
+fn c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).unwrap_or_default()
+}
+
+fn disassembly_to_c(dis: dis::Disassembly, show_bytes: bool) -> DisassemblyC {
+    let mut instructions = Vec::<InstructionC>::new();
+    for section in dis.sections() {
+        let listing = &section.instructions;
+        let instrs = listing.instruction_vec();
+        let offsets = listing.instruction_offset_vec_in(0..usize::MAX);
+        let sizes = listing.instruction_size_vec_in(0..usize::MAX);
+        let texts = listing.instruction_text_vec_in(0..usize::MAX);
+        instructions.extend(instrs.iter().zip(offsets).zip(sizes).zip(texts).map(|(((ins, offset), size), text)| {
+            let (mnemonic, operands) = dis::split_instruction_text(&text);
+            InstructionC {
+                addr: section.addr + offset as u64,
+                size,
+                kind: ins.kind(),
+                mnemonic: c_string(mnemonic),
+                operand_text: c_string(&operands.join(", ")),
+                operands: operands.iter().map(|op| c_string(op)).collect(),
+            }
+        }));
+    }
+    DisassemblyC { dis, instructions, show_bytes }
+}
+
+// Disassembles the executable sections of a file.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_disassemble(path: *const i8) -> *mut DisassemblyC {
+    let in_file = match cstr_to_string(path, "path") {
+        Ok(s) => s,
+        Err(()) => return std::ptr::null_mut(),
+    };
+    let contents = match read_file(&in_file) {
+        Ok(contents) => contents,
+        Err(()) => return std::ptr::null_mut(),
+    };
+    match try_parse(&in_file, || dis::disassemble(&contents).map(|dis| disassembly_to_c(dis, true))) {
+        Ok(dis) => Box::into_raw(Box::new(dis)),
+        Err(()) => std::ptr::null_mut(),
+    }
+}
+
+// Disassembles a copy of the program, so the handle stays valid and still
+// needs baretk_free_program.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_disassemble_program(program: *const ProgramC) -> *mut DisassemblyC {
+    let program = match program_ref(program) {
+        Some(program) => program,
+        None => return std::ptr::null_mut(),
+    };
+    match analysis_options(std::ptr::null()) {
+        Ok(options) => disassemble_to_c(program.program.clone(), &options),
+        Err(()) => std::ptr::null_mut(),
+    }
+}
+
+fn disassemble_to_c(program: Program, options: &AnalysisOptions) -> *mut DisassemblyC {
+    match catch_malformed("program", || dis::disassemble_with_options(program, options)) {
+        Ok(Ok(dis)) => Box::into_raw(Box::new(disassembly_to_c(dis, options.show_bytes))),
+        Ok(Err(error)) => {
+            report(error);
+            std::ptr::null_mut()
+        },
+        Err(()) => std::ptr::null_mut(),
+    }
+}
+
+// Like baretk_disassemble_program, with the sweep, architecture and base
+// address of the options. Their show_bytes is used for text from NULL options.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_disassemble_program_with_options(program: *const ProgramC, options: *const AnalysisOptionsC) -> *mut DisassemblyC {
+    let (program, options) = match (program_ref(program), analysis_options(options)) {
+        (Some(program), Ok(options)) => (program, options),
+        _ => return std::ptr::null_mut(),
+    };
+    disassemble_to_c(program.program.clone(), &options)
+}
+
+#[repr(C)]
+pub struct BufferSpecC {
+    // An "arch:" name from baretk_has_feature.
+    arch: *const c_char,
+    // 0 for the architecture's usual width, or 32 or 64.
+    bits: c_int,
+    // 0 for little endian, or LITTLE_ENDIAN or BIG_ENDIAN.
+    endianess: c_int,
+    base_addr: u64,
+}
+
+// Disassembles a buffer of code with no headers. The bytes are copied.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_disassemble_buffer(bytes: *const u8, size: usize, spec: *const BufferSpecC) -> *mut DisassemblyC {
+    if bytes.is_null() || spec.is_null() {
+        set_error(ErrorCode::NullArgument, format!("{} is NULL", if bytes.is_null() { "bytes" } else { "spec" }));
+        return std::ptr::null_mut()
+    }
+    let spec = unsafe { &*spec };
+    let arch = match cstr_to_string(spec.arch, "arch") {
+        Ok(arch) => arch,
+        Err(()) => return std::ptr::null_mut(),
+    };
+    let invalid = match (spec.bits, spec.endianess) {
+        _ if !options::ARCHITECTURES.contains(&arch.as_str()) => Some(format!("unknown architecture {}", arch)),
+        (0 | 32 | 64, 0 | 1 | 2) => None,
+        (0 | 32 | 64, endianess) => Some(format!("unknown endianess {}", endianess)),
+        (bits, _) => Some(format!("unsupported bits {}", bits)),
+    };
+    if let Some(what) = invalid {
+        set_error(ErrorCode::InvalidArgument, what);
+        return std::ptr::null_mut()
+    }
+    let slice = unsafe {
+        slice::from_raw_parts(bytes, size)
+    };
+    let spec = dis::BufferSpec { arch, bits: spec.bits as u8, endianess: spec.endianess as u8, base_addr: spec.base_addr };
+    match catch_malformed("buffer", || dis::disassemble_buffer(slice, &spec)) {
+        Ok(Ok(dis)) => Box::into_raw(Box::new(disassembly_to_c(dis, true))),
+        Ok(Err(error)) => {
+            report(error);
+            std::ptr::null_mut()
+        },
+        Err(()) => std::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_free_disassembly(dis: *mut DisassemblyC) {
+    if !dis.is_null() {
+        unsafe { drop(Box::from_raw(dis)) };
+    }
+}
+
+// An instruction passed to a disassembly callback. The strings are only
+// valid until the callback returns.
+#[repr(C)]
+pub struct InstructionInfoC {
+    addr: u64,
+    size: usize,
+    kind: u32,
+    mnemonic: *const c_char,
+    operands: *const c_char,
+    // Offset of the instruction in its section and the section's size, for
+    // showing progress.
+    offset: usize,
+    section_size: usize,
+}
+
+// Returns nonzero to go on to the next instruction, or 0 to stop.
+pub type InstructionCallback = extern "C" fn(user_data: *mut c_void, ins: *const InstructionInfoC) -> c_int;
+
+// Decodes every executable section one instruction at a time, in the order
+// of the sections of a disassembly, so nothing is kept. Returns 1 once the last
+// section ends or the callback stops it, and fails only if there's no
+// executable section at all.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_disassemble_with_callback(program: *const ProgramC, callback: Option<InstructionCallback>, user_data: *mut c_void) -> c_int {
+    let (program, callback) = match (program_ref(program), callback) {
+        (Some(program), Some(callback)) => (&program.program, callback),
+        (Some(_), None) => {
+            set_error(ErrorCode::NullArgument, "callback is NULL".to_string());
+            return 0
+        },
+        _ => return 0,
+    };
+    let sections = dis::executable_sections(program);
+    if sections.is_empty() {
+        report(BaretkError::NotFound("Executable section".to_string()));
+        return 0
+    }
+    // The callback is the host's and the program is only read, so they're fine after a panic.
+    let decoded = catch_malformed("program", panic::AssertUnwindSafe(|| {
+        let mut stopped = false;
+        for name in sections {
+            let section = &program.section_table[name];
+            let (base, section_size) = (section.addr, section.bytes.len());
+            let supported = dis::for_each_instruction(program, name, |ins, offset, size, text| {
+                let (mnemonic, operands) = dis::split_instruction_text(&text);
+                let mnemonic = c_string(mnemonic);
+                let operands = c_string(&operands.join(", "));
+                let info = InstructionInfoC {
+                    addr: base + offset as u64,
+                    size,
+                    kind: ins.kind(),
+                    mnemonic: mnemonic.as_ptr(),
+                    operands: operands.as_ptr(),
+                    offset,
+                    section_size,
+                };
+                stopped = callback(user_data, &info) == 0;
+                !stopped
+            });
+            if !supported || stopped {
+                return supported
+            }
+        }
+        true
+    }));
+    match decoded {
+        Ok(true) => 1,
+        Ok(false) => {
+            set_error(ErrorCode::InvalidArgument, format!("can't disassemble {}", program.machine_type));
+            0
+        },
+        Err(()) => 0,
+    }
+}
+
+#[repr(C)]
+pub struct DisassemblyTextOptionsC {
+    // Show each instruction's bytes after its text.
+    show_bytes: c_int,
+    // Only 0, the architecture's usual syntax (Intel for x86), is defined.
+    syntax: c_int,
+    // Addresses of the instructions to print; an end of 0 prints to the end of the last section.
+    start: u64,
+    end: u64,
+}
+
+// NULL options give the whole listing, with bytes unless the disassembly's
+// analysis options left them out.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_disassembly_get_text(dis: *const DisassemblyC, options: *const DisassemblyTextOptionsC) -> *mut c_char {
+    if dis.is_null() {
+        set_error(ErrorCode::NullArgument, "disassembly is NULL".to_string());
+        return std::ptr::null_mut()
+    }
+    let DisassemblyC { dis, show_bytes, .. } = unsafe { &*dis };
+    if options.is_null() {
+        return owned_string(&dis.print(*show_bytes))
+    }
+    let options = unsafe { &*options };
+    if options.syntax != 0 {
+        set_error(ErrorCode::InvalidArgument, format!("unknown syntax {}", options.syntax));
+        return std::ptr::null_mut()
+    }
+    let end = if options.end == 0 { u64::MAX } else { options.end };
+    owned_string(&dis.print_range(options.show_bytes != 0, options.start..end))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_instruction_count(dis: *const DisassemblyC) -> usize {
+    if dis.is_null() {
+        set_error(ErrorCode::NullArgument, "disassembly is NULL".to_string());
+        return 0
+    }
+    unsafe { (*dis).instructions.len() }
+}
+
+// The sections decoded, in address order. Their instructions follow each
+// other in the same order.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_disassembly_section_count(dis: *const DisassemblyC) -> usize {
+    if dis.is_null() {
+        set_error(ErrorCode::NullArgument, "disassembly is NULL".to_string());
+        return 0
+    }
+    unsafe { (*dis).dis.sections().len() }
+}
+
+fn disassembly_section_at<'a>(dis: *const DisassemblyC, index: usize) -> Option<&'a dis::DisassemblySection> {
+    if dis.is_null() {
+        set_error(ErrorCode::NullArgument, "disassembly is NULL".to_string());
+        return None
+    }
+    let sections = unsafe { (*dis).dis.sections() };
+    let section = sections.get(index);
+    if section.is_none() {
+        set_error(ErrorCode::OutOfRange, format!("section {} of {}", index, sections.len()));
+    }
+    section
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_disassembly_copy_section_name(dis: *const DisassemblyC, index: usize) -> *mut c_char {
+    disassembly_section_at(dis, index).map_or(std::ptr::null_mut(), |section| owned_string(&section.section_name))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_disassembly_section_address(dis: *const DisassemblyC, index: usize) -> u64 {
+    disassembly_section_at(dis, index).map_or(0, |section| section.addr)
+}
+
+fn instruction_at<'a>(dis: *const DisassemblyC, index: usize) -> Option<&'a InstructionC> {
+    if dis.is_null() {
+        set_error(ErrorCode::NullArgument, "disassembly is NULL".to_string());
+        return None
+    }
+    let instructions = unsafe { &(*dis).instructions };
+    let ins = instructions.get(index);
+    if ins.is_none() {
+        set_error(ErrorCode::OutOfRange, format!("instruction {} of {}", index, instructions.len()));
+    }
+    ins
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_instruction_address(dis: *const DisassemblyC, index: usize) -> u64 {
+    instruction_at(dis, index).map_or(0, |ins| ins.addr)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_instruction_size(dis: *const DisassemblyC, index: usize) -> usize {
+    instruction_at(dis, index).map_or(0, |ins| ins.size)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_instruction_kind(dis: *const DisassemblyC, index: usize) -> u32 {
+    instruction_at(dis, index).map_or(0, |ins| ins.kind)
+}
+
+// Strings returned for instructions belong to the disassembly and stay valid until it's freed.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_instruction_mnemonic(dis: *const DisassemblyC, index: usize) -> *const c_char {
+    instruction_at(dis, index).map_or(std::ptr::null(), |ins| ins.mnemonic.as_ptr())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_instruction_operands(dis: *const DisassemblyC, index: usize) -> *const c_char {
+    instruction_at(dis, index).map_or(std::ptr::null(), |ins| ins.operand_text.as_ptr())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_instruction_operand_count(dis: *const DisassemblyC, index: usize) -> usize {
+    instruction_at(dis, index).map_or(0, |ins| ins.operands.len())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_instruction_operand(dis: *const DisassemblyC, index: usize, operand: usize) -> *const c_char {
+    let ins = match instruction_at(dis, index) {
+        Some(ins) => ins,
+        None => return std::ptr::null(),
+    };
+    match ins.operands.get(operand) {
+        Some(op) => op.as_ptr(),
+        None => {
+            set_error(ErrorCode::OutOfRange, format!("operand {} of {}", operand, ins.operands.len()));
+            std::ptr::null()
+        }
+    }
+}
+
+#[repr(C)]
+pub struct DecompOptionsC {
+    // A name from baretk_list_languages, or NULL for pseudocode.
+    language: *const c_char,
+    // Name or "0x" address of the one function to decompile, or NULL for the code section.
+    function: *const c_char,
+    // Show the instructions each statement came from.
+    interleave: c_int,
+    // Sweep, architecture and base address to disassemble with, or NULL for the defaults.
+    analysis: *const AnalysisOptionsC,
+}
+
+struct DecompSettings {
+    lang: decomp::Language,
+    function: Option<String>,
+    interleave: bool,
+    analysis: AnalysisOptions,
+}
+
+// NULL options decompile the code section to pseudocode.
+fn decomp_settings(options: *const DecompOptionsC) -> Result<DecompSettings, ()> {
+    let mut settings = DecompSettings {
+        lang: decomp::Language::Pseudocode,
+        function: None,
+        interleave: false,
+        analysis: analysis_options(std::ptr::null())?,
+    };
+    if options.is_null() {
+        return Ok(settings)
+    }
+    let options = unsafe { &*options };
+    if !options.language.is_null() {
+        let name = cstr_to_string(options.language, "language")?;
+        settings.lang = match decomp::Language::from_name(&name) {
+            Some(lang) => lang,
+            None => {
+                set_error(ErrorCode::InvalidArgument, format!("unknown decomp language {}", name));
+                return Err(())
+            }
+        };
+    }
+    if !options.function.is_null() {
+        settings.function = Some(cstr_to_string(options.function, "function")?);
+    }
+    settings.interleave = options.interleave != 0;
+    settings.analysis = analysis_options(options.analysis)?;
+    Ok(settings)
+}
+
+struct NameList {
+    names: Vec<CString>,
+    pointers: Vec<*const c_char>,
+}
+
+// The pointers are into `names`, which is never changed once made.
+unsafe impl Send for NameList {}
+unsafe impl Sync for NameList {}
+
+static LANGUAGE_NAMES: OnceLock<NameList> = OnceLock::new();
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_list_languages(count: *mut usize) -> *const *const c_char {
+    if count.is_null() {
+        set_error(ErrorCode::NullArgument, "count is NULL".to_string());
+        return std::ptr::null()
+    }
+    let list = LANGUAGE_NAMES.get_or_init(|| {
+        let names: Vec<CString> = decomp::LANGUAGES.iter().map(|(name, _)| c_string(name)).collect();
+        let pointers = names.iter().map(|name| name.as_ptr()).collect();
+        NameList { names, pointers }
+    });
+    unsafe { *count = list.names.len() };
+    list.pointers.as_ptr()
+}
+
+pub struct DecompC {
+    decomp: decomp::Decomp,
+    interleave: bool,
+}
+
+fn decompile(what: &str, load: impl FnOnce() -> Result<Program, BaretkError> + panic::UnwindSafe, settings: DecompSettings) -> *mut DecompC {
+    let function = settings.function.clone();
+    let lang = settings.lang;
+    let analysis = settings.analysis;
+    let result = catch_malformed(what, move || {
+        let protos = proto::PrototypeDb::new();
+        let dis = dis::disassemble_with_options(load()?, &analysis)?;
+        match function {
+            Some(func) => decomp::decomp_function(dis, &func, lang, &protos),
+            None => Ok(decomp::decomp_program(dis, lang, &protos)),
+        }
+    });
+    match result {
+        Ok(Ok(decomp)) => Box::into_raw(Box::new(DecompC { decomp, interleave: settings.interleave })),
+        Ok(Err(error)) => {
+            report(error);
+            std::ptr::null_mut()
+        },
+        Err(()) => std::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_decompile(path: *const i8, options: *const DecompOptionsC) -> *mut DecompC {
+    let (in_file, settings) = match (cstr_to_string(path, "path"), decomp_settings(options)) {
+        (Ok(in_file), Ok(settings)) => (in_file, settings),
+        _ => return std::ptr::null_mut(),
+    };
+    let contents = match read_file(&in_file) {
+        Ok(contents) => contents,
+        Err(()) => return std::ptr::null_mut(),
+    };
+    decompile(&in_file, || prog::load_program_from_bytes(&contents), settings)
+}
+
+// Decompiles a copy of the program, like baretk_disassemble_program.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_decompile_program(program: *const ProgramC, options: *const DecompOptionsC) -> *mut DecompC {
+    let (program, settings) = match (program_ref(program), decomp_settings(options)) {
+        (Some(program), Ok(settings)) => (program, settings),
+        _ => return std::ptr::null_mut(),
+    };
+    let copy = program.program.clone();
+    decompile("program", || Ok(copy), settings)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_free_decomp(decomp: *mut DecompC) {
+    if !decomp.is_null() {
+        unsafe { drop(Box::from_raw(decomp)) };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_decomp_get_text(decomp: *const DecompC) -> *mut c_char {
+    if decomp.is_null() {
+        set_error(ErrorCode::NullArgument, "decomp is NULL".to_string());
+        return std::ptr::null_mut()
+    }
+    let decomp = unsafe { &*decomp };
+    owned_string(&decomp.decomp.print(decomp.interleave))
+}
+
+// Program and disassembly handles are only read once they're made, so hosts
+// can share them between threads.
+const _: () = {
+    const fn shareable<T: Send + Sync>() {}
+    shareable::<ProgramC>();
+    shareable::<DisassemblyC>();
+};
+
+// The decomp's Rc and arena are only reachable through its handle, so the
+// handle can move between threads as long as one uses it at a time.
+unsafe impl Send for DecompC {}
+
+pub type LogCallback = extern "C" fn(user_data: *mut c_void, level: c_int, message: *const c_char);
+
+#[derive(Clone, Copy)]
+struct LogSink {
+    callback: Option<LogCallback>,
+    user_data: *mut c_void,
+    max_level: log::LevelFilter,
+}
+
+// The host chooses the user data knowing the callback runs on the threads
+// that analyse with the context.
+unsafe impl Send for LogSink {}
+
+const NO_LOG: LogSink = LogSink { callback: None, user_data: std::ptr::null_mut(), max_level: log::LevelFilter::Off };
+
+thread_local! {
+    // The log of the context the current call was made with.
+    static CURRENT_LOG: Cell<LogSink> = const { Cell::new(NO_LOG) };
+}
+
+// Where messages go when the current context has no callback, or there's no context.
+static GLOBAL_LOG: Mutex<LogSink> = Mutex::new(NO_LOG);
+
+fn current_sink() -> LogSink {
+    match CURRENT_LOG.with(Cell::get) {
+        sink if sink.callback.is_some() => sink,
+        _ => *GLOBAL_LOG.lock().unwrap_or_else(|e| e.into_inner()),
+    }
+}
+
+struct ContextLogger;
+
+impl log::Log for ContextLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        let sink = current_sink();
+        sink.callback.is_some() && metadata.level() <= sink.max_level
+    }
+
+    fn log(&self, record: &log::Record) {
+        let sink = current_sink();
+        if record.level() > sink.max_level {
+            return;
+        }
+        if let Some(callback) = sink.callback {
+            let message = c_string(&record.args().to_string());
+            callback(sink.user_data, record.level() as c_int, message.as_ptr());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: ContextLogger = ContextLogger;
+
+pub struct ContextC {
+    log: Mutex<LogSink>,
+    cancel: CancelToken,
+}
+
+thread_local! {
+    // The token of the context the current call was made with, which the
+    // analysis options pick up.
+    static CURRENT_CANCEL: RefCell<Option<CancelToken>> = const { RefCell::new(None) };
+}
+
+// Runs f with the context's log and cancel token as the current thread's.
+fn with_context<T>(context: *const ContextC, f: impl FnOnce() -> T) -> T {
+    let (sink, cancel) = match unsafe { context.as_ref() } {
+        Some(context) => (*context.log.lock().unwrap_or_else(|e| e.into_inner()), Some(context.cancel.clone())),
+        None => (NO_LOG, None),
+    };
+    let previous = CURRENT_LOG.with(|current| current.replace(sink));
+    let previous_cancel = CURRENT_CANCEL.with(|current| current.replace(cancel));
+    let result = f();
+    CURRENT_LOG.with(|current| current.set(previous));
+    CURRENT_CANCEL.with(|current| current.replace(previous_cancel));
+    result
+}
+
+// Every level is let through here; each sink filters its own.
+fn install_logger() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+}
+
+fn level_filter(level: c_int) -> log::LevelFilter {
+    match level {
+        0 => log::LevelFilter::Off,
+        1 => log::LevelFilter::Error,
+        2 => log::LevelFilter::Warn,
+        3 => log::LevelFilter::Info,
+        4 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    }
+}
+
+// Sends messages up to max_level from calls made without a context, or with
+// one that has no callback, to callback. A NULL callback drops them again.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_set_log_callback(callback: Option<LogCallback>, user_data: *mut c_void, max_level: c_int) {
+    install_logger();
+    *GLOBAL_LOG.lock().unwrap_or_else(|e| e.into_inner()) = LogSink { callback, user_data, max_level: level_filter(max_level) };
+}
+
+#[no_mangle]
+pub extern "C" fn baretk_context_new() -> *mut ContextC {
+    install_logger();
+    Box::into_raw(Box::new(ContextC { log: Mutex::new(NO_LOG), cancel: CancelToken::new() }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_context_free(context: *mut ContextC) {
+    if !context.is_null() {
+        unsafe { drop(Box::from_raw(context)) };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_context_set_log_callback(context: *const ContextC, callback: Option<LogCallback>, user_data: *mut c_void, max_level: c_int) {
+    let context = match unsafe { context.as_ref() } {
+        Some(context) => context,
+        None => {
+            set_error(ErrorCode::NullArgument, "context is NULL".to_string());
+            return
+        }
+    };
+    *context.log.lock().unwrap_or_else(|e| e.into_inner()) = LogSink { callback, user_data, max_level: level_filter(max_level) };
+}
+
+// Makes the analyses running with the context stop and fail with
+// BARETK_ERROR_CANCELLED. Safe to call from any thread while they run; the
+// context stays cancelled until baretk_reset_cancel.
+#[no_mangle]
+pub unsafe extern "C" fn baretk_cancel(context: *const ContextC) {
+    match unsafe { context.as_ref() } {
+        Some(context) => context.cancel.cancel(),
+        None => set_error(ErrorCode::NullArgument, "context is NULL".to_string()),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_reset_cancel(context: *const ContextC) {
+    match unsafe { context.as_ref() } {
+        Some(context) => context.cancel.reset(),
+        None => set_error(ErrorCode::NullArgument, "context is NULL".to_string()),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_context_load_program(context: *const ContextC, path: *const i8) -> *mut ProgramC {
+    with_context(context, || baretk_load_program(path))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_context_load_program_from_bytes(context: *const ContextC, bytes: *const u8, size: usize) -> *mut ProgramC {
+    with_context(context, || baretk_load_program_from_bytes(bytes, size))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_context_disassemble_program(context: *const ContextC, program: *const ProgramC) -> *mut DisassemblyC {
+    with_context(context, || baretk_disassemble_program(program))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_context_disassemble_program_with_options(context: *const ContextC, program: *const ProgramC, options: *const AnalysisOptionsC) -> *mut DisassemblyC {
+    with_context(context, || baretk_disassemble_program_with_options(program, options))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn baretk_context_decompile_program(context: *const ContextC, program: *const ProgramC, options: *const DecompOptionsC) -> *mut DecompC {
+    with_context(context, || baretk_decompile_program(program, options))
 }
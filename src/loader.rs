@@ -0,0 +1,89 @@
+// The file formats programs are loaded from. Each loader probes the start of
+// the file and the first to recognize it loads it; anything none of them
+// recognize is loaded as a raw binary. Loaders registered at run time, such as
+// ones for proprietary containers, are tried before the built-in ones.
+
+use std::sync::{Arc, OnceLock, RwLock};
+
+use crate::elf;
+use crate::error::BaretkError;
+use crate::pe;
+use crate::prog::{self, Diagnostics, Program};
+use crate::query;
+
+pub trait Loader: Send + Sync {
+    // Short name of the format, e.g. "elf".
+    fn name(&self) -> &'static str;
+
+    // Whether the file looks like this format. Only the headers should be
+    // checked; a file that probes true and then fails to load isn't retried
+    // as another format.
+    fn probe(&self, bytes: &[u8]) -> bool;
+
+    // Sections should be ranges of `bytes` rather than copies, and the code to
+    // disassemble goes in a ".text" section. Problems that can be worked
+    // around go through diagnostics.tolerate, which returns the error in
+    // strict mode.
+    fn load(&self, bytes: &Arc<[u8]>, diagnostics: &mut Diagnostics) -> Result<Program, BaretkError>;
+}
+
+struct ElfLoader;
+
+impl Loader for ElfLoader {
+    fn name(&self) -> &'static str {
+        "elf"
+    }
+
+    fn probe(&self, bytes: &[u8]) -> bool {
+        bytes.starts_with(&[0x7f, 0x45, 0x4c, 0x46])
+    }
+
+    fn load(&self, bytes: &Arc<[u8]>, diagnostics: &mut Diagnostics) -> Result<Program, BaretkError> {
+        elf::load_program(bytes, diagnostics)
+    }
+}
+
+struct PeLoader;
+
+impl Loader for PeLoader {
+    fn name(&self) -> &'static str {
+        "pe"
+    }
+
+    fn probe(&self, bytes: &[u8]) -> bool {
+        pe::check_is_pe_executable(bytes)
+    }
+
+    fn load(&self, bytes: &Arc<[u8]>, diagnostics: &mut Diagnostics) -> Result<Program, BaretkError> {
+        pe::load_program(bytes, diagnostics)
+    }
+}
+
+// Mach-O headers are recognized but not parsed yet.
+struct MachOLoader;
+
+impl Loader for MachOLoader {
+    fn name(&self) -> &'static str {
+        "macho"
+    }
+
+    fn probe(&self, bytes: &[u8]) -> bool {
+        query::is_macho(bytes)
+    }
+
+    fn load(&self, bytes: &Arc<[u8]>, _: &mut Diagnostics) -> Result<Program, BaretkError> {
+        log::warn!("Mach-O files can't be loaded yet; loading as a raw binary.");
+        Ok(prog::build_raw_program(bytes, None, None, None))
+    }
+}
+
+// The built-in loaders, after any registered since.
+pub fn loaders() -> &'static RwLock<Vec<Arc<dyn Loader>>> {
+    static LOADERS: OnceLock<RwLock<Vec<Arc<dyn Loader>>>> = OnceLock::new();
+    LOADERS.get_or_init(|| RwLock::new(vec![Arc::new(ElfLoader), Arc::new(PeLoader), Arc::new(MachOLoader)]))
+}
+
+// The loader for the file, or None if it's a raw binary.
+pub fn find_loader(bytes: &[u8]) -> Option<Arc<dyn Loader>> {
+    loaders().read().unwrap_or_else(|err| err.into_inner()).iter().find(|loader| loader.probe(bytes)).cloned()
+}
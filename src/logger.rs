@@ -0,0 +1,44 @@
+// The command line's logger: diagnostics from the loaders and analyses go to
+// stderr, so they never mix with a command's output. -v shows debug messages,
+// -vv everything, and -q only errors.
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}: {}", record.level().as_str().to_lowercase(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+pub fn init(level: LevelFilter) {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+// Removes the verbosity flags from the arguments and returns the level they ask for.
+pub fn take_level(args: &mut Vec<String>) -> LevelFilter {
+    let mut level = LevelFilter::Warn;
+    args.retain(|arg| {
+        match arg.as_str() {
+            "-v" | "--verbose" => level = LevelFilter::Debug,
+            "-vv" => level = LevelFilter::Trace,
+            "-q" | "--quiet" => level = LevelFilter::Error,
+            _ => return true,
+        }
+        false
+    });
+    level
+}
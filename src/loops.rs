@@ -0,0 +1,241 @@
+// Loops and recursion: natural loops in each function's control flow graph
+// (found from back edges to a dominating header) with their nesting depth, and
+// the cycles in the call graph.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::cfg::{self, Cfg};
+use crate::dis::Disassembly;
+use crate::xref::{XrefDb, XrefKind};
+
+pub struct Loop {
+    pub header: u64,
+    // 1 for outermost loops.
+    pub depth: usize,
+    // Start addresses of the blocks in the loop, header included.
+    pub blocks: Vec<u64>,
+    // Blocks jumping back to the header.
+    pub latches: Vec<u64>,
+    pub instructions: usize,
+}
+
+pub struct FunctionLoops {
+    pub name: String,
+    pub addr: u64,
+    pub loops: Vec<Loop>,
+    // Some cycle can be entered other than through one header, so isn't a natural loop.
+    pub irreducible: bool,
+}
+
+pub struct LoopReport {
+    pub functions: Vec<FunctionLoops>,
+    // Strongly connected groups of functions calling each other, including
+    // functions calling themselves, by name.
+    pub cycles: Vec<Vec<String>>,
+}
+
+// dom[b][d] is whether block d dominates block b. Block 0 is the entry.
+fn dominators(preds: &[Vec<usize>]) -> Vec<Vec<bool>> {
+    let n = preds.len();
+    let mut dom = vec![vec![true; n]; n];
+    if n == 0 {
+        return dom
+    }
+    dom[0] = vec![false; n];
+    dom[0][0] = true;
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in 1..n {
+            let mut new = vec![!preds[b].is_empty(); n];
+            for p in &preds[b] {
+                for d in 0..n {
+                    new[d] = new[d] && dom[*p][d];
+                }
+            }
+            new[b] = true;
+            if new != dom[b] {
+                dom[b] = new;
+                changed = true;
+            }
+        }
+    }
+    dom
+}
+
+fn has_cycle(edges: &[Vec<usize>]) -> bool {
+    // 0: unvisited, 1: on the current path, 2: done.
+    fn visit(v: usize, edges: &[Vec<usize>], state: &mut [u8]) -> bool {
+        state[v] = 1;
+        for w in &edges[v] {
+            if state[*w] == 1 || (state[*w] == 0 && visit(*w, edges, state)) {
+                return true
+            }
+        }
+        state[v] = 2;
+        false
+    }
+    let mut state = vec![0u8; edges.len()];
+    (0..edges.len()).any(|v| state[v] == 0 && visit(v, edges, &mut state))
+}
+
+pub fn find_loops(graph: &Cfg) -> (Vec<Loop>, bool) {
+    let index: HashMap<u64, usize> = graph.blocks.iter().enumerate().map(|(i, b)| (b.start, i)).collect();
+    let n = graph.blocks.len();
+    let mut preds = vec![Vec::<usize>::new(); n];
+    for (i, block) in graph.blocks.iter().enumerate() {
+        for (succ, _) in &block.succs {
+            if let Some(j) = index.get(succ) {
+                preds[*j].push(i);
+            }
+        }
+    }
+    let dom = dominators(&preds);
+    let mut reachable = vec![false; n];
+    let mut work = vec![0];
+    while let Some(b) = work.pop() {
+        if b >= n || reachable[b] {
+            continue;
+        }
+        reachable[b] = true;
+        work.extend(graph.blocks[b].succs.iter().filter_map(|(succ, _)| index.get(succ).copied()));
+    }
+
+    // Group back edges by header, then collect every block reaching a latch without
+    // going through the header.
+    let mut headers = BTreeSet::<usize>::new();
+    let mut forward = vec![Vec::<usize>::new(); n];
+    for (i, block) in graph.blocks.iter().enumerate().filter(|(i, _)| reachable[*i]) {
+        for (succ, _) in &block.succs {
+            match index.get(succ) {
+                Some(j) if dom[i][*j] => { headers.insert(*j); },
+                Some(j) => forward[i].push(*j),
+                None => (),
+            }
+        }
+    }
+    // Without the back edges the graph is acyclic, unless some cycle has more than one entry.
+    let irreducible = has_cycle(&forward);
+    let mut loops = Vec::<(usize, Vec<bool>, Vec<usize>)>::new();
+    for h in headers {
+        let latches: Vec<usize> = preds[h].iter().copied().filter(|p| reachable[*p] && dom[*p][h]).collect();
+        let mut body = vec![false; n];
+        body[h] = true;
+        let mut work = latches.clone();
+        while let Some(b) = work.pop() {
+            if body[b] {
+                continue;
+            }
+            body[b] = true;
+            work.extend(preds[b].iter().copied());
+        }
+        loops.push((h, body, latches));
+    }
+
+    let contains = |outer: &Vec<bool>, inner: &Vec<bool>| inner.iter().zip(outer).all(|(i, o)| !*i || *o);
+    let mut out: Vec<Loop> = loops.iter().map(|(h, body, latches)| {
+        let depth = loops.iter().filter(|(h2, body2, _)| h2 != h && contains(body2, body)).count() + 1;
+        let members: Vec<usize> = (0..n).filter(|b| body[*b]).collect();
+        Loop {
+            header: graph.blocks[*h].start,
+            depth,
+            blocks: members.iter().map(|b| graph.blocks[*b].start).collect(),
+            latches: latches.iter().map(|b| graph.blocks[*b].start).collect(),
+            instructions: members.iter().map(|b| graph.blocks[*b].lines.len()).sum(),
+        }
+    }).collect();
+    // Outer loops come before the loops nested in them.
+    out.sort_by_key(|l| (l.blocks.first().copied(), l.depth));
+    (out, irreducible)
+}
+
+// Tarjan's algorithm for the strongly connected components of the call graph.
+struct Tarjan<'a> {
+    edges: &'a [BTreeSet<usize>],
+    index: Vec<Option<usize>>,
+    low: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    next: usize,
+    sccs: Vec<Vec<usize>>,
+}
+
+impl<'a> Tarjan<'a> {
+    fn visit(&mut self, v: usize) {
+        self.index[v] = Some(self.next);
+        self.low[v] = self.next;
+        self.next += 1;
+        self.stack.push(v);
+        self.on_stack[v] = true;
+        for w in self.edges[v].iter().copied() {
+            match self.index[w] {
+                None => {
+                    self.visit(w);
+                    self.low[v] = self.low[v].min(self.low[w]);
+                },
+                Some(i) if self.on_stack[w] => self.low[v] = self.low[v].min(i),
+                _ => (),
+            }
+        }
+        if Some(self.low[v]) == self.index[v] {
+            let mut scc = Vec::<usize>::new();
+            while let Some(w) = self.stack.pop() {
+                self.on_stack[w] = false;
+                scc.push(w);
+                if w == v {
+                    break;
+                }
+            }
+            self.sccs.push(scc);
+        }
+    }
+}
+
+pub fn analyze(dis: &Disassembly) -> LoopReport {
+    let funcs = dis.functions();
+    let base = dis.section_addr();
+
+    let functions = funcs.iter().map(|func| {
+        let (loops, irreducible) = find_loops(&cfg::build_cfg(dis, &func.name, func.range.clone()));
+        FunctionLoops { name: func.name.clone(), addr: func.addr, loops, irreducible }
+    }).collect();
+
+    // Call graph edges, from the function containing each call to the function called.
+    let starts: HashMap<u64, usize> = funcs.iter().enumerate().map(|(i, f)| (f.addr, i)).collect();
+    let containing = |addr: u64| funcs.iter().position(|f| f.range.contains(&(addr.wrapping_sub(base) as usize)));
+    let db = XrefDb::build(dis);
+    let mut edges = vec![BTreeSet::<usize>::new(); funcs.len()];
+    for (callee_addr, callee) in &starts {
+        for r in db.refs_to(*callee_addr).iter().filter(|r| r.kind != XrefKind::Data) {
+            if let Some(caller) = containing(r.from) {
+                // Jumps within a function are its own control flow, not calls.
+                if r.kind == XrefKind::Call || caller != *callee {
+                    edges[caller].insert(*callee);
+                }
+            }
+        }
+    }
+    let mut tarjan = Tarjan {
+        edges: &edges,
+        index: vec![None; funcs.len()],
+        low: vec![0; funcs.len()],
+        on_stack: vec![false; funcs.len()],
+        stack: Vec::new(),
+        next: 0,
+        sccs: Vec::new(),
+    };
+    for v in 0..funcs.len() {
+        if tarjan.index[v].is_none() {
+            tarjan.visit(v);
+        }
+    }
+    let mut cycles: Vec<Vec<String>> = tarjan.sccs.into_iter()
+        .filter(|scc| scc.len() > 1 || edges[scc[0]].contains(&scc[0]))
+        .map(|mut scc| {
+            scc.sort_by_key(|f| funcs[*f].addr);
+            scc.iter().map(|f| funcs[*f].name.clone()).collect()
+        })
+        .collect();
+    cycles.sort();
+    LoopReport { functions, cycles }
+}
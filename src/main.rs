@@ -1,16 +1,63 @@
 use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::process;
 use std::collections::HashMap;
+use std::time::Instant;
 mod dis;
+mod reg;
 mod decomp;
 mod query;
 mod prog;
 mod dump;
 mod util;
+mod syscall;
+mod proto;
+mod xref;
+mod resolve;
+mod memref;
+mod cfg;
+mod stack;
+mod loops;
+mod reach;
+mod packer;
+mod imports;
+mod audit;
+mod checksec;
+mod headers;
+mod validate;
+mod hashes;
+mod toolchain;
+mod sig;
+mod mapfile;
+mod json;
+mod annotations;
+mod project;
+mod diff;
+mod bindiff;
+mod hexfile;
+mod gadget;
+mod asm;
+mod patch;
+mod calls;
+mod demangle;
+mod script;
+mod logger;
+mod emu;
+mod options;
+mod cancel;
+mod error;
+mod loader;
+mod stream;
+#[cfg(feature = "sqlite")]
+mod db;
 
 mod elf;
 mod pe;
 
 mod arm;
+mod aarch64;
 mod x86;
 mod riscv;
 
@@ -39,35 +86,589 @@ fn parse_cmd_args(args: Vec<String>) -> ArgList {
     ArgList { named_args, pos_args }
 }
 
+// Writes output to path, printing why if it can't.
+fn try_write_file(path: &str, output: &[u8]) -> bool {
+    let result = File::create(path)
+        .map_err(|error| error::BaretkError::io(path, "creating", error))
+        .and_then(|mut file| file.write_all(output).map_err(|error| error::BaretkError::io(path, "writing", error)));
+    if let Err(err) = &result {
+        eprintln!("{}", err);
+    }
+    result.is_ok()
+}
+
+struct ScanSummary {
+    dir: String,
+    files: usize,
+    // Files the command doesn't apply to, such as non-executables for dump.
+    skipped: usize,
+    failed: usize,
+    // What the command counted over all files.
+    found: usize,
+    // Files where it counted anything.
+    hits: usize,
+}
+
+impl std::fmt::Display for ScanSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Scanned {} file(s) under {}", self.files - self.skipped - self.failed, self.dir)?;
+        if self.skipped != 0 {
+            write!(f, ", {} skipped", self.skipped)?;
+        }
+        if self.failed != 0 {
+            write!(f, ", {} failed", self.failed)?;
+        }
+        Ok(())
+    }
+}
+
+// Every regular file under a directory, recursively, sorted by path. Symbolic
+// links aren't followed, so link loops in extracted filesystems are harmless.
+fn files_in_dir(path: &str) -> Result<Vec<String>, ()> {
+    let mut files = Vec::<String>::new();
+    let mut dirs = vec![std::path::PathBuf::from(path)];
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(error) => {
+                eprintln!("Error reading directory {}: {}", dir.display(), error);
+                if dir.as_os_str() == path {
+                    return Err(());
+                }
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => dirs.push(entry.path()),
+                Ok(kind) if kind.is_file() => files.push(entry.path().to_string_lossy().to_string()),
+                _ => (),
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Runs `scan` on every file under a directory, with a header naming each one
+// if `headers` is set. `scan` returns a count of what it found, or Err to skip
+// the file. A file that makes the scan panic is counted as failed and the
+// rest are still scanned.
+fn scan_directory(dir: &str, headers: bool, mut scan: impl FnMut(&str, &[u8]) -> Result<usize, ()>) -> Option<ScanSummary> {
+    let files = files_in_dir(dir).ok()?;
+    let mut summary = ScanSummary { dir: dir.to_string(), files: files.len(), skipped: 0, failed: 0, found: 0, hits: 0 };
+    for path in &files {
+        let contents = match util::try_read_file_contents(path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                eprintln!("{}", err);
+                summary.failed += 1;
+                continue;
+            }
+        };
+        if headers {
+            println!("==> {} <==", path);
+        }
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| scan(path, &contents))) {
+            Ok(Ok(found)) => {
+                summary.found += found;
+                summary.hits += (found != 0) as usize;
+            },
+            Ok(Err(())) => summary.skipped += 1,
+            Err(_) => {
+                eprintln!("{}: failed", path);
+                summary.failed += 1;
+            },
+        }
+        if headers {
+            println!();
+        }
+    }
+    Some(summary)
+}
+
+fn is_executable(contents: &[u8]) -> bool {
+    matches!(query::get_file_type(contents), query::FileType::Elf | query::FileType::PE)
+}
+
 // An objdump-like utility.
 fn cmd_dump(args: ArgList) {
     if let Some(in_file) = args.pos_args.get(0) {
         let out_file = args.pos_args.get(1);
-        let output = dump::dump_program(&prog::load_program_from_file(in_file).unwrap());
+        if Path::new(in_file).is_dir() {
+            if out_file.is_some() {
+                eprintln!("An out_file can't be given for a directory; redirect the output instead.");
+                return;
+            }
+            // Only ELF and PE files; anything else would be dumped as a raw image.
+            let summary = scan_directory(in_file, true, |_, contents| {
+                if !is_executable(contents) {
+                    println!("(not an ELF or PE file)");
+                    return Err(());
+                }
+                let program = prog::load_program_from_bytes(contents).map_err(|err| eprintln!("{}", err))?;
+                println!("{}", dump::dump_program(&program, contents));
+                Ok(1)
+            });
+            if let Some(summary) = summary {
+                println!("{}: {} executable(s) dumped", summary, summary.found);
+            }
+            return;
+        }
+        let contents = match util::try_read_file_contents(in_file.as_str()) {
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            },
+            Ok(bytes) => bytes,
+        };
+        let program = match prog::load_program_from_bytes(&contents) {
+            Ok(program) => program,
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            },
+        };
+        let output = dump::dump_program(&program, &contents);
         if let Some(out) = out_file {
-            util::try_write_file(out, output.as_bytes());
+            try_write_file(out, output.as_bytes());
         }
         else {
             println!("{}", output);
         }
     }
     else {
-        eprintln!("Usage: baretk dump <in_file> [out_file]");
+        eprintln!("Usage: baretk dump <in_file|dir> [out_file]");
+        eprintln!("    a directory is scanned recursively for ELF and PE files");
+    }
+}
+
+// Applies the project given with -project, then adds the symbols from the map
+// file given with -map and the names and comments given with -annotations, if any.
+fn apply_symbol_files(program: &mut prog::Program, bytes: &[u8], args: &ArgList) -> Result<(), ()> {
+    if let Some(path) = args.named_args.get("project") {
+        project::Project::load_file(path).map_err(|err| eprintln!("{}", err))?.apply(program, bytes);
+    }
+    if let Some(path) = args.named_args.get("map") {
+        let symbols = mapfile::load_file(path).map_err(|err| eprintln!("{}", err))?;
+        mapfile::apply(program, symbols);
+    }
+    if let Some(path) = args.named_args.get("annotations") {
+        let entries = annotations::load_file(path).map_err(|err| eprintln!("{}", err))?;
+        annotations::apply(program, entries);
+    }
+    Ok(())
+}
+
+// A readelf/dumpbin-style report of the headers.
+fn cmd_info(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk info <in_file>");
+            return;
+        }
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+    match headers::header_info(&contents) {
+        Ok(Some(info)) => print!("{}", info),
+        Ok(None) => eprintln!("{} isn't an ELF or PE file; raw binaries have no headers.", in_file),
+        Err(err) => eprintln!("{}", err),
+    }
+}
+
+// Which exploit mitigations the binary was built with.
+fn cmd_checksec(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk checksec <in_file> [--json]");
+            eprintln!("    --json print one JSON object per mitigation");
+            return;
+        }
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+    let program = match prog::load_program_from_bytes(&contents) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    let mitigations = match checksec::checksec(&contents, &program) {
+        Ok(Some(mitigations)) => mitigations,
+        Ok(None) => {
+            eprintln!("{} isn't an ELF or PE file; raw binaries have no mitigations to report.", in_file);
+            return;
+        },
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    let json = args.named_args.contains_key("json");
+    for m in mitigations {
+        if json {
+            let mut line = String::new();
+            json::Value::Object(vec![
+                ("name".to_string(), json::Value::String(m.name.to_string())),
+                ("enabled".to_string(), json::Value::Bool(m.enabled)),
+                ("detail".to_string(), json::Value::String(m.detail)),
+            ]).write(&mut line);
+            println!("{}", line);
+        }
+        else {
+            println!("{:<13} {:<4} {}", m.name, if m.enabled { "yes" } else { "no" }, m.detail);
+        }
+    }
+}
+
+// Hashes of the file and each section, for reports and lookups.
+fn cmd_hash(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk hash <in_file> [--json]");
+            eprintln!("    MD5, SHA-1 and SHA-256 of the file and each section, ssdeep and the PE imphash");
+            eprintln!("    --json print one JSON object");
+            return;
+        }
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+    let program = match prog::load_program_from_bytes(&contents) {
+        Ok(program) => program,
+        // The file's own hashes don't need its headers.
+        Err(err) => {
+            eprintln!("{}; hashing the whole file only", err);
+            prog::build_program_from_binary(&contents, None, None, None)
+        },
+    };
+    let file = hashes::hashes(&contents);
+    let ssdeep = hashes::ssdeep(&contents);
+    let imphash = hashes::imphash(&program);
+    // Only sections stored in the file: not .bss, and not a raw binary's one section, which is the whole file.
+    let in_file_data = |section: &prog::Section| {
+        let start = section.offset as usize;
+        !section.bytes.is_empty() && contents.get(start..start + section.bytes.len()) == Some(section.bytes.as_slice())
+    };
+    let mut sections: Vec<(&String, &prog::Section)> = program.section_table.iter()
+        .filter(|(_, section)| program.format != "raw" && in_file_data(section))
+        .collect();
+    sections.sort_by_key(|(name, section)| (section.offset, name.as_str()));
+
+    if args.named_args.contains_key("json") {
+        let digests = |h: hashes::Hashes| vec![
+            ("md5".to_string(), json::Value::String(h.md5)),
+            ("sha1".to_string(), json::Value::String(h.sha1)),
+            ("sha256".to_string(), json::Value::String(h.sha256)),
+        ];
+        let mut members = vec![
+            ("file".to_string(), json::Value::String(in_file.clone())),
+            ("size".to_string(), json::Value::Number(contents.len().to_string())),
+        ];
+        members.extend(digests(file));
+        members.push(("ssdeep".to_string(), json::Value::String(ssdeep)));
+        members.push(("imphash".to_string(), imphash.map_or(json::Value::Null, json::Value::String)));
+        let sections = sections.iter().map(|(name, section)| {
+            let mut members = vec![
+                ("name".to_string(), json::Value::String(name.to_string())),
+                ("addr".to_string(), json::Value::hex(section.addr)),
+                ("size".to_string(), json::Value::Number(section.bytes.len().to_string())),
+            ];
+            members.extend(digests(hashes::hashes(&section.bytes)));
+            json::Value::Object(members)
+        }).collect();
+        members.push(("sections".to_string(), json::Value::Array(sections)));
+        let mut line = String::new();
+        json::Value::Object(members).write(&mut line);
+        println!("{}", line);
+        return;
+    }
+
+    println!("File     {} ({} bytes)", in_file, contents.len());
+    println!("MD5      {}", file.md5);
+    println!("SHA-1    {}", file.sha1);
+    println!("SHA-256  {}", file.sha256);
+    println!("ssdeep   {}", ssdeep);
+    if let Some(imphash) = imphash {
+        println!("imphash  {}", imphash);
+    }
+    for (name, section) in sections {
+        let h = hashes::hashes(&section.bytes);
+        println!("\nSection {} at {:#x} ({} bytes)", name, section.addr, section.bytes.len());
+        println!("  MD5      {}", h.md5);
+        println!("  SHA-1    {}", h.sha1);
+        println!("  SHA-256  {}", h.sha256);
+    }
+}
+
+fn cmd_sections(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk sections <in_file> [--exec|--writable]");
+            return;
+        }
+    };
+    let program = match prog::load_program_from_file(in_file) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(program) => program,
+    };
+    let mut perm = 0u8;
+    if args.named_args.contains_key("exec") {
+        perm |= util::RWX_EXEC;
+    }
+    if args.named_args.contains_key("writable") {
+        perm |= util::RWX_WRITE;
+    }
+    print!("{}", dump::sections_table(&program, perm));
+}
+
+// Writes the raw contents of a section or segment to a file, like objcopy -O binary -j.
+// The bytes as a C array definition followed by its length, like xxd -i.
+fn c_array(name: &str, bytes: &[u8]) -> String {
+    let mut out = format!("unsigned char {}[] = {{\n", name);
+    for line in bytes.chunks(12) {
+        let values: Vec<String> = line.iter().map(|b| format!("0x{:02x}", b)).collect();
+        out += format!("    {},\n", values.join(", ")).as_str();
+    }
+    out += format!("}};\nunsigned int {}_len = {};\n", name, bytes.len()).as_str();
+    out
+}
+
+fn cmd_extract(args: ArgList) {
+    let as_c_array = args.named_args.contains_key("as-c-array");
+    let as_hexstring = args.named_args.contains_key("as-hexstring");
+    let (in_file, out_file) = match (args.pos_args.get(0), args.named_args.get("o")) {
+        (Some(in_file), out_file) if out_file.is_some() || as_c_array || as_hexstring => (in_file, out_file),
+        _ => {
+            eprintln!("Usage: baretk extract <in_file> -section <name>|-segment <index> -o <out_file>");
+            eprintln!("    segments are numbered from 0 in header order, as in the Segments column of the sections command");
+            eprintln!("    --as-c-array write the bytes as C source, to stdout without -o");
+            eprintln!("    --as-hexstring write the bytes as one line of hex, to stdout without -o");
+            eprintln!("    -name <ident> name of the C array (default code)");
+            return;
+        }
+    };
+    if as_c_array && as_hexstring {
+        eprintln!("--as-c-array and --as-hexstring can't be combined.");
+        return;
+    }
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+    let program = match prog::load_program_from_bytes(&contents) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    let (what, addr, bytes) = if let Some(name) = args.named_args.get("section") {
+        match program.section_table.get(name) {
+            Some(section) => (format!("section {}", name), section.addr, section.bytes.as_slice()),
+            None => {
+                let mut names: Vec<&String> = program.section_table.keys().filter(|name| !name.is_empty()).collect();
+                names.sort();
+                eprintln!("No section named \"{}\". Sections: {}", name, names.iter().map(|name| name.as_str()).collect::<Vec<_>>().join(" "));
+                return;
+            }
+        }
+    }
+    else if let Some(index) = args.named_args.get("segment") {
+        let segment = match index.parse::<usize>().ok().and_then(|i| program.program_table.get(i)) {
+            Some(segment) => segment,
+            None => {
+                eprintln!("No segment {}; the file has {} segment(s).", index, program.program_table.len());
+                return;
+            }
+        };
+        let start = (segment.offset as usize).min(contents.len());
+        let end = (start + segment.size).min(contents.len());
+        (format!("segment {}", index), segment.vaddr, &contents[start..end])
+    }
+    else {
+        eprintln!("Expected -section <name> or -segment <index>.");
+        return;
+    };
+    let text = if as_c_array {
+        let name = args.named_args.get("name").map(|name| name.as_str()).unwrap_or("code");
+        if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            eprintln!("\"{}\" isn't a valid C identifier.", name);
+            return;
+        }
+        Some(format!("// {} ({:#x}) of {}\n{}", what, addr, in_file, c_array(name, bytes)))
+    }
+    else if as_hexstring {
+        Some(bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>() + "\n")
+    }
+    else {
+        None
+    };
+    match (out_file, text) {
+        (None, Some(text)) => print!("{}", text),
+        (Some(out_file), text) => {
+            let data = text.as_ref().map(|text| text.as_bytes()).unwrap_or(bytes);
+            if try_write_file(out_file, data) {
+                println!("Wrote {} byte(s) of {} ({:#x}) to {}", bytes.len(), what, addr, out_file);
+            }
+        },
+        (None, None) => {},
+    }
+}
+
+fn parse_hex_format(name: &str) -> Option<hexfile::Format> {
+    match name {
+        "raw" | "bin" => Some(hexfile::Format::Raw),
+        "ihex" | "hex" => Some(hexfile::Format::IntelHex),
+        "srec" => Some(hexfile::Format::SRecord),
+        _ => None,
+    }
+}
+
+// Converts firmware images between raw binary, Intel HEX and S-records.
+fn cmd_convert(args: ArgList) {
+    let (in_file, out_file) = match (args.pos_args.get(0), args.named_args.get("o")) {
+        (Some(in_file), Some(out_file)) => (in_file, out_file),
+        _ => {
+            eprintln!("Usage: baretk convert <in_file> -o <out_file>");
+            eprintln!("    formats are raw, ihex and srec, taken from the contents and the output extension");
+            eprintln!("    -from <format> input format, if not detected");
+            eprintln!("    -to <format> output format, if not the one the extension implies");
+            eprintln!("    -base <addr> load address of a raw input (default 0)");
+            eprintln!("    -entry <addr> start address to record in the output");
+            eprintln!("    -fill <byte> value for gaps between records in a raw output (default 0xff)");
+            return;
+        }
+    };
+    let mut numbers = HashMap::<&str, u64>::new();
+    for (name, default) in [("base", 0), ("fill", 0xff)] {
+        let value = match args.named_args.get(name) {
+            Some(s) => match parse_number(s) {
+                Some(value) => value,
+                None => {
+                    eprintln!("Can't convert \"{}\" to number.", s);
+                    return;
+                }
+            },
+            None => default,
+        };
+        numbers.insert(name, value);
+    }
+    let formats = (args.named_args.get("from").map(|s| parse_hex_format(s)), args.named_args.get("to").map(|s| parse_hex_format(s)));
+    if let (Some(None), _) | (_, Some(None)) = formats {
+        eprintln!("Unknown format. Expected raw, ihex or srec.");
+        return;
+    }
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+    let from = formats.0.flatten().unwrap_or_else(|| hexfile::detect(&contents));
+    let to = formats.1.flatten().unwrap_or_else(|| hexfile::format_for_path(out_file));
+    let mut image = match hexfile::Image::parse(&contents, &from, numbers["base"]) {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("{}: {}", in_file, err);
+            return;
+        }
+    };
+    if let Some(entry) = args.named_args.get("entry").and_then(|s| parse_number(s)) {
+        image.entry = Some(entry);
+    }
+    let output = match to {
+        hexfile::Format::Raw => {
+            let (base, bytes) = image.to_raw(numbers["fill"] as u8);
+            println!("Raw image starts at {:#x}, {} byte(s)", base, bytes.len());
+            Ok(bytes)
+        },
+        hexfile::Format::IntelHex => image.to_ihex().map(String::into_bytes),
+        hexfile::Format::SRecord => image.to_srec().map(String::into_bytes),
+    };
+    match output {
+        Ok(bytes) => {
+            if try_write_file(out_file, &bytes) {
+                println!("Wrote {} chunk(s), {:#x}..{:#x}, to {}", image.chunks.len(),
+                    image.chunks.first().map_or(0, |chunk| chunk.addr), image.end(), out_file);
+            }
+        },
+        Err(err) => eprintln!("{}", err),
     }
 }
 
 fn cmd_disassemble(args: ArgList) {
     if let Some(in_file) = args.pos_args.get(0) {
         let out_file = args.pos_args.get(1);
+        let options = match analysis_options(&args) {
+            Ok(options) => options,
+            Err(()) => return,
+        };
+        if options.memory_budget.is_some() {
+            stream_disassembly(in_file, out_file, &options, &args);
+            return;
+        }
         let contents = match util::try_read_file_contents(in_file.as_str()) {
-            Err(()) => { return; },
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            },
             Ok(bytes) => bytes,
         };
 
-        let disassembly = dis::disassemble(&contents);
-        let output = disassembly.print(true);
+        let mut program = match prog::load_program_with_options(&contents, &options) {
+            Ok(program) => program,
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            },
+        };
+        if apply_symbol_files(&mut program, &contents, &args).is_err() {
+            return;
+        }
+        if args.named_args.contains_key("demangle") {
+            demangle::demangle_symbols(&mut program);
+        }
+        let disassembly = match dis::disassemble_with_options(program, &options) {
+            Ok(disassembly) => disassembly,
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            },
+        };
+        let output = match args.named_args.contains_key("json") {
+            true => disassembly.print_json(),
+            false => disassembly.print(options.show_bytes),
+        };
         if let Some(out) = out_file {
-            util::try_write_file(out, output.as_bytes());
+            try_write_file(out, output.as_bytes());
         }
         else {
             println!("{}", output);
@@ -75,37 +676,290 @@ fn cmd_disassemble(args: ArgList) {
     }
     else {
         eprintln!("Usage: baretk dis <in_file> [out_file]");
+        eprintln!("    -project <file> project file saved by the project command");
+        eprintln!("    -map <file> GNU ld map file naming the functions of a stripped image");
+        eprintln!("    -annotations <file> JSON or CSV file of address names and comments");
+        eprintln!("    --json print one JSON object per instruction");
+        eprintln!("    --demangle show C++ and Rust symbol names demangled");
+        eprintln!("    --no-bytes leave out each instruction's bytes");
+        eprintln!("    -memory <size> stay within this much memory, e.g. 512M, by writing the listing as it's decoded");
+        print_analysis_usage();
+    }
+}
+
+// Writes the listing as it's decoded, reading raw images a window at a time.
+fn stream_disassembly(in_file: &str, out_file: Option<&String>, options: &options::AnalysisOptions, args: &ArgList) {
+    let format = match args.named_args.contains_key("json") {
+        true => stream::Format::Json,
+        false => stream::Format::Text { show_bytes: options.show_bytes },
+    };
+    let prepare = |program: &mut prog::Program, bytes: &[u8]| {
+        if apply_symbol_files(program, bytes, args).is_err() {
+            return false;
+        }
+        if args.named_args.contains_key("demangle") {
+            demangle::demangle_symbols(program);
+        }
+        true
+    };
+    let result = match out_file {
+        Some(out) => match File::create(out) {
+            Ok(file) => {
+                let mut writer = io::BufWriter::new(file);
+                stream::disassemble_file(in_file, options, &format, &mut writer, out, prepare)
+                    .and_then(|()| writer.flush().map_err(|err| error::BaretkError::io(out, "writing", err)))
+            },
+            Err(err) => Err(error::BaretkError::io(out, "creating", err)),
+        },
+        None => {
+            let mut writer = io::BufWriter::new(io::stdout().lock());
+            stream::disassemble_file(in_file, options, &format, &mut writer, "stdout", prepare)
+                .and_then(|()| writer.write_all(b"\n").and_then(|()| writer.flush()).map_err(|err| error::BaretkError::io("stdout", "writing", err)))
+        },
+    };
+    match result {
+        Ok(()) | Err(error::BaretkError::Cancelled) => (),
+        Err(err) => eprintln!("{}", err),
+    }
+}
+
+// The options shared by the commands that disassemble.
+fn analysis_options(args: &ArgList) -> Result<options::AnalysisOptions, ()> {
+    let mut options = options::AnalysisOptions::default();
+    if let Some(arch) = args.named_args.get("arch") {
+        if !options::ARCHITECTURES.contains(&arch.as_str()) {
+            eprintln!("Unknown architecture \"{}\". Expected {}.", arch, options::ARCHITECTURES.join(", "));
+            return Err(())
+        }
+        options.arch = Some(arch.clone());
+    }
+    if let Some(base) = args.named_args.get("base") {
+        match parse_number(base) {
+            Some(base) => options.base_addr = Some(base),
+            None => {
+                eprintln!("Invalid base address \"{}\".", base);
+                return Err(())
+            }
+        }
+    }
+    if let Some(sections) = args.named_args.get("thumb") {
+        options.thumb_sections = sections.split(',').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect();
+    }
+    if args.named_args.contains_key("recursive") {
+        options.sweep = options::Sweep::Recursive;
+    }
+    if args.named_args.contains_key("strict") {
+        options.parse_mode = options::ParseMode::Strict;
     }
+    if let Some(size) = args.named_args.get("memory") {
+        match parse_size(size) {
+            Some(size) => options.memory_budget = Some(size),
+            None => {
+                eprintln!("Invalid memory size \"{}\". Expected bytes, or a number ending in K, M or G.", size);
+                return Err(())
+            }
+        }
+    }
+    options.show_bytes = !args.named_args.contains_key("no-bytes");
+    Ok(options)
+}
+
+fn print_analysis_usage() {
+    eprintln!("    -arch <{}> decode as this architecture", options::ARCHITECTURES.join("|"));
+    eprintln!("    -base <addr> load address of a raw binary");
+    eprintln!("    -thumb <section,...> decode these ARM sections as Thumb (\"file\" for a raw binary)");
+    eprintln!("    --recursive only decode code reachable from the entry point and functions");
+    eprintln!("    --strict reject malformed headers instead of loading what can be read");
 }
 
 fn cmd_decompile(args: ArgList) {
     if let Some(in_file) = args.pos_args.get(0) {
         let contents = match util::try_read_file_contents(in_file.as_str()) {
-            Err(()) => { return; },
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            },
             Ok(bytes) => bytes,
         };
 
-        let decomp = decomp::decomp_program_from_bytes(&contents, decomp::Language::Pseudocode);
-        println!("{}", decomp.print());
+        let mut protos = proto::PrototypeDb::new();
+        if let Some(path) = args.named_args.get("protos") {
+            if let Err(err) = protos.load_file(path) {
+                eprintln!("{}", err);
+                return;
+            }
+        }
+
+        let lang = match args.named_args.get("lang").map(|s| s.as_str()) {
+            None => decomp::Language::Pseudocode,
+            Some(name) => match decomp::Language::from_name(name) {
+                Some(lang) => lang,
+                None => {
+                    let names: Vec<&str> = decomp::LANGUAGES.iter().map(|(name, _)| *name).collect();
+                    eprintln!("Unknown output language \"{}\". Expected {}.", name, names.join(" or "));
+                    return;
+                }
+            }
+        };
+
+        let options = match analysis_options(&args) {
+            Ok(options) => options,
+            Err(()) => return,
+        };
+        let mut program = match prog::load_program_with_options(&contents, &options) {
+            Ok(program) => program,
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            },
+        };
+        if apply_symbol_files(&mut program, &contents, &args).is_err() {
+            return;
+        }
+        if let Some(path) = args.named_args.get("sigs") {
+            let mut sigs = sig::SignatureDb::new();
+            if let Err(err) = sigs.load_file(path) {
+                eprintln!("{}", err);
+                return;
+            }
+            let matches = sigs.match_program(&program);
+            sig::apply_matches(&mut program, &matches);
+        }
+        if args.named_args.contains_key("demangle") {
+            demangle::demangle_symbols(&mut program);
+        }
+
+        let start = Instant::now();
+        let disassembly = match dis::disassemble_with_options(program, &options) {
+            Ok(disassembly) => disassembly,
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            },
+        };
+        if args.named_args.contains_key("split") {
+            let out_dir = match (args.named_args.get("o"), args.named_args.contains_key("func")) {
+                (Some(out_dir), false) => out_dir,
+                (_, true) => {
+                    eprintln!("--split writes every function; it can't be combined with -func.");
+                    return;
+                },
+                (None, _) => {
+                    eprintln!("--split needs an output directory given with -o.");
+                    return;
+                },
+            };
+            let decomps = decomp::decomp_functions(disassembly, lang, &protos);
+            write_split_decomp(&decomps, out_dir, &args, lang);
+            return;
+        }
+        let decomp = if let Some(func) = args.named_args.get("func") {
+            match decomp::decomp_function(disassembly, func, lang, &protos) {
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return;
+                },
+                Ok(decomp) => decomp,
+            }
+        } else {
+            decomp::decomp_program(disassembly, lang, &protos)
+        };
+        let lifted = Instant::now();
+        let interleave = args.named_args.contains_key("interleave");
+        let output = match args.named_args.contains_key("json") {
+            true => decomp.print_json(),
+            false => decomp.print(interleave) + "\n",
+        };
+        match args.named_args.get("o") {
+            Some(out_file) => { try_write_file(out_file, output.as_bytes()); },
+            None => print!("{}", output),
+        }
+        if args.named_args.contains_key("stats") {
+            eprintln!("lift: {:?}, print: {:?}", lifted - start, lifted.elapsed());
+            eprintln!("{}", decomp.stats());
+        }
     }
     else {
-        eprintln!("Usage: baretk dis <in_file> [out_file]");
+        eprintln!("Usage: baretk decomp <in_file>");
+        eprintln!("    -func <name|0xaddr> only decompile the given function");
+        eprintln!("    -lang <pseudo|rust> output language (default pseudo)");
+        eprintln!("    -protos <file> extra C function prototypes, one per line");
+        eprintln!("    -project <file> project file saved by the project command");
+        eprintln!("    -map <file> GNU ld map file naming the functions of a stripped image");
+        eprintln!("    -annotations <file> JSON or CSV file of address names and comments");
+        eprintln!("    -sigs <file> library function signatures used to name stripped functions");
+        eprintln!("    -o <path> output file, or directory with --split");
+        eprintln!("    --split write each function to its own file in the -o directory");
+        eprintln!("    --interleave print each source instruction above its lifted statement(s)");
+        eprintln!("    --stats print timings and expression memory usage to stderr");
+        eprintln!("    --json print one JSON object per statement");
+        eprintln!("    --demangle show C++ and Rust symbol names demangled");
+        print_analysis_usage();
     }
 }
 
-fn cmd_strings(args: ArgList) {
-    if let Some(in_file) = args.pos_args.get(0) {
-        let out_file = args.pos_args.get(1);
-        let contents = match util::try_read_file_contents(in_file.as_str()) {
-            Err(()) => { return; },
-            Ok(bytes) => bytes,
+// A file name for a function: characters other than letters, digits, '_', '-'
+// and '.' are replaced, and long (demangled) names are cut short.
+fn function_file_name(name: &str) -> String {
+    let mut file: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.') { c } else { '_' })
+        .take(100)
+        .collect();
+    if file.is_empty() || file.starts_with('.') {
+        file.insert(0, '_');
+    }
+    file
+}
+
+// Writes each decompiled function to its own file, adding a number to names
+// that would otherwise collide.
+fn write_split_decomp(decomps: &[decomp::Decomp], out_dir: &str, args: &ArgList, lang: decomp::Language) {
+    if let Err(error) = fs::create_dir_all(out_dir) {
+        eprintln!("Error creating directory {}: {}", out_dir, error);
+        return;
+    }
+    let json = args.named_args.contains_key("json");
+    let interleave = args.named_args.contains_key("interleave");
+    let ext = match (json, lang) {
+        (true, _) => "jsonl",
+        (false, decomp::Language::Rust) => "rs",
+        (false, decomp::Language::Pseudocode) => "txt",
+    };
+    let mut used = HashMap::<String, usize>::new();
+    let mut written = 0;
+    for decomp in decomps {
+        let base = function_file_name(decomp.name());
+        let count = used.entry(base.to_lowercase()).or_insert(0);
+        *count += 1;
+        let file = match *count {
+            1 => format!("{}.{}", base, ext),
+            n => format!("{}_{}.{}", base, n, ext),
         };
+        let output = if json { decomp.print_json() } else { decomp.print(interleave) };
+        if !try_write_file(Path::new(out_dir).join(file).to_string_lossy().as_ref(), output.as_bytes()) {
+            return;
+        }
+        written += 1;
+    }
+    println!("Wrote {} function(s) to {}", written, out_dir);
+}
+
+struct StringsOptions {
+    min_len: usize,
+    printable: bool,
+    encodings: Vec<query::Encoding>,
+    radix: Option<&'static str>,
+    filter: Option<regex::Regex>,
+    json: bool,
+}
 
+impl StringsOptions {
+    fn parse(args: &ArgList) -> Result<StringsOptions, ()> {
         let min_len = if let Some(opt) = args.named_args.get("n") {
             let res = opt.parse::<usize>();
             if let Err(err) = res {
                 eprintln!("Can't convert \"{}\" to number: {}", opt, err);
-                return;
+                return Err(());
             }
             else { 
                 res.ok() 
@@ -114,50 +968,1607 @@ fn cmd_strings(args: ArgList) {
             None
         }.unwrap_or(4);
 
-        let printable = args.named_args.contains_key("printable");
+        let encodings = match args.named_args.get("enc") {
+            Some(names) => match query::Encoding::parse_list(names) {
+                Some(encodings) => encodings,
+                None => {
+                    eprintln!("Unknown encoding in \"{}\". Expected ascii, utf8, utf16le, utf16be, utf16 or all.", names);
+                    return Err(());
+                }
+            },
+            None => vec![query::Encoding::Ascii],
+        };
 
-        let strings = query::get_strings(contents.as_slice(), min_len, printable);
-        if let Some(out) = out_file {
-            util::try_write_file_lines(out.as_str(), strings);
+        // Offsets in the radix -t gives, as with GNU strings.
+        let radix = match args.named_args.get("t").map(|s| s.as_str()) {
+            None => None,
+            Some("x") => Some("x"),
+            Some("d") => Some("d"),
+            Some("o") => Some("o"),
+            Some(other) => {
+                eprintln!("Unknown offset radix \"{}\". Expected x, d or o.", other);
+                return Err(());
+            }
+        };
+
+        let filter = match args.named_args.get("grep").map(|pattern| regex::Regex::new(pattern)) {
+            None => None,
+            Some(Ok(filter)) => Some(filter),
+            Some(Err(err)) => {
+                eprintln!("Invalid -grep pattern: {}", err);
+                return Err(());
+            }
+        };
+
+        Ok(StringsOptions {
+            min_len,
+            printable: args.named_args.contains_key("printable"),
+            encodings,
+            radix,
+            filter,
+            json: args.named_args.contains_key("json"),
+        })
+    }
+}
+
+// Prints or writes the strings of one file and returns how many were found.
+// `file` adds the file name to JSON objects, for directory scans.
+fn print_strings(in_file: &str, contents: &[u8], out_file: Option<&String>, options: &StringsOptions, file: bool) -> usize {
+    let mut strings = query::find_strings(contents, options.min_len, options.printable, &options.encodings, options.filter.as_ref());
+    let count = strings.len();
+    if options.radix.is_some() || options.json {
+        // Strings are still printed when the headers can't be read, just without a section.
+        match prog::load_program_from_bytes(contents) {
+            Ok(program) => query::locate_strings(&program, &mut strings),
+            Err(err) => eprintln!("{}", err),
         }
-        else {
-            println!("ASCII strings found in {}:", in_file);
-            for str in strings {
-                println!(" {}", str);
+    }
+    if options.json {
+        let mut output = String::new();
+        for found in strings {
+            let mut members = vec![
+                ("offset".to_string(), json::Value::hex(found.offset as u64)),
+                ("string".to_string(), json::Value::String(found.text)),
+                ("encoding".to_string(), json::Value::String(found.encoding.name().to_string())),
+            ];
+            if let Some(addr) = found.addr {
+                members.push(("addr".to_string(), json::Value::hex(addr)));
+            }
+            if let Some(section) = found.section {
+                members.push(("section".to_string(), json::Value::String(section)));
+            }
+            if file {
+                members.insert(0, ("file".to_string(), json::Value::String(in_file.to_string())));
             }
+            json::Value::Object(members).write(&mut output);
+            output += "\n";
+        }
+        match out_file {
+            Some(out) => { try_write_file(out, output.as_bytes()); },
+            None => print!("{}", output),
+        }
+        return count;
+    }
+    // With more than one encoding, each string says which it was found in.
+    let radix = options.radix;
+    let tagged = options.encodings.len() > 1;
+    let lines: Vec<String> = strings.into_iter().map(|found| {
+        let mut line = match radix {
+            Some("x") => format!("{:8x} ", found.offset),
+            Some("o") => format!("{:8o} ", found.offset),
+            Some(_) => format!("{:8} ", found.offset),
+            None => String::new(),
+        };
+        if radix.is_some() {
+            let addr = found.addr.map_or(String::new(), |addr| format!("{:#x}", addr));
+            line += format!("{:<12} {:<16} ", addr, found.section.unwrap_or_default()).as_str();
         }
+        if tagged {
+            line += format!("{:<8} ", found.encoding.name()).as_str();
+        }
+        line + found.text.as_str()
+    }).collect();
+    if let Some(out) = out_file {
+        try_write_file(out.as_str(), lines.iter().map(|line| line.clone() + "\n").collect::<String>().as_bytes());
     }
     else {
-        eprintln!("Usage: baretk strings <in_file> [out_file]");
-        eprintln!("    -n <num> min. string length (default 4)");
+        match options.encodings.as_slice() {
+            [encoding] => println!("{} strings found in {}:", encoding.name().to_ascii_uppercase(), in_file),
+            _ => println!("Strings found in {}:", in_file),
+        }
+        for line in lines {
+            println!(" {}", line);
+        }
     }
+    count
 }
 
-fn cmd_help() {
-    println!("Available commands:");
-    for cmd in COMMANDS {
-        println!("    baretk {} - {}", cmd.name, cmd.desc);
+fn cmd_strings(args: ArgList) {
+    if let Some(in_file) = args.pos_args.get(0) {
+        let out_file = args.pos_args.get(1);
+        let options = match StringsOptions::parse(&args) {
+            Ok(options) => options,
+            Err(()) => { return; },
+        };
+        if Path::new(in_file).is_dir() {
+            if out_file.is_some() {
+                eprintln!("An out_file can't be given for a directory; redirect the output instead.");
+                return;
+            }
+            let summary = scan_directory(in_file, false, |path, contents| {
+                Ok(print_strings(path, contents, None, &options, true))
+            });
+            if let Some(summary) = summary {
+                // Kept off stdout for JSON, so the output stays one object per line.
+                let line = format!("{}: {} string(s)", summary, summary.found);
+                if options.json { eprintln!("{}", line) } else { println!("\n{}", line) }
+            }
+            return;
+        }
+        let contents = match util::try_read_file_contents(in_file.as_str()) {
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            },
+            Ok(bytes) => bytes,
+        };
+        print_strings(in_file, &contents, out_file, &options, false);
+    }
+    else {
+        eprintln!("Usage: baretk strings <in_file|dir> [out_file]");
+        eprintln!("    -n <num> min. string length (default 4)");
+        eprintln!("    -enc <list> comma-separated encodings: ascii (default), utf8, utf16le, utf16be, utf16 or all");
+        eprintln!("    -grep <regex> only print strings matching the regular expression, e.g. \"https?://\"");
+        eprintln!("    -t <x|d|o> print the file offset in hex, decimal or octal, and the address and section");
+        eprintln!("    --json print one JSON object per string");
+        eprintln!("    a directory is scanned recursively, file by file");
     }
-    println!("    baretk help - Prints this help.");
-}
-
-struct Command {
-    name: &'static str,
-    desc: &'static str,
-    func: fn(ArgList),
+}
+
+fn cmd_symbols(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk symbols <in_file> [--json]");
+            eprintln!("    -project <file> project file saved by the project command");
+            eprintln!("    -map <file> GNU ld map file naming the functions of a stripped image");
+            eprintln!("    -annotations <file> JSON or CSV file of address names and comments");
+            eprintln!("    --json print one JSON object per symbol");
+            eprintln!("    --demangle show C++ and Rust symbol names demangled");
+            return;
+        }
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+    let mut program = match prog::load_program_from_bytes(&contents) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    if apply_symbol_files(&mut program, &contents, &args).is_err() {
+        return;
+    }
+    if args.named_args.contains_key("demangle") {
+        demangle::demangle_symbols(&mut program);
+    }
+    let mut symbols: Vec<&prog::Symbol> = program.symbol_table.iter().filter(|sym| !sym.name.is_empty()).collect();
+    symbols.sort_by_key(|sym| (sym.addr, sym.name.as_str()));
+    let json = args.named_args.contains_key("json");
+    for sym in symbols {
+        let kind = if sym.is_func { "func" } else { "object" };
+        if json {
+            let mut line = String::new();
+            json::Value::Object(vec![
+                ("name".to_string(), json::Value::String(sym.name.clone())),
+                ("addr".to_string(), json::Value::hex(sym.addr)),
+                ("size".to_string(), json::Value::Number(sym.size.to_string())),
+                ("type".to_string(), json::Value::String(kind.to_string())),
+            ]).write(&mut line);
+            println!("{}", line);
+        }
+        else {
+            println!("{:#018x} {:>8} {:6} {}", sym.addr, sym.size, kind, sym.name);
+        }
+    }
+}
+
+// A c++filt replacement: demangles the names given, or every name in the lines of stdin.
+fn cmd_demangle(args: ArgList) {
+    if args.pos_args.is_empty() {
+        if io::stdin().is_terminal() {
+            eprintln!("Usage: baretk demangle <name...>");
+            eprintln!("    with no names, demangles the names in each line of stdin");
+            return;
+        }
+        for line in io::stdin().lock().lines() {
+            match line {
+                Ok(line) => println!("{}", demangle::demangle_text(&line)),
+                Err(_) => break,
+            }
+        }
+        return;
+    }
+    for name in &args.pos_args {
+        println!("{}", demangle::demangle(name).unwrap_or_else(|| name.clone()));
+    }
+}
+
+fn cmd_xref(args: ArgList) {
+    let (in_file, addr) = match (args.pos_args.get(0), args.pos_args.get(1)) {
+        (Some(in_file), Some(addr)) => (in_file, addr),
+        _ => {
+            eprintln!("Usage: baretk xref <in_file> <0xaddr|symbol>");
+            return;
+        }
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+
+    let disassembly = match dis::disassemble(&contents) {
+        Ok(disassembly) => disassembly,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    let target = match disassembly.program().find_symbol(addr) {
+        Some(sym) => sym.addr,
+        None => match addr.strip_prefix("0x").and_then(|hex| u64::from_str_radix(hex, 16).ok()) {
+            Some(target) => target,
+            None => {
+                eprintln!("Can't find symbol or parse address \"{}\".", addr);
+                return;
+            }
+        }
+    };
+    let db = xref::XrefDb::build(&disassembly);
+    let listing = &disassembly.section().instructions;
+    let base = disassembly.section_addr();
+    let texts: HashMap<u64, String> = listing.instruction_offset_vec_in(0..usize::MAX).into_iter()
+        .map(|offset| base + offset as u64)
+        .zip(listing.instruction_text_vec_in(0..usize::MAX))
+        .collect();
+    let refs = db.refs_to(target);
+    println!("{} reference(s) to {:#010x}:", refs.len(), target);
+    for r in refs {
+        println!("  {:#010x} {:<4} {}", r.from, r.kind.name(), texts.get(&r.from).map(|s| s.as_str()).unwrap_or(""));
+    }
+}
+
+fn cmd_refs(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk refs <in_file> [0xaddr|symbol]");
+            eprintln!("    -project <file> project file with names and data types");
+            return;
+        }
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+
+    let mut program = match prog::load_program_from_bytes(&contents) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    if apply_symbol_files(&mut program, &contents, &args).is_err() {
+        return;
+    }
+    let disassembly = dis::disassemble_program(program);
+    let program = disassembly.program();
+    let map = memref::MemoryMap::build(&disassembly);
+    let listing = &disassembly.section().instructions;
+    let base = disassembly.section_addr();
+    let texts: HashMap<u64, String> = listing.instruction_offset_vec_in(0..usize::MAX).into_iter()
+        .map(|offset| base + offset as u64)
+        .zip(listing.instruction_text_vec_in(0..usize::MAX))
+        .collect();
+    let symbols = program.symbol_index();
+    let location = |addr: u64| match symbols.containing(addr) {
+        Some(sym) if sym.addr == addr => sym.name.clone(),
+        Some(sym) => format!("{}+{:#x}", sym.name, addr - sym.addr),
+        None => String::new(),
+    };
+    let data_type = |addr: u64| symbols.containing(addr)
+        .and_then(|sym| program.data_types.get(&sym.addr))
+        .map_or(String::new(), |ty| format!(" ({})", ty));
+
+    // With a target, list every accessor of it; otherwise summarize each accessed address.
+    if let Some(target) = args.pos_args.get(1) {
+        let range = match program.find_symbol(target) {
+            Some(sym) => sym.addr..sym.addr + sym.size.max(1),
+            None => match target.strip_prefix("0x").and_then(|hex| u64::from_str_radix(hex, 16).ok()) {
+                Some(addr) => addr..addr + 1,
+                None => {
+                    eprintln!("Can't find symbol or parse address \"{}\".", target);
+                    return;
+                }
+            }
+        };
+        let refs = map.accessors(range.clone());
+        println!("{} access(es) to {:#010x}..{:#010x}:", refs.len(), range.start, range.end);
+        for r in refs {
+            println!("  {:#010x} {:<5} {:<20} {}{}", r.from, r.access.name(), location(r.addr),
+                texts.get(&r.from).map(|s| s.as_str()).unwrap_or(""), data_type(r.addr));
+        }
+        return;
+    }
+    for (addr, refs) in map.refs_in(0..u64::MAX) {
+        let count = |access: memref::Access| refs.iter().filter(|r| r.access == access).count();
+        let mut funcs: Vec<String> = refs.iter()
+            .map(|r| program.function_at(r.from).map(|sym| sym.name.clone()).unwrap_or(format!("{:#x}", r.from)))
+            .collect();
+        funcs.dedup();
+        println!("{:#010x} {:<20} read {:<3} write {:<3} rw {:<3} addr {:<3} {}{}", addr, location(*addr),
+            count(memref::Access::Read), count(memref::Access::Write), count(memref::Access::ReadWrite),
+            count(memref::Access::Address), funcs.join(", "), data_type(*addr));
+    }
+}
+
+fn cmd_stack(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk stack <in_file> [-func name]");
+            return;
+        }
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+
+    let disassembly = match dis::disassemble(&contents) {
+        Ok(disassembly) => disassembly,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    let usage = stack::analyze(&disassembly);
+    if let Some(func) = args.named_args.get("func") {
+        let found = usage.iter().find(|u| &u.name == func)
+            .or_else(|| disassembly.program().find_symbol(func).and_then(|sym| usage.iter().find(|u| u.addr == sym.addr)));
+        match found {
+            Some(u) => {
+                println!("{} ({:#010x}): frame {} bytes, total {} bytes{}", u.name, u.addr, u.frame, u.total,
+                    if u.bounded { "" } else { " (lower bound)" });
+                if !u.notes().is_empty() {
+                    println!("  {}", u.notes());
+                }
+                println!("  deepest chain: {}", u.chain.join(" -> "));
+            },
+            None => eprintln!("Can't find function \"{}\".", func),
+        }
+        return;
+    }
+    println!("{:<10} {:<32} {:>8} {:>8}  {}", "address", "function", "frame", "total", "notes");
+    for u in &usage {
+        let total = if u.bounded { format!("{}", u.total) } else { format!(">={}", u.total) };
+        println!("{:#010x} {:<32} {:>8} {:>8}  {}", u.addr, u.name, u.frame, total, u.notes());
+    }
+}
+
+fn cmd_loops(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk loops <in_file> [-func name]");
+            return;
+        }
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+
+    let disassembly = match dis::disassemble(&contents) {
+        Ok(disassembly) => disassembly,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    let report = loops::analyze(&disassembly);
+    let func = args.named_args.get("func");
+    for f in report.functions.iter().filter(|f| func.map_or(!f.loops.is_empty() || f.irreducible, |name| &f.name == name)) {
+        let max_depth = f.loops.iter().map(|l| l.depth).max().unwrap_or(0);
+        println!("{} ({:#010x}): {} loop(s), max depth {}{}", f.name, f.addr, f.loops.len(), max_depth,
+            if f.irreducible { ", irreducible control flow" } else { "" });
+        for l in &f.loops {
+            let latches: Vec<String> = l.latches.iter().map(|latch| format!("{:#x}", latch)).collect();
+            println!("  {}loop at {:#010x}: depth {}, {} block(s), {} instruction(s), back edge(s) from {}",
+                "  ".repeat(l.depth - 1), l.header, l.depth, l.blocks.len(), l.instructions, latches.join(", "));
+        }
+    }
+    if func.is_some() {
+        return;
+    }
+    if report.cycles.is_empty() {
+        println!("No recursion found.");
+        return;
+    }
+    println!("Call cycles:");
+    for cycle in &report.cycles {
+        if cycle.len() == 1 {
+            println!("  {} (calls itself)", cycle[0]);
+        }
+        else {
+            println!("  {}", cycle.join(" <-> "));
+        }
+    }
+}
+
+fn cmd_unreachable(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk unreachable <in_file> [--symbols]");
+            return;
+        }
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+
+    let disassembly = match dis::disassemble(&contents) {
+        Ok(disassembly) => disassembly,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    let reach = reach::Reachability::build(&disassembly, args.named_args.contains_key("symbols"));
+    let regions = reach.unreachable_regions(&disassembly);
+    let (reached, total) = reach.coverage();
+    println!("{} of {} instruction(s) reachable from {} seed(s), {} unreachable region(s)", reached, total, reach.seeds.len(), regions.len());
+    for region in &regions {
+        let location = match (&region.function, region.whole_function) {
+            (Some(func), true) => format!("function {} is never called", func),
+            (Some(func), false) => format!("in {}", func),
+            (None, _) => String::new(),
+        };
+        println!("  {:#010x}..{:#010x} {:>5} instruction(s)  {}", region.start, region.end, region.instructions, location);
+    }
+}
+
+fn cmd_syscalls(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk syscalls <in_file>");
+            return;
+        }
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+
+    let disassembly = match dis::disassemble(&contents) {
+        Ok(disassembly) => disassembly,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    let program = disassembly.program();
+    let sites = calls::find_syscalls(&disassembly);
+    if sites.is_empty() {
+        println!("No system calls found.");
+        return;
+    }
+    // Group the sites by syscall, unknown numbers last.
+    let mut groups = Vec::<(Option<i64>, Option<&str>, Vec<u64>)>::new();
+    for site in &sites {
+        match groups.iter_mut().find(|(number, _, _)| *number == site.number) {
+            Some(group) => group.2.push(site.addr),
+            None => groups.push((site.number, site.name, vec![site.addr])),
+        }
+    }
+    groups.sort_by_key(|(number, _, _)| (number.is_none(), *number));
+    println!("{:<20} {:>6} {:>5}  {}", "name", "number", "count", "call sites");
+    for (number, name, addrs) in &groups {
+        let sites: Vec<String> = addrs.iter().map(|addr| match program.function_at(*addr) {
+            Some(sym) => format!("{:#x} ({})", addr, sym.name),
+            None => format!("{:#x}", addr),
+        }).collect();
+        let number = number.map_or("?".to_string(), |n| n.to_string());
+        println!("{:<20} {:>6} {:>5}  {}", name.unwrap_or("unknown"), number, addrs.len(), sites.join(", "));
+    }
+}
+
+fn cmd_coverage(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk coverage <in_file> [--symbols]");
+            return;
+        }
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+
+    let disassembly = match dis::disassemble(&contents) {
+        Ok(disassembly) => disassembly,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    let reach = reach::Reachability::build(&disassembly, args.named_args.contains_key("symbols"));
+    let c = reach.coverage_bytes(&disassembly, &contents);
+    let percent = |n: u64, of: u64| if of == 0 { 0.0 } else { n as f64 * 100.0 / of as f64 };
+    println!("Executable bytes:      {:>10}", c.executable);
+    println!("Disassembled:          {:>10} ({:.1}% of executable)", c.disassembled, percent(c.disassembled, c.executable));
+    println!("  reachable:           {:>10} ({:.1}%) from {} seed(s)", c.reached, percent(c.reached, c.disassembled), reach.seeds.len());
+    println!("  unreferenced code:   {:>10} ({:.1}%)", c.unreached, percent(c.unreached, c.disassembled));
+    println!("  undecodable:         {:>10} ({:.1}%)", c.undecoded, percent(c.undecoded, c.disassembled));
+    println!("  padding:             {:>10} ({:.1}%)", c.padding, percent(c.padding, c.disassembled));
+    if c.overlay > 0 {
+        println!("Overlay past the image:{:>10} byte(s)", c.overlay);
+    }
+}
+
+// Loads, disassembles and decompiles every binary under a directory, reporting
+// how much of each code section decoded so decoder regressions show up as numbers.
+fn cmd_verify(args: ArgList) {
+    let dir = match args.pos_args.get(0) {
+        Some(dir) if Path::new(dir).is_dir() => dir,
+        _ => {
+            eprintln!("Usage: baretk verify <dir>");
+            eprintln!("    every ELF and PE file under the directory is checked");
+            return;
+        }
+    };
+    let percent = |n: u64, of: u64| if of == 0 { 0.0 } else { n as f64 * 100.0 / of as f64 };
+    let (mut decoded_total, mut code_total, mut errors) = (0u64, 0u64, 0usize);
+    let summary = scan_directory(dir, false, |path, contents| {
+        if !is_executable(contents) {
+            return Err(());
+        }
+        let disassembly = match prog::load_program_from_bytes(contents).and_then(|program| dis::disassemble_with_options(program, &options::AnalysisOptions::default())) {
+            Ok(disassembly) => disassembly,
+            Err(err) => {
+                println!("{}: {}", path, err);
+                errors += 1;
+                return Ok(0);
+            },
+        };
+        let program = disassembly.program();
+        let code = program.section_table.get(program.code_section()).map_or(0, |section| section.bytes.len() as u64);
+        let (mut decoded, mut unknown) = (0u64, 0usize);
+        let listing = &disassembly.section().instructions;
+        for (ins, size) in listing.instruction_vec().iter().zip(listing.instruction_size_vec_in(0..usize::MAX)) {
+            if ins.opcode == "unk" {
+                unknown += 1;
+            }
+            else {
+                decoded += size as u64;
+            }
+        }
+        let mut line = format!("{}: {} {}/{} byte(s) decoded ({:.1}%), {} unknown instruction(s)",
+            path, program.machine_type, decoded, code, percent(decoded, code), unknown);
+        if !program.warnings.is_empty() {
+            line += format!(", {} warning(s)", program.warnings.len()).as_str();
+        }
+        let protos = proto::PrototypeDb::new();
+        // A decompiler panic is reported with the file's numbers instead of dropping them.
+        if std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| decomp::decomp_program(disassembly, decomp::Language::Pseudocode, &protos))).is_err() {
+            line += ", decompile failed";
+            errors += 1;
+        }
+        println!("{}", line);
+        decoded_total += decoded;
+        code_total += code;
+        Ok(unknown)
+    });
+    if let Some(summary) = summary {
+        println!("{}: {}/{} byte(s) decoded ({:.1}%), {} unknown instruction(s) in {} file(s), {} error(s)",
+            summary, decoded_total, code_total, percent(decoded_total, code_total), summary.found, summary.hits, errors + summary.failed);
+    }
+}
+
+// Writes functions, instructions, xrefs, strings and symbols to a SQLite database.
+#[cfg(feature = "sqlite")]
+fn cmd_db(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk db <in_file> [out_file]");
+            eprintln!("    out_file defaults to <in_file>.{}", db::EXTENSION);
+            eprintln!("    -project <file> project file saved by the project command");
+            eprintln!("    -map <file> GNU ld map file naming the functions of a stripped image");
+            eprintln!("    -annotations <file> JSON or CSV file of address names and comments");
+            print_analysis_usage();
+            return;
+        }
+    };
+    let out_file = args.pos_args.get(1).cloned().unwrap_or(format!("{}.{}", in_file, db::EXTENSION));
+    let options = match analysis_options(&args) {
+        Ok(options) => options,
+        Err(()) => return,
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+    let mut program = match prog::load_program_with_options(&contents, &options) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    if apply_symbol_files(&mut program, &contents, &args).is_err() {
+        return;
+    }
+    let disassembly = match dis::disassemble_with_options(program, &options) {
+        Ok(disassembly) => disassembly,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    match db::write_database(&out_file, &disassembly, &contents, options.min_string_len) {
+        Ok(()) => println!("Wrote {}", out_file),
+        Err(err) => eprintln!("{}", err),
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn cmd_db(_: ArgList) {
+    eprintln!("This build of baretk can't write databases; rebuild it with --features sqlite.");
+}
+
+fn cmd_calls(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk calls <in_file>");
+            eprintln!("    -project <file> project file saved by the project command");
+            eprintln!("    -map <file> GNU ld map file naming the functions of a stripped image");
+            eprintln!("    -annotations <file> JSON or CSV file of address names and comments");
+            return;
+        }
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+    let mut program = match prog::load_program_from_bytes(&contents) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    if apply_symbol_files(&mut program, &contents, &args).is_err() {
+        return;
+    }
+    let disassembly = dis::disassemble_program(program);
+    let calls = calls::find_calls(&disassembly);
+    for call in &calls {
+        let target = call.target.map_or("?".to_string(), |t| format!("{:#010x}", t));
+        let mut line = format!("{:#010x} {:<8} {:<10} {}", call.from, call.kind.name(), target, call.name.as_deref().unwrap_or(""));
+        if let Some(func) = &call.function {
+            line = format!("{:<60} in {}", line, func);
+        }
+        println!("{}", line.trim_end());
+    }
+    let count = |kind: calls::CallKind| calls.iter().filter(|call| call.kind == kind).count();
+    println!("{} call(s): {} direct, {} plt, {} import, {} indirect ({} unresolved)", calls.len(),
+        count(calls::CallKind::Direct), count(calls::CallKind::Plt), count(calls::CallKind::Import),
+        count(calls::CallKind::Indirect), calls.iter().filter(|call| call.target.is_none()).count());
+}
+
+fn cmd_audit(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk audit <in_file|dir>");
+            eprintln!("    a directory is scanned recursively for ELF and PE files");
+            return;
+        }
+    };
+    if Path::new(in_file).is_dir() {
+        let summary = scan_directory(in_file, true, |_, contents| {
+            if !is_executable(contents) {
+                println!("(not an ELF or PE file)");
+                return Err(());
+            }
+            Ok(print_audit(contents))
+        });
+        if let Some(summary) = summary {
+            println!("{}: {} dangerous function(s) in {} file(s)", summary, summary.found, summary.hits);
+        }
+        return;
+    }
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+    print_audit(&contents);
+}
+
+// Prints the dangerous functions a binary uses and returns how many there are.
+fn print_audit(contents: &[u8]) -> usize {
+    let disassembly = match dis::disassemble(contents) {
+        Ok(disassembly) => disassembly,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 0;
+        },
+    };
+    let findings = audit::audit(&disassembly);
+    if findings.is_empty() {
+        println!("No dangerous functions found.");
+        return 0;
+    }
+    for finding in &findings {
+        let origin = if finding.imported { "imported" } else { "linked" };
+        if finding.sites.is_empty() {
+            println!("{} ({}, {}): no call sites found", finding.name, finding.reason, origin);
+            continue;
+        }
+        println!("{} ({}, {}): {} call site(s)", finding.name, finding.reason, origin, finding.sites.len());
+        for (site, func) in &finding.sites {
+            match func {
+                Some(func) => println!("  {:#010x} in {}", site, func),
+                None => println!("  {:#010x}", site),
+            }
+        }
+    }
+    findings.len()
+}
+
+fn cmd_cfg(args: ArgList) {
+    if let Some(in_file) = args.pos_args.get(0) {
+        let contents = match util::try_read_file_contents(in_file.as_str()) {
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            },
+            Ok(bytes) => bytes,
+        };
+
+        let disassembly = match dis::disassemble(&contents) {
+            Ok(disassembly) => disassembly,
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            },
+        };
+        let (name, range) = if let Some(func) = args.named_args.get("func") {
+            match disassembly.function_range(func) {
+                Err(err) => {
+                    eprintln!("{}", err);
+                    return;
+                },
+                Ok(found) => found,
+            }
+        } else {
+            (disassembly.section().section_name.clone(), 0..usize::MAX)
+        };
+        let cfg = cfg::build_cfg(&disassembly, &name, range);
+        let out_file = args.named_args.get("o");
+        let svg = args.named_args.contains_key("svg") || out_file.map_or(false, |out| out.ends_with(".svg"));
+        let output = if svg { cfg.to_svg() } else { cfg.to_dot() };
+        if let Some(out) = out_file {
+            try_write_file(out, output.as_bytes());
+        }
+        else {
+            println!("{}", output);
+        }
+    }
+    else {
+        eprintln!("Usage: baretk cfg <in_file>");
+        eprintln!("    -func <name|0xaddr> only graph the given function");
+        eprintln!("    -o <out_file> write the graph to a file");
+        eprintln!("    --svg draw the graph as SVG instead of Graphviz DOT (the default for .svg files)");
+    }
+}
+
+// Creates or updates a project file with the names, comments and data types
+// given, so later runs can load them with -project.
+fn cmd_project(args: ArgList) {
+    let (in_file, project_file) = match (args.pos_args.get(0), args.pos_args.get(1)) {
+        (Some(in_file), Some(project_file)) => (in_file, project_file),
+        _ => {
+            eprintln!("Usage: baretk project <in_file> <project_file>");
+            eprintln!("    -map <file> GNU ld map file naming the functions of a stripped image");
+            eprintln!("    -annotations <file> JSON or CSV file of address names and comments");
+            eprintln!("    -sigs <file> library function signatures used to name stripped functions");
+            eprintln!("    -type <0xaddr:type[count]> mark data, e.g. 0x404010:u32[4] (u8..u64, i8..i64, f32, f64, char, ptr)");
+            return;
+        }
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+    let mut program = match prog::load_program_from_bytes(&contents) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    // An existing project is updated rather than replaced.
+    if std::path::Path::new(project_file).exists() {
+        match project::Project::load_file(project_file) {
+            Ok(saved) => saved.apply(&mut program, &contents),
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            },
+        }
+    }
+    if apply_symbol_files(&mut program, &contents, &args).is_err() {
+        return;
+    }
+    if let Some(path) = args.named_args.get("sigs") {
+        let mut sigs = sig::SignatureDb::new();
+        if let Err(err) = sigs.load_file(path) {
+            eprintln!("{}", err);
+            return;
+        }
+        let matches = sigs.match_program(&program);
+        sig::apply_matches(&mut program, &matches);
+    }
+    if let Some(mark) = args.named_args.get("type") {
+        let parsed = mark.split_once(':').and_then(|(addr, ty)| Some((parse_number(addr)?, project::parse_type(ty)?)));
+        match parsed {
+            Some((addr, (name, count))) => project::mark_type(&mut program, addr, &name, count),
+            None => {
+                eprintln!("Can't parse type mark \"{}\". Expected 0xaddr:type or 0xaddr:type[count].", mark);
+                return;
+            }
+        }
+    }
+    let mut saved = project::Project::capture(&program, &contents);
+    // The xrefs are saved so the next load of a patched build only analyses what changed.
+    let analysis = project::SavedAnalysis::capture(&dis::disassemble_program(program));
+    let xrefs = analysis.xrefs.len();
+    saved.analysis = Some(analysis);
+    if let Err(err) = saved.save_file(project_file) {
+        eprintln!("{}", err);
+        return;
+    }
+    println!("Saved {} symbol(s), {} comment(s), {} type mark(s) and {} xref(s) to {}.",
+        saved.symbols.len(), saved.comments.len(), saved.types.len(), xrefs, project_file);
+}
+
+fn cmd_sigs(args: ArgList) {
+    let (in_file, sig_file) = match (args.pos_args.get(0), args.pos_args.get(1)) {
+        (Some(in_file), Some(sig_file)) => (in_file, sig_file),
+        _ => {
+            eprintln!("Usage: baretk sigs <in_file> <sig_file>");
+            return;
+        }
+    };
+    let program = match prog::load_program_from_file(in_file) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(program) => program,
+    };
+    let mut sigs = sig::SignatureDb::new();
+    if let Err(err) = sigs.load_file(sig_file) {
+        eprintln!("{}", err);
+        return;
+    }
+    let matches = sigs.match_program(&program);
+    println!("{} function(s) matched:", matches.len());
+    for m in matches {
+        println!("  {:#010x} {}", m.addr, m.name);
+    }
+}
+
+// Makes a signature from a function for the sigs command to find elsewhere.
+fn cmd_makesig(args: ArgList) {
+    let (in_file, func) = match (args.pos_args.get(0), args.named_args.get("func")) {
+        (Some(in_file), Some(func)) => (in_file, func),
+        _ => {
+            eprintln!("Usage: baretk makesig <in_file> -func <name|0xaddr>");
+            eprintln!("    -o <sig_file> signature file to add it to, JSON if it ends in .json (default: print it)");
+            eprintln!("    -len <bytes> longest pattern (default {})", sig::DEFAULT_PATTERN_LEN);
+            eprintln!("    -project <file> project file saved by the project command");
+            eprintln!("    -map <file> GNU ld map file naming the functions of a stripped image");
+            eprintln!("    -annotations <file> JSON or CSV file of address names and comments");
+            print_analysis_usage();
+            return;
+        }
+    };
+    let max_len = match args.named_args.get("len").map(|len| len.parse::<usize>()) {
+        None => sig::DEFAULT_PATTERN_LEN,
+        Some(Ok(len)) if len > 0 => len,
+        Some(_) => {
+            eprintln!("Invalid pattern length \"{}\".", args.named_args["len"]);
+            return;
+        }
+    };
+    let options = match analysis_options(&args) {
+        Ok(options) => options,
+        Err(()) => return,
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+    let mut program = match prog::load_program_with_options(&contents, &options) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    if apply_symbol_files(&mut program, &contents, &args).is_err() {
+        return;
+    }
+    let disassembly = match dis::disassemble_with_options(program, &options) {
+        Ok(disassembly) => disassembly,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    let signature = match sig::function_signature(&disassembly, func, max_len) {
+        Ok(signature) => signature,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    let out_file = match args.named_args.get("o") {
+        Some(out_file) => out_file,
+        None => {
+            println!("{}", signature.to_line());
+            return;
+        }
+    };
+    // JSON files are rewritten with the signature added, replacing one with
+    // the same name; other files get another line.
+    let line = signature.to_line();
+    let output = if out_file.ends_with(".json") {
+        let mut sigs = sig::SignatureDb::new();
+        if Path::new(out_file).exists() {
+            if let Err(err) = sigs.load_file(out_file) {
+                eprintln!("{}", err);
+                return;
+            }
+        }
+        sigs.add(signature);
+        sigs.to_json()
+    }
+    else {
+        let mut text = fs::read_to_string(out_file).unwrap_or_default();
+        if !text.is_empty() && !text.ends_with('\n') {
+            text += "\n";
+        }
+        text + line.as_str() + "\n"
+    };
+    if try_write_file(out_file, output.as_bytes()) {
+        println!("{}", line);
+        println!("Wrote {}", out_file);
+    }
+}
+
+fn cmd_diff(args: ArgList) {
+    let (old_file, new_file) = match (args.pos_args.get(0), args.pos_args.get(1)) {
+        (Some(old_file), Some(new_file)) => (old_file, new_file),
+        _ => {
+            eprintln!("Usage: baretk diff <old_file> <new_file> [out_file]");
+            return;
+        }
+    };
+    let old = match util::try_read_file_contents(old_file.as_str()).and_then(|bytes| dis::disassemble(&bytes)) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(disassembly) => disassembly,
+    };
+    let new = match util::try_read_file_contents(new_file.as_str()).and_then(|bytes| dis::disassemble(&bytes)) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(disassembly) => disassembly,
+    };
+    let output = diff::diff_functions(&old, &new).print();
+    if let Some(out) = args.pos_args.get(2) {
+        try_write_file(out, output.as_bytes());
+    }
+    else {
+        println!("{}", output);
+    }
+}
+
+fn cmd_bindiff(args: ArgList) {
+    let (old_file, new_file) = match (args.pos_args.get(0), args.pos_args.get(1)) {
+        (Some(old_file), Some(new_file)) => (old_file, new_file),
+        _ => {
+            eprintln!("Usage: baretk bindiff <old_file> <new_file> [out_file]");
+            eprintln!("    -context <num> bytes of unchanged context to dump around each range (default 16)");
+            return;
+        }
+    };
+    let context = match args.named_args.get("context").map(|s| s.parse::<usize>()) {
+        None => 16,
+        Some(Ok(n)) => n,
+        Some(Err(err)) => {
+            eprintln!("Can't convert context to number: {}", err);
+            return;
+        }
+    };
+    let old = match util::try_read_file_contents(old_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+    let new = match util::try_read_file_contents(new_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+    let ranges = bindiff::diff_ranges(&old, &new);
+    if ranges.is_empty() {
+        println!("Files are identical.");
+        return;
+    }
+    let program = match prog::load_program_from_bytes(&old) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    let output = bindiff::print(&old, &new, &program, &ranges, context);
+    if let Some(out) = args.pos_args.get(2) {
+        try_write_file(out, output.as_bytes());
+    }
+    else {
+        println!("{}", output);
+    }
+}
+
+// Encodes a -u16/-u32/-u64 value in the program's byte order.
+fn integer_pattern(args: &ArgList, endianess: u8) -> Option<Result<Vec<u8>, String>> {
+    let (name, size) = [("u16", 2), ("u32", 4), ("u64", 8)].into_iter().find(|(name, _)| args.named_args.contains_key(*name))?;
+    let text = &args.named_args[name];
+    let value = match parse_number(text) {
+        Some(value) if size == 8 || value >> (size * 8) == 0 => value,
+        _ => return Some(Err(format!("Can't convert \"{}\" to a {}.", text, name))),
+    };
+    let bytes = match endianess {
+        util::BIG_ENDIAN => value.to_be_bytes()[8 - size..].to_vec(),
+        _ => value.to_le_bytes()[..size].to_vec(),
+    };
+    Some(Ok(bytes))
+}
+
+fn cmd_gadgets(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk gadgets <in_file>");
+            eprintln!("    -max-len <num> most instructions in a gadget, including the ending (default 5)");
+            eprintln!("    -ending <list> comma-separated endings: ret (default), jmp, call, syscall or all");
+            return;
+        }
+    };
+    let max_len = match args.named_args.get("max-len").map(|s| s.parse::<usize>()) {
+        None => 5,
+        Some(Ok(n)) if n > 0 => n,
+        Some(_) => {
+            eprintln!("-max-len must be a positive number.");
+            return;
+        }
+    };
+    let endings = match args.named_args.get("ending") {
+        None => vec![gadget::Ending::Ret],
+        Some(names) => match gadget::Ending::parse_list(names) {
+            Some(endings) => endings,
+            None => {
+                eprintln!("Unknown ending in \"{}\". Expected ret, jmp, call, syscall or all.", names);
+                return;
+            }
+        },
+    };
+    let disassembly = match util::try_read_file_contents(in_file.as_str()).and_then(|bytes| dis::disassemble(&bytes)) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(disassembly) => disassembly,
+    };
+    let gadgets = gadget::find_gadgets(&disassembly, max_len, &endings);
+    for gadget in &gadgets {
+        if gadget.count > 1 {
+            println!("{:#010x}: {} ({} times)", gadget.addr, gadget.text, gadget.count);
+        }
+        else {
+            println!("{:#010x}: {}", gadget.addr, gadget.text);
+        }
+    }
+    println!("{} unique gadget(s)", gadgets.len());
+}
+
+fn cmd_search(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) => in_file,
+        None => {
+            eprintln!("Usage: baretk search <in_file> -pattern \"48 8B ?? ?? E8\"|-string <text>|-u32 <value>");
+            eprintln!("    pattern bytes are hex, ?? for any byte, or a nibble mask such as 4?");
+            eprintln!("    -string <text> ASCII text, without a terminating zero");
+            eprintln!("    -u16|-u32|-u64 <value> integer in the binary's byte order, hex with 0x or decimal");
+            return;
+        }
+    };
+    let program = match prog::load_program_from_file(in_file) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(program) => program,
+    };
+    let pattern = if let Some(pattern) = args.named_args.get("pattern") {
+        match sig::Pattern::parse(pattern) {
+            Some(pattern) => pattern,
+            None => {
+                eprintln!("Can't parse pattern \"{}\".", pattern);
+                return;
+            }
+        }
+    }
+    else if let Some(text) = args.named_args.get("string").filter(|text| !text.is_empty()) {
+        sig::Pattern::exact(text.as_bytes().to_vec())
+    }
+    else {
+        match integer_pattern(&args, program.endianess) {
+            Some(Ok(bytes)) => sig::Pattern::exact(bytes),
+            Some(Err(err)) => {
+                eprintln!("{}", err);
+                return;
+            },
+            None => {
+                eprintln!("Expected -pattern, -string, -u16, -u32 or -u64.");
+                return;
+            }
+        }
+    };
+    let mut names: Vec<&String> = program.section_table.keys().collect();
+    names.sort_by_key(|name| program.section_table[*name].addr);
+    let symbols = program.symbol_index();
+    let mut count = 0;
+    for name in names {
+        let section = &program.section_table[name];
+        for offset in pattern.find_all(&section.bytes) {
+            // Sections that aren't loaded, such as .comment and .strtab, are located by file offset.
+            if section.addr == 0 && section.offset != 0 {
+                println!("{:#010x} {:<16} (file offset)", section.offset + offset as u64, name);
+                count += 1;
+                continue;
+            }
+            let addr = section.addr + offset as u64;
+            let func = match symbols.containing(addr) {
+                Some(sym) => format!("{}+{:#x}", sym.name, addr - sym.addr),
+                None => String::new(),
+            };
+            println!("{:#010x} {:<16} {}", addr, name, func);
+            count += 1;
+        }
+    }
+    println!("{} match(es)", count);
+}
+
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse::<u64>().ok(),
+    }
+}
+
+// A byte count with an optional K, M or G suffix, e.g. "512M".
+fn parse_size(s: &str) -> Option<usize> {
+    let (digits, shift) = match s.char_indices().last()? {
+        (i, 'K' | 'k') => (&s[..i], 10),
+        (i, 'M' | 'm') => (&s[..i], 20),
+        (i, 'G' | 'g') => (&s[..i], 30),
+        _ => (s, 0),
+    };
+    let n = digits.parse::<usize>().ok()?;
+    n.checked_mul(1 << shift)
+}
+
+fn parse_hex_bytes(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+// Overwrites bytes at addresses, given as hex or as instructions to assemble,
+// and writes the result to a new file along with a spec that undoes it.
+fn cmd_patch(args: ArgList) {
+    let in_file = match args.pos_args.get(0) {
+        Some(in_file) if args.named_args.contains_key("addr") || args.named_args.contains_key("spec") => in_file,
+        _ => {
+            eprintln!("Usage: baretk patch <in_file> -addr <addr> -bytes <hex>|-asm <instructions>");
+            eprintln!("       baretk patch <in_file> -spec <file>");
+            eprintln!("    -bytes <hex> bytes to write, e.g. 9090");
+            eprintln!("    -asm <instructions> x86 or RISC-V instructions separated by ';', with absolute branch targets");
+            eprintln!("    -original <hex> bytes expected at the address, checked before writing");
+            eprintln!("    -spec <file> JSON array of patches: {{\"addr\", \"original\", \"bytes\" or \"asm\", \"comment\"}}");
+            eprintln!("    -o <out_file> file to write (default <in_file>.patched)");
+            eprintln!("    -record <file> spec that undoes the patches (default <out_file>.revert.json)");
+            eprintln!("    -arch <{}> assemble for this architecture", options::ARCHITECTURES.join("|"));
+            eprintln!("    -base <addr> load address of a raw binary");
+            return;
+        }
+    };
+    let out_file = args.named_args.get("o").cloned().unwrap_or(format!("{}.patched", in_file));
+    let record_file = args.named_args.get("record").cloned().unwrap_or(format!("{}.revert.json", out_file));
+    let options = match analysis_options(&args) {
+        Ok(options) => options,
+        Err(()) => return,
+    };
+    let entries = if let Some(spec_file) = args.named_args.get("spec") {
+        let text = match util::try_read_file_contents(spec_file.as_str()) {
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            },
+            Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+        };
+        match patch::parse(&text) {
+            Ok(entries) => entries,
+            Err(err) => {
+                eprintln!("{}: {}", spec_file, err);
+                return;
+            }
+        }
+    }
+    else {
+        let addr = match args.named_args.get("addr").and_then(|s| parse_number(s)) {
+            Some(addr) => addr,
+            None => {
+                eprintln!("Invalid address \"{}\".", args.named_args["addr"]);
+                return;
+            }
+        };
+        let hex = |name: &str| args.named_args.get(name).map(|hex| patch::parse_hex(hex).ok_or(hex));
+        let original = match hex("original") {
+            Some(Err(hex)) => {
+                eprintln!("Invalid hex bytes \"{}\".", hex);
+                return;
+            },
+            original => original.map(Result::unwrap_or_default),
+        };
+        let replacement = match (hex("bytes"), args.named_args.get("asm")) {
+            (Some(Ok(bytes)), None) => patch::Replacement::Bytes(bytes),
+            (Some(Err(hex)), None) => {
+                eprintln!("Invalid hex bytes \"{}\".", hex);
+                return;
+            },
+            (None, Some(text)) => patch::Replacement::Asm(text.clone()),
+            _ => {
+                eprintln!("Expected either -bytes or -asm.");
+                return;
+            }
+        };
+        vec![patch::PatchEntry { addr, original, replacement, comment: None }]
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+    let program = match prog::load_program_with_options(&contents, &options) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    let (patched, applied) = match patch::apply(&program, &contents, &entries) {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    for patch in &applied {
+        let comment = patch.comment.as_ref().map_or(String::new(), |comment| format!(" ; {}", comment));
+        println!("{:#010x} (file offset {:#x}): {} -> {}{}", patch.addr, patch.offset, patch::hex(&patch.original), patch::hex(&patch.bytes), comment);
+    }
+    if try_write_file(&out_file, &patched) && try_write_file(&record_file, patch::revert_spec(&applied).as_bytes()) {
+        println!("Wrote {}, and {} to undo the patch(es) with -spec", out_file, record_file);
+    }
+}
+
+fn cmd_emu(args: ArgList) {
+    let (in_file, func) = match (args.pos_args.get(0), args.named_args.get("func")) {
+        (Some(in_file), Some(func)) => (in_file, func),
+        _ => {
+            eprintln!("Usage: baretk emu <in_file> -func <name|0xaddr>");
+            eprintln!("    -regs <reg=value,...> initial register values");
+            eprintln!("    -mem <0xaddr=hexbytes,...> initial memory contents");
+            eprintln!("    -steps <num> max. statements to execute (default 100000)");
+            return;
+        }
+    };
+    let contents = match util::try_read_file_contents(in_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => bytes,
+    };
+    let protos = proto::PrototypeDb::new();
+    let decomp = match dis::disassemble(&contents).and_then(|dis| decomp::decomp_function(dis, func, decomp::Language::Pseudocode, &protos)) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(decomp) => decomp,
+    };
+
+    let mut emu = emu::Emulator::new(&decomp);
+    for assignment in args.named_args.get("regs").map(|s| s.split(',').collect()).unwrap_or(Vec::new()) {
+        match assignment.split_once('=').and_then(|(reg, value)| Some((emu.parse_register(reg.trim())?, parse_number(value.trim())?))) {
+            Some((reg, value)) => emu.set_register(reg, value),
+            None => {
+                eprintln!("Can't parse register value \"{}\".", assignment);
+                return;
+            }
+        }
+    }
+    for assignment in args.named_args.get("mem").map(|s| s.split(',').collect()).unwrap_or(Vec::new()) {
+        match assignment.split_once('=').and_then(|(addr, bytes)| Some((parse_number(addr.trim())?, parse_hex_bytes(bytes.trim())?))) {
+            Some((addr, bytes)) => emu.write_memory(addr, &bytes),
+            None => {
+                eprintln!("Can't parse memory contents \"{}\".", assignment);
+                return;
+            }
+        }
+    }
+    let max_steps = match args.named_args.get("steps").map(|s| s.parse::<usize>()) {
+        None => 100000,
+        Some(Ok(n)) => n,
+        Some(Err(err)) => {
+            eprintln!("Can't convert steps to number: {}", err);
+            return;
+        }
+    };
+
+    let stop = emu.run(max_steps);
+    match stop {
+        emu::Stop::Returned => println!("Returned after {} statement(s).", emu.steps),
+        emu::Stop::StepLimit => println!("Stopped after {} statement(s): step limit reached.", emu.steps),
+        emu::Stop::LeftFunction(addr) => println!("Stopped after {} statement(s): jumped out of the function to {:#010x}.", emu.steps, addr),
+        emu::Stop::Unsupported(msg) => println!("Stopped after {} statement(s): {}", emu.steps, msg),
+    }
+    println!("Registers:");
+    for (reg, value) in emu.registers() {
+        println!("  {:<6} = {:#x}", reg, value);
+    }
+    let written = emu.written_memory();
+    if !written.is_empty() {
+        println!("Memory written:");
+        for (addr, bytes) in written {
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            let text: String = bytes.iter().map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' }).collect();
+            println!("  {:#010x}: {} |{}|", addr, hex.join(" "), text);
+        }
+    }
+    if !emu.calls.is_empty() {
+        println!("Calls:");
+        for call in &emu.calls {
+            println!("  {}", call);
+        }
+    }
+}
+
+fn cmd_help() {
+    println!("Available commands:");
+    for cmd in COMMANDS {
+        println!("    baretk {} - {}", cmd.name, cmd.desc);
+    }
+    println!("    baretk help - Prints this help.");
+    println!("Options for every command:");
+    println!("    -v, -vv more diagnostics on stderr; -q errors only");
+}
+
+struct Command {
+    name: &'static str,
+    desc: &'static str,
+    func: fn(ArgList),
 }
 
 const COMMANDS: &[Command] = &[
     Command { name: "dis", desc: "Disassembles an input binary.", func: cmd_disassemble },
     Command { name: "decomp", desc: "Decompiles an input binary.", func: cmd_decompile },
     Command { name: "dump", desc: "Dumps information from an input binary.", func: cmd_dump },
+    Command { name: "sections", desc: "Lists sections with their permissions and segments.", func: cmd_sections },
+    Command { name: "extract", desc: "Writes the contents of a section or segment to a file or as C source or hex.", func: cmd_extract },
+    Command { name: "convert", desc: "Converts between raw binary, Intel HEX and S-record images.", func: cmd_convert },
+    Command { name: "info", desc: "Prints the headers of an ELF or PE file in detail.", func: cmd_info },
+    Command { name: "cfg", desc: "Exports a control flow graph in Graphviz DOT or SVG format.", func: cmd_cfg },
+    Command { name: "stack", desc: "Reports the worst-case stack usage of each function.", func: cmd_stack },
+    Command { name: "loops", desc: "Lists the loops in each function and recursive call cycles.", func: cmd_loops },
+    Command { name: "unreachable", desc: "Lists code never reached from the entry point.", func: cmd_unreachable },
+    Command { name: "syscalls", desc: "Summarizes the system calls made by an input binary.", func: cmd_syscalls },
+    Command { name: "coverage", desc: "Reports how much executable code is reachable.", func: cmd_coverage },
+    Command { name: "verify", desc: "Reports decode coverage and errors for every binary in a directory.", func: cmd_verify },
+    Command { name: "calls", desc: "Lists every call with its target.", func: cmd_calls },
+    Command { name: "hash", desc: "Prints file and section hashes, ssdeep and the PE imphash.", func: cmd_hash },
+    Command { name: "checksec", desc: "Reports the exploit mitigations an ELF or PE file was built with.", func: cmd_checksec },
+    Command { name: "audit", desc: "Lists calls to dangerous library functions.", func: cmd_audit },
+    Command { name: "sigs", desc: "Names library functions using a signature file.", func: cmd_sigs },
+    Command { name: "makesig", desc: "Makes a signature from a function for the sigs command.", func: cmd_makesig },
+    Command { name: "db", desc: "Writes the analysis to a SQLite database for querying with SQL.", func: cmd_db },
+    Command { name: "project", desc: "Saves names, comments and data types to a project file.", func: cmd_project },
+    Command { name: "diff", desc: "Compares the functions of two builds of a program.", func: cmd_diff },
+    Command { name: "bindiff", desc: "Compares two files byte by byte.", func: cmd_bindiff },
+    Command { name: "gadgets", desc: "Lists ROP gadgets in the code section.", func: cmd_gadgets },
+    Command { name: "patch", desc: "Overwrites bytes at an address with hex bytes or assembled instructions.", func: cmd_patch },
+    Command { name: "search", desc: "Searches an input binary for a byte pattern.", func: cmd_search },
+    Command { name: "emu", desc: "Runs a function in the IR emulator.", func: cmd_emu },
+    Command { name: "xref", desc: "Lists the instructions that refer to an address.", func: cmd_xref },
+    Command { name: "refs", desc: "Lists the instructions reading and writing data addresses.", func: cmd_refs },
     Command { name: "strings", desc: "Prints strings found in an input binary.", func: cmd_strings },
+    Command { name: "symbols", desc: "Lists the symbols of an input binary.", func: cmd_symbols },
+    Command { name: "demangle", desc: "Demangles C++ and Rust symbol names.", func: cmd_demangle },
+    Command { name: "run", desc: "Runs a script of commands against an input binary.", func: cmd_run },
 ];
 
+// Runs a script of commands against one binary. Each runs in its own process
+// with its output sent to a file, so one that fails doesn't stop the rest.
+fn cmd_run(args: ArgList) {
+    let (script_file, in_file) = match (args.pos_args.get(0), args.pos_args.get(1)) {
+        (Some(script_file), Some(in_file)) => (script_file, in_file),
+        _ => {
+            eprintln!("Usage: baretk run <script> <in_file>");
+            eprintln!("    -o <dir> directory for the outputs (default baretk-out)");
+            return;
+        }
+    };
+    let out_dir = args.named_args.get("o").map(|s| s.as_str()).unwrap_or("baretk-out");
+    let text = match util::try_read_file_contents(script_file.as_str()) {
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+        Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+    };
+    let steps = match script::parse(&text, in_file) {
+        Ok(steps) => steps,
+        Err(err) => {
+            eprintln!("{}: {}", script_file, err);
+            return;
+        }
+    };
+    for (i, step) in steps.iter().enumerate() {
+        if step.command == "run" || !COMMANDS.iter().any(|cmd| cmd.name == step.command) {
+            eprintln!("{}: line {}: unknown command \"{}\"", script_file, step.line, step.command);
+            return;
+        }
+        if steps[..i].iter().any(|s| s.output == step.output) {
+            eprintln!("{}: line {}: output {} is already written by an earlier line", script_file, step.line, step.output);
+            return;
+        }
+    }
+    if util::try_read_file_contents(in_file.as_str()).is_err() {
+        return;
+    }
+    if let Err(error) = fs::create_dir_all(out_dir) {
+        eprintln!("Error creating directory {}: {}", out_dir, error);
+        return;
+    }
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(error) => {
+            eprintln!("Can't find the baretk executable: {}", error);
+            return;
+        }
+    };
+
+    let mut failed = 0;
+    for step in &steps {
+        let path = Path::new(out_dir).join(&step.output);
+        let file = match File::create(&path) {
+            Ok(file) => file,
+            Err(error) => {
+                eprintln!("Error creating file {}: {}", path.display(), error);
+                return;
+            }
+        };
+        let status = process::Command::new(&exe).arg(&step.command).args(&step.args).stdout(file).status();
+        match status {
+            Ok(status) if status.success() => println!("{:4}: {} -> {}", step.line, step.command, path.display()),
+            Ok(_) => {
+                println!("{:4}: {} failed, partial output in {}", step.line, step.command, path.display());
+                failed += 1;
+            },
+            Err(error) => {
+                println!("{:4}: {} couldn't be run: {}", step.line, step.command, error);
+                failed += 1;
+            },
+        }
+    }
+    println!("{} command(s) run, {} failed; outputs in {}", steps.len(), failed, out_dir);
+}
+
 fn main() {
     let mut args = env::args();
     args.next().expect("program");
 
+    let mut args: Vec<String> = args.collect();
+    logger::init(logger::take_level(&mut args));
+    let mut args = args.into_iter();
+
     if let Some(command) = args.next() {
         if let Some(cmd) = COMMANDS.iter().find(|cmd| cmd.name == command.as_str()) {
             (cmd.func)(parse_cmd_args(args.collect()));
@@ -0,0 +1,135 @@
+// Symbols from a GNU ld map file (-Wl,-Map=out.map), for naming the code of a
+// stripped image with the names from the build that produced it. Only the
+// "Linker script and memory map" part is read. Section lines give the address
+// and size of each input section, and the symbol lines under them name the
+// addresses defined there:
+//
+//      .text.main     0x0000000008000120       0x3c build/main.o
+//                     0x0000000008000120                main
+//
+// Symbols in code sections become functions, sized up to the next symbol in
+// the same section. The map only lists global symbols, but code built with
+// -ffunction-sections has a `.text.<name>` section per function, which names
+// static functions too. Symbols assigned by the linker script (`_estack = 0x20005000`)
+// are kept as plain symbols.
+
+use crate::error::BaretkError;
+use crate::prog::{Program, Symbol};
+use crate::util;
+
+struct InputSection {
+    name: String,
+    addr: u64,
+    size: u64,
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s.strip_prefix("0x")?, 16).ok()
+}
+
+fn is_code_section(name: &str) -> bool {
+    name == ".text" || name.starts_with(".text.") || name == ".init" || name == ".fini"
+}
+
+// The function a -ffunction-sections section holds, e.g. "foo" for ".text.unlikely.foo".
+fn function_section_name(name: &str) -> Option<&str> {
+    let mut name = name.strip_prefix(".text.")?;
+    for prefix in ["unlikely.", "startup.", "hot.", "exit."] {
+        name = name.strip_prefix(prefix).unwrap_or(name);
+    }
+    match name {
+        "unlikely" | "startup" | "hot" | "exit" | "" => None,
+        _ => Some(name),
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty() && !s.starts_with('.') && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+pub fn parse(text: &str) -> Vec<Symbol> {
+    let start = text.find("Linker script and memory map").unwrap_or(0);
+    let mut sections = Vec::<InputSection>::new();
+    // Address, name and the index of the section defining it.
+    let mut found = Vec::<(u64, String, Option<usize>)>::new();
+    let mut wrapped: Option<String> = None;
+    for line in text[start..].lines() {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let is_section_name = |s: &str| s.starts_with('.') || s == "COMMON";
+        match tokens.as_slice() {
+            [] => (),
+            // A section name too long for its column has its address and size on the next line.
+            [name] if is_section_name(name) => wrapped = Some(name.to_string()),
+            [addr, size, ..] if wrapped.is_some() => {
+                let name = wrapped.take().unwrap();
+                if let (Some(addr), Some(size)) = (parse_hex(addr), parse_hex(size)) {
+                    sections.push(InputSection { name, addr, size });
+                }
+            },
+            [name, addr, size, ..] if is_section_name(name) => {
+                if let (Some(addr), Some(size)) = (parse_hex(addr), parse_hex(size)) {
+                    sections.push(InputSection { name: name.to_string(), addr, size });
+                }
+            },
+            [addr, name] if is_identifier(name) => {
+                if let Some(addr) = parse_hex(addr) {
+                    found.push((addr, name.to_string(), sections.len().checked_sub(1)));
+                }
+            },
+            [addr, name, "=", ..] if is_identifier(name) => {
+                if let Some(addr) = parse_hex(addr) {
+                    found.push((addr, name.to_string(), None));
+                }
+            },
+            _ => wrapped = None,
+        }
+    }
+
+    let mut symbols = Vec::<Symbol>::new();
+    for (i, section) in sections.iter().enumerate() {
+        if let Some(name) = function_section_name(&section.name) {
+            if section.size != 0 && !found.iter().any(|(addr, _, index)| *addr == section.addr && *index == Some(i)) {
+                symbols.push(Symbol { name: name.to_string(), addr: section.addr, size: section.size, is_func: true });
+            }
+        }
+    }
+    for (addr, name, index) in &found {
+        let section = match index.and_then(|i| sections.get(i)) {
+            Some(section) if *addr >= section.addr && *addr <= section.addr + section.size => section,
+            _ => {
+                symbols.push(Symbol { name: name.clone(), addr: *addr, size: 0, is_func: false });
+                continue;
+            },
+        };
+        let end = found.iter()
+            .filter(|(a, _, i)| *a > *addr && i == index)
+            .map(|(a, _, _)| *a)
+            .min()
+            .unwrap_or(section.addr + section.size);
+        let is_func = is_code_section(&section.name);
+        symbols.push(Symbol { name: name.clone(), addr: *addr, size: if is_func { end - addr } else { 0 }, is_func });
+    }
+    symbols
+}
+
+pub fn load_file(path: &str) -> Result<Vec<Symbol>, BaretkError> {
+    let contents = util::try_read_file_contents(path)?;
+    let symbols = parse(&String::from_utf8_lossy(&contents));
+    if symbols.is_empty() {
+        return Err(BaretkError::Malformed { what: path.to_string(), offset: None, section: None,
+            reason: "no symbols found, expected a GNU ld map file".to_string() })
+    }
+    Ok(symbols)
+}
+
+// Adds the symbols the program doesn't have yet, returning how many were added.
+pub fn apply(program: &mut Program, symbols: Vec<Symbol>) -> usize {
+    let mut added = 0;
+    for sym in symbols {
+        if !program.symbol_table.iter().any(|s| s.name == sym.name && s.addr == sym.addr) {
+            program.symbol_table.push(sym);
+            added += 1;
+        }
+    }
+    added
+}
@@ -0,0 +1,143 @@
+// Map of which instructions read, write or take the address of which memory
+// addresses. Addresses come from absolute and pc-relative operands and from
+// registers whose values are known within a basic block (`lui`/`addi` pairs,
+// ARM literal pool loads, x86 `mov reg, offset`).
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::dis::{self, Disassembly, Instruction};
+use crate::resolve::{self, State};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+    // The address was computed into a register, e.g. to pass a pointer.
+    Address,
+}
+
+impl Access {
+    pub fn name(self) -> &'static str {
+        match self {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::ReadWrite => "rw",
+            Access::Address => "addr",
+        }
+    }
+}
+
+pub struct MemRef {
+    // Address of the accessing instruction.
+    pub from: u64,
+    pub addr: u64,
+    // Access size in bytes, 0 for address references.
+    pub size: u8,
+    pub access: Access,
+}
+
+// Maps each accessed address to the instructions accessing it.
+pub struct MemoryMap {
+    refs: BTreeMap<u64, Vec<MemRef>>,
+}
+
+fn riscv_access(opcode: &str) -> Option<(Access, u8)> {
+    match opcode {
+        "lb" | "lbu" => Some((Access::Read, 1)),
+        "lh" | "lhu" => Some((Access::Read, 2)),
+        "lw" | "lwu" => Some((Access::Read, 4)),
+        "ld" => Some((Access::Read, 8)),
+        "sb" => Some((Access::Write, 1)),
+        "sh" => Some((Access::Write, 2)),
+        "sw" => Some((Access::Write, 4)),
+        "sd" => Some((Access::Write, 8)),
+        _ => None,
+    }
+}
+
+// How the memory operand at the index is accessed. x86 instructions write
+// their first operand; lifted read-modify-write forms repeat it as the second.
+fn operand_access(ins: &Instruction, index: usize) -> Access {
+    match ins.opcode {
+        "str" => Access::Write,
+        "ldr" | "ldrs" | "cmp" | "test" | "push" | "call" | "b" => Access::Read,
+        _ if index == 0 && ins.operands.get(1) == ins.operands.first() => Access::ReadWrite,
+        _ if index == 0 => Access::Write,
+        _ => Access::Read,
+    }
+}
+
+impl MemoryMap {
+    pub fn build(dis: &Disassembly) -> MemoryMap {
+        MemoryMap::build_in(dis, 0..usize::MAX)
+    }
+
+    // Builds the map for the instructions in the section offset range.
+    pub fn build_in(dis: &Disassembly, range: Range<usize>) -> MemoryMap {
+        let program = dis.program();
+        let code = program.section_table.get(program.code_section());
+        // Sections that aren't loaded (symbol and string tables) have address 0.
+        let in_data = |addr: u64| program.section_table.values()
+            .filter(|section| section.addr != 0 && code.map_or(true, |code| !std::ptr::eq(*section, code)))
+            .any(|section| addr >= section.addr && addr < section.addr + section.bytes.len() as u64);
+        let mut map = MemoryMap { refs: BTreeMap::new() };
+        resolve::walk_in(dis, range, |state: &State, ins, addr, size| {
+            if let Some((access, access_size)) = riscv_access(ins.opcode) {
+                if let Some(target) = state.load_address(ins, addr, size) {
+                    map.add(target, MemRef { from: addr, addr: target, size: access_size, access });
+                }
+            }
+            for (i, op) in ins.operands.iter().enumerate() {
+                // A repeated read-modify-write operand is only recorded once.
+                if i == 1 && ins.operands.first() == Some(op) {
+                    continue;
+                }
+                if let dis::Operand::Memory(_, _, _, _, mem_size) = *op {
+                    if let Some(target) = state.address(op, addr, size) {
+                        map.add(target, MemRef { from: addr, addr: target, size: mem_size, access: operand_access(ins, i) });
+                    }
+                }
+            }
+            // A pointer into data built in a register, or loaded from a literal pool or GOT.
+            // Register to register copies aren't new references.
+            let copy = ins.opcode == "mov" && matches!(ins.operands.get(1), Some(dis::Operand::Register(_)));
+            if matches!(ins.opcode, "mov" | "add" | "ldr" | "ld" | "lw") && !copy && ins.cond() == dis::COND_AL {
+                if let Some(value) = state.result(ins, addr, size).filter(|v| in_data(*v)) {
+                    map.add(value, MemRef { from: addr, addr: value, size: 0, access: Access::Address });
+                }
+            }
+        });
+        map
+    }
+
+    fn add(&mut self, to: u64, r: MemRef) {
+        self.refs.entry(to).or_default().push(r);
+    }
+
+    // Instructions accessing exactly the address, in address order.
+    pub fn refs_to(&self, addr: u64) -> &[MemRef] {
+        match self.refs.get(&addr) {
+            Some(refs) => refs.as_slice(),
+            None => &[],
+        }
+    }
+
+    // Every accessed address in the range, with its accesses.
+    pub fn refs_in(&self, range: Range<u64>) -> impl Iterator<Item = (&u64, &Vec<MemRef>)> {
+        self.refs.range(range)
+    }
+
+    // Every access overlapping the range, e.g. all accessors of a global or any of its fields.
+    pub fn accessors(&self, range: Range<u64>) -> Vec<&MemRef> {
+        // Accesses starting up to 8 bytes below can still overlap the range.
+        let start = range.start.saturating_sub(8);
+        let mut out: Vec<&MemRef> = self.refs.range(start..range.end)
+            .flat_map(|(_, refs)| refs.iter())
+            .filter(|r| r.addr + (r.size.max(1) as u64) > range.start)
+            .collect();
+        out.sort_by_key(|r| (r.from, r.addr));
+        out
+    }
+}
@@ -0,0 +1,69 @@
+// Settings for loading and analysing a program, taken by the entry points
+// instead of being hard-coded. The defaults give baretk's usual output.
+
+use crate::cancel::CancelToken;
+
+// Machine types the disassembler decodes, as an architecture override names them.
+pub const ARCHITECTURES: &[&str] = &["x86", "amd64", "arm", "aarch64", "riscv"];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Syntax {
+    // Each architecture's own syntax, Intel for x86.
+    Native,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Sweep {
+    // Decode the code section from start to end.
+    Linear,
+    // Decode only what's reachable from the entry point and function symbols.
+    Recursive,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ParseMode {
+    // Clamp sizes and skip bad entries, keeping a warning on the program for each.
+    Permissive,
+    // Reject the file at the first malformed structure.
+    Strict,
+}
+
+#[derive(Clone)]
+pub struct AnalysisOptions {
+    // Shortest run of characters reported as a string.
+    pub min_string_len: usize,
+    pub syntax: Syntax,
+    // Show each instruction's bytes in listings.
+    pub show_bytes: bool,
+    pub sweep: Sweep,
+    // Machine type to decode as instead of the one the headers give.
+    pub arch: Option<String>,
+    // Load address of raw binaries, which have no headers to give one.
+    pub base_addr: Option<u64>,
+    // ARM sections to decode as Thumb where the symbols don't say; "file" for
+    // raw binaries.
+    pub thumb_sections: Vec<String>,
+    pub parse_mode: ParseMode,
+    // Bytes of memory to stay within, for images too big to decode in memory
+    // at once. None for no limit.
+    pub memory_budget: Option<usize>,
+    // Checked while disassembling; a clone cancels from another thread.
+    pub cancel: CancelToken,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        AnalysisOptions {
+            min_string_len: 4,
+            syntax: Syntax::Native,
+            show_bytes: true,
+            sweep: Sweep::Linear,
+            arch: None,
+            base_addr: None,
+            thumb_sections: Vec::new(),
+            parse_mode: ParseMode::Permissive,
+            memory_budget: None,
+            cancel: CancelToken::new(),
+        }
+    }
+}
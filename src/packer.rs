@@ -0,0 +1,117 @@
+// Heuristics for spotting packed or obfuscated binaries, which have to be
+// unpacked before disassembly or decompilation tell you anything useful.
+
+use crate::prog::Program;
+use crate::util::{RWX_EXEC, RWX_WRITE};
+
+// Sections above this entropy (in bits per byte) are likely compressed or encrypted.
+const HIGH_ENTROPY: f64 = 7.2;
+// Sections smaller than this are too short for their entropy to mean much.
+const MIN_ENTROPY_SIZE: usize = 256;
+
+const PACKER_SECTIONS: &[(&str, &str)] = &[
+    ("UPX0", "UPX"),
+    ("UPX1", "UPX"),
+    ("UPX2", "UPX"),
+    (".UPX", "UPX"),
+    (".aspack", "ASPack"),
+    (".adata", "ASPack"),
+    (".petite", "Petite"),
+    (".nsp0", "NsPack"),
+    (".nsp1", "NsPack"),
+    (".MPRESS1", "MPRESS"),
+    (".MPRESS2", "MPRESS"),
+    ("PEC2", "PECompact"),
+    ("pec1", "PECompact"),
+    (".themida", "Themida"),
+    (".winlice", "WinLicense"),
+    (".vmp0", "VMProtect"),
+    (".vmp1", "VMProtect"),
+    (".enigma1", "Enigma"),
+    (".enigma2", "Enigma"),
+];
+
+pub struct PackerReport {
+    pub findings: Vec<String>,
+    // Findings that on their own are strong evidence of packing.
+    pub strong: usize,
+}
+
+impl PackerReport {
+    pub fn is_suspicious(&self) -> bool {
+        self.strong > 0 || self.findings.len() >= 2
+    }
+}
+
+// Shannon entropy in bits per byte, from 0.0 (constant) to 8.0 (random).
+pub fn entropy(bytes: &[u8]) -> f64 {
+    if bytes.is_empty() {
+        return 0.0
+    }
+    let mut counts = [0usize; 256];
+    for b in bytes {
+        counts[*b as usize] += 1;
+    }
+    let len = bytes.len() as f64;
+    counts.iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+// Number of imported symbols, if the format tells us. ELF imports are the
+// undefined dynamic symbols; for PE only the presence of .idata is known.
+fn import_count(program: &Program) -> Option<usize> {
+    match program.format {
+        "elf" if program.section_table.contains_key(".dynsym") =>
+            Some(program.symbol_table.iter().filter(|sym| sym.addr == 0 && !sym.name.is_empty()).count()),
+        "pe" => program.section_table.get(".idata").map(|idata| idata.bytes.len() / 20),
+        _ => None,
+    }
+}
+
+pub fn analyze(program: &Program) -> PackerReport {
+    let mut findings = Vec::<String>::new();
+    let mut strong = 0;
+
+    let mut names: Vec<&String> = program.section_table.keys().collect();
+    names.sort();
+    for name in &names {
+        if let Some((_, packer)) = PACKER_SECTIONS.iter().find(|(section, _)| section.eq_ignore_ascii_case(name)) {
+            findings.push(format!("section {} is used by {}", name, packer));
+            strong += 1;
+        }
+    }
+    for name in &names {
+        let bytes = &program.section_table[*name].bytes;
+        if bytes.len() < MIN_ENTROPY_SIZE {
+            continue;
+        }
+        let e = entropy(bytes);
+        if e >= HIGH_ENTROPY {
+            findings.push(format!("section {} has high entropy ({:.2} bits/byte), likely compressed or encrypted", name, e));
+        }
+    }
+
+    if program.format == "elf" && program.section_table.is_empty() && !program.program_table.is_empty() {
+        findings.push("no section headers, only segments".to_string());
+    }
+
+    let wx = program.program_table.iter()
+        .filter(|seg| seg.perm & (RWX_WRITE | RWX_EXEC) == (RWX_WRITE | RWX_EXEC))
+        .count();
+    if wx > 0 {
+        findings.push(format!("{} writable and executable segment(s)", wx));
+    }
+
+    match import_count(program) {
+        Some(0) if program.format == "pe" => findings.push("empty import table".to_string()),
+        Some(n) if n > 0 && n <= 3 => findings.push(format!("only {} imported symbol(s)", n)),
+        _ => (),
+    }
+
+    PackerReport { findings, strong }
+}
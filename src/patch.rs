@@ -0,0 +1,144 @@
+// Patches to a binary, each with the bytes it expects to replace so nothing is
+// written over a build it wasn't made for. Spec files are a JSON array:
+//
+//     [
+//       {"addr": "0x1040", "original": "31 ed 49", "bytes": "eb fe 90", "comment": "spin at the entry point"},
+//       {"addr": "0x1130", "original": "74 05", "asm": "jmp 0x1137"}
+//     ]
+//
+// Addresses are the ones the disassembler shows. Bytes are hex, with or
+// without spaces, and "asm" is assembled at the address instead. The record of
+// applied patches is a spec in the same form, which turns the patched binary
+// back into the original.
+
+use crate::asm;
+use crate::error::BaretkError;
+use crate::json::{self, Value};
+use crate::prog::Program;
+use crate::reg::Arch;
+
+pub enum Replacement {
+    Bytes(Vec<u8>),
+    Asm(String),
+}
+
+pub struct PatchEntry {
+    pub addr: u64,
+    // The bytes expected at the address, or None to write without checking.
+    // Everything written has to be checked, so the new bytes can't be longer.
+    pub original: Option<Vec<u8>>,
+    pub replacement: Replacement,
+    pub comment: Option<String>,
+}
+
+// A patch as made, with the bytes it replaced.
+pub struct AppliedPatch {
+    pub addr: u64,
+    pub offset: usize,
+    pub original: Vec<u8>,
+    pub bytes: Vec<u8>,
+    pub comment: Option<String>,
+}
+
+pub fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s.bytes().filter(|c| !c.is_ascii_whitespace()).collect();
+    if digits.len() % 2 != 0 {
+        return None
+    }
+    digits.chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()).collect()
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join(" ")
+}
+
+// Parses a spec. Errors give the byte offset or the index of a bad entry.
+pub fn parse(text: &str) -> Result<Vec<PatchEntry>, String> {
+    let value = json::parse(text).map_err(|offset| format!("invalid JSON at byte {}", offset))?;
+    let entries = value.as_array().ok_or("expected an array of patches".to_string())?;
+    let mut out = Vec::<PatchEntry>::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let field = |key: &str| entry.get(key).and_then(|value| value.as_str());
+        let addr = entry.get("addr").and_then(|addr| addr.as_u64()).ok_or(format!("patch {} has no valid \"addr\"", i))?;
+        let original = field("original").and_then(parse_hex).ok_or(format!("patch {} has no valid \"original\" bytes", i))?;
+        let replacement = match (field("bytes"), field("asm")) {
+            (Some(bytes), None) => Replacement::Bytes(parse_hex(bytes).ok_or(format!("patch {} has invalid \"bytes\"", i))?),
+            (None, Some(text)) => Replacement::Asm(text.to_string()),
+            _ => return Err(format!("patch {} needs either \"bytes\" or \"asm\"", i)),
+        };
+        out.push(PatchEntry { addr, original: Some(original), replacement, comment: field("comment").map(str::to_string) });
+    }
+    Ok(out)
+}
+
+// The file offset of `len` bytes at an address, which have to be in one
+// section loaded from the file.
+fn locate(program: &Program, addr: u64, len: usize) -> Result<usize, BaretkError> {
+    let offset = program.file_offset(addr)
+        .ok_or_else(|| BaretkError::InvalidArgument(format!("Address {:#x} isn't in a section loaded from the file.", addr)))?;
+    let last = addr.checked_add(len as u64 - 1).and_then(|last| program.file_offset(last));
+    if last != Some(offset + len as u64 - 1) {
+        return Err(BaretkError::InvalidArgument(format!("The {} byte(s) at {:#x} run past the end of the section.", len, addr)))
+    }
+    Ok(offset as usize)
+}
+
+// Makes the patches to a copy of `contents`, the file `program` was loaded
+// from. Every patch is checked before any is made, and none may overlap.
+pub fn apply(program: &Program, contents: &[u8], entries: &[PatchEntry]) -> Result<(Vec<u8>, Vec<AppliedPatch>), BaretkError> {
+    let mut applied = Vec::<AppliedPatch>::new();
+    for entry in entries {
+        let bytes = match &entry.replacement {
+            Replacement::Bytes(bytes) => bytes.clone(),
+            Replacement::Asm(text) => {
+                let arch = Arch::from_machine_type(&program.machine_type)
+                    .ok_or_else(|| BaretkError::InvalidArgument(format!("Can't assemble for architecture {}.", program.machine_type)))?;
+                asm::assemble(arch, program.bits, text, entry.addr)?
+            },
+        };
+        if bytes.is_empty() {
+            return Err(BaretkError::InvalidArgument(format!("The patch at {:#x} has nothing to write.", entry.addr)))
+        }
+        let checked = entry.original.as_ref().map_or(0, |original| original.len());
+        if entry.original.is_some() && bytes.len() > checked {
+            return Err(BaretkError::InvalidArgument(format!("The patch at {:#x} writes {} byte(s) but only {} original byte(s) are given.", entry.addr, bytes.len(), checked)))
+        }
+        let offset = locate(program, entry.addr, bytes.len().max(checked))?;
+        if let Some(original) = &entry.original {
+            let found = &contents[offset..offset + original.len()];
+            if found != original.as_slice() {
+                return Err(BaretkError::InvalidArgument(format!("The bytes at {:#x} are {}, not {}; the patch is for a different build.", entry.addr, hex(found), hex(original))))
+            }
+        }
+        let range = offset..offset + bytes.len();
+        if let Some(other) = applied.iter().find(|other| range.start < other.offset + other.bytes.len() && other.offset < range.end) {
+            return Err(BaretkError::InvalidArgument(format!("The patches at {:#x} and {:#x} overlap.", other.addr, entry.addr)))
+        }
+        applied.push(AppliedPatch { addr: entry.addr, offset, original: contents[range].to_vec(), bytes, comment: entry.comment.clone() });
+    }
+    let mut patched = contents.to_vec();
+    for patch in &applied {
+        patched[patch.offset..patch.offset + patch.bytes.len()].copy_from_slice(&patch.bytes);
+    }
+    Ok((patched, applied))
+}
+
+// A spec that undoes the patches: it expects the new bytes and puts the
+// original ones back.
+pub fn revert_spec(applied: &[AppliedPatch]) -> String {
+    let mut out = String::from("[");
+    for (i, patch) in applied.iter().enumerate() {
+        out += if i == 0 { "\n  " } else { ",\n  " };
+        let mut members = vec![
+            ("addr".to_string(), Value::hex(patch.addr)),
+            ("original".to_string(), Value::String(hex(&patch.bytes))),
+            ("bytes".to_string(), Value::String(hex(&patch.original))),
+        ];
+        if let Some(comment) = &patch.comment {
+            members.push(("comment".to_string(), Value::String(comment.clone())));
+        }
+        Value::Object(members).write(&mut out);
+    }
+    out += if applied.is_empty() { "]\n" } else { "\n]\n" };
+    out
+}
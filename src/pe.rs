@@ -1,10 +1,13 @@
 use core::str;
 use std::collections::HashMap;
+use std::sync::Arc;
 
-use crate::prog::{Program, Section, Segment};
-use crate::util::{read_u16_from_slice, read_u32_from_slice, LITTLE_ENDIAN, RWX_EXEC, RWX_WRITE, RWX_READ};
+use crate::error::BaretkError;
+use crate::prog::{self, Diagnostics, Import, Program, Section, Segment};
+use crate::util::{read_checked, read_u16_checked, read_u32_checked, read_u64_checked, LITTLE_ENDIAN, RWX_EXEC, RWX_WRITE, RWX_READ};
+use crate::validate::{check, Finding};
 
-const PE_OFFSET_OFFSET: usize = 0x3c;
+pub const PE_OFFSET_OFFSET: usize = 0x3c;
 
 pub fn check_is_pe_executable(bytes: &[u8]) -> bool {
     // DOS header
@@ -32,9 +35,10 @@ impl MachineType {
     const RISCV64: MachineType = MachineType(0x5064);
     const I386: MachineType = MachineType(0x14c); // i386 (x86 32-bit)
     const AMD64: MachineType = MachineType(0x8664); // (x86-64)
+    const ARM64: MachineType = MachineType(0xaa64);
 }
 
-fn get_machine_type_string(machine: u16) -> &'static str {
+pub fn get_machine_type_string(machine: u16) -> &'static str {
     let m = MachineType(machine);
     match m {
         MachineType::UNKNOWN => "unknown",
@@ -42,13 +46,14 @@ fn get_machine_type_string(machine: u16) -> &'static str {
         MachineType::RISCV64 => "riscv64",
         MachineType::I386 => "x86",
         MachineType::AMD64 => "amd64",
+        MachineType::ARM64 => "aarch64",
         _ => "?",
     }
 }
 
-const IMAGE_SCN_MEM_EXECUTE: u32 = 0x20000000;
-const IMAGE_SCN_MEM_READ: u32 = 0x40000000;
-const IMAGE_SCN_MEM_WRITE: u32 = 0x80000000;
+pub const IMAGE_SCN_MEM_EXECUTE: u32 = 0x20000000;
+pub const IMAGE_SCN_MEM_READ: u32 = 0x40000000;
+pub const IMAGE_SCN_MEM_WRITE: u32 = 0x80000000;
 
 fn get_rwx_perm(flags: u32) -> u8 {
     let mut out = 0u8;
@@ -64,7 +69,7 @@ fn get_rwx_perm(flags: u32) -> u8 {
     out
 }
 
-fn get_name_from_section_header(hdr: &SectionHeader) -> String {
+pub fn get_name_from_section_header(hdr: &SectionHeader) -> String {
     let mut s = String::new();
     for c in hdr.name {
         if c.is_ascii() && c != 0 {
@@ -107,25 +112,26 @@ fn characteristics_string(c: u16) -> String {
 }
 
 #[derive(Debug)]
-struct CoffHeader {
-    machine: u16,
-    num_sections: u16,
-    timestamp: u32,
+pub struct CoffHeader {
+    pub machine: u16,
+    pub num_sections: u16,
+    pub timestamp: u32,
     // depracated_symbol_table_ptr: u32,  // We don't need this.
     // depracated_number_of_symbols: u32, // or this.
-    optional_header_size: u16,
-    characteristics: u16,
+    pub optional_header_size: u16,
+    pub characteristics: u16,
 }
 
-struct OptionalHeader {
-    magic: u16,
-    major_link_ver: u8,
-    minor_link_ver: u8,
-    code_size: u32,
-    data_size: u32,
-    bss_size: u32,
-    entry_point: u32,
-    base_addr: u32,
+pub struct OptionalHeader {
+    pub magic: u16,
+    pub major_link_ver: u8,
+    pub minor_link_ver: u8,
+    pub code_size: u32,
+    pub data_size: u32,
+    pub bss_size: u32,
+    pub entry_point: u32,
+    pub base_addr: u32,
+    pub section_alignment: u32,
 }
 
 struct WinHeader {
@@ -133,73 +139,97 @@ struct WinHeader {
     file_alignment: u32,
 }
 
-struct SectionHeader {
-    name: [u8; 8],
-    virtual_size: u32,
-    virtual_addr: u32,
-    data_size: u32,
-    data_ptr: u32,
-    reloc_ptr: u32,
-    _line_num_ptr: u32,
-    _reloc_count: u16,
-    _line_num_count: u16,
-    characteristics: u32,
+pub struct SectionHeader {
+    pub name: [u8; 8],
+    pub virtual_size: u32,
+    pub virtual_addr: u32,
+    pub data_size: u32,
+    pub data_ptr: u32,
+    pub reloc_ptr: u32,
+    pub _line_num_ptr: u32,
+    pub _reloc_count: u16,
+    pub _line_num_count: u16,
+    pub characteristics: u32,
 }
 
-fn read_coff_header(bytes: &[u8], offset: usize) -> CoffHeader {
-    CoffHeader {
-        machine: read_u16_from_slice(bytes, offset+0x4, LITTLE_ENDIAN),
-        num_sections: read_u16_from_slice(bytes, offset+0x6, LITTLE_ENDIAN),
-        timestamp: read_u32_from_slice(bytes, offset+0x8, LITTLE_ENDIAN),
-        optional_header_size: read_u16_from_slice(bytes, offset+0x14, LITTLE_ENDIAN),
-        characteristics: read_u16_from_slice(bytes, offset+0x16, LITTLE_ENDIAN),
-    }
+// The signature and COFF file header at the offset the DOS header gives.
+pub fn read_coff_header(bytes: &[u8], offset: usize) -> Result<CoffHeader, BaretkError> {
+    let bytes = read_checked(bytes, offset, 0x18, "PE file header")?;
+    let u16_at = |at| read_u16_checked(bytes, at, LITTLE_ENDIAN, "PE file header");
+    let u32_at = |at| read_u32_checked(bytes, at, LITTLE_ENDIAN, "PE file header");
+    Ok(CoffHeader {
+        machine: u16_at(0x4)?,
+        num_sections: u16_at(0x6)?,
+        timestamp: u32_at(0x8)?,
+        optional_header_size: u16_at(0x14)?,
+        characteristics: u16_at(0x16)?,
+    })
 }
 
-fn read_optional_header(bytes: &[u8], offset: usize) -> OptionalHeader {
-    OptionalHeader {
-        magic: read_u16_from_slice(bytes, offset, LITTLE_ENDIAN),
-        major_link_ver: bytes[offset+0x2],
-        minor_link_ver: bytes[offset+0x3],
-        code_size: read_u32_from_slice(bytes, offset+0x4, LITTLE_ENDIAN),
-        data_size: read_u32_from_slice(bytes, offset+0x8, LITTLE_ENDIAN),
-        bss_size: read_u32_from_slice(bytes, offset+0xc, LITTLE_ENDIAN),
-        entry_point: read_u32_from_slice(bytes, offset+0x10, LITTLE_ENDIAN),
-        base_addr: read_u32_from_slice(bytes, offset+0x14, LITTLE_ENDIAN),
-    }
+pub fn read_optional_header(bytes: &[u8], offset: usize) -> Result<OptionalHeader, BaretkError> {
+    let bytes = read_checked(bytes, offset, 0x24, "PE optional header")?;
+    let u16_at = |at| read_u16_checked(bytes, at, LITTLE_ENDIAN, "PE optional header");
+    let u32_at = |at| read_u32_checked(bytes, at, LITTLE_ENDIAN, "PE optional header");
+    Ok(OptionalHeader {
+        magic: u16_at(0x0)?,
+        major_link_ver: bytes[0x2],
+        minor_link_ver: bytes[0x3],
+        code_size: u32_at(0x4)?,
+        data_size: u32_at(0x8)?,
+        bss_size: u32_at(0xc)?,
+        entry_point: u32_at(0x10)?,
+        base_addr: u32_at(0x14)?,
+        section_alignment: u32_at(0x20)?,
+    })
 }
 
-fn read_windows_header_32p(bytes: &[u8], offset: usize) -> WinHeader {
-    WinHeader {
-        section_alignment: read_u32_from_slice(bytes, offset+0x4, LITTLE_ENDIAN),
-        file_alignment: read_u32_from_slice(bytes, offset+0x8, LITTLE_ENDIAN),
-    }
+fn read_windows_header_32p(bytes: &[u8], offset: usize) -> Result<WinHeader, BaretkError> {
+    Ok(WinHeader {
+        section_alignment: read_u32_checked(bytes, offset+0x4, LITTLE_ENDIAN, "PE optional header")?,
+        file_alignment: read_u32_checked(bytes, offset+0x8, LITTLE_ENDIAN, "PE optional header")?,
+    })
+}
+
+pub fn read_section_header_32(bytes: &[u8], offset: usize) -> Result<SectionHeader, BaretkError> {
+    let bytes = read_checked(bytes, offset, 40, "PE section headers")?;
+    let u16_at = |at| read_u16_checked(bytes, at, LITTLE_ENDIAN, "PE section headers");
+    let u32_at = |at| read_u32_checked(bytes, at, LITTLE_ENDIAN, "PE section headers");
+    Ok(SectionHeader {
+        name: bytes[..8].try_into().expect("Bad array slice"),
+        virtual_size: u32_at(0x8)?,
+        virtual_addr: u32_at(0xc)?,
+        data_size: u32_at(0x10)?,
+        data_ptr: u32_at(0x14)?,
+        reloc_ptr: u32_at(0x18)?,
+        _line_num_ptr: u32_at(0x1c)?,
+        _reloc_count: u16_at(0x20)?,
+        _line_num_count: u16_at(0x22)?,
+        characteristics: u32_at(0x24)?,
+    })
 }
 
-fn read_section_header_32(bytes: &[u8], offset: usize) -> SectionHeader {
-    SectionHeader {
-        name: bytes[offset..offset+8].try_into().expect("Bad array slice"),
-        virtual_size: read_u32_from_slice(bytes, offset+0x8, LITTLE_ENDIAN),
-        virtual_addr: read_u32_from_slice(bytes, offset+0xc, LITTLE_ENDIAN),
-        data_size: read_u32_from_slice(bytes, offset+0x10, LITTLE_ENDIAN),
-        data_ptr: read_u32_from_slice(bytes, offset+0x14, LITTLE_ENDIAN),
-        reloc_ptr: read_u32_from_slice(bytes, offset+0x18, LITTLE_ENDIAN),
-        _line_num_ptr: read_u32_from_slice(bytes, offset+0x1c, LITTLE_ENDIAN),
-        _reloc_count: read_u16_from_slice(bytes, offset+0x20, LITTLE_ENDIAN),
-        _line_num_count: read_u16_from_slice(bytes, offset+0x22, LITTLE_ENDIAN),
-        characteristics: read_u32_from_slice(bytes, offset+0x24, LITTLE_ENDIAN),
+// Alignment from the IMAGE_SCN_ALIGN_* field, or 0 when the section doesn't set one.
+pub fn section_alignment(characteristics: u32) -> u32 {
+    match (characteristics >> 20) & 0xf {
+        0 => 0,
+        n => 1 << (n - 1),
     }
 }
 
-fn build_section_table(bytes: &[u8], _coff_header: &CoffHeader, section_headers: &HashMap<String, SectionHeader>) -> HashMap<String, Section> {
+// Images align every section to the optional header's section alignment;
+// object files give each its own.
+fn build_section_table(bytes: &Arc<[u8]>, _coff_header: &CoffHeader, section_headers: &HashMap<String, SectionHeader>, image_alignment: u32, diagnostics: &mut Diagnostics) -> Result<HashMap<String, Section>, BaretkError> {
     let mut hashmap = HashMap::<String, Section>::new();
     for (k, v) in section_headers {
         hashmap.insert(k.to_string(), Section {
             addr: v.data_ptr as u64,
-            bytes: bytes[v.data_ptr as usize..(v.data_ptr as usize + v.data_size as usize)].to_vec()
+            bytes: prog::section_bytes(bytes, k, v.data_ptr as u64, v.data_size as u64, diagnostics)?,
+            offset: v.data_ptr as u64,
+            perm: get_rwx_perm(v.characteristics),
+            align: match section_alignment(v.characteristics) { 0 => image_alignment, align => align } as u64,
         });
     }
-    hashmap
+    Ok(hashmap)
 }
 
 fn build_program_table(_bytes: &[u8], _coff_header: &CoffHeader, section_headers: &HashMap<String, SectionHeader>) -> Vec<Segment> {
@@ -213,33 +243,262 @@ fn build_program_table(_bytes: &[u8], _coff_header: &CoffHeader, section_headers
             size: entry.data_size as usize,
         });
     }
+    // In address order, so segment numbers are the same from run to run.
+    v.sort_by_key(|seg| seg.vaddr);
     v
 }
 
-fn build_program(bytes: &[u8], coff_header: &CoffHeader, opt_header: Option<OptionalHeader>, section_headers: &HashMap<String, SectionHeader>) -> Program {
-    Program {
+// File offset of a relative virtual address, if a section holds it.
+pub fn rva_to_offset(rva: u32, section_headers: &HashMap<String, SectionHeader>) -> Option<usize> {
+    section_headers.values()
+        .find(|hdr| rva >= hdr.virtual_addr && (rva as u64) < hdr.virtual_addr as u64 + hdr.virtual_size.max(hdr.data_size) as u64)
+        .map(|hdr| ((rva - hdr.virtual_addr) as u64 + hdr.data_ptr as u64) as usize)
+}
+
+fn c_string_at(bytes: &[u8], offset: usize) -> String {
+    let tail = bytes.get(offset..).unwrap_or(&[]);
+    let end = tail.iter().position(|b| *b == 0).unwrap_or(tail.len());
+    String::from_utf8_lossy(&tail[..end]).to_string()
+}
+
+// Walks the import directory: one descriptor per DLL, each with a table of
+// thunks naming the functions (or ordinals) imported into its IAT slots.
+fn read_import_table(bytes: &[u8], opt_offset: usize, opt: &OptionalHeader, section_headers: &HashMap<String, SectionHeader>, diagnostics: &mut Diagnostics) -> Result<Vec<Import>, BaretkError> {
+    let mut imports = Vec::<Import>::new();
+    let is_64 = opt.magic == 0x20b;
+    // The import directory is the second data directory entry.
+    let import_dir = opt_offset + if is_64 { 0x78 } else { 0x68 };
+    if bytes.len() < import_dir + 8 {
+        return Ok(imports)
+    }
+    let image_base = if is_64 {
+        read_u64_checked(bytes, opt_offset + 0x18, LITTLE_ENDIAN, "PE optional header")?
+    } else {
+        u64::from(read_u32_checked(bytes, opt_offset + 0x1c, LITTLE_ENDIAN, "PE optional header")?)
+    };
+    let thunk_size = if is_64 { 8 } else { 4 };
+    let directory_rva = read_u32_checked(bytes, import_dir, LITTLE_ENDIAN, "PE optional header")?;
+    let mut desc = match rva_to_offset(directory_rva, section_headers) {
+        Some(offset) => offset,
+        None if directory_rva == 0 => return Ok(imports),
+        None => {
+            diagnostics.tolerate(BaretkError::malformed("PE import directory", import_dir as u64,
+                format!("RVA {:#x} isn't in any section", directory_rva)))?;
+            return Ok(imports)
+        },
+    };
+    while desc + 20 <= bytes.len() {
+        let lookup_rva = read_u32_checked(bytes, desc, LITTLE_ENDIAN, "PE import directory")?;
+        let name_rva = read_u32_checked(bytes, desc + 12, LITTLE_ENDIAN, "PE import directory")?;
+        let iat_rva = read_u32_checked(bytes, desc + 16, LITTLE_ENDIAN, "PE import directory")?;
+        if name_rva == 0 && iat_rva == 0 {
+            break;
+        }
+        desc += 20;
+        let library = rva_to_offset(name_rva, section_headers).map(|offset| c_string_at(bytes, offset)).unwrap_or_default();
+        // Bound imports overwrite the IAT, so names come from the lookup table when there is one.
+        let thunks_rva = if lookup_rva != 0 { lookup_rva } else { iat_rva };
+        let thunks = match rva_to_offset(thunks_rva, section_headers) {
+            Some(offset) => offset,
+            None => {
+                diagnostics.tolerate(BaretkError::malformed("PE import directory", (desc - 20) as u64,
+                    format!("the thunks of {} at RVA {:#x} aren't in any section", library, thunks_rva)))?;
+                continue;
+            },
+        };
+        for i in 0.. {
+            let at = thunks + i * thunk_size;
+            if at + thunk_size > bytes.len() {
+                break;
+            }
+            let (thunk, by_ordinal) = if is_64 {
+                let t = read_u64_checked(bytes, at, LITTLE_ENDIAN, "PE import thunks")?;
+                (t & 0x7fffffff, t & (1 << 63) != 0)
+            } else {
+                let t = u64::from(read_u32_checked(bytes, at, LITTLE_ENDIAN, "PE import thunks")?);
+                (t & 0x7fffffff, t & (1 << 31) != 0)
+            };
+            if thunk == 0 && !by_ordinal {
+                break;
+            }
+            let name = if by_ordinal {
+                format!("#{}", thunk & 0xffff)
+            } else {
+                rva_to_offset(thunk as u32, section_headers).map(|offset| c_string_at(bytes, offset + 2)).unwrap_or_default()
+            };
+            imports.push(Import {
+                library: library.clone(),
+                name,
+                slot: image_base.wrapping_add(iat_rva as u64 + (i * thunk_size) as u64),
+                stub: None,
+            });
+        }
+    }
+    Ok(imports)
+}
+
+fn build_program(bytes: &Arc<[u8]>, coff_header: &CoffHeader, opt_header: Option<OptionalHeader>, section_headers: &HashMap<String, SectionHeader>, import_table: Vec<Import>, diagnostics: &mut Diagnostics) -> Result<Program, BaretkError> {
+    Ok(Program {
+        format: "pe",
         bits: if let Some(opt) = &opt_header { match opt.magic { 0x10b => 32, 0x20b => 64, _ => 32} } else { 32 },
         endianess: LITTLE_ENDIAN,
         machine_type: get_machine_type_string(coff_header.machine).to_string(),
         entry_point: if let Some(opt) = &opt_header { opt.entry_point as u64 } else { 0 },
         program_table: build_program_table(bytes, coff_header, section_headers),
-        section_table: build_section_table(bytes, coff_header, section_headers)
+        section_table: build_section_table(bytes, coff_header, section_headers, opt_header.as_ref().map_or(0, |opt| opt.section_alignment), diagnostics)?,
+        symbol_table: Vec::new(),
+        import_table,
+        comments: HashMap::new(),
+        data_types: HashMap::new(),
+        warnings: Vec::new(),
+        thumb_switches: Vec::new(),
+        thumb_sections: Vec::new(),
+        prior_xrefs: None,
+    })
+}
+
+// Machine type and bits from the COFF header and optional header magic,
+// without reading the section table.
+pub fn identify(bytes: &[u8]) -> (&'static str, u8, u8) {
+    let headers = read_u32_checked(bytes, PE_OFFSET_OFFSET, LITTLE_ENDIAN, "DOS header").and_then(|offset| {
+        let offset = offset as usize;
+        Ok((read_u16_checked(bytes, offset + 0x4, LITTLE_ENDIAN, "PE file header")?, read_u16_checked(bytes, offset + 0x18, LITTLE_ENDIAN, "PE optional header")?))
+    });
+    let (machine, magic) = match headers {
+        Ok(headers) => headers,
+        Err(_) => return ("unknown", 0, LITTLE_ENDIAN),
+    };
+    let bits = match magic { 0x20b => 64, _ => 32 };
+    (get_machine_type_string(machine), bits, LITTLE_ENDIAN)
+}
+
+pub fn read_section_table(bytes: &[u8], offset: usize, coff_header: &CoffHeader, diagnostics: &mut Diagnostics) -> Result<HashMap<String, SectionHeader>, BaretkError> {
+    let toffset = coff_header.optional_header_size as usize + offset + 0x18;
+    let mut section_table = HashMap::<String, SectionHeader>::new();
+    for i in 0..coff_header.num_sections {
+        // A permissive load keeps the sections before the end of the file.
+        let section_header = match read_section_header_32(bytes, toffset+(i as usize * 40)) {
+            Ok(section_header) => section_header,
+            Err(err) => {
+                diagnostics.tolerate(err)?;
+                break;
+            },
+        };
+        let section_name = get_name_from_section_header(&section_header);
+        section_table.insert(section_name.to_string(), section_header);
     }
+    Ok(section_table)
 }
 
-pub fn load_program_from_bytes(bytes: &[u8]) -> Program {
-    let b: &[u8; 4] = (&bytes[PE_OFFSET_OFFSET..PE_OFFSET_OFFSET + 4]).try_into().unwrap();
-    let offset = u32::from_le_bytes(*b) as usize;
-    let coff_header = read_coff_header(bytes, offset);
-    println!("{} machine ({}), {} section(s)", get_machine_type_string(coff_header.machine), characteristics_string(coff_header.characteristics),
+// The optional header checksum the way the loader and imagehlp compute it:
+// the file's 16-bit words summed with end-around carry, skipping the checksum
+// field, plus the file size.
+fn image_checksum(bytes: &[u8], checksum_offset: usize) -> u32 {
+    let mut sum = 0u64;
+    for (i, word) in bytes.chunks(2).enumerate() {
+        if i * 2 == checksum_offset || i * 2 == checksum_offset + 2 {
+            continue;
+        }
+        sum += u64::from(word[0]) | u64::from(word.get(1).copied().unwrap_or(0)) << 8;
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    (sum as u32).wrapping_add(bytes.len() as u32)
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    if align <= 1 { value } else { value.div_ceil(align) * align }
+}
+
+// Whether the checksum matches and the alignments, section layout and image
+// size agree with each other.
+pub fn validate(bytes: &[u8]) -> Result<Vec<Finding>, BaretkError> {
+    let offset = read_u32_checked(bytes, PE_OFFSET_OFFSET, LITTLE_ENDIAN, "DOS header")? as usize;
+    let coff_header = read_coff_header(bytes, offset)?;
+    let mut findings = Vec::<Finding>::new();
+    let opt = offset + 0x18;
+    if coff_header.optional_header_size < 0x44 {
+        findings.push(Finding::suspicious(format!("optional header is only {:#x} bytes", coff_header.optional_header_size)));
+        return Ok(findings)
+    }
+    let u32_at = |at| read_u32_checked(bytes, at, LITTLE_ENDIAN, "PE optional header");
+    let (section_alignment, file_alignment) = (u32_at(opt + 0x20)? as u64, u32_at(opt + 0x24)? as u64);
+    let (image_size, headers_size, checksum) = (u32_at(opt + 0x38)? as u64, u32_at(opt + 0x3c)? as u64, u32_at(opt + 0x40)?);
+
+    let computed = image_checksum(bytes, opt + 0x40);
+    findings.push(match checksum {
+        // Only drivers and some system DLLs are required to have one.
+        0 => Finding::valid("no checksum set"),
+        _ if checksum == computed => Finding::valid(format!("checksum {:#010x} matches", checksum)),
+        _ => Finding::suspicious(format!("checksum {:#010x} doesn't match the file's {:#010x}", checksum, computed)),
+    });
+
+    let mut problems = Vec::<String>::new();
+    if !section_alignment.is_power_of_two() || !file_alignment.is_power_of_two() {
+        problems.push(format!("section alignment {:#x} and file alignment {:#x} should be powers of two", section_alignment, file_alignment));
+    }
+    if file_alignment > section_alignment {
+        problems.push(format!("file alignment {:#x} is more than the section alignment {:#x}", file_alignment, section_alignment));
+    }
+    else if section_alignment >= 0x1000 && !(0x200..=0x10000).contains(&file_alignment) {
+        problems.push(format!("file alignment {:#x} isn't between 0x200 and 0x10000", file_alignment));
+    }
+    if headers_size % file_alignment.max(1) != 0 {
+        problems.push(format!("size of headers {:#x} isn't a multiple of the file alignment", headers_size));
+    }
+    check(&mut findings, "alignments are consistent", problems);
+
+    let mut problems = Vec::<String>::new();
+    let toffset = coff_header.optional_header_size as usize + opt;
+    let mut sections = Vec::<(String, u64, u64)>::new();
+    for i in 0..coff_header.num_sections as usize {
+        let header = read_section_header_32(bytes, toffset + i * 40)?;
+        let name = get_name_from_section_header(&header);
+        let (addr, data_ptr, data_size) = (header.virtual_addr as u64, header.data_ptr as u64, header.data_size as u64);
+        if addr % section_alignment.max(1) != 0 {
+            problems.push(format!("section {} address {:#x} isn't aligned to {:#x}", name, addr, section_alignment));
+        }
+        if data_size != 0 && data_ptr % file_alignment.max(1) != 0 {
+            problems.push(format!("section {} data at {:#x} isn't aligned to {:#x}", name, data_ptr, file_alignment));
+        }
+        if data_size != 0 && data_ptr + data_size > bytes.len() as u64 {
+            problems.push(format!("section {} runs past the end of the file", name));
+        }
+        let size = if header.virtual_size != 0 { header.virtual_size as u64 } else { data_size };
+        sections.push((name, addr, addr + size));
+    }
+    for pair in sections.windows(2) {
+        if pair[1].1 < pair[0].2 {
+            problems.push(format!("sections {} and {} overlap or are out of address order", pair[0].0, pair[1].0));
+        }
+    }
+    if let Some((_, _, end)) = sections.iter().max_by_key(|(_, _, end)| *end) {
+        if image_size != align_up(*end, section_alignment) {
+            problems.push(format!("size of image {:#x} isn't where the sections end, {:#x}", image_size, align_up(*end, section_alignment)));
+        }
+    }
+    check(&mut findings, "sections are aligned, in order and don't overlap", problems);
+    Ok(findings)
+}
+
+pub fn load_program(bytes: &Arc<[u8]>, diagnostics: &mut Diagnostics) -> Result<Program, BaretkError> {
+    let offset = read_u32_checked(bytes, PE_OFFSET_OFFSET, LITTLE_ENDIAN, "DOS header")? as usize;
+    let coff_header = read_coff_header(bytes, offset)?;
+    log::debug!("{} machine ({}), {} section(s)", get_machine_type_string(coff_header.machine), characteristics_string(coff_header.characteristics),
         coff_header.num_sections);
     let optional_header = if coff_header.optional_header_size > 0 {
-        Some(read_optional_header(bytes, offset+0x18))
+        // Without the optional header, a permissive load goes on with no entry point or imports.
+        match read_optional_header(bytes, offset+0x18) {
+            Ok(opt) => Some(opt),
+            Err(err) => {
+                diagnostics.tolerate(err)?;
+                None
+            },
+        }
     } else {
         None
     };
     if let Some(ref opt) = optional_header {
-        println!("{} v{}.{}, base_addr=0x{:08x} code_size=0x{:08x} entry_point=0x{:08x}", 
+        log::debug!("{} v{}.{}, base_addr=0x{:08x} code_size=0x{:08x} entry_point=0x{:08x}",
             match opt.magic { 0x10b => "PE32", 0x20b => "PE32+", _ => ""},
             opt.major_link_ver,
             opt.minor_link_ver,
@@ -247,15 +506,12 @@ pub fn load_program_from_bytes(bytes: &[u8]) -> Program {
             opt.code_size,
             opt.entry_point);
     }
-    let toffset = coff_header.optional_header_size as usize + offset + 0x18;
-    // println!("Section table: 0x{:08x}", toffset);
-    let mut section_table = HashMap::<String, SectionHeader>::new();
-    for i in 0..coff_header.num_sections {
-        let section_header = read_section_header_32(bytes, toffset+(i as usize * 40));
-        let section_name = get_name_from_section_header(&section_header);
-        // println!("{:<8} 0x{:<08x}, 0x{:<08x}", section_name, section_header.virtual_addr, section_header.virtual_size);
-        section_table.insert(section_name.to_string(), section_header);
-    }
-    println!("TODO: finish parsing PE executable files.\n");
-    build_program(bytes, &coff_header, optional_header, &section_table)
+    let section_table = read_section_table(bytes, offset, &coff_header, diagnostics)?;
+    let import_table = match &optional_header {
+        // Only when the optional header is long enough to have the import directory.
+        Some(opt) if coff_header.optional_header_size as usize >= if opt.magic == 0x20b { 0x80 } else { 0x70 } =>
+            read_import_table(bytes, offset + 0x18, opt, &section_table, diagnostics)?,
+        _ => Vec::new(),
+    };
+    build_program(bytes, &coff_header, optional_header, &section_table, import_table, diagnostics)
 }
\ No newline at end of file
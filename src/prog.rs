@@ -1,14 +1,72 @@
 use std::collections::HashMap;
-use crate::query;
-use crate::elf;
-use crate::pe;
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+use crate::decomp::{self, Decomp, Language};
+use crate::dis::{self, Disassembly};
+use crate::error::BaretkError;
+use crate::loader;
+use crate::options::{AnalysisOptions, ParseMode};
+use crate::proto::PrototypeDb;
 use crate::util;
+use crate::xref::PriorXrefs;
 
+// A section's bytes, as a range of the buffer the file was loaded from. The
+// sections of a program share that buffer, so loading doesn't copy each
+// section and cloning a Program doesn't copy the file.
+#[derive(Clone)]
+pub struct SectionBytes {
+    buffer: Arc<[u8]>,
+    range: Range<usize>,
+    in_file: bool,
+}
+
+impl SectionBytes {
+    pub fn new(buffer: &Arc<[u8]>, range: Range<usize>) -> Self {
+        SectionBytes { buffer: buffer.clone(), range, in_file: true }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buffer[self.range.clone()]
+    }
+
+    // Whether the bytes were read from the file, rather than made up like a
+    // zeroed .bss.
+    pub fn in_file(&self) -> bool {
+        self.in_file
+    }
+}
+
+impl Deref for SectionBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+// Bytes that aren't in the file, such as a zeroed .bss.
+impl From<Vec<u8>> for SectionBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        let range = 0..bytes.len();
+        SectionBytes { buffer: bytes.into(), range, in_file: false }
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Section {
     pub addr: u64,
-    pub bytes: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::json::serialize_bytes"))]
+    pub bytes: SectionBytes,
+    // File offset of the section's bytes.
+    pub offset: u64,
+    // RWX_* permissions the section is mapped with.
+    pub perm: u8,
+    pub align: u64,
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Segment {
     pub perm: u8,
     pub offset: u64,
@@ -17,13 +75,138 @@ pub struct Segment {
     pub size: usize,
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u64,
+    pub size: u64,
+    pub is_func: bool,
+}
+
+// The symbol table sorted by address, for binary searches instead of scans of
+// the whole table. Symbols at the same address keep their table order, and
+// where several match a lookup the last of them wins.
+pub struct SymbolIndex<'a> {
+    symbols: Vec<&'a Symbol>,
+    functions: Vec<&'a Symbol>,
+    // Named function symbols without a size, which contain everything up to
+    // the next function.
+    unsized_functions: Vec<&'a Symbol>,
+    // The largest size of any symbol, counting unsized ones as 1. Nothing
+    // further below an address than this can contain it, apart from unsized
+    // functions.
+    max_size: u64,
+}
+
+impl<'a> SymbolIndex<'a> {
+    pub fn new(symbols: &'a [Symbol]) -> SymbolIndex<'a> {
+        let mut symbols: Vec<&Symbol> = symbols.iter().collect();
+        symbols.sort_by_key(|sym| sym.addr);
+        let functions: Vec<&Symbol> = symbols.iter().copied().filter(|sym| sym.is_func).collect();
+        let unsized_functions = functions.iter().copied().filter(|sym| sym.size == 0 && !sym.name.is_empty()).collect();
+        let max_size = symbols.iter().map(|sym| sym.size.max(1)).max().unwrap_or(1);
+        SymbolIndex { symbols, functions, unsized_functions, max_size }
+    }
+
+    // Symbols starting at the address, named or not, in table order.
+    pub fn starting_at(&self, addr: u64) -> &[&'a Symbol] {
+        let start = self.symbols.partition_point(|sym| sym.addr < addr);
+        let end = self.symbols.partition_point(|sym| sym.addr <= addr);
+        &self.symbols[start..end]
+    }
+
+    // The first symbol, named or not, starting above the address.
+    pub fn next_after(&self, addr: u64) -> Option<&'a Symbol> {
+        self.symbols.get(self.symbols.partition_point(|sym| sym.addr <= addr)).copied()
+    }
+
+    // The first function symbol, named or not, starting at the address.
+    pub fn function_starting_at(&self, addr: u64) -> Option<&'a Symbol> {
+        let i = self.functions.partition_point(|sym| sym.addr < addr);
+        self.functions.get(i).filter(|sym| sym.addr == addr).copied()
+    }
+
+    // The function symbol starting closest at or below the address, whatever its size.
+    pub fn nearest_function(&self, addr: u64) -> Option<&'a Symbol> {
+        let end = self.functions.partition_point(|sym| sym.addr <= addr);
+        end.checked_sub(1).map(|i| self.functions[i])
+    }
+
+    // Symbols starting at or below the address that could contain it, closest first.
+    fn below(&self, addr: u64) -> impl Iterator<Item = &'a Symbol> + '_ {
+        let end = self.symbols.partition_point(|sym| sym.addr <= addr);
+        self.symbols[..end].iter().rev().take_while(move |sym| addr - sym.addr < self.max_size).copied()
+    }
+
+    // The named function symbol containing the address: the closest one at or
+    // below it, as long as the address is inside its size (when the size is known).
+    pub fn function_containing(&self, addr: u64) -> Option<&'a Symbol> {
+        let sized = self.below(addr)
+            .find(|sym| sym.is_func && !sym.name.is_empty() && (sym.size == 0 || addr < sym.addr + sym.size));
+        if sized.is_some() {
+            return sized
+        }
+        let end = self.unsized_functions.partition_point(|sym| sym.addr <= addr);
+        end.checked_sub(1).map(|i| self.unsized_functions[i])
+    }
+
+    // The named symbol of any kind containing the address, preferring data
+    // objects over functions starting at the same place.
+    pub fn containing(&self, addr: u64) -> Option<&'a Symbol> {
+        let mut best: Option<&Symbol> = None;
+        for sym in self.below(addr).filter(|sym| !sym.name.is_empty() && addr < sym.addr + sym.size.max(1)) {
+            match best {
+                Some(found) if found.addr != sym.addr => break,
+                Some(found) if found.is_func && !sym.is_func => best = Some(sym),
+                Some(_) => (),
+                None => best = Some(sym),
+            }
+        }
+        best
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Import {
+    // Library the function is imported from, when the format records it.
+    pub library: String,
+    pub name: String,
+    // Address of the GOT or IAT slot holding the imported address.
+    pub slot: u64,
+    // Address of the PLT stub jumping through the slot, if there is one.
+    pub stub: Option<u64>,
+}
+
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Program {
+    // "elf", "pe" or "raw".
+    pub format: &'static str,
     pub bits: u8,
     pub endianess: u8,
     pub machine_type: String,
     pub entry_point: u64,
     pub program_table: Vec<Segment>,
-    pub section_table: HashMap<String, Section>
+    pub section_table: HashMap<String, Section>,
+    pub symbol_table: Vec<Symbol>,
+    // Imports read while loading; ELF imports are found from relocations by the imports module.
+    pub import_table: Vec<Import>,
+    // Comments on addresses, shown next to their instructions.
+    pub comments: HashMap<u64, String>,
+    // Data types marked on addresses, such as "u32[4]".
+    pub data_types: HashMap<u64, String>,
+    // What a permissive load worked around: clamped sizes and skipped entries.
+    pub warnings: Vec<String>,
+    // ARM only: addresses where the code switches to Thumb (true) or ARM
+    // (false), sorted, from mapping symbols and the low bit of function symbols.
+    pub thumb_switches: Vec<(u64, bool)>,
+    // ARM only: sections decoded as Thumb where no switch says otherwise.
+    pub thumb_sections: Vec<String>,
+    // Xrefs from a project saved for an earlier build, reused where the code hasn't changed.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub prior_xrefs: Option<Arc<PriorXrefs>>,
 }
 
 impl Program {
@@ -45,13 +228,90 @@ impl Program {
         }
         (section, segment)
     }
+
+    // Name of the section disassembly starts from.
+    pub fn code_section(&self) -> &'static str {
+        if self.section_table.contains_key(".text") { ".text" } else { "file" }
+    }
+
+    // Symbols sorted by address. Build one for repeated lookups rather than
+    // calling function_at or symbol_at in a loop.
+    pub fn symbol_index(&self) -> SymbolIndex<'_> {
+        SymbolIndex::new(&self.symbol_table)
+    }
+
+    // The function symbol containing the address; see SymbolIndex::function_containing.
+    pub fn function_at(&self, addr: u64) -> Option<&Symbol> {
+        self.symbol_index().function_containing(addr)
+    }
+
+    // The named symbol of any kind containing the address; see SymbolIndex::containing.
+    pub fn symbol_at(&self, addr: u64) -> Option<&Symbol> {
+        self.symbol_index().containing(addr)
+    }
+
+    // The section holding a file offset, and the address the offset is loaded at,
+    // or None for sections that aren't loaded.
+    pub fn section_at_offset(&self, offset: u64) -> Option<(&str, Option<u64>)> {
+        self.section_table.iter()
+            .filter(|(name, section)| !name.is_empty() && !section.bytes.is_empty())
+            .find(|(_, section)| offset >= section.offset && offset < section.offset + section.bytes.len() as u64)
+            .map(|(name, section)| {
+                let loaded = section.addr != 0 || section.offset == 0;
+                (name.as_str(), if loaded { Some(section.addr + offset - section.offset) } else { None })
+            })
+    }
+
+    // The file offset an address is loaded from, or None if no section holds
+    // it in the file. The inverse of section_at_offset.
+    pub fn file_offset(&self, addr: u64) -> Option<u64> {
+        self.section_table.iter()
+            .filter(|(name, section)| !name.is_empty() && section.bytes.in_file() && (section.addr != 0 || section.offset == 0))
+            .find(|(_, section)| addr >= section.addr && addr < section.addr + section.bytes.len() as u64)
+            .map(|(_, section)| section.offset + addr - section.addr)
+    }
+
+    // Looks up a symbol by name, or by address if the string starts with "0x".
+    pub fn find_symbol(&self, name: &str) -> Option<&Symbol> {
+        if let Some(hex) = name.strip_prefix("0x") {
+            let addr = u64::from_str_radix(hex, 16).ok()?;
+            self.symbol_table.iter().find(|sym| sym.addr == addr && sym.is_func)
+                .or_else(|| self.symbol_table.iter().find(|sym| sym.addr == addr))
+        }
+        else {
+            self.symbol_table.iter().find(|sym| sym.name == name)
+        }
+    }
+
+    // Disassembles the code section with the options' sweep and overrides.
+    pub fn disassemble(self, options: &AnalysisOptions) -> Result<Disassembly, BaretkError> {
+        dis::disassemble_with_options(self, options)
+    }
+
+    // Decompiles the code section, or only the function named by symbol or
+    // "0x..." address, without any prototypes loaded.
+    pub fn decompile(self, lang: Language, function: Option<&str>, options: &AnalysisOptions) -> Result<Decomp, BaretkError> {
+        let dis = self.disassemble(options)?;
+        let protos = PrototypeDb::new();
+        match function {
+            Some(func) => decomp::decomp_function(dis, func, lang, &protos),
+            None => Ok(decomp::decomp_program(dis, lang, &protos)),
+        }
+    }
 }
 
 pub fn build_program_from_binary(bytes: &[u8], bits: Option<u8>, endianess: Option<u8>, machine_type: Option<String>) -> Program {
+    build_raw_program(&Arc::from(bytes), bits, endianess, machine_type)
+}
+
+pub(crate) fn build_raw_program(bytes: &Arc<[u8]>, bits: Option<u8>, endianess: Option<u8>, machine_type: Option<String>) -> Program {
     let mut section_table = HashMap::<String, Section>::new();
     section_table.insert(String::from("file"), Section {
         addr: 0x0,
-        bytes: bytes.to_vec().clone()
+        bytes: SectionBytes::new(bytes, 0..bytes.len()),
+        offset: 0x0,
+        perm: 0x7,
+        align: 1,
     });
     let mut program_table = Vec::<Segment>::new();
     program_table.push(Segment {
@@ -62,27 +322,111 @@ pub fn build_program_from_binary(bytes: &[u8], bits: Option<u8>, endianess: Opti
         size: bytes.len(),
     });
     Program {
+        format: "raw",
         bits: bits.unwrap_or_default(),
         endianess: endianess.unwrap_or_default(),
         machine_type: machine_type.unwrap_or("unknown".to_string()),
         entry_point: 0,
         program_table,
         section_table,
+        symbol_table: Vec::new(),
+        import_table: Vec::new(),
+        comments: HashMap::new(),
+        data_types: HashMap::new(),
+        warnings: Vec::new(),
+        thumb_switches: Vec::new(),
+        thumb_sections: Vec::new(),
+        prior_xrefs: None,
     }
 }
 
-pub fn load_program_from_file(path: &String) -> Result<Program, ()> {
-    match util::try_read_file_contents(path) {
-        Err(()) => Err(()),
-        Ok(contents) => Ok(load_program_from_bytes(&contents)),
+// The problems found while parsing a file. Strict mode stops at the first;
+// permissive mode keeps it as a warning so the caller can work around it.
+pub struct Diagnostics {
+    mode: ParseMode,
+    pub warnings: Vec<String>,
+}
+
+impl Diagnostics {
+    pub fn new(mode: ParseMode) -> Self {
+        Diagnostics { mode, warnings: Vec::new() }
+    }
+
+    pub fn tolerate(&mut self, error: BaretkError) -> Result<(), BaretkError> {
+        if self.mode == ParseMode::Strict {
+            return Err(error)
+        }
+        self.warnings.push(error.to_string());
+        Ok(())
+    }
+}
+
+// The part of a section that's in the file; truncated files lose the rest.
+pub fn section_bytes(bytes: &Arc<[u8]>, name: &str, offset: u64, size: u64, diagnostics: &mut Diagnostics) -> Result<SectionBytes, BaretkError> {
+    let start = (offset as usize).min(bytes.len());
+    let end = offset.saturating_add(size).min(bytes.len() as u64) as usize;
+    if end - start < size as usize {
+        diagnostics.tolerate(BaretkError::Malformed { what: "section data".to_string(), offset: Some(offset), section: Some(name.to_string()),
+            reason: format!("{:#x} of {:#x} byte(s) are in the file", end - start, size) })?;
     }
+    Ok(SectionBytes::new(bytes, start..end))
+}
+
+pub fn load_program_from_file(path: &str) -> Result<Program, BaretkError> {
+    load_program_from_shared(util::try_read_file_contents(path)?.into(), ParseMode::Permissive)
+}
+
+// Applies the architecture override, the Thumb sections and, for raw
+// binaries, the base address. Applying the same options again changes nothing.
+pub fn apply_options(program: &mut Program, options: &AnalysisOptions) {
+    if let Some(arch) = &options.arch {
+        program.machine_type = arch.clone();
+        if program.bits == 0 {
+            program.bits = if arch == "amd64" || arch == "aarch64" { 64 } else { 32 };
+        }
+    }
+    for name in &options.thumb_sections {
+        if !program.thumb_sections.contains(name) {
+            program.thumb_sections.push(name.clone());
+        }
+    }
+    if let (Some(base), "raw") = (options.base_addr, program.format) {
+        if let Some(section) = program.section_table.get_mut("file") {
+            section.addr = base;
+        }
+        for segment in program.program_table.iter_mut() {
+            segment.vaddr = base;
+            segment.paddr = base;
+        }
+        program.entry_point = base;
+    }
+}
+
+pub fn load_program_with_options(bytes: &[u8], options: &AnalysisOptions) -> Result<Program, BaretkError> {
+    let mut program = load_program_from_shared(Arc::from(bytes), options.parse_mode)?;
+    apply_options(&mut program, options);
+    Ok(program)
+}
+
+// Copies the bytes once; the sections share the copy.
+pub fn load_program_from_bytes(bytes: &[u8]) -> Result<Program, BaretkError> {
+    load_program_from_shared(Arc::from(bytes), ParseMode::Permissive)
 }
 
-pub fn load_program_from_bytes(bytes: &[u8]) -> Program {
-    let file_type = query::get_file_type(bytes);
-    match file_type {
-        query::FileType::Elf => elf::load_program_from_bytes(bytes),
-        query::FileType::PE  => pe::load_program_from_bytes(bytes),
-        _ => build_program_from_binary(bytes, None, None, None)
+// Loads without copying: the sections are ranges of the caller's buffer.
+// Headers that point outside the file are an error rather than a panic.
+pub fn load_program_from_shared(bytes: Arc<[u8]>, mode: ParseMode) -> Result<Program, BaretkError> {
+    let mut diagnostics = Diagnostics::new(mode);
+    let mut program = match loader::find_loader(&bytes) {
+        Some(loader) => {
+            log::debug!("Loading as {}", loader.name());
+            loader.load(&bytes, &mut diagnostics)?
+        },
+        None => build_raw_program(&bytes, None, None, None),
+    };
+    for warning in &diagnostics.warnings {
+        log::warn!("{}", warning);
     }
+    program.warnings = diagnostics.warnings;
+    Ok(program)
 }
\ No newline at end of file
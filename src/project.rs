@@ -0,0 +1,337 @@
+// Analysis projects: the names, comments and data types worked out for a
+// binary, saved as JSON so the next run picks up where the last one stopped.
+//
+//     {
+//       "file_size": 8848,
+//       "checksum": "0x9ae16a3b2f90404f",
+//       "code_section": ".text",
+//       "symbols": [
+//         {"addr": "0x401000", "name": "parse_header", "size": 10, "func": true}
+//       ],
+//       "comments": [
+//         {"addr": "0x401004", "comment": "checks the magic first"}
+//       ],
+//       "types": [
+//         {"addr": "0x404010", "type": "u32", "count": 4}
+//       ],
+//       "section_checksums": [
+//         {"name": ".rodata", "checksum": "0x5f0b4a3c9e1d2a77"}
+//       ],
+//       "code_blocks": [
+//         "0x2c6a9b7f01e4d853"
+//       ],
+//       "xrefs": [
+//         {"from": "0x401010", "to": "0x401000", "kind": "call"}
+//       ]
+//     }
+//
+// Only symbols the binary doesn't define itself are saved: names from map
+// files, signatures and annotations, and renamed symbols.
+//
+// The xrefs are saved with checksums of the sections and of each block of the
+// code section. When the binary is loaded again after a patch, the xrefs of
+// unchanged blocks are reused and only the changed blocks are analysed again.
+// A change to any other section, which jump tables are read from, means
+// starting over.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::dis::Disassembly;
+use crate::error::BaretkError;
+use crate::json::{self, Value};
+use crate::prog::{self, Program, Symbol};
+use crate::util;
+use crate::xref::{PriorXrefs, Xref, XrefDb, XrefKind};
+
+// Size of the code section blocks checksummed on their own.
+const BLOCK_SIZE: usize = 4096;
+
+pub struct Project {
+    pub file_size: u64,
+    pub checksum: u64,
+    pub symbols: Vec<Symbol>,
+    pub comments: BTreeMap<u64, String>,
+    // Element type name and count.
+    pub types: BTreeMap<u64, (String, u64)>,
+    pub analysis: Option<SavedAnalysis>,
+}
+
+// What's needed to reuse the xrefs of a binary when it's loaded again.
+pub struct SavedAnalysis {
+    pub code_section: String,
+    // Checksums of the sections other than the code section.
+    pub sections: BTreeMap<String, u64>,
+    // Checksum of each BLOCK_SIZE block of the code section.
+    pub blocks: Vec<u64>,
+    // Referenced address and reference.
+    pub xrefs: Vec<(u64, Xref)>,
+}
+
+impl SavedAnalysis {
+    pub fn capture(dis: &Disassembly) -> SavedAnalysis {
+        let program = dis.program();
+        let code_section = dis.section().section_name.clone();
+        SavedAnalysis {
+            sections: section_checksums(program, &code_section),
+            blocks: block_checksums(program, &code_section),
+            code_section,
+            xrefs: XrefDb::build(dis).all().map(|(to, xref)| (to, xref.clone())).collect(),
+        }
+    }
+
+    // The saved xrefs with the code that changed since, or None if the code
+    // section changed size or any other section changed.
+    fn prior_xrefs(&self, program: &Program) -> Option<PriorXrefs> {
+        let blocks = block_checksums(program, &self.code_section);
+        if program.code_section() != self.code_section || blocks.len() != self.blocks.len() || section_checksums(program, &self.code_section) != self.sections {
+            return None
+        }
+        let mut changed = Vec::<std::ops::Range<usize>>::new();
+        for (i, block) in blocks.iter().enumerate() {
+            if self.blocks[i] == *block {
+                continue;
+            }
+            let start = i * BLOCK_SIZE;
+            match changed.last_mut() {
+                Some(last) if last.end == start => last.end = start + BLOCK_SIZE,
+                _ => changed.push(start..start + BLOCK_SIZE),
+            }
+        }
+        Some(PriorXrefs { xrefs: self.xrefs.clone(), changed })
+    }
+}
+
+fn section_checksums(program: &Program, code_section: &str) -> BTreeMap<String, u64> {
+    program.section_table.iter()
+        .filter(|(name, _)| name.as_str() != code_section)
+        .map(|(name, section)| (name.clone(), checksum(&section.bytes)))
+        .collect()
+}
+
+fn block_checksums(program: &Program, code_section: &str) -> Vec<u64> {
+    match program.section_table.get(code_section) {
+        Some(section) => section.bytes.chunks(BLOCK_SIZE).map(checksum).collect(),
+        None => Vec::new(),
+    }
+}
+
+// FNV-1a, to notice a project being used with a different binary.
+pub fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325u64, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+// Size in bytes of a data type name, where pointers are the program's word size.
+pub fn type_size(name: &str, bits: u8) -> Option<u64> {
+    match name {
+        "u8" | "i8" | "char" => Some(1),
+        "u16" | "i16" => Some(2),
+        "u32" | "i32" | "f32" => Some(4),
+        "u64" | "i64" | "f64" => Some(8),
+        "ptr" => Some(bits as u64 / 8),
+        _ => None,
+    }
+}
+
+// Parses a type mark such as "u32" or "char[16]" into the element type and count.
+pub fn parse_type(s: &str) -> Option<(String, u64)> {
+    let (name, count) = match s.strip_suffix(']').and_then(|s| s.split_once('[')) {
+        Some((name, count)) => (name, count.parse::<u64>().ok()?),
+        None => (s, 1),
+    };
+    type_size(name, 64)?;
+    Some((name.to_string(), count))
+}
+
+fn type_text(name: &str, count: u64) -> String {
+    if count == 1 { name.to_string() } else { format!("{}[{}]", name, count) }
+}
+
+impl Project {
+    // Captures what the program knows beyond what loading `bytes` gives.
+    pub fn capture(program: &Program, bytes: &[u8]) -> Project {
+        // The program was loaded from these bytes, so they parse again.
+        let original = prog::load_program_from_bytes(bytes).map(|p| p.symbol_table).unwrap_or_default();
+        let symbols = program.symbol_table.iter()
+            .filter(|sym| !sym.name.is_empty())
+            .filter(|sym| !original.iter().any(|s| s.name == sym.name && s.addr == sym.addr))
+            .map(|sym| Symbol { name: sym.name.clone(), addr: sym.addr, size: sym.size, is_func: sym.is_func })
+            .collect();
+        Project {
+            file_size: bytes.len() as u64,
+            checksum: checksum(bytes),
+            symbols,
+            comments: program.comments.iter().map(|(addr, comment)| (*addr, comment.clone())).collect(),
+            types: program.data_types.iter()
+                .filter_map(|(addr, text)| parse_type(text).map(|t| (*addr, t)))
+                .collect(),
+            analysis: None,
+        }
+    }
+
+    pub fn load_file(path: &str) -> Result<Project, BaretkError> {
+        let contents = util::try_read_file_contents(path)?;
+        let value = json::parse(&String::from_utf8_lossy(&contents))
+            .map_err(|offset| BaretkError::malformed(path, offset as u64, "invalid JSON".to_string()))?;
+        let mut project = Project {
+            file_size: value.get("file_size").and_then(|v| v.as_u64()).unwrap_or(0),
+            checksum: value.get("checksum").and_then(|v| v.as_u64()).unwrap_or(0),
+            symbols: Vec::new(),
+            comments: BTreeMap::new(),
+            types: BTreeMap::new(),
+            analysis: None,
+        };
+        let entries = |key: &str| value.get(key).and_then(|v| v.as_array()).unwrap_or(&[]);
+        for entry in entries("symbols") {
+            match (entry.get("addr").and_then(|v| v.as_u64()), entry.get("name").and_then(|v| v.as_str())) {
+                (Some(addr), Some(name)) => project.symbols.push(Symbol {
+                    name: name.to_string(),
+                    addr,
+                    size: entry.get("size").and_then(|v| v.as_u64()).unwrap_or(0),
+                    is_func: entry.get("func").and_then(|v| v.as_bool()).unwrap_or(false),
+                }),
+                _ => log::warn!("{}: skipping a symbol without an address and name", path),
+            }
+        }
+        for entry in entries("comments") {
+            if let (Some(addr), Some(comment)) = (entry.get("addr").and_then(|v| v.as_u64()), entry.get("comment").and_then(|v| v.as_str())) {
+                project.comments.insert(addr, comment.to_string());
+            }
+        }
+        for entry in entries("types") {
+            let addr = entry.get("addr").and_then(|v| v.as_u64());
+            let name = entry.get("type").and_then(|v| v.as_str()).filter(|name| type_size(name, 64).is_some());
+            match (addr, name) {
+                (Some(addr), Some(name)) => {
+                    let count = entry.get("count").and_then(|v| v.as_u64()).unwrap_or(1);
+                    project.types.insert(addr, (name.to_string(), count));
+                },
+                _ => log::warn!("{}: skipping a type mark without an address and known type", path),
+            }
+        }
+        if let (Some(code_section), Some(blocks)) = (value.get("code_section").and_then(|v| v.as_str()), value.get("code_blocks").and_then(|v| v.as_array())) {
+            let mut analysis = SavedAnalysis {
+                code_section: code_section.to_string(),
+                sections: BTreeMap::new(),
+                blocks: blocks.iter().map(|v| v.as_u64().unwrap_or(0)).collect(),
+                xrefs: Vec::new(),
+            };
+            for entry in entries("section_checksums") {
+                if let (Some(name), Some(sum)) = (entry.get("name").and_then(|v| v.as_str()), entry.get("checksum").and_then(|v| v.as_u64())) {
+                    analysis.sections.insert(name.to_string(), sum);
+                }
+            }
+            for entry in entries("xrefs") {
+                let from = entry.get("from").and_then(|v| v.as_u64());
+                let to = entry.get("to").and_then(|v| v.as_u64());
+                let kind = entry.get("kind").and_then(|v| v.as_str()).and_then(XrefKind::from_name);
+                match (from, to, kind) {
+                    (Some(from), Some(to), Some(kind)) => analysis.xrefs.push((to, Xref { from, kind })),
+                    _ => log::warn!("{}: skipping an xref without an address, target and kind", path),
+                }
+            }
+            project.analysis = Some(analysis);
+        }
+        Ok(project)
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{\n");
+        out += format!("  \"file_size\": {},\n  \"checksum\": \"{:#018x}\",\n", self.file_size, self.checksum).as_str();
+        if let Some(analysis) = &self.analysis {
+            out += "  \"code_section\": ";
+            json::write_string(&mut out, &analysis.code_section);
+            out += ",\n";
+        }
+        let mut section = |key: &str, items: Vec<Value>, last: bool| {
+            out += format!("  \"{}\": [", key).as_str();
+            for (i, item) in items.iter().enumerate() {
+                out += if i == 0 { "\n    " } else { ",\n    " };
+                item.write(&mut out);
+            }
+            out += if items.is_empty() { "]" } else { "\n  ]" };
+            out += if last { "\n" } else { ",\n" };
+        };
+        let mut symbols: Vec<&Symbol> = self.symbols.iter().collect();
+        symbols.sort_by_key(|sym| (sym.addr, sym.name.clone()));
+        section("symbols", symbols.iter().map(|sym| Value::Object(vec![
+            ("addr".to_string(), Value::hex(sym.addr)),
+            ("name".to_string(), Value::String(sym.name.clone())),
+            ("size".to_string(), Value::Number(sym.size.to_string())),
+            ("func".to_string(), Value::Bool(sym.is_func)),
+        ])).collect(), false);
+        section("comments", self.comments.iter().map(|(addr, comment)| Value::Object(vec![
+            ("addr".to_string(), Value::hex(*addr)),
+            ("comment".to_string(), Value::String(comment.clone())),
+        ])).collect(), false);
+        section("types", self.types.iter().map(|(addr, (name, count))| Value::Object(vec![
+            ("addr".to_string(), Value::hex(*addr)),
+            ("type".to_string(), Value::String(name.clone())),
+            ("count".to_string(), Value::Number(count.to_string())),
+        ])).collect(), self.analysis.is_none());
+        if let Some(analysis) = &self.analysis {
+            section("section_checksums", analysis.sections.iter().map(|(name, sum)| Value::Object(vec![
+                ("name".to_string(), Value::String(name.clone())),
+                ("checksum".to_string(), Value::String(format!("{:#018x}", sum))),
+            ])).collect(), false);
+            section("code_blocks", analysis.blocks.iter().map(|sum| Value::String(format!("{:#018x}", sum))).collect(), false);
+            section("xrefs", analysis.xrefs.iter().map(|(to, xref)| Value::Object(vec![
+                ("from".to_string(), Value::hex(xref.from)),
+                ("to".to_string(), Value::hex(*to)),
+                ("kind".to_string(), Value::String(xref.kind.name().to_string())),
+            ])).collect(), true);
+        }
+        out += "}\n";
+        out
+    }
+
+    pub fn save_file(&self, path: &str) -> Result<(), BaretkError> {
+        std::fs::write(path, self.to_json()).map_err(|error| BaretkError::io(path, "writing", error))
+    }
+
+    // Symbols rename the ones of the same kind already at their address, or are
+    // added. `bytes` is the binary the program was loaded from, to warn when it
+    // isn't the one the project was saved for.
+    pub fn apply(&self, program: &mut Program, bytes: &[u8]) {
+        if self.file_size != bytes.len() as u64 || self.checksum != checksum(bytes) {
+            log::warn!("the project was saved for a different binary; addresses may not match");
+        }
+        for saved in &self.symbols {
+            if program.symbol_table.iter().any(|sym| sym.addr == saved.addr && sym.name == saved.name) {
+                continue;
+            }
+            // Aliases saved for the same address are each kept, not renamed into one another.
+            let saved_names: Vec<&str> = self.symbols.iter().filter(|s| s.addr == saved.addr).map(|s| s.name.as_str()).collect();
+            let existing = program.symbol_table.iter_mut()
+                .find(|sym| sym.addr == saved.addr && sym.is_func == saved.is_func && !sym.name.is_empty() && !saved_names.contains(&sym.name.as_str()));
+            match existing {
+                Some(sym) => {
+                    sym.name = saved.name.clone();
+                    sym.size = if saved.size != 0 { saved.size } else { sym.size };
+                },
+                None => program.symbol_table.push(Symbol { name: saved.name.clone(), addr: saved.addr, size: saved.size, is_func: saved.is_func }),
+            }
+        }
+        for (addr, comment) in &self.comments {
+            program.comments.insert(*addr, comment.clone());
+        }
+        for (addr, (name, count)) in &self.types {
+            mark_type(program, *addr, name, *count);
+        }
+        if let Some(analysis) = &self.analysis {
+            program.prior_xrefs = analysis.prior_xrefs(program).map(Arc::new);
+        }
+    }
+}
+
+// Marks an address as holding `count` values of a type. Unnamed data gets a
+// symbol covering it, named after the type and address.
+pub fn mark_type(program: &mut Program, addr: u64, name: &str, count: u64) {
+    let size = type_size(name, program.bits).unwrap_or(1) * count;
+    program.data_types.insert(addr, type_text(name, count));
+    match program.symbol_table.iter_mut().find(|sym| sym.addr == addr && !sym.is_func && !sym.name.is_empty()) {
+        Some(sym) if sym.size == 0 => sym.size = size,
+        Some(_) => (),
+        None => program.symbol_table.push(Symbol { name: format!("{}_{:x}", name, addr), addr, size, is_func: false }),
+    }
+}
@@ -0,0 +1,176 @@
+// Function prototypes for well-known library calls, so decompiled calls to them
+// can be given the right number of named arguments.
+
+use std::collections::HashMap;
+
+use crate::error::BaretkError;
+use crate::util;
+
+pub struct Prototype {
+    pub name: String,
+    pub params: Vec<String>,
+    pub variadic: bool,
+    pub returns_void: bool,
+}
+
+const BUILTIN_PROTOTYPES: &str = "
+// libc
+void *memcpy(void *dest, const void *src, size_t n);
+void *memmove(void *dest, const void *src, size_t n);
+void *memset(void *s, int c, size_t n);
+int memcmp(const void *s1, const void *s2, size_t n);
+void *memchr(const void *s, int c, size_t n);
+size_t strlen(const char *s);
+char *strcpy(char *dest, const char *src);
+char *strncpy(char *dest, const char *src, size_t n);
+char *strcat(char *dest, const char *src);
+char *strncat(char *dest, const char *src, size_t n);
+int strcmp(const char *s1, const char *s2);
+int strncmp(const char *s1, const char *s2, size_t n);
+char *strchr(const char *s, int c);
+char *strrchr(const char *s, int c);
+char *strstr(const char *haystack, const char *needle);
+char *strdup(const char *s);
+void *malloc(size_t size);
+void *calloc(size_t nmemb, size_t size);
+void *realloc(void *ptr, size_t size);
+void free(void *ptr);
+int printf(const char *format, ...);
+int fprintf(FILE *stream, const char *format, ...);
+int sprintf(char *str, const char *format, ...);
+int snprintf(char *str, size_t size, const char *format, ...);
+int scanf(const char *format, ...);
+int sscanf(const char *str, const char *format, ...);
+int puts(const char *s);
+int putchar(int c);
+int fputs(const char *s, FILE *stream);
+char *fgets(char *s, int size, FILE *stream);
+FILE *fopen(const char *pathname, const char *mode);
+int fclose(FILE *stream);
+size_t fread(void *ptr, size_t size, size_t nmemb, FILE *stream);
+size_t fwrite(const void *ptr, size_t size, size_t nmemb, FILE *stream);
+int open(const char *pathname, int flags, ...);
+int close(int fd);
+ssize_t read(int fd, void *buf, size_t count);
+ssize_t write(int fd, const void *buf, size_t count);
+void exit(int status);
+void abort(void);
+int atoi(const char *nptr);
+long strtol(const char *nptr, char **endptr, int base);
+char *getenv(const char *name);
+int system(const char *command);
+int socket(int domain, int type, int protocol);
+int connect(int sockfd, const struct sockaddr *addr, socklen_t addrlen);
+int bind(int sockfd, const struct sockaddr *addr, socklen_t addrlen);
+int listen(int sockfd, int backlog);
+int accept(int sockfd, struct sockaddr *addr, socklen_t *addrlen);
+ssize_t send(int sockfd, const void *buf, size_t len, int flags);
+ssize_t recv(int sockfd, void *buf, size_t len, int flags);
+void *mmap(void *addr, size_t length, int prot, int flags, int fd, off_t offset);
+int munmap(void *addr, size_t length);
+int __libc_start_main(void *main, int argc, char **argv, void *init, void *fini, void *rtld_fini, void *stack_end);
+void __stack_chk_fail(void);
+
+// Win32
+HANDLE CreateFileA(LPCSTR lpFileName, DWORD dwDesiredAccess, DWORD dwShareMode, LPSECURITY_ATTRIBUTES lpSecurityAttributes, DWORD dwCreationDisposition, DWORD dwFlagsAndAttributes, HANDLE hTemplateFile);
+HANDLE CreateFileW(LPCWSTR lpFileName, DWORD dwDesiredAccess, DWORD dwShareMode, LPSECURITY_ATTRIBUTES lpSecurityAttributes, DWORD dwCreationDisposition, DWORD dwFlagsAndAttributes, HANDLE hTemplateFile);
+BOOL ReadFile(HANDLE hFile, LPVOID lpBuffer, DWORD nNumberOfBytesToRead, LPDWORD lpNumberOfBytesRead, LPOVERLAPPED lpOverlapped);
+BOOL WriteFile(HANDLE hFile, LPCVOID lpBuffer, DWORD nNumberOfBytesToWrite, LPDWORD lpNumberOfBytesWritten, LPOVERLAPPED lpOverlapped);
+BOOL CloseHandle(HANDLE hObject);
+LPVOID VirtualAlloc(LPVOID lpAddress, SIZE_T dwSize, DWORD flAllocationType, DWORD flProtect);
+BOOL VirtualFree(LPVOID lpAddress, SIZE_T dwSize, DWORD dwFreeType);
+BOOL VirtualProtect(LPVOID lpAddress, SIZE_T dwSize, DWORD flNewProtect, PDWORD lpflOldProtect);
+FARPROC GetProcAddress(HMODULE hModule, LPCSTR lpProcName);
+HMODULE LoadLibraryA(LPCSTR lpLibFileName);
+HMODULE LoadLibraryW(LPCWSTR lpLibFileName);
+HMODULE GetModuleHandleA(LPCSTR lpModuleName);
+HMODULE GetModuleHandleW(LPCWSTR lpModuleName);
+int MessageBoxA(HWND hWnd, LPCSTR lpText, LPCSTR lpCaption, UINT uType);
+int MessageBoxW(HWND hWnd, LPCWSTR lpText, LPCWSTR lpCaption, UINT uType);
+void ExitProcess(UINT uExitCode);
+BOOL CreateProcessA(LPCSTR lpApplicationName, LPSTR lpCommandLine, LPSECURITY_ATTRIBUTES lpProcessAttributes, LPSECURITY_ATTRIBUTES lpThreadAttributes, BOOL bInheritHandles, DWORD dwCreationFlags, LPVOID lpEnvironment, LPCSTR lpCurrentDirectory, LPSTARTUPINFOA lpStartupInfo, LPPROCESS_INFORMATION lpProcessInformation);
+HANDLE CreateThread(LPSECURITY_ATTRIBUTES lpThreadAttributes, SIZE_T dwStackSize, LPTHREAD_START_ROUTINE lpStartAddress, LPVOID lpParameter, DWORD dwCreationFlags, LPDWORD lpThreadId);
+void Sleep(DWORD dwMilliseconds);
+UINT WinExec(LPCSTR lpCmdLine, UINT uCmdShow);
+DWORD GetLastError(void);
+";
+
+fn last_identifier(s: &str) -> Option<&str> {
+    let s = s.trim_end_matches(|c: char| c.is_whitespace() || c == ']' || c == '[');
+    let start = s.rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).map(|i| i + 1).unwrap_or(0);
+    if start < s.len() { Some(&s[start..]) } else { None }
+}
+
+// Parses a single C declaration such as `int puts(const char *s);`.
+pub fn parse_prototype(line: &str) -> Option<Prototype> {
+    let line = line.trim().trim_end_matches(';').trim_end();
+    let open = line.find('(')?;
+    let close = line.rfind(')')?;
+    if close < open {
+        return None
+    }
+    let head = &line[..open];
+    let name = last_identifier(head)?;
+    let ret = head[..head.len() - name.len()].trim();
+    let mut params = Vec::<String>::new();
+    let mut variadic = false;
+    let args = line[open + 1..close].trim();
+    if args != "void" && !args.is_empty() {
+        for (i, arg) in args.split(',').map(str::trim).enumerate() {
+            if arg == "..." {
+                variadic = true;
+                continue;
+            }
+            // A parameter given only as a type gets a positional name.
+            let param = match last_identifier(arg) {
+                Some(ident) if arg.len() > ident.len() && !arg[..arg.len() - ident.len()].trim().is_empty() => ident.to_string(),
+                _ => format!("arg{}", i),
+            };
+            params.push(param);
+        }
+    }
+    Some(Prototype { name: name.to_string(), params, variadic, returns_void: ret == "void" })
+}
+
+pub struct PrototypeDb {
+    prototypes: HashMap<String, Prototype>,
+}
+
+impl PrototypeDb {
+    pub fn new() -> Self {
+        let mut db = PrototypeDb { prototypes: HashMap::new() };
+        db.add_source(BUILTIN_PROTOTYPES);
+        db
+    }
+
+    // Adds one prototype per line. Blank lines and // or # comments are skipped.
+    // Returns the lines that couldn't be parsed.
+    pub fn add_source(&mut self, source: &str) -> Vec<usize> {
+        let mut bad = Vec::<usize>::new();
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") || line.starts_with('#') {
+                continue;
+            }
+            match parse_prototype(line) {
+                Some(proto) => { self.prototypes.insert(proto.name.clone(), proto); },
+                None => bad.push(i + 1),
+            }
+        }
+        bad
+    }
+
+    pub fn load_file(&mut self, path: &str) -> Result<(), BaretkError> {
+        let contents = util::try_read_file_contents(path)?;
+        for line in self.add_source(&String::from_utf8_lossy(&contents)) {
+            log::warn!("{}:{}: can't parse prototype", path, line);
+        }
+        Ok(())
+    }
+
+    // Symbol versions and PLT suffixes ("memcpy@plt", "puts@GLIBC_2.2.5") are ignored.
+    pub fn get(&self, name: &str) -> Option<&Prototype> {
+        let name = name.split('@').next().unwrap_or(name);
+        self.prototypes.get(name)
+    }
+}
@@ -1,9 +1,32 @@
+use regex::Regex;
+
+use crate::elf;
+use crate::options::AnalysisOptions;
 use crate::pe;
+use crate::prog::Program;
+use crate::util::{read_u32_from_slice, BIG_ENDIAN, LITTLE_ENDIAN};
 
+#[derive(Clone, Copy, PartialEq)]
 pub enum FileType {
     RawBinary,
     Elf,
     PE,
+    // Recognised, but loaded as a raw binary.
+    MachO,
+}
+
+const MACHO_MAGIC_32: u32 = 0xfeedface;
+const MACHO_MAGIC_64: u32 = 0xfeedfacf;
+const MACHO_FAT_MAGIC: u32 = 0xcafebabe;
+
+// Java class files share the fat magic; a fat binary has only a few architectures.
+pub(crate) fn is_macho(bytes: &[u8]) -> bool {
+    let (magic, count) = match (read_u32_from_slice(bytes, 0, BIG_ENDIAN), read_u32_from_slice(bytes, 4, BIG_ENDIAN)) {
+        (Some(magic), Some(count)) => (magic, count),
+        _ => return false,
+    };
+    [MACHO_MAGIC_32, MACHO_MAGIC_64].contains(&magic) || [MACHO_MAGIC_32, MACHO_MAGIC_64].contains(&magic.swap_bytes())
+        || (magic == MACHO_FAT_MAGIC && count < 20)
 }
 
 pub fn get_file_type(bytes: &[u8]) -> FileType {
@@ -13,40 +36,360 @@ pub fn get_file_type(bytes: &[u8]) -> FileType {
     else if pe::check_is_pe_executable(bytes) {
         return FileType::PE
     }
+    else if is_macho(bytes) {
+        return FileType::MachO
+    }
     FileType::RawBinary
 }
 
+// What a file is, read from its first header only, so it can be shown
+// before deciding to load the whole program.
+pub struct FileInfo {
+    pub file_type: FileType,
+    // As the loaders name it, or "unknown".
+    pub machine_type: &'static str,
+    // 0 when the headers don't say.
+    pub bits: u8,
+    pub endianess: u8,
+}
+
+// The first architecture of a fat binary is the one described. Fat headers
+// are big endian whatever the architectures inside are.
+fn identify_macho(bytes: &[u8]) -> (&'static str, u8, u8) {
+    // A field that's cut off reads as 0, which is unknown.
+    let magic = read_u32_from_slice(bytes, 0, BIG_ENDIAN).unwrap_or(0);
+    let (cputype, endianess) = match magic {
+        MACHO_FAT_MAGIC => (read_u32_from_slice(bytes, 8, BIG_ENDIAN), LITTLE_ENDIAN),
+        MACHO_MAGIC_32 | MACHO_MAGIC_64 => (read_u32_from_slice(bytes, 4, BIG_ENDIAN), BIG_ENDIAN),
+        _ => (read_u32_from_slice(bytes, 4, LITTLE_ENDIAN), LITTLE_ENDIAN),
+    };
+    let cputype = cputype.unwrap_or(0);
+    // CPU_ARCH_ABI64 marks the 64-bit variant of a CPU type.
+    let machine = match cputype {
+        0x7 => "x86",
+        0x0100_0007 => "amd64",
+        0xc => "arm",
+        0x0100_000c => "aarch64",
+        _ => "unknown",
+    };
+    let bits = match (magic, magic.swap_bytes()) {
+        (MACHO_MAGIC_64, _) | (_, MACHO_MAGIC_64) => 64,
+        (MACHO_MAGIC_32, _) | (_, MACHO_MAGIC_32) => 32,
+        _ if cputype & 0x0100_0000 != 0 => 64,
+        _ => 32,
+    };
+    (machine, bits, endianess)
+}
+
+pub fn get_file_info(bytes: &[u8]) -> FileInfo {
+    let file_type = get_file_type(bytes);
+    let (machine_type, bits, endianess) = match file_type {
+        FileType::Elf => elf::identify(bytes),
+        FileType::PE => pe::identify(bytes),
+        FileType::MachO => identify_macho(bytes),
+        FileType::RawBinary => ("unknown", 0, LITTLE_ENDIAN),
+    };
+    FileInfo { file_type, machine_type, bits, endianess }
+}
+
+const LOW_BITS: u64 = 0x0101_0101_0101_0101;
+const HIGH_BITS: u64 = 0x8080_8080_8080_8080;
+
+// Sets the top bit of each of eight bytes that's in lowest..=0x7f. Adding
+// 0x80 - lowest to the low seven bits sets it for bytes from lowest up, and
+// bytes from 0x80 up are masked out.
+fn allowed_bytes(word: u64, lowest: u8) -> u64 {
+    ((word & !HIGH_BITS) + LOW_BITS * (0x80 - lowest) as u64) & !word & HIGH_BITS
+}
+
+// How many bytes from `index` on are ASCII that a string can hold: 0x01 to
+// 0x7f, or 0x20 to 0x7f when printable. Checked eight bytes at a time.
+fn ascii_run_len(bytes: &[u8], index: usize, lowest: u8) -> usize {
+    let rest = &bytes[index..];
+    let mut len = 0usize;
+    for word in rest.chunks_exact(8) {
+        if allowed_bytes(u64::from_le_bytes(word.try_into().unwrap()), lowest) != HIGH_BITS {
+            break;
+        }
+        len += 8;
+    }
+    len + rest[len..].iter().take_while(|&&b| (lowest..=0x7f).contains(&b)).count()
+}
+
+// Runs too short to be strings are stepped over without returning, up to
+// this many bytes at a time so a chunk's scan doesn't run far past its end.
+const MAX_ASCII_SKIP: usize = 4096;
+
 fn try_ascii_string(index: usize, bytes: &[u8], min_len: usize, printable: bool) -> (Option<String>, usize) {
+    let lowest = if printable { 0x20u8 } else { 0x01 };
+    let limit = bytes.len().min(index.saturating_add(MAX_ASCII_SKIP));
+    // Find the first run with at least min_len allowed bytes; the scan
+    // resumes there, since every run before it is too short. Words with
+    // none or only allowed bytes are passed over whole.
+    let mut start = index;
+    let mut pos = index;
+    while pos < limit && pos - start < min_len {
+        if let Some(word) = bytes.get(pos..pos + 8) {
+            match allowed_bytes(u64::from_le_bytes(word.try_into().unwrap()), lowest) {
+                0 => {
+                    pos += 8;
+                    start = pos;
+                    continue;
+                },
+                HIGH_BITS => {
+                    pos += 8;
+                    continue;
+                },
+                _ => (),
+            }
+        }
+        if !(lowest..=0x7f).contains(&bytes[pos]) {
+            start = pos + 1;
+        }
+        pos += 1;
+    }
+    if start > index {
+        return (None, start - index)
+    }
+    let len = ascii_run_len(bytes, index, lowest);
+    // A run ended by a byte above 0x7f isn't ASCII text.
+    if len < min_len || bytes.get(index + len).is_some_and(|&b| b > 0x7f) {
+        return (None, len + 1)
+    }
+    (Some(String::from_utf8_lossy(&bytes[index..index + len]).into_owned()), len + 1)
+}
+
+// Multi-byte characters must be valid UTF-8.
+fn try_utf8_string(index: usize, bytes: &[u8], min_len: usize, printable: bool) -> (Option<String>, usize) {
     let mut len = 0usize;
+    let mut chars = 0usize;
     while index + len < bytes.len() {
-        if (printable && bytes[index + len] < 0x20u8) || bytes[index + len] == 0 {
+        let b = bytes[index + len];
+        if (printable && b < 0x20u8) || b == 0 {
             break;
         }
-        else if bytes[index + len] <= 0x7fu8 {
-            len += 1;
-            continue;
+        let size = match b {
+            0x00..=0x7f => 1,
+            0xc2..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf4 => 4,
+            _ => 0,
+        };
+        match bytes.get(index + len..index + len + size).map(std::str::from_utf8) {
+            Some(Ok(_)) if size > 0 => {
+                len += size;
+                chars += 1;
+            },
+            _ => break,
         }
-        else {
-            return (None, len + 1);
+    }
+    if chars >= min_len {
+        return (Some(String::from_utf8_lossy(&bytes[index..index + len]).into_owned()), len + 1)
+    }
+    (None, len + 1)
+}
+
+// ASCII and Latin-1 text in 16-bit code units. Other code units are rejected,
+// as almost any pair of bytes would otherwise read as some character.
+fn try_utf16_string(index: usize, bytes: &[u8], min_len: usize, printable: bool, big_endian: bool) -> (Option<String>, usize) {
+    let mut text = String::new();
+    let mut len = 0usize;
+    while let Some(unit) = bytes.get(index + len..index + len + 2) {
+        let unit = if big_endian { u16::from_be_bytes([unit[0], unit[1]]) } else { u16::from_le_bytes([unit[0], unit[1]]) };
+        let accepted = match unit {
+            0 => false,
+            0x01..=0x1f => !printable,
+            0x20..=0x7f | 0xa0..=0xff => true,
+            _ => false,
+        };
+        if !accepted {
+            break;
         }
+        text.push(char::from(unit as u8));
+        len += 2;
     }
-    if len >= min_len {
-        return (Some(String::from_utf8_lossy(&bytes[index..index + len]).into_owned()), len + 1);
+    if len / 2 >= min_len {
+        return (Some(text), len + 2)
     }
-    else {
-        return (None, len + 1)
+    // Step a single byte, so strings at odd offsets are found too.
+    (None, 1)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Ascii,
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Ascii => "ascii",
+            Encoding::Utf8 => "utf8",
+            Encoding::Utf16Le => "utf16le",
+            Encoding::Utf16Be => "utf16be",
+        }
+    }
+
+    // Parses a comma-separated list such as "ascii,utf16le". "utf16" is both
+    // byte orders and "all" is every encoding but ascii, which utf8 includes.
+    pub fn parse_list(s: &str) -> Option<Vec<Encoding>> {
+        let mut out = Vec::<Encoding>::new();
+        for name in s.split(',') {
+            match name.trim().to_ascii_lowercase().as_str() {
+                "ascii" => out.push(Encoding::Ascii),
+                "utf8" | "utf-8" => out.push(Encoding::Utf8),
+                "utf16le" | "utf-16le" => out.push(Encoding::Utf16Le),
+                "utf16be" | "utf-16be" => out.push(Encoding::Utf16Be),
+                "utf16" | "utf-16" => out.extend([Encoding::Utf16Le, Encoding::Utf16Be]),
+                "all" => out.extend([Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be]),
+                _ => return None,
+            }
+        }
+        Some(out)
     }
 }
 
-pub fn get_strings(bytes: &[u8], min_len: usize, printable: bool) -> Vec<String> {
-    let mut index = 0usize;
-    let mut strings = Vec::<String>::new();
-    while index < bytes.len() {
-        let (str, size) = try_ascii_string(index, bytes, min_len, printable);
-        if let Some(s) = str {
-            strings.push(s);
+pub struct FoundString {
+    // File offset of the first byte.
+    pub offset: usize,
+    pub encoding: Encoding,
+    pub text: String,
+    // Where the program maps the string, filled in by locate_strings.
+    pub addr: Option<u64>,
+    pub section: Option<String>,
+}
+
+// Inputs are split into chunks of at least this many bytes, each scanned on its own thread.
+const MIN_CHUNK_SIZE: usize = 1 << 20;
+// Positions of a chunk's scan kept to find where the scan before it joins up.
+const JOIN_STEPS: usize = 64;
+
+fn try_string(encoding: Encoding, index: usize, bytes: &[u8], min_len: usize, printable: bool) -> (Option<String>, usize) {
+    match encoding {
+        Encoding::Ascii => try_ascii_string(index, bytes, min_len, printable),
+        Encoding::Utf8 => try_utf8_string(index, bytes, min_len, printable),
+        Encoding::Utf16Le => try_utf16_string(index, bytes, min_len, printable, false),
+        Encoding::Utf16Be => try_utf16_string(index, bytes, min_len, printable, true),
+    }
+}
+
+struct ChunkScan {
+    // The first positions tried, in order.
+    steps: Vec<usize>,
+    // Where the scan stopped, at or past the chunk's end.
+    end: usize,
+}
+
+// Tries positions from `start` until reaching `end`. Strings may run past the end.
+fn scan_chunk(bytes: &[u8], start: usize, end: usize, min_len: usize, printable: bool, encoding: Encoding, strings: &mut Vec<FoundString>) -> ChunkScan {
+    let mut steps = Vec::<usize>::new();
+    let mut index = start;
+    while index < end {
+        if steps.len() < JOIN_STEPS {
+            steps.push(index);
+        }
+        let (str, size) = try_string(encoding, index, bytes, min_len, printable);
+        if let Some(text) = str {
+            strings.push(FoundString { offset: index, encoding, text, addr: None, section: None });
         }
         index += size;
     }
+    ChunkScan { steps, end: index }
+}
+
+// Adds the strings of one encoding, in file order. Large inputs are scanned in
+// chunks in parallel. A chunk's scan starts at its first byte, which may be
+// inside a string the chunk before it found, so the scan from where that
+// chunk stopped is continued until it tries a position the chunk's scan
+// tried too. From there both scans are the same, so the result matches
+// scanning the whole input on one thread.
+fn scan_strings(bytes: &[u8], min_len: usize, printable: bool, encoding: Encoding, strings: &mut Vec<FoundString>) {
+    let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let chunks = (bytes.len() / MIN_CHUNK_SIZE).clamp(1, threads);
+    if chunks == 1 {
+        scan_chunk(bytes, 0, bytes.len(), min_len, printable, encoding, strings);
+        return
+    }
+    let chunk_end = |i: usize| bytes.len() * (i + 1) / chunks;
+    let scans: Vec<(Vec<FoundString>, ChunkScan)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..chunks).map(|i| {
+            let start = if i == 0 { 0 } else { chunk_end(i - 1) };
+            scope.spawn(move || {
+                let mut found = Vec::<FoundString>::new();
+                let scan = scan_chunk(bytes, start, chunk_end(i), min_len, printable, encoding, &mut found);
+                (found, scan)
+            })
+        }).collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+
+    let mut index = 0usize;
+    for (i, (found, scan)) in scans.into_iter().enumerate() {
+        let end = chunk_end(i);
+        let mut steps = scan.steps.iter().peekable();
+        while index < end {
+            while steps.next_if(|&&step| step < index).is_some() {}
+            if steps.peek() == Some(&&index) {
+                strings.extend(found.into_iter().filter(|s| s.offset >= index));
+                index = scan.end;
+                break;
+            }
+            let (str, size) = try_string(encoding, index, bytes, min_len, printable);
+            if let Some(text) = str {
+                strings.push(FoundString { offset: index, encoding, text, addr: None, section: None });
+            }
+            index += size;
+        }
+    }
+}
+
+// With a filter, only the strings it matches are returned.
+pub fn get_strings(bytes: &[u8], min_len: usize, printable: bool, filter: Option<&Regex>) -> Vec<String> {
+    find_strings(bytes, min_len, printable, &[Encoding::Ascii], filter).into_iter().map(|s| s.text).collect()
+}
+
+// ASCII strings of at least options.min_string_len characters, in file order.
+pub fn strings(bytes: &[u8], options: &AnalysisOptions) -> Vec<FoundString> {
+    find_strings(bytes, options.min_string_len.max(1), false, &[Encoding::Ascii], None)
+}
+
+// Strings of at least `min_len` characters in each of the encodings, in file order.
+pub fn find_strings(bytes: &[u8], min_len: usize, printable: bool, encodings: &[Encoding], filter: Option<&Regex>) -> Vec<FoundString> {
+    let mut strings = Vec::<FoundString>::new();
+    for encoding in encodings {
+        scan_strings(bytes, min_len, printable, *encoding, &mut strings);
+    }
+    strings.sort_by_key(|s| s.offset);
+    // UTF-16 text read one byte off in the other byte order looks like a string
+    // too; keep whichever starts first.
+    let is_utf16 = |e: Encoding| e == Encoding::Utf16Le || e == Encoding::Utf16Be;
+    let mut end = [0usize; 2];
+    strings.retain(|s| {
+        if !is_utf16(s.encoding) {
+            return true
+        }
+        let (this, other) = if s.encoding == Encoding::Utf16Le { (0, 1) } else { (1, 0) };
+        if s.offset < end[other] {
+            return false
+        }
+        end[this] = s.offset + s.text.chars().count() * 2;
+        true
+    });
+    if let Some(filter) = filter {
+        strings.retain(|s| filter.is_match(&s.text));
+    }
     strings
-}
\ No newline at end of file
+}
+
+// Fills in the section and load address of strings found in the file a program was loaded from.
+pub fn locate_strings(program: &Program, strings: &mut [FoundString]) {
+    for found in strings {
+        if let Some((section, addr)) = program.section_at_offset(found.offset as u64) {
+            found.section = Some(section.to_string());
+            found.addr = addr;
+        }
+    }
+}
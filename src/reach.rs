@@ -0,0 +1,260 @@
+// Reachability of the code section: which instructions can run when execution
+// starts from the entry point and exported functions (and, optionally, every
+// function symbol),
+// following branches, calls, resolved indirect branches and code pointers
+// stored in data. Code never reached is dead, only entered in ways the
+// analysis can't see, or hidden.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::dis::{self, Disassembly, Flow};
+use crate::headers;
+use crate::imports;
+use crate::resolve;
+use crate::util::RWX_EXEC;
+
+pub struct Region {
+    pub start: u64,
+    // Address one past the last instruction.
+    pub end: u64,
+    pub instructions: usize,
+    // The function symbol the region lies in, if any.
+    pub function: Option<String>,
+    // The region starts exactly at that function, so the whole function is never called.
+    pub whole_function: bool,
+}
+
+// Where the executable bytes of a program went.
+pub struct Coverage {
+    // Bytes in executable segments, or in the code section when there are none.
+    pub executable: u64,
+    // Bytes of the code section, which is what gets disassembled.
+    pub disassembled: u64,
+    pub reached: u64,
+    // Unreached bytes that are alignment padding.
+    pub padding: u64,
+    // Unreached bytes that don't decode, likely data in the code or a missed entry.
+    pub undecoded: u64,
+    // Other unreached instructions.
+    pub unreached: u64,
+    // File bytes past the end of the last segment or section, e.g. appended data.
+    pub overlay: u64,
+}
+
+pub struct Reachability {
+    pub seeds: Vec<u64>,
+    // Addresses of every instruction reached.
+    pub reached: BTreeSet<u64>,
+    addrs: Vec<u64>,
+    sizes: Vec<usize>,
+}
+
+// Code addresses held in the program's constructor and destructor tables.
+fn pointer_table_seeds(dis: &Disassembly) -> Vec<u64> {
+    let program = dis.program();
+    let word: u8 = if program.bits == 64 { 8 } else { 4 };
+    let mut seeds = Vec::<u64>::new();
+    for name in [".preinit_array", ".init_array", ".fini_array", ".ctors", ".dtors"] {
+        if let Some(section) = program.section_table.get(name) {
+            for i in 0..section.bytes.len() / word as usize {
+                if let Some(ptr) = resolve::read_program_value(program, section.addr + i as u64 * word as u64, word) {
+                    seeds.push(ptr);
+                }
+            }
+        }
+    }
+    seeds
+}
+
+impl Reachability {
+    // Walks the code from the seeds. With `all_symbols`, every function symbol is a seed too.
+    pub fn build(dis: &Disassembly, all_symbols: bool) -> Reachability {
+        let program = dis.program();
+        let listing = &dis.section().instructions;
+        let instrs = listing.instruction_vec();
+        let base = dis.section_addr();
+        let addrs: Vec<u64> = listing.instruction_offset_vec_in(0..usize::MAX).iter().map(|o| base + *o as u64).collect();
+        let sizes = listing.instruction_size_vec_in(0..usize::MAX);
+        let index: HashMap<u64, usize> = addrs.iter().enumerate().map(|(i, a)| (*a, i)).collect();
+        let resolved = resolve::resolve_indirect_in(dis, 0..usize::MAX);
+
+        // PE sections are placed at their file offsets, while the entry point is relative to the image.
+        let entry = match program.format {
+            "pe" => program.program_table.iter()
+                .find(|seg| program.entry_point >= seg.vaddr && program.entry_point < seg.vaddr + seg.size as u64)
+                .map_or(program.entry_point, |seg| program.entry_point - seg.vaddr + seg.offset),
+            _ => program.entry_point,
+        };
+        let mut seeds = vec![entry];
+        seeds.extend(pointer_table_seeds(dis));
+        seeds.extend(imports::exports(program).into_iter().map(|(_, addr)| addr));
+        // Code whose address is taken, e.g. callbacks passed in registers.
+        resolve::walk_in(dis, 0..usize::MAX, |state, ins, addr, size| {
+            let copy = ins.opcode == "mov" && matches!(ins.operands.get(1), Some(dis::Operand::Register(_)));
+            if matches!(ins.opcode, "mov" | "add" | "ldr" | "ld" | "lw") && !copy {
+                if let Some(value) = state.result(ins, addr, size).filter(|v| index.contains_key(v)) {
+                    seeds.push(value);
+                }
+            }
+        });
+        // Function pointer tables in data, such as vtables and handler arrays.
+        let functions: BTreeSet<u64> = program.symbol_table.iter().filter(|sym| sym.is_func).map(|sym| sym.addr).collect();
+        let code = program.code_section();
+        let word: u8 = if program.bits == 64 { 8 } else { 4 };
+        for (name, section) in &program.section_table {
+            if name.as_str() == code || section.addr == 0 {
+                continue;
+            }
+            for i in 0..section.bytes.len() / word as usize {
+                match resolve::read_program_value(program, section.addr + i as u64 * word as u64, word) {
+                    Some(ptr) if functions.contains(&ptr) => seeds.push(ptr),
+                    _ => (),
+                }
+            }
+        }
+        if all_symbols {
+            seeds.extend(program.symbol_table.iter().filter(|sym| sym.is_func && sym.addr != 0).map(|sym| sym.addr));
+        }
+        seeds.sort();
+        seeds.dedup();
+        seeds.retain(|seed| index.contains_key(seed));
+
+        let mut reached = BTreeSet::<u64>::new();
+        let mut work: Vec<usize> = seeds.iter().map(|seed| index[seed]).collect();
+        while let Some(mut i) = work.pop() {
+            // Follow the straight line of code from here until control leaves it.
+            while i < instrs.len() && reached.insert(addrs[i]) {
+                let (ins, addr) = (&instrs[i], addrs[i]);
+                if let Some(t) = dis::call_target(ins, addr).and_then(|t| index.get(&t)) {
+                    work.push(*t);
+                }
+                let flow = match resolved.get(&addr) {
+                    Some(r) if r.is_call => {
+                        if let Some(t) = index.get(&r.target) {
+                            work.push(*t);
+                        }
+                        Flow::Next
+                    },
+                    Some(r) if ins.cond() == dis::COND_AL => Flow::Jump(Some(r.target)),
+                    Some(r) => Flow::Branch(Some(r.target)),
+                    None => dis::flow(ins, addr),
+                };
+                match flow {
+                    Flow::Next => i += 1,
+                    Flow::Branch(target) => {
+                        if let Some(t) = target.and_then(|t| index.get(&t)) {
+                            work.push(*t);
+                        }
+                        i += 1;
+                    },
+                    Flow::Jump(target) => {
+                        if let Some(t) = target.and_then(|t| index.get(&t)) {
+                            work.push(*t);
+                        }
+                        break;
+                    },
+                    Flow::Stop => break,
+                }
+            }
+        }
+        Reachability { seeds, reached, addrs, sizes }
+    }
+
+    pub fn is_reached(&self, addr: u64) -> bool {
+        self.reached.contains(&addr)
+    }
+
+    // Reached and total instruction counts.
+    pub fn coverage(&self) -> (usize, usize) {
+        (self.reached.len(), self.addrs.len())
+    }
+
+    // Whether each instruction is alignment padding.
+    fn padding(&self, dis: &Disassembly) -> Vec<bool> {
+        let program = dis.program();
+        let bytes = program.section_table.get(program.code_section()).map(|section| section.bytes.as_slice()).unwrap_or(&[]);
+        let base = dis.section_addr();
+        let instrs = dis.section().instructions.instruction_vec();
+        (0..self.addrs.len()).map(|i| {
+            let start = (self.addrs[i] - base) as usize;
+            let raw = bytes.get(start..start + self.sizes[i]).unwrap_or(&[]);
+            instrs[i].opcode == "nop" || raw.iter().all(|b| *b == 0x00 || *b == 0xcc || *b == 0x90)
+        }).collect()
+    }
+
+    // Byte counts of reached and unreached code. `bytes` is the whole input file,
+    // for finding appended data.
+    pub fn coverage_bytes(&self, dis: &Disassembly, bytes: &[u8]) -> Coverage {
+        let program = dis.program();
+        let padding = self.padding(dis);
+        let instrs = dis.section().instructions.instruction_vec();
+        let mut coverage = Coverage { executable: 0, disassembled: 0, reached: 0, padding: 0, undecoded: 0, unreached: 0, overlay: 0 };
+        coverage.disassembled = program.section_table.get(program.code_section()).map_or(0, |section| section.bytes.len() as u64);
+        coverage.executable = program.program_table.iter()
+            .filter(|seg| seg.perm & RWX_EXEC != 0)
+            .map(|seg| seg.size as u64)
+            .sum();
+        if coverage.executable == 0 {
+            coverage.executable = coverage.disassembled;
+        }
+        for i in 0..self.addrs.len() {
+            let size = self.sizes[i] as u64;
+            if self.is_reached(self.addrs[i]) {
+                coverage.reached += size;
+            }
+            else if padding[i] {
+                coverage.padding += size;
+            }
+            else if instrs[i].opcode == "unk" {
+                coverage.undecoded += size;
+            }
+            else {
+                coverage.unreached += size;
+            }
+        }
+        if let Some(end) = headers::image_size(bytes) {
+            coverage.overlay = (bytes.len() as u64).saturating_sub(end);
+        }
+        coverage
+    }
+
+    // Runs of unreached instructions, leaving out alignment padding.
+    pub fn unreachable_regions(&self, dis: &Disassembly) -> Vec<Region> {
+        let program = dis.program();
+        let padding = self.padding(dis);
+        let is_padding = |i: usize| padding[i];
+
+        let mut regions = Vec::<Region>::new();
+        let mut i = 0;
+        while i < self.addrs.len() {
+            if self.is_reached(self.addrs[i]) {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < self.addrs.len() && !self.is_reached(self.addrs[i]) {
+                i += 1;
+            }
+            // Trim padding from both ends, and drop regions that are nothing but padding.
+            let (mut first, mut last) = (start, i);
+            while first < last && is_padding(first) {
+                first += 1;
+            }
+            while last > first && is_padding(last - 1) {
+                last -= 1;
+            }
+            if first == last {
+                continue;
+            }
+            let function = program.function_at(self.addrs[first]);
+            regions.push(Region {
+                start: self.addrs[first],
+                end: self.addrs[last - 1] + self.sizes[last - 1] as u64,
+                instructions: last - first,
+                function: function.map(|sym| sym.name.clone()),
+                whole_function: function.map_or(false, |sym| sym.addr == self.addrs[first]),
+            });
+        }
+        regions
+    }
+}
@@ -0,0 +1,290 @@
+// Registers of every architecture, identified by number rather than by name so
+// analyses can compare them, look up what they're used for and find the full
+// register a part belongs to. Names are only for display.
+
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Arch {
+    X86,
+    Arm,
+    RiscV,
+    AArch64,
+}
+
+impl Arch {
+    // The architecture of a Program::machine_type, e.g. "amd64" -> X86.
+    pub fn from_machine_type(machine_type: &str) -> Option<Arch> {
+        match machine_type {
+            "x86" | "amd64" => Some(Arch::X86),
+            "arm" => Some(Arch::Arm),
+            "riscv" => Some(Arch::RiscV),
+            "aarch64" => Some(Arch::AArch64),
+            _ => None,
+        }
+    }
+
+    fn id(self) -> u16 {
+        match self {
+            Arch::X86 => 1,
+            Arch::Arm => 2,
+            Arch::RiscV => 3,
+            Arch::AArch64 => 4,
+        }
+    }
+}
+
+// What a register is used for, beyond holding values.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RegClass {
+    General,
+    StackPointer,
+    FramePointer,
+    // Holds the return address of calls.
+    Link,
+    ProgramCounter,
+    // Always reads as zero.
+    Zero,
+}
+
+// The architecture in the high byte and its register number in the low byte.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct Reg(u16);
+
+// x86 registers are numbered by size, in the encoding order within each size:
+// 64-bit, 32-bit, 16-bit, low bytes, then the high bytes ah..bh and the
+// instruction pointer.
+const X86_NAMES: [&str; 69] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15",
+    "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d", "r12d", "r13d", "r14d", "r15d",
+    "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w", "r12w", "r13w", "r14w", "r15w",
+    "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b", "r13b", "r14b", "r15b",
+    "ah", "ch", "dh", "bh",
+    // Printed as pc like on the other architectures.
+    "pc",
+];
+const X86_HIGH_BYTES: u16 = 64;
+const X86_IP: u16 = 68;
+
+const ARM_NAMES: [&str; 16] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "fp", "ip", "sp", "lr", "pc",
+];
+
+const RISCV_NAMES: [&str; 32] = [
+    "Zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+// AArch64 registers are the 64-bit x registers with sp as number 31, the
+// 32-bit w registers with wsp, then the zero registers and the pc.
+const AARCH64_NAMES: [&str; 67] = [
+    "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14", "x15",
+    "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27", "x28", "x29", "x30", "sp",
+    "w0", "w1", "w2", "w3", "w4", "w5", "w6", "w7", "w8", "w9", "w10", "w11", "w12", "w13", "w14", "w15",
+    "w16", "w17", "w18", "w19", "w20", "w21", "w22", "w23", "w24", "w25", "w26", "w27", "w28", "w29", "w30", "wsp",
+    "xzr", "wzr", "pc",
+];
+const AARCH64_XZR: u16 = 64;
+const AARCH64_WZR: u16 = 65;
+const AARCH64_PC: u16 = 66;
+
+impl Reg {
+    pub const RAX: Reg = Reg::x86(0);
+    pub const RCX: Reg = Reg::x86(1);
+    pub const RDX: Reg = Reg::x86(2);
+    pub const RSP: Reg = Reg::x86(4);
+    pub const RBP: Reg = Reg::x86(5);
+    pub const RSI: Reg = Reg::x86(6);
+    pub const RDI: Reg = Reg::x86(7);
+    pub const R8: Reg = Reg::x86(8);
+    pub const R9: Reg = Reg::x86(9);
+    pub const R10: Reg = Reg::x86(10);
+    pub const EAX: Reg = Reg::x86(16);
+    pub const ECX: Reg = Reg::x86(17);
+    pub const EDX: Reg = Reg::x86(18);
+    pub const EBX: Reg = Reg::x86(19);
+    pub const ESP: Reg = Reg::x86(20);
+    pub const EBP: Reg = Reg::x86(21);
+    pub const ESI: Reg = Reg::x86(22);
+    pub const EDI: Reg = Reg::x86(23);
+    pub const X86_PC: Reg = Reg::x86(X86_IP);
+
+    pub const ARM_SP: Reg = Reg::arm(13);
+    pub const ARM_PC: Reg = Reg::arm(15);
+
+    pub const RISCV_SP: Reg = Reg::riscv(2);
+    pub const RISCV_A0: Reg = Reg::riscv(10);
+    pub const RISCV_A1: Reg = Reg::riscv(11);
+    pub const RISCV_A2: Reg = Reg::riscv(12);
+    pub const RISCV_A3: Reg = Reg::riscv(13);
+    pub const RISCV_A4: Reg = Reg::riscv(14);
+    pub const RISCV_A5: Reg = Reg::riscv(15);
+    pub const RISCV_A6: Reg = Reg::riscv(16);
+    pub const RISCV_A7: Reg = Reg::riscv(17);
+
+    pub const AARCH64_SP: Reg = Reg::aarch64(31);
+    pub const AARCH64_PC: Reg = Reg::aarch64(AARCH64_PC as u8);
+
+    const fn x86(n: u16) -> Reg {
+        Reg(1 << 8 | n)
+    }
+
+    // An x86 register by its encoding and size in bytes. A size of 0 is a
+    // high byte register, for encodings 4-7 without a REX prefix.
+    pub fn x86_sized(n: u8, size: u8) -> Reg {
+        let n = u16::from(n & 0xf);
+        match size {
+            8 => Reg::x86(n),
+            4 => Reg::x86(16 + n),
+            2 => Reg::x86(32 + n),
+            1 => Reg::x86(48 + n),
+            _ if (4..8).contains(&n) => Reg::x86(X86_HIGH_BYTES + n - 4),
+            _ => Reg::x86(48 + n),
+        }
+    }
+
+    pub const fn arm(n: u8) -> Reg {
+        Reg(2 << 8 | (n & 0xf) as u16)
+    }
+
+    pub const fn riscv(n: u8) -> Reg {
+        Reg(3 << 8 | (n & 0x1f) as u16)
+    }
+
+    // An AArch64 register by its index in AARCH64_NAMES, e.g. 0 for x0.
+    pub const fn aarch64(n: u8) -> Reg {
+        Reg(4 << 8 | n as u16)
+    }
+
+    // An AArch64 general register by its encoding. Encoding 31 is the stack
+    // pointer in some operands and the zero register in the rest.
+    pub fn aarch64_sized(n: u8, is64: bool, sp: bool) -> Reg {
+        let n = u16::from(n & 0x1f);
+        let i = match (n == 31 && !sp, is64) {
+            (true, true) => AARCH64_XZR,
+            (true, false) => AARCH64_WZR,
+            (false, true) => n,
+            (false, false) => 32 + n,
+        };
+        Reg(4 << 8 | i)
+    }
+
+    // A register by its name on the architecture, e.g. "eax" for x86.
+    pub fn parse(arch: Arch, name: &str) -> Option<Reg> {
+        let names: &[&str] = match arch {
+            Arch::X86 => &X86_NAMES,
+            Arch::Arm => &ARM_NAMES,
+            Arch::RiscV => &RISCV_NAMES,
+            Arch::AArch64 => &AARCH64_NAMES,
+        };
+        names.iter().position(|n| *n == name).map(|i| Reg(arch.id() << 8 | i as u16))
+    }
+
+    pub fn arch(self) -> Arch {
+        match self.0 >> 8 {
+            1 => Arch::X86,
+            2 => Arch::Arm,
+            4 => Arch::AArch64,
+            _ => Arch::RiscV,
+        }
+    }
+
+    fn index(self) -> u16 {
+        self.0 & 0xff
+    }
+
+    // The number the register is encoded as in instructions, e.g. 1 for rcx,
+    // ecx, cx and cl, 5 for ch and 10 for RISC-V a0.
+    pub fn number(self) -> u8 {
+        let i = self.index();
+        match self.arch() {
+            Arch::X86 if (X86_HIGH_BYTES..X86_IP).contains(&i) => (i - X86_HIGH_BYTES + 4) as u8,
+            Arch::X86 => (i % 16) as u8,
+            Arch::AArch64 if i < AARCH64_XZR => (i % 32) as u8,
+            Arch::AArch64 => 31,
+            _ => i as u8,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        let i = self.index() as usize;
+        match self.arch() {
+            Arch::X86 => X86_NAMES[i],
+            Arch::Arm => ARM_NAMES[i],
+            Arch::RiscV => RISCV_NAMES[i],
+            Arch::AArch64 => AARCH64_NAMES[i],
+        }
+    }
+
+    pub fn class(self) -> RegClass {
+        let i = self.index();
+        match self.arch() {
+            Arch::X86 if i == X86_IP => RegClass::ProgramCounter,
+            Arch::X86 if i < X86_HIGH_BYTES && i % 16 == 4 => RegClass::StackPointer,
+            Arch::X86 if i < X86_HIGH_BYTES && i % 16 == 5 => RegClass::FramePointer,
+            Arch::Arm => match i {
+                11 => RegClass::FramePointer,
+                13 => RegClass::StackPointer,
+                14 => RegClass::Link,
+                15 => RegClass::ProgramCounter,
+                _ => RegClass::General,
+            },
+            Arch::RiscV => match i {
+                0 => RegClass::Zero,
+                1 => RegClass::Link,
+                2 => RegClass::StackPointer,
+                8 => RegClass::FramePointer,
+                _ => RegClass::General,
+            },
+            Arch::AArch64 => match i {
+                AARCH64_XZR | AARCH64_WZR => RegClass::Zero,
+                AARCH64_PC => RegClass::ProgramCounter,
+                31 | 63 => RegClass::StackPointer,
+                29 | 61 => RegClass::FramePointer,
+                30 | 62 => RegClass::Link,
+                _ => RegClass::General,
+            },
+            _ => RegClass::General,
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        let i = self.index();
+        match self.arch() {
+            Arch::X86 if i == X86_IP => 64,
+            Arch::X86 if i >= X86_HIGH_BYTES => 8,
+            Arch::X86 => 64 >> (i / 16),
+            Arch::Arm => 32,
+            // XLEN, counted as the widest.
+            Arch::RiscV => 64,
+            Arch::AArch64 if (32..64).contains(&i) || i == AARCH64_WZR => 32,
+            Arch::AArch64 => 64,
+        }
+    }
+
+    // The full register this one is part of, with the bit offset and width of
+    // the part, e.g. ah -> (rax, 8, 8) and w0 -> (x0, 0, 32). Registers of the
+    // other architectures are their own full register.
+    pub fn part(self) -> (Reg, u32, u32) {
+        let i = self.index();
+        match self.arch() {
+            Arch::X86 if i == X86_IP => (self, 0, 64),
+            Arch::X86 if i >= X86_HIGH_BYTES => (Reg::x86(i - X86_HIGH_BYTES), 8, 8),
+            Arch::X86 => (Reg::x86(i % 16), 0, self.bits()),
+            Arch::AArch64 if (32..64).contains(&i) => (Reg::aarch64((i - 32) as u8), 0, 32),
+            Arch::AArch64 if i == AARCH64_WZR => (Reg::aarch64(AARCH64_XZR as u8), 0, 32),
+            _ => (self, 0, self.bits()),
+        }
+    }
+
+    // The full register this one is part of, e.g. eax -> rax.
+    pub fn full(self) -> Reg {
+        self.part().0
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
@@ -0,0 +1,265 @@
+// Resolves indirect branches and calls (`jmp rax`, `call [rip+x]`, `bx r3`,
+// `jalr a5`) whose target is built from constants or loaded from a fixed
+// address, by tracking known register values through each basic block.
+// Values aren't carried across block boundaries or calls.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Range;
+
+use crate::dis::{self, Disassembly, Instruction};
+use crate::prog::Program;
+use crate::util::{self, BIG_ENDIAN, LITTLE_ENDIAN};
+use crate::reg::{Arch, Reg, RegClass};
+
+#[derive(Clone, Copy)]
+pub struct Resolved {
+    pub target: u64,
+    pub is_call: bool,
+}
+
+pub(crate) struct State<'a> {
+    program: &'a Program,
+    word_size: u8,
+    regs: HashMap<Reg, u64>,
+}
+
+// Reads a value of the given size from the program image, if the address is mapped.
+pub fn read_program_value(program: &Program, addr: u64, size: u8) -> Option<u64> {
+    let endianness = if program.endianess == BIG_ENDIAN { BIG_ENDIAN } else { LITTLE_ENDIAN };
+    for section in program.section_table.values() {
+        let start = match addr.checked_sub(section.addr) {
+            Some(start) if start < section.bytes.len() as u64 => start as usize,
+            _ => continue,
+        };
+        let value = match size {
+            1 => util::read_u8_checked(&section.bytes, start, "program image").map(u64::from),
+            2 => util::read_u16_checked(&section.bytes, start, endianness, "program image").map(u64::from),
+            4 => util::read_u32_checked(&section.bytes, start, endianness, "program image").map(u64::from),
+            8 => util::read_u64_checked(&section.bytes, start, endianness, "program image"),
+            _ => return None,
+        };
+        // A value running past the end of the section may be in an adjacent one.
+        if let Ok(value) = value {
+            return Some(value)
+        }
+    }
+    None
+}
+
+impl<'a> State<'a> {
+    fn register_key(&self, r: Reg) -> (Reg, u32, u32) {
+        match r.arch() {
+            Arch::X86 | Arch::AArch64 => r.part(),
+            _ => (r, 0, self.word_size as u32 * 8),
+        }
+    }
+
+    pub(crate) fn get(&self, r: Reg) -> Option<u64> {
+        if r.class() == RegClass::Zero {
+            return Some(0)
+        }
+        let (key, shift, bits) = self.register_key(r);
+        let v = self.regs.get(&key)? >> shift;
+        Some(if bits >= 64 { v } else { v & ((1 << bits) - 1) })
+    }
+
+    // Only full-width writes (and x86 32-bit writes, which clear the upper half) are tracked.
+    fn set(&mut self, r: Reg, value: Option<u64>) {
+        let (key, shift, bits) = self.register_key(r);
+        match value {
+            Some(v) if shift == 0 && bits >= 32 => {
+                let v = if bits >= 64 { v } else { v & ((1 << bits) - 1) };
+                self.regs.insert(key, v);
+            },
+            _ => { self.regs.remove(&key); },
+        }
+    }
+
+    // The address a memory operand refers to, when the base register is known.
+    pub(crate) fn address(&self, op: &dis::Operand, addr: u64, size: usize) -> Option<u64> {
+        match *op {
+            // x86 rip-relative displacements are i32.
+            dis::Operand::Memory(Some(Reg::X86_PC), None, _, offset, _) => Some((addr + size as u64).wrapping_add(offset as i32 as i64 as u64)),
+            dis::Operand::Memory(Some(Reg::ARM_PC), None, _, offset, _) => Some((addr + 8).wrapping_add(offset as u64)),
+            dis::Operand::Memory(Some(Reg::AARCH64_PC), None, _, offset, _) => Some(addr.wrapping_add(offset as u64)),
+            dis::Operand::Memory(None, None, _, offset, _) => Some(offset as u64),
+            dis::Operand::Memory(Some(base), None, _, offset, _) => Some(self.get(base)?.wrapping_add(offset as u64)),
+            dis::Operand::Memory(Some(base), Some(index), scale, offset, _) => {
+                let index = self.get(index)?.wrapping_mul(scale.max(1) as u64);
+                Some(self.get(base)?.wrapping_add(index).wrapping_add(offset as u64))
+            },
+            _ => None,
+        }
+    }
+
+    pub(crate) fn value(&self, op: &dis::Operand, addr: u64, size: usize) -> Option<u64> {
+        match *op {
+            dis::Operand::Immediate(i) => Some(i as u64),
+            dis::Operand::Register(Reg::ARM_PC) => Some(addr + 8),
+            dis::Operand::Register(r) => self.get(r),
+            dis::Operand::Memory(_, _, _, _, mem_size) => read_program_value(self.program, self.address(op, addr, size)?, mem_size),
+            dis::Operand::ShiftedRegister(r, dis::SHIFT_LSL, amount) => Some(self.get(r)? << amount),
+            dis::Operand::ShiftedRegister(r, dis::SHIFT_LSR, amount) => Some(self.get(r)? >> amount),
+            _ => None,
+        }
+    }
+
+    fn binary(&self, ins: &Instruction, addr: u64, size: usize, f: fn(u64, u64) -> u64) -> Option<u64> {
+        let lhs = self.value(ins.operands.get(1)?, addr, size)?;
+        let rhs = self.value(ins.operands.get(2)?, addr, size)?;
+        Some(f(lhs, rhs))
+    }
+
+    // The value an instruction writes to its destination register, if it can be worked out.
+    pub(crate) fn result(&self, ins: &Instruction, addr: u64, size: usize) -> Option<u64> {
+        match ins.opcode {
+            "mov" | "ldr" | "zext" if ins.flags & (dis::FLAG_PRE_INDEX | dis::FLAG_POST_INDEX | dis::FLAG_REGISTER_OFFSET) == 0 =>
+                ins.operands.get(1).and_then(|op| self.value(op, addr, size)),
+            "add" => self.binary(ins, addr, size, u64::wrapping_add),
+            "sub" => self.binary(ins, addr, size, u64::wrapping_sub),
+            "and" => self.binary(ins, addr, size, |a, b| a & b),
+            "or" => self.binary(ins, addr, size, |a, b| a | b),
+            "xor" => self.binary(ins, addr, size, |a, b| a ^ b),
+            "movt" => match ins.operands.first() {
+                Some(dis::Operand::Register(r)) => self.get(*r).zip(ins.operands.get(1).and_then(|op| self.value(op, addr, size)))
+                    .map(|(low, high)| (low & 0xffff) | (high << 16)),
+                _ => None,
+            },
+            // 32-bit lui/auipc encode imm[31:12]; the compressed c.lui already holds the shifted value.
+            "lui" => ins.operands.get(1).and_then(|op| self.value(op, addr, size))
+                .map(|imm| if size == 4 { imm << 12 } else { imm }),
+            "auipc" => ins.operands.get(1).and_then(|op| self.value(op, addr, size)).map(|imm| addr.wrapping_add(imm << 12)),
+            // AArch64 adrp is relative to the instruction's 4KB page.
+            // x86 lea yields the address of its memory operand.
+            "lea" => ins.operands.get(1).and_then(|op| self.address(op, addr, size)),
+            "adr" => ins.operands.get(1).and_then(|op| self.value(op, addr, size)).map(|imm| addr.wrapping_add(imm)),
+            "adrp" => ins.operands.get(1).and_then(|op| self.value(op, addr, size)).map(|imm| (addr & !0xfff).wrapping_add(imm)),
+            "ld" | "lw" | "lwu" => {
+                let target = self.load_address(ins, addr, size)?;
+                let load_size = match ins.opcode { "ld" => 8, _ => 4 };
+                read_program_value(self.program, target, load_size)
+                    .map(|v| if ins.opcode == "lw" { v as u32 as i32 as i64 as u64 } else { v })
+            },
+            _ => None,
+        }
+    }
+
+    // The address a RISC-V load or store (`rd, rs1, imm`) accesses.
+    pub(crate) fn load_address(&self, ins: &Instruction, addr: u64, size: usize) -> Option<u64> {
+        let base = ins.operands.get(1).and_then(|op| self.value(op, addr, size))?;
+        let offset = ins.operands.get(2).and_then(|op| self.value(op, addr, size))?;
+        Some(base.wrapping_add(offset))
+    }
+
+    // Updates the known registers for one instruction.
+    fn step(&mut self, ins: &Instruction, addr: u64, size: usize) {
+        match ins.opcode {
+            "call" | "svc" | "syscall" => {
+                self.regs.clear();
+                return
+            },
+            // Stores and compares don't write a register; anything else unknown clobbers every register operand.
+            "str" | "sb" | "sh" | "sw" | "sd" | "cmp" | "cmn" | "test" | "tst" | "teq" | "b" | "push" | "nop" => return,
+            "pop" | "ldm" => {
+                for op in &ins.operands {
+                    if let dis::Operand::Register(r) = op {
+                        self.set(*r, None);
+                    }
+                }
+                return
+            },
+            _ => (),
+        }
+        // Conditionally executed instructions leave the destination unknown.
+        let value = if ins.cond() == dis::COND_AL { self.result(ins, addr, size) } else { None };
+        if let Some(dis::Operand::Register(r)) = ins.operands.first() {
+            self.set(*r, value);
+        }
+        if ins.opcode == "ldr" && ins.flags & (dis::FLAG_PRE_INDEX | dis::FLAG_POST_INDEX) != 0 {
+            if let Some(dis::Operand::Memory(Some(base), _, _, _, _)) = ins.operands.get(1) {
+                self.set(*base, None);
+            }
+        }
+    }
+
+    // The target of an indirect branch or call, if its value is known.
+    fn indirect_target(&self, ins: &Instruction, addr: u64, size: usize) -> Option<Resolved> {
+        let (op, is_call) = match ins.opcode {
+            "b" => (ins.operands.first()?, false),
+            "call" => (ins.operands.first()?, true),
+            "jalr" => {
+                let base = ins.operands.get(1).and_then(|op| self.value(op, addr, size))?;
+                let offset = ins.operands.get(2).and_then(|op| self.value(op, addr, size)).unwrap_or(0);
+                let is_call = !matches!(ins.operands.first(), Some(dis::Operand::Register(r)) if r.class() == RegClass::Zero);
+                return Some(Resolved { target: base.wrapping_add(offset) & !1, is_call })
+            },
+            _ => return None,
+        };
+        if let dis::Operand::Immediate(_) = op {
+            return None
+        }
+        let target = self.value(op, addr, size)?;
+        // Bit 0 of an ARM interworking branch target selects Thumb state.
+        let target = if self.program.machine_type == "arm" { target & !1 } else { target };
+        Some(Resolved { target, is_call })
+    }
+}
+
+fn is_control_flow(ins: &Instruction) -> bool {
+    matches!(ins.opcode, "b" | "call" | "ret" | "jal" | "jalr" | "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" | "tbz" | "tbnz")
+        || (matches!(ins.opcode, "pop" | "ldm") && ins.operands.iter().any(|op| matches!(op, dis::Operand::Register(r) if r.class() == RegClass::ProgramCounter)))
+}
+
+// Walks the instructions in the section offset range, calling `f` with the register
+// values known just before each instruction executes.
+pub(crate) fn walk_in<F>(dis: &Disassembly, range: Range<usize>, mut f: F)
+    where F: FnMut(&State, &Instruction, u64, usize)
+{
+    let listing = &dis.section().instructions;
+    let instrs = listing.instruction_vec_in(range.clone());
+    let offsets = listing.instruction_offset_vec_in(range.clone());
+    let sizes = listing.instruction_size_vec_in(range);
+    let base = dis.section_addr();
+    let program = dis.program();
+
+    // Direct branch targets start new blocks, where nothing is known about registers.
+    let mut leaders = BTreeSet::<u64>::new();
+    for (ins, offset) in instrs.iter().zip(&offsets) {
+        let addr = base + *offset as u64;
+        let rel = match ins.opcode {
+            "b" | "call" => ins.operands.first(),
+            "jal" => ins.operands.get(1),
+            "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" | "tbz" | "tbnz" => ins.operands.get(2),
+            _ => None,
+        };
+        if let Some(dis::Operand::Immediate(rel)) = rel {
+            leaders.insert(addr.wrapping_add(*rel as u64));
+        }
+    }
+
+    let word_size = if program.bits == 0 { 4 } else { program.bits / 8 };
+    let mut state = State { program, word_size, regs: HashMap::new() };
+    for ((ins, offset), size) in instrs.iter().zip(&offsets).zip(&sizes) {
+        let addr = base + *offset as u64;
+        if leaders.contains(&addr) {
+            state.regs.clear();
+        }
+        f(&state, ins, addr, *size);
+        if is_control_flow(ins) && ins.opcode != "call" {
+            state.regs.clear();
+        } else {
+            state.step(ins, addr, *size);
+        }
+    }
+}
+
+// Resolved indirect branches in the section offset range, keyed by branch address.
+pub fn resolve_indirect_in(dis: &Disassembly, range: Range<usize>) -> BTreeMap<u64, Resolved> {
+    let mut out = BTreeMap::<u64, Resolved>::new();
+    walk_in(dis, range, |state, ins, addr, size| {
+        if let Some(resolved) = state.indirect_target(ins, addr, size) {
+            out.insert(addr, resolved);
+        }
+    });
+    out
+}
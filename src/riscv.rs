@@ -1,5 +1,7 @@
+use crate::cancel::CancelToken;
 use crate::dis::{self, DisassemblySection};
 use crate::prog::{Section, Program};
+use crate::reg::Reg;
 use crate::util::{i32_sign, BitExtr};
 
 #[derive(PartialEq)]
@@ -38,49 +40,14 @@ impl Register {
     // const T3: Register = Register(0x1c);
     // const T4: Register = Register(0x1d);
     // const T5: Register = Register(0x1e);
-    const T6: Register = Register(0x1f);
-    const COUNT: usize = Self::T6.0 as usize + 1;
+    // const T6: Register = Register(0x1f);
 
-    const REG_NAMES: [&'static str; Self::COUNT] = [
-        "Zero",
-        "ra",
-        "sp",
-        "gp",
-        "tp",
-        "t0",
-        "t1",
-        "t2",
-        "s0",
-        "s1",
-        "a0",
-        "a1",
-        "a2",
-        "a3",
-        "a4",
-        "a5",
-        "a6",
-        "a7",
-        "s2",
-        "s3",
-        "s4",
-        "s5",
-        "s6",
-        "s7",
-        "s8",
-        "s9",
-        "s10",
-        "s11",
-        "t3",
-        "t4",
-        "t5",
-        "t6",
-    ];
+    fn reg(self) -> Reg {
+        Reg::riscv(self.0)
+    }
 
     fn name(self) -> &'static str {
-        if (self.0 as usize) < Self::REG_NAMES.len() {
-            return Self::REG_NAMES[self.0 as usize]
-        }
-        "?"
+        self.reg().name()
     }
 }
 
@@ -138,6 +105,7 @@ enum Operation {
     Sh,
     Sw,
     Sd,
+    Ecall,
     Unknown,
 }
 
@@ -205,7 +173,7 @@ impl Operand {
 
     fn into(self) -> dis::Operand {
         match self {
-            Self::Reg(r) => dis::Operand::Register(Register(r).name()),
+            Self::Reg(r) => dis::Operand::Register(Register(r).reg()),
             Self::ImmU8(x) => dis::Operand::Immediate(x.into()),
             Self::ImmU16(x) => dis::Operand::Immediate(x.into()),
             Self::ImmU32(x) =>  dis::Operand::Immediate(x.into()),
@@ -337,6 +305,7 @@ impl Instruction {
             Operation::Bge   => format!("bge {}, {}, {}", self.rs1.print(), self.rs2.print(), self.imm.print()),
             Operation::Bltu  => format!("bltu {}, {}, {}", self.rs1.print(), self.rs2.print(), self.imm.print()),
             Operation::Bgeu  => format!("bgeu {}, {}, {}", self.rs1.print(), self.rs2.print(), self.imm.print()),
+            Operation::Ecall => format!("ecall"),
             Operation::Unknown => format!("???"),
             // _ => format!("unknown")
         }
@@ -382,43 +351,12 @@ impl Instruction {
             Operation::Bltu  => dis::Instruction { opcode: "bltu", operands: vec![self.rs1.into(), self.rs2.into(), self.imm.into()], flags: 0 },
             Operation::Bge   => dis::Instruction { opcode: "bge", operands: vec![self.rs1.into(), self.rs2.into(), self.imm.into()], flags: 0 },
             Operation::Bgeu  => dis::Instruction { opcode: "bgeu", operands: vec![self.rs1.into(), self.rs2.into(), self.imm.into()], flags: 0 },
+            Operation::Ecall => dis::Instruction { opcode: "syscall", operands: vec![], flags: 0 },
             _  => dis::Instruction { opcode: "unk", operands: vec![], flags: 0 },
         }
     }
 }
 
-fn instr_op_rd_rs1_rs2(op: Operation, ins: u32, offset: usize, ins_size: u8) -> Instruction {
-    let rd = rd(ins) as u8;
-    let rs1 = rs1(ins) as u8;
-    let rs2 = rs2(ins) as u8;
-    Instruction { operation: op, rd: Operand::Reg(rd), rs1: Operand::Reg(rs1), rs2: Operand::Reg(rs2), rs3: Operand::Nothing, imm: Operand::Nothing, offset, ins_size }
-}
-
-fn instr_op_rd_rs1_imm12(op: Operation, ins: u32, offset: usize, ins_size: u8) -> Instruction {
-    let rd = rd(ins) as u8;
-    let rs1 = rs1(ins) as u8;
-    let imm = imm12(ins);
-    Instruction { operation: op, rd: Operand::Reg(rd), rs1: Operand::Reg(rs1), rs2: Operand::Nothing, rs3: Operand::Nothing, imm: Operand::ImmS32(imm), offset, ins_size }
-}
-
-fn instr_op_rs1_rs2_imm12_s(op: Operation, ins: u32, offset: usize, ins_size: u8) -> Instruction {
-    let rs1 = rs1(ins) as u8;
-    let rs2 = rs2(ins) as u8;
-    let imm = imm12_s(ins);
-    Instruction { operation: op, rd: Operand::Nothing, rs1: Operand::Reg(rs1), rs2: Operand::Reg(rs2), rs3: Operand::Nothing, imm: Operand::ImmS32(imm), offset, ins_size }
-}
-
-fn instr_op_rd_rs1_shamt(op: Operation, ins: u32, offset: usize, ins_size: u8) -> Instruction {
-    let rd = rd(ins) as u8;
-    let rs1 = rs1(ins) as u8;
-    let imm = shamt(ins);
-    Instruction { operation: op, rd: Operand::Reg(rd), rs1: Operand::Reg(rs1), rs2: Operand::Nothing, rs3: Operand::Nothing, imm: Operand::ImmU32(imm), offset, ins_size }
-}
-
-fn opcode(ins: u32) -> u32 {
-    ins & 0x7f
-}
-
 fn rd(ins: u32) -> u32 {
     (ins >> 7) & 0b11111
 }
@@ -431,14 +369,6 @@ fn rs2(ins: u32) -> u32 {
     (ins >> 20) & 0b11111
 }
 
-fn funct3(ins: u32) -> u32 {
-    (ins >> 12) & 0b111
-}
-
-fn funct7(ins: u32) -> u32 {
-    ins >> 25
-}
-
 fn imm20(ins: u32) -> i32 {
     (ins as i32) >> 12
 }
@@ -469,358 +399,6 @@ fn csr(ins: u32) -> u32 {
     ins.bextr(31, 20)
 }
 
-fn instr_op_rd_imm20(op: Operation, ins: u32, offset: usize) -> Instruction {
-    let rd = rd(ins) as u8;
-    let imm = imm20(ins);
-    Instruction { operation: op, rd: Operand::Reg(rd), rs1: Operand::Nothing, rs2: Operand::Nothing, rs3: Operand::Nothing, imm: Operand::ImmS32(imm), offset, ins_size: 4 }
-}
-
-fn instr_op_rd_jimm20(op: Operation, ins: u32, offset: usize) -> Instruction {
-    let rd = rd(ins) as u8;
-    let imm = jimm20(ins);
-    Instruction { operation: op, rd: Operand::Reg(rd), rs1: Operand::Nothing, rs2: Operand::Nothing, rs3: Operand::Nothing, imm: Operand::ImmS32(imm), offset, ins_size: 4 }
-}
-
-fn instr_op_rs1_rs2_branch(op: Operation, ins: u32, offset: usize) -> Instruction {
-    let rs1 = rs1(ins) as u8;
-    let rs2 = rs2(ins) as u8;
-    let imm = branch(ins);
-    Instruction { operation: op, rd: Operand::Nothing, rs1: Operand::Reg(rs1), rs2: Operand::Reg(rs2), rs3: Operand::Nothing, imm: Operand::ImmS32(imm), offset, ins_size: 4 }
-}
-
-fn instr_op_rs1_csr(op: Operation, ins: u32, offset: usize) -> Instruction {
-    let rd = rd(ins) as u8;
-    let rs1 = rs1(ins) as u8;
-    let imm = csr(ins);
-    Instruction { operation: op, rd: Operand::Reg(rd), rs1: Operand::Reg(rs1), rs2: Operand::Nothing, rs3: Operand::Nothing, imm: Operand::ImmU32(imm), offset, ins_size: 4 }
-}
-
-fn disassemble_lui(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_imm20(Operation::Lui, ins, offset)
-}
-
-fn disassemble_auipc(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_imm20(Operation::Auipc, ins, offset)
-}
-
-fn disassemble_jal(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_jimm20(Operation::Jal, ins, offset)
-}
-
-fn disassemble_jalr(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_imm12(Operation::Jalr, ins, offset, 4)
-}
-
-fn disassemble_beq(ins: u32, offset: usize) -> Instruction {
-    instr_op_rs1_rs2_branch(Operation::Beq, ins, offset)
-}
-
-fn disassemble_bne(ins: u32, offset: usize) -> Instruction {
-    instr_op_rs1_rs2_branch(Operation::Bne, ins, offset)
-}
-
-fn disassemble_blt(ins: u32, offset: usize) -> Instruction {
-    instr_op_rs1_rs2_branch(Operation::Blt, ins, offset)
-}
-
-fn disassemble_bge(ins: u32, offset: usize) -> Instruction {
-    instr_op_rs1_rs2_branch(Operation::Bge, ins, offset)
-}
-
-fn disassemble_bltu(ins: u32, offset: usize) -> Instruction {
-    instr_op_rs1_rs2_branch(Operation::Bltu, ins, offset)
-}
-
-fn disassemble_bgeu(ins: u32, offset: usize) -> Instruction {
-    instr_op_rs1_rs2_branch(Operation::Bgeu, ins, offset)
-}
-
-fn disassemble_addi(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_imm12(Operation::Addi, ins, offset, 4)
-}
-
-fn disassemble_addiw(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_imm12(Operation::Addiw, ins, offset, 4)
-}
-
-fn disassemble_xori(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_imm12(Operation::Xori, ins, offset, 4)
-}
-
-fn disassemble_ori(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_imm12(Operation::Ori, ins, offset, 4)
-}
-
-fn disassemble_slti(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_imm12(Operation::Slti, ins, offset, 4)
-}
-
-fn disassemble_sltui(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_imm12(Operation::Sltui, ins, offset, 4)
-}
-
-fn disassemble_andi(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_imm12(Operation::Andi, ins, offset, 4)
-}
-
-fn disassemble_slli(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_shamt(Operation::Slli, ins, offset, 4)
-}
-
-fn disassemble_slliw(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_shamt(Operation::Slliw, ins, offset, 4)
-}
-
-fn disassemble_srli(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_shamt(Operation::Srli, ins, offset, 4)
-}
-
-fn disassemble_srliw(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_shamt(Operation::Srliw, ins, offset, 4)
-}
-
-fn disassemble_srai(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_shamt(Operation::Srai, ins, offset, 4)
-}
-
-fn disassemble_sraiw(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_shamt(Operation::Sraiw, ins, offset, 4)
-}
-
-fn disassemble_add(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Add, ins, offset, 4)
-}
-
-fn disassemble_sub(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Sub, ins, offset, 4)
-}
-
-fn disassemble_xor(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Xor, ins, offset, 4)
-}
-
-fn disassemble_and(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::And, ins, offset, 4)
-}
-
-fn disassemble_or(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Or, ins, offset, 4)
-}
-
-fn disassemble_slt(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Slt, ins, offset, 4)
-}
-
-fn disassemble_sltu(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Sltu, ins, offset, 4)
-}
-
-fn disassemble_sll(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Sll, ins, offset, 4)
-}
-
-fn disassemble_srl(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Srl, ins, offset, 4)
-}
-
-fn disassemble_sra(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Sra, ins, offset, 4)
-}
-
-fn disassemble_mul(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Mul, ins, offset, 4)
-}
-
-fn disassemble_addw(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Addw, ins, offset, 4)
-}
-
-fn disassemble_subw(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Subw, ins, offset, 4)
-}
-
-fn disassemble_sllw(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Sllw, ins, offset, 4)
-}
-
-fn disassemble_srlw(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Srlw, ins, offset, 4)
-}
-
-fn disassemble_sraw(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Sraw, ins, offset, 4)
-}
-
-fn disassemble_mulw(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_rs2(Operation::Mulw, ins, offset, 4)
-}
-
-fn disassemble_lb(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_imm12(Operation::Lb, ins, offset, 4)
-}
-
-fn disassemble_lbu(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_imm12(Operation::Lbu, ins, offset, 4)
-}
-
-fn disassemble_lh(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_imm12(Operation::Lh, ins, offset, 4)
-}
-
-fn disassemble_lhu(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_imm12(Operation::Lhu, ins, offset, 4)
-}
-
-fn disassemble_lw(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_imm12(Operation::Lw, ins, offset, 4)
-}
-
-fn disassemble_lwu(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_imm12(Operation::Lwu, ins, offset, 4)
-}
-
-fn disassemble_ld(ins: u32, offset: usize) -> Instruction {
-    instr_op_rd_rs1_imm12(Operation::Ld, ins, offset, 4)
-}
-
-fn disassemble_sb(ins: u32, offset: usize) -> Instruction {
-    instr_op_rs1_rs2_imm12_s(Operation::Sb, ins, offset, 4)
-}
-
-fn disassemble_sh(ins: u32, offset: usize) -> Instruction {
-    instr_op_rs1_rs2_imm12_s(Operation::Sh, ins, offset, 4)
-}
-
-fn disassemble_sw(ins: u32, offset: usize) -> Instruction {
-    instr_op_rs1_rs2_imm12_s(Operation::Sw, ins, offset, 4)
-}
-
-fn disassemble_sd(ins: u32, offset: usize) -> Instruction {
-    instr_op_rs1_rs2_imm12_s(Operation::Sd, ins, offset, 4)
-}
-
-fn disassemble_csrrw(ins: u32, offset: usize) -> Instruction {
-    instr_op_rs1_csr(Operation::Sd, ins, offset)
-}
-
-fn disassemble_32(ins: u32, offset: usize) -> Option<Instruction> {
-    let opcode = opcode(ins);
-    let funct3 = funct3(ins);
-    match opcode {
-        0b0110111 => Some(disassemble_lui(ins, offset)),
-        0b0010111 => Some(disassemble_auipc(ins, offset)),
-        0b1101111 => Some(disassemble_jal(ins, offset)),
-        0b1100111 => Some(disassemble_jalr(ins, offset)),
-        0b1100011 => {
-            match funct3 {
-                0b000 => Some(disassemble_beq(ins, offset)),
-                0b001 => Some(disassemble_bne(ins, offset)),
-                0b100 => Some(disassemble_blt(ins, offset)),
-                0b101 => Some(disassemble_bge(ins, offset)),
-                0b110 => Some(disassemble_bltu(ins, offset)),
-                0b111 => Some(disassemble_bgeu(ins, offset)),
-                _ => None
-            }
-        },
-        0b0000011 => {
-            match funct3 {
-                0b000 => Some(disassemble_lb(ins, offset)),
-                0b001 => Some(disassemble_lh(ins, offset)),
-                0b010 => Some(disassemble_lw(ins, offset)),
-                0b011 => Some(disassemble_ld(ins, offset)),
-                0b100 => Some(disassemble_lbu(ins, offset)),
-                0b101 => Some(disassemble_lhu(ins, offset)),
-                0b110 => Some(disassemble_lwu(ins, offset)),
-                _ => None
-            }
-        },
-        0b0100011 => {
-            match funct3 {
-                0b000 => Some(disassemble_sb(ins, offset)),
-                0b001 => Some(disassemble_sh(ins, offset)),
-                0b010 => Some(disassemble_sw(ins, offset)),
-                0b011 => Some(disassemble_sd(ins, offset)),
-                _ => None
-            }
-        },
-        0b0010011 => {
-            match funct3 {
-                0b000 => Some(disassemble_addi(ins, offset)),
-                0b001 => Some(disassemble_slli(ins, offset)),
-                0b010 => Some(disassemble_slti(ins, offset)),
-                0b011 => Some(disassemble_sltui(ins, offset)),
-                0b100 => Some(disassemble_xori(ins, offset)),
-                0b101 => match funct7(ins) {
-                    0b0000000 => Some(disassemble_srli(ins, offset)),
-                    0b0100000 => Some(disassemble_srai(ins, offset)),
-                    _ => None
-                },
-                0b110 => Some(disassemble_ori(ins, offset)),
-                0b111 => Some(disassemble_andi(ins, offset)),
-                _ => None
-            }
-        },
-        0b0011011 => {
-            match funct3 {
-                0b000 => Some(disassemble_addiw(ins, offset)),
-                0b001 => Some(disassemble_slliw(ins, offset)),
-                0b101 => match funct7(ins) {
-                    0b0000000 => Some(disassemble_srliw(ins, offset)),
-                    0b0100000 => Some(disassemble_sraiw(ins, offset)),
-                    _ => None
-                },
-                _ => None
-            }
-        },
-        0b0110011 => {
-            match funct3 {
-                0b000 => match funct7(ins) {
-                    0b0000000 => Some(disassemble_add(ins, offset)),
-                    0b0000001 => Some(disassemble_mul(ins, offset)),
-                    0b0100000 => Some(disassemble_sub(ins, offset)),
-                    _ => None
-                },
-                0b001 => Some(disassemble_sll(ins, offset)),
-                0b010 => Some(disassemble_slt(ins, offset)),
-                0b011 => Some(disassemble_sltu(ins, offset)),
-                0b100 => Some(disassemble_xor(ins, offset)),
-                0b101 => match funct7(ins) {
-                    0b0000000 => Some(disassemble_srl(ins, offset)),
-                    0b0100000 => Some(disassemble_sra(ins, offset)),
-                    _ => None
-                },
-                0b110 => Some(disassemble_or(ins, offset)),
-                0b111 => Some(disassemble_and(ins, offset)),
-                _ => None
-            }
-        },
-        0b0111011 => {
-            match funct3 {
-                0b000 => match funct7(ins) {
-                    0b0000000 => Some(disassemble_addw(ins, offset)),
-                    0b0000001 => Some(disassemble_mulw(ins, offset)),
-                    0b0100000 => Some(disassemble_subw(ins, offset)),
-                    _ => None
-                },
-                0b001 => Some(disassemble_sllw(ins, offset)),
-                0b101 => match funct7(ins) {
-                    0b0000000 => Some(disassemble_srlw(ins, offset)),
-                    0b0100000 => Some(disassemble_sraw(ins, offset)),
-                    _ => None
-                },
-                _ => None
-            }
-        },
-        0b1110011 => {
-            match funct3 {
-                0b001 => Some(disassemble_csrrw(ins, offset)),
-                _ => None
-            }
-        },
-        _ => None
-    }
-}
-
 // rd' = ins[2:4]
 fn rd_rs2_p(ins: u16) -> u16 {
     (ins >> 2) & 0b111
@@ -858,225 +436,266 @@ fn c_bimm9(ins: u16) -> i16 {
     | (ins.bextr(6, 5) << 6) | (ins.bextr(4, 3) << 1) | (ins.bextr(2, 2) << 5)) as i16
 }
 
-fn disassemble_c_lw(ins: u16, offset: usize) -> Instruction {
-    let rd = rd_rs2_p(ins) as u8 + Register::S0.0;
-    let rs1 = rs1_p(ins) as u8 + Register::S0.0;
-    let imm = c_uimm7(ins);
-    Instruction { operation: Operation::Lw, rd: Operand::Reg(rd), rs1: Operand::Reg(rs1), rs2: Operand::Nothing, rs3: Operand::Nothing, imm: Operand::ImmU16(imm), offset, ins_size: 2 }
-}
-
-fn disassemble_c_li(ins: u16, offset: usize) -> Instruction {
-    let rd = rd(ins as u32) as u8;
-    let imm = c_imm6(ins);
-    Instruction { operation: Operation::Li, rd: Operand::Reg(rd), rs1: Operand::Nothing, rs2: Operand::Nothing, rs3: Operand::Nothing, imm: Operand::ImmS16(imm), offset, ins_size: 2 }
-}
-
-fn disassemble_c_lui(ins: u16, offset: usize) -> Instruction {
-    let rd = rd(ins as u32) as u8;
-    let imm = ((ins.bextr(12, 11) as u32) << 17) | ((ins.bextr(6, 2) << 12) as u32);
-    Instruction { operation: Operation::Lui, rd: Operand::Reg(rd), rs1: Operand::Nothing, rs2: Operand::Nothing, rs3: Operand::Nothing, imm: Operand::ImmU32(imm), offset, ins_size: 2 }
-}
-
-fn disassemble_c_sub(ins: u16, offset: usize) -> Instruction {
-    let rd = rd_rs2_p(ins) as u8 + Register::S0.0;
-    let rs = rs1_p(ins) as u8 + Register::S0.0;
-    Instruction { operation: Operation::Sub, rd: Operand::Reg(rd), rs1: Operand::Reg(rd), rs2: Operand::Reg(rs), rs3: Operand::Nothing, imm: Operand::Nothing, offset, ins_size: 2 }
+fn c_lui_imm(ins: u16) -> u32 {
+    ((ins.bextr(12, 11) as u32) << 17) | ((ins.bextr(6, 2) << 12) as u32)
 }
 
-fn disassemble_c_xor(ins: u16, offset: usize) -> Instruction {
-    let rd = rd_rs2_p(ins) as u8 + Register::S0.0;
-    let rs = rs1_p(ins) as u8 + Register::S0.0;
-    Instruction { operation: Operation::Xor, rd: Operand::Reg(rd), rs1: Operand::Reg(rd), rs2: Operand::Reg(rs), rs3: Operand::Nothing, imm: Operand::Nothing, offset, ins_size: 2 }
-}
-
-fn disassemble_c_or(ins: u16, offset: usize) -> Instruction {
-    let rd = rd_rs2_p(ins) as u8 + Register::S0.0;
-    let rs = rs1_p(ins) as u8 + Register::S0.0;
-    Instruction { operation: Operation::Or, rd: Operand::Reg(rd), rs1: Operand::Reg(rd), rs2: Operand::Reg(rs), rs3: Operand::Nothing, imm: Operand::Nothing, offset, ins_size: 2 }
-}
-
-fn disassemble_c_and(ins: u16, offset: usize) -> Instruction {
-    let rd = rd_rs2_p(ins) as u8 + Register::S0.0;
-    let rs = rs1_p(ins) as u8 + Register::S0.0;
-    Instruction { operation: Operation::And, rd: Operand::Reg(rd), rs1: Operand::Reg(rd), rs2: Operand::Reg(rs), rs3: Operand::Nothing, imm: Operand::Nothing, offset, ins_size: 2 }
-}
-
-fn disassemble_c_addi(ins: u16, offset: usize) -> Instruction {
-    let rd = rd(ins as u32) as u8;
-    let imm = c_imm6(ins);
-    Instruction { operation: Operation::Addi, rd: Operand::Reg(rd), rs1: Operand::Reg(rd), rs2: Operand::Nothing, rs3: Operand::Nothing, imm: Operand::ImmS16(imm), offset, ins_size: 2 }
-}
-
-fn disassemble_c_subw(ins: u16, offset: usize) -> Instruction {
-    let rd = rd_rs2_p(ins) as u8 + Register::S0.0;
-    let rs = rs1_p(ins) as u8 + Register::S0.0;
-    Instruction { operation: Operation::Subw, rd: Operand::Reg(rd), rs1: Operand::Reg(rd), rs2: Operand::Reg(rs), rs3: Operand::Nothing, imm: Operand::Nothing, offset, ins_size: 2 }
-}
-
-fn disassemble_c_addw(ins: u16, offset: usize) -> Instruction {
-    let rd = rd_rs2_p(ins) as u8 + Register::S0.0;
-    let rs = rs1_p(ins) as u8 + Register::S0.0;
-    Instruction { operation: Operation::Addw, rd: Operand::Reg(rd), rs1: Operand::Reg(rd), rs2: Operand::Reg(rs), rs3: Operand::Nothing, imm: Operand::Nothing, offset, ins_size: 2 }
-}
-
-fn disassemble_c_jr(ins: u16, offset: usize) -> Instruction {
-    let rs1 = rd(ins as u32) as u8;
-    Instruction { operation: Operation::Jalr, rd: Operand::Reg(Register::ZERO.0), rs1: Operand::Reg(rs1), rs2: Operand::Nothing, rs3: Operand::Nothing, imm: Operand::ImmS16(0), offset, ins_size: 2 }
-}
-
-fn disassemble_c_jalr(ins: u16, offset: usize) -> Instruction {
-    let rs1 = rd(ins as u32) as u8;
-    Instruction { operation: Operation::Jalr, rd: Operand::Reg(Register::RA.0), rs1: Operand::Reg(rs1), rs2: Operand::Nothing, rs3: Operand::Nothing, imm: Operand::ImmS16(0), offset, ins_size: 2 }
-}
-
-fn disassemble_c_mv(ins: u16, offset: usize) -> Instruction {
-    let rd = rd(ins as u32) as u8;
-    let rs2 = c_rs2(ins) as u8;
-    Instruction { operation: Operation::Add, rd: Operand::Reg(rd), rs1: Operand::Reg(Register::ZERO.0), rs2: Operand::Reg(rs2), rs3: Operand::Nothing, imm: Operand::Nothing, offset, ins_size: 2 }
-}
-
-fn disassemble_c_add(ins: u16, offset: usize) -> Instruction {
-    let rd = rd(ins as u32) as u8;
-    let rs2 = c_rs2(ins) as u8;
-    Instruction { operation: Operation::Add, rd: Operand::Reg(rd), rs1: Operand::Reg(rd), rs2: Operand::Reg(rs2), rs3: Operand::Nothing, imm: Operand::Nothing, offset, ins_size: 2 }
-}
-
-fn disassemble_c_j(ins: u16, offset: usize) -> Instruction {
+fn c_jimm12(ins: u16) -> i16 {
     let i = ins as i16;
-    let imm = ((i.bextr(12, 12) << 11) as u16) | (ins.bextr(11, 11) << 4)
+    (((i.bextr(12, 12) << 11) as u16) | (ins.bextr(11, 11) << 4)
         | (ins.bextr(10, 9) << 8) | (ins.bextr(8, 8) << 10) | (ins.bextr(7, 7) << 6)
-        | (ins.bextr(6, 6) << 7) | (ins.bextr(5, 3) << 1) | (ins.bextr(2, 2) << 5);
-    Instruction { operation: Operation::Jal, rd: Operand::Reg(Register::ZERO.0), rs1: Operand::Nothing, rs2: Operand::Nothing, rs3: Operand::Nothing, imm: Operand::ImmS16(imm as i16), offset, ins_size: 2 }
+        | (ins.bextr(6, 6) << 7) | (ins.bextr(5, 3) << 1) | (ins.bextr(2, 2) << 5)) as i16
 }
 
-fn disassemble_c_lwsp(ins: u16, offset: usize) -> Instruction {
-    let rd = rd(ins as u32) as u8;
-    let imm = c_uimm8sp(ins);
-    Instruction { operation: Operation::Lw, rd: Operand::Reg(rd), rs1: Operand::Reg(Register::SP.0), rs2: Operand::Nothing, rs3: Operand::Nothing, imm: Operand::ImmU16(imm), offset, ins_size: 2 }
+// Where an instruction keeps its operands, named after the encoding formats
+// of the RISC-V spec. Compressed formats name the instruction when they're
+// specific to one.
+#[derive(Clone, Copy)]
+enum Format {
+    // rd, rs1, rs2
+    R,
+    // rd, rs1, 12-bit immediate
+    I,
+    // rd, rs1, shift amount
+    Shift,
+    // rs1, rs2, 12-bit store offset
+    S,
+    // rs1, rs2, branch offset
+    B,
+    // rd, upper 20 bits
+    U,
+    // rd, jump offset
+    J,
+    // rd, rs1, CSR number
+    Csr,
+    System,
+    // rd', rs1', load offset
+    CL,
+    // rd, 6-bit immediate
+    CLi,
+    // rd, rd, 6-bit immediate
+    CAddi,
+    CLui,
+    // rd', rd', rs2'
+    CA,
+    CJr,
+    CJalr,
+    CMv,
+    CAdd,
+    CJ,
+    CLwsp,
+    CSwsp,
+    // rs1', zero, branch offset
+    CB,
+}
+
+// An instruction matches an entry when its bits under the mask equal the
+// entry's; the first match decodes it. New instructions only need an entry,
+// unless they keep their operands in a new format.
+struct Opcode {
+    mask: u32,
+    bits: u32,
+    operation: Operation,
+    format: Format,
+}
+
+const fn op(mask: u32, bits: u32, operation: Operation, format: Format) -> Opcode {
+    Opcode { mask, bits, operation, format }
+}
+
+// The opcode, funct3 and funct7 fields.
+const OPCODE: u32 = 0x0000007f;
+const FUNCT3: u32 = 0x0000707f;
+const FUNCT7: u32 = 0xfe00707f;
+
+const OPCODES_32: &[Opcode] = &[
+    op(OPCODE, 0x00000037, Operation::Lui, Format::U),
+    op(OPCODE, 0x00000017, Operation::Auipc, Format::U),
+    op(OPCODE, 0x0000006f, Operation::Jal, Format::J),
+    op(OPCODE, 0x00000067, Operation::Jalr, Format::I),
+    op(FUNCT3, 0x00000063, Operation::Beq, Format::B),
+    op(FUNCT3, 0x00001063, Operation::Bne, Format::B),
+    op(FUNCT3, 0x00004063, Operation::Blt, Format::B),
+    op(FUNCT3, 0x00005063, Operation::Bge, Format::B),
+    op(FUNCT3, 0x00006063, Operation::Bltu, Format::B),
+    op(FUNCT3, 0x00007063, Operation::Bgeu, Format::B),
+    op(FUNCT3, 0x00000003, Operation::Lb, Format::I),
+    op(FUNCT3, 0x00001003, Operation::Lh, Format::I),
+    op(FUNCT3, 0x00002003, Operation::Lw, Format::I),
+    op(FUNCT3, 0x00003003, Operation::Ld, Format::I),
+    op(FUNCT3, 0x00004003, Operation::Lbu, Format::I),
+    op(FUNCT3, 0x00005003, Operation::Lhu, Format::I),
+    op(FUNCT3, 0x00006003, Operation::Lwu, Format::I),
+    op(FUNCT3, 0x00000023, Operation::Sb, Format::S),
+    op(FUNCT3, 0x00001023, Operation::Sh, Format::S),
+    op(FUNCT3, 0x00002023, Operation::Sw, Format::S),
+    op(FUNCT3, 0x00003023, Operation::Sd, Format::S),
+    op(FUNCT3, 0x00000013, Operation::Addi, Format::I),
+    op(FUNCT3, 0x00001013, Operation::Slli, Format::Shift),
+    op(FUNCT3, 0x00002013, Operation::Slti, Format::I),
+    op(FUNCT3, 0x00003013, Operation::Sltui, Format::I),
+    op(FUNCT3, 0x00004013, Operation::Xori, Format::I),
+    op(FUNCT7, 0x00005013, Operation::Srli, Format::Shift),
+    op(FUNCT7, 0x40005013, Operation::Srai, Format::Shift),
+    op(FUNCT3, 0x00006013, Operation::Ori, Format::I),
+    op(FUNCT3, 0x00007013, Operation::Andi, Format::I),
+    op(FUNCT3, 0x0000001b, Operation::Addiw, Format::I),
+    op(FUNCT3, 0x0000101b, Operation::Slliw, Format::Shift),
+    op(FUNCT7, 0x0000501b, Operation::Srliw, Format::Shift),
+    op(FUNCT7, 0x4000501b, Operation::Sraiw, Format::Shift),
+    op(FUNCT7, 0x00000033, Operation::Add, Format::R),
+    op(FUNCT7, 0x02000033, Operation::Mul, Format::R),
+    op(FUNCT7, 0x40000033, Operation::Sub, Format::R),
+    op(FUNCT3, 0x00001033, Operation::Sll, Format::R),
+    op(FUNCT3, 0x00002033, Operation::Slt, Format::R),
+    op(FUNCT3, 0x00003033, Operation::Sltu, Format::R),
+    op(FUNCT3, 0x00004033, Operation::Xor, Format::R),
+    op(FUNCT7, 0x00005033, Operation::Srl, Format::R),
+    op(FUNCT7, 0x40005033, Operation::Sra, Format::R),
+    op(FUNCT3, 0x00006033, Operation::Or, Format::R),
+    op(FUNCT3, 0x00007033, Operation::And, Format::R),
+    op(FUNCT7, 0x0000003b, Operation::Addw, Format::R),
+    op(FUNCT7, 0x0200003b, Operation::Mulw, Format::R),
+    op(FUNCT7, 0x4000003b, Operation::Subw, Format::R),
+    op(FUNCT3, 0x0000103b, Operation::Sllw, Format::R),
+    op(FUNCT7, 0x0000503b, Operation::Srlw, Format::R),
+    op(FUNCT7, 0x4000503b, Operation::Sraw, Format::R),
+    op(0xffffffff, 0x00000073, Operation::Ecall, Format::System),
+    // csrrw, shown as sd until there's a CSR operation.
+    op(FUNCT3, 0x00001073, Operation::Sd, Format::Csr),
+];
+
+// The quadrant (bits 0-1) and funct3 (bits 13-15) of compressed instructions.
+const C_FUNCT3: u32 = 0xe003;
+// Also bits 5-6 and 10-12, which pick the arithmetic instructions.
+const C_ARITH: u32 = 0xfc63;
+// Also bits 11-12 and rs2 (bits 2-6).
+const C_JUMP: u32 = 0xf87f;
+// Also bits 11-12.
+const C_MOVE: u32 = 0xf803;
+
+const OPCODES_16: &[Opcode] = &[
+    op(C_FUNCT3, 0x4000, Operation::Lw, Format::CL),
+    op(C_FUNCT3, 0x0001, Operation::Addi, Format::CAddi),
+    op(C_FUNCT3, 0x4001, Operation::Li, Format::CLi),
+    op(C_FUNCT3, 0x6001, Operation::Lui, Format::CLui),
+    op(C_ARITH, 0x8c01, Operation::Sub, Format::CA),
+    op(C_ARITH, 0x8c21, Operation::Xor, Format::CA),
+    op(C_ARITH, 0x8c41, Operation::Or, Format::CA),
+    op(C_ARITH, 0x8c61, Operation::And, Format::CA),
+    op(C_ARITH, 0x9c01, Operation::Subw, Format::CA),
+    op(C_ARITH, 0x9c21, Operation::Addw, Format::CA),
+    op(C_FUNCT3, 0xa001, Operation::Jal, Format::CJ),
+    op(C_FUNCT3, 0xc001, Operation::Beq, Format::CB),
+    op(C_FUNCT3, 0xe001, Operation::Bne, Format::CB),
+    op(C_FUNCT3, 0x4002, Operation::Lw, Format::CLwsp),
+    op(C_JUMP, 0x8002, Operation::Jalr, Format::CJr),
+    op(C_MOVE, 0x8002, Operation::Add, Format::CMv),
+    op(C_JUMP, 0x8802, Operation::Jalr, Format::CJalr),
+    op(C_MOVE, 0x8802, Operation::Add, Format::CAdd),
+    op(C_FUNCT3, 0xc002, Operation::Sw, Format::CSwsp),
+];
+
+fn decode(opcodes: &[Opcode], ins: u32, offset: usize) -> Option<Instruction> {
+    let opcode = opcodes.iter().find(|opcode| ins & opcode.mask == opcode.bits)?;
+    let reg = |r: u32| Operand::Reg(r as u8);
+    // Compressed register fields name x8 to x15.
+    let reg_p = |r: u16| Operand::Reg(r as u8 + Register::S0.0);
+    let c = ins as u16;
+    let (rd, rs1, rs2, imm) = match opcode.format {
+        Format::R => (reg(rd(ins)), reg(rs1(ins)), reg(rs2(ins)), Operand::Nothing),
+        Format::I => (reg(rd(ins)), reg(rs1(ins)), Operand::Nothing, Operand::ImmS32(imm12(ins))),
+        Format::Shift => (reg(rd(ins)), reg(rs1(ins)), Operand::Nothing, Operand::ImmU32(shamt(ins))),
+        Format::S => (Operand::Nothing, reg(rs1(ins)), reg(rs2(ins)), Operand::ImmS32(imm12_s(ins))),
+        Format::B => (Operand::Nothing, reg(rs1(ins)), reg(rs2(ins)), Operand::ImmS32(branch(ins))),
+        Format::U => (reg(rd(ins)), Operand::Nothing, Operand::Nothing, Operand::ImmS32(imm20(ins))),
+        Format::J => (reg(rd(ins)), Operand::Nothing, Operand::Nothing, Operand::ImmS32(jimm20(ins))),
+        Format::Csr => (reg(rd(ins)), reg(rs1(ins)), Operand::Nothing, Operand::ImmU32(csr(ins))),
+        Format::System => (Operand::Nothing, Operand::Nothing, Operand::Nothing, Operand::Nothing),
+        Format::CL => (reg_p(rd_rs2_p(c)), reg_p(rs1_p(c)), Operand::Nothing, Operand::ImmU16(c_uimm7(c))),
+        Format::CLi => (reg(rd(ins)), Operand::Nothing, Operand::Nothing, Operand::ImmS16(c_imm6(c))),
+        Format::CAddi => (reg(rd(ins)), reg(rd(ins)), Operand::Nothing, Operand::ImmS16(c_imm6(c))),
+        Format::CLui => (reg(rd(ins)), Operand::Nothing, Operand::Nothing, Operand::ImmU32(c_lui_imm(c))),
+        Format::CA => (reg_p(rd_rs2_p(c)), reg_p(rd_rs2_p(c)), reg_p(rs1_p(c)), Operand::Nothing),
+        Format::CJr => (Operand::Reg(Register::ZERO.0), reg(rd(ins)), Operand::Nothing, Operand::ImmS16(0)),
+        Format::CJalr => (Operand::Reg(Register::RA.0), reg(rd(ins)), Operand::Nothing, Operand::ImmS16(0)),
+        Format::CMv => (reg(rd(ins)), Operand::Reg(Register::ZERO.0), reg(c_rs2(c).into()), Operand::Nothing),
+        Format::CAdd => (reg(rd(ins)), reg(rd(ins)), reg(c_rs2(c).into()), Operand::Nothing),
+        Format::CJ => (Operand::Reg(Register::ZERO.0), Operand::Nothing, Operand::Nothing, Operand::ImmS16(c_jimm12(c))),
+        Format::CLwsp => (reg(rd(ins)), Operand::Reg(Register::SP.0), Operand::Nothing, Operand::ImmU16(c_uimm8sp(c))),
+        Format::CSwsp => (Operand::Nothing, reg(c_rs2(c).into()), Operand::Reg(Register::SP.0), Operand::ImmU16(c_uimm8sp_s(c))),
+        Format::CB => (Operand::Nothing, reg_p(rs1_p(c)), Operand::Reg(Register::ZERO.0), Operand::ImmS16(c_bimm9(c))),
+    };
+    let ins_size = if ins & 3 == 3 { 4 } else { 2 };
+    Some(Instruction { operation: opcode.operation, rd, rs1, rs2, rs3: Operand::Nothing, imm, offset, ins_size })
 }
 
-fn disassemble_c_swsp(ins: u16, offset: usize) -> Instruction {
-    let rs2 = c_rs2(ins) as u8;
-    let imm = c_uimm8sp_s(ins);
-    Instruction { operation: Operation::Sw, rd: Operand::Nothing, rs1: Operand::Reg(rs2), rs2: Operand::Reg(Register::SP.0), rs3: Operand::Nothing, imm: Operand::ImmU16(imm), offset, ins_size: 2 }
+fn disassemble_32(ins: u32, offset: usize) -> Option<Instruction> {
+    decode(OPCODES_32, ins, offset)
 }
 
-fn disassemble_c_beqz(ins: u16, offset: usize) -> Instruction {
-    let rs1 = rs1_p(ins) as u8 + Register::S0.0;
-    let imm = c_bimm9(ins);
-    Instruction { operation: Operation::Beq, rd: Operand::Nothing, rs1: Operand::Reg(rs1), rs2: Operand::Reg(Register::ZERO.0), rs3: Operand::Nothing, imm: Operand::ImmS16(imm), offset, ins_size: 2 }
+fn disassemble_16(ins: u16, offset: usize) -> Option<Instruction> {
+    decode(OPCODES_16, ins.into(), offset)
 }
 
-fn disassemble_c_bnez(ins: u16, offset: usize) -> Instruction {
-    let rs1 = rs1_p(ins) as u8 + Register::S0.0;
-    let imm = c_bimm9(ins);
-    Instruction { operation: Operation::Bne, rd: Operand::Nothing, rs1: Operand::Reg(rs1), rs2: Operand::Reg(Register::ZERO.0), rs3: Operand::Nothing, imm: Operand::ImmS16(imm), offset, ins_size: 2 }
+// The size of the instruction at offset, if all of it is in bytes.
+fn instruction_size(bytes: &[u8], offset: usize) -> Option<usize> {
+    let size = if bytes.get(offset)? & 3 == 3 { 4 } else { 2 };
+    if offset + size > bytes.len() {
+        return None
+    }
+    Some(size)
 }
 
-fn disassemble_16(ins: u16, offset: usize) -> Option<Instruction> {
-    let op = ins & 3;
-    let funct = (ins >> 13) & 7;
-    match op {
-        0b00 => match funct {
-            0b010 => Some(disassemble_c_lw(ins, offset)),
-            _ => None,
-        },
-        0b01 => match funct {
-            0b000 => Some(disassemble_c_addi(ins, offset)),
-            0b010 => Some(disassemble_c_li(ins, offset)),
-            0b011 => match rd(ins.into()) {
-                _ => Some(disassemble_c_lui(ins, offset)),
-            },
-            0b100 => match ins.bextr(11, 10) {
-                0b11 => match ins.bextr(12, 12) {
-                    0b0 => match ins.bextr(6, 5) {
-                        0b00 => Some(disassemble_c_sub(ins, offset)),
-                        0b01 => Some(disassemble_c_xor(ins, offset)),
-                        0b10 => Some(disassemble_c_or(ins, offset)),
-                        0b11 => Some(disassemble_c_and(ins, offset)),
-                        _ => None,
-                    },
-                    0b1 => match ins.bextr(6, 5) {
-                        0b00 => Some(disassemble_c_subw(ins, offset)),
-                        0b01 => Some(disassemble_c_addw(ins, offset)),
-                        _ => None,
-                    },
-                    _ => None
-                },
-                _ => None,
-            },
-            0b101 => Some(disassemble_c_j(ins, offset)),
-            0b110 => Some(disassemble_c_beqz(ins, offset)),
-            0b111 => Some(disassemble_c_bnez(ins, offset)),
-            _ => None,
-        },
-        0b10 => match funct {
-            0b010 => Some(disassemble_c_lwsp(ins, offset)),
-            0b100 => match ins.bextr(12, 11) {
-                0x0 => if c_rs2(ins) == 0 { 
-                    Some(disassemble_c_jr(ins, offset))
-                } else {
-                    Some(disassemble_c_mv(ins, offset))
-                },
-                0x1 => if c_rs2(ins) == 0 {
-                    Some(disassemble_c_jalr(ins, offset))
-                } else {
-                    Some(disassemble_c_add(ins, offset))
-                },
-                _ => None
-            },
-            0b110 => Some(disassemble_c_swsp(ins, offset)),
-            _ => None,
-        },
+fn disassemble_instruction(bytes: &[u8], offset: usize) -> Option<Instruction> {
+    let ins = &bytes[offset..offset + instruction_size(bytes, offset)?];
+    match *ins {
+        [b0, b1, b2, b3] => disassemble_32(u32::from_le_bytes([b0, b1, b2, b3]), offset),
+        [b0, b1] => disassemble_16(u16::from_le_bytes([b0, b1]), offset),
         _ => None,
     }
 }
 
-fn disassemble_instruction(bytes: &[u8], offset: usize) -> Option<Instruction> {
-    let ins = u32::from_le_bytes(bytes[offset..offset+4].try_into().unwrap());
-    if (ins & 3) == 3 {
-        return disassemble_32(ins, offset)
-    }
-    disassemble_16(u16::from_le_bytes(bytes[offset..offset+2].try_into().unwrap()), offset)
+pub fn disassemble_riscv_at(bytes: &[u8], offset: usize) -> Option<Instruction> {
+    disassemble_instruction(bytes, offset)
 }
 
-pub fn disassemble_riscv(section: &Section, section_name: &String, _program: &Program) -> DisassemblySection {
-    let mut instrs = Vec::<Instruction>::new();
+// Decodes one instruction after another, passing each to f until it returns
+// false. A trailing partial instruction is left out.
+pub fn decode_riscv(bytes: &[u8], mut f: impl FnMut(Instruction) -> bool) {
     let mut offset: usize = 0;
-    let limit = 32usize;
-    let bytes = section.bytes.as_slice();
-    while offset + 4 < limit {
-        let instr = disassemble_instruction(bytes, offset);
-        if instr.is_some() {
-            let ins = instr.unwrap();
-            offset += ins.ins_size as usize;
-            instrs.push(ins);
-        }
-        else if offset + 4 < limit && (u32::from_le_bytes(bytes[offset..offset+4].try_into().unwrap()) & 3) == 3 {
-            instrs.push(Instruction { operation: Operation::Unknown,
-                rd: Operand::Nothing,
-                rs1: Operand::Nothing,
-                rs2: Operand::Nothing,
-                rs3: Operand::Nothing,
-                imm: Operand::Nothing,
-                offset,
-                ins_size: 4});
-            offset += 4;
+    while let Some(ins_size) = instruction_size(bytes, offset) {
+        let ins = if let Some(ins) = disassemble_instruction(bytes, offset) {
+            ins
         }
         else {
-            instrs.push(Instruction { operation: Operation::Unknown,
+            log::debug!("Unknown RISC-V instruction at offset {:#x}", offset);
+            Instruction { operation: Operation::Unknown,
                 rd: Operand::Nothing,
                 rs1: Operand::Nothing,
                 rs2: Operand::Nothing,
                 rs3: Operand::Nothing,
                 imm: Operand::Nothing,
                 offset,
-                ins_size: 2});
-            offset += 2;
+                ins_size: ins_size as u8 }
+        };
+        offset += ins.ins_size as usize;
+        if !f(ins) {
+            return;
         }
     }
+}
+
+// Stops early, with the instructions so far, once cancel is cancelled.
+pub fn disassemble_riscv(section: &Section, section_name: &String, _program: &Program, cancel: &CancelToken) -> DisassemblySection {
+    let mut instrs = Vec::<Instruction>::new();
+    decode_riscv(section.bytes.as_slice(), |ins| {
+        instrs.push(ins);
+        !cancel.is_cancelled()
+    });
     DisassemblySection {
         section_name: section_name.clone(),
+        addr: section.addr,
         instructions: crate::dis::InstructionListing::Rv(instrs),
     }
 }
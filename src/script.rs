@@ -0,0 +1,80 @@
+// Batch scripts: a file of baretk commands run in order against one binary,
+// so an analysis can be checked in next to the code and rerun.
+//
+//     # triage
+//     info
+//     strings -enc all -grep "https?://" > urls.txt
+//     dis --demangle
+//     bindiff $file old/firmware.bin
+//
+// The binary is passed to each command as its first argument, or wherever
+// $file appears. Each command's output goes to its own file in the output
+// directory, named after the line and command unless "> name" is given.
+
+pub struct Step {
+    // Line number in the script.
+    pub line: usize,
+    pub command: String,
+    pub args: Vec<String>,
+    pub output: String,
+}
+
+// Splits a line into words; double quotes group words with spaces and
+// backslashes escape the next character inside them.
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::<String>::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => word.extend(chars.next()),
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated quote".to_string()),
+                    }
+                }
+            },
+            c if c.is_whitespace() => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+// Parses a script, substituting `file` for $file. Errors give the line number.
+pub fn parse(text: &str, file: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::<Step>::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |what: &str| format!("line {}: {}", i + 1, what);
+        let mut words = split_words(line).map_err(|what| error(&what))?;
+        let output = match words.iter().position(|word| word == ">") {
+            Some(pos) if pos + 2 == words.len() => {
+                let name = words.pop().unwrap_or_default();
+                words.pop();
+                if name.contains('/') || name.contains('\\') || name == ".." {
+                    return Err(error("output names can't contain a path"))
+                }
+                Some(name)
+            },
+            Some(_) => return Err(error("\">\" must be followed by one output name")),
+            None => None,
+        };
+        let command = words.remove(0);
+        let mut args: Vec<String> = words.iter().map(|word| word.replace("$file", file)).collect();
+        if !words.iter().any(|word| word.contains("$file")) {
+            args.insert(0, file.to_string());
+        }
+        let output = output.unwrap_or_else(|| format!("{:02}-{}.txt", steps.len() + 1, command));
+        steps.push(Step { line: i + 1, command, args, output });
+    }
+    Ok(steps)
+}
@@ -0,0 +1,503 @@
+// FLIRT-style library function signatures. A signature is a byte pattern taken
+// from the start of a known function; relocated bytes (call targets, addresses)
+// are wildcarded so the pattern still matches when the function is statically
+// linked somewhere else.
+//
+// Signature files have one signature per line, a name followed by the pattern:
+//
+//     # comment
+//     strlen  48 89 f8 0f b6 10 84 d2 74 ?? 48 83 c0 01
+//
+// Each pattern byte is two hex digits, "??" for any byte, or a nibble mask such as "4?".
+//
+// Files ending in .json, or starting with '[', hold the same as an array:
+//
+//     [{"name": "strlen", "pattern": "48 89 f8 0f b6 10 84 d2 74 ?? 48 83 c0 01"}]
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::arm;
+use crate::dis::{self, Disassembly, Flow, Operand};
+use crate::error::BaretkError;
+use crate::json::{self, Value};
+use crate::prog::{Program, Symbol};
+use crate::reg::{Arch, RegClass};
+use crate::util::{self, BIG_ENDIAN};
+
+// Patterns with fewer fixed bytes than this match too much to be useful.
+const MIN_FIXED_BYTES: usize = 6;
+
+// How much of a function a signature is made from by default. Like FLIRT, the
+// start of a function is enough to tell it apart.
+pub const DEFAULT_PATTERN_LEN: usize = 32;
+
+pub struct Pattern {
+    pub bytes: Vec<u8>,
+    // Bits set in the mask must match; a zero mask byte is a full wildcard.
+    pub mask: Vec<u8>,
+}
+
+fn parse_nibble(c: char) -> Option<(u8, u8)> {
+    match c {
+        '?' => Some((0, 0)),
+        _ => c.to_digit(16).map(|d| (d as u8, 0xf)),
+    }
+}
+
+impl Pattern {
+    // Parses whitespace separated pattern bytes such as "48 8B ?? ?? E8".
+    pub fn parse(s: &str) -> Option<Pattern> {
+        let mut bytes = Vec::<u8>::new();
+        let mut mask = Vec::<u8>::new();
+        for token in s.split_whitespace() {
+            let mut chars = token.chars();
+            let (hi, lo) = match (chars.next(), chars.next(), chars.next()) {
+                (Some(hi), Some(lo), None) => (parse_nibble(hi)?, parse_nibble(lo)?),
+                _ => return None,
+            };
+            bytes.push(hi.0 << 4 | lo.0);
+            mask.push(hi.1 << 4 | lo.1);
+        }
+        if bytes.is_empty() { None } else { Some(Pattern { bytes, mask }) }
+    }
+
+    // A pattern matching exactly these bytes.
+    pub fn exact(bytes: Vec<u8>) -> Pattern {
+        let mask = vec![0xff; bytes.len()];
+        Pattern { bytes, mask }
+    }
+
+    pub fn fixed_bytes(&self) -> usize {
+        self.mask.iter().filter(|m| **m == 0xff).count()
+    }
+
+    pub fn matches_at(&self, haystack: &[u8], offset: usize) -> bool {
+        match haystack.get(offset..offset + self.bytes.len()) {
+            Some(window) => window.iter().zip(&self.bytes).zip(&self.mask).all(|((b, p), m)| b & m == p & m),
+            None => false,
+        }
+    }
+
+    // The pattern as parse reads it.
+    pub fn print(&self) -> String {
+        self.bytes.iter().zip(&self.mask).map(|(b, m)| {
+            let nibble = |shift: u8| match (m >> shift) & 0xf {
+                0xf => format!("{:x}", (b >> shift) & 0xf),
+                _ => "?".to_string(),
+            };
+            nibble(4) + nibble(0).as_str()
+        }).collect::<Vec<String>>().join(" ")
+    }
+
+    // Offsets of every match in haystack.
+    pub fn find_all(&self, haystack: &[u8]) -> Vec<usize> {
+        (0..haystack.len()).filter(|i| self.matches_at(haystack, *i)).collect()
+    }
+}
+
+pub struct Signature {
+    pub name: String,
+    pub pattern: Pattern,
+}
+
+impl Signature {
+    // The signature as a line of a signature file.
+    pub fn to_line(&self) -> String {
+        format!("{}  {}", self.name, self.pattern.print())
+    }
+}
+
+pub struct SigMatch {
+    pub addr: u64,
+    pub name: String,
+}
+
+pub struct SignatureDb {
+    sigs: Vec<Signature>,
+}
+
+impl SignatureDb {
+    pub fn new() -> Self {
+        SignatureDb { sigs: Vec::new() }
+    }
+
+    // Returns the lines that couldn't be parsed.
+    pub fn add_source(&mut self, source: &str) -> Vec<usize> {
+        let mut bad = Vec::<usize>::new();
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, pattern) = match line.split_once(char::is_whitespace) {
+                Some((name, pattern)) => (name, Pattern::parse(pattern)),
+                None => (line, None),
+            };
+            match pattern {
+                Some(pattern) if pattern.fixed_bytes() >= MIN_FIXED_BYTES => self.sigs.push(Signature { name: name.to_string(), pattern }),
+                _ => bad.push(i + 1),
+            }
+        }
+        bad
+    }
+
+    // Adds a JSON array of signatures, returning the indexes of the entries
+    // that couldn't be parsed.
+    pub fn add_json_source(&mut self, source: &str) -> Result<Vec<usize>, String> {
+        let value = json::parse(source).map_err(|offset| format!("invalid JSON at byte {}", offset))?;
+        let entries = value.as_array().ok_or("expected an array of signatures".to_string())?;
+        let mut bad = Vec::<usize>::new();
+        for (i, entry) in entries.iter().enumerate() {
+            let name = entry.get("name").and_then(|name| name.as_str()).filter(|name| !name.is_empty());
+            let pattern = entry.get("pattern").and_then(|pattern| pattern.as_str()).and_then(Pattern::parse);
+            match (name, pattern) {
+                (Some(name), Some(pattern)) if pattern.fixed_bytes() >= MIN_FIXED_BYTES => self.sigs.push(Signature { name: name.to_string(), pattern }),
+                _ => bad.push(i),
+            }
+        }
+        Ok(bad)
+    }
+
+    // Loads JSON or the line format, by extension or, failing that, by whether the file starts with '['.
+    pub fn load_file(&mut self, path: &str) -> Result<(), BaretkError> {
+        let contents = util::try_read_file_contents(path)?;
+        let text = String::from_utf8_lossy(&contents);
+        if path.ends_with(".json") || text.trim_start().starts_with('[') {
+            let bad = self.add_json_source(&text)
+                .map_err(|reason| BaretkError::Malformed { what: path.to_string(), offset: None, section: None, reason })?;
+            for i in bad {
+                log::warn!("{}: signature {} can't be parsed", path, i);
+            }
+        }
+        else {
+            for line in self.add_source(&text) {
+                log::warn!("{}:{}: can't parse signature", path, line);
+            }
+        }
+        Ok(())
+    }
+
+    // Adds a signature, replacing any with the same name.
+    pub fn add(&mut self, sig: Signature) {
+        self.sigs.retain(|old| old.name != sig.name);
+        self.sigs.push(sig);
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("[");
+        for (i, sig) in self.sigs.iter().enumerate() {
+            out += if i == 0 { "\n  " } else { ",\n  " };
+            Value::Object(vec![
+                ("name".to_string(), Value::String(sig.name.clone())),
+                ("pattern".to_string(), Value::String(sig.pattern.print())),
+            ]).write(&mut out);
+        }
+        out += if self.sigs.is_empty() { "]\n" } else { "\n]\n" };
+        out
+    }
+
+    // Matches every signature against the code section. Where signatures overlap,
+    // the one with the most fixed bytes wins; addresses that already have a
+    // symbol are left alone.
+    pub fn match_program(&self, program: &Program) -> Vec<SigMatch> {
+        let section = match program.section_table.get(program.code_section()) {
+            Some(section) => section,
+            None => return Vec::new(),
+        };
+        let symbols = program.symbol_index();
+        let mut best = BTreeMap::<u64, &Signature>::new();
+        for sig in &self.sigs {
+            for offset in sig.pattern.find_all(&section.bytes) {
+                let addr = section.addr + offset as u64;
+                if symbols.starting_at(addr).iter().any(|sym| !sym.name.is_empty()) {
+                    continue;
+                }
+                let better = best.get(&addr).map_or(true, |old| sig.pattern.fixed_bytes() > old.pattern.fixed_bytes());
+                if better {
+                    best.insert(addr, sig);
+                }
+            }
+        }
+        best.into_iter().map(|(addr, sig)| SigMatch { addr, name: sig.name.clone() }).collect()
+    }
+}
+
+// Adds matched functions to the program's symbol table so later stages can use the names.
+pub fn apply_matches(program: &mut Program, matches: &[SigMatch]) {
+    for m in matches {
+        program.symbol_table.push(Symbol { name: m.name.clone(), addr: m.addr, size: 0, is_func: true });
+    }
+}
+
+// Makes a signature from the first `max_len` bytes of a function, given by
+// symbol name or "0x..." address. The bytes that change when the function is
+// linked somewhere else are wildcarded: the targets of calls and jumps out of
+// the function, pc-relative offsets, and addresses in the program's sections.
+pub fn function_signature(dis: &Disassembly, func: &str, max_len: usize) -> Result<Signature, BaretkError> {
+    let program = dis.program();
+    let (name, section, range) = dis.find_function(func)?;
+    let bytes = program.section_table.get(&section.section_name).map_or(&[][..], |loaded| loaded.bytes.as_slice());
+    let end = range.end.min(range.start.saturating_add(max_len)).min(bytes.len());
+    if end <= range.start {
+        return Err(BaretkError::NotFound(format!("Code of function \"{}\"", name)))
+    }
+    let func_addrs = section.addr + range.start as u64..section.addr + range.end as u64;
+    let arch = Arch::from_machine_type(&program.machine_type);
+    let loaded = program.section_table.get(&section.section_name);
+    let arm_decoder = loaded.filter(|_| arch == Some(Arch::Arm)).map(|loaded| arm::Decoder::new(program, &section.section_name, loaded));
+    let listing = &section.instructions;
+    let instructions = listing.instruction_vec_in(range.start..end);
+    let offsets = listing.instruction_offset_vec_in(range.start..end);
+    let sizes = listing.instruction_size_vec_in(range.start..end);
+    let mut mask = vec![0xffu8; end - range.start];
+    // RISC-V and AArch64 registers holding the upper bits of an address from
+    // auipc, lui or adrp.
+    let mut upper_regs = Vec::<u8>::new();
+    for ((ins, offset), size) in instructions.iter().zip(offsets).zip(sizes) {
+        let addr = section.addr + offset as u64;
+        let ins_bytes = &bytes[offset..(offset + size).min(bytes.len())];
+        let ins_mask = match arch {
+            Some(Arch::X86) => x86_mask(program, ins, ins_bytes, addr, &func_addrs),
+            Some(Arch::Arm) if arm_decoder.as_ref().is_some_and(|decoder| decoder.is_thumb(offset)) => thumb_mask(program, ins, ins_bytes, addr, &func_addrs),
+            Some(Arch::Arm) => arm_mask(program, ins_bytes, addr, &func_addrs),
+            Some(Arch::RiscV) => riscv_mask(program, ins, ins_bytes, addr, &func_addrs, &mut upper_regs),
+            Some(Arch::AArch64) => aarch64_mask(ins, ins_bytes, addr, &func_addrs, &mut upper_regs),
+            None => vec![0xff; ins_bytes.len()],
+        };
+        for (i, m) in ins_mask.into_iter().enumerate() {
+            // Patterns can only leave out whole nibbles.
+            let m = (if m & 0xf0 == 0xf0 { 0xf0 } else { 0 }) | (if m & 0x0f == 0x0f { 0x0f } else { 0 });
+            if let Some(slot) = mask.get_mut(offset + i - range.start) {
+                *slot = m;
+            }
+        }
+    }
+    // Wildcards at the end don't tell anything apart.
+    while mask.last() == Some(&0) {
+        mask.pop();
+    }
+    let pattern = Pattern { bytes: bytes[range.start..range.start + mask.len()].to_vec(), mask };
+    if pattern.fixed_bytes() < MIN_FIXED_BYTES {
+        return Err(BaretkError::InvalidArgument(format!("Function \"{}\" has only {} fixed byte(s), too few for a signature.", name, pattern.fixed_bytes())))
+    }
+    Ok(Signature { name, pattern })
+}
+
+// Whether a value is an address in one of the program's loaded sections.
+fn is_program_addr(program: &Program, value: u64) -> bool {
+    program.section_table.values().any(|section| section.addr != 0 && value >= section.addr && value < section.addr + section.bytes.len() as u64)
+}
+
+// The target of a direct call, jump or branch.
+fn branch_target(ins: &dis::Instruction, addr: u64) -> Option<u64> {
+    match dis::flow(ins, addr) {
+        Flow::Jump(target) | Flow::Branch(target) => target,
+        _ => dis::call_target(ins, addr),
+    }
+}
+
+// Wildcards the bytes of a field with this value, looked for after the first
+// opcode byte.
+fn wildcard_field(bytes: &[u8], mask: &mut [u8], value: &[u8]) -> bool {
+    match (1..bytes.len()).find(|i| bytes[*i..].starts_with(value)) {
+        Some(i) => {
+            mask[i..i + value.len()].fill(0);
+            true
+        },
+        None => false,
+    }
+}
+
+// x86 instructions vary in length, so fields are found from the operands:
+// relative targets end the instruction, and displacements and immediates are
+// looked for by value.
+fn x86_mask(program: &Program, ins: &dis::Instruction, bytes: &[u8], addr: u64, func: &Range<u64>) -> Vec<u8> {
+    let mut mask = vec![0xff; bytes.len()];
+    if let Some(target) = branch_target(ins, addr) {
+        if !func.contains(&target) {
+            let len = if bytes.len() >= 5 { 4 } else { 1 };
+            let start = bytes.len().saturating_sub(len);
+            mask[start..].fill(0);
+        }
+        return mask
+    }
+    for op in &ins.operands {
+        let value = match op {
+            Operand::Memory(Some(base), _, _, disp, _) if base.class() == RegClass::ProgramCounter => {
+                wildcard_field(bytes, &mut mask, &(*disp as i32).to_le_bytes());
+                continue;
+            },
+            Operand::Memory(_, _, _, value, _) | Operand::Immediate(value) => *value as u64,
+            _ => continue,
+        };
+        if is_program_addr(program, value) && !(program.bits == 64 && wildcard_field(bytes, &mut mask, &value.to_le_bytes())) {
+            wildcard_field(bytes, &mut mask, &(value as u32).to_le_bytes());
+        }
+    }
+    mask
+}
+
+// ARM instructions are one word with fixed fields.
+fn arm_mask(program: &Program, bytes: &[u8], addr: u64, func: &Range<u64>) -> Vec<u8> {
+    let Ok(word) = <[u8; 4]>::try_from(bytes) else {
+        return vec![0xff; bytes.len()]
+    };
+    let big = program.endianess == BIG_ENDIAN;
+    let word = if big { u32::from_be_bytes(word) } else { u32::from_le_bytes(word) };
+    let mask: u32 = if word >> 28 != 0xf && (word >> 25) & 0x7 == 0b101 {
+        // b and bl, with a word offset from 8 bytes ahead.
+        let target = addr.wrapping_add(8).wrapping_add((((word << 8) as i32 >> 6) as i64) as u64);
+        if func.contains(&target) { !0 } else { 0xff00_0000 }
+    }
+    else if (word >> 26) & 0x3 == 0b01 && (word >> 16) & 0xf == 15 {
+        // ldr and str relative to pc.
+        0xffff_f000
+    }
+    else if (word >> 25) & 0x7 == 0 && word & 0x0040_0090 == 0x0040_0090 && (word >> 16) & 0xf == 15 {
+        // ldrh, ldrd and the like relative to pc, with the offset split in two.
+        0xffff_f0f0
+    }
+    else if is_program_addr(program, word as u64) {
+        // Literal pool entries holding addresses.
+        0
+    }
+    else {
+        !0
+    };
+    if big { mask.to_be_bytes().to_vec() } else { mask.to_le_bytes().to_vec() }
+}
+
+// Thumb instructions are one or two halfwords. Branches out of the function
+// keep their opcode and condition, and pc-relative loads and adr their register.
+fn thumb_mask(program: &Program, ins: &dis::Instruction, bytes: &[u8], addr: u64, func: &Range<u64>) -> Vec<u8> {
+    let big = program.endianess == BIG_ENDIAN;
+    let half = |i: usize| if big { u16::from_be_bytes([bytes[i], bytes[i + 1]]) } else { u16::from_le_bytes([bytes[i], bytes[i + 1]]) };
+    let leaves = branch_target(ins, addr).is_some_and(|target| !func.contains(&target));
+    let masks: Vec<u16> = match bytes.len() {
+        2 => {
+            let hw = half(0);
+            vec![if leaves && hw >> 12 == 0xd {
+                0xff00
+            } else if leaves && hw >> 11 == 0b11100 {
+                0xf800
+            } else if leaves && hw & 0xf500 == 0xb100 {
+                // cbz and cbnz.
+                0xfd07
+            } else if hw >> 11 == 0b01001 || hw >> 11 == 0b10100 {
+                // ldr from a literal and adr.
+                0xff00
+            } else {
+                !0
+            }]
+        },
+        4 => {
+            let (hw1, hw2) = (half(0), half(2));
+            if leaves && hw1 >> 11 == 0b11110 && hw2 & 0x8000 != 0 {
+                // b with a condition, then b, bl and blx.
+                if hw2 & 0x5000 == 0 { vec![0xfbc0, 0xd000] } else { vec![0xf800, 0xd000] }
+            } else if hw1 & 0xfe1f == 0xf81f {
+                vec![!0, 0xf000]
+            } else if hw1 & 0xfe5f == 0xe85f {
+                // ldrd from a literal.
+                vec![!0, 0xff00]
+            } else if hw1 & 0xfbff == 0xf2af || hw1 & 0xfbff == 0xf20f {
+                // adr.w.
+                vec![0xfbff, 0x0f00]
+            } else if util::read_u32_from_slice(bytes, 0, program.endianess).is_some_and(|word| is_program_addr(program, u64::from(word))) {
+                // Literal pool entries holding addresses.
+                vec![0, 0]
+            } else {
+                vec![!0, !0]
+            }
+        },
+        _ => return vec![0xff; bytes.len()],
+    };
+    masks.into_iter().flat_map(|m| if big { m.to_be_bytes() } else { m.to_le_bytes() }).collect()
+}
+
+// RISC-V addresses are split between an auipc or lui and the instruction
+// using the register it set, whose 12-bit offset is wildcarded too.
+fn riscv_mask(program: &Program, ins: &dis::Instruction, bytes: &[u8], addr: u64, func: &Range<u64>, upper_regs: &mut Vec<u8>) -> Vec<u8> {
+    let leaves = branch_target(ins, addr).is_some_and(|target| !func.contains(&target));
+    if bytes.len() == 2 {
+        // Compressed jumps and branches.
+        let mask: u16 = match ins.opcode {
+            "jal" if leaves => 0xe003,
+            "beq" | "bne" if leaves => 0xe383,
+            _ => !0,
+        };
+        if let Some(Operand::Register(rd)) = ins.operands.first().filter(|_| !matches!(ins.opcode, "beq" | "bne" | "sw" | "sd")) {
+            upper_regs.retain(|reg| *reg != rd.number());
+        }
+        return mask.to_le_bytes().to_vec()
+    }
+    let Ok(word) = <[u8; 4]>::try_from(bytes) else {
+        return vec![0xff; bytes.len()]
+    };
+    let word = u32::from_le_bytes(word);
+    let (opcode, rd, rs1) = (word & 0x7f, ((word >> 7) & 0x1f) as u8, ((word >> 15) & 0x1f) as u8);
+    let upper = (word & 0xffff_f000) as i32 as i64 as u64;
+    let mask: u32 = match opcode {
+        // jal keeps rd, branches and stores their registers and funct3.
+        0x6f if leaves => 0x0000_0fff,
+        0x63 if leaves => 0x01ff_f07f,
+        0x17 => 0x0000_0fff,
+        0x37 if is_program_addr(program, upper) => 0x0000_0fff,
+        0x03 | 0x13 | 0x1b | 0x67 if upper_regs.contains(&rs1) => 0x000f_ffff,
+        0x23 if upper_regs.contains(&rs1) => 0x01ff_f07f,
+        _ => !0,
+    };
+    if matches!(opcode, 0x17 | 0x37) && mask != !0 {
+        upper_regs.push(rd);
+    }
+    else if !matches!(opcode, 0x23 | 0x63) {
+        upper_regs.retain(|reg| *reg != rd);
+    }
+    mask.to_le_bytes().to_vec()
+}
+
+// AArch64 addresses are split between an adrp and the add, load or store using
+// the register it set, whose 12-bit offset is wildcarded too.
+fn aarch64_mask(ins: &dis::Instruction, bytes: &[u8], addr: u64, func: &Range<u64>, page_regs: &mut Vec<u8>) -> Vec<u8> {
+    let Ok(word) = <[u8; 4]>::try_from(bytes) else {
+        return vec![0xff; bytes.len()]
+    };
+    let word = u32::from_le_bytes(word);
+    let leaves = branch_target(ins, addr).is_some_and(|target| !func.contains(&target));
+    let (rd, rn) = ((word & 0x1f) as u8, ((word >> 5) & 0x1f) as u8);
+    let mask: u32 = if word & 0x7c00_0000 == 0x1400_0000 {
+        // b and bl.
+        if leaves { 0xfc00_0000 } else { !0 }
+    }
+    else if word & 0xff00_0010 == 0x5400_0000 || word & 0x7e00_0000 == 0x3400_0000 {
+        // b.cond, cbz and cbnz keep the condition or register.
+        if leaves { 0xff00_001f } else { !0 }
+    }
+    else if word & 0x7e00_0000 == 0x3600_0000 {
+        // tbz and tbnz keep the bit number and register.
+        if leaves { 0xfff8_001f } else { !0 }
+    }
+    else if word & 0x1f00_0000 == 0x1000_0000 {
+        // adr and adrp.
+        0x9f00_001f
+    }
+    else if word & 0x3b00_0000 == 0x1800_0000 {
+        // Loads relative to pc.
+        0xff00_001f
+    }
+    else if page_regs.contains(&rn) && (word & 0x7f80_0000 == 0x1100_0000 || word & 0x3b00_0000 == 0x3900_0000) {
+        // add, or a load or store with an unsigned offset.
+        0xffc0_03ff
+    }
+    else {
+        !0
+    };
+    if word & 0x9f00_0000 == 0x9000_0000 {
+        page_regs.push(rd);
+    }
+    else if let Some(Operand::Register(r)) = ins.operands.first().filter(|_| !matches!(ins.opcode, "str" | "ldm" | "stm" | "stp" | "stnp" | "cmp" | "cmn" | "tst" | "b" | "beq" | "bne" | "tbz" | "tbnz" | "call")) {
+        page_regs.retain(|reg| *reg != r.number());
+    }
+    mask.to_le_bytes().to_vec()
+}
@@ -0,0 +1,260 @@
+// Worst-case stack usage per function. Each function's own frame is found by
+// following stack pointer adjustments through its basic blocks; the total adds
+// the deepest chain of calls made from it. Recursion, stack allocations of
+// unknown size and calls that can't be followed make the total a lower bound,
+// and are reported as such.
+
+use std::collections::HashMap;
+
+use crate::cfg;
+use crate::dis::{self, Disassembly, Instruction};
+use crate::reg::{Reg, RegClass};
+use crate::resolve;
+
+pub struct StackUsage {
+    pub name: String,
+    pub addr: u64,
+    // Deepest the function's own frame gets, in bytes.
+    pub frame: u64,
+    // Frame plus the deepest call chain below it, including return addresses.
+    pub total: u64,
+    // The stack pointer is adjusted by a value only known at run time.
+    pub dynamic: bool,
+    // The function is part of a call cycle.
+    pub recursive: bool,
+    // Calls through unresolved pointers or to code outside the known functions.
+    pub unknown_calls: usize,
+    // No dynamic frames, recursion or unknown calls anywhere below the function,
+    // so the total is an upper bound.
+    pub bounded: bool,
+    // Names of the functions on the deepest call chain, starting with this one.
+    pub chain: Vec<String>,
+}
+
+struct Call {
+    // Stack depth in the caller when the callee starts, including any return address.
+    depth: u64,
+    target: u64,
+}
+
+struct Frame {
+    frame: u64,
+    dynamic: bool,
+    calls: Vec<Call>,
+    unknown_calls: usize,
+}
+
+// Stack pointer and the size of a pushed word.
+fn stack_pointer(dis: &Disassembly) -> (Reg, u64) {
+    match dis.program().machine_type.as_str() {
+        "amd64" => (Reg::RSP, 8),
+        "x86" => (Reg::ESP, 4),
+        "riscv" if dis.program().bits == 32 => (Reg::RISCV_SP, 4),
+        "riscv" => (Reg::RISCV_SP, 8),
+        "arm" => (Reg::ARM_SP, 4),
+        "aarch64" => (Reg::AARCH64_SP, 8),
+        _ => (Reg::ARM_SP, (dis.program().bits / 8) as u64),
+    }
+}
+
+// How much deeper the stack gets after the instruction, or None if it changes
+// by an amount that isn't known.
+fn stack_change(ins: &Instruction, sp: Reg, word: u64) -> Option<i64> {
+    let is_sp = |op: Option<&dis::Operand>| matches!(op, Some(dis::Operand::Register(r)) if *r == sp);
+    let imm = |op: Option<&dis::Operand>| match op {
+        Some(dis::Operand::Immediate(i)) => Some(*i),
+        _ => None,
+    };
+    match ins.opcode {
+        "push" => Some((word * ins.operands.len().max(1) as u64) as i64),
+        "pop" => Some(-((word * ins.operands.len().max(1) as u64) as i64)),
+        "ldm" | "stm" if is_sp(ins.operands.first()) => imm(ins.operands.get(2)).map(|w| -w),
+        "ldr" | "str" if ins.flags & (dis::FLAG_PRE_INDEX | dis::FLAG_POST_INDEX) != 0 => match ins.operands.get(1) {
+            Some(dis::Operand::Memory(base, _, _, _, _)) if *base == Some(sp) => imm(ins.operands.get(2)).map(|w| -w),
+            _ => Some(0),
+        },
+        "add" | "sub" if is_sp(ins.operands.first()) => {
+            if !is_sp(ins.operands.get(1)) {
+                return None
+            }
+            let amount = imm(ins.operands.get(2))?;
+            Some(if ins.opcode == "sub" { amount } else { -amount })
+        },
+        _ => Some(0),
+    }
+}
+
+fn analyze_frame(dis: &Disassembly, func: &dis::Function, starts: &HashMap<u64, usize>) -> Frame {
+    let (sp, word) = stack_pointer(dis);
+    // Only x86 pushes a return address; the others keep it in a link register.
+    let return_address = if matches!(dis.program().machine_type.as_str(), "amd64" | "x86") { word } else { 0 };
+    let listing = &dis.section().instructions;
+    let instrs = listing.instruction_vec_in(func.range.clone());
+    let base = dis.section_addr();
+    let addrs: Vec<u64> = listing.instruction_offset_vec_in(func.range.clone()).iter().map(|o| base + *o as u64).collect();
+    let resolved = resolve::resolve_indirect_in(dis, func.range.clone());
+    let graph = cfg::build_cfg(dis, &func.name, func.range.clone());
+    let inside = |t: u64| t >= func.addr && addrs.last().map_or(false, |last| t <= *last);
+
+    let mut result = Frame { frame: 0, dynamic: false, calls: Vec::new(), unknown_calls: 0 };
+    // Blocks are visited in address order, entered with the depth of the first
+    // visited predecessor, or of the block before them when none was.
+    let mut entry = HashMap::<u64, i64>::new();
+    let mut depth = 0i64;
+    let mut i = 0;
+    for block in &graph.blocks {
+        depth = *entry.get(&block.start).unwrap_or(&depth);
+        while i < addrs.len() && addrs[i] < block.start {
+            i += 1;
+        }
+        while i < addrs.len() && addrs[i] < block.end {
+            let (ins, addr) = (&instrs[i], addrs[i]);
+            let relative = match ins.opcode {
+                "call" | "b" => ins.operands.first(),
+                "jal" => ins.operands.get(1),
+                _ => None,
+            };
+            let direct = match relative {
+                Some(dis::Operand::Immediate(rel)) => Some(addr.wrapping_add(*rel as u64)),
+                _ => None,
+            };
+            let is_call = match ins.opcode {
+                "call" => true,
+                "jal" | "jalr" => !matches!(ins.operands.first(), Some(dis::Operand::Register(r)) if r.class() == RegClass::Zero),
+                _ => false,
+            };
+            let target = direct.or(resolved.get(&addr).map(|r| r.target));
+            // Jumps to the start of another function are tail calls.
+            let tail_call = !is_call && ins.opcode == "b" && target.map_or(false, |t| !inside(t) && starts.contains_key(&t));
+            if is_call || tail_call {
+                let push = if is_call { return_address } else { 0 };
+                match target.filter(|t| starts.contains_key(t)) {
+                    Some(t) => result.calls.push(Call { depth: depth.max(0) as u64 + push, target: t }),
+                    None => result.unknown_calls += 1,
+                }
+            }
+            match stack_change(ins, sp, word) {
+                Some(change) => depth += change,
+                None => result.dynamic = true,
+            }
+            result.frame = result.frame.max(depth.max(0) as u64);
+            i += 1;
+        }
+        for (succ, _) in &block.succs {
+            entry.entry(*succ).or_insert(depth);
+        }
+    }
+    result
+}
+
+struct Walker<'a> {
+    names: Vec<&'a str>,
+    starts: &'a HashMap<u64, usize>,
+    frames: Vec<Frame>,
+    // Total and the callee on the deepest chain, once known.
+    totals: Vec<Option<(u64, Option<usize>)>>,
+    recursive: Vec<bool>,
+    on_stack: Vec<usize>,
+}
+
+impl<'a> Walker<'a> {
+    fn total(&mut self, f: usize) -> u64 {
+        if let Some((total, _)) = self.totals[f] {
+            return total
+        }
+        // A call back into a function still being walked is a cycle; it adds nothing.
+        if let Some(pos) = self.on_stack.iter().position(|g| *g == f) {
+            for g in &self.on_stack[pos..] {
+                self.recursive[*g] = true;
+            }
+            return 0
+        }
+        self.on_stack.push(f);
+        let mut best = (self.frames[f].frame, None);
+        let calls: Vec<(u64, usize)> = self.frames[f].calls.iter().map(|c| (c.depth, self.starts[&c.target])).collect();
+        for (depth, callee) in calls {
+            let total = depth + self.total(callee);
+            if total > best.0 {
+                best = (total, Some(callee));
+            }
+        }
+        self.on_stack.pop();
+        self.totals[f] = Some(best);
+        best.0
+    }
+
+    fn bounded(&self, f: usize) -> bool {
+        let mut seen = vec![false; self.frames.len()];
+        let mut work = vec![f];
+        while let Some(g) = work.pop() {
+            if seen[g] {
+                continue;
+            }
+            seen[g] = true;
+            let frame = &self.frames[g];
+            if frame.dynamic || frame.unknown_calls > 0 || self.recursive[g] {
+                return false
+            }
+            work.extend(frame.calls.iter().map(|c| self.starts[&c.target]));
+        }
+        true
+    }
+
+    fn chain(&self, f: usize) -> Vec<String> {
+        let mut out = vec![self.names[f].to_string()];
+        let mut cur = f;
+        while let Some(Some((_, Some(next)))) = self.totals.get(cur) {
+            // Stop if a cycle brings the chain back around.
+            if out.len() > self.names.len() {
+                break;
+            }
+            out.push(self.names[*next].to_string());
+            cur = *next;
+        }
+        out
+    }
+}
+
+pub fn analyze(dis: &Disassembly) -> Vec<StackUsage> {
+    let funcs = dis.functions();
+    let starts: HashMap<u64, usize> = funcs.iter().enumerate().map(|(i, f)| (f.addr, i)).collect();
+    let frames: Vec<Frame> = funcs.iter().map(|f| analyze_frame(dis, f, &starts)).collect();
+    let mut walker = Walker {
+        names: funcs.iter().map(|f| f.name.as_str()).collect(),
+        starts: &starts,
+        frames,
+        totals: vec![None; funcs.len()],
+        recursive: vec![false; funcs.len()],
+        on_stack: Vec::new(),
+    };
+    for f in 0..funcs.len() {
+        walker.total(f);
+    }
+    funcs.iter().enumerate().map(|(f, func)| StackUsage {
+        name: func.name.clone(),
+        addr: func.addr,
+        frame: walker.frames[f].frame,
+        total: walker.totals[f].map_or(0, |(total, _)| total),
+        dynamic: walker.frames[f].dynamic,
+        recursive: walker.recursive[f],
+        unknown_calls: walker.frames[f].unknown_calls,
+        bounded: walker.bounded(f),
+        chain: walker.chain(f),
+    }).collect()
+}
+
+impl StackUsage {
+    pub fn notes(&self) -> String {
+        let mut notes = Vec::<String>::new();
+        if self.recursive {
+            notes.push("recursive".to_string());
+        }
+        if self.dynamic {
+            notes.push("dynamic".to_string());
+        }
+        if self.unknown_calls > 0 {
+            notes.push(format!("{} unknown call(s)", self.unknown_calls));
+        }
+        notes.join(", ")
+    }
+}
@@ -0,0 +1,163 @@
+// Disassembly of images too big to hold decoded at once. Each instruction is
+// written out as it's decoded instead of being kept in a listing, and raw
+// images are read from the file a window at a time, so memory use stays
+// within the budget however big the image is.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+use crate::dis::{self, InstructionRecord};
+use crate::error::BaretkError;
+use crate::loader;
+use crate::options::{AnalysisOptions, Sweep};
+use crate::prog::{self, Program};
+
+// Longest instruction of any architecture. Decoding stops this far from the
+// end of a window, unless it's the last, so no instruction is cut in two.
+const MAX_INSTRUCTION_SIZE: usize = 16;
+const MIN_WINDOW_SIZE: usize = 1 << 16;
+
+// A window is held twice, as read and as the program's copy, which leaves half
+// the budget for everything else.
+pub fn window_size(budget: usize) -> usize {
+    (budget / 4).max(MIN_WINDOW_SIZE)
+}
+
+pub enum Format {
+    Text { show_bytes: bool },
+    Json,
+}
+
+// Disassembles a file to `out`, named `out_name` in errors, within
+// options.memory_budget. ELF and PE files are loaded whole, since their headers
+// can point anywhere in the file, but their listing is still streamed.
+// `prepare` is given the program before it's decoded, to add symbols and
+// comments, and returns false to stop with Err(Cancelled).
+pub fn disassemble_file(path: &str, options: &AnalysisOptions, format: &Format, out: &mut impl Write, out_name: &str, mut prepare: impl FnMut(&mut Program, &[u8]) -> bool) -> Result<(), BaretkError> {
+    if options.sweep == Sweep::Recursive {
+        return Err(BaretkError::InvalidArgument("The recursive sweep keeps everything it finds and can't be used with a memory budget.".to_string()))
+    }
+    let window = window_size(options.memory_budget.unwrap_or(usize::MAX));
+    let mut file = File::open(path).map_err(|error| BaretkError::io(path, "opening", error))?;
+    let file_size = file.metadata().map_err(|error| BaretkError::io(path, "reading", error))?.len();
+    let mut bytes = read_window(&mut file, path, 0, window)?;
+    if loader::find_loader(&bytes).is_some() || bytes.len() as u64 == file_size {
+        file.read_to_end(&mut bytes).map_err(|error| BaretkError::io(path, "reading", error))?;
+        let bytes: Arc<[u8]> = Arc::from(bytes);
+        let mut program = prog::load_program_from_shared(bytes.clone(), options.parse_mode)?;
+        prog::apply_options(&mut program, options);
+        if !prepare(&mut program, &bytes) {
+            return Err(BaretkError::Cancelled)
+        }
+        if !program.section_table.contains_key(program.code_section()) {
+            return Err(BaretkError::NotFound(format!("Code section {}", program.code_section())))
+        }
+        // Every executable section, in the same order and with the same
+        // headers as a listing kept in memory.
+        for (i, name) in dis::executable_sections(&program).into_iter().enumerate() {
+            write_header(&program, name, i == 0, format, out, out_name)?;
+            write_listing(&program, name, format, true, out, out_name)?;
+        }
+        return Ok(())
+    }
+    // A raw image is decoded as a run of raw programs, each starting where the
+    // last stopped decoding. `prepare` only sees the first, whose symbols and
+    // comments are handed on to the rest.
+    let mut pos = 0u64;
+    let mut prepared = None;
+    loop {
+        let last = pos + bytes.len() as u64 == file_size;
+        let mut program = prog::build_program_from_binary(&bytes, None, None, None);
+        prog::apply_options(&mut program, options);
+        if let Some(section) = program.section_table.get_mut("file") {
+            section.addr += pos;
+        }
+        match prepared.take() {
+            Some((symbols, comments)) => (program.symbol_table, program.comments) = (symbols, comments),
+            None => {
+                if !prepare(&mut program, &bytes) {
+                    return Err(BaretkError::Cancelled)
+                }
+                write_header(&program, program.code_section(), true, format, out, out_name)?;
+            },
+        }
+        let decoded = write_listing(&program, program.code_section(), format, last, out, out_name)?;
+        if last {
+            return Ok(())
+        }
+        prepared = Some((program.symbol_table, program.comments));
+        pos += decoded as u64;
+        bytes = read_window(&mut file, path, pos, window)?;
+    }
+}
+
+fn read_window(file: &mut File, path: &str, pos: u64, len: usize) -> Result<Vec<u8>, BaretkError> {
+    let mut bytes = Vec::<u8>::new();
+    file.seek(SeekFrom::Start(pos)).map_err(|error| BaretkError::io(path, "reading", error))?;
+    file.take(len as u64).read_to_end(&mut bytes).map_err(|error| BaretkError::io(path, "reading", error))?;
+    Ok(bytes)
+}
+
+// Text listings start each section with its name and address, after a blank
+// line unless it's the first.
+fn write_header(program: &Program, section_name: &str, first: bool, format: &Format, out: &mut impl Write, out_name: &str) -> Result<(), BaretkError> {
+    if let Format::Text { .. } = format {
+        let mut header = if first { String::new() } else { String::from("\n") };
+        header += format!(".section {}\n", section_name).as_str();
+        if let Some(section) = program.section_table.get(section_name) {
+            header += format!(".org {:#010x}\n", section.addr).as_str();
+        }
+        out.write_all(header.as_bytes()).map_err(|error| BaretkError::io(out_name, "writing", error))?;
+    }
+    Ok(())
+}
+
+// Writes the instructions of one section and returns the section offset
+// decoding stopped at. Unless `last`, it stops before any instruction that
+// could run past the end of the section.
+fn write_listing(program: &Program, section_name: &str, format: &Format, last: bool, out: &mut impl Write, out_name: &str) -> Result<usize, BaretkError> {
+    let (addr, bytes) = match program.section_table.get(section_name) {
+        Some(section) => (section.addr, section.bytes.as_slice()),
+        None => return Err(BaretkError::NotFound(format!("Section {}", section_name))),
+    };
+    let labels: HashMap<u64, &str> = program.symbol_table.iter()
+        .filter(|sym| sym.is_func && !sym.name.is_empty())
+        .map(|sym| (sym.addr, sym.name.as_str()))
+        .collect();
+    let mut end = bytes.len();
+    let mut result = Ok(());
+    let mut line = String::new();
+    let supported = dis::for_each_instruction(program, section_name, |_, offset, size, text| {
+        if !last && offset + MAX_INSTRUCTION_SIZE > bytes.len() {
+            end = offset;
+            return false
+        }
+        let ins_bytes = &bytes[offset..(offset + size).min(bytes.len())];
+        line.clear();
+        match format {
+            Format::Text { show_bytes } => dis::print_line(&mut line, &text, addr + offset as u64, show_bytes.then_some(ins_bytes), &labels, &program.comments),
+            Format::Json => {
+                let (mnemonic, operands) = dis::split_instruction_text(&text);
+                let record = InstructionRecord {
+                    addr: addr + offset as u64,
+                    bytes: ins_bytes.to_vec(),
+                    mnemonic: mnemonic.to_string(),
+                    operands,
+                    label: labels.get(&(addr + offset as u64)).map(|label| label.to_string()),
+                    comment: program.comments.get(&(addr + offset as u64)).cloned(),
+                };
+                record.to_json().write(&mut line);
+                line += "\n";
+            },
+        }
+        result = out.write_all(line.as_bytes());
+        result.is_ok()
+    });
+    if !supported {
+        return Err(BaretkError::UnsupportedArch(program.machine_type.clone()))
+    }
+    result.map_err(|error| BaretkError::io(out_name, "writing", error))?;
+    Ok(end)
+}
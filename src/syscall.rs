@@ -0,0 +1,295 @@
+// Linux system call tables, used to name syscall instructions in decompiled output
+// and to summarize the system calls a program makes.
+
+use crate::reg::Reg;
+
+pub struct Syscall {
+    pub number: i64,
+    pub name: &'static str,
+    pub argc: u8,
+}
+
+// Where a syscall convention takes its number and arguments from, and where the result goes.
+pub struct SyscallAbi {
+    pub number: Reg,
+    pub args: &'static [Reg],
+    pub ret: Reg,
+    pub table: &'static [Syscall],
+}
+
+const fn sys(number: i64, name: &'static str, argc: u8) -> Syscall {
+    Syscall { number, name, argc }
+}
+
+static SYSCALLS_I386: [Syscall; 47] = [
+    sys(1, "sys_exit", 1),
+    sys(2, "sys_fork", 0),
+    sys(3, "sys_read", 3),
+    sys(4, "sys_write", 3),
+    sys(5, "sys_open", 3),
+    sys(6, "sys_close", 1),
+    sys(7, "sys_waitpid", 3),
+    sys(8, "sys_creat", 2),
+    sys(9, "sys_link", 2),
+    sys(10, "sys_unlink", 1),
+    sys(11, "sys_execve", 3),
+    sys(12, "sys_chdir", 1),
+    sys(13, "sys_time", 1),
+    sys(15, "sys_chmod", 2),
+    sys(19, "sys_lseek", 3),
+    sys(20, "sys_getpid", 0),
+    sys(24, "sys_getuid", 0),
+    sys(33, "sys_access", 2),
+    sys(37, "sys_kill", 2),
+    sys(38, "sys_rename", 2),
+    sys(39, "sys_mkdir", 2),
+    sys(40, "sys_rmdir", 1),
+    sys(41, "sys_dup", 1),
+    sys(42, "sys_pipe", 1),
+    sys(45, "sys_brk", 1),
+    sys(54, "sys_ioctl", 3),
+    sys(55, "sys_fcntl", 3),
+    sys(63, "sys_dup2", 2),
+    sys(64, "sys_getppid", 0),
+    sys(90, "sys_mmap", 1),
+    sys(91, "sys_munmap", 2),
+    sys(102, "sys_socketcall", 2),
+    sys(114, "sys_wait4", 4),
+    sys(120, "sys_clone", 5),
+    sys(122, "sys_uname", 1),
+    sys(125, "sys_mprotect", 3),
+    sys(162, "sys_nanosleep", 2),
+    sys(183, "sys_getcwd", 2),
+    sys(190, "sys_vfork", 0),
+    sys(192, "sys_mmap2", 6),
+    sys(224, "sys_gettid", 0),
+    sys(240, "sys_futex", 6),
+    sys(252, "sys_exit_group", 1),
+    sys(265, "sys_clock_gettime", 2),
+    sys(295, "sys_openat", 4),
+    sys(355, "sys_getrandom", 3),
+    sys(359, "sys_socket", 3),
+];
+
+static SYSCALLS_AMD64: [Syscall; 61] = [
+    sys(0, "sys_read", 3),
+    sys(1, "sys_write", 3),
+    sys(2, "sys_open", 3),
+    sys(3, "sys_close", 1),
+    sys(4, "sys_stat", 2),
+    sys(5, "sys_fstat", 2),
+    sys(6, "sys_lstat", 2),
+    sys(7, "sys_poll", 3),
+    sys(8, "sys_lseek", 3),
+    sys(9, "sys_mmap", 6),
+    sys(10, "sys_mprotect", 3),
+    sys(11, "sys_munmap", 2),
+    sys(12, "sys_brk", 1),
+    sys(13, "sys_rt_sigaction", 4),
+    sys(14, "sys_rt_sigprocmask", 4),
+    sys(16, "sys_ioctl", 3),
+    sys(17, "sys_pread64", 4),
+    sys(18, "sys_pwrite64", 4),
+    sys(19, "sys_readv", 3),
+    sys(20, "sys_writev", 3),
+    sys(21, "sys_access", 2),
+    sys(22, "sys_pipe", 1),
+    sys(23, "sys_select", 5),
+    sys(24, "sys_sched_yield", 0),
+    sys(25, "sys_mremap", 5),
+    sys(32, "sys_dup", 1),
+    sys(33, "sys_dup2", 2),
+    sys(35, "sys_nanosleep", 2),
+    sys(39, "sys_getpid", 0),
+    sys(41, "sys_socket", 3),
+    sys(42, "sys_connect", 3),
+    sys(43, "sys_accept", 3),
+    sys(44, "sys_sendto", 6),
+    sys(45, "sys_recvfrom", 6),
+    sys(49, "sys_bind", 3),
+    sys(50, "sys_listen", 2),
+    sys(56, "sys_clone", 5),
+    sys(57, "sys_fork", 0),
+    sys(58, "sys_vfork", 0),
+    sys(59, "sys_execve", 3),
+    sys(60, "sys_exit", 1),
+    sys(61, "sys_wait4", 4),
+    sys(62, "sys_kill", 2),
+    sys(63, "sys_uname", 1),
+    sys(72, "sys_fcntl", 3),
+    sys(79, "sys_getcwd", 2),
+    sys(80, "sys_chdir", 1),
+    sys(82, "sys_rename", 2),
+    sys(83, "sys_mkdir", 2),
+    sys(84, "sys_rmdir", 1),
+    sys(87, "sys_unlink", 1),
+    sys(89, "sys_readlink", 3),
+    sys(90, "sys_chmod", 2),
+    sys(102, "sys_getuid", 0),
+    sys(110, "sys_getppid", 0),
+    sys(158, "sys_arch_prctl", 2),
+    sys(186, "sys_gettid", 0),
+    sys(202, "sys_futex", 6),
+    sys(231, "sys_exit_group", 1),
+    sys(257, "sys_openat", 4),
+    sys(318, "sys_getrandom", 3),
+];
+
+// ARM EABI numbers. OABI binaries encode the same number in the svc immediate,
+// offset by 0x900000.
+static SYSCALLS_ARM: [Syscall; 47] = [
+    sys(1, "sys_exit", 1),
+    sys(2, "sys_fork", 0),
+    sys(3, "sys_read", 3),
+    sys(4, "sys_write", 3),
+    sys(5, "sys_open", 3),
+    sys(6, "sys_close", 1),
+    sys(8, "sys_creat", 2),
+    sys(9, "sys_link", 2),
+    sys(10, "sys_unlink", 1),
+    sys(11, "sys_execve", 3),
+    sys(12, "sys_chdir", 1),
+    sys(15, "sys_chmod", 2),
+    sys(19, "sys_lseek", 3),
+    sys(20, "sys_getpid", 0),
+    sys(24, "sys_getuid", 0),
+    sys(33, "sys_access", 2),
+    sys(37, "sys_kill", 2),
+    sys(38, "sys_rename", 2),
+    sys(39, "sys_mkdir", 2),
+    sys(40, "sys_rmdir", 1),
+    sys(41, "sys_dup", 1),
+    sys(42, "sys_pipe", 1),
+    sys(45, "sys_brk", 1),
+    sys(54, "sys_ioctl", 3),
+    sys(55, "sys_fcntl", 3),
+    sys(63, "sys_dup2", 2),
+    sys(64, "sys_getppid", 0),
+    sys(91, "sys_munmap", 2),
+    sys(114, "sys_wait4", 4),
+    sys(120, "sys_clone", 5),
+    sys(122, "sys_uname", 1),
+    sys(125, "sys_mprotect", 3),
+    sys(162, "sys_nanosleep", 2),
+    sys(183, "sys_getcwd", 2),
+    sys(190, "sys_vfork", 0),
+    sys(192, "sys_mmap2", 6),
+    sys(224, "sys_gettid", 0),
+    sys(240, "sys_futex", 6),
+    sys(248, "sys_exit_group", 1),
+    sys(263, "sys_clock_gettime", 2),
+    sys(281, "sys_socket", 3),
+    sys(282, "sys_bind", 3),
+    sys(283, "sys_connect", 3),
+    sys(284, "sys_listen", 2),
+    sys(285, "sys_accept", 3),
+    sys(322, "sys_openat", 4),
+    sys(384, "sys_getrandom", 3),
+];
+
+// The generic table shared by RISC-V and other newer ports.
+static SYSCALLS_GENERIC: [Syscall; 44] = [
+    sys(17, "sys_getcwd", 2),
+    sys(23, "sys_dup", 1),
+    sys(24, "sys_dup3", 3),
+    sys(25, "sys_fcntl", 3),
+    sys(29, "sys_ioctl", 3),
+    sys(34, "sys_mkdirat", 3),
+    sys(35, "sys_unlinkat", 3),
+    sys(49, "sys_chdir", 1),
+    sys(56, "sys_openat", 4),
+    sys(57, "sys_close", 1),
+    sys(59, "sys_pipe2", 2),
+    sys(61, "sys_getdents64", 3),
+    sys(62, "sys_lseek", 3),
+    sys(63, "sys_read", 3),
+    sys(64, "sys_write", 3),
+    sys(65, "sys_readv", 3),
+    sys(66, "sys_writev", 3),
+    sys(78, "sys_readlinkat", 4),
+    sys(80, "sys_fstat", 2),
+    sys(93, "sys_exit", 1),
+    sys(94, "sys_exit_group", 1),
+    sys(98, "sys_futex", 6),
+    sys(101, "sys_nanosleep", 2),
+    sys(113, "sys_clock_gettime", 2),
+    sys(124, "sys_sched_yield", 0),
+    sys(129, "sys_kill", 2),
+    sys(134, "sys_rt_sigaction", 4),
+    sys(160, "sys_uname", 1),
+    sys(172, "sys_getpid", 0),
+    sys(173, "sys_getppid", 0),
+    sys(174, "sys_getuid", 0),
+    sys(178, "sys_gettid", 0),
+    sys(198, "sys_socket", 3),
+    sys(200, "sys_bind", 3),
+    sys(201, "sys_listen", 2),
+    sys(202, "sys_accept", 3),
+    sys(203, "sys_connect", 3),
+    sys(214, "sys_brk", 1),
+    sys(215, "sys_munmap", 2),
+    sys(220, "sys_clone", 5),
+    sys(221, "sys_execve", 3),
+    sys(222, "sys_mmap", 6),
+    sys(226, "sys_mprotect", 3),
+    sys(260, "sys_wait4", 4),
+];
+
+static ABI_I386: SyscallAbi = SyscallAbi {
+    number: Reg::EAX,
+    args: &[Reg::EBX, Reg::ECX, Reg::EDX, Reg::ESI, Reg::EDI, Reg::EBP],
+    ret: Reg::EAX,
+    table: &SYSCALLS_I386,
+};
+
+static ABI_AMD64: SyscallAbi = SyscallAbi {
+    number: Reg::RAX,
+    args: &[Reg::RDI, Reg::RSI, Reg::RDX, Reg::R10, Reg::R8, Reg::R9],
+    ret: Reg::RAX,
+    table: &SYSCALLS_AMD64,
+};
+
+static ABI_ARM: SyscallAbi = SyscallAbi {
+    number: Reg::arm(7),
+    args: &[Reg::arm(0), Reg::arm(1), Reg::arm(2), Reg::arm(3), Reg::arm(4), Reg::arm(5), Reg::arm(6)],
+    ret: Reg::arm(0),
+    table: &SYSCALLS_ARM,
+};
+
+static ABI_AARCH64: SyscallAbi = SyscallAbi {
+    number: Reg::aarch64(8),
+    args: &[Reg::aarch64(0), Reg::aarch64(1), Reg::aarch64(2), Reg::aarch64(3), Reg::aarch64(4), Reg::aarch64(5)],
+    ret: Reg::aarch64(0),
+    table: &SYSCALLS_GENERIC,
+};
+
+static ABI_RISCV: SyscallAbi = SyscallAbi {
+    number: Reg::RISCV_A7,
+    args: &[Reg::RISCV_A0, Reg::RISCV_A1, Reg::RISCV_A2, Reg::RISCV_A3, Reg::RISCV_A4, Reg::RISCV_A5],
+    ret: Reg::RISCV_A0,
+    table: &SYSCALLS_GENERIC,
+};
+
+// The convention used by a syscall instruction. `svc` is the immediate of an
+// int/svc instruction, or None for syscall/ecall.
+pub fn syscall_abi(machine_type: &str, svc: Option<i64>) -> Option<&'static SyscallAbi> {
+    match (machine_type, svc) {
+        ("x86" | "amd64", Some(0x80)) => Some(&ABI_I386),
+        ("amd64", None) => Some(&ABI_AMD64),
+        ("arm", Some(_)) => Some(&ABI_ARM),
+        ("aarch64", Some(_)) => Some(&ABI_AARCH64),
+        ("riscv" | "riscv32" | "riscv64", None) => Some(&ABI_RISCV),
+        _ => None,
+    }
+}
+
+impl SyscallAbi {
+    pub fn lookup(&self, number: i64) -> Option<&'static Syscall> {
+        self.table.iter().find(|sys| sys.number == number)
+    }
+}
+
+// The syscall number encoded directly in an ARM OABI `svc` immediate.
+pub fn arm_oabi_number(svc: i64) -> Option<i64> {
+    if svc >= 0x900000 && svc < 0xa00000 { Some(svc - 0x900000) } else { None }
+}
@@ -0,0 +1,160 @@
+// Identifies the compiler and runtime a binary was built with from the
+// fingerprints they leave behind.
+
+use crate::prog::Program;
+use crate::util::{read_u32_from_slice, LITTLE_ENDIAN};
+
+pub struct Toolchain {
+    pub name: String,
+    // Probable version, when the fingerprint carries one.
+    pub version: Option<String>,
+    // What the guess is based on.
+    pub evidence: String,
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+// Version-like token following `prefix`, e.g. "11.4.0" after "GCC: (Ubuntu ...) ".
+fn version_after<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = &s[s.find(prefix)? + prefix.len()..];
+    let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '-')).unwrap_or(rest.len());
+    if end > 0 { Some(&rest[..end]) } else { None }
+}
+
+// ELF .comment holds one NUL-terminated string per toolchain component that touched the file.
+fn from_comment(program: &Program, out: &mut Vec<Toolchain>) {
+    let comment = match program.section_table.get(".comment") {
+        Some(section) => &section.bytes,
+        None => return,
+    };
+    for entry in comment.split(|b| *b == 0).filter(|s| !s.is_empty()) {
+        let entry = String::from_utf8_lossy(entry).to_string();
+        let (name, version) = if entry.starts_with("GCC:") {
+            // "GCC: (Ubuntu 11.4.0-1ubuntu1~22.04) 11.4.0" - the version is the last word.
+            ("GCC", entry.rsplit(' ').next().map(str::to_string))
+        } else if entry.contains("clang version") {
+            ("Clang", version_after(&entry, "clang version ").map(str::to_string))
+        } else if entry.starts_with("rustc version") {
+            ("rustc", version_after(&entry, "rustc version ").map(str::to_string))
+        } else if entry.starts_with("Linker: LLD") {
+            ("LLD", version_after(&entry, "Linker: LLD ").map(str::to_string))
+        } else {
+            continue;
+        };
+        out.push(Toolchain { name: name.to_string(), version, evidence: format!(".comment \"{}\"", entry) });
+    }
+}
+
+// Visual Studio release for an MSVC tool build number.
+fn msvc_release(build: u16, prod_id: u16) -> &'static str {
+    match build {
+        50727 if prod_id < 0xcc => "Visual Studio 2005",
+        21022 | 30729 => "Visual Studio 2008",
+        30319 | 40219 => "Visual Studio 2010",
+        50727 | 51025 | 51106 | 60315 | 60610 | 61030 => "Visual Studio 2012",
+        21005 | 30501 | 31101 | 40629 => "Visual Studio 2013",
+        23026..=24999 => "Visual Studio 2015",
+        25000..=27099 => "Visual Studio 2017",
+        27500..=30159 => "Visual Studio 2019",
+        30400..=u16::MAX => "Visual Studio 2022",
+        _ => "unknown Visual Studio",
+    }
+}
+
+// The Rich header sits between the DOS stub and the PE header. It's a list of
+// (product id, build, count) entries XORed with a checksum key that follows "Rich".
+fn from_rich_header(bytes: &[u8], out: &mut Vec<Toolchain>) {
+    let pe_offset = match read_u32_from_slice(bytes, 0x3c, LITTLE_ENDIAN) {
+        Some(pe_offset) => (pe_offset as usize).min(bytes.len()),
+        None => return,
+    };
+    let stub = &bytes[..pe_offset];
+    let rich = match find(stub, b"Rich") {
+        Some(rich) => rich,
+        None => return,
+    };
+    let key = match read_u32_from_slice(stub, rich + 4, LITTLE_ENDIAN) {
+        Some(key) => key,
+        None => return,
+    };
+    let dans = 0x536e6144 ^ key; // "DanS"
+    let start = match (0..rich).step_by(4).find(|i| read_u32_from_slice(stub, *i, LITTLE_ENDIAN) == Some(dans)) {
+        Some(start) => start,
+        None => return,
+    };
+    // The "DanS" marker is followed by three zero padding dwords.
+    let mut newest: Option<(u16, u16)> = None;
+    let mut i = start + 16;
+    while i + 8 <= rich {
+        let comp_id = read_u32_from_slice(stub, i, LITTLE_ENDIAN).unwrap_or(0) ^ key;
+        let (prod_id, build) = ((comp_id >> 16) as u16, comp_id as u16);
+        if build != 0 && newest.map_or(true, |(_, b)| build > b) {
+            newest = Some((prod_id, build));
+        }
+        i += 8;
+    }
+    if let Some((prod_id, build)) = newest {
+        out.push(Toolchain {
+            name: format!("MSVC ({})", msvc_release(build, prod_id)),
+            version: Some(format!("build {}", build)),
+            evidence: format!("Rich header, {} entries", (rich - start - 16) / 8),
+        });
+    }
+}
+
+// Go linkers write a "\xff Go buildinf:" header. Since Go 1.18 the version string
+// is stored inline, length-prefixed, 32 bytes in.
+fn from_go_buildinfo(bytes: &[u8], out: &mut Vec<Toolchain>) {
+    let magic = match find(bytes, b"\xff Go buildinf:") {
+        Some(magic) => magic,
+        None => return,
+    };
+    let inline = bytes.get(magic + 15).map_or(false, |flags| flags & 0x2 != 0);
+    let version = if inline {
+        bytes.get(magic + 32).and_then(|len| {
+            let len = *len as usize;
+            bytes.get(magic + 33..magic + 33 + len).map(|v| String::from_utf8_lossy(v).to_string())
+        })
+    } else {
+        // Older binaries point at the version string instead; fall back to searching for it.
+        find(&bytes[magic..], b"go1.").map(|i| {
+            let v = &bytes[magic + i..];
+            let end = v.iter().position(|b| !(b.is_ascii_alphanumeric() || *b == b'.')).unwrap_or(v.len());
+            String::from_utf8_lossy(&v[..end]).to_string()
+        })
+    };
+    out.push(Toolchain { name: "Go".to_string(), version, evidence: "Go build info".to_string() });
+}
+
+// Rust's standard library leaves panic messages and source paths containing the
+// rustc commit hash, even in stripped binaries.
+fn from_rust_strings(bytes: &[u8], out: &mut Vec<Toolchain>) {
+    if out.iter().any(|t| t.name == "rustc") {
+        return
+    }
+    if let Some(i) = find(bytes, b"/rustc/") {
+        let hash = &bytes[i + 7..];
+        let end = hash.iter().position(|b| !b.is_ascii_hexdigit()).unwrap_or(hash.len());
+        out.push(Toolchain {
+            name: "rustc".to_string(),
+            version: None,
+            evidence: format!("std source path, commit {}", String::from_utf8_lossy(&hash[..end])),
+        });
+    }
+    else if find(bytes, b"called `Option::unwrap()` on a `None` value").is_some() {
+        out.push(Toolchain { name: "rustc".to_string(), version: None, evidence: "Rust panic strings".to_string() });
+    }
+}
+
+pub fn identify(program: &Program, bytes: &[u8]) -> Vec<Toolchain> {
+    let mut out = Vec::<Toolchain>::new();
+    from_comment(program, &mut out);
+    if program.format == "pe" {
+        from_rich_header(bytes, &mut out);
+    }
+    from_go_buildinfo(bytes, &mut out);
+    from_rust_strings(bytes, &mut out);
+    out
+}
@@ -1,5 +1,7 @@
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::Read;
+
+use crate::error::BaretkError;
 
 pub const LITTLE_ENDIAN: u8 = 0x1;
 pub const BIG_ENDIAN: u8 = 0x2;
@@ -8,40 +10,66 @@ pub const RWX_EXEC: u8 = 0x1;
 pub const RWX_WRITE: u8 = 0x2;
 pub const RWX_READ: u8 = 0x4;
 
-pub fn read_u16_from_slice(bytes: &[u8], start: usize, endianness: u8) -> u16 {
-    let b: &[u8; 2] = (&bytes[start..start+2]).try_into().unwrap();
-    match endianness { 
-        LITTLE_ENDIAN => u16::from_le_bytes(*b), 
-        BIG_ENDIAN => u16::from_be_bytes(*b),
-        _ => panic!("unknown endian type {}", endianness)
+// The `N` bytes at `start` in little-endian order, so every read below can
+// use from_le_bytes whatever the file's byte order, or None if they run past
+// the end. Anything but BIG_ENDIAN reads as little endian.
+fn le_bytes<const N: usize>(bytes: &[u8], start: usize, endianness: u8) -> Option<[u8; N]> {
+    let mut b: [u8; N] = bytes.get(start..start.checked_add(N)?)?.try_into().ok()?;
+    if endianness == BIG_ENDIAN {
+        b.reverse();
     }
+    Some(b)
+}
+
+pub fn read_u16_from_slice(bytes: &[u8], start: usize, endianness: u8) -> Option<u16> {
+    le_bytes(bytes, start, endianness).map(u16::from_le_bytes)
 }
 
-pub fn read_u32_from_slice(bytes: &[u8], start: usize, endianness: u8) -> u32 {
-    let b: &[u8; 4] = (&bytes[start..start+4]).try_into().unwrap();
-    match endianness { 
-        LITTLE_ENDIAN => u32::from_le_bytes(*b), 
-        BIG_ENDIAN => u32::from_be_bytes(*b),
-        _ => panic!("unknown endian type {}", endianness)
+pub fn read_u32_from_slice(bytes: &[u8], start: usize, endianness: u8) -> Option<u32> {
+    le_bytes(bytes, start, endianness).map(u32::from_le_bytes)
+}
+
+pub fn read_u64_from_slice(bytes: &[u8], start: usize, endianness: u8) -> Option<u64> {
+    le_bytes(bytes, start, endianness).map(u64::from_le_bytes)
+}
+
+// A signed read, sign-extended to i64 for displacements.
+pub fn read_i32_from_slice(bytes: &[u8], start: usize, endianness: u8) -> Option<i64> {
+    le_bytes(bytes, start, endianness).map(|b| i64::from(i32::from_le_bytes(b)))
+}
+
+// Bounds-checked reads for parsing headers, whose offsets and counts can't be
+// trusted. `what` names the structure being read in the error.
+pub fn read_checked<'a>(bytes: &'a [u8], start: usize, len: usize, what: &str) -> Result<&'a [u8], BaretkError> {
+    match start.checked_add(len).and_then(|end| bytes.get(start..end)) {
+        Some(slice) => Ok(slice),
+        None => Err(BaretkError::malformed(what, start as u64, format!("{:#x} byte(s) needed, but the file ends at {:#x}", len, bytes.len()))),
     }
 }
 
-pub fn read_u64_from_slice(bytes: &[u8], start: usize, endianness: u8) -> u64 {
-    let b: &[u8; 8] = (&bytes[start..start+8]).try_into().unwrap();
-    match endianness { 
-        LITTLE_ENDIAN => u64::from_le_bytes(*b), 
-        BIG_ENDIAN => u64::from_be_bytes(*b),
-        _ => panic!("unknown endian type {}", endianness)
+pub fn read_u8_checked(bytes: &[u8], start: usize, what: &str) -> Result<u8, BaretkError> {
+    Ok(read_checked(bytes, start, 1, what)?[0])
+}
+
+fn read_le_checked<const N: usize>(bytes: &[u8], start: usize, endianness: u8, what: &str) -> Result<[u8; N], BaretkError> {
+    let mut b: [u8; N] = [0; N];
+    b.copy_from_slice(read_checked(bytes, start, N, what)?);
+    if endianness == BIG_ENDIAN {
+        b.reverse();
     }
+    Ok(b)
 }
 
-pub fn read_u32_to_u64_from_slice(bytes: &[u8], start: usize, endianness: u8, ) -> u64 {
-    let b: &[u8; 4] = (&bytes[start..start+4]).try_into().unwrap();
-    u64::from(match endianness { 
-        LITTLE_ENDIAN => u32::from_le_bytes(*b), 
-        BIG_ENDIAN => u32::from_be_bytes(*b),
-        _ => panic!("unknown endian type {}", endianness)
-    })
+pub fn read_u16_checked(bytes: &[u8], start: usize, endianness: u8, what: &str) -> Result<u16, BaretkError> {
+    read_le_checked(bytes, start, endianness, what).map(u16::from_le_bytes)
+}
+
+pub fn read_u32_checked(bytes: &[u8], start: usize, endianness: u8, what: &str) -> Result<u32, BaretkError> {
+    read_le_checked(bytes, start, endianness, what).map(u32::from_le_bytes)
+}
+
+pub fn read_u64_checked(bytes: &[u8], start: usize, endianness: u8, what: &str) -> Result<u64, BaretkError> {
+    read_le_checked(bytes, start, endianness, what).map(u64::from_le_bytes)
 }
 
 pub fn i32_sign(x: i32) -> &'static str {
@@ -80,51 +108,9 @@ impl BitExtr for i16 {
     }
 }
 
-pub fn try_write_file(path: &str, output: &[u8]) -> bool {
-    let mut file = match File::create(path) {
-        Ok(file) => file,
-        Err(error) => {
-            eprintln!("Error creating file {}: {}", path, error);
-            return false;
-        }
-    };
-    if let Err(error) = file.write(output) {
-        eprintln!("Error writing file {}: {}", path, error);
-        return false;
-    }
-    true
-}
-
-pub fn try_write_file_lines(path: &str, lines: Vec<String>) -> bool {
-    let mut file = match File::create(path) {
-        Ok(file) => file,
-        Err(error) => {
-            eprintln!("Error creating file {}: {}", path, error);
-            return false;
-        }
-    };
-    for line in lines {
-        if let Err(error) = file.write((line + "\n").as_bytes()) {
-            eprintln!("Error writing file {}: {}", path, error);
-            return false;
-        }
-    }
-    true
-}
-
-pub fn try_read_file_contents(path: &str) -> Result<Vec<u8>, ()> {
-    let mut file = match File::open(path) {
-        Ok(file) => file,
-        Err(error) => {
-            eprintln!("Error opening file {}: {}", path, error);
-            return Err(());
-        }
-    };
-
+pub fn try_read_file_contents(path: &str) -> Result<Vec<u8>, BaretkError> {
+    let mut file = File::open(path).map_err(|error| BaretkError::io(path, "opening", error))?;
     let mut contents: Vec<u8> = vec![];
-    if let Err(error) = file.read_to_end(&mut contents) {
-        eprintln!("Error reading file {}: {}", path, error);
-        return Err(());
-    }
+    file.read_to_end(&mut contents).map_err(|error| BaretkError::io(path, "reading", error))?;
     Ok(contents)
 }
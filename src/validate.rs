@@ -0,0 +1,52 @@
+// Structural checks of the headers, for spotting tampered or hand-crafted
+// files: the PE checksum and section layout, and whether ELF header fields,
+// segments and sections agree with each other and with the file.
+
+use crate::elf;
+use crate::pe;
+use crate::prog::Program;
+
+pub struct Finding {
+    pub suspicious: bool,
+    pub text: String,
+}
+
+impl Finding {
+    pub fn valid(text: impl Into<String>) -> Finding {
+        Finding { suspicious: false, text: text.into() }
+    }
+
+    pub fn suspicious(text: impl Into<String>) -> Finding {
+        Finding { suspicious: true, text: text.into() }
+    }
+}
+
+// Adds `passed` as a valid finding when there are no problems, or else each
+// problem as a suspicious one.
+pub fn check(findings: &mut Vec<Finding>, passed: &str, problems: Vec<String>) {
+    if problems.is_empty() {
+        findings.push(Finding::valid(passed));
+    }
+    findings.extend(problems.into_iter().map(Finding::suspicious));
+}
+
+pub struct ValidationReport {
+    pub findings: Vec<Finding>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        !self.findings.iter().any(|finding| finding.suspicious)
+    }
+}
+
+pub fn validate(program: &Program, bytes: &[u8]) -> ValidationReport {
+    let findings = match program.format {
+        "elf" => elf::validate(bytes),
+        "pe" => pe::validate(bytes),
+        _ => Ok(Vec::new()),
+    };
+    // Headers too broken to read again are as suspicious as it gets.
+    let findings = findings.unwrap_or_else(|err| vec![Finding::suspicious(err.to_string())]);
+    ValidationReport { findings }
+}
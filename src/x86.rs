@@ -1,119 +1,51 @@
+use crate::cancel::CancelToken;
 use crate::dis::{self, DisassemblySection};
 use crate::prog::{Section, Program};
+use crate::reg::Reg;
 use crate::util::i32_sign;
 
 const AX: u8 = 0x0;
 const CX: u8 = 0x1;
 const DX: u8 = 0x2;
-const BX: u8 = 0x3;
-const SP: u8 = 0x4;
-const BP: u8 = 0x5;
 const SI: u8 = 0x6;
 const DI: u8 = 0x7;
-// const R8: u8 = 0x8;
-// const R9: u8 = 0x9;
-// const R10: u8 = 0xa;
-// const R11: u8 = 0xb;
-// const R12: u8 = 0xc;
-// const R13: u8 = 0xd;
-// const R14: u8 = 0xe;
-// const R15: u8 = 0xf;
-
-// const AH: u8 = 0x4;
-// const CH: u8 = 0x5;
-// const DH: u8 = 0x6;
-// const BH: u8 = 0x7;
-
-const OPCODE_ADD_BYTE_STR: u8 = 0x00;
-const OPCODE_ADD_DWORD_STR: u8 = 0x01;
-const OPCODE_ADD_BYTE_LD: u8 = 0x02;
-const OPCODE_ADD_DWORD_LD: u8 = 0x03;
-const OPCODE_ADD_AL_IMM8: u8 = 0x04;
-const OPCODE_OR_BYTE_STR: u8 = 0x08;
-const OPCODE_OR_DWORD_STR: u8 = 0x09;
-const OPCODE_OR_BYTE_LD: u8 = 0x0a;
-const OPCODE_OR_DWORD_LD: u8 = 0x0b;
-const OPCODE_OR_AL_IMM8: u8 = 0x0c;
-const OPCODE_ADC_BYTE_STR: u8 = 0x10;
-const OPCODE_ADC_DWORD_STR: u8 = 0x11;
-const OPCODE_ADC_BYTE_LD: u8 = 0x12;
-const OPCODE_ADC_DWORD_LD: u8 = 0x13;
-const OPCODE_ADC_AL_IMM8: u8 = 0x14;
-const OPCODE_AND_BYTE_STR: u8 = 0x20;
-const OPCODE_AND_DWORD_STR: u8 = 0x21;
-const OPCODE_AND_BYTE_LD: u8 = 0x22;
-const OPCODE_AND_DWORD_LD: u8 = 0x23;
-const OPCODE_AND_AL_IMM8: u8 = 0x24;
-const OPCODE_SUB_BYTE_STR: u8 = 0x28;
-const OPCODE_SUB_DWORD_STR: u8 = 0x29;
-const OPCODE_SUB_BYTE_LD: u8 = 0x2a;
-const OPCODE_SUB_DWORD_LD: u8 = 0x2b;
-const OPCODE_SUB_AL_IMM8: u8 = 0x2c;
-const OPCODE_XOR_BYTE_STR: u8 = 0x30;
-const OPCODE_XOR_DWORD_STR: u8 = 0x31;
-const OPCODE_XOR_BYTE_LD: u8 = 0x32;
-const OPCODE_XOR_DWORD_LD: u8 = 0x33;
-const OPCODE_XOR_AL_IMM8: u8 = 0x34;
-const OPCODE_CMP_BYTE_STR: u8 = 0x38;
-const OPCODE_CMP_DWORD_STR: u8 = 0x39;
-const OPCODE_CMP_BYTE_LD: u8 = 0x3a;
-const OPCODE_CMP_DWORD_LD: u8 = 0x3b;
-const OPCODE_CMP_AL_IMM8: u8 = 0x3c;
-const OPCODE_REX_W: u8 = 0x48;
-const OPCODE_PUSH_REG: u8 = 0x50;
-const OPCODE_PUSH_RAX: u8 = OPCODE_PUSH_REG+AX;
-const OPCODE_PUSH_RCX: u8 = OPCODE_PUSH_REG+CX;
-const OPCODE_PUSH_RDX: u8 = OPCODE_PUSH_REG+DX;
-const OPCODE_PUSH_RBX: u8 = OPCODE_PUSH_REG+BX;
-const OPCODE_PUSH_RSP: u8 = OPCODE_PUSH_REG+SP;
-const OPCODE_PUSH_RBP: u8 = OPCODE_PUSH_REG+BP;
-const OPCODE_PUSH_RSI: u8 = OPCODE_PUSH_REG+SI;
-const OPCODE_PUSH_RDI: u8 = OPCODE_PUSH_REG+DI;
-const OPCODE_POP_REG: u8 = 0x58;
-const OPCODE_POP_RAX: u8 = OPCODE_POP_REG+AX;
-const OPCODE_POP_RCX: u8 = OPCODE_POP_REG+CX;
-const OPCODE_POP_RDX: u8 = OPCODE_POP_REG+DX;
-const OPCODE_POP_RBX: u8 = OPCODE_POP_REG+BX;
-const OPCODE_POP_RSP: u8 = OPCODE_POP_REG+SP;
-const OPCODE_POP_RBP: u8 = OPCODE_POP_REG+BP;
-const OPCODE_POP_RSI: u8 = OPCODE_POP_REG+SI;
-const OPCODE_POP_RDI: u8 = OPCODE_POP_REG+DI;
-const OPCODE_OP_BYTE_IMM: u8 = 0x80;
-const OPCODE_OP_DWORD_IMM: u8 = 0x83;
-const OPCODE_TEST_BYTE_STR: u8 = 0x84;
-const OPCODE_TEST_DWORD_STR: u8 = 0x85;
-const OPCODE_MOV_BYTE_STR: u8 = 0x88;
-const OPCODE_MOV_DWORD_STR: u8 = 0x89;
-const OPCODE_MOV_BYTE_LD: u8 = 0x8a;
-const OPCODE_MOV_DWORD_LD: u8 = 0x8b;
-const OPCODE_NOP: u8 = 0x90;
-const OPCODE_MOV_REG_IMM8: u8 = 0xb0;
-const OPCODE_MOV_AL: u8 = OPCODE_MOV_REG_IMM8+AX;
-const OPCODE_MOV_CL: u8 = OPCODE_MOV_REG_IMM8+CX;
-const OPCODE_MOV_DL: u8 = OPCODE_MOV_REG_IMM8+DX;
-const OPCODE_MOV_BL: u8 = OPCODE_MOV_REG_IMM8+BX;
-const OPCODE_MOV_SP: u8 = OPCODE_MOV_REG_IMM8+SP;
-const OPCODE_MOV_BP: u8 = OPCODE_MOV_REG_IMM8+BP;
-const OPCODE_MOV_SIL: u8 = OPCODE_MOV_REG_IMM8+SI;
-const OPCODE_MOV_DIL: u8 = OPCODE_MOV_REG_IMM8+DI;
-const OPCODE_MOV_REG_IMM: u8 = 0xb8;
-const OPCODE_MOV_RAX: u8 = OPCODE_MOV_REG_IMM+AX;
-const OPCODE_MOV_RCX: u8 = OPCODE_MOV_REG_IMM+CX;
-const OPCODE_MOV_RDX: u8 = OPCODE_MOV_REG_IMM+DX;
-const OPCODE_MOV_RBX: u8 = OPCODE_MOV_REG_IMM+BX;
-const OPCODE_MOV_RSP: u8 = OPCODE_MOV_REG_IMM+SP;
-const OPCODE_MOV_RBP: u8 = OPCODE_MOV_REG_IMM+BP;
-const OPCODE_MOV_RSI: u8 = OPCODE_MOV_REG_IMM+SI;
-const OPCODE_MOV_RDI: u8 = OPCODE_MOV_REG_IMM+DI;
-const OPCODE_RET: u8 = 0xc3;
-const OPCODE_CALL: u8 = 0xe8;
 
 const OPSIZE_BYTE: u8 = 0x0;
 const OPSIZE_WORD: u8 = 0x1;
 const OPSIZE_DWORD: u8 = 0x2;
 const OPSIZE_QWORD: u8 = 0x3;
+// Memory operands of lea, which only computes the address.
+const OPSIZE_NONE: u8 = 0x4;
+
+const OPCODE_TWO_BYTE: u8 = 0x0f;
+
+// The longest an instruction can be, prefixes included.
+const MAX_INSTRUCTION_SIZE: usize = 15;
+
+// The REX prefix and its bits: W for 64-bit operands, and R, X and B for the
+// high bit of the ModRM reg, SIB index and r/m, SIB base or opcode register.
+// The low nibble of the prefix byte is WRXB, so the bits are kept as is.
+const PREFIX_REX_W: u8 = 8;
+const PREFIX_REX_R: u8 = 4;
+const PREFIX_REX_X: u8 = 2;
+const PREFIX_REX_B: u8 = 1;
+const PREFIX_REX: u8 = 16;
+
+// The 4th bit of a register number from a REX bit.
+fn rex_bit(rex: u8, bit: u8) -> u8 {
+    if rex & bit != 0 { 0x8 } else { 0 }
+}
 
-#[derive(Clone, Copy)]
+// Condition codes in the order of the low nibble of Jcc opcodes.
+const JCC_NAMES: [&str; 16] = ["jo", "jno", "jb", "jae", "je", "jne", "jbe", "ja", "js", "jns", "jp", "jnp", "jl", "jge", "jle", "jg"];
+const SETCC_NAMES: [&str; 16] = ["seto", "setno", "setb", "setae", "sete", "setne", "setbe", "seta", "sets", "setns", "setp", "setnp", "setl", "setge", "setle", "setg"];
+const CMOVCC_NAMES: [&str; 16] = ["cmovo", "cmovno", "cmovb", "cmovae", "cmove", "cmovne", "cmovbe", "cmova", "cmovs", "cmovns", "cmovp", "cmovnp", "cmovl", "cmovge", "cmovle", "cmovg"];
+const CONDITIONS: [u64; 16] = [
+    dis::COND_VS, dis::COND_VC, dis::COND_LTU, dis::COND_GEU, dis::COND_EQ, dis::COND_NE, dis::COND_LEU, dis::COND_GTU,
+    dis::COND_MI, dis::COND_PL, dis::COND_PE, dis::COND_PO, dis::COND_LT, dis::COND_GE, dis::COND_LE, dis::COND_GT,
+];
+
+#[derive(Clone, Copy, PartialEq)]
 enum Operation {
     Add,
     Adc,
@@ -125,15 +57,343 @@ enum Operation {
     Cmp,
     Test,
     Mov,
+    Movzx,
+    Movsx,
+    Movsxd,
+    Lea,
+    Xchg,
     Nop,
     Push,
     Pop,
     Ret,
     Call,
+    Jmp,
+    // Conditions are the low nibble of the opcode.
+    Jcc(u8),
+    Setcc(u8),
+    Cmovcc(u8),
+    Rol,
+    Ror,
+    Rcl,
+    Rcr,
+    Shl,
+    Shr,
+    Sar,
+    Not,
+    Neg,
+    Mul,
+    Imul,
+    Div,
+    Idiv,
+    Inc,
+    Dec,
+    Cbw,
+    Cwde,
+    Cdqe,
+    Cwd,
+    Cdq,
+    Cqo,
+    Movs,
+    Stos,
+    Leave,
+    Hlt,
+    Int,
+    Int3,
+    Syscall,
+    Ud2,
+    Cpuid,
+    Rdtsc,
+    Endbr64,
     Unknown,
 }
 
-const PREFIX_REX_W: u8 = 1;
+impl Operation {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Add     => "add",
+            Self::Adc     => "adc",
+            Self::Sub     => "sub",
+            Self::Sbb     => "sbb",
+            Self::And     => "and",
+            Self::Or      => "or",
+            Self::Xor     => "xor",
+            Self::Cmp     => "cmp",
+            Self::Test    => "test",
+            Self::Mov     => "mov",
+            Self::Movzx   => "movzx",
+            Self::Movsx   => "movsx",
+            Self::Movsxd  => "movsxd",
+            Self::Lea     => "lea",
+            Self::Xchg    => "xchg",
+            Self::Nop     => "nop",
+            Self::Push    => "push",
+            Self::Pop     => "pop",
+            Self::Ret     => "ret",
+            Self::Call    => "call",
+            Self::Jmp     => "jmp",
+            Self::Jcc(cond) => JCC_NAMES[cond as usize],
+            Self::Setcc(cond) => SETCC_NAMES[cond as usize],
+            Self::Cmovcc(cond) => CMOVCC_NAMES[cond as usize],
+            Self::Rol     => "rol",
+            Self::Ror     => "ror",
+            Self::Rcl     => "rcl",
+            Self::Rcr     => "rcr",
+            Self::Shl     => "shl",
+            Self::Shr     => "shr",
+            Self::Sar     => "sar",
+            Self::Not     => "not",
+            Self::Neg     => "neg",
+            Self::Mul     => "mul",
+            Self::Imul    => "imul",
+            Self::Div     => "div",
+            Self::Idiv    => "idiv",
+            Self::Inc     => "inc",
+            Self::Dec     => "dec",
+            Self::Cbw     => "cbw",
+            Self::Cwde    => "cwde",
+            Self::Cdqe    => "cdqe",
+            Self::Cwd     => "cwd",
+            Self::Cdq     => "cdq",
+            Self::Cqo     => "cqo",
+            Self::Movs    => "movs",
+            Self::Stos    => "stos",
+            Self::Leave   => "leave",
+            Self::Hlt     => "hlt",
+            Self::Int     => "int",
+            Self::Int3    => "int3",
+            Self::Syscall => "syscall",
+            Self::Ud2     => "ud2",
+            Self::Cpuid   => "cpuid",
+            Self::Rdtsc   => "rdtsc",
+            Self::Endbr64 => "endbr64",
+            Self::Unknown => "(bad)",
+        }
+    }
+}
+
+// Operations of the ALU opcodes 0x00-0x3f by bits 3-5, and of the 0x80-0x83
+// group by the ModRM reg field.
+const GROUP1: [Operation; 8] = [Operation::Add, Operation::Or, Operation::Adc, Operation::Sbb, Operation::And, Operation::Sub, Operation::Xor, Operation::Cmp];
+// Shifts and rotates.
+const GROUP2: [Operation; 8] = [Operation::Rol, Operation::Ror, Operation::Rcl, Operation::Rcr, Operation::Shl, Operation::Shr, Operation::Shl, Operation::Sar];
+// 0xf6/0xf7 other than test, which takes an immediate.
+const GROUP3: [Operation; 8] = [Operation::Unknown, Operation::Unknown, Operation::Not, Operation::Neg, Operation::Mul, Operation::Imul, Operation::Div, Operation::Idiv];
+// Sign extension of the accumulator by operand size.
+const CBW: [Operation; 3] = [Operation::Cbw, Operation::Cwde, Operation::Cdqe];
+const CWD: [Operation; 3] = [Operation::Cwd, Operation::Cdq, Operation::Cqo];
+
+// How a table row names its instruction.
+#[derive(Clone, Copy)]
+enum Op {
+    One(Operation),
+    // Picked by the ModRM reg field.
+    Group(&'static [Operation; 8]),
+    // Picked by bits 3-5 of the opcode.
+    ByOpcode(&'static [Operation; 8]),
+    // Picked by a 16, 32 or 64-bit operand size.
+    Sized(&'static [Operation; 3]),
+    // Takes the condition in the low nibble of the opcode.
+    Cond(fn(u8) -> Operation),
+}
+
+// Operand size of a table row. Full is 32 bits, or 16 with 0x66 and 64 with
+// REX.W. Stack is 64 bits, or 16 with 0x66.
+#[derive(Clone, Copy)]
+enum Size {
+    Byte,
+    Word,
+    Dword,
+    Full,
+    Stack,
+}
+
+// Operand layout of a table row. Immediates follow the operand size, and
+// are at most 32 bits sign-extended, except for mov reg, imm.
+#[derive(Clone, Copy, PartialEq)]
+enum Form {
+    None,
+    Rm,
+    RmReg,
+    RegRm,
+    // reg, r/m of a fixed size, as in movzx.
+    RegRm8,
+    RegRm16,
+    RegRm32,
+    // reg, m with no access size, as in lea.
+    RegAddr,
+    RmImm,
+    RmImm8,
+    RmOne,
+    RmCl,
+    RegRmImm,
+    RegRmImm8,
+    // The accumulator sized to the operand and an immediate.
+    AccImm,
+    // The register in the low 3 bits of the opcode.
+    OpReg,
+    OpRegImm,
+    OpRegAcc,
+    Rel,
+    Imm,
+    Imm8,
+    // String instructions on [rdi] with the accumulator or [rsi].
+    StrAcc,
+    StrStr,
+    // No operands, and a ModRM byte that must have this value.
+    Fixed(u8),
+}
+
+impl Form {
+    fn has_modrm(self) -> bool {
+        matches!(self, Self::Rm | Self::RmReg | Self::RegRm | Self::RegRm8 | Self::RegRm16 | Self::RegRm32 | Self::RegAddr | Self::RmImm | Self::RmImm8 | Self::RmOne | Self::RmCl | Self::RegRmImm | Self::RegRmImm8)
+    }
+}
+
+const ANY: Option<u8> = None;
+
+// One row of an opcode table: the opcode matches when it equals the first
+// field under the mask in the second. Rows for one member of a group give
+// its ModRM reg field. The first matching row is used.
+struct Opcode(u8, u8, Option<u8>, Op, Size, Form);
+
+const ONE_BYTE: &[Opcode] = &[
+    Opcode(0x00, 0xc7, ANY, Op::ByOpcode(&GROUP1), Size::Byte, Form::RmReg),
+    Opcode(0x01, 0xc7, ANY, Op::ByOpcode(&GROUP1), Size::Full, Form::RmReg),
+    Opcode(0x02, 0xc7, ANY, Op::ByOpcode(&GROUP1), Size::Byte, Form::RegRm),
+    Opcode(0x03, 0xc7, ANY, Op::ByOpcode(&GROUP1), Size::Full, Form::RegRm),
+    Opcode(0x04, 0xc7, ANY, Op::ByOpcode(&GROUP1), Size::Byte, Form::AccImm),
+    Opcode(0x05, 0xc7, ANY, Op::ByOpcode(&GROUP1), Size::Full, Form::AccImm),
+    Opcode(0x50, 0xf8, ANY, Op::One(Operation::Push), Size::Stack, Form::OpReg),
+    Opcode(0x58, 0xf8, ANY, Op::One(Operation::Pop), Size::Stack, Form::OpReg),
+    Opcode(0x63, 0xff, ANY, Op::One(Operation::Movsxd), Size::Full, Form::RegRm32),
+    Opcode(0x68, 0xff, ANY, Op::One(Operation::Push), Size::Stack, Form::Imm),
+    Opcode(0x69, 0xff, ANY, Op::One(Operation::Imul), Size::Full, Form::RegRmImm),
+    Opcode(0x6a, 0xff, ANY, Op::One(Operation::Push), Size::Stack, Form::Imm8),
+    Opcode(0x6b, 0xff, ANY, Op::One(Operation::Imul), Size::Full, Form::RegRmImm8),
+    Opcode(0x70, 0xf0, ANY, Op::Cond(Operation::Jcc), Size::Byte, Form::Rel),
+    Opcode(0x80, 0xff, ANY, Op::Group(&GROUP1), Size::Byte, Form::RmImm),
+    Opcode(0x81, 0xff, ANY, Op::Group(&GROUP1), Size::Full, Form::RmImm),
+    Opcode(0x83, 0xff, ANY, Op::Group(&GROUP1), Size::Full, Form::RmImm8),
+    Opcode(0x84, 0xff, ANY, Op::One(Operation::Test), Size::Byte, Form::RmReg),
+    Opcode(0x85, 0xff, ANY, Op::One(Operation::Test), Size::Full, Form::RmReg),
+    Opcode(0x86, 0xff, ANY, Op::One(Operation::Xchg), Size::Byte, Form::RmReg),
+    Opcode(0x87, 0xff, ANY, Op::One(Operation::Xchg), Size::Full, Form::RmReg),
+    Opcode(0x88, 0xff, ANY, Op::One(Operation::Mov), Size::Byte, Form::RmReg),
+    Opcode(0x89, 0xff, ANY, Op::One(Operation::Mov), Size::Full, Form::RmReg),
+    Opcode(0x8a, 0xff, ANY, Op::One(Operation::Mov), Size::Byte, Form::RegRm),
+    Opcode(0x8b, 0xff, ANY, Op::One(Operation::Mov), Size::Full, Form::RegRm),
+    Opcode(0x8d, 0xff, ANY, Op::One(Operation::Lea), Size::Full, Form::RegAddr),
+    Opcode(0x8f, 0xff, Some(0), Op::One(Operation::Pop), Size::Stack, Form::Rm),
+    Opcode(0x90, 0xff, ANY, Op::One(Operation::Nop), Size::Byte, Form::None),
+    Opcode(0x90, 0xf8, ANY, Op::One(Operation::Xchg), Size::Full, Form::OpRegAcc),
+    Opcode(0x98, 0xff, ANY, Op::Sized(&CBW), Size::Full, Form::None),
+    Opcode(0x99, 0xff, ANY, Op::Sized(&CWD), Size::Full, Form::None),
+    Opcode(0xa4, 0xff, ANY, Op::One(Operation::Movs), Size::Byte, Form::StrStr),
+    Opcode(0xa5, 0xff, ANY, Op::One(Operation::Movs), Size::Full, Form::StrStr),
+    Opcode(0xa8, 0xff, ANY, Op::One(Operation::Test), Size::Byte, Form::AccImm),
+    Opcode(0xa9, 0xff, ANY, Op::One(Operation::Test), Size::Full, Form::AccImm),
+    Opcode(0xaa, 0xff, ANY, Op::One(Operation::Stos), Size::Byte, Form::StrAcc),
+    Opcode(0xab, 0xff, ANY, Op::One(Operation::Stos), Size::Full, Form::StrAcc),
+    Opcode(0xb0, 0xf8, ANY, Op::One(Operation::Mov), Size::Byte, Form::OpRegImm),
+    Opcode(0xb8, 0xf8, ANY, Op::One(Operation::Mov), Size::Full, Form::OpRegImm),
+    Opcode(0xc0, 0xff, ANY, Op::Group(&GROUP2), Size::Byte, Form::RmImm8),
+    Opcode(0xc1, 0xff, ANY, Op::Group(&GROUP2), Size::Full, Form::RmImm8),
+    Opcode(0xc2, 0xff, ANY, Op::One(Operation::Ret), Size::Word, Form::Imm),
+    Opcode(0xc3, 0xff, ANY, Op::One(Operation::Ret), Size::Stack, Form::None),
+    Opcode(0xc6, 0xff, Some(0), Op::One(Operation::Mov), Size::Byte, Form::RmImm),
+    Opcode(0xc7, 0xff, Some(0), Op::One(Operation::Mov), Size::Full, Form::RmImm),
+    Opcode(0xc9, 0xff, ANY, Op::One(Operation::Leave), Size::Stack, Form::None),
+    Opcode(0xcc, 0xff, ANY, Op::One(Operation::Int3), Size::Byte, Form::None),
+    Opcode(0xcd, 0xff, ANY, Op::One(Operation::Int), Size::Byte, Form::Imm),
+    Opcode(0xd0, 0xff, ANY, Op::Group(&GROUP2), Size::Byte, Form::RmOne),
+    Opcode(0xd1, 0xff, ANY, Op::Group(&GROUP2), Size::Full, Form::RmOne),
+    Opcode(0xd2, 0xff, ANY, Op::Group(&GROUP2), Size::Byte, Form::RmCl),
+    Opcode(0xd3, 0xff, ANY, Op::Group(&GROUP2), Size::Full, Form::RmCl),
+    Opcode(0xe8, 0xff, ANY, Op::One(Operation::Call), Size::Dword, Form::Rel),
+    Opcode(0xe9, 0xff, ANY, Op::One(Operation::Jmp), Size::Dword, Form::Rel),
+    Opcode(0xeb, 0xff, ANY, Op::One(Operation::Jmp), Size::Byte, Form::Rel),
+    Opcode(0xf4, 0xff, ANY, Op::One(Operation::Hlt), Size::Byte, Form::None),
+    Opcode(0xf6, 0xff, Some(0), Op::One(Operation::Test), Size::Byte, Form::RmImm),
+    Opcode(0xf6, 0xff, ANY, Op::Group(&GROUP3), Size::Byte, Form::Rm),
+    Opcode(0xf7, 0xff, Some(0), Op::One(Operation::Test), Size::Full, Form::RmImm),
+    Opcode(0xf7, 0xff, ANY, Op::Group(&GROUP3), Size::Full, Form::Rm),
+    Opcode(0xfe, 0xff, Some(0), Op::One(Operation::Inc), Size::Byte, Form::Rm),
+    Opcode(0xfe, 0xff, Some(1), Op::One(Operation::Dec), Size::Byte, Form::Rm),
+    Opcode(0xff, 0xff, Some(0), Op::One(Operation::Inc), Size::Full, Form::Rm),
+    Opcode(0xff, 0xff, Some(1), Op::One(Operation::Dec), Size::Full, Form::Rm),
+    Opcode(0xff, 0xff, Some(2), Op::One(Operation::Call), Size::Stack, Form::Rm),
+    Opcode(0xff, 0xff, Some(4), Op::One(Operation::Jmp), Size::Stack, Form::Rm),
+    Opcode(0xff, 0xff, Some(6), Op::One(Operation::Push), Size::Stack, Form::Rm),
+];
+
+// Second opcode byte after OPCODE_TWO_BYTE.
+const TWO_BYTE: &[Opcode] = &[
+    Opcode(0x05, 0xff, ANY, Op::One(Operation::Syscall), Size::Byte, Form::None),
+    Opcode(0x0b, 0xff, ANY, Op::One(Operation::Ud2), Size::Byte, Form::None),
+    Opcode(0x1e, 0xff, ANY, Op::One(Operation::Endbr64), Size::Byte, Form::Fixed(0xfa)),
+    // Hint NOPs, including the multi-byte nop r/m.
+    Opcode(0x18, 0xf8, ANY, Op::One(Operation::Nop), Size::Full, Form::Rm),
+    Opcode(0x31, 0xff, ANY, Op::One(Operation::Rdtsc), Size::Byte, Form::None),
+    Opcode(0x40, 0xf0, ANY, Op::Cond(Operation::Cmovcc), Size::Full, Form::RegRm),
+    Opcode(0x80, 0xf0, ANY, Op::Cond(Operation::Jcc), Size::Dword, Form::Rel),
+    Opcode(0x90, 0xf0, ANY, Op::Cond(Operation::Setcc), Size::Byte, Form::Rm),
+    Opcode(0xa2, 0xff, ANY, Op::One(Operation::Cpuid), Size::Byte, Form::None),
+    Opcode(0xaf, 0xff, ANY, Op::One(Operation::Imul), Size::Full, Form::RegRm),
+    Opcode(0xb6, 0xff, ANY, Op::One(Operation::Movzx), Size::Full, Form::RegRm8),
+    Opcode(0xb7, 0xff, ANY, Op::One(Operation::Movzx), Size::Full, Form::RegRm16),
+    Opcode(0xbe, 0xff, ANY, Op::One(Operation::Movsx), Size::Full, Form::RegRm8),
+    Opcode(0xbf, 0xff, ANY, Op::One(Operation::Movsx), Size::Full, Form::RegRm16),
+];
+
+// A memory operand. rip-relative operands have no base or index.
+#[derive(Clone, Copy, Default)]
+struct Mem {
+    base: Option<u8>,
+    index: Option<u8>,
+    // log2 of the index multiplier.
+    scale: u8,
+    disp: i32,
+    rip: bool,
+    // fs or gs; the others are ignored in 64-bit mode.
+    segment: Option<&'static str>,
+}
+
+impl Mem {
+    fn print(self) -> String {
+        let segment = self.segment.map(|s| format!("{}:", s)).unwrap_or_default();
+        if self.rip {
+            return format!("{}[rip{}0x{:08x}]", segment, i32_sign(self.disp), self.disp.unsigned_abs())
+        }
+        let mut out = String::new();
+        if let Some(base) = self.base {
+            out += print_reg(OPSIZE_QWORD, base);
+        }
+        if let Some(index) = self.index {
+            if !out.is_empty() {
+                out += "+";
+            }
+            out += print_reg(OPSIZE_QWORD, index);
+            if self.scale != 0x0 {
+                out += format!("*{}", 1 << self.scale).as_str();
+            }
+        }
+        if out.is_empty() {
+            out = format!("0x{:x}", self.disp as u32);
+        } else if self.disp != 0x0 {
+            out += format!("{}0x{:02x}", i32_sign(self.disp), self.disp.unsigned_abs()).as_str();
+        }
+        format!("{}[{}]", segment, out)
+    }
+
+    fn into(self, size: u8) -> dis::Operand {
+        let bytes = [1, 2, 4, 8, 0][size as usize];
+        if self.rip {
+            return dis::Operand::Memory(Some(Reg::X86_PC), None, 0, self.disp.into(), bytes)
+        }
+        let scale = if self.index.is_some() { 1 << self.scale } else { 0 };
+        dis::Operand::Memory(self.base.map(|r| Reg::x86_sized(r, 8)), self.index.map(|r| Reg::x86_sized(r, 8)), scale, self.disp.into(), bytes)
+    }
+}
 
 #[derive(Clone, Copy)]
 enum Operand {
@@ -141,48 +401,23 @@ enum Operand {
     ImmU8(u8),
     ImmU16(u16),
     ImmU32(u32),
+    ImmU64(u64),
     ImmS8(i8),
     ImmS32(i32),
+    // Byte registers as encoded with a REX prefix (al-dil, r8b-r15b) and
+    // without one, where 4-7 are ah-bh.
     Reg8(u8),
     Reg8H(u8),
     Reg16(u8),
     Reg32(u8),
     Reg64(u8),
-    PtrRegByte(u8, i32),
-    PtrRegRegByte(u8, u8, u8),
-    PtrRegRegWord(u8, u8, u8),
-    PtrRegRegDword(u8, u8, u8),
-    PtrRegRegQword(u8, u8, u8),
-    PtrRegWord(u8, i32),
-    PtrRegDword(u8, i32),
-    PtrRegQword(u8, i32),
-    PtrRelByte(u32),
-    PtrRelWord(u32),
-    PtrRelDword(u32),
-    PtrRelQword(u32),
+    // Memory of an OPSIZE.
+    Mem(u8, Mem),
 }
 
-static REG_NAMES: [[&'static str; 5]; 16] = [
-    ["al",   "ax",   "eax",  "rax", "al"],
-    ["cl",   "cx",   "ecx",  "rcx", "cl"],
-    ["dl",   "dx",   "edx",  "rdx", "dl"],
-    ["bl",   "bx",   "ebx",  "rbx", "bl"],
-    ["spl",  "sp",   "esp",  "rsp", "ah"],
-    ["bpl",  "bp",   "ebp",  "rbp", "ch"],
-    ["sil",  "si",   "esi",  "rsi", "dh"],
-    ["dil",  "di",   "edi",  "rdi", "bh"],
-    ["r8l",  "r8w",  "r8d",  "r8",  "r8l"],
-    ["r9l",  "r9w",  "r9d",  "r9",  "r9l"],
-    ["r10l", "r10w", "r10d", "r10", "r10l"],
-    ["r11l", "r11w", "r11d", "r11", "r11l"],
-    ["r12l", "r12w", "r12d", "r12", "r12l"],
-    ["r13l", "r13w", "r13d", "r13", "r13l"],
-    ["r14l", "r14w", "r14d", "r14", "r14l"],
-    ["r15l", "r15w", "r15d", "r15", "r15l"],
-];
-
-fn print_reg(s: usize, x: u8) -> &'static str {
-    REG_NAMES[x as usize][s]
+// Columns of the 8-bit, 16-bit, 32-bit, 64-bit and high byte registers.
+fn print_reg(s: u8, x: u8) -> &'static str {
+    Reg::x86_sized(x, [1, 2, 4, 8, 0][s as usize]).name()
 }
 
 impl Operand {
@@ -191,134 +426,84 @@ impl Operand {
             Self::ImmU8(x)  => format!("0x{:x}", x),
             Self::ImmU16(x)  => format!("0x{:x}", x),
             Self::ImmU32(x)  => format!("0x{:x}", x),
+            Self::ImmU64(x)  => format!("0x{:x}", x),
             Self::ImmS8(x)  => format!("{}", x),
             Self::ImmS32(x)  => format!("{}", x),
-            Self::Reg8(x)  => format!("{}", print_reg(0x0, x)),
-            Self::Reg8H(x) => format!("{}", print_reg(0x4, x)),
-            Self::Reg16(x) => format!("{}", print_reg(0x1, x)),
-            Self::Reg32(x) => format!("{}", print_reg(0x2, x)),
-            Self::Reg64(x) => format!("{}", print_reg(0x3, x)),
-            Self::PtrRegByte(reg, offset) => {
-                if offset == 0x0 {
-                    format!("BYTE PTR [{}]", print_reg(0x3, reg))
-                } else {
-                    format!("BYTE PTR [{}{}0x{:02x}]", print_reg(0x3, reg), i32_sign(offset), offset.abs())
-                }
+            Self::Reg8(x)  => print_reg(OPSIZE_BYTE, x).to_string(),
+            Self::Reg8H(x) => print_reg(OPSIZE_NONE, x).to_string(),
+            Self::Reg16(x) => print_reg(OPSIZE_WORD, x).to_string(),
+            Self::Reg32(x) => print_reg(OPSIZE_DWORD, x).to_string(),
+            Self::Reg64(x) => print_reg(OPSIZE_QWORD, x).to_string(),
+            Self::Mem(size, mem) => match size {
+                OPSIZE_BYTE => format!("BYTE PTR {}", mem.print()),
+                OPSIZE_WORD => format!("WORD PTR {}", mem.print()),
+                OPSIZE_DWORD => format!("DWORD PTR {}", mem.print()),
+                OPSIZE_QWORD => format!("QWORD PTR {}", mem.print()),
+                _ => mem.print(),
             },
-            Self::PtrRegWord(reg, offset) => {
-                if offset == 0x0 {
-                    format!("WORD PTR [{}]", print_reg(0x3, reg))
-                } else {
-                    format!("WORD PTR [{}{}0x{:02x}]", print_reg(0x3, reg), i32_sign(offset), offset.abs())
-                }
-            },
-            Self::PtrRegDword(reg, offset) => {
-                if offset == 0x0 {
-                    format!("DWORD PTR [{}]", print_reg(0x3, reg))
-                } else {
-                    format!("DWORD PTR [{}{}0x{:02x}]", print_reg(0x3, reg), i32_sign(offset), offset.abs())
-                }
-            },
-            Self::PtrRegQword(reg, offset) => {
-                if offset == 0x0 {
-                    format!("QWORD PTR [{}]", print_reg(0x3, reg))
-                } else {
-                    format!("QWORD PTR [{}{}0x{:04x}]", print_reg(0x3, reg), i32_sign(offset), offset.abs())
-                }
-            },
-            Self::PtrRelByte(rel) => format!("BYTE PTR [rip+0x{:08x}]", rel),
-            Self::PtrRelWord(rel) => format!("WORD PTR [rip+0x{:08x}]", rel),
-            Self::PtrRelDword(rel) => format!("DWORD PTR [rip+0x{:08x}]", rel),
-            Self::PtrRelQword(rel) => format!("QWORD PTR [rip+0x{:08x}]", rel),
-            Self::PtrRegRegByte(base, offset, mul) => {
-                if mul == 0x1 {
-                    format!("BYTE PTR [{}+{}]", print_reg(0x3, base), print_reg(0x3, offset))
-                } else {
-                    format!("BYTE PTR [{}+{}*{}]", print_reg(0x3, base), print_reg(0x3, offset), mul)
-                }
-            },
-            Self::PtrRegRegWord(base, offset, mul) => {
-                if mul == 0x1 {
-                    format!("WORD PTR [{}+{}]", print_reg(0x3, base), print_reg(0x3, offset))
-                } else {
-                    format!("WORD PTR [{}+{}*{}]", print_reg(0x3, base), print_reg(0x3, offset), mul)
-                }
-            },
-            Self::PtrRegRegDword(base, offset, mul) => {
-                if mul == 0x1 {
-                    format!("DWORD PTR [{}+{}]", print_reg(0x3, base), print_reg(0x3, offset))
-                } else {
-                    format!("DWORD PTR [{}+{}*{}]", print_reg(0x3, base), print_reg(0x3, offset), mul)
-                }
-            },
-            Self::PtrRegRegQword(base, offset, mul) => {
-                if mul == 0x1 {
-                    format!("QWORD PTR [{}+{}]", print_reg(0x3, base), print_reg(0x3, offset))
-                } else {
-                    format!("QWORD PTR [{}+{}*{}]", print_reg(0x3, base), print_reg(0x3, offset), mul)
-                }
-            },
-            _ => format!("???"),
+            Self::Nothing => String::new(),
         }
     }
 
     fn into(self) -> dis::Operand {
         match self {
-            Self::Reg8(x)  => dis::Operand::Register(print_reg(0x0, x)),
-            Self::Reg8H(x) => dis::Operand::Register(print_reg(0x4, x)),
-            Self::Reg16(x) => dis::Operand::Register(print_reg(0x1, x)),
-            Self::Reg32(x) => dis::Operand::Register(print_reg(0x2, x)),
-            Self::Reg64(x) => dis::Operand::Register(print_reg(0x3, x)),
+            Self::Reg8(x)  => dis::Operand::Register(Reg::x86_sized(x, 1)),
+            Self::Reg8H(x) => dis::Operand::Register(Reg::x86_sized(x, 0)),
+            Self::Reg16(x) => dis::Operand::Register(Reg::x86_sized(x, 2)),
+            Self::Reg32(x) => dis::Operand::Register(Reg::x86_sized(x, 4)),
+            Self::Reg64(x) => dis::Operand::Register(Reg::x86_sized(x, 8)),
             Self::ImmU8(x) => dis::Operand::Immediate(x.into()),
             Self::ImmU16(x) => dis::Operand::Immediate(x.into()),
             Self::ImmU32(x) => dis::Operand::Immediate(x.into()),
+            Self::ImmU64(x) => dis::Operand::Immediate(x as i64),
             Self::ImmS8(x) => dis::Operand::Immediate(x.into()),
             Self::ImmS32(x) => dis::Operand::Immediate(x.into()),
-            Self::PtrRegByte(reg, offset) => dis::Operand::Memory(print_reg(0x3, reg), "", offset.into(), 1),
-            Self::PtrRegWord(reg, offset) => dis::Operand::Memory(print_reg(0x3, reg), "", offset.into(), 2),
-            Self::PtrRegDword(reg, offset) => dis::Operand::Memory(print_reg(0x3, reg), "", offset.into(), 4),
-            Self::PtrRegQword(reg, offset) => dis::Operand::Memory(print_reg(0x3, reg), "", offset.into(), 8),
-            Self::PtrRelByte(rel) => dis::Operand::Memory(".", "", rel.into(), 1),
-            Self::PtrRelWord(rel) => dis::Operand::Memory(".", "", rel.into(), 2),
-            Self::PtrRelDword(rel) => dis::Operand::Memory(".", "", rel.into(), 4),
-            Self::PtrRelQword(rel) => dis::Operand::Memory(".", "", rel.into(), 8),
-            Self::PtrRegRegByte(base, offset, _mul) => dis::Operand::Memory(print_reg(0x3, base), print_reg(0x0, offset), 0x0, 1),
-            Self::PtrRegRegWord(base, offset, _mul) => dis::Operand::Memory(print_reg(0x3, base), print_reg(0x1, offset), 0x0, 2),
-            Self::PtrRegRegDword(base, offset, _mul) => dis::Operand::Memory(print_reg(0x3, base), print_reg(0x2, offset), 0x0, 4),
-            Self::PtrRegRegQword(base, offset, _mul) => dis::Operand::Memory(print_reg(0x3, base), print_reg(0x3, offset), 0x0, 8),
+            Self::Mem(size, mem) => mem.into(size),
             Self::Nothing => dis::Operand::Nothing,
         }
     }
+
+    // Register r at the size of this operand.
+    fn sized_like(self, r: u8) -> dis::Operand {
+        let bytes = match self {
+            Self::Reg8(_) | Self::Reg8H(_) | Self::Mem(OPSIZE_BYTE, _) => 1,
+            Self::Reg16(_) | Self::Mem(OPSIZE_WORD, _) => 2,
+            Self::Reg32(_) | Self::Mem(OPSIZE_DWORD, _) => 4,
+            _ => 8,
+        };
+        dis::Operand::Register(Reg::x86_sized(r, bytes))
+    }
+
+    // The register an operand names, for shifts lifted as shifted moves.
+    fn reg(self) -> Option<Reg> {
+        match self.into() {
+            dis::Operand::Register(r) => Some(r),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
 pub struct Instruction {
     operation: Operation,
-    reg1: Operand,
-    reg2: Operand,
+    operands: [Operand; 3],
+    // A rep (0xf3) prefix on a string instruction.
+    rep: bool,
     offset: usize,
     ins_size: u8,
 }
 
 impl Instruction {
     pub fn print(self) -> String {
-        match self.operation {
-            Operation::Add  => format!("add {}, {}", self.reg1.print(), self.reg2.print()),
-            Operation::Adc  => format!("adc {}, {}", self.reg1.print(), self.reg2.print()),
-            Operation::Sub  => format!("sub {}, {}", self.reg1.print(), self.reg2.print()),
-            Operation::Or   => format!("or {}, {}",  self.reg1.print(), self.reg2.print()),
-            Operation::And  => format!("and {}, {}",  self.reg1.print(), self.reg2.print()),
-            Operation::Xor  => format!("xor {}, {}",  self.reg1.print(), self.reg2.print()),
-            Operation::Test => format!("test {}, {}",  self.reg1.print(), self.reg2.print()),
-            Operation::Cmp  => format!("cmp {}, {}",  self.reg1.print(), self.reg2.print()),
-            Operation::Mov  => format!("mov {}, {}",  self.reg1.print(), self.reg2.print()),
-            Operation::Push => format!("push {}",    self.reg1.print()),
-            Operation::Pop  => format!("pop {}",     self.reg1.print()),
-            Operation::Nop  => format!("nop"),
-            Operation::Ret  => format!("ret"),
-            Operation::Call => format!("call {}", self.reg1.print()),
-            Operation::Unknown => format!("(bad)"),
-            _ => format!("unknown")
+        let operands: Vec<String> = self.operands.iter()
+            .filter(|op| !matches!(op, Operand::Nothing))
+            .map(|op| op.print())
+            .collect();
+        let name = if self.rep { format!("rep {}", self.operation.name()) } else { self.operation.name().to_string() };
+        if operands.is_empty() {
+            name
+        } else {
+            format!("{} {}", name, operands.join(", "))
         }
     }
 
@@ -331,386 +516,347 @@ impl Instruction {
     }
 
     pub fn into(&self) -> dis::Instruction {
+        let [op0, op1, op2] = self.operands;
+        let instruction = |opcode: &'static str, operands: Vec<dis::Operand>, flags: u64| dis::Instruction { opcode, operands, flags };
+        // Shifts of a register are moves of a shifted register, like on ARM.
+        let shift = |shift: u8| match (op0.reg(), op1) {
+            (Some(r), Operand::Reg8(rs)) => instruction("mov", vec![op0.into(), dis::Operand::RegisterShiftedRegister(r, shift, Reg::x86_sized(rs, 1))], 0),
+            (Some(r), _) => instruction("mov", vec![op0.into(), dis::Operand::ShiftedRegister(r, shift, match op1.into() { dis::Operand::Immediate(x) => x, _ => 0 })], 0),
+            (None, _) => instruction(self.operation.name(), vec![op0.into(), op1.into()], 0),
+        };
         match self.operation {
-            Operation::Add   => dis::Instruction { opcode: "add", operands: vec![self.reg1.into(), self.reg1.into(), self.reg2.into()], flags: 0 },
-            Operation::Sub   => dis::Instruction { opcode: "sub", operands: vec![self.reg1.into(), self.reg1.into(), self.reg2.into()], flags: 0 },
-            Operation::And   => dis::Instruction { opcode: "and", operands: vec![self.reg1.into(), self.reg1.into(), self.reg2.into()], flags: 0 },
-            Operation::Or    => dis::Instruction { opcode: "or", operands: vec![self.reg1.into(), self.reg1.into(), self.reg2.into()], flags: 0 },
-            Operation::Xor   => dis::Instruction { opcode: "xor", operands: vec![self.reg1.into(), self.reg1.into(), self.reg2.into()], flags: 0 },
-            Operation::Mov   => dis::Instruction { opcode: "mov", operands: vec![self.reg1.into(), self.reg2.into()], flags: 0 },
-            Operation::Call  => dis::Instruction { opcode: "call", operands: vec![self.reg1.into()], flags: 0 },
-            Operation::Push  => dis::Instruction { opcode: "push", operands: vec![self.reg1.into()], flags: 0 },
-            Operation::Pop   => dis::Instruction { opcode: "pop", operands: vec![self.reg1.into()], flags: 0 },
-            Operation::Nop   => dis::Instruction { opcode: "nop", operands: vec![], flags: 0 },
-            Operation::Ret   => dis::Instruction { opcode: "ret", operands: vec![], flags: 0 },
-            _ => panic!(""),
+            Operation::Add   => instruction("add", vec![op0.into(), op0.into(), op1.into()], dis::FLAG_SETS_FLAGS),
+            Operation::Adc   => instruction("adc", vec![op0.into(), op0.into(), op1.into()], dis::FLAG_SETS_FLAGS),
+            Operation::Sub   => instruction("sub", vec![op0.into(), op0.into(), op1.into()], dis::FLAG_SETS_FLAGS),
+            Operation::Sbb   => instruction("sbb", vec![op0.into(), op0.into(), op1.into()], dis::FLAG_SETS_FLAGS),
+            Operation::And   => instruction("and", vec![op0.into(), op0.into(), op1.into()], dis::FLAG_SETS_FLAGS),
+            Operation::Or    => instruction("or", vec![op0.into(), op0.into(), op1.into()], dis::FLAG_SETS_FLAGS),
+            Operation::Xor   => instruction("xor", vec![op0.into(), op0.into(), op1.into()], dis::FLAG_SETS_FLAGS),
+            Operation::Inc   => instruction("add", vec![op0.into(), op0.into(), dis::Operand::Immediate(1)], dis::FLAG_SETS_FLAGS),
+            Operation::Dec   => instruction("sub", vec![op0.into(), op0.into(), dis::Operand::Immediate(1)], dis::FLAG_SETS_FLAGS),
+            Operation::Not   => instruction("not", vec![op0.into(), op0.into()], 0),
+            // One-operand multiplies write the high half to rdx, like ARM's long multiplies.
+            Operation::Mul | Operation::Imul if matches!(op1, Operand::Nothing) => {
+                let opcode = if self.operation == Operation::Mul { "umull" } else { "smull" };
+                instruction(opcode, vec![op0.sized_like(AX), op0.sized_like(DX), op0.sized_like(AX), op0.into()], 0)
+            },
+            Operation::Imul => match op2 {
+                Operand::Nothing => instruction("mul", vec![op0.into(), op0.into(), op1.into()], 0),
+                _ => instruction("mul", vec![op0.into(), op1.into(), op2.into()], 0),
+            },
+            Operation::Shl   => shift(dis::SHIFT_LSL),
+            Operation::Shr   => shift(dis::SHIFT_LSR),
+            Operation::Sar   => shift(dis::SHIFT_ASR),
+            Operation::Cmp   => instruction("cmp", vec![op0.into(), op1.into()], 0),
+            Operation::Test  => instruction("test", vec![op0.into(), op1.into()], 0),
+            Operation::Mov   => instruction("mov", vec![op0.into(), op1.into()], 0),
+            Operation::Movzx => instruction("zext", vec![op0.into(), op1.into()], 0),
+            Operation::Movsx | Operation::Movsxd => instruction("sext", vec![op0.into(), op1.into()], 0),
+            // The accumulator sign-extended in place, and into the data register as its high half.
+            Operation::Cbw   => instruction("sext", vec![dis::Operand::Register(Reg::x86_sized(AX, 2)), dis::Operand::Register(Reg::x86_sized(AX, 1))], 0),
+            Operation::Cwde  => instruction("sext", vec![dis::Operand::Register(Reg::x86_sized(AX, 4)), dis::Operand::Register(Reg::x86_sized(AX, 2))], 0),
+            Operation::Cdqe  => instruction("sext", vec![dis::Operand::Register(Reg::x86_sized(AX, 8)), dis::Operand::Register(Reg::x86_sized(AX, 4))], 0),
+            Operation::Cwd | Operation::Cdq | Operation::Cqo => {
+                let size = match self.operation { Operation::Cwd => 2, Operation::Cdq => 4, _ => 8 };
+                let high = dis::Operand::ShiftedRegister(Reg::x86_sized(AX, size), dis::SHIFT_ASR, size as i64 * 8 - 1);
+                instruction("mov", vec![dis::Operand::Register(Reg::x86_sized(DX, size)), high], 0)
+            },
+            // The condition's truth value, stored as a byte.
+            Operation::Setcc(cond) => instruction("set", vec![op0.into()], CONDITIONS[cond as usize]),
+            Operation::Cmovcc(cond) => instruction("mov", vec![op0.into(), op1.into()], CONDITIONS[cond as usize]),
+            Operation::Jmp   => instruction("b", vec![op0.into()], dis::COND_AL),
+            Operation::Jcc(cond) => instruction("b", vec![op0.into()], CONDITIONS[cond as usize]),
+            Operation::Call  => instruction("call", vec![op0.into()], 0),
+            Operation::Push  => instruction("push", vec![op0.into()], 0),
+            Operation::Pop   => instruction("pop", vec![op0.into()], 0),
+            Operation::Nop   => instruction("nop", vec![], 0),
+            Operation::Ret   => instruction("ret", vec![], 0),
+            Operation::Int   => instruction("svc", vec![op0.into()], 0),
+            Operation::Syscall => instruction("syscall", vec![], 0),
+            Operation::Unknown => instruction("unk", vec![], 0),
+            _ => instruction(self.operation.name(), self.operands.iter()
+                .filter(|op| !matches!(op, Operand::Nothing))
+                .map(|op| Operand::into(*op))
+                .collect(), 0),
         }
     }
 }
 
-fn ins_dest_src(foffset: usize, ins_size: u8, operation: Operation, dest: Operand, source: Operand) -> Instruction {
-    Instruction { operation, reg1: dest, reg2: source, offset: foffset, ins_size }
+fn read_imm(bytes: &[u8], offset: usize, size: usize) -> Option<u64> {
+    let b = bytes.get(offset..offset+size)?;
+    Some(b.iter().rev().fold(0, |acc, x| acc << 8 | *x as u64))
 }
 
-fn ins_single_op(foffset: usize, ins_size: u8, operation: Operation, op: Operand) -> Instruction {
-    Instruction { operation, reg1: op, reg2: Operand::Nothing, offset: foffset, ins_size }
+// Reads an immediate at pos and moves pos past it.
+fn take_imm(bytes: &[u8], pos: &mut usize, size: usize) -> Option<u64> {
+    let x = read_imm(bytes, *pos, size)?;
+    *pos += size;
+    Some(x)
 }
 
-// op dest:r8, source:r8
-fn ins_regh_regh(foffset: usize, ins_size: u8, operation: Operation, op_size: u8, dest: u8, source: u8) -> Instruction {
-    match op_size {
-        OPSIZE_BYTE  => ins_dest_src(foffset, ins_size, operation, Operand::Reg8H(dest), Operand::Reg8H(source)),
-        OPSIZE_WORD  => ins_dest_src(foffset, ins_size, operation, Operand::Reg16(dest), Operand::Reg16(source)),
-        OPSIZE_DWORD => ins_dest_src(foffset, ins_size, operation, Operand::Reg32(dest),Operand::Reg32(source)),
-        OPSIZE_QWORD => ins_dest_src(foffset, ins_size, operation, Operand::Reg64(dest),Operand::Reg64(source)),
-        _ => panic!("Invalid op size")
-    }
+fn read_imm32(bytes: &[u8], offset: usize) -> Option<u32> {
+    read_imm(bytes, offset, 4).map(|x| x as u32)
 }
 
-// op dest:r8, source:imm8
-fn ins_regh_imm8(foffset: usize, ins_size: u8, operation: Operation, op_size: u8, dest: u8, source: i8) -> Instruction {
-    match op_size {
-        OPSIZE_BYTE  => ins_dest_src(foffset, ins_size, operation, Operand::Reg8H(dest), Operand::ImmS8(source)),
-        OPSIZE_WORD  => ins_dest_src(foffset, ins_size, operation, Operand::Reg16(dest), Operand::ImmS8(source)),
-        OPSIZE_DWORD => ins_dest_src(foffset, ins_size, operation, Operand::Reg32(dest),Operand::ImmS8(source)),
-        OPSIZE_QWORD => ins_dest_src(foffset, ins_size, operation, Operand::Reg64(dest),Operand::ImmS8(source)),
-        _ => panic!("Invalid op size")
-    }
+// The r/m half of a ModRM byte, before the operand size is applied.
+#[derive(Clone, Copy)]
+enum ModRm {
+    Reg(u8),
+    Mem(Mem),
 }
 
-// op SIZE PTR [dest:r+offset:i], source:r
-fn ins_preg_regh(foffset: usize, ins_size: u8, operation: Operation, op_size: u8, dest: u8, offset: i32, source: u8) -> Instruction {
-    match op_size {
-        OPSIZE_BYTE  => ins_dest_src(foffset, ins_size, operation, Operand::PtrRegByte(dest, offset), Operand::Reg8H(source)),
-        OPSIZE_WORD  => ins_dest_src(foffset, ins_size, operation, Operand::PtrRegWord(dest, offset), Operand::Reg16(source)),
-        OPSIZE_DWORD => ins_dest_src(foffset, ins_size, operation, Operand::PtrRegDword(dest, offset), Operand::Reg32(source)),
-        OPSIZE_QWORD => ins_dest_src(foffset, ins_size, operation, Operand::PtrRegQword(dest, offset), Operand::Reg64(source)),
-        _ => panic!("Invalid op size")
+// Decodes the ModRM byte at offset along with any SIB byte and displacement,
+// extending the register numbers with the REX bits in rex.
+// Returns the reg field, the r/m operand and the number of bytes consumed.
+fn decode_modrm(bytes: &[u8], offset: usize, rex: u8) -> Option<(u8, ModRm, usize)> {
+    let x = *bytes.get(offset)?;
+    let mode = x >> 6;
+    let reg = ((x >> 3) & 0b111) | rex_bit(rex, PREFIX_REX_R);
+    // rip-relative and base-less forms go by the low 3 bits, so r13 and r12
+    // as the base still need a displacement and a SIB byte.
+    let rm = x & 0b111;
+    if mode == 0b11 {
+        return Some((reg, ModRm::Reg(rm | rex_bit(rex, PREFIX_REX_B)), 1))
     }
-}
-
-// op dest:r, SIZE PTR [source:r+offset:i]
-fn ins_regh_preg(foffset: usize, ins_size: u8, operation: Operation, op_size: u8, dest: u8, source: u8, offset: i32) -> Instruction {
-    match op_size {
-        OPSIZE_BYTE  => ins_dest_src(foffset, ins_size, operation, Operand::Reg8H(dest), Operand::PtrRegByte(source, offset)),
-        OPSIZE_WORD  => ins_dest_src(foffset, ins_size, operation, Operand::Reg16(dest), Operand::PtrRegWord(source, offset)),
-        OPSIZE_DWORD => ins_dest_src(foffset, ins_size, operation, Operand::Reg32(dest), Operand::PtrRegDword(source, offset)),
-        OPSIZE_QWORD => ins_dest_src(foffset, ins_size, operation, Operand::Reg64(dest), Operand::PtrRegQword(source, offset)),
-        _ => panic!("Invalid op size")
+    if mode == 0b00 && rm == 0x5 {
+        let disp = read_imm32(bytes, offset+1)? as i32;
+        return Some((reg, ModRm::Mem(Mem { disp, rip: true, ..Mem::default() }), 5))
     }
-}
-
-// op dest:r, SIZE PTR [base:r+offset:r*mul:i]
-fn ins_regh_pregreg(foffset: usize, ins_size: u8, operation: Operation, op_size: u8, dest: u8, source: u8, offset: u8, mul: u8) -> Instruction {
-    match op_size {
-        OPSIZE_BYTE  => ins_dest_src(foffset, ins_size, operation, Operand::Reg8H(dest), Operand::PtrRegRegByte(source, offset, mul)),
-        OPSIZE_WORD  => ins_dest_src(foffset, ins_size, operation, Operand::Reg16(dest), Operand::PtrRegRegWord(source, offset, mul)),
-        OPSIZE_DWORD => ins_dest_src(foffset, ins_size, operation, Operand::Reg32(dest), Operand::PtrRegRegDword(source, offset, mul)),
-        OPSIZE_QWORD => ins_dest_src(foffset, ins_size, operation, Operand::Reg64(dest), Operand::PtrRegRegQword(source, offset, mul)),
-        _ => panic!("Invalid op size")
+    let mut len = 1;
+    let mut mem = Mem { base: Some(rm | rex_bit(rex, PREFIX_REX_B)), ..Mem::default() };
+    // A SIB base of 5 with no displacement is no base and a disp32.
+    let mut disp32 = mode == 0b10;
+    if rm == 0x4 {
+        let y = *bytes.get(offset+1)?;
+        len += 1;
+        // An index of 4 is none, unless REX.X makes it r12.
+        let index = ((y >> 3) & 0b111) | rex_bit(rex, PREFIX_REX_X);
+        if index != 0x4 {
+            mem.index = Some(index);
+            mem.scale = y >> 6;
+        }
+        if mode == 0b00 && y & 0b111 == 0x5 {
+            mem.base = None;
+            disp32 = true;
+        } else {
+            mem.base = Some((y & 0b111) | rex_bit(rex, PREFIX_REX_B));
+        }
     }
-}
-
-// op SIZE PTR [base:r+offset:r*mul:i], source:r
-fn ins_pregreg_regh(foffset: usize, ins_size: u8, operation: Operation, op_size: u8, source: u8, dest: u8, offset: u8, mul: u8) -> Instruction {
-    match op_size {
-        OPSIZE_BYTE  => ins_dest_src(foffset, ins_size, operation, Operand::PtrRegRegByte(source, offset, mul), Operand::Reg8H(source)),
-        OPSIZE_WORD  => ins_dest_src(foffset, ins_size, operation, Operand::PtrRegRegWord(source, offset, mul), Operand::Reg16(source)),
-        OPSIZE_DWORD => ins_dest_src(foffset, ins_size, operation, Operand::PtrRegRegDword(source, offset, mul), Operand::Reg32(dest)),
-        OPSIZE_QWORD => ins_dest_src(foffset, ins_size, operation, Operand::PtrRegRegQword(source, offset, mul), Operand::Reg64(dest)),
-        _ => panic!("Invalid op size")
+    if disp32 {
+        mem.disp = read_imm32(bytes, offset+len)? as i32;
+        len += 4;
+    } else if mode == 0b01 {
+        mem.disp = *bytes.get(offset+len)? as i8 as i32;
+        len += 1;
     }
+    Some((reg, ModRm::Mem(mem), len))
 }
 
-// op dest:r, SIZE PTR [ip+offset:i]
-fn ins_regh_prel(foffset: usize, ins_size: u8, operation: Operation, op_size: u8, dest: u8, offset: u32) -> Instruction {
+// Byte registers 4-7 are ah-bh without a REX prefix and spl-dil with one.
+fn sized_reg(op_size: u8, reg: u8, rex: u8) -> Operand {
     match op_size {
-        OPSIZE_BYTE =>  ins_dest_src(foffset, ins_size, operation, Operand::Reg8H(dest), Operand::PtrRelByte(offset)),
-        OPSIZE_WORD =>  ins_dest_src(foffset, ins_size, operation, Operand::Reg16(dest), Operand::PtrRelWord(offset)),
-        OPSIZE_DWORD => ins_dest_src(foffset, ins_size, operation, Operand::Reg32(dest), Operand::PtrRelDword(offset)),
-        OPSIZE_QWORD => ins_dest_src(foffset, ins_size, operation, Operand::Reg64(dest), Operand::PtrRelQword(offset)),
-        _ => panic!("Invalid op size")
+        OPSIZE_BYTE if rex & PREFIX_REX != 0 => Operand::Reg8(reg),
+        OPSIZE_BYTE  => Operand::Reg8H(reg),
+        OPSIZE_WORD  => Operand::Reg16(reg),
+        OPSIZE_DWORD => Operand::Reg32(reg),
+        _            => Operand::Reg64(reg),
     }
 }
 
-// op SIZE PTR [ip+offset:i], source:r
-fn ins_prel_regh(foffset: usize, ins_size: u8, operation: Operation, op_size: u8, source: u8, offset: u32) -> Instruction {
-    match op_size {
-        OPSIZE_BYTE =>  ins_dest_src(foffset, ins_size, operation, Operand::PtrRelByte(offset), Operand::Reg8H(source)),
-        OPSIZE_WORD =>  ins_dest_src(foffset, ins_size, operation, Operand::PtrRelWord(offset), Operand::Reg16(source)),
-        OPSIZE_DWORD => ins_dest_src(foffset, ins_size, operation, Operand::PtrRelDword(offset),Operand::Reg32(source)),
-        OPSIZE_QWORD => ins_dest_src(foffset, ins_size, operation, Operand::PtrRelQword(offset),Operand::Reg64(source)),
-        _ => panic!("Invalid op size")
-    }
+// Prefixes seen before the opcode.
+#[derive(Clone, Copy, Default)]
+struct Prefixes {
+    rex: u8,
+    opsize: bool,
+    rep: bool,
+    segment: Option<&'static str>,
 }
 
-fn disassemble_x86_op_op(operation: Operation, bytes: &[u8], offset: usize, op_size: u8, swap_operands: bool) -> Option<Instruction> {
-    if offset + 1 >= bytes.len() {
-        return None
-    }
-    let x = bytes[offset+1];
-    if x & 0b11000000 == 0 {
-        let source = (x >> 3) & 0b111;
-        let op2 = x & 0b111;
-        if op2 == 0x4 {
-            let y = bytes[offset+2];
-            let reg2 = (y >> 3) & 0b111;
-            let reg1 = y & 0b111;
-            let mul = (y >> 6) & 0b11;
-            if swap_operands {
-                return Some(ins_regh_pregreg(offset, 3, operation, op_size, source, reg1, reg2, mul))
-            }
-            else {
-                return Some(ins_pregreg_regh(offset, 3, operation, op_size, source, reg1, reg2, mul))
-            }
-        }
-        else if op2 == 0x5 {
-            let rel = u32::from_le_bytes([bytes[offset+2], bytes[offset+3], bytes[offset+4], bytes[offset+5]]);
-            if swap_operands {
-                return Some(ins_regh_prel(offset, 6, operation, op_size, source, rel))
-            }
-            else {
-                return Some(ins_prel_regh(offset, 6, operation, op_size, source, rel))
-            }
-        }
-        else {
-            let dest = match x & 0b111 {
-                0x0 => AX,
-                0x1 => CX,
-                0x2 => DX,
-                0x3 => BX,
-                0x6 => SI,
-                0x7 => DI,
-                _ => DI,
-            };
-            if swap_operands {
-                return Some(ins_regh_preg(offset, 2, operation, op_size, source, dest, 0x0))
-            } else {
-                return Some(ins_preg_regh(offset, 2, operation, op_size, dest, 0x0, source))
-            }
-        }
-    }
-    else if x & 0b11000000 == 0b01000000 {
-        let source = (x >> 3) & 0b111;
-        let op2 = x & 0b111;
-        let o = if bytes[offset+2] & 0x80 != 0 { -(0x100 - bytes[offset+2] as i32) } else { bytes[offset+2] as i32 };
-        if swap_operands {
-            return Some(ins_regh_preg(offset, 3, operation, op_size, source, op2, o))
-        } else {
-            return Some(ins_preg_regh(offset, 3, operation, op_size, op2, o, source))
-        }
-    }
-    else if x & 0b11000000 == 0b11000000 {
-        let dest = (x >> 3) & 0b111;
-        let source = x & 0b111;
-        if swap_operands {
-            return Some(ins_regh_regh(offset, 2, operation, op_size, source, dest))
+// Reads the legacy and REX prefixes at offset, returning them and the offset
+// of the opcode. A REX prefix only counts right before the opcode.
+fn decode_prefixes(bytes: &[u8], offset: usize) -> Option<(Prefixes, usize)> {
+    let mut prefixes = Prefixes::default();
+    let mut pos = offset;
+    loop {
+        match *bytes.get(pos)? {
+            0x40..=0x4f => {
+                prefixes.rex = PREFIX_REX | (bytes[pos] & 0xf);
+                pos += 1;
+                continue
+            },
+            0x66 => prefixes.opsize = true,
+            0xf3 => prefixes.rep = true,
+            0x64 => prefixes.segment = Some("fs"),
+            0x65 => prefixes.segment = Some("gs"),
+            // lock, repne, the address size and the other segments.
+            0xf0 | 0xf2 | 0x67 | 0x26 | 0x2e | 0x36 | 0x3e => (),
+            _ => return Some((prefixes, pos)),
         }
-        else {
-            return Some(ins_regh_regh(offset, 2, operation, op_size, dest, source))
+        prefixes.rex = 0;
+        pos += 1;
+        if pos - offset >= MAX_INSTRUCTION_SIZE {
+            return None
         }
     }
-    None
 }
 
-fn disassemble_x86_al_imm8(operation: Operation, bytes: &[u8], offset: usize) -> Option<Instruction> {
-    let imm = bytes[offset+1];
-    Some(ins_dest_src(offset, 2, operation, Operand::Reg8(AX), Operand::ImmU8(imm)))
+// An immediate of the operand size, at most 32 bits sign-extended.
+fn read_sized_imm(bytes: &[u8], offset: usize, op_size: u8) -> Option<(Operand, usize)> {
+    match op_size {
+        OPSIZE_BYTE => Some((Operand::ImmU8(*bytes.get(offset)?), 1)),
+        OPSIZE_WORD => Some((Operand::ImmU16(read_imm(bytes, offset, 2)? as u16), 2)),
+        _ => Some((Operand::ImmS32(read_imm32(bytes, offset)? as i32), 4)),
+    }
 }
 
-fn disassemble_x86_op_imm(bytes: &[u8], offset: usize, op_size: u8, _swap_operands: bool) -> Option<Instruction> {
-    if offset + 1 >= bytes.len() {
-        return None
-    }
-    let x = bytes[offset+1];
-    let operation = match (x >> 3) & 0b111 {
-        0x0 => Operation::Add,
-        0x1 => Operation::Or,
-        0x2 => Operation::Adc,
-        0x3 => Operation::Sbb,
-        0x4 => Operation::And,
-        0x5 => Operation::Sub,
-        0x6 => Operation::Xor,
-        0x7 => Operation::Cmp,
-        _ => return None
+// Decodes one instruction in stages: prefixes, the opcode and its table row,
+// then ModRM with any SIB and displacement, then any immediate.
+fn decode_instruction(bytes: &[u8], offset: usize) -> Option<Instruction> {
+    let (prefixes, mut pos) = decode_prefixes(bytes, offset)?;
+    let rex = prefixes.rex;
+    let mut opcode = *bytes.get(pos)?;
+    pos += 1;
+    let table = if opcode == OPCODE_TWO_BYTE {
+        opcode = *bytes.get(pos)?;
+        pos += 1;
+        TWO_BYTE
+    } else {
+        ONE_BYTE
+    };
+    let modrm_reg = bytes.get(pos).map(|x| (x >> 3) & 0b111);
+    let Opcode(_, _, _, op, size, form) = table.iter()
+        .find(|row| opcode & row.1 == row.0 && (row.2.is_none() || (row.5.has_modrm() && row.2 == modrm_reg))
+            && !matches!(row.5, Form::Fixed(x) if bytes.get(pos) != Some(&x)))?;
+    let op_size = match size {
+        Size::Byte => OPSIZE_BYTE,
+        Size::Word => OPSIZE_WORD,
+        Size::Dword => OPSIZE_DWORD,
+        Size::Full if rex & PREFIX_REX_W != 0 => OPSIZE_QWORD,
+        Size::Full | Size::Stack if prefixes.opsize => OPSIZE_WORD,
+        Size::Full => OPSIZE_DWORD,
+        Size::Stack => OPSIZE_QWORD,
     };
-    if x & 0b11000000 == 0b11000000 {
-        let source = bytes[offset+2] as i8;
-        let dest = x & 0b111;
-        return Some(ins_regh_imm8(offset, 3, operation, op_size, dest, source))
-    }
-    None
-}
 
-fn disassemble_x86_push_pop(operation: Operation, bytes: &[u8], offset: usize) -> Option<Instruction> {
-    let imm = bytes[offset] - match operation { Operation::Push => OPCODE_PUSH_REG, Operation::Pop => OPCODE_POP_REG, _ => 0 };
-    Some(ins_single_op(offset, 1, operation, Operand::Reg64(imm)))
-}
+    let (reg, rm) = if form.has_modrm() {
+        let (reg, rm, len) = decode_modrm(bytes, pos, rex)?;
+        pos += len;
+        (reg, rm)
+    } else {
+        (0, ModRm::Reg(0))
+    };
+    let operation = match *op {
+        Op::One(operation) => operation,
+        Op::Group(group) => group[(reg & 0b111) as usize],
+        Op::ByOpcode(group) => group[((opcode >> 3) & 0b111) as usize],
+        Op::Sized(sized) => sized[op_size as usize - 1],
+        Op::Cond(f) => f(opcode & 0xf),
+    };
+    if operation == Operation::Unknown {
+        return None
+    }
 
-fn disassemble_x86_branch_imm(operation: Operation, bytes: &[u8], offset: usize, op_size: u8) -> Option<Instruction> {
-    match op_size {
-        OPSIZE_BYTE => {
-            let imm = bytes[offset+1] as i8;
-            Some(ins_single_op(offset, 2, operation, Operand::ImmS8(imm + 2)))
+    let rm_operand = |op_size: u8| match rm {
+        ModRm::Reg(r) => sized_reg(op_size, r, rex),
+        ModRm::Mem(mem) => Operand::Mem(op_size, Mem { segment: prefixes.segment, ..mem }),
+    };
+    let reg_operand = sized_reg(op_size, reg, rex);
+    let opcode_reg = (opcode & 0b111) | rex_bit(rex, PREFIX_REX_B);
+    let string_mem = |r: u8| Operand::Mem(op_size, Mem { base: Some(r), ..Mem::default() });
+    let operands = match form {
+        Form::None => [Operand::Nothing; 3],
+        Form::Fixed(_) => {
+            pos += 1;
+            [Operand::Nothing; 3]
         },
-        OPSIZE_DWORD => {
-            let imm = u32::from_le_bytes([bytes[offset+1], bytes[offset+2], bytes[offset+3], bytes[offset+4]]);
-            Some(ins_single_op(offset, 5, operation, Operand::ImmU32(imm + 5)))
+        Form::Rm => [rm_operand(op_size), Operand::Nothing, Operand::Nothing],
+        Form::RmReg => [rm_operand(op_size), reg_operand, Operand::Nothing],
+        Form::RegRm8 => [reg_operand, rm_operand(OPSIZE_BYTE), Operand::Nothing],
+        Form::RegRm16 => [reg_operand, rm_operand(OPSIZE_WORD), Operand::Nothing],
+        Form::RegRm32 => [reg_operand, rm_operand(OPSIZE_DWORD), Operand::Nothing],
+        Form::RegRm => [reg_operand, rm_operand(op_size), Operand::Nothing],
+        Form::RegAddr => match rm {
+            ModRm::Mem(_) => [reg_operand, rm_operand(OPSIZE_NONE), Operand::Nothing],
+            ModRm::Reg(_) => return None,
         },
-        _ => None
-    }
-}
-
-fn disassemble_x86_mov_imm(bytes: &[u8], offset: usize, op_size: u8) -> Option<Instruction> {
-    let reg = bytes[offset] - match op_size { OPSIZE_BYTE => OPCODE_MOV_REG_IMM8, _ => OPCODE_MOV_REG_IMM };
-    match op_size {
-        OPSIZE_BYTE  => {
-            let imm = bytes[offset+1];
-            Some(ins_dest_src(offset, 2, Operation::Mov, Operand::Reg8(reg), Operand::ImmU8(imm)))
+        Form::RmImm | Form::AccImm | Form::Imm | Form::RegRmImm => {
+            let (x, len) = read_sized_imm(bytes, pos, op_size)?;
+            pos += len;
+            match form {
+                Form::RmImm => [rm_operand(op_size), x, Operand::Nothing],
+                Form::AccImm => [sized_reg(op_size, AX, rex), x, Operand::Nothing],
+                Form::Imm => [x, Operand::Nothing, Operand::Nothing],
+                _ => [reg_operand, rm_operand(op_size), x],
+            }
+        },
+        Form::RmImm8 => [rm_operand(op_size), Operand::ImmS8(take_imm(bytes, &mut pos, 1)? as i8), Operand::Nothing],
+        Form::RegRmImm8 => [reg_operand, rm_operand(op_size), Operand::ImmS8(take_imm(bytes, &mut pos, 1)? as i8)],
+        Form::Imm8 => [Operand::ImmS8(take_imm(bytes, &mut pos, 1)? as i8), Operand::Nothing, Operand::Nothing],
+        Form::RmOne => [rm_operand(op_size), Operand::ImmS8(1), Operand::Nothing],
+        Form::RmCl => [rm_operand(op_size), Operand::Reg8(CX), Operand::Nothing],
+        Form::OpReg => [sized_reg(op_size, opcode_reg, rex), Operand::Nothing, Operand::Nothing],
+        Form::OpRegAcc => [sized_reg(op_size, opcode_reg, rex), sized_reg(op_size, AX, rex), Operand::Nothing],
+        Form::OpRegImm => {
+            let x = match op_size {
+                OPSIZE_BYTE => Operand::ImmU8(take_imm(bytes, &mut pos, 1)? as u8),
+                OPSIZE_WORD => Operand::ImmU16(take_imm(bytes, &mut pos, 2)? as u16),
+                OPSIZE_DWORD => Operand::ImmU32(take_imm(bytes, &mut pos, 4)? as u32),
+                _ => Operand::ImmU64(take_imm(bytes, &mut pos, 8)?),
+            };
+            [sized_reg(op_size, opcode_reg, rex), x, Operand::Nothing]
         },
-        OPSIZE_DWORD => {
-            let imm = u32::from_le_bytes([bytes[offset+1], bytes[offset+2], bytes[offset+3], bytes[offset+4]]);
-            Some(ins_dest_src(offset, 5, Operation::Mov, Operand::Reg32(reg), Operand::ImmU32(imm)))
+        // Branch targets are relative to the start of the instruction.
+        Form::Rel => {
+            let rel = match op_size {
+                OPSIZE_BYTE => take_imm(bytes, &mut pos, 1)? as i8 as i32,
+                _ => take_imm(bytes, &mut pos, 4)? as i32,
+            };
+            [Operand::ImmS32(rel.wrapping_add((pos - offset) as i32)), Operand::Nothing, Operand::Nothing]
         },
-        _ => None
+        Form::StrAcc => [string_mem(DI), sized_reg(op_size, AX, rex), Operand::Nothing],
+        Form::StrStr => [string_mem(DI), string_mem(SI), Operand::Nothing],
+    };
+    if pos - offset > MAX_INSTRUCTION_SIZE {
+        return None
     }
+    let rep = prefixes.rep && matches!(form, Form::StrAcc | Form::StrStr);
+    Some(Instruction { operation, operands, rep, offset, ins_size: (pos - offset) as u8 })
 }
 
-fn rex_w_qword_or_dword(prefix: u8) -> u8 {
-    if (prefix & PREFIX_REX_W) != 0 { OPSIZE_QWORD } else { OPSIZE_DWORD }
+// Decodes the single instruction at a byte offset, which needn't be one linear
+// disassembly reaches.
+pub fn disassemble_x86_at(bytes: &[u8], offset: usize) -> Option<Instruction> {
+    decode_instruction(bytes, offset)
 }
 
-fn disassemble_x86_instruction(bytes: &[u8], offset: usize, prefix: u8) -> Option<Instruction> {
-    if offset >= bytes.len() {
-        return None
-    }
-    let opcode = bytes[offset];
-    match opcode {
-        OPCODE_REX_W => {
-            let ins = disassemble_x86_instruction(bytes, offset + 1, prefix | PREFIX_REX_W);
-            if ins.is_some() {
-                let mut ins_ = ins.unwrap();
-                ins_.ins_size += 1;
-                ins_.offset = offset;
-                return Some(ins_);
-            }
-            return None
+// Decodes one instruction after another, passing each to f until it returns false.
+pub fn decode_x86(bytes: &[u8], mut f: impl FnMut(Instruction) -> bool) {
+    let mut offset = 0x0;
+    while offset < bytes.len() {
+        let ins = decode_instruction(bytes, offset).unwrap_or_else(|| {
+            log::debug!("Unknown x86 opcode {:#04x} at offset {:#x}", bytes[offset], offset);
+            Instruction {
+                operation: Operation::Unknown,
+                operands: [Operand::Nothing; 3],
+                rep: false,
+                offset, ins_size: 1}
+        });
+        offset += ins.ins_size as usize;
+        if !f(ins) {
+            return;
         }
-        _ => (),
-    };
-    match opcode {
-        OPCODE_ADD_BYTE_STR  => disassemble_x86_op_op(Operation::Add, bytes, offset, OPSIZE_BYTE, false),
-        OPCODE_ADD_DWORD_STR => disassemble_x86_op_op(Operation::Add, bytes, offset, OPSIZE_DWORD, false),
-        OPCODE_ADD_BYTE_LD   => disassemble_x86_op_op(Operation::Add, bytes, offset, OPSIZE_BYTE, true),
-        OPCODE_ADD_DWORD_LD  => disassemble_x86_op_op(Operation::Add, bytes, offset, OPSIZE_DWORD, true),
-        OPCODE_ADD_AL_IMM8   => disassemble_x86_al_imm8(Operation::Add, bytes, offset),
-        OPCODE_OR_BYTE_STR   => disassemble_x86_op_op(Operation::Or, bytes, offset, OPSIZE_BYTE, false),
-        OPCODE_OR_DWORD_STR  => disassemble_x86_op_op(Operation::Or, bytes, offset, OPSIZE_DWORD, false),
-        OPCODE_OR_BYTE_LD    => disassemble_x86_op_op(Operation::Or, bytes, offset, OPSIZE_BYTE, true),
-        OPCODE_OR_DWORD_LD   => disassemble_x86_op_op(Operation::Or, bytes, offset, OPSIZE_DWORD, true),
-        OPCODE_OR_AL_IMM8    => disassemble_x86_al_imm8(Operation::Or, bytes, offset),
-        OPCODE_ADC_BYTE_STR  => disassemble_x86_op_op(Operation::Adc, bytes, offset, OPSIZE_BYTE, false),
-        OPCODE_ADC_DWORD_STR => disassemble_x86_op_op(Operation::Adc, bytes, offset, OPSIZE_DWORD, false),
-        OPCODE_ADC_BYTE_LD   => disassemble_x86_op_op(Operation::Adc, bytes, offset, OPSIZE_BYTE, true),
-        OPCODE_ADC_DWORD_LD  => disassemble_x86_op_op(Operation::Adc, bytes, offset, OPSIZE_DWORD, true),
-        OPCODE_ADC_AL_IMM8   => disassemble_x86_al_imm8(Operation::Adc, bytes, offset),
-        OPCODE_AND_BYTE_STR  => disassemble_x86_op_op(Operation::And, bytes, offset, OPSIZE_BYTE, false),
-        OPCODE_AND_DWORD_STR => disassemble_x86_op_op(Operation::And, bytes, offset, OPSIZE_DWORD, false),
-        OPCODE_AND_BYTE_LD   => disassemble_x86_op_op(Operation::And, bytes, offset, OPSIZE_BYTE, true),
-        OPCODE_AND_DWORD_LD  => disassemble_x86_op_op(Operation::And, bytes, offset, OPSIZE_DWORD, true),
-        OPCODE_AND_AL_IMM8   => disassemble_x86_al_imm8(Operation::And, bytes, offset),
-        OPCODE_SUB_BYTE_STR  => disassemble_x86_op_op(Operation::Sub, bytes, offset, OPSIZE_BYTE, false),
-        OPCODE_SUB_DWORD_STR => disassemble_x86_op_op(Operation::Sub, bytes, offset, OPSIZE_DWORD, false),
-        OPCODE_SUB_BYTE_LD   => disassemble_x86_op_op(Operation::Sub, bytes, offset, OPSIZE_BYTE, true),
-        OPCODE_SUB_DWORD_LD  => disassemble_x86_op_op(Operation::Sub, bytes, offset, OPSIZE_DWORD, true),
-        OPCODE_SUB_AL_IMM8   => disassemble_x86_al_imm8(Operation::Sub, bytes, offset),
-        OPCODE_XOR_BYTE_STR  => disassemble_x86_op_op(Operation::Xor, bytes, offset, OPSIZE_BYTE, false),
-        OPCODE_XOR_DWORD_STR => disassemble_x86_op_op(Operation::Xor, bytes, offset, OPSIZE_DWORD, false),
-        OPCODE_XOR_BYTE_LD   => disassemble_x86_op_op(Operation::Xor, bytes, offset, OPSIZE_BYTE, true),
-        OPCODE_XOR_DWORD_LD  => disassemble_x86_op_op(Operation::Xor, bytes, offset, OPSIZE_DWORD, true),
-        OPCODE_XOR_AL_IMM8   => disassemble_x86_al_imm8(Operation::Xor, bytes, offset),
-        OPCODE_CMP_BYTE_STR  => disassemble_x86_op_op(Operation::Cmp, bytes, offset, OPSIZE_BYTE, false),
-        OPCODE_CMP_DWORD_STR => disassemble_x86_op_op(Operation::Cmp, bytes, offset, OPSIZE_DWORD, false),
-        OPCODE_CMP_BYTE_LD   => disassemble_x86_op_op(Operation::Cmp, bytes, offset, OPSIZE_BYTE, true),
-        OPCODE_CMP_DWORD_LD  => disassemble_x86_op_op(Operation::Cmp, bytes, offset, OPSIZE_DWORD, true),
-        OPCODE_CMP_AL_IMM8   => disassemble_x86_al_imm8(Operation::Cmp, bytes, offset),
-        OPCODE_PUSH_RAX      => disassemble_x86_push_pop(Operation::Push, bytes, offset),
-        OPCODE_PUSH_RCX      => disassemble_x86_push_pop(Operation::Push, bytes, offset),
-        OPCODE_PUSH_RDX      => disassemble_x86_push_pop(Operation::Push, bytes, offset),
-        OPCODE_PUSH_RBX      => disassemble_x86_push_pop(Operation::Push, bytes, offset),
-        OPCODE_PUSH_RSP      => disassemble_x86_push_pop(Operation::Push, bytes, offset),
-        OPCODE_PUSH_RBP      => disassemble_x86_push_pop(Operation::Push, bytes, offset),
-        OPCODE_PUSH_RSI      => disassemble_x86_push_pop(Operation::Push, bytes, offset),
-        OPCODE_PUSH_RDI      => disassemble_x86_push_pop(Operation::Push, bytes, offset),
-        OPCODE_POP_RAX       => disassemble_x86_push_pop(Operation::Pop, bytes, offset),
-        OPCODE_POP_RCX       => disassemble_x86_push_pop(Operation::Pop, bytes, offset),
-        OPCODE_POP_RDX       => disassemble_x86_push_pop(Operation::Pop, bytes, offset),
-        OPCODE_POP_RBX       => disassemble_x86_push_pop(Operation::Pop, bytes, offset),
-        OPCODE_POP_RSP       => disassemble_x86_push_pop(Operation::Pop, bytes, offset),
-        OPCODE_POP_RBP       => disassemble_x86_push_pop(Operation::Pop, bytes, offset),
-        OPCODE_POP_RSI       => disassemble_x86_push_pop(Operation::Pop, bytes, offset),
-        OPCODE_POP_RDI       => disassemble_x86_push_pop(Operation::Pop, bytes, offset),
-        OPCODE_OP_BYTE_IMM   => disassemble_x86_op_imm(bytes, offset, OPSIZE_BYTE, false),
-        OPCODE_OP_DWORD_IMM   => disassemble_x86_op_imm(bytes, offset, rex_w_qword_or_dword(prefix), false),
-        OPCODE_TEST_BYTE_STR  => disassemble_x86_op_op(Operation::Mov, bytes, offset, OPSIZE_BYTE, false),
-        OPCODE_TEST_DWORD_STR => disassemble_x86_op_op(Operation::Test, bytes, offset, rex_w_qword_or_dword(prefix), false),
-        OPCODE_MOV_BYTE_STR  => disassemble_x86_op_op(Operation::Mov, bytes, offset, OPSIZE_BYTE, false),
-        OPCODE_MOV_DWORD_STR => disassemble_x86_op_op(Operation::Mov, bytes, offset, rex_w_qword_or_dword(prefix), false),
-        OPCODE_MOV_BYTE_LD   => disassemble_x86_op_op(Operation::Mov, bytes, offset, OPSIZE_BYTE, true),
-        OPCODE_MOV_DWORD_LD  => disassemble_x86_op_op(Operation::Mov, bytes, offset, OPSIZE_DWORD, true),
-        OPCODE_NOP           => Some(Instruction { operation: Operation::Nop, reg1: Operand::Nothing, reg2: Operand::Nothing, offset, ins_size: 1 }),
-        OPCODE_MOV_AL        => disassemble_x86_mov_imm(bytes, offset, OPSIZE_BYTE),
-        OPCODE_MOV_CL        => disassemble_x86_mov_imm(bytes, offset, OPSIZE_BYTE),
-        OPCODE_MOV_DL        => disassemble_x86_mov_imm(bytes, offset, OPSIZE_BYTE),
-        OPCODE_MOV_BL        => disassemble_x86_mov_imm(bytes, offset, OPSIZE_BYTE),
-        OPCODE_MOV_SP        => disassemble_x86_mov_imm(bytes, offset, OPSIZE_BYTE),
-        OPCODE_MOV_BP        => disassemble_x86_mov_imm(bytes, offset, OPSIZE_BYTE),
-        OPCODE_MOV_SIL       => disassemble_x86_mov_imm(bytes, offset, OPSIZE_BYTE),
-        OPCODE_MOV_DIL       => disassemble_x86_mov_imm(bytes, offset, OPSIZE_BYTE),
-        OPCODE_MOV_RAX       => disassemble_x86_mov_imm(bytes, offset, OPSIZE_DWORD),
-        OPCODE_MOV_RCX       => disassemble_x86_mov_imm(bytes, offset, OPSIZE_DWORD),
-        OPCODE_MOV_RDX       => disassemble_x86_mov_imm(bytes, offset, OPSIZE_DWORD),
-        OPCODE_MOV_RBX       => disassemble_x86_mov_imm(bytes, offset, OPSIZE_DWORD),
-        OPCODE_MOV_RSP       => disassemble_x86_mov_imm(bytes, offset, OPSIZE_DWORD),
-        OPCODE_MOV_RBP       => disassemble_x86_mov_imm(bytes, offset, OPSIZE_DWORD),
-        OPCODE_MOV_RSI       => disassemble_x86_mov_imm(bytes, offset, OPSIZE_DWORD),
-        OPCODE_MOV_RDI       => disassemble_x86_mov_imm(bytes, offset, OPSIZE_DWORD),
-        OPCODE_RET           => Some(Instruction { offset, ins_size: 1, operation: Operation::Ret, reg1: Operand::Nothing, reg2: Operand::Nothing }),
-        OPCODE_CALL         => disassemble_x86_branch_imm(Operation::Call, bytes, offset, OPSIZE_DWORD),
-        _ => None
     }
 }
 
-pub fn disassemble_x86(section: &Section, section_name: &String, program: &Program) -> DisassemblySection {
-    let mut offset = 0x0;
-    let bytes = &[
-        0x50u8,
-        0x31, 0xc0,
-        0x89, 0x47, 0xf4,
-        0x58,
-        0x90,
-        0xc3
-    ];
+// Stops early, with the instructions so far, once cancel is cancelled.
+pub fn disassemble_x86(section: &Section, section_name: &String, _program: &Program, cancel: &CancelToken) -> DisassemblySection {
     let mut instrs = Vec::<Instruction>::new();
-    // let bytes = section.bytes.as_slice();
-    while offset < bytes.len() { 
-        let res = disassemble_x86_instruction(bytes, offset, 0);
-        if res.is_some() {
-            let ins = res.unwrap();
-            offset += ins.ins_size as usize;
-            instrs.push(ins);
-        }
-        else {
-            instrs.push(Instruction {
-                operation: Operation::Unknown, 
-                reg1: Operand::Nothing, 
-                reg2: Operand::Nothing, 
-                offset, ins_size: 1});
-            offset += 1;
-        }
-    }
+    decode_x86(section.bytes.as_slice(), |ins| {
+        instrs.push(ins);
+        !cancel.is_cancelled()
+    });
     DisassemblySection {
         section_name: section_name.clone(),
+        addr: section.addr,
         instructions: crate::dis::InstructionListing::X86(instrs)
     }
 }
@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::dis::{self, Disassembly};
+use crate::reg::{Reg, RegClass};
+use crate::resolve;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum XrefKind {
+    Call,
+    Jump,
+    Data,
+}
+
+impl XrefKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            XrefKind::Call => "call",
+            XrefKind::Jump => "jump",
+            XrefKind::Data => "data",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "call" => Some(XrefKind::Call),
+            "jump" => Some(XrefKind::Jump),
+            "data" => Some(XrefKind::Data),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Xref {
+    // Address of the referencing instruction.
+    pub from: u64,
+    pub kind: XrefKind,
+}
+
+// Xrefs saved for an earlier build of the program, to be reused where its code
+// section hasn't changed since.
+pub struct PriorXrefs {
+    // Referenced address and reference.
+    pub xrefs: Vec<(u64, Xref)>,
+    // Section offsets of the code that has changed and has to be analysed again.
+    pub changed: Vec<Range<usize>>,
+}
+
+// Maps each referenced address to the instructions that refer to it.
+pub struct XrefDb {
+    refs: BTreeMap<u64, Vec<Xref>>,
+}
+
+// Absolute address of a memory operand, if it can be known without register values.
+// x86 rip-relative operands are relative to the next instruction, ARM reads pc as
+// the current instruction plus 8, and AArch64 as the instruction itself.
+pub(crate) fn memory_target(op: &dis::Operand, addr: u64, size: usize) -> Option<u64> {
+    match *op {
+        dis::Operand::Memory(Some(Reg::X86_PC), None, _, offset, _) => Some((addr + size as u64).wrapping_add(offset as u64)),
+        dis::Operand::Memory(Some(Reg::ARM_PC), None, _, offset, _) => Some((addr + 8).wrapping_add(offset as u64)),
+        dis::Operand::Memory(Some(Reg::AARCH64_PC), None, _, offset, _) => Some(addr.wrapping_add(offset as u64)),
+        dis::Operand::Memory(None, None, _, offset, _) => Some(offset as u64),
+        _ => None,
+    }
+}
+
+fn branch_target(op: &dis::Operand, addr: u64) -> Option<u64> {
+    match *op {
+        dis::Operand::Immediate(rel) => Some(addr.wrapping_add(rel as u64)),
+        _ => None,
+    }
+}
+
+impl XrefDb {
+    // Reuses the program's prior xrefs outside the code that has changed.
+    pub fn build(dis: &Disassembly) -> XrefDb {
+        let mut db = XrefDb { refs: BTreeMap::new() };
+        match &dis.program().prior_xrefs {
+            Some(prior) => {
+                let base = dis.section_addr();
+                let changed = |addr: u64| prior.changed.iter().any(|range| addr >= base && range.contains(&((addr - base) as usize)));
+                for (to, xref) in prior.xrefs.iter().filter(|(_, xref)| !changed(xref.from)) {
+                    db.add(*to, xref.from, xref.kind);
+                }
+                for range in &prior.changed {
+                    db.add_range(dis, range.clone());
+                }
+            },
+            None => db.add_range(dis, 0..usize::MAX),
+        }
+        for refs in db.refs.values_mut() {
+            refs.sort_by_key(|xref| xref.from);
+        }
+        db
+    }
+
+    // Adds the references of the instructions at section offsets in the range.
+    fn add_range(&mut self, dis: &Disassembly, range: Range<usize>) {
+        let listing = &dis.section().instructions;
+        let instrs = listing.instruction_vec_in(range.clone());
+        let offsets = listing.instruction_offset_vec_in(range.clone());
+        let sizes = listing.instruction_size_vec_in(range.clone());
+        let base = dis.section_addr();
+        for ((ins, offset), size) in instrs.iter().zip(offsets).zip(sizes) {
+            let addr = base + offset as u64;
+            let branch = match ins.opcode {
+                "call" => ins.operands.first().and_then(|op| branch_target(op, addr)).map(|t| (t, XrefKind::Call)),
+                "b" => ins.operands.first().and_then(|op| branch_target(op, addr)).map(|t| (t, XrefKind::Jump)),
+                "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" | "tbz" | "tbnz" => ins.operands.get(2).and_then(|op| branch_target(op, addr)).map(|t| (t, XrefKind::Jump)),
+                "jal" => {
+                    let kind = if matches!(ins.operands[0], dis::Operand::Register(r) if r.class() == RegClass::Zero) { XrefKind::Jump } else { XrefKind::Call };
+                    ins.operands.get(1).and_then(|op| branch_target(op, addr)).map(|t| (t, kind))
+                },
+                _ => None,
+            };
+            if let Some((target, kind)) = branch {
+                self.add(target, addr, kind);
+                continue;
+            }
+            // A register offset leaves the address unknown.
+            if ins.flags & dis::FLAG_REGISTER_OFFSET != 0 {
+                continue;
+            }
+            for op in &ins.operands {
+                if let Some(target) = memory_target(op, addr, size) {
+                    self.add(target, addr, XrefKind::Data);
+                }
+            }
+        }
+        for (from, resolved) in resolve::resolve_indirect_in(dis, range) {
+            self.add(resolved.target, from, if resolved.is_call { XrefKind::Call } else { XrefKind::Jump });
+        }
+    }
+
+    // Every reference, as the referenced address and the reference.
+    pub fn all(&self) -> impl Iterator<Item = (u64, &Xref)> {
+        self.refs.iter().flat_map(|(to, refs)| refs.iter().map(move |xref| (*to, xref)))
+    }
+
+    fn add(&mut self, to: u64, from: u64, kind: XrefKind) {
+        self.refs.entry(to).or_default().push(Xref { from, kind });
+    }
+
+    // Instructions referring to the address, in address order.
+    pub fn refs_to(&self, addr: u64) -> &[Xref] {
+        match self.refs.get(&addr) {
+            Some(refs) => refs.as_slice(),
+            None => &[],
+        }
+    }
+
+    // Every referenced address in the range, with its references.
+    pub fn refs_in(&self, range: std::ops::Range<u64>) -> impl Iterator<Item = (&u64, &Vec<Xref>)> {
+        self.refs.range(range)
+    }
+
+    // Addresses that are the target of at least one call.
+    pub fn call_targets(&self) -> Vec<u64> {
+        self.refs.iter()
+            .filter(|(_, refs)| refs.iter().any(|r| r.kind == XrefKind::Call))
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+}
@@ -0,0 +1,346 @@
+// Decoder output for known encodings, as assembled by llvm-mc. Branch targets
+// print as offsets from the instruction, like the rest of baretk's listings.
+
+use baretk::AnalysisOptions;
+
+// Each instruction decoded from headerless code, as "mnemonic operands".
+fn listing(arch: &str, thumb: bool, bytes: &[u8]) -> Vec<String> {
+    let options = AnalysisOptions {
+        arch: Some(arch.to_string()),
+        thumb_sections: if thumb { vec!["file".to_string()] } else { vec![] },
+        ..AnalysisOptions::default()
+    };
+    let dis = baretk::load_bytes(bytes, &options).unwrap().disassemble(&options).unwrap();
+    dis.instructions()
+        .map(|ins| format!("{} {}", ins.mnemonic, ins.operands.join(", ")).trim_end().to_string())
+        .collect()
+}
+
+#[test]
+fn aarch64() {
+    let bytes = [
+        0xfd, 0x7b, 0xbf, 0xa9, // stp x29, x30, [sp, #-16]!
+        0xfd, 0x03, 0x00, 0x91, // mov x29, sp
+        0x00, 0x00, 0x00, 0xb0, // adrp x0, 0x1000
+        0x00, 0x40, 0x00, 0x91, // add x0, x0, #16
+        0x01, 0x08, 0x40, 0xb9, // ldr w1, [x0, #8]
+        0x3f, 0x0c, 0x00, 0x71, // cmp w1, #3
+        0x01, 0x01, 0x00, 0x54, // b.ne +32
+        0x00, 0x00, 0x00, 0x94, // bl +0
+        0xe0, 0x17, 0x9f, 0x1a, // cset w0, eq
+        0xfd, 0x7b, 0xc1, 0xa8, // ldp x29, x30, [sp], #16
+        0xc0, 0x03, 0x5f, 0xd6, // ret
+    ];
+    assert_eq!(listing("aarch64", false, &bytes), [
+        "stp x29, x30, [sp, #-16]!",
+        "mov x29, sp",
+        "adrp x0, 4096",
+        "add x0, x0, #16",
+        "ldr w1, [x0, #8]",
+        "cmp w1, #3",
+        "b.ne 32",
+        "bl 0",
+        "cset w0, eq",
+        "ldp x29, x30, [sp], #16",
+        "ret",
+    ]);
+}
+
+#[test]
+fn aarch64_extend() {
+    let bytes = [
+        0xaf, 0x62, 0x24, 0x2b, // adds w15, w21, w4, uxtx
+        0xaf, 0x6e, 0x24, 0x2b, // adds w15, w21, w4, uxtx #3
+        0xaf, 0x62, 0x24, 0xab, // adds x15, x21, x4, uxtx
+        0xff, 0x63, 0x24, 0x8b, // add sp, sp, x4, uxtx
+        0xff, 0x6f, 0x24, 0x8b, // add sp, sp, x4, uxtx #3
+        0xff, 0x4f, 0x24, 0x0b, // add wsp, wsp, w4, uxtw #3
+        0x20, 0x68, 0x62, 0xf8, // ldr x0, [x1, x2]
+        0x20, 0x78, 0x62, 0xf8, // ldr x0, [x1, x2, lsl #3]
+    ];
+    assert_eq!(listing("aarch64", false, &bytes), [
+        "adds w15, w21, w4, uxtw",
+        "adds w15, w21, w4, uxtw #3",
+        "adds x15, x21, x4, uxtx",
+        "add sp, sp, x4",
+        "add sp, sp, x4, lsl #3",
+        "add wsp, wsp, w4, lsl #3",
+        "ldr x0, [x1, x2]",
+        "ldr x0, [x1, x2, lsl #3]",
+    ]);
+}
+
+#[test]
+fn aarch64_atomics() {
+    let bytes = [
+        0x41, 0x7c, 0xa0, 0x88, // cas w0, w1, [x2]
+        0xe1, 0xff, 0xe0, 0xc8, // casal x0, x1, [sp]
+        0x82, 0x7c, 0x20, 0x48, // casp x0, x1, x2, x3, [x4]
+        0x41, 0x00, 0xe0, 0xf8, // ldaddal x0, x1, [x2]
+        0x41, 0x10, 0xa0, 0x38, // ldclrab w0, w1, [x2]
+        0x5f, 0x00, 0x20, 0xb8, // stadd w0, [x2]
+        0x41, 0x80, 0xe0, 0xf8, // swpal x0, x1, [x2]
+        0x20, 0xc0, 0xbf, 0xf8, // ldapr x0, [x1]
+        0x40, 0x04, 0x7f, 0xc8, // ldxp x0, x1, [x2]
+        0x40, 0x04, 0x23, 0xc8, // stxp w3, x0, x1, [x2]
+        0x20, 0x88, 0x40, 0xf8, // ldtr x0, [x1, #8]
+        0x20, 0x80, 0x5f, 0xd9, // ldapur x0, [x1, #-8]
+        0x20, 0x30, 0x00, 0x99, // stlur w0, [x1, #3]
+        0x20, 0x7c, 0xdf, 0xc8, // ldlar x0, [x1]
+        0x20, 0x7c, 0x9f, 0x08, // stllrb w0, [x1]
+        0x20, 0x40, 0xc2, 0x1a, // crc32b w0, w1, w2
+        0x20, 0x5c, 0xc2, 0x9a, // crc32cx w0, w1, x2
+        0x01, 0x08, 0x1f, 0xd7, // braa x0, x1
+        0xdf, 0x08, 0x1f, 0xd6, // braaz x6
+    ];
+    assert_eq!(listing("aarch64", false, &bytes), [
+        "cas w0, w1, [x2]",
+        "casal x0, x1, [sp]",
+        "casp x0, x1, x2, x3, [x4]",
+        "ldaddal x0, x1, [x2]",
+        "ldclrab w0, w1, [x2]",
+        "stadd w0, [x2]",
+        "swpal x0, x1, [x2]",
+        "ldapr x0, [x1]",
+        "ldxp x0, x1, [x2]",
+        "stxp w3, x0, x1, [x2]",
+        "ldtr x0, [x1, #8]",
+        "ldapur x0, [x1, #-8]",
+        "stlur w0, [x1, #3]",
+        "ldlar x0, [x1]",
+        "stllrb w0, [x1]",
+        "crc32b w0, w1, w2",
+        "crc32cx w0, w1, x2",
+        "braa x0, x1",
+        "braaz x6",
+    ]);
+}
+
+// Longer than 32 bytes, mixing full and compressed instructions, and ending
+// with a compressed one.
+#[test]
+fn riscv_section() {
+    let bytes = [
+        0x13, 0x01, 0x01, 0xfe, // addi sp, sp, -32
+        0x33, 0x05, 0xb5, 0x00, // add a0, a0, a1
+        0xb3, 0x05, 0xc5, 0x40, // sub a1, a0, a2
+        0x33, 0xf6, 0xd5, 0x00, // and a2, a1, a3
+        0xb3, 0x66, 0xe6, 0x00, // or a3, a2, a4
+        0x13, 0x05, 0x15, 0x00, // addi a0, a0, 1
+        0x05, 0x45,             // c.li a0, 1
+        0x89, 0x45,             // c.li a1, 2
+        0x13, 0xc7, 0x37, 0x00, // xori a4, a5, 3
+        0x63, 0x04, 0xb5, 0x00, // beq a0, a1, 8
+        0x13, 0x01, 0x01, 0x02, // addi sp, sp, 32
+        0x82, 0x80,             // c.jr ra
+    ];
+    assert_eq!(listing("riscv", false, &bytes), [
+        "addi sp, sp, -32",
+        "add a0, a0, a1",
+        "sub a1, a0, a2",
+        "and a2, a1, a3",
+        "or a3, a2, a4",
+        "addi a0, a0, 1",
+        "li a0, 1",
+        "li a1, 2",
+        "xori a4, a5, 3",
+        "beq a0, a1, 8",
+        "addi sp, sp, 32",
+        "ret",
+    ]);
+    // A section of a single instruction, and one ending in half of one.
+    assert_eq!(listing("riscv", false, &[0x67, 0x80, 0x00, 0x00]), ["ret"]);
+    assert_eq!(listing("riscv", false, &[0x82, 0x80, 0x13, 0x01]), ["ret"]);
+}
+
+#[test]
+fn thumb() {
+    let bytes = [
+        0x41, 0xf2, 0x34, 0x20, // movw r0, #0x1234
+        0x4f, 0xf0, 0xff, 0x01, // mov.w r1, #0xff
+        0x07, 0x22,             // movs r2, #7
+        0x10, 0xb5,             // push {r4, lr}
+        0x10, 0xbd,             // pop {r4, pc}
+    ];
+    assert_eq!(listing("arm", true, &bytes), [
+        "movw r0, #4660",
+        "mov r1, #255",
+        "movs r2, #7",
+        "push {r4, lr}",
+        "pop {r4, pc}",
+    ]);
+}
+
+#[test]
+fn thumb_system() {
+    let bytes = [
+        0xef, 0xf3, 0x10, 0x80, // mrs r0, primask
+        0x83, 0xf3, 0x11, 0x88, // msr basepri, r3
+        0x84, 0xf3, 0x00, 0x88, // msr apsr_nzcvq, r4
+        0x51, 0xe8, 0x02, 0x0f, // ldrex r0, [r1, #8]
+        0x41, 0xe8, 0x00, 0x02, // strex r2, r0, [r1]
+        0xd4, 0xe8, 0x5f, 0x3f, // ldrexh r3, [r4]
+        0xc4, 0xe8, 0x45, 0x3f, // strexb r5, r3, [r4]
+        0xbf, 0xf3, 0x2f, 0x8f, // clrex
+        0x01, 0xf3, 0x0f, 0x10, // ssat r0, #16, r1, lsl #4
+        0xa3, 0xf3, 0x87, 0x02, // usat r2, #7, r3, asr #2
+        0x51, 0xf8, 0x04, 0x0e, // ldrt r0, [r1, #4]
+        0x03, 0xf8, 0x01, 0x2e, // strbt r2, [r3, #1]
+    ];
+    assert_eq!(listing("arm", true, &bytes), [
+        "mrs r0, primask",
+        "msr basepri, r3",
+        "msr apsr_nzcvq, r4",
+        "ldrex r0, [r1, #8]",
+        "strex r2, r0, [r1]",
+        "ldrexh r3, [r4]",
+        "strexb r5, r3, [r4]",
+        "clrex",
+        "ssat r0, #16, r1, lsl #4",
+        "usat r2, #7, r3, asr #2",
+        "ldrt r0, [r1, #4]",
+        "strbt r2, [r3, #1]",
+    ]);
+}
+
+// UNDEFINED and UNPREDICTABLE encodings decode as unknown.
+#[test]
+fn thumb_undefined() {
+    let bytes = [
+        0x00, 0xf0, 0x01, 0xe8, // blx with H set
+        0x2d, 0xe9, 0x00, 0x60, // stmdb sp!, {sp, lr}
+        0x80, 0xe8, 0x00, 0x80, // stmia r0, {pc}
+        0xbd, 0xe8, 0x00, 0xc0, // ldmia sp!, {lr, pc}
+    ];
+    assert_eq!(listing("arm", true, &bytes), ["???"; 4]);
+}
+
+#[test]
+fn arm() {
+    let bytes = [
+        0x34, 0x02, 0x01, 0xe3, // movw r0, #0x1234
+        0x78, 0x06, 0x45, 0xe3, // movt r0, #0x5678
+        0xff, 0x10, 0xa0, 0xe3, // mov r1, #255
+        0x1e, 0xff, 0x2f, 0xe1, // bx lr
+    ];
+    assert_eq!(listing("arm", false, &bytes), [
+        "movw r0, #4660",
+        "movt r0, #22136",
+        "mov r1, #255",
+        "bx lr",
+    ]);
+}
+
+#[test]
+fn arm_addressing() {
+    let bytes = [
+        0x02, 0x04, 0x91, 0xe7, // ldr r0, [r1, r2, lsl #8]
+        0x02, 0x00, 0x11, 0xe7, // ldr r0, [r1, -r2]
+        0x4c, 0x40, 0x1f, 0xe7, // ldr r4, [pc, -r12, asr #32]
+        0x45, 0x00, 0xe2, 0xa0, // rscge r0, r2, r5, asr #32
+        0x61, 0x00, 0xa0, 0xe1, // rrx r0, r1
+        0x04, 0x00, 0xb1, 0xe4, // ldrt r0, [r1], #4
+        0x04, 0x20, 0x63, 0xe6, // strbt r2, [r3], -r4
+        0xb2, 0x50, 0xf6, 0xe0, // ldrht r5, [r6], #2
+        0xd1, 0x70, 0xf8, 0xe0, // ldrsbt r7, [r8], #1
+        0xf2, 0x00, 0x31, 0xe0, // ldrsht r0, [r1], -r2
+        0xb6, 0x00, 0xe1, 0xe0, // strht r0, [r1], #6
+        0x06, 0x00, 0xd0, 0xe8, // ldm r0, {r1, r2}^
+        0x01, 0x40, 0x4d, 0xe9, // stmdb sp, {r0, lr}^
+        0x01, 0x80, 0xfd, 0xe8, // ldm sp!, {r0, pc}^
+        0x04, 0x00, 0x91, 0xe4, // ldr r0, [r1], #4
+    ];
+    assert_eq!(listing("arm", false, &bytes), [
+        "ldr r0, [r1, r2, lsl #8]",
+        "ldr r0, [r1, -r2]",
+        "ldr r4, [pc, -ip, asr #32]",
+        "rscge r0, r2, r5, asr #32",
+        "mov r0, r1, rrx",
+        "ldrt r0, [r1], #4",
+        "strbt r2, [r3], -r4",
+        "ldrht r5, [r6], #2",
+        "ldrsbt r7, [r8], #1",
+        "ldrsht r0, [r1], -r2",
+        "strht r0, [r1], #6",
+        "ldmia r0, {r1, r2}^",
+        "stmdb sp, {r0, lr}^",
+        "ldmia sp!, {r0, pc}^",
+        "ldr r0, [r1], #4",
+    ]);
+}
+
+#[test]
+fn x86_rex() {
+    let bytes = [
+        0x4f, 0x8b, 0x64, 0xf5, 0x10, // mov r12, qword ptr [r13+r14*8+0x10]
+        0x41, 0x01, 0xc0,             // add r8d, eax
+        0x41, 0xb1, 0x01,             // mov r9b, 1
+        0x44, 0x88, 0x3f,             // mov byte ptr [rdi], r15b
+        0x41, 0x57,                   // push r15
+        0x40, 0x88, 0xfe,             // mov sil, dil
+        0x66, 0x45, 0x31, 0xda,       // xor r10w, r11w
+    ];
+    assert_eq!(listing("amd64", false, &bytes), [
+        "mov r12, QWORD PTR [r13+r14*8+0x10]",
+        "add r8d, eax",
+        "mov r9b, 0x1",
+        "mov BYTE PTR [rdi], r15b",
+        "push r15",
+        "mov sil, dil",
+        "xor r10w, r11w",
+    ]);
+}
+
+#[test]
+fn x86_extend() {
+    let bytes = [
+        0x48, 0x63, 0x47, 0x04, // movsxd rax, dword ptr [rdi+4]
+        0x0f, 0xbe, 0xcb,       // movsx ecx, bl
+        0x0f, 0xb6, 0x16,       // movzx edx, byte ptr [rsi]
+        0x44, 0x0f, 0xb7, 0x02, // movzx r8d, word ptr [rdx]
+        0x66, 0x98,             // cbw
+        0x98,                   // cwde
+        0x48, 0x98,             // cdqe
+        0x66, 0x99,             // cwd
+        0x99,                   // cdq
+        0x48, 0x99,             // cqo
+    ];
+    assert_eq!(listing("amd64", false, &bytes), [
+        "movsxd rax, DWORD PTR [rdi+0x04]",
+        "movsx ecx, bl",
+        "movzx edx, BYTE PTR [rsi]",
+        "movzx r8d, WORD PTR [rdx]",
+        "cbw",
+        "cwde",
+        "cdqe",
+        "cwd",
+        "cdq",
+        "cqo",
+    ]);
+}
+
+#[test]
+fn x86_0f_map() {
+    let bytes = [
+        0x0f, 0x85, 0x00, 0x01, 0x00, 0x00, // jne, rel32 0x100 past its end
+        0x0f, 0x94, 0xc0,                   // sete al
+        0x41, 0x0f, 0x9c, 0xc0,             // setl r8b
+        0x48, 0x0f, 0x4f, 0xc2,             // cmovg rax, rdx
+        0x0f, 0xaf, 0xc1,                   // imul eax, ecx
+        0x0f, 0x1f, 0x00,                   // nop dword ptr [rax]
+        0x66, 0x0f, 0x1f, 0x04, 0x00,       // nop word ptr [rax+rax]
+        0x0f, 0xa2,                         // cpuid
+        0x0f, 0x31,                         // rdtsc
+    ];
+    assert_eq!(listing("amd64", false, &bytes), [
+        "jne 262",
+        "sete al",
+        "setl r8b",
+        "cmovg rax, rdx",
+        "imul eax, ecx",
+        "nop DWORD PTR [rax]",
+        "nop WORD PTR [rax+rax]",
+        "cpuid",
+        "rdtsc",
+    ]);
+}
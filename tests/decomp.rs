@@ -0,0 +1,88 @@
+// Statements lifted from known encodings, as assembled by llvm-mc.
+
+use baretk::{AnalysisOptions, Language};
+
+// The statements of the function decompiled from headerless code.
+fn statements(arch: &str, bytes: &[u8], lang: Language) -> Vec<String> {
+    let options = AnalysisOptions { arch: Some(arch.to_string()), ..AnalysisOptions::default() };
+    let decomp = baretk::load_bytes(bytes, &options).unwrap().decompile(lang, None, &options).unwrap();
+    decomp.print(false).lines()
+        .filter(|line| line.starts_with("    "))
+        .map(|line| line.trim().to_string())
+        .collect()
+}
+
+#[test]
+fn extending_moves() {
+    let bytes = [
+        0x48, 0x63, 0x47, 0x04, // movsxd rax, dword ptr [rdi+4]
+        0x0f, 0xbe, 0xcb,       // movsx ecx, bl
+        0x0f, 0xb6, 0x16,       // movzx edx, byte ptr [rsi]
+        0x48, 0x98,             // cdqe
+        0x99,                   // cdq
+        0xc3,                   // ret
+    ];
+    assert_eq!(statements("amd64", &bytes, Language::Pseudocode), [
+        "rax = (i64)*i32((rdi + 4))",
+        "ecx = (i32)bl",
+        "edx = (u32)*u8(rsi)",
+        "rax = (i64)eax",
+        "edx = (eax >>s 31)",
+        "return",
+    ]);
+    assert_eq!(statements("amd64", &bytes, Language::Rust), [
+        "let mut rax = ((*((rdi).wrapping_add(4) as *const u32) as u64) as i32 as i64 as u64);",
+        "let mut ecx = (bl as i8 as i32 as u32 as u64);",
+        "let mut edx = ((*(rsi as *const u8) as u64) as u8 as u32 as u64);",
+        "rax = (eax as i32 as i64 as u64);",
+        "edx = ((eax as i32 as i64) >> (31 as i64)) as u64;",
+        "return;",
+    ]);
+}
+
+#[test]
+fn conditional_moves_and_sets() {
+    let bytes = [
+        0x39, 0xf7,             // cmp edi, esi
+        0x0f, 0x9c, 0xc0,       // setl al
+        0x48, 0x83, 0xff, 0x05, // cmp rdi, 5
+        0x48, 0x0f, 0x4f, 0xc2, // cmovg rax, rdx
+        0x85, 0xc9,             // test ecx, ecx
+        0x0f, 0x94, 0xc2,       // sete dl
+        0x0f, 0xaf, 0xc1,       // imul eax, ecx
+        0xc3,                   // ret
+    ];
+    assert_eq!(statements("amd64", &bytes, Language::Pseudocode), [
+        "al = (u8)(edi < esi)",
+        "if (rdi > 5) rax = rdx",
+        "dl = (u8)(ecx == 0)",
+        "eax = (eax * ecx)",
+        "return",
+    ]);
+}
+
+#[test]
+fn arm_register_offsets() {
+    let bytes = [
+        0x02, 0x04, 0x91, 0xe7, // ldr r0, [r1, r2, lsl #8]
+        0x02, 0x00, 0x11, 0xe7, // ldr r0, [r1, -r2]
+        0x42, 0x31, 0x91, 0xe7, // ldr r3, [r1, r2, asr #2]
+        0x02, 0x51, 0x91, 0xe7, // ldr r5, [r1, r2, lsl #2]
+        0x61, 0x00, 0xa0, 0xe1, // rrx r0, r1
+        0x02, 0x60, 0x31, 0xe7, // ldr r6, [r1, -r2]!
+        0x82, 0x61, 0x01, 0xe6, // str r6, [r1], -r2, lsl #3
+    ];
+    assert_eq!(statements("arm", &bytes, Language::Pseudocode), [
+        "r0 = *u32((r1 + (r2 << 8)))",
+        "r0 = *u32((r1 - r2))",
+        "r3 = *u32((r1 + (r2 >>s 2)))",
+        "r5 = *u32((r1 + (r2 * 4)))",
+        "r0 = __rrx(r1)",
+        "do:",
+        "r1 = (r1 - r2)",
+        "r6 = *u32(r1)",
+        "do:",
+        "*u32(r1) = r6",
+        "r1 = (r1 - (r2 << 3))",
+    ]);
+}
@@ -0,0 +1,128 @@
+// `dis -memory` streams the listing as it decodes; its output should be the
+// same as the listing `dis` builds in memory.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+// x86-64 code for the two executable sections.
+const INIT: &[u8] = &[
+    0x48, 0x83, 0xec, 0x08, // sub rsp, 8
+    0x48, 0x83, 0xc4, 0x08, // add rsp, 8
+    0xc3,                   // ret
+];
+const TEXT: &[u8] = &[
+    0x55,                   // push rbp
+    0x48, 0x89, 0xe5,       // mov rbp, rsp
+    0x0f, 0xb6, 0x06,       // movzx eax, byte ptr [rsi]
+    0x39, 0xf7,             // cmp edi, esi
+    0x0f, 0x9c, 0xc0,       // setl al
+    0xe8, 0xdf, 0xff, 0xff, 0xff, // call _init
+    0xc9,                   // leave
+    0xc3,                   // ret
+];
+const BASE: u64 = 0x400000;
+const INIT_OFFSET: usize = 0x1000;
+const TEXT_OFFSET: usize = 0x1010;
+
+// An Elf64_Shdr from its fields in order: name, type, flags, addr, offset,
+// size, link, info, addralign and entsize.
+fn section_header(out: &mut Vec<u8>, fields: [u64; 10]) {
+    for (field, width) in fields.into_iter().zip([4, 4, 8, 8, 8, 8, 4, 4, 8, 8]) {
+        out.extend(&field.to_le_bytes()[..width]);
+    }
+}
+
+// A static amd64 executable with code in .init and .text, and a function
+// symbol at the start of each.
+fn elf() -> Vec<u8> {
+    let shstrtab = b"\0.init\0.text\0.symtab\0.strtab\0.shstrtab\0";
+    let strtab = b"\0_init\0_start\0";
+    let mut symtab = vec![0u8; 24];
+    for (name, section, offset, size) in [(1u32, 1u16, INIT_OFFSET, INIT.len()), (7, 2, TEXT_OFFSET, TEXT.len())] {
+        symtab.extend(name.to_le_bytes());
+        symtab.extend([0x12, 0]); // STB_GLOBAL, STT_FUNC
+        symtab.extend(section.to_le_bytes());
+        symtab.extend((BASE + offset as u64).to_le_bytes());
+        symtab.extend((size as u64).to_le_bytes());
+    }
+    let symtab_offset = TEXT_OFFSET + TEXT.len().next_multiple_of(8);
+    let strtab_offset = symtab_offset + symtab.len();
+    let shstrtab_offset = strtab_offset + strtab.len();
+    let sh_offset = (shstrtab_offset + shstrtab.len()).next_multiple_of(8);
+
+    let mut out = Vec::new();
+    out.extend(b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0");
+    out.extend(2u16.to_le_bytes()); // ET_EXEC
+    out.extend(0x3eu16.to_le_bytes()); // EM_X86_64
+    out.extend(1u32.to_le_bytes());
+    out.extend((BASE + TEXT_OFFSET as u64).to_le_bytes());
+    out.extend(64u64.to_le_bytes());
+    out.extend((sh_offset as u64).to_le_bytes());
+    out.extend(0u32.to_le_bytes());
+    for half in [64u16, 56, 1, 64, 6, 5] {
+        out.extend(half.to_le_bytes());
+    }
+    // One PT_LOAD covering both code sections.
+    let code_size = (TEXT_OFFSET + TEXT.len() - INIT_OFFSET) as u64;
+    out.extend(1u32.to_le_bytes());
+    out.extend(5u32.to_le_bytes());
+    for word in [INIT_OFFSET as u64, BASE + INIT_OFFSET as u64, BASE + INIT_OFFSET as u64, code_size, code_size, 0x1000] {
+        out.extend(word.to_le_bytes());
+    }
+    out.resize(INIT_OFFSET, 0);
+    out.extend(INIT);
+    out.resize(TEXT_OFFSET, 0);
+    out.extend(TEXT);
+    out.resize(symtab_offset, 0);
+    out.extend(&symtab);
+    out.extend(strtab);
+    out.extend(shstrtab);
+    out.resize(sh_offset, 0);
+    let (init_addr, text_addr) = (BASE + INIT_OFFSET as u64, BASE + TEXT_OFFSET as u64);
+    section_header(&mut out, [0; 10]);
+    section_header(&mut out, [1, 1, 6, init_addr, INIT_OFFSET as u64, INIT.len() as u64, 0, 0, 1, 0]);
+    section_header(&mut out, [7, 1, 6, text_addr, TEXT_OFFSET as u64, TEXT.len() as u64, 0, 0, 1, 0]);
+    section_header(&mut out, [13, 2, 0, 0, symtab_offset as u64, symtab.len() as u64, 4, 1, 8, 24]);
+    section_header(&mut out, [21, 3, 0, 0, strtab_offset as u64, strtab.len() as u64, 0, 0, 1, 0]);
+    section_header(&mut out, [29, 3, 0, 0, shstrtab_offset as u64, shstrtab.len() as u64, 0, 0, 1, 0]);
+    out
+}
+
+fn temp_file(name: &str, bytes: &[u8]) -> PathBuf {
+    let path = env::temp_dir().join(format!("baretk-{}-{}", std::process::id(), name));
+    fs::write(&path, bytes).unwrap();
+    path
+}
+
+fn baretk(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_baretk")).args(args).output().unwrap();
+    assert!(output.status.success(), "baretk {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+// The listing of every mode both ways, with the memory budget given as args.
+fn assert_same_listing(path: &str, options: &[&str], budget: &[&str]) {
+    for format in [&[][..], &["--no-bytes"], &["--json"]] {
+        let args: Vec<&str> = ["dis"].iter().chain(options).chain(format).copied().chain([path]).collect();
+        let streamed: Vec<&str> = ["dis"].iter().chain(options).chain(budget).chain(format).copied().chain([path]).collect();
+        assert_eq!(baretk(&args), baretk(&streamed), "{:?}", format);
+    }
+}
+
+#[test]
+fn elf_sections() {
+    let path = temp_file("sections.elf", &elf());
+    let path = path.to_str().unwrap();
+    let listing = baretk(&["dis", path]);
+    assert!(listing.contains(".section .init") && listing.contains(".section .text"), "{}", listing);
+    assert_same_listing(path, &[], &["-memory", "512M"]);
+    fs::remove_file(path).unwrap();
+}
+
+// A raw image bigger than the window is decoded a window at a time.
+#[test]
+fn raw_windows() {
+    let path = temp_file("windows.bin", &TEXT.repeat(10000));
+    let path = path.to_str().unwrap();
+    assert_same_listing(path, &["-arch", "amd64"], &["-memory", "64K"]);
+    fs::remove_file(path).unwrap();
+}